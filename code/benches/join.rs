@@ -0,0 +1,167 @@
+//! Sort-merge join benchmarks comparing the level 3 strategies.
+//!
+//! Each group varies one parameter of the input, the others keep the defaults below:
+//! * `cardinality` - number of tuples of each side.
+//! * `selectivity` - exact share of the keys, and so of the tuples, found on both sides.
+//! * `key_range` - upper bound of the keys, a larger bound spreads the 1000 keys further.
+//! * `radix_bits` - hash bits partitioning the build side of the hash join, see
+//!   HashEqJoin::set_radix_bits.
+//! * `tpch` - lineitem joined with orders on the order key, see TpchGenerator, without
+//!   and with skewed lineitems.
+//! * `unique_keys` - the general merge against the unique key fast path, see
+//!   SortMergeJoin::set_unique_right, on lineitem joined with orders (FK-PK) and orders
+//!   joined with themselves (1:1).
+//!
+//! The tables are generated from fixed seeds, so every run joins the same tuples.
+use std::fmt::Display;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use join::common::{OpIterator, SimplePredicateOp, Tuple, TupleIterator};
+use join::generator::{create_selective_tuples, create_vec_tuple, get_int_table_schema, TpchGenerator, DEFAULT_SEED};
+use join::join::{HashEqJoin, MergeStrategy, SortMergeJoin};
+
+const TUPLES: usize = 2048;
+const RANGE: usize = 1000;
+const WIDTH: usize = 2;
+
+const STRATEGIES: [(&str, MergeStrategy); 3] = [
+    ("m-way", MergeStrategy::M_WAY),
+    ("m-pass", MergeStrategy::M_PASS),
+    ("radix", MergeStrategy::Radix),
+];
+
+// helper method to build a join of copies of the tables, outside of the measured time
+fn build(left: &[Tuple], right: &[Tuple], strategy: MergeStrategy) -> SortMergeJoin {
+    let schema = get_int_table_schema(WIDTH);
+    let s1: Box<dyn OpIterator + Send> = Box::new(TupleIterator::new(left.to_vec(), schema.clone()));
+    let s2: Box<dyn OpIterator + Send> = Box::new(TupleIterator::new(right.to_vec(), schema));
+    SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, strategy).unwrap()
+}
+
+// helper method to run a whole join and count its output
+fn run(mut op: impl OpIterator) -> usize {
+    op.open().unwrap();
+    let mut count = 0;
+    while op.next().unwrap().is_some() {
+        count += 1;
+    }
+    count
+}
+
+// helper method to benchmark every strategy on one pair of tables
+fn bench_tables(c: &mut Criterion, group: &str, parameter: impl Display, left: &[Tuple], right: &[Tuple]) {
+    let mut group = c.benchmark_group(group);
+    group.sample_size(10);
+    for (name, strategy) in STRATEGIES {
+        group.bench_with_input(BenchmarkId::new(name, &parameter), &strategy, |b, strategy| {
+            b.iter_batched(|| build(left, right, strategy.clone()), |op| black_box(run(op)), BatchSize::LargeInput)
+        });
+    }
+    group.finish();
+}
+
+fn cardinality(c: &mut Criterion) {
+    for tuple_number in [1 << 11, 1 << 15, 1 << 17] {
+        let left = create_vec_tuple(tuple_number, WIDTH, RANGE, DEFAULT_SEED);
+        let right = create_vec_tuple(tuple_number, WIDTH, RANGE, DEFAULT_SEED + 1);
+        bench_tables(c, "cardinality", tuple_number, &left, &right);
+    }
+}
+
+fn selectivity(c: &mut Criterion) {
+    for percent in [10, 30, 50] {
+        let tables = create_selective_tuples(TUPLES, WIDTH, RANGE, percent as f64 / 100.0, DEFAULT_SEED);
+        bench_tables(c, "selectivity", format!("{}%", percent), &tables.left, &tables.right);
+    }
+}
+
+fn key_range(c: &mut Criterion) {
+    for range in [5000, 10000, 100000] {
+        let left = create_vec_tuple(TUPLES, WIDTH, range, DEFAULT_SEED);
+        let right = create_vec_tuple(TUPLES, WIDTH, range, DEFAULT_SEED + 1);
+        bench_tables(c, "key_range", range, &left, &right);
+    }
+}
+
+fn radix_bits(c: &mut Criterion) {
+    let left = create_vec_tuple(1 << 17, WIDTH, 1 << 20, DEFAULT_SEED);
+    let right = create_vec_tuple(1 << 17, WIDTH, 1 << 20, DEFAULT_SEED + 1);
+    let schema = get_int_table_schema(WIDTH);
+    let mut group = c.benchmark_group("radix_bits");
+    group.sample_size(10);
+    for bits in [0, 4, 8, 12] {
+        group.bench_with_input(BenchmarkId::new("hash", bits), &bits, |b, bits| {
+            b.iter_batched(
+                || {
+                    let s1 = TupleIterator::new(left.clone(), schema.clone());
+                    let s2 = TupleIterator::new(right.clone(), schema.clone());
+                    let mut op = HashEqJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2).unwrap();
+                    op.set_radix_bits(*bits);
+                    op
+                },
+                |op| black_box(run(op)),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn tpch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tpch");
+    group.sample_size(10);
+    for skew in [0.0, 1.0] {
+        let mut generator = TpchGenerator::new(0.01, DEFAULT_SEED);
+        generator.set_skew(skew);
+        let (lineitem, orders) = (generator.lineitem(), generator.orders());
+        for (name, strategy) in STRATEGIES {
+            group.bench_with_input(BenchmarkId::new(name, format!("skew {}", skew)), &strategy, |b, strategy| {
+                b.iter_batched(
+                    || {
+                        let s1: Box<dyn OpIterator + Send> =
+                            Box::new(TupleIterator::new(lineitem.clone(), TpchGenerator::lineitem_schema()));
+                        let s2: Box<dyn OpIterator + Send> =
+                            Box::new(TupleIterator::new(orders.clone(), TpchGenerator::orders_schema()));
+                        SortMergeJoin::new(SimplePredicateOp::Equals, 0, 0, s1, s2, strategy.clone()).unwrap()
+                    },
+                    |op| black_box(run(op)),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn unique_keys(c: &mut Criterion) {
+    let mut group = c.benchmark_group("unique_keys");
+    group.sample_size(10);
+    let generator = TpchGenerator::new(0.01, DEFAULT_SEED);
+    let (lineitem, orders) = (generator.lineitem(), generator.orders());
+    let tables = [
+        ("fk-pk", &lineitem, TpchGenerator::lineitem_schema(), false),
+        ("1:1", &orders, TpchGenerator::orders_schema(), true),
+    ];
+    for (data, left, left_schema, unique_left) in tables {
+        for (name, unique) in [("general", false), ("unique", true)] {
+            group.bench_with_input(BenchmarkId::new(name, data), &unique, |b, unique| {
+                b.iter_batched(
+                    || {
+                        let s1: Box<dyn OpIterator + Send> = Box::new(TupleIterator::new(left.clone(), left_schema.clone()));
+                        let s2: Box<dyn OpIterator + Send> =
+                            Box::new(TupleIterator::new(orders.clone(), TpchGenerator::orders_schema()));
+                        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 0, 0, s1, s2, MergeStrategy::M_WAY).unwrap();
+                        op.set_unique_left(*unique && unique_left);
+                        op.set_unique_right(*unique);
+                        op
+                    },
+                    |op| black_box(run(op)),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, cardinality, selectivity, key_range, radix_bits, tpch, unique_keys);
+criterion_main!(benches);
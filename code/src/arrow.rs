@@ -0,0 +1,215 @@
+//! Arrow IPC output of operators, with the arrow feature, so join results load directly into
+//! pandas or polars, e.g. with `pyarrow.ipc.open_file` or `polars.read_ipc`.
+//!
+//! Int fields become Int32 columns and string fields Utf8 columns, named after the
+//! attributes. A field missing from a tuple is a null, allowed only in nullable attributes.
+use std::io::Write;
+use std::sync::Arc;
+use arrow_array::{ArrayRef, Int32Array, RecordBatch, StringArray};
+use arrow_ipc::writer::{FileWriter, StreamWriter};
+use arrow_schema::{ArrowError, DataType as ArrowType, Field as ArrowField, Schema, SchemaRef};
+use crate::common::{CrustyError, DataType, Field, OpIterator, TableSchema, Tuple};
+
+/// Number of tuples per record batch written by write_arrow.
+pub const BATCH_ROWS: usize = 64 * 1024;
+
+/// Framing of the record batches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrowFormat {
+    /// IPC stream, read front to back.
+    Stream,
+    /// IPC file (Feather v2), with a footer indexing the batches.
+    File,
+}
+
+impl From<ArrowError> for CrustyError {
+    fn from(error: ArrowError) -> Self {
+        CrustyError::IOError(error.to_string())
+    }
+}
+
+/// Returns the Arrow schema of tuples of a schema.
+///
+/// # Arguments
+///
+/// * `schema` - Schema of the tuples.
+pub fn arrow_schema(schema: &TableSchema) -> SchemaRef {
+    let fields: Vec<ArrowField> = schema
+        .attributes()
+        .map(|a| {
+            let dtype = match a.dtype() {
+                DataType::Int => ArrowType::Int32,
+                DataType::String => ArrowType::Utf8,
+            };
+            ArrowField::new(a.name(), dtype, a.is_nullable())
+        })
+        .collect();
+    Arc::new(Schema::new(fields))
+}
+
+/// Convert tuples into a record batch of their columns.
+///
+/// Fails with a ValidationError if a field does not have the type of its attribute, or is
+/// missing from a tuple while its attribute is not nullable.
+///
+/// # Arguments
+///
+/// * `schema` - Arrow schema of the tuples, see arrow_schema.
+/// * `tuples` - Tuples of the batch.
+pub fn to_record_batch(schema: &SchemaRef, tuples: &[Tuple]) -> Result<RecordBatch, CrustyError> {
+    let mismatch = |i: usize, t: &Tuple| CrustyError::ValidationError(format!("field {} of tuple {} does not match the schema", i, t));
+    let columns = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(i, field)| -> Result<ArrayRef, CrustyError> {
+            let column: ArrayRef = match field.data_type() {
+                ArrowType::Int32 => Arc::new(
+                    tuples
+                        .iter()
+                        .map(|t| match t.get_field(i) {
+                            Some(Field::IntField(v)) => Ok(Some(*v)),
+                            None => Ok(None),
+                            Some(_) => Err(mismatch(i, t)),
+                        })
+                        .collect::<Result<Int32Array, _>>()?,
+                ),
+                _ => Arc::new(
+                    tuples
+                        .iter()
+                        .map(|t| match t.get_field(i) {
+                            Some(Field::StringField(v)) => Ok(Some(v.as_str())),
+                            None => Ok(None),
+                            Some(_) => Err(mismatch(i, t)),
+                        })
+                        .collect::<Result<StringArray, _>>()?,
+                ),
+            };
+            Ok(column)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    RecordBatch::try_new(schema.clone(), columns).map_err(|e| CrustyError::ValidationError(e.to_string()))
+}
+
+/// Run an operator and write its tuples as Arrow IPC record batches of BATCH_ROWS tuples.
+///
+/// Returns the number of tuples written. The operator is opened and closed.
+///
+/// # Arguments
+///
+/// * `op` - Operator to run, e.g. a join.
+/// * `writer` - Destination of the batches, e.g. a file.
+/// * `format` - Stream or file framing.
+pub fn write_arrow(op: &mut dyn OpIterator, writer: &mut dyn Write, format: ArrowFormat) -> Result<usize, CrustyError> {
+    let schema = arrow_schema(op.get_schema());
+    let mut sink = match format {
+        ArrowFormat::Stream => BatchWriter::Stream(StreamWriter::try_new(writer, &schema)?),
+        ArrowFormat::File => BatchWriter::File(FileWriter::try_new(writer, &schema)?),
+    };
+    op.open()?;
+    let mut count = 0;
+    let mut batch = Vec::with_capacity(BATCH_ROWS);
+    loop {
+        let t = op.next()?;
+        let done = t.is_none();
+        batch.extend(t);
+        if batch.len() == BATCH_ROWS || (done && !batch.is_empty()) {
+            sink.write(&to_record_batch(&schema, &batch)?)?;
+            count += batch.len();
+            batch.clear();
+        }
+        if done {
+            break;
+        }
+    }
+    op.close()?;
+    sink.finish()?;
+    Ok(count)
+}
+
+/// Writer of either framing.
+enum BatchWriter<W: Write> {
+    Stream(StreamWriter<W>),
+    File(FileWriter<W>),
+}
+
+impl<W: Write> BatchWriter<W> {
+    // helper method to write a record batch
+    fn write(&mut self, batch: &RecordBatch) -> Result<(), ArrowError> {
+        match self {
+            BatchWriter::Stream(w) => w.write(batch),
+            BatchWriter::File(w) => w.write(batch),
+        }
+    }
+
+    // helper method to write the end of the stream or the footer of the file
+    fn finish(&mut self) -> Result<(), ArrowError> {
+        match self {
+            BatchWriter::Stream(w) => w.finish(),
+            BatchWriter::File(w) => w.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use arrow_array::Array;
+    use arrow_ipc::reader::{FileReader, StreamReader};
+    use crate::common::{Attribute, SimplePredicateOp, TupleIterator};
+    use crate::generator::{create_vec_tuple, get_int_table_schema};
+    use crate::join::{MergeStrategy, SortMergeJoin};
+
+    #[test]
+    fn join_output() -> Result<(), CrustyError> {
+        let scan = |seed| TupleIterator::new(create_vec_tuple(9000, 2, 1000, seed), get_int_table_schema(2));
+        let mut join = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(1), scan(2), MergeStrategy::M_WAY)?;
+        let mut expected = Vec::new();
+        join.open()?;
+        while let Some(t) = join.next()? {
+            expected.push(t);
+        }
+        join.close()?;
+        assert!(expected.len() > BATCH_ROWS);
+
+        for format in [ArrowFormat::Stream, ArrowFormat::File] {
+            let mut bytes = Vec::new();
+            assert_eq!(write_arrow(&mut join, &mut bytes, format)?, expected.len());
+            let batches: Vec<RecordBatch> = match format {
+                ArrowFormat::Stream => StreamReader::try_new(Cursor::new(bytes), None)?.collect::<Result<_, _>>()?,
+                ArrowFormat::File => FileReader::try_new(Cursor::new(bytes), None)?.collect::<Result<_, _>>()?,
+            };
+            assert_eq!(batches.len(), 2);
+            assert_eq!(batches[0].schema(), arrow_schema(join.get_schema()));
+            let mut actual = Vec::new();
+            for batch in &batches {
+                let columns: Vec<&Int32Array> = batch.columns().iter().map(|c| c.as_any().downcast_ref().unwrap()).collect();
+                actual.extend((0..batch.num_rows()).map(|row| Tuple::new(columns.iter().map(|c| Field::IntField(c.value(row))).collect())));
+            }
+            assert_eq!(actual, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn nulls_and_strings() -> Result<(), CrustyError> {
+        let mut padded = Attribute::new(String::from("name"), DataType::String);
+        padded.nullable = true;
+        let schema = TableSchema::new(vec![Attribute::new(String::from("id"), DataType::Int), padded]);
+        let tuples = vec![
+            Tuple::new(vec![Field::IntField(1), Field::StringField(String::from("a"))]),
+            Tuple::new(vec![Field::IntField(2)]),
+        ];
+        let batch = to_record_batch(&arrow_schema(&schema), &tuples)?;
+        let names: &StringArray = batch.column(1).as_any().downcast_ref().unwrap();
+        assert_eq!((names.value(0), names.is_null(1)), ("a", true));
+
+        // a null id or a string id does not fit
+        let missing = vec![Tuple::new(Vec::new())];
+        assert!(matches!(to_record_batch(&arrow_schema(&schema), &missing), Err(CrustyError::ValidationError(_))));
+        let wrong = vec![Tuple::new(vec![Field::StringField(String::from("1")), Field::StringField(String::from("a"))])];
+        assert!(matches!(to_record_batch(&arrow_schema(&schema), &wrong), Err(CrustyError::ValidationError(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,367 @@
+//! Async operators, so the library can be embedded in async services.
+//!
+//! Scans read their files without blocking the executor. Blocking runs a sync operator
+//! tree, e.g. a join, on the blocking thread pool; BlockOn goes the other way and feeds
+//! an async scan into the sync operators.
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::PathBuf;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::runtime::{Builder, Runtime};
+use crate::common::{not_open, CrustyError, OpIterator, TableSchema, Tuple};
+use crate::operators::parse_csv_line;
+
+/// Default number of tuples Blocking fetches with each call on the blocking thread pool.
+pub const BLOCKING_BATCH_SIZE: usize = 1024;
+
+/// Async counterpart of OpIterator.
+///
+/// Implementations may write `async fn` for the methods; the futures must be Send so they
+/// can run on a multi-threaded executor.
+pub trait AsyncOpIterator {
+    /// Opens the iterator. This must be called before any of the other methods.
+    fn open(&mut self) -> impl Future<Output = Result<(), CrustyError>> + Send;
+
+    /// Advances the iterator and returns the next tuple from the operator.
+    ///
+    /// Returns None when iteration is finished.
+    fn next(&mut self) -> impl Future<Output = Result<Option<Tuple>, CrustyError>> + Send;
+
+    /// Closes the iterator.
+    fn close(&mut self) -> impl Future<Output = Result<(), CrustyError>> + Send;
+
+    /// Returns the iterator to the start.
+    fn rewind(&mut self) -> impl Future<Output = Result<(), CrustyError>> + Send;
+
+    /// Returns the schema associated with this OpIterator.
+    fn get_schema(&self) -> &TableSchema;
+}
+
+/// Async scan of a CSV file without header, see operators::CsvScan.
+pub struct AsyncCsvScan {
+    /// Path of the file.
+    path: PathBuf,
+    /// Schema of the lines.
+    schema: TableSchema,
+    /// Reader of the file while open.
+    reader: Option<BufReader<File>>,
+    /// Number of the last line read, for error messages.
+    line: usize,
+}
+
+impl AsyncCsvScan {
+    /// AsyncCsvScan constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file.
+    /// * `schema` - Schema of the lines.
+    pub fn new(path: impl Into<PathBuf>, schema: TableSchema) -> Self {
+        Self {
+            path: path.into(),
+            schema,
+            reader: None,
+            line: 0,
+        }
+    }
+}
+
+impl AsyncOpIterator for AsyncCsvScan {
+    async fn open(&mut self) -> Result<(), CrustyError> {
+        self.reader = Some(BufReader::new(File::open(&self.path).await?));
+        self.line = 0;
+        Ok(())
+    }
+
+    async fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        let reader = match self.reader.as_mut() {
+            Some(reader) => reader,
+            None => return not_open(),
+        };
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).await? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+            // empty lines, e.g. a trailing one, are skipped
+            let line = line.trim_end_matches(['\n', '\r']);
+            if !line.is_empty() {
+                return parse_csv_line(line, self.line, &self.schema).map(Some);
+            }
+        }
+    }
+
+    async fn close(&mut self) -> Result<(), CrustyError> {
+        if self.reader.take().is_none() {
+            return not_open();
+        }
+        Ok(())
+    }
+
+    async fn rewind(&mut self) -> Result<(), CrustyError> {
+        if self.reader.is_none() {
+            return not_open();
+        }
+        self.open().await
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+/// Runs a sync operator on the blocking thread pool of the tokio runtime.
+///
+/// Tuples are fetched in batches, so the executor is only left once per batch. Must be
+/// used inside a tokio runtime.
+pub struct Blocking<T> {
+    /// Wrapped operator, None while a call runs on the pool.
+    op: Option<T>,
+    /// Schema of the wrapped operator.
+    schema: TableSchema,
+    /// Tuples fetched and not returned yet.
+    buffer: VecDeque<Tuple>,
+    /// Number of tuples fetched by each call on the pool.
+    batch_size: usize,
+    /// Whether the operator returned its last tuple.
+    done: bool,
+}
+
+impl<T: OpIterator + Send + 'static> Blocking<T> {
+    /// Blocking constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Operator to run.
+    pub fn new(op: T) -> Self {
+        Self {
+            schema: op.get_schema().clone(),
+            op: Some(op),
+            buffer: VecDeque::new(),
+            batch_size: BLOCKING_BATCH_SIZE,
+            done: false,
+        }
+    }
+
+    /// Set the number of tuples fetched by each call on the blocking pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `batch_size` - Number of tuples, at least 1.
+    pub fn set_batch_size(&mut self, batch_size: usize) {
+        self.batch_size = batch_size.max(1);
+    }
+
+    /// Returns the wrapped operator, None if a call was cancelled while it ran.
+    pub fn into_inner(self) -> Option<T> {
+        self.op
+    }
+
+    // helper method to run a call on the operator on the blocking pool
+    async fn run<R: Send + 'static>(
+        &mut self,
+        call: impl FnOnce(&mut T) -> Result<R, CrustyError> + Send + 'static,
+    ) -> Result<R, CrustyError> {
+        let mut op = self
+            .op
+            .take()
+            .ok_or_else(|| CrustyError::ExecutionError(String::from("operator lost by a cancelled call")))?;
+        let (op, res) = tokio::task::spawn_blocking(move || {
+            let res = call(&mut op);
+            (op, res)
+        })
+        .await
+        .map_err(|e| CrustyError::ExecutionError(e.to_string()))?;
+        self.op = Some(op);
+        res
+    }
+}
+
+impl<T: OpIterator + Send + 'static> AsyncOpIterator for Blocking<T> {
+    async fn open(&mut self) -> Result<(), CrustyError> {
+        self.buffer.clear();
+        self.done = false;
+        self.run(|op| op.open()).await
+    }
+
+    async fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if self.buffer.is_empty() && !self.done {
+            let batch_size = self.batch_size;
+            let batch = self
+                .run(move |op| {
+                    let mut batch = Vec::with_capacity(batch_size);
+                    while batch.len() < batch_size {
+                        match op.next()? {
+                            Some(t) => batch.push(t),
+                            None => break,
+                        }
+                    }
+                    Ok(batch)
+                })
+                .await?;
+            self.done = batch.len() < batch_size;
+            self.buffer.extend(batch);
+        }
+        Ok(self.buffer.pop_front())
+    }
+
+    async fn close(&mut self) -> Result<(), CrustyError> {
+        self.buffer.clear();
+        self.run(|op| op.close()).await
+    }
+
+    async fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.buffer.clear();
+        self.done = false;
+        self.run(|op| op.rewind()).await
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+/// Runs an async operator to completion on its own single-threaded runtime, so it can be
+/// the child of the sync operators.
+///
+/// Its methods block the calling thread, so it must not be used on an executor thread:
+/// run the sync tree that holds it inside Blocking instead.
+pub struct BlockOn<A> {
+    /// Wrapped operator.
+    inner: A,
+    /// Runtime driving the wrapped operator, taken when dropped.
+    runtime: Option<Runtime>,
+}
+
+impl<A: AsyncOpIterator> BlockOn<A> {
+    /// BlockOn constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - Operator to run.
+    pub fn new(inner: A) -> Result<Self, CrustyError> {
+        Ok(Self {
+            inner,
+            runtime: Some(Builder::new_current_thread().build()?),
+        })
+    }
+}
+
+impl<A> Drop for BlockOn<A> {
+    /// Shuts the runtime down without blocking, so the operator can be dropped in an
+    /// async context.
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+impl<A: AsyncOpIterator> OpIterator for BlockOn<A> {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.runtime.as_ref().unwrap().block_on(self.inner.open())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        self.runtime.as_ref().unwrap().block_on(self.inner.next())
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.runtime.as_ref().unwrap().block_on(self.inner.close())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.runtime.as_ref().unwrap().block_on(self.inner.rewind())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        self.inner.get_schema()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{env, fs, process};
+    use crate::common::{DataType, Field, SimplePredicateOp};
+    use crate::join::{MergeStrategy, SortMergeJoin};
+    use crate::operators::CsvScan;
+
+    // helper method to write a CSV fixture, returning its path
+    fn write_file(name: &str, lines: &[&str]) -> PathBuf {
+        let path = env::temp_dir().join(format!("join-async-{}-{}.csv", process::id(), name));
+        fs::write(&path, lines.join("\n")).unwrap();
+        path
+    }
+
+    fn schema() -> TableSchema {
+        TableSchema::from_vecs(vec!["id", "name"], vec![DataType::Int, DataType::String])
+    }
+
+    // helper method to read every tuple of an async operator
+    async fn collect(op: &mut impl AsyncOpIterator) -> Result<Vec<Tuple>, CrustyError> {
+        let mut res = Vec::new();
+        while let Some(t) = op.next().await? {
+            res.push(t);
+        }
+        Ok(res)
+    }
+
+    #[test]
+    fn csv_scan() -> Result<(), CrustyError> {
+        let path = write_file("scan", &["1,a", "2,b", "", "3,c"]);
+        let runtime = Builder::new_current_thread().build()?;
+        let res = runtime.block_on(async {
+            let mut scan = AsyncCsvScan::new(&path, schema());
+            scan.open().await?;
+            let first = collect(&mut scan).await?;
+            scan.rewind().await?;
+            assert_eq!(collect(&mut scan).await?, first);
+            scan.close().await?;
+            Ok::<_, CrustyError>(first)
+        })?;
+        let mut sync = CsvScan::new(&path, schema());
+        sync.open()?;
+        let mut expected = Vec::new();
+        while let Some(t) = sync.next()? {
+            expected.push(t);
+        }
+        assert_eq!(res, expected);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn join_over_bridges() -> Result<(), CrustyError> {
+        let left = write_file("left", &["1,a", "2,b", "3,c"]);
+        let right = write_file("right", &["3,x", "1,y", "1,z"]);
+        let runtime = Builder::new_current_thread().build()?;
+        let res = runtime.block_on(async {
+            // the async scans feed the sync join, run on the blocking pool
+            let join = SortMergeJoin::new(
+                SimplePredicateOp::Equals,
+                0,
+                0,
+                BlockOn::new(AsyncCsvScan::new(&left, schema()))?,
+                BlockOn::new(AsyncCsvScan::new(&right, schema()))?,
+                MergeStrategy::M_WAY,
+            )?;
+            let mut op = Blocking::new(join);
+            op.set_batch_size(2);
+            assert_eq!(op.get_schema().size(), 4);
+            op.open().await?;
+            let res = collect(&mut op).await?;
+            op.close().await?;
+            Ok::<_, CrustyError>(res)
+        })?;
+        let ids: Vec<(Field, Field)> = res.into_iter().map(|t| (t.field_vals[1].clone(), t.field_vals[3].clone())).collect();
+        let s = |s: &str| Field::StringField(s.to_string());
+        assert_eq!(ids, vec![(s("a"), s("y")), (s("a"), s("z")), (s("c"), s("x"))]);
+        fs::remove_file(left)?;
+        fs::remove_file(right)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use std::sync::{Arc, Mutex};
+use crate::common::CrustyError;
+use crate::heap::{HeapFile, Page};
+
+/// Page accesses of a buffer pool or of one of its readers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Pages found in a frame.
+    pub hits: usize,
+    /// Pages read from their file.
+    pub misses: usize,
+    /// Pages evicted to free a frame.
+    pub evictions: usize,
+}
+
+/// Frame of the pool holding a page.
+struct Frame {
+    /// Id of the file and index of the page.
+    key: (usize, usize),
+    /// Cached page.
+    page: Arc<Page>,
+    /// Number of readers holding the page.
+    pins: usize,
+    /// Tick of the last access, the frame with the smallest is evicted first.
+    last_used: u64,
+}
+
+/// Frames and counters of a pool, behind its lock.
+#[derive(Default)]
+struct PoolState {
+    /// Occupied frames.
+    frames: Vec<Frame>,
+    /// Access counter ordering the frames by recency.
+    tick: u64,
+    /// Accesses since the pool was created.
+    stats: PoolStats,
+}
+
+/// Fixed number of page frames shared by the readers of heap files.
+///
+/// A fetched page stays pinned until it is unpinned; once every frame is occupied the
+/// least recently used unpinned frame is evicted. The last page of a file still being
+/// written is read around the pool, as it may change.
+pub struct BufferPool {
+    /// Number of frames.
+    capacity: usize,
+    /// Frames and counters.
+    state: Mutex<PoolState>,
+}
+
+impl BufferPool {
+    /// Create an empty pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - Number of frames, at least 1.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(PoolState::default()),
+        }
+    }
+
+    /// Returns the number of frames.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the accesses since the pool was created.
+    pub fn stats(&self) -> PoolStats {
+        self.state.lock().unwrap().stats
+    }
+
+    /// Returns the number of pinned frames.
+    pub fn pinned(&self) -> usize {
+        self.state.lock().unwrap().frames.iter().filter(|frame| frame.pins > 0).count()
+    }
+
+    /// Pin a page of a file, reading it into a frame on a miss.
+    ///
+    /// Returns the page and whether it was a hit. Fails with an ExecutionError if every
+    /// frame is pinned.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - File of the page.
+    /// * `page` - Index of the page.
+    pub fn fetch_page(&self, file: &mut HeapFile, page: usize) -> Result<(Arc<Page>, bool), CrustyError> {
+        if !file.is_page_full(page) {
+            return Ok((Arc::new(file.read_page(page)?), false));
+        }
+        let key = (file.id(), page);
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        if let Some(frame) = state.frames.iter_mut().find(|frame| frame.key == key) {
+            frame.pins += 1;
+            frame.last_used = tick;
+            let page = frame.page.clone();
+            state.stats.hits += 1;
+            return Ok((page, true));
+        }
+        if state.frames.len() >= self.capacity {
+            let victim = state
+                .frames
+                .iter()
+                .enumerate()
+                .filter(|(_, frame)| frame.pins == 0)
+                .min_by_key(|(_, frame)| frame.last_used)
+                .map(|(i, _)| i)
+                .ok_or_else(|| CrustyError::ExecutionError(String::from("every buffer pool frame is pinned")))?;
+            state.frames.swap_remove(victim);
+            state.stats.evictions += 1;
+        }
+        let page_data = Arc::new(file.read_page(page)?);
+        state.frames.push(Frame {
+            key,
+            page: page_data.clone(),
+            pins: 1,
+            last_used: tick,
+        });
+        state.stats.misses += 1;
+        Ok((page_data, false))
+    }
+
+    /// Unpin a page fetched from the pool, a no-op if it is not in a frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - Id of the file of the page, see HeapFile::id.
+    /// * `page` - Index of the page.
+    pub fn unpin(&self, file: usize, page: usize) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(frame) = state.frames.iter_mut().find(|frame| frame.key == (file, page)) {
+            frame.pins = frame.pins.saturating_sub(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lru_eviction() -> Result<(), CrustyError> {
+        let mut file = HeapFile::temp()?;
+        // 4 full pages, then a partial one
+        while file.num_pages() < 5 {
+            file.insert(&[1; 1000])?;
+        }
+        let pool = BufferPool::new(2);
+        assert!(!pool.fetch_page(&mut file, 0)?.1);
+        assert!(!pool.fetch_page(&mut file, 1)?.1);
+        assert!(pool.fetch_page(&mut file, 0)?.1);
+        assert_eq!(pool.pinned(), 2);
+        assert!(pool.fetch_page(&mut file, 2).is_err());
+
+        pool.unpin(file.id(), 0);
+        pool.unpin(file.id(), 0);
+        pool.unpin(file.id(), 1);
+        // page 1 is the least recently used
+        assert!(!pool.fetch_page(&mut file, 2)?.1);
+        assert!(pool.fetch_page(&mut file, 0)?.1);
+        let (tail, hit) = pool.fetch_page(&mut file, 4)?;
+        assert!(!hit && tail.slots() > 0);
+        assert_eq!(pool.stats(), PoolStats { hits: 2, misses: 3, evictions: 1 });
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::common::{CrustyError, DataType, OpIterator, TableSchema, Tuple, TupleIterator};
+use crate::generator::create_vec_tuple;
+use crate::operators::CsvScan;
+use crate::plan::LogicalPlan;
+
+/// Where the tuples of a table come from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ScanSource {
+    /// Tuples held in memory.
+    Tuples(Vec<Tuple>),
+    /// CSV file without header, see operators::CsvScan.
+    Csv(PathBuf),
+    /// Uniform random integers drawn again on every scan, see generator::create_vec_tuple.
+    Generated {
+        /// Upper bound (exclusive) of the values, at least 1000.
+        range: usize,
+        /// Seed of the values, the same seed gives the same tuples.
+        seed: u64,
+    },
+}
+
+impl ScanSource {
+    /// Create a scan of the source.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the tuples.
+    /// * `rows` - Number of tuples, only used by generated sources.
+    pub fn scan(&self, schema: &TableSchema, rows: usize) -> Box<dyn OpIterator + Send> {
+        match self {
+            ScanSource::Tuples(tuples) => Box::new(TupleIterator::new(tuples.clone(), schema.clone())),
+            ScanSource::Csv(path) => Box::new(CsvScan::new(path.clone(), schema.clone())),
+            ScanSource::Generated { range, seed } => Box::new(TupleIterator::new(
+                create_vec_tuple(rows, schema.size(), *range, *seed),
+                schema.clone(),
+            )),
+        }
+    }
+}
+
+/// A registered table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableEntry {
+    /// Schema of the tuples.
+    pub schema: TableSchema,
+    /// Where the tuples come from.
+    pub source: ScanSource,
+    /// Number of tuples.
+    pub rows: usize,
+}
+
+/// Tables known by name, so scans can be created from the name alone.
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    /// Registered tables by name.
+    tables: HashMap<String, TableEntry>,
+}
+
+impl Catalog {
+    /// Create a new empty catalog.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register tuples held in memory, replacing any table of the same name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the table.
+    /// * `tuples` - Tuples of the table.
+    /// * `schema` - Schema of the tuples.
+    pub fn register_tuples(&mut self, name: &str, tuples: Vec<Tuple>, schema: TableSchema) {
+        let rows = tuples.len();
+        self.register(name, schema, ScanSource::Tuples(tuples), rows);
+    }
+
+    /// Register a CSV file, replacing any table of the same name. Its lines are counted
+    /// for the estimates.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the table.
+    /// * `path` - Path of the file.
+    /// * `schema` - Schema of the lines.
+    pub fn register_csv(&mut self, name: &str, path: impl Into<PathBuf>, schema: TableSchema) -> Result<(), CrustyError> {
+        let path = path.into();
+        let mut rows = 0;
+        for line in BufReader::new(File::open(&path)?).lines() {
+            if !line?.trim_end().is_empty() {
+                rows += 1;
+            }
+        }
+        self.register(name, schema, ScanSource::Csv(path), rows);
+        Ok(())
+    }
+
+    /// Register a table of uniform random integers, replacing any table of the same name.
+    ///
+    /// Fails with a ValidationError if the schema has a non integer field.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the table.
+    /// * `schema` - Schema of the tuples.
+    /// * `rows` - Number of tuples.
+    /// * `range` - Upper bound (exclusive) of the values, at least 1000.
+    /// * `seed` - Seed of the values.
+    pub fn register_generated(
+        &mut self,
+        name: &str,
+        schema: TableSchema,
+        rows: usize,
+        range: usize,
+        seed: u64,
+    ) -> Result<(), CrustyError> {
+        if let Some(attr) = schema.attributes().find(|a| *a.dtype() != DataType::Int) {
+            return Err(CrustyError::ValidationError(format!(
+                "generated field {} is not an integer",
+                attr.name()
+            )));
+        }
+        self.register(name, schema, ScanSource::Generated { range, seed }, rows);
+        Ok(())
+    }
+
+    // helper method to add an entry
+    fn register(&mut self, name: &str, schema: TableSchema, source: ScanSource, rows: usize) {
+        self.tables.insert(name.to_string(), TableEntry { schema, source, rows });
+    }
+
+    /// Returns the table of a name, if registered.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the table.
+    pub fn get(&self, name: &str) -> Option<&TableEntry> {
+        self.tables.get(name)
+    }
+
+    /// Returns the names of the registered tables, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.tables.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    // helper method to find a table, failing with a ValidationError if it is missing
+    fn entry(&self, name: &str) -> Result<&TableEntry, CrustyError> {
+        self.get(name)
+            .ok_or_else(|| CrustyError::ValidationError(format!("no table is named {}", name)))
+    }
+
+    /// Create a scan of a table.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the table.
+    pub fn scan(&self, name: &str) -> Result<Box<dyn OpIterator + Send>, CrustyError> {
+        let entry = self.entry(name)?;
+        Ok(entry.source.scan(&entry.schema, entry.rows))
+    }
+
+    /// Create the logical plan of a scan of a table.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the table.
+    pub fn plan(&self, name: &str) -> Result<LogicalPlan, CrustyError> {
+        let entry = self.entry(name)?;
+        Ok(LogicalPlan::Scan {
+            name: name.to_string(),
+            schema: entry.schema.clone(),
+            source: entry.source.clone(),
+            rows: entry.rows,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::Field;
+    use crate::generator::{get_int_table_schema, DEFAULT_SEED};
+    use crate::operators::write_csv;
+
+    #[test]
+    fn scan_by_name() -> Result<(), CrustyError> {
+        let mut catalog = Catalog::new();
+        catalog.register_tuples("t", vec![Tuple::new(vec![Field::IntField(1)])], get_int_table_schema(1));
+        catalog.register_generated("g", get_int_table_schema(2), 100, 1000, DEFAULT_SEED)?;
+        assert_eq!(catalog.names(), vec!["g", "t"]);
+        assert_eq!(write_csv(catalog.scan("t")?.as_mut(), &mut std::io::sink())?, 1);
+
+        // a generated table returns the same tuples on every scan
+        let (mut first, mut second) = (Vec::new(), Vec::new());
+        assert_eq!(write_csv(catalog.scan("g")?.as_mut(), &mut first)?, 100);
+        write_csv(catalog.scan("g")?.as_mut(), &mut second)?;
+        assert_eq!(first, second);
+        assert_eq!(catalog.plan("g")?.estimated_rows(), 100);
+
+        assert!(matches!(catalog.scan("missing"), Err(CrustyError::ValidationError(_))));
+        let strings = TableSchema::from_vecs(vec!["s"], vec![DataType::String]);
+        assert!(matches!(
+            catalog.register_generated("s", strings, 1, 1000, 0),
+            Err(CrustyError::ValidationError(_))
+        ));
+        Ok(())
+    }
+}
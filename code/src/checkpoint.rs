@@ -0,0 +1,177 @@
+//! Checkpoints of the sorted runs of a sort-merge join, see SortMergeJoin::save_state.
+//!
+//! A checkpoint is a directory holding a spill file per sorted run and a state file, in
+//! JSON, listing for each side of the join its runs with their tuple counts and key ranges,
+//! the number of rows of the child the runs hold and whether the child was sorted whole.
+//! A join restoring the checkpoint reads the runs back instead of sorting their rows again,
+//! and reads its children past the rows the runs hold, so only the rest is sorted.
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::common::{CrustyError, Field, Tuple};
+use crate::spill::{SpillCompression, SpillFile};
+
+/// Name of the state file in a checkpoint directory.
+pub const STATE_FILE: &str = "state.json";
+
+/// A sorted run of a checkpoint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunState {
+    /// Name of the run file in the checkpoint directory.
+    pub file: PathBuf,
+    /// Number of tuples.
+    pub rows: usize,
+    /// Key of the first tuple, None for an empty run.
+    pub min_key: Option<Field>,
+    /// Key of the last tuple, None for an empty run.
+    pub max_key: Option<Field>,
+    /// Compression of the run file.
+    pub compression: SpillCompression,
+}
+
+/// Sorted runs of one side of a join.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SideState {
+    /// Index of the join key in the tuples.
+    pub key: usize,
+    /// Number of fields of the tuples.
+    pub width: usize,
+    /// Number of rows of the child held by the runs, the first ones it returns.
+    pub rows: usize,
+    /// Whether the runs hold every row of the child.
+    pub sorted: bool,
+    /// Runs, each sorted on the key.
+    pub runs: Vec<RunState>,
+}
+
+impl SideState {
+    /// Create the state of a side without runs.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Index of the join key.
+    /// * `width` - Number of fields of the tuples.
+    /// * `rows` - Number of rows of the child the runs will hold.
+    /// * `sorted` - Whether the runs will hold every row of the child.
+    pub fn new(key: usize, width: usize, rows: usize, sorted: bool) -> Self {
+        Self {
+            key,
+            width,
+            rows,
+            sorted,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Write a sorted run held in memory to a run file of the checkpoint.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Checkpoint directory.
+    /// * `name` - Name of the side, prefixing the run files.
+    /// * `tuples` - Tuples of the run, sorted on the key.
+    /// * `compression` - Compression of the run file.
+    pub fn add_run(&mut self, dir: &Path, name: &str, tuples: &[Tuple], compression: SpillCompression) -> Result<(), CrustyError> {
+        let mut file = SpillFile::create()?;
+        file.set_compression(compression);
+        for t in tuples {
+            file.write(t)?;
+        }
+        let key = |t: Option<&Tuple>| t.and_then(|t| t.get_field(self.key)).cloned();
+        let (min_key, max_key) = (key(tuples.first()), key(tuples.last()));
+        self.push(dir, name, &mut file, min_key, max_key)
+    }
+
+    /// Copy a sorted run spilled to disk to a run file of the checkpoint.
+    ///
+    /// The copy is read once for the key range of the run.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Checkpoint directory.
+    /// * `name` - Name of the side, prefixing the run files.
+    /// * `file` - Spill file of the run, of CBOR tuples sorted on the key.
+    pub fn add_spilled(&mut self, dir: &Path, name: &str, file: &mut SpillFile) -> Result<(), CrustyError> {
+        self.push(dir, name, file, None, None)?;
+        let run = self.runs.last_mut().unwrap();
+        let mut reader = SpillFile::open(dir.join(&run.file), None, run.compression, run.rows)?.into_reader()?;
+        while let Some(t) = reader.next_tuple()? {
+            let key = t.get_field(self.key).cloned();
+            if run.min_key.is_none() {
+                run.min_key = key.clone();
+            }
+            run.max_key = key;
+        }
+        Ok(())
+    }
+
+    // helper method to save a spill file as the next run file
+    fn push(
+        &mut self,
+        dir: &Path,
+        name: &str,
+        file: &mut SpillFile,
+        min_key: Option<Field>,
+        max_key: Option<Field>,
+    ) -> Result<(), CrustyError> {
+        let file_name = PathBuf::from(format!("{}-{}.run", name, self.runs.len()));
+        file.save(dir.join(&file_name))?;
+        self.runs.push(RunState {
+            file: file_name,
+            rows: file.len(),
+            min_key,
+            max_key,
+            compression: file.compression(),
+        });
+        Ok(())
+    }
+
+    /// Open the run files, failing with a ValidationError if one does not hold the number of
+    /// tuples of its state, see SpillFile::open.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Checkpoint directory.
+    pub fn open_runs(&self, dir: &Path) -> Result<Vec<SpillFile>, CrustyError> {
+        self.runs
+            .iter()
+            .map(|run| SpillFile::open(dir.join(&run.file), None, run.compression, run.rows))
+            .collect()
+    }
+}
+
+/// Sorted runs of both sides of a join, see the module documentation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SortState {
+    /// Runs of the left child.
+    pub left: SideState,
+    /// Runs of the right child.
+    pub right: SideState,
+}
+
+impl SortState {
+    /// Write the state file of a checkpoint directory, replacing it.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Checkpoint directory.
+    pub fn save(&self, dir: &Path) -> Result<(), CrustyError> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| CrustyError::CrustyError(e.to_string()))?;
+        fs::write(dir.join(STATE_FILE), json)?;
+        Ok(())
+    }
+
+    /// Read the state file of a checkpoint directory.
+    ///
+    /// Fails with a ValidationError if the file is not a state.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Checkpoint directory.
+    pub fn load(dir: &Path) -> Result<Self, CrustyError> {
+        let json = fs::read(dir.join(STATE_FILE))?;
+        serde_json::from_slice(&json).map_err(|e| {
+            CrustyError::ValidationError(format!("{} is not a checkpoint: {}", dir.join(STATE_FILE).display(), e))
+        })
+    }
+}
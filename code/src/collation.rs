@@ -0,0 +1,77 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use crate::common::Field;
+
+/// How strings are compared, sorted and hashed by the joins.
+///
+/// A collation maps each field to a sort key such that two fields compare like their keys,
+/// the way databases build sort keys with strxfrm. Fields with equal keys are equal under
+/// the collation, so the keys are also what the hash join hashes. Integers are their own
+/// key under every collation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Collation {
+    /// Byte order of the strings.
+    #[default]
+    Binary,
+    /// Order of the lowercase strings, so "Ann" equals "ann".
+    CaseInsensitive,
+    /// Primary strength order of the root locale: accents, case and ligatures are
+    /// ignored, so "Émile" equals "emile" and "Straße" equals "strasse".
+    #[cfg(feature = "locale")]
+    Locale,
+}
+
+impl Collation {
+    /// Returns the sort key of a field.
+    ///
+    /// # Arguments
+    ///
+    /// * `field` - Field to find the key of.
+    pub fn sort_key<'a>(&self, field: &'a Field) -> Cow<'a, Field> {
+        match (self, field) {
+            (Collation::Binary, _) | (_, Field::IntField(_)) => Cow::Borrowed(field),
+            (Collation::CaseInsensitive, Field::StringField(s)) => Cow::Owned(Field::StringField(s.to_lowercase())),
+            #[cfg(feature = "locale")]
+            (Collation::Locale, Field::StringField(s)) => {
+                Cow::Owned(Field::StringField(deunicode::deunicode(s).to_lowercase()))
+            }
+        }
+    }
+
+    /// Compare two fields under the collation.
+    ///
+    /// # Arguments
+    ///
+    /// * `left` - Left field.
+    /// * `right` - Right field.
+    pub fn cmp(&self, left: &Field, right: &Field) -> Ordering {
+        match self {
+            Collation::Binary => left.cmp(right),
+            _ => self.sort_key(left).cmp(&self.sort_key(right)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // helper method to make a string field
+    fn string(s: &str) -> Field {
+        Field::StringField(s.to_string())
+    }
+
+    #[test]
+    fn orders_strings() {
+        assert_eq!(Collation::Binary.cmp(&string("Bob"), &string("ann")), Ordering::Less);
+        assert_eq!(Collation::CaseInsensitive.cmp(&string("Bob"), &string("ann")), Ordering::Greater);
+        assert_eq!(Collation::CaseInsensitive.cmp(&string("ANN"), &string("ann")), Ordering::Equal);
+        assert_eq!(Collation::CaseInsensitive.sort_key(&Field::IntField(3)).as_ref(), &Field::IntField(3));
+        #[cfg(feature = "locale")]
+        {
+            assert_eq!(Collation::Locale.cmp(&string("Émile"), &string("emile")), Ordering::Equal);
+            assert_eq!(Collation::Locale.cmp(&string("Straße"), &string("STRASSE")), Ordering::Equal);
+            assert_eq!(Collation::Locale.cmp(&string("élan"), &string("eve")), Ordering::Less);
+        }
+    }
+}
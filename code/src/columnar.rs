@@ -0,0 +1,469 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use crate::common::{CrustyError, Field, Tuple};
+use crate::config::CoreAffinity;
+use crate::dictionary::Dictionary;
+use crate::morsel::{default_workers, parallel_map_on};
+
+/// Tuples stored column by column.
+///
+/// The batch kernels below sort and join row ids by looking at the key column only, so
+/// wide tuples are moved once when the result is gathered instead of cloned at every
+/// compare-exchange.
+///
+/// String columns can be dictionary encoded, as in Arrow or Parquet: the column holds the
+/// IntField code of each value and the rows are decoded as they leave the batch. Codes of a
+/// sorted dictionary follow the order of their strings, so the kernels sort and merge the
+/// codes; the keys of an unsorted dictionary are decoded first.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ColumnarBatch {
+    /// Values of each field, one vector per column.
+    columns: Vec<Vec<Field>>,
+    /// Dictionary of each column, None if it is not encoded.
+    dictionaries: Vec<Option<Dictionary>>,
+    /// Number of rows.
+    rows: usize,
+}
+
+impl ColumnarBatch {
+    /// Create a batch from tuples, moving their fields into the columns.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuples` - Tuples of the batch, all with the same number of fields.
+    pub fn from_tuples(tuples: Vec<Tuple>) -> Self {
+        let width = tuples.first().map_or(0, |t| t.size());
+        let rows = tuples.len();
+        let mut columns: Vec<Vec<Field>> = (0..width).map(|_| Vec::with_capacity(rows)).collect();
+        for t in tuples {
+            for (column, field) in columns.iter_mut().zip(t.field_vals) {
+                column.push(field);
+            }
+        }
+        Self {
+            columns,
+            dictionaries: vec![None; width],
+            rows,
+        }
+    }
+
+    /// Returns the number of rows.
+    pub fn len(&self) -> usize {
+        self.rows
+    }
+
+    /// Returns true if the batch has no rows.
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+
+    /// Returns the number of columns.
+    pub fn width(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// Returns the values of a column, the codes of an encoded column, None if there is no
+    /// such column.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Index of the column.
+    pub fn column(&self, index: usize) -> Option<&[Field]> {
+        self.columns.get(index).map(Vec::as_slice)
+    }
+
+    /// Returns the dictionary of a column, None if it is not encoded.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Index of the column.
+    pub fn dictionary(&self, index: usize) -> Option<&Dictionary> {
+        self.dictionaries.get(index)?.as_ref()
+    }
+
+    /// Returns the values of a column, decoded if it is encoded, None if there is no such
+    /// column.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Index of the column.
+    pub fn decoded_column(&self, index: usize) -> Option<Cow<'_, [Field]>> {
+        let column = self.column(index)?;
+        Some(match self.dictionary(index) {
+            None => Cow::Borrowed(column),
+            Some(dict) => Cow::Owned(column.iter().map(|field| decode_field(Some(dict), field.clone())).collect()),
+        })
+    }
+
+    /// Returns the keys the kernels sort a column on: the codes of a sorted dictionary,
+    /// otherwise the values.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Index of the column.
+    pub fn key_column(&self, index: usize) -> Option<Cow<'_, [Field]>> {
+        match self.dictionary(index) {
+            Some(dict) if dict.is_sorted() => self.column(index).map(Cow::Borrowed),
+            _ => self.decoded_column(index),
+        }
+    }
+
+    /// Dictionary encode a string column.
+    ///
+    /// A sorted dictionary gives codes in the order of the strings, so the column can be
+    /// sorted on them; an unsorted one gives codes in the order the strings first appear
+    /// and skips sorting the distinct strings. Fails with an ExecutionError if the column
+    /// does not exist, is already encoded or holds a field that is not a string.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Index of the column.
+    /// * `sorted` - Whether to sort the dictionary.
+    pub fn encode_column(&mut self, index: usize, sorted: bool) -> Result<(), CrustyError> {
+        let (dict, mut codes) = self.column_codes(index)?;
+        let dict = match sorted {
+            true => {
+                let (global, mut remaps) = Dictionary::merge(std::slice::from_ref(&dict));
+                let remap = remaps.remove(0);
+                codes.iter_mut().for_each(|code| *code = remap[*code as usize]);
+                global
+            }
+            false => dict,
+        };
+        self.set_codes(index, codes, dict);
+        Ok(())
+    }
+
+    /// Dictionary encode the string key columns of two batches with one sorted dictionary,
+    /// so their codes compare like their strings, e.g. before join_batches.
+    ///
+    /// Fails with an ExecutionError as encode_column does.
+    ///
+    /// # Arguments
+    ///
+    /// * `left` - Left batch.
+    /// * `left_index` - Index of the left key column.
+    /// * `right` - Right batch.
+    /// * `right_index` - Index of the right key column.
+    pub fn encode_join_keys(left: &mut Self, left_index: usize, right: &mut Self, right_index: usize) -> Result<(), CrustyError> {
+        let (left_dict, left_codes) = left.column_codes(left_index)?;
+        let (right_dict, right_codes) = right.column_codes(right_index)?;
+        // the local dictionaries are merged as the partitions of dictionary::encode_partitions
+        let (global, remaps) = Dictionary::merge(&[left_dict, right_dict]);
+        let remapped = |codes: Vec<u32>, remap: &[u32]| codes.into_iter().map(|code| remap[code as usize]).collect();
+        left.set_codes(left_index, remapped(left_codes, &remaps[0]), global.clone());
+        right.set_codes(right_index, remapped(right_codes, &remaps[1]), global);
+        Ok(())
+    }
+
+    /// Replace the codes of an encoded column with their strings, a no-op on a column that
+    /// is not encoded.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Index of the column.
+    pub fn decode_column(&mut self, index: usize) {
+        if let Some(dict) = self.dictionaries.get_mut(index).and_then(Option::take) {
+            for field in self.columns[index].iter_mut() {
+                let code = std::mem::replace(field, Field::IntField(0));
+                *field = decode_field(Some(&dict), code);
+            }
+        }
+    }
+
+    // helper method to encode a column with a dictionary in order of appearance
+    fn column_codes(&self, index: usize) -> Result<(Dictionary, Vec<u32>), CrustyError> {
+        let column = match (self.column(index), self.dictionary(index)) {
+            (Some(column), None) => column,
+            (None, _) => return Err(CrustyError::ExecutionError(format!("batch has no column {}", index))),
+            (Some(_), Some(_)) => return Err(CrustyError::ExecutionError(format!("column {} is already encoded", index))),
+        };
+        let mut dict = Dictionary::new();
+        let codes = column
+            .iter()
+            .map(|field| match field {
+                Field::StringField(s) => Ok(dict.encode(s)),
+                _ => Err(CrustyError::ExecutionError(format!("field {} is not a string field", index))),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok((dict, codes))
+    }
+
+    // helper method to replace the values of a column with their codes
+    fn set_codes(&mut self, index: usize, codes: Vec<u32>, dict: Dictionary) {
+        self.columns[index] = codes.into_iter().map(|code| Field::IntField(code as i32)).collect();
+        self.dictionaries[index] = Some(dict);
+    }
+
+    /// Returns a copy of a row as a tuple, with its encoded fields decoded.
+    ///
+    /// # Arguments
+    ///
+    /// * `row` - Row id, below len.
+    pub fn row(&self, row: usize) -> Tuple {
+        Tuple::from_fields(
+            self.columns
+                .iter()
+                .zip(&self.dictionaries)
+                .map(|(column, dict)| decode_field(dict.as_ref(), column[row].clone()))
+                .collect(),
+        )
+    }
+
+    /// Move rows out of the batch as tuples, in the order of the row ids, with their
+    /// encoded fields decoded.
+    ///
+    /// Each row can be taken once; the fields left behind are placeholders.
+    ///
+    /// # Arguments
+    ///
+    /// * `row_ids` - Ids of the rows to take.
+    pub fn take_rows(&mut self, row_ids: &[usize]) -> Vec<Tuple> {
+        row_ids
+            .iter()
+            .map(|row| {
+                Tuple::from_fields(
+                    self.columns
+                        .iter_mut()
+                        .zip(&self.dictionaries)
+                        .map(|(column, dict)| decode_field(dict.as_ref(), std::mem::replace(&mut column[*row], Field::IntField(0))))
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+}
+
+// helper method to turn the code of an encoded field back into its string
+fn decode_field(dict: Option<&Dictionary>, field: Field) -> Field {
+    match (dict, &field) {
+        (Some(dict), Field::IntField(code)) => match dict.decode(*code as u32) {
+            Some(value) => Field::StringField(value.to_string()),
+            None => field,
+        },
+        _ => field,
+    }
+}
+
+/// Sort row ids into runs ordered on a key column, the runs in parallel.
+///
+/// Ties keep the order of the row ids.
+///
+/// # Arguments
+///
+/// * `keys` - Key of every row.
+/// * `run_size` - Number of rows in a run.
+/// * `cores` - Cores the sort workers are pinned to, unpinned if None.
+pub fn sort_row_ids(keys: &[Field], run_size: usize, cores: Option<CoreAffinity>) -> Vec<Vec<usize>> {
+    let ids: Vec<usize> = (0..keys.len()).collect();
+    let chunks: Vec<&[usize]> = ids.chunks(run_size.max(1)).collect();
+    parallel_map_on(chunks, default_workers(), cores, |chunk| {
+        let mut run = chunk.to_vec();
+        run.sort_by_key(|row| &keys[*row]);
+        run
+    })
+}
+
+/// Merge sorted runs of row ids into one run ordered on a key column.
+///
+/// Ties go to the earlier run, so merging runs of ascending row ids is stable.
+///
+/// # Arguments
+///
+/// * `keys` - Key of every row.
+/// * `runs` - Runs of row ids, each sorted on the key.
+pub fn merge_row_ids(keys: &[Field], runs: Vec<Vec<usize>>) -> Vec<usize> {
+    let mut res = Vec::with_capacity(runs.iter().map(Vec::len).sum());
+    let mut cursors: Vec<_> = runs.into_iter().map(|run| run.into_iter()).collect();
+    let mut heap = BinaryHeap::with_capacity(cursors.len());
+    for (run, cursor) in cursors.iter_mut().enumerate() {
+        if let Some(row) = cursor.next() {
+            heap.push(Reverse((&keys[row], run, row)));
+        }
+    }
+    while let Some(Reverse((_, run, row))) = heap.pop() {
+        res.push(row);
+        if let Some(next) = cursors[run].next() {
+            heap.push(Reverse((&keys[next], run, next)));
+        }
+    }
+    res
+}
+
+/// Merge join two runs of row ids on equal keys, returning the pairs of matching row ids.
+///
+/// Every pair of a many-to-many match is returned once, ordered on the key, then the left
+/// row, then the right row.
+///
+/// # Arguments
+///
+/// * `left_keys` - Key of every left row.
+/// * `left` - Left row ids sorted on the key.
+/// * `right_keys` - Key of every right row.
+/// * `right` - Right row ids sorted on the key.
+pub fn merge_join_row_ids(left_keys: &[Field], left: &[usize], right_keys: &[Field], right: &[usize]) -> Vec<(usize, usize)> {
+    let mut res = Vec::new();
+    let (mut l, mut r) = (0, 0);
+    while l < left.len() && r < right.len() {
+        let key = &left_keys[left[l]];
+        match key.cmp(&right_keys[right[r]]) {
+            std::cmp::Ordering::Less => l += 1,
+            std::cmp::Ordering::Greater => r += 1,
+            std::cmp::Ordering::Equal => {
+                let block_len = right[r..].iter().take_while(|row| right_keys[**row] == *key).count();
+                while l < left.len() && left_keys[left[l]] == *key {
+                    res.extend(right[r..r + block_len].iter().map(|row| (left[l], *row)));
+                    l += 1;
+                }
+                r += block_len;
+            }
+        }
+    }
+    res
+}
+
+/// Equi-join two batches, sorting and merging only their key columns.
+///
+/// Returns the joined tuples, left fields then right fields, ordered on the key. Fails
+/// with None if a key column does not exist.
+///
+/// Key columns encoded with the same sorted dictionary, see encode_join_keys, are sorted
+/// and merged on their codes; other encoded keys are decoded first.
+///
+/// # Arguments
+///
+/// * `left` - Left batch.
+/// * `left_index` - Index of the left key column.
+/// * `right` - Right batch.
+/// * `right_index` - Index of the right key column.
+/// * `run_size` - Number of rows sorted by each thread before the merge.
+pub fn join_batches(
+    left: &ColumnarBatch,
+    left_index: usize,
+    right: &ColumnarBatch,
+    right_index: usize,
+    run_size: usize,
+) -> Option<Vec<Tuple>> {
+    let shared = match (left.dictionary(left_index), right.dictionary(right_index)) {
+        (Some(left_dict), Some(right_dict)) => left_dict.is_sorted() && left_dict == right_dict,
+        _ => false,
+    };
+    let (left_keys, right_keys) = match shared {
+        true => (left.key_column(left_index)?, right.key_column(right_index)?),
+        false => (left.decoded_column(left_index)?, right.decoded_column(right_index)?),
+    };
+    let (left_keys, right_keys) = (&left_keys[..], &right_keys[..]);
+    let left_ids = merge_row_ids(left_keys, sort_row_ids(left_keys, run_size, None));
+    let right_ids = merge_row_ids(right_keys, sort_row_ids(right_keys, run_size, None));
+    let pairs = merge_join_row_ids(left_keys, &left_ids, right_keys, &right_ids);
+    Some(pairs.into_iter().map(|(l, r)| left.row(l).merge(&right.row(r))).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{CrustyError, SimplePredicateOp, TupleIterator};
+    use crate::generator::{create_vec_tuple, get_int_table_schema};
+    use crate::join::Join;
+    use crate::operators::compare_rows;
+
+    #[test]
+    fn sort_and_take() {
+        let tuples = create_vec_tuple(100, 3, 1000, 7);
+        let mut batch = ColumnarBatch::from_tuples(tuples.clone());
+        assert_eq!((batch.len(), batch.width()), (100, 3));
+        assert_eq!(batch.row(5), tuples[5]);
+
+        let keys = batch.column(1).unwrap().to_vec();
+        let ids = merge_row_ids(&keys, sort_row_ids(&keys, 16, None));
+        let sorted = batch.take_rows(&ids);
+        let mut expected = tuples;
+        expected.sort_by(|a, b| a.get_field(1).cmp(&b.get_field(1)));
+        // both sorts are stable
+        assert_eq!(sorted, expected);
+    }
+
+    fn string_tuples(n: usize, seed: usize) -> Vec<Tuple> {
+        let keys = ["kiwi", "fig", "pear", "apple", "lime"];
+        (0..n)
+            .map(|i| Tuple::new(vec![Field::IntField(i as i32), Field::StringField(keys[(i * 7 + seed) % keys.len()].to_string())]))
+            .collect()
+    }
+
+    #[test]
+    fn dictionary_encoded() -> Result<(), CrustyError> {
+        let tuples = string_tuples(50, 0);
+        let mut expected = tuples.clone();
+        expected.sort_by(|a, b| a.get_field(1).cmp(&b.get_field(1)));
+        for sorted in [true, false] {
+            let mut batch = ColumnarBatch::from_tuples(tuples.clone());
+            batch.encode_column(1, sorted)?;
+            assert_eq!(batch.dictionary(1).unwrap().is_sorted(), sorted);
+            assert_eq!(batch.column(1).unwrap()[0], Field::IntField(if sorted { 2 } else { 0 }));
+            assert_eq!(batch.row(3), tuples[3]);
+            // codes of the unsorted dictionary are decoded to be sorted
+            let keys = batch.key_column(1).unwrap();
+            assert_eq!(keys.iter().all(|key| matches!(key, Field::IntField(_))), sorted);
+            let ids = merge_row_ids(&keys, sort_row_ids(&keys, 8, None));
+            assert_eq!(batch.take_rows(&ids), expected);
+        }
+        let mut batch = ColumnarBatch::from_tuples(tuples.clone());
+        assert!(batch.encode_column(0, true).is_err());
+        batch.encode_column(1, true)?;
+        assert!(batch.encode_column(1, true).is_err());
+        batch.decode_column(1);
+        assert_eq!(batch, ColumnarBatch::from_tuples(tuples));
+        Ok(())
+    }
+
+    #[test]
+    fn join_dictionary_encoded() -> Result<(), CrustyError> {
+        let (left, right) = (string_tuples(40, 0), string_tuples(30, 2));
+        let plain = join_batches(&ColumnarBatch::from_tuples(left.clone()), 1, &ColumnarBatch::from_tuples(right.clone()), 1, 8).unwrap();
+        let (mut left_batch, mut right_batch) = (ColumnarBatch::from_tuples(left.clone()), ColumnarBatch::from_tuples(right.clone()));
+        // an unsorted left dictionary is decoded to join the plain right keys
+        left_batch.encode_column(1, false)?;
+        assert_eq!(join_batches(&left_batch, 1, &right_batch, 1, 8).unwrap(), plain);
+        // a shared sorted dictionary is joined on the codes
+        let mut left_batch = ColumnarBatch::from_tuples(left);
+        ColumnarBatch::encode_join_keys(&mut left_batch, 1, &mut right_batch, 1)?;
+        assert_eq!(left_batch.dictionary(1), right_batch.dictionary(1));
+        assert_eq!(join_batches(&left_batch, 1, &right_batch, 1, 8).unwrap(), plain);
+        Ok(())
+    }
+
+    #[test]
+    fn columnar_kernel_on_strings() {
+        use crate::config::SortConfig;
+        use crate::join::{form_runs, SortKernel};
+        let tuples = string_tuples(64, 1);
+        let runs = form_runs(SortKernel::Columnar, tuples.clone(), 1, &SortConfig::new(16, 16), 8);
+        assert_eq!(runs.iter().map(Vec::len).sum::<usize>(), tuples.len());
+        for run in runs {
+            assert!(run.windows(2).all(|w| w[0].get_field(1) <= w[1].get_field(1)));
+            assert!(run.iter().all(|t| tuples.contains(t)));
+        }
+    }
+
+    #[test]
+    fn join_matches_nested_loop() -> Result<(), CrustyError> {
+        let left = create_vec_tuple(300, 2, 1000, 1);
+        let right = create_vec_tuple(200, 2, 1000, 2);
+        let joined = join_batches(
+            &ColumnarBatch::from_tuples(left.clone()),
+            1,
+            &ColumnarBatch::from_tuples(right.clone()),
+            1,
+            32,
+        )
+        .unwrap();
+        assert!(joined.windows(2).all(|w| w[0].get_field(1) <= w[1].get_field(1)));
+
+        let scan = |tuples: Vec<Tuple>| Box::new(TupleIterator::new(tuples, get_int_table_schema(2)));
+        let mut oracle = Join::new(SimplePredicateOp::Equals, 1, 1, scan(left), scan(right))?;
+        compare_rows(&mut oracle, &mut TupleIterator::new(joined, get_int_table_schema(4)))?;
+        assert!(join_batches(&ColumnarBatch::default(), 0, &ColumnarBatch::default(), 0, 4).is_none());
+        Ok(())
+    }
+}
@@ -1,8 +1,11 @@
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{fmt, io};
 use std::cmp::{max, min};
+use std::ops::RangeInclusive;
 use std::collections::HashMap;
 use std::error::Error;
+use crate::collation::Collation;
+use crate::metrics::OpMetrics;
 
 /// Predicate expression.
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -47,6 +50,20 @@ impl SimplePredicateOp {
         }
     }
 
+    /// Do predicate comparison of two fields under a collation.
+    ///
+    /// # Arguments
+    ///
+    /// * `left_field` - Left field of the predicate.
+    /// * `right_field` - Right field of the predicate.
+    /// * `collation` - How strings are compared.
+    pub fn compare_collated(&self, left_field: &Field, right_field: &Field, collation: Collation) -> bool {
+        match collation {
+            Collation::Binary => self.compare(left_field, right_field),
+            _ => self.compare(&collation.sort_key(left_field), &collation.sort_key(right_field)),
+        }
+    }
+
     /// Flip the operator.
     pub fn flip(&self) -> Self {
         match self {
@@ -59,7 +76,7 @@ impl SimplePredicateOp {
     }
 }
 /// Operators for simple predicates
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum SimplePredicateOp {
     Equals,
     GreaterThan,
@@ -151,6 +168,19 @@ impl From<io::Error> for CrustyError {
 impl Error for CrustyError {}
 
 
+/// Returns the error of an operator used while it is not open.
+///
+/// With the `panic-not-open` feature it panics instead, as the operators used to.
+pub fn not_open<T>() -> Result<T, CrustyError> {
+    if cfg!(feature = "panic-not-open") {
+        panic!("Operator has not been opened")
+    }
+    Err(CrustyError::ExecutionError(String::from("operator not open")))
+}
+
+/// Number of bytes reserved for the contents of a string field in a fixed-size row.
+pub const STRING_CAPACITY: usize = 128;
+
 /// Enumerate the supported dtypes.
 #[derive(PartialEq, Serialize, Deserialize, Clone, Debug)]
 pub enum DataType {
@@ -176,7 +206,7 @@ impl Field {
                 let s_len: usize = s.len();
                 let mut result = s_len.to_le_bytes().to_vec();
                 let mut s_bytes = s.clone().into_bytes();
-                let padding_len: usize = 128 - s_bytes.len();
+                let padding_len: usize = STRING_CAPACITY - s_bytes.len();
                 let pad = vec![0; padding_len];
                 s_bytes.extend(&pad);
                 result.extend(s_bytes);
@@ -196,7 +226,7 @@ impl Field {
     /// Unwraps string fields.
     pub fn unwrap_string_field(&self) -> &str {
         match self {
-            Field::StringField(s) => &s,
+            Field::StringField(s) => s,
             _ => panic!("Expected String"),
         }
     }
@@ -211,7 +241,7 @@ impl fmt::Display for Field {
 }
 
 /// Aggregation operations.
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum AggOp {
     Avg,
     Count,
@@ -317,7 +347,7 @@ impl FieldIdentifier {
 
     /// Returns the aggregate operator.
     pub fn agg_op(&self) -> Option<AggOp> {
-        self.op.clone()
+        self.op
     }
 
     /// Set an alias for the field identifier.
@@ -346,11 +376,26 @@ impl FieldIdentifier {
     }
 }
 
+/// Number of fields a tuple holds without a heap allocation, with the smallvec feature.
+pub const INLINE_FIELDS: usize = 4;
+
+/// Field values of a tuple, stored inline up to INLINE_FIELDS fields.
+///
+/// Inline fields save the allocation of every tuple but make tuples larger to move: on the
+/// join of 2^17 tuples per side measured in the README, m-way and radix were slower and
+/// m-pass about the same, so it is opt-in.
+#[cfg(feature = "smallvec")]
+pub type Fields = smallvec::SmallVec<[Field; INLINE_FIELDS]>;
+
+/// Field values of a tuple, see the smallvec feature for inline storage.
+#[cfg(not(feature = "smallvec"))]
+pub type Fields = Vec<Field>;
+
 /// Tuple type.
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Hash)]
 pub struct Tuple {
     /// Tuple data.
-    pub field_vals: Vec<Field>,
+    pub field_vals: Fields,
 }
 impl Tuple {
     /// Create a new tuple with the given data.
@@ -358,7 +403,19 @@ impl Tuple {
     /// # Arguments
     ///
     /// * `field_vals` - Field values of the tuple.
+    // the conversion is the identity without the smallvec feature
+    #[allow(clippy::useless_conversion)]
     pub fn new(field_vals: Vec<Field>) -> Self {
+        Self { field_vals: field_vals.into() }
+    }
+
+    /// Create a new tuple from field values already in their inline storage, e.g. collected
+    /// from an iterator, without going through a Vec.
+    ///
+    /// # Arguments
+    ///
+    /// * `field_vals` - Field values of the tuple.
+    pub fn from_fields(field_vals: Fields) -> Self {
         Self { field_vals }
     }
 
@@ -371,6 +428,16 @@ impl Tuple {
         self.field_vals.get(i)
     }
 
+    /// Get the field of an attribute, None if the schema has no such attribute.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the tuple.
+    /// * `name` - Name of the attribute.
+    pub fn get_field_by_name(&self, schema: &TableSchema, name: &str) -> Option<&Field> {
+        schema.index_of(name).ok().and_then(|i| self.get_field(i))
+    }
+
     /// Update the index at field.
     ///
     /// # Arguments
@@ -401,9 +468,10 @@ impl Tuple {
     ///
     /// * `other` - Other tuple to append.
     pub fn merge(&self, other: &Self) -> Self {
-        let mut fields = self.field_vals.clone();
-        fields.append(&mut other.field_vals.clone());
-        Self::new(fields)
+        let mut fields = Fields::with_capacity(self.size() + other.size());
+        fields.extend(self.field_vals.iter().cloned());
+        fields.extend(other.field_vals.iter().cloned());
+        Self::from_fields(fields)
     }
 
     pub fn get_bytes(&self) -> Vec<u8> {
@@ -441,6 +509,152 @@ impl fmt::Display for Tuple {
     }
 }
 
+/// A tuple read through its schema, so fields are found by attribute name.
+#[derive(Debug, Clone, Copy)]
+pub struct SchemaBoundTuple<'a> {
+    /// Tuple read.
+    tuple: &'a Tuple,
+    /// Schema of the tuple.
+    schema: &'a TableSchema,
+}
+
+impl<'a> SchemaBoundTuple<'a> {
+    /// Bind a tuple to its schema.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple` - Tuple read.
+    /// * `schema` - Schema of the tuple.
+    pub fn new(tuple: &'a Tuple, schema: &'a TableSchema) -> Self {
+        Self { tuple, schema }
+    }
+
+    /// Returns the field of an attribute.
+    ///
+    /// Fails with a ValidationError if the schema has no such attribute, or an
+    /// ExecutionError if the tuple is shorter than its schema.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the attribute.
+    pub fn get(&self, name: &str) -> Result<&'a Field, CrustyError> {
+        let i = self.schema.index_of(name)?;
+        self.tuple
+            .get_field(i)
+            .ok_or_else(|| CrustyError::ExecutionError(format!("tuple has no field {} for {}", i, name)))
+    }
+
+    /// Returns the integer of an attribute, see get.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the attribute.
+    pub fn get_int(&self, name: &str) -> Result<i32, CrustyError> {
+        match self.get(name)? {
+            Field::IntField(x) => Ok(*x),
+            _ => Err(CrustyError::ExecutionError(format!("{} is not an integer", name))),
+        }
+    }
+
+    /// Returns the string of an attribute, see get.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the attribute.
+    pub fn get_str(&self, name: &str) -> Result<&'a str, CrustyError> {
+        match self.get(name)? {
+            Field::StringField(s) => Ok(s),
+            _ => Err(CrustyError::ExecutionError(format!("{} is not a string", name))),
+        }
+    }
+
+    /// Returns the tuple.
+    pub fn tuple(&self) -> &'a Tuple {
+        self.tuple
+    }
+
+    /// Returns the schema.
+    pub fn schema(&self) -> &'a TableSchema {
+        self.schema
+    }
+}
+
+/// A joined row borrowed from the left and right tuples it comes from, so it can be
+/// compared or consumed without copying their fields.
+///
+/// Materialize it with to_tuple only where an owned Tuple is needed, e.g. as the output
+/// of an operator.
+#[derive(Debug, Clone, Copy)]
+pub struct TupleRef<'a> {
+    /// Left tuple.
+    left: &'a Tuple,
+    /// Right tuple, its fields follow the left ones.
+    right: &'a Tuple,
+    /// Indices of the fields of the row among the left fields followed by the right fields,
+    /// all if None.
+    projection: Option<&'a [usize]>,
+}
+
+impl<'a> TupleRef<'a> {
+    /// View a left and a right tuple as their joined row.
+    ///
+    /// # Arguments
+    ///
+    /// * `left` - Left tuple.
+    /// * `right` - Right tuple.
+    /// * `projection` - Fields of the row among the left fields followed by the right
+    ///   fields, all if None.
+    pub fn new(left: &'a Tuple, right: &'a Tuple, projection: Option<&'a [usize]>) -> Self {
+        Self { left, right, projection }
+    }
+
+    /// Returns the left tuple of the row.
+    pub fn left(&self) -> &'a Tuple {
+        self.left
+    }
+
+    /// Returns the right tuple of the row.
+    pub fn right(&self) -> &'a Tuple {
+        self.right
+    }
+
+    /// Get the field at index, None if the row has no such field.
+    ///
+    /// # Arguments
+    ///
+    /// * `i` - Index of the field in the row.
+    pub fn get_field(&self, i: usize) -> Option<&'a Field> {
+        let i = match self.projection {
+            Some(columns) => *columns.get(i)?,
+            None => i,
+        };
+        match self.left.get_field(i) {
+            Some(field) => Some(field),
+            None => self.right.get_field(i - self.left.size()),
+        }
+    }
+
+    /// Return the number of fields of the row.
+    pub fn size(&self) -> usize {
+        match self.projection {
+            Some(columns) => columns.len(),
+            None => self.left.size() + self.right.size(),
+        }
+    }
+
+    /// Returns an iterator over the field values of the row.
+    pub fn field_vals(&self) -> impl Iterator<Item = &'a Field> + '_ {
+        (0..self.size()).filter_map(|i| self.get_field(i))
+    }
+
+    /// Copy the fields of the row into an owned tuple.
+    pub fn to_tuple(&self) -> Tuple {
+        match self.projection {
+            None => self.left.merge(self.right),
+            Some(_) => Tuple::from_fields(self.field_vals().cloned().collect()),
+        }
+    }
+}
 
 pub type ContainerId = u16;
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
@@ -454,6 +668,18 @@ pub enum Constraint {
     NotNullFKey(ContainerId),
 }
 
+impl Constraint {
+    /// Returns whether every tuple must have a value of the attribute.
+    pub fn is_not_null(&self) -> bool {
+        matches!(self, Constraint::PrimaryKey | Constraint::NotNull | Constraint::UniqueNotNull | Constraint::NotNullFKey(_))
+    }
+
+    /// Returns whether no two tuples may have the same value of the attribute.
+    pub fn is_unique(&self) -> bool {
+        matches!(self, Constraint::PrimaryKey | Constraint::Unique | Constraint::UniqueNotNull)
+    }
+}
+
 
 /// Handle attributes. Pairs the name with the dtype.
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
@@ -464,6 +690,9 @@ pub struct Attribute {
     pub dtype: DataType,
     /// Attribute constraint
     pub constraint: Constraint,
+    /// Whether tuples may have no value of the attribute, e.g. the padded side of an outer join.
+    #[serde(default)]
+    pub nullable: bool,
 }
 impl Attribute {
     /// Create a new attribute with the given name and dtype.
@@ -473,12 +702,12 @@ impl Attribute {
     /// * `name` - Name of the attribute.
     /// * `dtype` - Dtype of the attribute.
     // pub fn new(name: String, dtype: DataType) -> Self { Self { name, dtype, is_pk: false } }
-
     pub fn new(name: String, dtype: DataType) -> Self {
         Self {
             name,
             dtype,
             constraint: Constraint::None,
+            nullable: false,
         }
     }
 
@@ -487,6 +716,7 @@ impl Attribute {
             name,
             dtype,
             constraint,
+            nullable: false,
         }
     }
 
@@ -495,9 +725,15 @@ impl Attribute {
             name,
             dtype,
             constraint: Constraint::PrimaryKey,
+            nullable: false,
         }
     }
 
+    /// Returns whether tuples may have no value of the attribute.
+    pub fn is_nullable(&self) -> bool {
+        self.nullable
+    }
+
     /// Returns the name of the attribute.
     pub fn name(&self) -> &str {
         &self.name
@@ -508,12 +744,14 @@ impl Attribute {
         &self.dtype
     }
 
-    // TODO(williamma12): Where does the 132 come from?
     /// Returns the length of the dtype in bytes.
+    ///
+    /// A string takes a 4 byte length followed by STRING_CAPACITY bytes of contents, see
+    /// TableSchema::serialize_row.
     pub fn get_byte_len(&self) -> usize {
         match self.dtype {
             DataType::Int => 4,
-            DataType::String => 132,
+            DataType::String => 4 + STRING_CAPACITY,
         }
     }
 }
@@ -595,6 +833,19 @@ impl TableSchema {
         self.name_map.get(name)
     }
 
+    /// Get the index of the attribute, failing with a ValidationError if there is none.
+    ///
+    /// The path every lookup by name goes through: tuples, join predicates and projections.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the attribute to get the index for.
+    pub fn index_of(&self, name: &str) -> Result<usize, CrustyError> {
+        self.get_field_index(name)
+            .copied()
+            .ok_or_else(|| CrustyError::ValidationError(format!("no field is named {}", name)))
+    }
+
     /// Returns attribute(s) that are primary keys
     ///
     ///
@@ -635,6 +886,54 @@ impl TableSchema {
         Self::new(attrs)
     }
 
+    /// Merge two schemas into the schema of an outer join, marking the attributes of the
+    /// sides padded for unmatched tuples as nullable.
+    ///
+    /// A padded side has no value for the rows of an unmatched tuple of the other side, so
+    /// ConstraintValidator rejects a NotNull constraint on its attributes.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Other schema to add to current schema.
+    /// * `pad_left` - Whether the current side is padded, as in a right or full outer join.
+    /// * `pad_right` - Whether the other side is padded, as in a left or full outer join.
+    pub fn merge_outer(&self, other: &Self, pad_left: bool, pad_right: bool) -> Self {
+        let padded = |schema: &Self, padded: bool| {
+            schema.attributes.iter().map(|attr| Attribute {
+                nullable: attr.nullable || padded,
+                ..attr.clone()
+            }).collect::<Vec<_>>()
+        };
+        let mut attrs = padded(self, pad_left);
+        attrs.append(&mut padded(other, pad_right));
+        Self::new(attrs)
+    }
+
+    /// Merge two schemas into one, prefixing the attribute names with table aliases.
+    ///
+    /// Attributes are named alias.name, or alias.i after their index i in their schema if
+    /// they have no name, so the merged names are unique when the aliases differ.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Other schema to add to current schema.
+    /// * `left_alias` - Alias of the current schema.
+    /// * `right_alias` - Alias of the other schema.
+    pub fn merge_qualified(&self, other: &Self, left_alias: &str, right_alias: &str) -> Self {
+        let qualify = |schema: &Self, alias: &str| {
+            schema.attributes.iter().enumerate().map(|(i, attr)| {
+                let name = if attr.name.is_empty() { i.to_string() } else { attr.name.clone() };
+                Attribute {
+                    name: format!("{}.{}", alias, name),
+                    ..attr.clone()
+                }
+            }).collect::<Vec<_>>()
+        };
+        let mut attrs = qualify(self, left_alias);
+        attrs.append(&mut qualify(other, right_alias));
+        Self::new(attrs)
+    }
+
     /// Returns the length of the schema.
     pub fn size(&self) -> usize {
         self.attributes.len()
@@ -648,6 +947,95 @@ impl TableSchema {
         }
         total
     }
+
+    /// Returns the byte offset of every attribute in a fixed-size row of the schema.
+    pub fn field_offsets(&self) -> Vec<usize> {
+        let mut offsets = Vec::with_capacity(self.attributes.len());
+        let mut offset = 0;
+        for attr in self.attributes.iter() {
+            offsets.push(offset);
+            offset += attr.get_byte_len();
+        }
+        offsets
+    }
+
+    /// Serialize a tuple into a row of exactly byte_size bytes.
+    ///
+    /// Each attribute starts at its offset of field_offsets. Integers are 4 bytes little
+    /// endian; strings are a 4 byte little endian length then their contents padded with
+    /// zeros to STRING_CAPACITY bytes. Fails with a ValidationError if the tuple does not
+    /// match the schema or a string is longer than STRING_CAPACITY bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple` - Tuple to serialize.
+    pub fn serialize_row(&self, tuple: &Tuple) -> Result<Vec<u8>, CrustyError> {
+        if tuple.size() != self.size() {
+            return Err(CrustyError::ValidationError(format!(
+                "tuple has {} fields, the schema has {}",
+                tuple.size(),
+                self.size()
+            )));
+        }
+        let mut row = Vec::with_capacity(self.byte_size());
+        for (i, (attr, field)) in self.attributes.iter().zip(tuple.field_vals()).enumerate() {
+            match (attr.dtype(), field) {
+                (DataType::Int, Field::IntField(x)) => row.extend_from_slice(&x.to_le_bytes()),
+                (DataType::String, Field::StringField(s)) if s.len() <= STRING_CAPACITY => {
+                    row.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                    row.extend_from_slice(s.as_bytes());
+                    row.resize(row.len() + STRING_CAPACITY - s.len(), 0);
+                }
+                (DataType::String, Field::StringField(s)) => {
+                    return Err(CrustyError::ValidationError(format!(
+                        "field {} has {} bytes, more than {}",
+                        i,
+                        s.len(),
+                        STRING_CAPACITY
+                    )))
+                }
+                (dtype, field) => {
+                    return Err(CrustyError::ValidationError(format!("field {} is {}, expected {:?}", i, field, dtype)))
+                }
+            }
+        }
+        Ok(row)
+    }
+
+    /// Deserialize a row written by serialize_row back into a tuple.
+    ///
+    /// Fails with a ValidationError if the row is not byte_size bytes long or holds an
+    /// invalid string.
+    ///
+    /// # Arguments
+    ///
+    /// * `row` - Bytes of the row.
+    pub fn deserialize_row(&self, row: &[u8]) -> Result<Tuple, CrustyError> {
+        if row.len() != self.byte_size() {
+            return Err(CrustyError::ValidationError(format!(
+                "row has {} bytes, the schema has {}",
+                row.len(),
+                self.byte_size()
+            )));
+        }
+        let mut fields = Fields::with_capacity(self.size());
+        for (i, (attr, offset)) in self.attributes.iter().zip(self.field_offsets()).enumerate() {
+            let int = |at: usize| u32::from_le_bytes(row[at..at + 4].try_into().unwrap());
+            match attr.dtype() {
+                DataType::Int => fields.push(Field::IntField(int(offset) as i32)),
+                DataType::String => {
+                    let len = int(offset) as usize;
+                    let bytes = row[offset + 4..offset + 4 + STRING_CAPACITY]
+                        .get(..len)
+                        .ok_or_else(|| CrustyError::ValidationError(format!("field {} has length {}", i, len)))?;
+                    let s = String::from_utf8(bytes.to_vec())
+                        .map_err(|_| CrustyError::ValidationError(format!("field {} is not utf-8", i)))?;
+                    fields.push(Field::StringField(s));
+                }
+            }
+        }
+        Ok(Tuple::from_fields(fields))
+    }
 }
 
 
@@ -659,9 +1047,9 @@ pub trait OpIterator {
     ///
     /// Returns None when iteration is finished.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// Panic if iterator is not open.
+    /// Fails with an ExecutionError if iterator is not open.
     fn next(&mut self) -> Result<Option<Tuple>, CrustyError>;
 
     /// Closes the iterator.
@@ -669,18 +1057,190 @@ pub trait OpIterator {
 
     /// Returns the iterator to the start.
     ///
-    /// Returns None when iteration is finished.
+    /// # Errors
     ///
-    /// # Panics
-    ///
-    /// Panic if iterator is not open.
+    /// Fails with an ExecutionError if iterator is not open.
     fn rewind(&mut self) -> Result<(), CrustyError>;
 
     /// Returns the schema associated with this OpIterator.
     fn get_schema(&self) -> &TableSchema;
+
+    /// Asks the iterator to produce its output sorted ascending on a field.
+    ///
+    /// Returns true if the iterator agrees, in which case the output after the next
+    /// open is sorted on the field. Iterators that cannot provide the order return false.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Index of the field to sort on.
+    fn request_order(&mut self, index: usize) -> bool {
+        self.sort_order().is_some_and(|order| order.first() == Some(&index))
+    }
+
+    /// Asks the iterator to skip the tuples whose field lies outside a range, as its consumer
+    /// would drop them, e.g. right tuples of an equi-join outside the left key range.
+    ///
+    /// Returns true if the iterator agrees, in which case the output after the next open or
+    /// rewind holds only the tuples with the field in the range. None lifts a range asked
+    /// before.
+    /// Iterators that cannot skip tuples return false and keep returning all of them.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Index of the field.
+    /// * `range` - Values of the field to keep, if any.
+    fn request_key_range(&mut self, index: usize, range: Option<RangeInclusive<Field>>) -> bool {
+        let _ = (index, range);
+        false
+    }
+
+    /// Returns the fields the output is sorted ascending on, most significant first, if
+    /// the iterator always produces that order.
+    ///
+    /// Consumers such as the sort-merge join skip sorting on a leading field of the order.
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        None
+    }
+
+    /// Returns the name of the operator in execution trees, its type name by default.
+    fn name(&self) -> String {
+        let name = std::any::type_name::<Self>();
+        let name = name.split('<').next().unwrap_or(name);
+        name.rsplit("::").next().unwrap_or(name).to_string()
+    }
+
+    /// Returns the metrics collected since the last open.
+    fn metrics(&self) -> OpMetrics {
+        OpMetrics::default()
+    }
+
+    /// Returns the children of the operator, left to right.
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        Vec::new()
+    }
+}
+
+// Forwards every call to the operator behind a handle, so operators can own their
+// children or borrow them.
+macro_rules! forward_op_iterator {
+    () => {
+        fn open(&mut self) -> Result<(), CrustyError> {
+            (**self).open()
+        }
+
+        fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+            (**self).next()
+        }
+
+        fn close(&mut self) -> Result<(), CrustyError> {
+            (**self).close()
+        }
+
+        fn rewind(&mut self) -> Result<(), CrustyError> {
+            (**self).rewind()
+        }
+
+        fn get_schema(&self) -> &TableSchema {
+            (**self).get_schema()
+        }
+
+        fn request_order(&mut self, index: usize) -> bool {
+            (**self).request_order(index)
+        }
+
+        fn request_key_range(&mut self, index: usize, range: Option<RangeInclusive<Field>>) -> bool {
+            (**self).request_key_range(index, range)
+        }
+
+        fn sort_order(&self) -> Option<Vec<usize>> {
+            (**self).sort_order()
+        }
+
+        fn name(&self) -> String {
+            (**self).name()
+        }
+
+        fn metrics(&self) -> OpMetrics {
+            (**self).metrics()
+        }
+
+        fn children(&self) -> Vec<&dyn OpIterator> {
+            (**self).children()
+        }
+    };
+}
+
+/// An owned child.
+impl<T: OpIterator + ?Sized> OpIterator for Box<T> {
+    forward_op_iterator!();
+}
+
+/// A borrowed child, left to its owner once the parent is dropped.
+impl<T: OpIterator + ?Sized> OpIterator for &mut T {
+    forward_op_iterator!();
 }
 
 
+/// What a scan does with tuples that do not match its schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaPolicy {
+    /// Return the tuples as they are.
+    Ignore,
+    /// Fail with a ValidationError.
+    Error,
+    /// Drop the fields past the end of the schema.
+    Truncate,
+    /// Drop the fields past the end of the schema and fill missing ones with defaults.
+    Pad,
+}
+impl SchemaPolicy {
+    /// Apply the policy to a tuple.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple` - Tuple to check.
+    /// * `schema` - Schema the tuple should match.
+    pub fn apply(&self, mut tuple: Tuple, schema: &TableSchema) -> Result<Tuple, CrustyError> {
+        match self {
+            SchemaPolicy::Ignore => {}
+            SchemaPolicy::Error => {
+                if tuple.size() != schema.size() {
+                    return Err(CrustyError::ValidationError(format!(
+                        "tuple has {} fields but schema has {} attributes",
+                        tuple.size(),
+                        schema.size()
+                    )));
+                }
+                for (i, (field, attr)) in tuple.field_vals().zip(schema.attributes()).enumerate() {
+                    let dtype = match field {
+                        Field::IntField(_) => DataType::Int,
+                        Field::StringField(_) => DataType::String,
+                    };
+                    if &dtype != attr.dtype() {
+                        return Err(CrustyError::ValidationError(format!(
+                            "field {} is {:?} but attribute is {:?}",
+                            i,
+                            dtype,
+                            attr.dtype()
+                        )));
+                    }
+                }
+            }
+            SchemaPolicy::Truncate => tuple.field_vals.truncate(schema.size()),
+            SchemaPolicy::Pad => {
+                tuple.field_vals.truncate(schema.size());
+                for attr in schema.attributes().skip(tuple.size()) {
+                    tuple.field_vals.push(match attr.dtype() {
+                        DataType::Int => Field::IntField(0),
+                        DataType::String => Field::StringField(String::new()),
+                    });
+                }
+            }
+        }
+        Ok(tuple)
+    }
+}
+
 /// Iterator over a Vec of tuples, mainly used for testing.
 pub struct TupleIterator {
     /// Tuples to iterate over.
@@ -689,10 +1249,14 @@ pub struct TupleIterator {
     schema: TableSchema,
     /// Current tuple in iteration.
     index: Option<usize>,
+    /// What to do with tuples that do not match the schema.
+    policy: SchemaPolicy,
 }
 impl TupleIterator {
     /// Create a new tuple iterator over a set of results.
     ///
+    /// Tuples are returned as they are, even if they do not match the schema.
+    ///
     /// # Arguments
     ///
     /// * `tuples` - Tuples to iterate over.
@@ -702,8 +1266,18 @@ impl TupleIterator {
             index: None,
             tuples,
             schema,
+            policy: SchemaPolicy::Ignore,
         }
     }
+
+    /// Set what to do with tuples that do not match the schema.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - Policy applied to each tuple as it is scanned.
+    pub fn set_schema_policy(&mut self, policy: SchemaPolicy) {
+        self.policy = policy;
+    }
 }
 impl OpIterator for TupleIterator {
     /// Opens the iterator without returning a tuple.
@@ -712,19 +1286,22 @@ impl OpIterator for TupleIterator {
         Ok(())
     }
 
-    /// Retrieves the next tuple in the iterator.
+    /// Retrieves the next tuple in the iterator, applying the schema policy to it.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// Panics if the TupleIterator has not been opened.
+    /// Fails with an ExecutionError if the TupleIterator has not been opened.
     fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
         let i = match self.index {
-            None => panic!("Operator has not been opened"),
+            None => return not_open(),
             Some(i) => i,
         };
         let tuple = self.tuples.get(i);
         self.index = Some(i + 1);
-        Ok(tuple.cloned())
+        match tuple {
+            None => Ok(None),
+            Some(t) => self.policy.apply(t.clone(), &self.schema).map(Some),
+        }
     }
 
     /// Closes the tuple iterator.
@@ -735,12 +1312,12 @@ impl OpIterator for TupleIterator {
 
     /// Make iterator point to the first tuple again.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// Panics if the TupleIterator has not been opened.
+    /// Fails with an ExecutionError if the TupleIterator has not been opened.
     fn rewind(&mut self) -> Result<(), CrustyError> {
         if self.index.is_none() {
-            panic!("Operator has not been opened")
+            return not_open();
         }
         self.close()?;
         self.open()
@@ -750,4 +1327,127 @@ impl OpIterator for TupleIterator {
     fn get_schema(&self) -> &TableSchema {
         &self.schema
     }
+
+    /// Agrees to a sort order only if the tuples are already sorted on the field.
+    fn request_order(&mut self, index: usize) -> bool {
+        self.tuples
+            .windows(2)
+            .all(|w| w[0].get_field(index) <= w[1].get_field(index))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn scan(policy: SchemaPolicy) -> TupleIterator {
+        let tuples = vec![
+            Tuple::new(vec![Field::IntField(1), Field::IntField(2), Field::IntField(3)]),
+            Tuple::new(vec![Field::IntField(4)]),
+        ];
+        let schema = TableSchema::from_vecs(vec!["a", "b"], vec![DataType::Int, DataType::String]);
+        let mut scan = TupleIterator::new(tuples, schema);
+        scan.set_schema_policy(policy);
+        scan
+    }
+
+    #[test]
+    fn fixed_size_rows() -> Result<(), CrustyError> {
+        let schema = TableSchema::from_vecs(vec!["a", "s", "b"], vec![DataType::Int, DataType::String, DataType::Int]);
+        assert_eq!(schema.field_offsets(), vec![0, 4, 136]);
+        assert_eq!(schema.byte_size(), 140);
+        let t = Tuple::new(vec![Field::IntField(-5), Field::StringField(String::from("héllo")), Field::IntField(9)]);
+        let row = schema.serialize_row(&t)?;
+        assert_eq!(row.len(), schema.byte_size());
+        assert_eq!(&row[4..8], &6u32.to_le_bytes());
+        assert_eq!(&row[136..], &9i32.to_le_bytes());
+        assert_eq!(schema.deserialize_row(&row)?, t);
+
+        let long = Tuple::new(vec![Field::IntField(0), Field::StringField("x".repeat(129)), Field::IntField(0)]);
+        assert!(matches!(schema.serialize_row(&long), Err(CrustyError::ValidationError(_))));
+        let swapped = Tuple::new(vec![Field::StringField(String::new()), Field::IntField(0), Field::IntField(0)]);
+        assert!(schema.serialize_row(&swapped).is_err());
+        assert!(schema.deserialize_row(&row[1..]).is_err());
+        let mut corrupt = row;
+        corrupt[4] = 200;
+        assert!(schema.deserialize_row(&corrupt).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn merge_qualified() {
+        let left = TableSchema::from_vecs(vec!["id", "name"], vec![DataType::Int, DataType::String]);
+        let right = TableSchema::new(vec![Attribute::new(String::new(), DataType::Int), Attribute::new(String::from("id"), DataType::Int)]);
+        let merged = left.merge_qualified(&right, "l", "r");
+        assert_eq!(merged.get_field_index("l.id"), Some(&0));
+        assert_eq!(merged.get_field_index("r.0"), Some(&2));
+        assert_eq!(merged.get_field_index("r.id"), Some(&3));
+        assert_eq!(merged.get_attribute(1).unwrap().dtype(), &DataType::String);
+        // the unqualified merge keeps only the last of the duplicate names
+        assert_eq!(left.merge(&right).get_field_index("id"), Some(&3));
+    }
+
+    #[test]
+    fn field_by_name() {
+        let schema = TableSchema::from_vecs(vec!["id", "name"], vec![DataType::Int, DataType::String]);
+        let t = Tuple::new(vec![Field::IntField(7), Field::StringField(String::from("ann"))]);
+        assert_eq!(t.get_field_by_name(&schema, "name"), t.get_field(1));
+        assert_eq!(t.get_field_by_name(&schema, "age"), None);
+
+        let bound = SchemaBoundTuple::new(&t, &schema);
+        assert_eq!(bound.get_int("id"), Ok(7));
+        assert_eq!(bound.get_str("name"), Ok("ann"));
+        assert!(matches!(bound.get("age"), Err(CrustyError::ValidationError(_))));
+        assert!(matches!(bound.get_int("name"), Err(CrustyError::ExecutionError(_))));
+        let short = Tuple::new(vec![Field::IntField(7)]);
+        assert!(matches!(SchemaBoundTuple::new(&short, &schema).get("name"), Err(CrustyError::ExecutionError(_))));
+    }
+
+    #[test]
+    fn tuple_ref_view() {
+        let left = Tuple::new(vec![Field::IntField(1), Field::StringField(String::from("a"))]);
+        let right = Tuple::new(vec![Field::IntField(2)]);
+        let row = TupleRef::new(&left, &right, None);
+        assert_eq!((row.size(), row.get_field(2), row.get_field(3)), (3, Some(&Field::IntField(2)), None));
+        assert_eq!(row.to_tuple(), left.merge(&right));
+        // a projection reads the fields in its order
+        let columns = [2, 1];
+        let row = TupleRef::new(&left, &right, Some(&columns));
+        assert_eq!(row.field_vals().collect::<Vec<_>>(), vec![&Field::IntField(2), &Field::StringField(String::from("a"))]);
+        assert_eq!(row.to_tuple().size(), 2);
+    }
+
+    #[test]
+    fn schema_policy_ignore() -> Result<(), CrustyError> {
+        let mut op = scan(SchemaPolicy::Ignore);
+        op.open()?;
+        assert_eq!(op.next()?.unwrap().size(), 3);
+        assert_eq!(op.next()?.unwrap().size(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn schema_policy_error() -> Result<(), CrustyError> {
+        let mut op = scan(SchemaPolicy::Error);
+        op.open()?;
+        assert!(matches!(op.next(), Err(CrustyError::ValidationError(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn schema_policy_truncate_and_pad() -> Result<(), CrustyError> {
+        let mut op = scan(SchemaPolicy::Truncate);
+        op.open()?;
+        assert_eq!(op.next()?.unwrap().field_vals.to_vec(), vec![Field::IntField(1), Field::IntField(2)]);
+        assert_eq!(op.next()?.unwrap().field_vals.to_vec(), vec![Field::IntField(4)]);
+
+        let mut op = scan(SchemaPolicy::Pad);
+        op.open()?;
+        assert_eq!(op.next()?.unwrap().size(), 2);
+        assert_eq!(
+            op.next()?.unwrap().field_vals.to_vec(),
+            vec![Field::IntField(4), Field::StringField(String::new())]
+        );
+        Ok(())
+    }
 }
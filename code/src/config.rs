@@ -0,0 +1,752 @@
+use std::fmt;
+use std::fs;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::common::CrustyError;
+use crate::metrics::Instant;
+use crate::spill::SpillCompression;
+
+/// Run-formation configuration for the multi-level sort in SortMergeJoin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortConfig {
+    /// Number of tuples sorted by the level 1 sorting network.
+    pub run_size: usize,
+    /// Number of tuples a run should reach before the level 3 phase.
+    pub target_run_size: usize,
+    /// Number of runs merged into one at each merge level, at least 2.
+    pub merge_fan_in: usize,
+    /// Upper bound in bytes for a single run, if any.
+    pub memory_budget: Option<usize>,
+    /// Cores the sort workers are pinned to, if any.
+    pub sort_cores: Option<CoreAffinity>,
+    /// Compression of the runs spilled to disk.
+    pub spill_compression: SpillCompression,
+}
+
+impl Default for SortConfig {
+    /// Runs of 4 tuples merged once into runs of 8, as in the original two-level scheme.
+    fn default() -> Self {
+        Self {
+            run_size: 4,
+            target_run_size: 8,
+            merge_fan_in: 2,
+            memory_budget: None,
+            sort_cores: None,
+            spill_compression: SpillCompression::None,
+        }
+    }
+}
+
+impl SortConfig {
+    /// Create a new sort configuration without a memory budget.
+    ///
+    /// # Arguments
+    ///
+    /// * `run_size` - Number of tuples in a level 1 run.
+    /// * `target_run_size` - Number of tuples a run should reach by merging.
+    pub fn new(run_size: usize, target_run_size: usize) -> Self {
+        Self {
+            run_size: run_size.max(1),
+            target_run_size,
+            merge_fan_in: 2,
+            memory_budget: None,
+            sort_cores: None,
+            spill_compression: SpillCompression::None,
+        }
+    }
+
+    /// Create a sort configuration sized on the caches: a level 1 run fills half of the
+    /// L1 data cache and the runs are merged until they fill half of the L2 cache, the
+    /// other halves holding the merge output.
+    ///
+    /// Both sizes are powers of two of at least 4 tuples, see CacheSizes::detect.
+    ///
+    /// # Arguments
+    ///
+    /// * `cache` - Cache sizes of the machine.
+    /// * `tuple_bytes` - Estimated size of a tuple in bytes.
+    pub fn for_cache(cache: &CacheSizes, tuple_bytes: usize) -> Self {
+        let tuples = |bytes: usize| {
+            let n = (bytes / 2 / tuple_bytes.max(1)).max(4);
+            // largest power of two not above n
+            1 << (usize::BITS - 1 - n.leading_zeros())
+        };
+        let run_size = tuples(cache.l1_bytes);
+        Self::new(run_size, tuples(cache.l2_bytes).max(run_size))
+    }
+
+    /// Set the number of runs merged into one at each merge level.
+    ///
+    /// A larger fan-in takes fewer passes over the tuples to reach the target run size, at
+    /// the cost of a heap over more run heads.
+    ///
+    /// # Arguments
+    ///
+    /// * `fan_in` - Number of runs, at least 2.
+    pub fn set_merge_fan_in(&mut self, fan_in: usize) {
+        self.merge_fan_in = fan_in.max(2);
+    }
+
+    /// Set the memory budget for a single run.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Maximum number of bytes a run may occupy.
+    pub fn set_memory_budget(&mut self, bytes: usize) {
+        self.memory_budget = Some(bytes);
+    }
+
+    /// Pin the sort workers to a range of cores.
+    ///
+    /// # Arguments
+    ///
+    /// * `cores` - Cores of the sort workers.
+    pub fn set_sort_cores(&mut self, cores: CoreAffinity) {
+        self.sort_cores = Some(cores);
+    }
+
+    /// Set the compression of the runs spilled to disk.
+    ///
+    /// # Arguments
+    ///
+    /// * `compression` - Compression of the spilled runs.
+    pub fn set_spill_compression(&mut self, compression: SpillCompression) {
+        self.spill_compression = compression;
+    }
+
+    /// Returns the number of merge levels after the level 1 sort.
+    ///
+    /// Runs grow merge_fan_in times at each level until they reach the target run size, or
+    /// until one more level would exceed the memory budget.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple_bytes` - Estimated size of a tuple in bytes.
+    pub fn merge_levels(&self, tuple_bytes: usize) -> usize {
+        let cap = self.memory_budget.map(|budget| budget / tuple_bytes.max(1));
+        let fan_in = self.merge_fan_in.max(2);
+        let mut size = self.run_size;
+        let mut levels = 0;
+        while size < self.target_run_size && cap.is_none_or(|cap| size * fan_in <= cap) {
+            size *= fan_in;
+            levels += 1;
+        }
+        levels
+    }
+
+    /// Returns the number of tuples of a run after the merge levels.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple_bytes` - Estimated size of a tuple in bytes.
+    pub fn final_run_size(&self, tuple_bytes: usize) -> usize {
+        let levels = self.merge_levels(tuple_bytes) as u32;
+        self.run_size.saturating_mul(self.merge_fan_in.max(2).saturating_pow(levels))
+    }
+}
+
+/// Default size of the L1 data cache of a core, when it cannot be detected.
+pub const DEFAULT_L1_BYTES: usize = 32 * 1024;
+
+/// Default size of the L2 cache of a core, when it cannot be detected.
+pub const DEFAULT_L2_BYTES: usize = 256 * 1024;
+
+/// Sizes of the data caches of a core, see SortConfig::for_cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheSizes {
+    /// Bytes of the L1 data cache.
+    pub l1_bytes: usize,
+    /// Bytes of the L2 cache.
+    pub l2_bytes: usize,
+}
+
+impl Default for CacheSizes {
+    /// DEFAULT_L1_BYTES and DEFAULT_L2_BYTES.
+    fn default() -> Self {
+        Self {
+            l1_bytes: DEFAULT_L1_BYTES,
+            l2_bytes: DEFAULT_L2_BYTES,
+        }
+    }
+}
+
+impl CacheSizes {
+    /// Read the cache sizes of the first core from sysfs on Linux; a size that cannot be
+    /// read keeps its default.
+    pub fn detect() -> Self {
+        let mut sizes = Self::default();
+        let dir = "/sys/devices/system/cpu/cpu0/cache";
+        for index in 0..8 {
+            let read = |name: &str| fs::read_to_string(format!("{}/index{}/{}", dir, index, name));
+            let (Ok(level), Ok(kind), Ok(size)) = (read("level"), read("type"), read("size")) else {
+                continue;
+            };
+            let Some(bytes) = parse_cache_size(&size) else {
+                continue;
+            };
+            match (level.trim(), kind.trim()) {
+                ("1", "Data" | "Unified") => sizes.l1_bytes = bytes,
+                ("2", "Data" | "Unified") => sizes.l2_bytes = bytes,
+                _ => {}
+            }
+        }
+        sizes
+    }
+}
+
+// helper method to parse a sysfs cache size, e.g. 48K or 2M
+fn parse_cache_size(size: &str) -> Option<usize> {
+    let size = size.trim();
+    let (digits, unit) = match size.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => size.split_at(i),
+        None => (size, ""),
+    };
+    let n: usize = digits.parse().ok()?;
+    match unit {
+        "" => Some(n),
+        "K" => Some(n * 1024),
+        "M" => Some(n * 1024 * 1024),
+        "G" => Some(n * 1024 * 1024 * 1024),
+        _ => None,
+    }
+}
+
+/// A range of cores the workers of a parallel phase are pinned to, round-robin.
+///
+/// Pinning keeps benchmark numbers stable on multi-socket machines, e.g. by keeping the
+/// sort on the cores of one socket. It needs the affinity feature; without it, or on a
+/// platform without thread affinity, the workers run unpinned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoreAffinity {
+    /// Id of the first core.
+    pub first: usize,
+    /// Number of cores, at least 1.
+    pub count: usize,
+}
+
+impl CoreAffinity {
+    /// Create a new range of cores.
+    ///
+    /// # Arguments
+    ///
+    /// * `first` - Id of the first core.
+    /// * `count` - Number of cores.
+    pub fn new(first: usize, count: usize) -> Self {
+        Self {
+            first,
+            count: count.max(1),
+        }
+    }
+
+    /// Returns the core of a worker.
+    ///
+    /// # Arguments
+    ///
+    /// * `worker` - Index of the worker.
+    pub fn core(&self, worker: usize) -> usize {
+        self.first + worker % self.count
+    }
+}
+
+/// Memory budget of a query, shared by its operators.
+///
+/// Operators may override their share with a budget of their own, e.g. a large build
+/// side hash table next to a small sort. The context arbitrates the totals: overrides
+/// are granted in full while they fit in the global budget and scaled down together
+/// otherwise, and the operators without an override split whatever is left evenly.
+///
+/// LogicalPlan::to_physical_in registers the joins of a plan and bounds each with the
+/// MemoryTracker of its grant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionContext {
+    /// Memory available to the whole query in bytes.
+    memory_budget: usize,
+    /// Registered operators and their budget override, if any.
+    operators: Vec<(String, Option<usize>)>,
+}
+
+impl ExecutionContext {
+    /// Create a new execution context without operators.
+    ///
+    /// # Arguments
+    ///
+    /// * `memory_budget` - Memory available to the whole query in bytes.
+    pub fn new(memory_budget: usize) -> Self {
+        Self {
+            memory_budget,
+            operators: Vec::new(),
+        }
+    }
+
+    /// Register an operator sharing the global budget.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the operator.
+    pub fn register(&mut self, name: &str) {
+        if !self.operators.iter().any(|(op, _)| op == name) {
+            self.operators.push((name.to_string(), None));
+        }
+    }
+
+    /// Set the budget of an operator, registering it if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the operator.
+    /// * `bytes` - Memory requested by the operator in bytes.
+    pub fn set_operator_budget(&mut self, name: &str, bytes: usize) {
+        self.register(name);
+        if let Some((_, budget)) = self.operators.iter_mut().find(|(op, _)| op == name) {
+            *budget = Some(bytes);
+        }
+    }
+
+    /// Returns the memory granted to an operator in bytes, or None if it is not registered.
+    ///
+    /// Operators spill once they hold more than their grant.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the operator.
+    pub fn operator_budget(&self, name: &str) -> Option<usize> {
+        let requested: usize = self.operators.iter().filter_map(|(_, budget)| *budget).sum();
+        let shared = self.operators.iter().filter(|(_, budget)| budget.is_none()).count();
+        let (_, budget) = self.operators.iter().find(|(op, _)| op == name)?;
+        Some(match budget {
+            Some(bytes) if requested > self.memory_budget => {
+                (*bytes as u128 * self.memory_budget as u128 / requested as u128) as usize
+            }
+            Some(bytes) => *bytes,
+            None => self.memory_budget.saturating_sub(requested) / shared,
+        })
+    }
+}
+
+/// Bytes held by the operators of a query, shared between them.
+///
+/// Operators charge their buffered tuples and spill once a charge does not fit in the
+/// limit. Clones share the same counters, so one tracker can be handed to every operator
+/// of a plan and report the peak of the whole query.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTracker {
+    /// Counters shared by the clones.
+    state: Arc<TrackerState>,
+}
+
+/// Counters of a MemoryTracker.
+#[derive(Debug, Default)]
+struct TrackerState {
+    /// Maximum number of bytes, if bounded.
+    limit: Option<usize>,
+    /// Number of bytes currently charged.
+    used: AtomicUsize,
+    /// Largest number of bytes charged at once.
+    peak: AtomicUsize,
+}
+
+impl MemoryTracker {
+    /// Create a new tracker with a limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - Maximum number of bytes the operators may hold before spilling.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            state: Arc::new(TrackerState {
+                limit: Some(limit),
+                ..TrackerState::default()
+            }),
+        }
+    }
+
+    /// Create a new tracker without a limit, only recording the peak.
+    pub fn unbounded() -> Self {
+        Self::default()
+    }
+
+    /// Charge bytes if they fit in the limit, returns false and charges nothing otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Number of bytes to charge.
+    pub fn try_reserve(&self, bytes: usize) -> bool {
+        let limit = self.state.limit;
+        let reserved = self.state.used.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+            let used = used + bytes;
+            limit.is_none_or(|limit| used <= limit).then_some(used)
+        });
+        match reserved {
+            Ok(previous) => {
+                self.state.peak.fetch_max(previous + bytes, Ordering::SeqCst);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Charge bytes even if they exceed the limit, for memory an operator cannot spill.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Number of bytes to charge.
+    pub fn reserve(&self, bytes: usize) {
+        let used = self.state.used.fetch_add(bytes, Ordering::SeqCst) + bytes;
+        self.state.peak.fetch_max(used, Ordering::SeqCst);
+    }
+
+    /// Return charged bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Number of bytes to return.
+    pub fn release(&self, bytes: usize) {
+        let _ = self.state.used.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| Some(used.saturating_sub(bytes)));
+    }
+
+    /// Returns the limit in bytes, if any.
+    pub fn limit(&self) -> Option<usize> {
+        self.state.limit
+    }
+
+    /// Returns the number of bytes currently charged.
+    pub fn used(&self) -> usize {
+        self.state.used.load(Ordering::SeqCst)
+    }
+
+    /// Returns the largest number of bytes charged at once.
+    pub fn peak(&self) -> usize {
+        self.state.peak.load(Ordering::SeqCst)
+    }
+}
+
+/// Flag asking the operators of a query to stop, shared between them.
+///
+/// Operators check the token while they read or join tuples, and fail with
+/// `CrustyError::ExecutionError("cancelled")` once it is cancelled. Clones share the flag,
+/// so the token can be cancelled from another thread while the plan runs.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    /// Flag shared by the clones.
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Create a new token, not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the operators checking the token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns true if the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Fails with an ExecutionError if the token was cancelled.
+    pub fn check(&self) -> Result<(), CrustyError> {
+        match self.is_cancelled() {
+            true => Err(CrustyError::ExecutionError(String::from("cancelled"))),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Default number of rows between two progress reports of an operator.
+pub const PROGRESS_INTERVAL: usize = 1024;
+
+/// Rows processed by a phase of an operator, passed to its progress callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Phase of the operator, e.g. "sort left" or "probe".
+    pub phase: &'static str,
+    /// Number of rows the phase has processed so far.
+    pub rows: usize,
+}
+
+/// Callback receiving the progress of an operator, called from the threads running it.
+pub type ProgressCallback = Arc<dyn Fn(Progress) + Send + Sync>;
+
+/// Progress reporting and cancellation of long-running operators.
+///
+/// Operators tick the monitor as they process rows: every tick checks the cancellation
+/// token, and every interval rows the progress is reported to the callback and the
+/// deadline is checked. The deadline is also checked between morsels and runs, so an
+/// operator past it fails with `CrustyError::ExecutionError("deadline exceeded")`. The
+/// default monitor reports nothing and never stops an operator.
+#[derive(Clone)]
+pub struct ProgressMonitor {
+    /// Token checked on every tick, if any.
+    cancellation: Option<CancellationToken>,
+    /// Time the operators must be done by, if any.
+    deadline: Option<Instant>,
+    /// Callback receiving the progress, if any.
+    callback: Option<ProgressCallback>,
+    /// Number of rows between two reports.
+    interval: usize,
+}
+
+impl Default for ProgressMonitor {
+    fn default() -> Self {
+        Self {
+            cancellation: None,
+            deadline: None,
+            callback: None,
+            interval: PROGRESS_INTERVAL,
+        }
+    }
+}
+
+impl fmt::Debug for ProgressMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressMonitor")
+            .field("cancellation", &self.cancellation)
+            .field("deadline", &self.deadline)
+            .field("callback", &self.callback.is_some())
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+impl ProgressMonitor {
+    /// Create a new monitor without token, deadline or callback.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the token the operators stop on.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - Cancellation token, e.g. one shared by the whole plan.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancellation = Some(token);
+    }
+
+    /// Set the time the operators must be done by.
+    ///
+    /// # Arguments
+    ///
+    /// * `deadline` - Deadline of the execution.
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(deadline);
+    }
+
+    /// Set the deadline to a time from now.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - Time the operators may run from now.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.deadline = Some(Instant::now() + timeout);
+    }
+
+    /// Returns the time the operators must be done by, if any.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Set the callback receiving the progress.
+    ///
+    /// # Arguments
+    ///
+    /// * `callback` - Progress callback.
+    /// * `interval` - Number of rows between two reports, at least 1.
+    pub fn set_progress_callback(&mut self, callback: ProgressCallback, interval: usize) {
+        self.callback = Some(callback);
+        self.interval = interval.max(1);
+    }
+
+    /// Returns true if the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.as_ref().is_some_and(|token| token.is_cancelled())
+    }
+
+    /// Fails with an ExecutionError if the token was cancelled.
+    pub fn check(&self) -> Result<(), CrustyError> {
+        self.cancellation.as_ref().map_or(Ok(()), |token| token.check())
+    }
+
+    /// Fails with an ExecutionError if the token was cancelled or the deadline has passed.
+    ///
+    /// Reading the clock costs more than the token, so operators call this between
+    /// morsels and runs rather than on every row.
+    pub fn check_deadline(&self) -> Result<(), CrustyError> {
+        self.check()?;
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                Err(CrustyError::ExecutionError(String::from("deadline exceeded")))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the token after a phase processed a row, reporting the progress every
+    /// interval rows.
+    ///
+    /// # Arguments
+    ///
+    /// * `phase` - Phase of the operator.
+    /// * `rows` - Number of rows the phase has processed so far.
+    pub fn tick(&self, phase: &'static str, rows: usize) -> Result<(), CrustyError> {
+        self.advance(phase, rows.saturating_sub(1), rows)
+    }
+
+    /// Check the token after a phase processed a batch of rows, reporting the progress and
+    /// checking the deadline if the batch crossed a multiple of the interval.
+    ///
+    /// # Arguments
+    ///
+    /// * `phase` - Phase of the operator.
+    /// * `before` - Number of rows the phase had processed before the batch.
+    /// * `rows` - Number of rows the phase has processed so far.
+    pub fn advance(&self, phase: &'static str, before: usize, rows: usize) -> Result<(), CrustyError> {
+        if rows / self.interval > before / self.interval {
+            self.report(phase, rows);
+            self.check_deadline()
+        } else {
+            self.check()
+        }
+    }
+
+    /// Report the progress of a phase to the callback, e.g. once it is done.
+    ///
+    /// # Arguments
+    ///
+    /// * `phase` - Phase of the operator.
+    /// * `rows` - Number of rows the phase has processed so far.
+    pub fn report(&self, phase: &'static str, rows: usize) {
+        if let Some(callback) = &self.callback {
+            callback(Progress { phase, rows });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default_is_two_level() {
+        assert_eq!(SortConfig::default().merge_levels(8), 1);
+    }
+
+    #[test]
+    fn levels_until_target() {
+        assert_eq!(SortConfig::new(4, 4).merge_levels(8), 0);
+        assert_eq!(SortConfig::new(4, 64).merge_levels(8), 4);
+        assert_eq!(SortConfig::new(4, 65).merge_levels(8), 5);
+    }
+
+    #[test]
+    fn levels_with_fan_in() {
+        let mut config = SortConfig::new(4, 64);
+        config.set_merge_fan_in(4);
+        assert_eq!((config.merge_levels(8), config.final_run_size(8)), (2, 64));
+        config.set_merge_fan_in(3);
+        assert_eq!((config.merge_levels(8), config.final_run_size(8)), (3, 108));
+    }
+
+    #[test]
+    fn cache_sized_runs() {
+        let cache = CacheSizes { l1_bytes: 48 * 1024, l2_bytes: 2 * 1024 * 1024 };
+        let config = SortConfig::for_cache(&cache, 8);
+        assert_eq!((config.run_size, config.target_run_size), (2048, 131072));
+        // tuples larger than the caches still get runs of 4
+        let config = SortConfig::for_cache(&cache, 1 << 30);
+        assert_eq!((config.run_size, config.target_run_size), (4, 4));
+        assert_eq!(parse_cache_size("48K\n"), Some(48 * 1024));
+        assert_eq!(parse_cache_size("2M"), Some(2 << 20));
+        assert_eq!(parse_cache_size("big"), None);
+        let detected = CacheSizes::detect();
+        assert!(detected.l1_bytes > 0 && detected.l2_bytes > 0);
+    }
+
+    #[test]
+    fn levels_capped_by_budget() {
+        let mut config = SortConfig::new(4, 1024);
+        config.set_memory_budget(20 * 8);
+        assert_eq!(config.merge_levels(8), 2);
+    }
+
+    #[test]
+    fn operator_overrides() {
+        let mut ctx = ExecutionContext::new(1000);
+        ctx.set_operator_budget("hash", 600);
+        ctx.set_operator_budget("sort", 100);
+        ctx.register("scan");
+        ctx.register("filter");
+        assert_eq!(ctx.operator_budget("hash"), Some(600));
+        assert_eq!(ctx.operator_budget("sort"), Some(100));
+        assert_eq!(ctx.operator_budget("scan"), Some(150));
+        assert_eq!(ctx.operator_budget("join"), None);
+    }
+
+    #[test]
+    fn overrides_scaled_to_budget() {
+        let mut ctx = ExecutionContext::new(1000);
+        ctx.set_operator_budget("hash", 1500);
+        ctx.set_operator_budget("sort", 500);
+        ctx.register("scan");
+        assert_eq!(ctx.operator_budget("hash"), Some(750));
+        assert_eq!(ctx.operator_budget("sort"), Some(250));
+        assert_eq!(ctx.operator_budget("scan"), Some(0));
+    }
+
+    #[test]
+    fn tracker_limit_and_peak() {
+        let tracker = MemoryTracker::new(100);
+        let shared = tracker.clone();
+        assert!(tracker.try_reserve(60));
+        assert!(!shared.try_reserve(50));
+        assert_eq!(tracker.used(), 60);
+        shared.reserve(50);
+        tracker.release(80);
+        assert_eq!((tracker.used(), tracker.peak()), (30, 110));
+        assert!(MemoryTracker::unbounded().try_reserve(usize::MAX / 2));
+    }
+
+    #[test]
+    fn monitor_reports_and_cancels() {
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = reports.clone();
+        let token = CancellationToken::new();
+        let mut monitor = ProgressMonitor::new();
+        monitor.set_cancellation_token(token.clone());
+        monitor.set_progress_callback(Arc::new(move |progress| seen.lock().unwrap().push(progress)), 2);
+        for rows in 1..=5 {
+            assert!(monitor.tick("scan", rows).is_ok());
+        }
+        monitor.report("scan", 5);
+        assert!(monitor.advance("join", 5, 9).is_ok());
+        assert!(monitor.advance("join", 9, 9).is_ok());
+        let rows: Vec<usize> = reports.lock().unwrap().iter().map(|progress| progress.rows).collect();
+        assert_eq!(rows, vec![2, 4, 5, 9]);
+        token.cancel();
+        assert!(monitor.is_cancelled());
+        match monitor.tick("scan", 6) {
+            Err(CrustyError::ExecutionError(msg)) => assert_eq!(msg, "cancelled"),
+            res => panic!("expected a cancellation, got {:?}", res),
+        }
+        assert!(ProgressMonitor::default().check().is_ok());
+    }
+
+    #[test]
+    fn monitor_deadline() {
+        let mut monitor = ProgressMonitor::new();
+        monitor.set_timeout(Duration::from_secs(3600));
+        assert!(monitor.check_deadline().is_ok());
+        monitor.set_deadline(Instant::now());
+        // only interval boundaries read the clock
+        assert!(monitor.tick("scan", 1).is_ok());
+        match monitor.advance("scan", 1, PROGRESS_INTERVAL) {
+            Err(CrustyError::ExecutionError(msg)) => assert_eq!(msg, "deadline exceeded"),
+            res => panic!("expected a timeout, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,684 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::common::{Constraint, CrustyError, OpIterator, SimplePredicateOp, TableSchema};
+use crate::config::{MemoryTracker, SortConfig};
+use crate::join::{HashEqJoin, Join, MergeStrategy, SortMergeJoin};
+
+/// Bytes of bookkeeping per tuple held in a hash table bucket.
+pub const HASH_ENTRY_OVERHEAD: usize = 32;
+
+/// Join algorithms known to the cost model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JoinAlgorithm {
+    NestedLoop,
+    HashEq,
+    SortMergeMWay,
+    SortMergeMPass,
+}
+
+impl JoinAlgorithm {
+    /// Every algorithm known to the cost model.
+    pub const ALL: [JoinAlgorithm; 4] = [
+        JoinAlgorithm::NestedLoop,
+        JoinAlgorithm::HashEq,
+        JoinAlgorithm::SortMergeMWay,
+        JoinAlgorithm::SortMergeMPass,
+    ];
+
+    /// Returns true if the algorithm evaluates the predicate correctly.
+    ///
+    /// The hash join only matches equal keys, and the sort-merge join stops scanning a
+    /// right run at the first key larger than the left key. M-way is costed as joining each
+    /// left partition with the right partition of the same key range, which only holds for
+    /// equal keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Predicate operator of the join.
+    pub fn supports(&self, op: SimplePredicateOp) -> bool {
+        match self {
+            JoinAlgorithm::NestedLoop => true,
+            JoinAlgorithm::HashEq | JoinAlgorithm::SortMergeMWay => matches!(op, SimplePredicateOp::Equals),
+            JoinAlgorithm::SortMergeMPass => matches!(
+                op,
+                SimplePredicateOp::Equals | SimplePredicateOp::GreaterThan | SimplePredicateOp::GreaterThanOrEq
+            ),
+        }
+    }
+}
+
+/// Size of one join input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JoinInput {
+    /// Number of tuples.
+    pub rows: usize,
+    /// Estimated size of a tuple in bytes.
+    pub tuple_bytes: usize,
+}
+
+impl JoinInput {
+    /// Create a new join input.
+    ///
+    /// # Arguments
+    ///
+    /// * `rows` - Number of tuples.
+    /// * `schema` - Schema of the tuples.
+    pub fn new(rows: usize, schema: &TableSchema) -> Self {
+        Self {
+            rows,
+            tuple_bytes: schema.byte_size(),
+        }
+    }
+
+    /// Returns the size of the whole input in bytes, clamped to usize::MAX.
+    pub fn bytes(&self) -> usize {
+        self.rows.saturating_mul(self.tuple_bytes)
+    }
+}
+
+/// Configuration of a simulated join.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JoinConfig {
+    /// Join algorithm.
+    pub algorithm: JoinAlgorithm,
+    /// Number of level 3 partitions (m-way) or hash partitions (hash join).
+    pub partitions: usize,
+    /// Run sizes of the sorting/merging levels.
+    pub sort_config: SortConfig,
+    /// Memory available to the join in bytes, if bounded.
+    pub memory_budget: Option<usize>,
+    /// Whether the left and right inputs arrive sorted on their join keys.
+    pub presorted: (bool, bool),
+}
+
+impl JoinConfig {
+    /// Create a new join configuration with the default run sizes and no memory budget.
+    ///
+    /// # Arguments
+    ///
+    /// * `algorithm` - Join algorithm.
+    /// * `partitions` - Number of partitions.
+    pub fn new(algorithm: JoinAlgorithm, partitions: usize) -> Self {
+        Self {
+            algorithm,
+            partitions: partitions.max(1),
+            sort_config: SortConfig::default(),
+            memory_budget: None,
+            presorted: (false, false),
+        }
+    }
+}
+
+/// Predicted memory use of a join.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryEstimate {
+    /// Peak bytes held by the join operator.
+    pub peak_bytes: usize,
+    /// Bytes that do not fit in the memory budget and would spill.
+    pub spill_bytes: usize,
+}
+
+/// Predict the peak memory and spill volume of a join without executing it.
+///
+/// The estimate follows how the operators materialize data: nested loop streams both
+/// children, the hash join holds the left child in its table, and the sort-merge join
+/// holds both children plus the copies made for its worker threads and its output.
+///
+/// # Arguments
+///
+/// * `left` - Left input of the join.
+/// * `right` - Right input of the join.
+/// * `output_rows` - Estimated number of output tuples.
+/// * `config` - Configuration of the join.
+pub fn estimate_memory(
+    left: &JoinInput,
+    right: &JoinInput,
+    output_rows: usize,
+    config: &JoinConfig,
+) -> MemoryEstimate {
+    // sizes are clamped to usize::MAX, as the output rows are
+    let output_bytes = output_rows.saturating_mul(left.tuple_bytes + right.tuple_bytes);
+    let (peak_bytes, spillable_bytes) = match config.algorithm {
+        // one tuple of each side
+        JoinAlgorithm::NestedLoop => (left.tuple_bytes + right.tuple_bytes, 0),
+        // the build side is split into partitions, only one has to be resident
+        JoinAlgorithm::HashEq => {
+            let table = left.rows.saturating_mul(left.tuple_bytes + HASH_ENTRY_OVERHEAD);
+            (table, table - table / config.partitions)
+        }
+        // both sorted sides and the copies handed to the partition threads
+        JoinAlgorithm::SortMergeMWay => {
+            let sorted = left.bytes().saturating_add(right.bytes());
+            (sorted.saturating_mul(2).saturating_add(output_bytes), sorted)
+        }
+        // both sorted sides only, the threads join slices of the left runs with the shared
+        // right runs
+        JoinAlgorithm::SortMergeMPass => {
+            let sorted = left.bytes().saturating_add(right.bytes());
+            (sorted.saturating_add(output_bytes), sorted)
+        }
+    };
+    let spill_bytes = match config.memory_budget {
+        Some(budget) if peak_bytes > budget => (peak_bytes - budget).min(spillable_bytes),
+        _ => 0,
+    };
+    MemoryEstimate {
+        peak_bytes: peak_bytes - spill_bytes,
+        spill_bytes,
+    }
+}
+
+/// Estimate the number of output tuples of a join.
+///
+/// Equi-joins follow |L| * |R| / max(V(L), V(R)), where V is the number of distinct keys
+/// of a side and defaults to its row count (every key unique). Range predicates keep a
+/// third of the cross product. Estimates past usize::MAX, e.g. cross products on 32-bit
+/// targets, are clamped to it.
+///
+/// # Arguments
+///
+/// * `left_rows` - Number of left tuples.
+/// * `right_rows` - Number of right tuples.
+/// * `op` - Predicate operator of the join.
+/// * `distinct_keys` - Number of distinct left and right keys, if known.
+pub fn estimate_output_rows(
+    left_rows: usize,
+    right_rows: usize,
+    op: SimplePredicateOp,
+    distinct_keys: (Option<usize>, Option<usize>),
+) -> usize {
+    // in f64 like estimate_cost, the cast back saturates
+    let cross = left_rows as f64 * right_rows as f64;
+    let distinct = distinct_keys
+        .0
+        .unwrap_or(left_rows)
+        .max(distinct_keys.1.unwrap_or(right_rows))
+        .max(1) as f64;
+    let rows = match op {
+        SimplePredicateOp::Equals => (cross / distinct).floor(),
+        SimplePredicateOp::NotEq => cross - (cross / distinct).floor(),
+        SimplePredicateOp::All => cross,
+        _ => (cross / 3.0).floor(),
+    };
+    rows as usize
+}
+
+/// Estimate the work of a join as the number of tuples touched.
+///
+/// The sort-merge joins do not sort the inputs that are presorted.
+///
+/// # Arguments
+///
+/// * `left` - Left input of the join.
+/// * `right` - Right input of the join.
+/// * `output_rows` - Estimated number of output tuples.
+/// * `config` - Configuration of the join.
+pub fn estimate_cost(left: &JoinInput, right: &JoinInput, output_rows: usize, config: &JoinConfig) -> f64 {
+    let (l, r, out) = (left.rows as f64, right.rows as f64, output_rows as f64);
+    let sort_cost = |rows: f64, presorted: bool| if presorted { 0.0 } else { rows * rows.max(2.0).log2() };
+    let sort = sort_cost(l, config.presorted.0) + sort_cost(r, config.presorted.1);
+    match config.algorithm {
+        JoinAlgorithm::NestedLoop => l * r + out,
+        // build the table, then probe it
+        JoinAlgorithm::HashEq => 2.0 * l + r + out,
+        JoinAlgorithm::SortMergeMWay => sort + l + r + out,
+        // every left run scans every right tuple
+        JoinAlgorithm::SortMergeMPass => {
+            let run_rows = config.sort_config.final_run_size(left.tuple_bytes);
+            let runs = left.rows.div_ceil(run_rows.max(1)) as f64;
+            sort + l + runs * r + out
+        }
+    }
+}
+
+/// One side of a planned join, described without its data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanInput {
+    /// Schema of the tuples.
+    pub schema: TableSchema,
+    /// Number of tuples.
+    pub rows: usize,
+    /// Index of the join key.
+    pub key_index: usize,
+    /// Number of distinct join keys, if known.
+    pub distinct_keys: Option<usize>,
+    /// Whether the tuples arrive sorted on the join key.
+    pub sorted: bool,
+}
+
+impl PlanInput {
+    /// Create a new plan input with unknown distinct keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the tuples.
+    /// * `rows` - Number of tuples.
+    /// * `key_index` - Index of the join key.
+    pub fn new(schema: TableSchema, rows: usize, key_index: usize) -> Self {
+        Self {
+            schema,
+            rows,
+            key_index,
+            distinct_keys: None,
+            sorted: false,
+        }
+    }
+
+    /// Set the number of distinct join keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `distinct_keys` - Number of distinct join keys.
+    pub fn set_distinct_keys(&mut self, distinct_keys: usize) {
+        self.distinct_keys = Some(distinct_keys);
+    }
+
+    /// Set whether the tuples arrive sorted on the join key.
+    ///
+    /// # Arguments
+    ///
+    /// * `sorted` - True if the input is sorted on the join key.
+    pub fn set_sorted(&mut self, sorted: bool) {
+        self.sorted = sorted;
+    }
+
+    /// Returns the size of the input for the memory estimate.
+    pub fn input(&self) -> JoinInput {
+        JoinInput::new(self.rows, &self.schema)
+    }
+}
+
+/// A join to be vetted by a dry run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JoinPlan {
+    /// Left side of the join.
+    pub left: PlanInput,
+    /// Right side of the join.
+    pub right: PlanInput,
+    /// Predicate operator of the join.
+    pub op: SimplePredicateOp,
+    /// Algorithm to use, chosen by the dry run if None.
+    pub algorithm: Option<JoinAlgorithm>,
+    /// Number of partitions.
+    pub partitions: usize,
+    /// Memory available to the join in bytes, if bounded.
+    pub memory_budget: Option<usize>,
+}
+
+impl JoinPlan {
+    /// Create a new join plan with the algorithm left to the dry run.
+    ///
+    /// # Arguments
+    ///
+    /// * `left` - Left side of the join.
+    /// * `right` - Right side of the join.
+    /// * `op` - Predicate operator of the join.
+    pub fn new(left: PlanInput, right: PlanInput, op: SimplePredicateOp) -> Self {
+        Self {
+            left,
+            right,
+            op,
+            algorithm: None,
+            partitions: 3,
+            memory_budget: None,
+        }
+    }
+
+    /// Force the join algorithm.
+    ///
+    /// # Arguments
+    ///
+    /// * `algorithm` - Join algorithm.
+    pub fn set_algorithm(&mut self, algorithm: JoinAlgorithm) {
+        self.algorithm = Some(algorithm);
+    }
+
+    /// Set the memory budget of the join.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Memory available to the join in bytes.
+    pub fn set_memory_budget(&mut self, bytes: usize) {
+        self.memory_budget = Some(bytes);
+    }
+
+    // helper method to check the plan could execute
+    fn validate(&self) -> Result<(), CrustyError> {
+        let left = self.left.schema.get_attribute(self.left.key_index);
+        let right = self.right.schema.get_attribute(self.right.key_index);
+        let (left, right) = match (left, right) {
+            (Some(left), Some(right)) => (left, right),
+            _ => {
+                return Err(CrustyError::ValidationError(format!(
+                    "join keys {} and {} are not in the schemas",
+                    self.left.key_index, self.right.key_index
+                )))
+            }
+        };
+        if left.dtype() != right.dtype() {
+            return Err(CrustyError::ValidationError(format!(
+                "join keys have types {:?} and {:?}",
+                left.dtype(),
+                right.dtype()
+            )));
+        }
+        match self.algorithm {
+            Some(algorithm) if !algorithm.supports(self.op) => Err(CrustyError::ValidationError(format!(
+                "{:?} does not support {:?}",
+                algorithm, self.op
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Estimates of one candidate algorithm.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CandidateEstimate {
+    /// Join algorithm.
+    pub algorithm: JoinAlgorithm,
+    /// Estimated work in tuples touched.
+    pub cost: f64,
+    /// Predicted memory use.
+    pub memory: MemoryEstimate,
+}
+
+/// Decisions of a dry run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DryRunReport {
+    /// Estimated number of output tuples.
+    pub output_rows: usize,
+    /// Estimates of every algorithm considered.
+    pub candidates: Vec<CandidateEstimate>,
+    /// Algorithm selected to execute the join.
+    pub chosen: JoinAlgorithm,
+}
+
+/// Vet a join plan without reading any data.
+///
+/// Validates the plan, estimates its output cardinality and the cost and memory of every
+/// algorithm supporting the predicate (or only the forced one), and selects the algorithm
+/// that spills least, then costs least.
+///
+/// # Arguments
+///
+/// * `plan` - Join to vet.
+pub fn dry_run(plan: &JoinPlan) -> Result<DryRunReport, CrustyError> {
+    plan.validate()?;
+    let (left, right) = (plan.left.input(), plan.right.input());
+    let output_rows = estimate_output_rows(
+        plan.left.rows,
+        plan.right.rows,
+        plan.op,
+        (plan.left.distinct_keys, plan.right.distinct_keys),
+    );
+    let algorithms = match plan.algorithm {
+        Some(algorithm) => vec![algorithm],
+        None => JoinAlgorithm::ALL.into_iter().filter(|a| a.supports(plan.op)).collect(),
+    };
+    let candidates: Vec<CandidateEstimate> = algorithms
+        .into_iter()
+        .map(|algorithm| {
+            let mut config = JoinConfig::new(algorithm, plan.partitions);
+            config.memory_budget = plan.memory_budget;
+            config.presorted = (plan.left.sorted, plan.right.sorted);
+            CandidateEstimate {
+                algorithm,
+                cost: estimate_cost(&left, &right, output_rows, &config),
+                memory: estimate_memory(&left, &right, output_rows, &config),
+            }
+        })
+        .collect();
+    let chosen = candidates
+        .iter()
+        .min_by(|a, b| {
+            a.memory
+                .spill_bytes
+                .cmp(&b.memory.spill_bytes)
+                .then(a.cost.total_cmp(&b.cost))
+        })
+        .map(|c| c.algorithm)
+        .unwrap_or(JoinAlgorithm::NestedLoop);
+    Ok(DryRunReport {
+        output_rows,
+        candidates,
+        chosen,
+    })
+}
+
+impl fmt::Display for DryRunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "chose {:?} for {} output rows", self.chosen, self.output_rows)?;
+        for c in &self.candidates {
+            write!(
+                f,
+                "; {:?} cost {:.0}, peak {} bytes, spill {} bytes",
+                c.algorithm, c.cost, c.memory.peak_bytes, c.memory.spill_bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns whether an equi-join matches a left foreign key with a unique right key, e.g. the
+/// primary key it references, so each left tuple joins at most one right tuple.
+///
+/// # Arguments
+///
+/// * `plan` - Join to check, its schemas holding the constraints of the catalog.
+pub fn foreign_key_join(plan: &JoinPlan) -> bool {
+    let constraint = plan.left.schema.get_attribute(plan.left.key_index).map(|a| &a.constraint);
+    let foreign = matches!(constraint, Some(Constraint::ForeignKey(_) | Constraint::NotNullFKey(_)));
+    matches!(plan.op, SimplePredicateOp::Equals) && foreign && unique_key(&plan.right)
+}
+
+// helper method to check whether the key attribute of a side is unique
+fn unique_key(input: &PlanInput) -> bool {
+    input.schema.get_attribute(input.key_index).is_some_and(|a| a.constraint.is_unique())
+}
+
+/// Pick the join algorithm of a plan and build its operator over the children.
+///
+/// Sides whose child declares a sort order starting with the join key are costed as
+/// presorted. A foreign key join, see foreign_key_join, is estimated to return a tuple per
+/// left tuple. The sort-merge joins assume the keys of a side are unique when its key
+/// attribute is, see Constraint::is_unique. The returned report records the estimates
+/// behind the decision, so it can be logged next to the measurements of the join. The
+/// memory budget of the plan bounds the hash and sort-merge joins, which spill beyond it.
+///
+/// # Arguments
+///
+/// * `plan` - Join to plan, its algorithm is forced if set.
+/// * `left_child` - Left child of the join.
+/// * `right_child` - Right child of the join.
+pub fn choose_join(
+    plan: &JoinPlan,
+    left_child: Box<dyn OpIterator + Send>,
+    right_child: Box<dyn OpIterator + Send>,
+) -> Result<(Box<dyn OpIterator + Send>, DryRunReport), CrustyError> {
+    let mut plan = plan.clone();
+    let sorted_on = |child: &dyn OpIterator, key: usize| child.sort_order().is_some_and(|order| order.first() == Some(&key));
+    plan.left.sorted |= sorted_on(left_child.as_ref(), plan.left.key_index);
+    plan.right.sorted |= sorted_on(right_child.as_ref(), plan.right.key_index);
+    if foreign_key_join(&plan) {
+        // the left keys are among the right ones
+        plan.right.distinct_keys = Some(plan.right.rows);
+        plan.left.distinct_keys = Some(plan.left.distinct_keys.unwrap_or(plan.left.rows).min(plan.right.rows));
+    }
+    let report = dry_run(&plan)?;
+    let memory = plan.memory_budget.map_or_else(MemoryTracker::unbounded, MemoryTracker::new);
+    let (op, l, r) = (plan.op, plan.left.key_index, plan.right.key_index);
+    let join: Box<dyn OpIterator + Send> = match report.chosen {
+        JoinAlgorithm::NestedLoop => Box::new(Join::new(op, l, r, left_child, right_child)?),
+        JoinAlgorithm::HashEq => {
+            let mut join = HashEqJoin::new(op, l, r, left_child, right_child)?;
+            join.set_partitions(plan.partitions);
+            join.set_memory_tracker(memory);
+            Box::new(join)
+        }
+        JoinAlgorithm::SortMergeMWay | JoinAlgorithm::SortMergeMPass => {
+            let strategy = match report.chosen {
+                JoinAlgorithm::SortMergeMWay => MergeStrategy::MWay {
+                    partitions: plan.partitions,
+                },
+                _ => MergeStrategy::M_PASS,
+            };
+            let mut join = SortMergeJoin::new(op, l, r, left_child, right_child, strategy)?;
+            join.set_memory_tracker(memory);
+            join.set_unique_left(unique_key(&plan.left));
+            join.set_unique_right(unique_key(&plan.right));
+            Box::new(join)
+        }
+    };
+    Ok((join, report))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{Attribute, DataType, TupleIterator};
+    use crate::generator::{create_vec_tuple, get_int_table_schema, DEFAULT_SEED};
+    use crate::operators::{compare_rows, Sort};
+
+    const LEFT: JoinInput = JoinInput { rows: 1024, tuple_bytes: 8 };
+    const RIGHT: JoinInput = JoinInput { rows: 2048, tuple_bytes: 12 };
+
+    #[test]
+    fn nested_loop_streams() {
+        let config = JoinConfig::new(JoinAlgorithm::NestedLoop, 1);
+        let estimate = estimate_memory(&LEFT, &RIGHT, 100, &config);
+        assert_eq!(estimate, MemoryEstimate { peak_bytes: 20, spill_bytes: 0 });
+    }
+
+    #[test]
+    fn m_pass_shares_right_runs() {
+        let config = JoinConfig::new(JoinAlgorithm::SortMergeMPass, 1);
+        let estimate = estimate_memory(&LEFT, &RIGHT, 0, &config);
+        // 128 runs of 8 tuples, all joined with the same right runs
+        assert_eq!(estimate.peak_bytes, LEFT.bytes() + RIGHT.bytes());
+        let m_way = estimate_memory(&LEFT, &RIGHT, 0, &JoinConfig::new(JoinAlgorithm::SortMergeMWay, 1));
+        assert!(estimate.peak_bytes < m_way.peak_bytes);
+    }
+
+    #[test]
+    fn hash_spills_over_budget() {
+        let mut config = JoinConfig::new(JoinAlgorithm::HashEq, 4);
+        let table = 1024 * (8 + HASH_ENTRY_OVERHEAD);
+        config.memory_budget = Some(table / 2);
+        let estimate = estimate_memory(&LEFT, &RIGHT, 0, &config);
+        assert_eq!(estimate, MemoryEstimate { peak_bytes: table / 2, spill_bytes: table / 2 });
+
+        config.memory_budget = Some(table / 8);
+        let estimate = estimate_memory(&LEFT, &RIGHT, 0, &config);
+        assert_eq!(estimate.spill_bytes, table * 3 / 4);
+    }
+
+    fn plan_input(rows: usize, key_type: DataType) -> PlanInput {
+        PlanInput::new(TableSchema::from_vecs(vec!["id", "key"], vec![DataType::Int, key_type]), rows, 1)
+    }
+
+    #[test]
+    fn dry_run_selects() -> Result<(), CrustyError> {
+        let mut left = plan_input(1000, DataType::Int);
+        left.set_distinct_keys(100);
+        let plan = JoinPlan::new(left.clone(), plan_input(5000, DataType::Int), SimplePredicateOp::Equals);
+        let report = dry_run(&plan)?;
+        assert_eq!(report.output_rows, 1000);
+        assert_eq!(report.candidates.len(), 4);
+        assert_eq!(report.chosen, JoinAlgorithm::HashEq);
+
+        // only the nested loop handles a not-equal predicate
+        let plan = JoinPlan::new(left, plan_input(5000, DataType::Int), SimplePredicateOp::NotEq);
+        assert_eq!(dry_run(&plan)?.chosen, JoinAlgorithm::NestedLoop);
+        Ok(())
+    }
+
+    #[test]
+    fn huge_inputs_saturate() -> Result<(), CrustyError> {
+        // the cross product does not fit in a usize, as 100k x 100k on a 32-bit target
+        let rows = 1 << (usize::BITS / 2 + 1);
+        assert_eq!(estimate_output_rows(rows, rows, SimplePredicateOp::Equals, (None, None)), rows);
+        assert_eq!(estimate_output_rows(rows, rows, SimplePredicateOp::All, (None, None)), usize::MAX);
+        assert_eq!(estimate_output_rows(10, 3, SimplePredicateOp::NotEq, (Some(3), None)), 20);
+        for op in [SimplePredicateOp::Equals, SimplePredicateOp::GreaterThan] {
+            let report = dry_run(&JoinPlan::new(plan_input(rows, DataType::Int), plan_input(rows, DataType::Int), op))?;
+            assert!(report.candidates.iter().all(|c| c.memory.peak_bytes > 0));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn dry_run_validates() {
+        let plan = JoinPlan::new(plan_input(10, DataType::Int), plan_input(10, DataType::String), SimplePredicateOp::Equals);
+        assert!(matches!(dry_run(&plan), Err(CrustyError::ValidationError(_))));
+
+        let mut plan = JoinPlan::new(plan_input(10, DataType::Int), plan_input(10, DataType::Int), SimplePredicateOp::LessThan);
+        plan.set_algorithm(JoinAlgorithm::HashEq);
+        assert!(matches!(dry_run(&plan), Err(CrustyError::ValidationError(_))));
+
+        let mut plan = JoinPlan::new(plan_input(10, DataType::Int), plan_input(10, DataType::Int), SimplePredicateOp::Equals);
+        plan.right.key_index = 2;
+        assert!(matches!(dry_run(&plan), Err(CrustyError::ValidationError(_))));
+    }
+
+    fn scan(rows: usize) -> Box<dyn OpIterator + Send> {
+        let tuples = create_vec_tuple(rows, 2, 1000, DEFAULT_SEED);
+        Box::new(TupleIterator::new(tuples, get_int_table_schema(2)))
+    }
+
+    #[test]
+    fn choose_presorted() -> Result<(), CrustyError> {
+        let plan = JoinPlan::new(plan_input(1000, DataType::Int), plan_input(1000, DataType::Int), SimplePredicateOp::Equals);
+        let (_, report) = choose_join(&plan, scan(1000), scan(1000))?;
+        assert_eq!(report.chosen, JoinAlgorithm::HashEq);
+
+        // sorted children save the sort-merge join its sort
+        let sorted = |rows| Box::new(Sort::new(scan(rows), vec![1]));
+        let (mut join, report) = choose_join(&plan, sorted(1000), sorted(1000))?;
+        assert_eq!(report.chosen, JoinAlgorithm::SortMergeMWay);
+        assert!(report.to_string().starts_with("chose SortMergeMWay for 1000 output rows; NestedLoop cost"));
+        let mut oracle = Join::new(SimplePredicateOp::Equals, 1, 1, scan(1000), scan(1000))?;
+        compare_rows(&mut oracle, join.as_mut())?;
+        Ok(())
+    }
+
+    #[test]
+    fn choose_foreign_key() -> Result<(), CrustyError> {
+        let input = |rows, constraint| {
+            let attrs = vec![
+                Attribute::new(String::from("id"), DataType::Int),
+                Attribute::new_with_constraint(String::from("key"), DataType::Int, constraint),
+            ];
+            PlanInput::new(TableSchema::new(attrs), rows, 1)
+        };
+        let plan = JoinPlan::new(input(4000, Constraint::ForeignKey(1)), input(1000, Constraint::PrimaryKey), SimplePredicateOp::Equals);
+        assert!(foreign_key_join(&plan));
+        // a tuple per left tuple instead of |L| * |R| / |L|
+        let (_, report) = choose_join(&plan, scan(4000), scan(1000))?;
+        assert_eq!(report.output_rows, 4000);
+
+        let plan = JoinPlan::new(input(4000, Constraint::ForeignKey(1)), input(1000, Constraint::None), SimplePredicateOp::Equals);
+        assert!(!foreign_key_join(&plan));
+        let (_, report) = choose_join(&plan, scan(4000), scan(1000))?;
+        assert_eq!(report.output_rows, 1000);
+        Ok(())
+    }
+
+    #[test]
+    fn choose_predicate() -> Result<(), CrustyError> {
+        let plan = JoinPlan::new(plan_input(100, DataType::Int), plan_input(100, DataType::Int), SimplePredicateOp::LessThan);
+        let (mut join, report) = choose_join(&plan, scan(100), scan(100))?;
+        assert_eq!(report.chosen, JoinAlgorithm::NestedLoop);
+        let mut oracle = Join::new(SimplePredicateOp::LessThan, 1, 1, scan(100), scan(100))?;
+        compare_rows(&mut oracle, join.as_mut())?;
+
+        // greater than is merged, but not by m-way which only joins equal key ranges
+        let plan = JoinPlan::new(plan_input(1000, DataType::Int), plan_input(1000, DataType::Int), SimplePredicateOp::GreaterThan);
+        let (mut join, report) = choose_join(&plan, scan(1000), scan(1000))?;
+        assert_eq!(report.chosen, JoinAlgorithm::SortMergeMPass);
+        assert!(report.candidates.iter().all(|c| c.algorithm != JoinAlgorithm::SortMergeMWay));
+        let mut oracle = Join::new(SimplePredicateOp::GreaterThan, 1, 1, scan(1000), scan(1000))?;
+        compare_rows(&mut oracle, join.as_mut())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,550 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use crate::common::{CrustyError, DataType, Field, OpIterator, TableSchema, Tuple};
+use crate::morsel::parallel_map;
+
+/// Dictionary mapping string values to integer codes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dictionary {
+    /// String value of each code.
+    values: Vec<String>,
+    /// Mapping from string value to code.
+    codes: HashMap<String, u32>,
+}
+
+impl Dictionary {
+    /// Create a new empty dictionary.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the code of the value, adding it to the dictionary if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - String value to encode.
+    pub fn encode(&mut self, value: &str) -> u32 {
+        if let Some(code) = self.codes.get(value) {
+            return *code;
+        }
+        let code = self.values.len() as u32;
+        self.values.push(value.to_string());
+        self.codes.insert(value.to_string(), code);
+        code
+    }
+
+    /// Returns the code of the value if it is in the dictionary.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - String value to look up.
+    pub fn code(&self, value: &str) -> Option<u32> {
+        self.codes.get(value).copied()
+    }
+
+    /// Returns the string value of the code.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - Code to decode.
+    pub fn decode(&self, code: u32) -> Option<&str> {
+        self.values.get(code as usize).map(|s| s.as_str())
+    }
+
+    /// Returns the number of distinct values.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns true if the dictionary has no values.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns true if codes follow the order of their string values.
+    pub fn is_sorted(&self) -> bool {
+        self.values.windows(2).all(|w| w[0] < w[1])
+    }
+
+    /// Merge partition-local dictionaries into one sorted global dictionary.
+    ///
+    /// Returns the global dictionary and, for each local dictionary, the table
+    /// remapping its local codes to global codes.
+    ///
+    /// # Arguments
+    ///
+    /// * `locals` - Local dictionaries built by the workers.
+    pub fn merge(locals: &[Dictionary]) -> (Dictionary, Vec<Vec<u32>>) {
+        let mut values: Vec<&str> = locals
+            .iter()
+            .flat_map(|d| d.values.iter().map(|s| s.as_str()))
+            .collect();
+        values.sort_unstable();
+        values.dedup();
+
+        // codes are assigned in string order so the global codes preserve ordering
+        let mut global = Dictionary::new();
+        for value in values {
+            global.encode(value);
+        }
+        let remaps = locals
+            .iter()
+            .map(|d| d.values.iter().map(|s| global.codes[s]).collect())
+            .collect();
+        (global, remaps)
+    }
+}
+
+/// Partitions of tuples, e.g. the key ranges of a join.
+type Partitions = Vec<Vec<Tuple>>;
+
+// helper method to encode the key of each tuple in a partition with a local dictionary
+fn encode_partition(partition: &[Tuple], index: usize, dict: &mut Dictionary) -> Result<Vec<u32>, CrustyError> {
+    let mut codes = Vec::with_capacity(partition.len());
+    for t in partition {
+        match t.get_field(index) {
+            Some(Field::StringField(s)) => codes.push(dict.encode(s)),
+            _ => {
+                return Err(CrustyError::ExecutionError(format!(
+                    "field {} is not a string field",
+                    index
+                )))
+            }
+        }
+    }
+    Ok(codes)
+}
+
+// helper method to encode groups of partitions in parallel, one thread and local
+// dictionary per group, then merge the local dictionaries and remap the codes at gather;
+// each partition comes with the index of its key field
+fn encode_groups(groups: Vec<Vec<(Vec<Tuple>, usize)>>) -> Result<(Dictionary, Vec<Partitions>), CrustyError> {
+    let workers = groups.len();
+    let results = parallel_map(groups, workers, |group| {
+        let mut dict = Dictionary::new();
+        let mut encoded = Vec::with_capacity(group.len());
+        for (partition, index) in group {
+            let codes = encode_partition(&partition, index, &mut dict)?;
+            encoded.push((codes, partition, index));
+        }
+        Ok::<_, CrustyError>((dict, encoded))
+    });
+
+    let mut locals = Vec::new();
+    let mut encoded = Vec::new();
+    for result in results {
+        let (dict, group) = result?;
+        locals.push(dict);
+        encoded.push(group);
+    }
+
+    // gather: merge the local dictionaries and remap local codes to global ones
+    let (global, remaps) = Dictionary::merge(&locals);
+    let mut res = Vec::new();
+    for (group, remap) in encoded.into_iter().zip(remaps) {
+        let mut partitions = Vec::new();
+        for (codes, mut partition, index) in group {
+            for (t, code) in partition.iter_mut().zip(codes) {
+                t.set_field(index, Field::IntField(remap[code as usize] as i32));
+            }
+            partitions.push(partition);
+        }
+        res.push(partitions);
+    }
+    Ok((global, res))
+}
+
+/// Dictionary encode the string key of every partition in parallel.
+///
+/// Each partition is encoded by its own thread with a local dictionary; the local
+/// dictionaries are merged at the gather stage and the codes remapped, so no worker
+/// waits on a shared global dictionary. The key field of each tuple is replaced by
+/// an IntField holding its global code. Codes preserve string order, so the encoded
+/// partitions can be sorted and joined like integer keys.
+///
+/// # Arguments
+///
+/// * `partitions` - Partitions of tuples to encode.
+/// * `index` - Index of the string key field.
+pub fn encode_partitions(
+    partitions: Vec<Vec<Tuple>>,
+    index: usize,
+) -> Result<(Dictionary, Vec<Vec<Tuple>>), CrustyError> {
+    let groups = partitions.into_iter().map(|partition| vec![(partition, index)]).collect();
+    let (global, groups) = encode_groups(groups)?;
+    Ok((global, groups.into_iter().flatten().collect()))
+}
+
+/// Dictionary encode the string join keys of the partitions of both sides of a join.
+///
+/// Like encode_partitions, with one thread and local dictionary per pair of left and
+/// right partitions, as the m-way workers of SortMergeJoin join them.
+///
+/// # Arguments
+///
+/// * `left` - Left partitions.
+/// * `left_index` - Index of the left string key field.
+/// * `right` - Right partitions, as many as the left ones.
+/// * `right_index` - Index of the right string key field.
+pub fn encode_join_partitions(
+    left: Partitions,
+    left_index: usize,
+    right: Partitions,
+    right_index: usize,
+) -> Result<(Dictionary, Partitions, Partitions), CrustyError> {
+    let groups = left
+        .into_iter()
+        .zip(right)
+        .map(|(l, r)| vec![(l, left_index), (r, right_index)])
+        .collect();
+    let (global, groups) = encode_groups(groups)?;
+    let (mut left, mut right) = (Vec::new(), Vec::new());
+    for mut group in groups {
+        right.push(group.pop().unwrap_or_default());
+        left.push(group.pop().unwrap_or_default());
+    }
+    Ok((global, left, right))
+}
+
+/// Replace the encoded key of every tuple with its string value.
+///
+/// # Arguments
+///
+/// * `tuples` - Tuples with an encoded key field.
+/// * `index` - Index of the encoded key field.
+/// * `dict` - Dictionary used to encode the key.
+pub fn decode_tuples(tuples: &mut [Tuple], index: usize, dict: &Dictionary) -> Result<(), CrustyError> {
+    for t in tuples.iter_mut() {
+        let code = match t.get_field(index) {
+            Some(Field::IntField(code)) => *code as u32,
+            _ => return Err(CrustyError::ExecutionError(format!("field {} is not encoded", index))),
+        };
+        let value = dict
+            .decode(code)
+            .ok_or_else(|| CrustyError::ExecutionError(format!("unknown dictionary code {}", code)))?;
+        t.set_field(index, Field::StringField(value.to_string()));
+    }
+    Ok(())
+}
+
+/// Dictionary shared by the operators of a plan, interning strings as 4-byte ids.
+///
+/// Ids are given in the order strings are first seen, so they only compare equal or not:
+/// intern equi-join keys, not keys ordered by a range predicate.
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    /// Dictionary of the interned strings, shared by the clones.
+    dict: Arc<RwLock<Dictionary>>,
+}
+
+impl Interner {
+    /// Create a new empty interner.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the id of a string, interning it if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - String to intern.
+    pub fn intern(&self, value: &str) -> u32 {
+        if let Some(id) = self.dict.read().unwrap().code(value) {
+            return id;
+        }
+        self.dict.write().unwrap().encode(value)
+    }
+
+    /// Returns the string of an id, None if it was not given by this interner.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Interned id.
+    pub fn resolve(&self, id: u32) -> Option<String> {
+        self.dict.read().unwrap().decode(id).map(str::to_string)
+    }
+
+    /// Returns the number of interned strings.
+    pub fn len(&self) -> usize {
+        self.dict.read().unwrap().len()
+    }
+
+    /// Returns true if no string was interned.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Replaces a string field of every tuple of its child with its interned id, so a join
+/// above compares and hashes 4-byte ids instead of whole strings.
+///
+/// Intern both children of an equi-join with the same Interner, then turn the ids back
+/// into strings above the join with Resolve.
+pub struct Intern {
+    /// Child node.
+    child: Box<dyn OpIterator + Send>,
+    /// Index of the interned field.
+    index: usize,
+    /// Interner shared with the other side of the join.
+    interner: Interner,
+    /// Schema of the child, with an Int interned field.
+    schema: TableSchema,
+}
+
+impl Intern {
+    /// Intern constructor.
+    ///
+    /// Fails with a ValidationError if the field is not a string field of the child.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - Child node.
+    /// * `index` - Index of the string field to intern.
+    /// * `interner` - Interner shared with the other side of the join.
+    pub fn new(child: Box<dyn OpIterator + Send>, index: usize, interner: Interner) -> Result<Self, CrustyError> {
+        let schema = retyped_schema(child.get_schema(), &[index], DataType::String, DataType::Int)?;
+        Ok(Self {
+            child,
+            index,
+            interner,
+            schema,
+        })
+    }
+}
+
+impl OpIterator for Intern {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        match self.child.next()? {
+            None => Ok(None),
+            Some(mut t) => {
+                let id = match t.get_field(self.index) {
+                    Some(Field::StringField(s)) => self.interner.intern(s),
+                    _ => return Err(CrustyError::ExecutionError(format!("field {} is not a string field", self.index))),
+                };
+                t.set_field(self.index, Field::IntField(id as i32));
+                Ok(Some(t))
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.child.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.child.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.child]
+    }
+
+    /// Ids do not follow the order of their strings, so only the other fields can be ordered.
+    fn request_order(&mut self, index: usize) -> bool {
+        index != self.index && self.child.request_order(index)
+    }
+
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        unordered_after(self.child.sort_order(), &[self.index])
+    }
+}
+
+/// Replaces interned ids with their strings, e.g. the join keys above a join of Intern
+/// children.
+pub struct Resolve {
+    /// Child node.
+    child: Box<dyn OpIterator + Send>,
+    /// Indices of the interned fields.
+    indices: Vec<usize>,
+    /// Interner that gave the ids.
+    interner: Interner,
+    /// Schema of the child, with String resolved fields.
+    schema: TableSchema,
+}
+
+impl Resolve {
+    /// Resolve constructor.
+    ///
+    /// Fails with a ValidationError if a field is not an Int field of the child.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - Child node.
+    /// * `indices` - Indices of the interned fields, e.g. both join keys.
+    /// * `interner` - Interner that gave the ids.
+    pub fn new(child: Box<dyn OpIterator + Send>, indices: Vec<usize>, interner: Interner) -> Result<Self, CrustyError> {
+        let schema = retyped_schema(child.get_schema(), &indices, DataType::Int, DataType::String)?;
+        Ok(Self {
+            child,
+            indices,
+            interner,
+            schema,
+        })
+    }
+}
+
+impl OpIterator for Resolve {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        match self.child.next()? {
+            None => Ok(None),
+            Some(mut t) => {
+                for index in self.indices.iter() {
+                    let value = match t.get_field(*index) {
+                        Some(Field::IntField(id)) => self.interner.resolve(*id as u32),
+                        _ => None,
+                    };
+                    let value =
+                        value.ok_or_else(|| CrustyError::ExecutionError(format!("field {} is not an interned id", index)))?;
+                    t.set_field(*index, Field::StringField(value));
+                }
+                Ok(Some(t))
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.child.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.child.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.child]
+    }
+
+    /// An order on the ids is not one on the strings.
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        unordered_after(self.child.sort_order(), &self.indices)
+    }
+}
+
+// helper method to change the type of some fields of a schema, checking their old type
+fn retyped_schema(schema: &TableSchema, indices: &[usize], from: DataType, to: DataType) -> Result<TableSchema, CrustyError> {
+    let mut attrs: Vec<_> = schema.attributes().cloned().collect();
+    for index in indices {
+        match attrs.get_mut(*index) {
+            Some(attr) if attr.dtype == from => attr.dtype = to.clone(),
+            _ => return Err(CrustyError::ValidationError(format!("field {} is not a {:?} field", index, from))),
+        }
+    }
+    Ok(TableSchema::new(attrs))
+}
+
+// helper method to cut a sort order at the first field whose values were replaced
+fn unordered_after(order: Option<Vec<usize>>, replaced: &[usize]) -> Option<Vec<usize>> {
+    let order: Vec<usize> = order?.into_iter().take_while(|i| !replaced.contains(i)).collect();
+    if order.is_empty() {
+        None
+    } else {
+        Some(order)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn string_tuples(keys: &[&str]) -> Vec<Tuple> {
+        keys.iter()
+            .enumerate()
+            .map(|(i, k)| Tuple::new(vec![Field::IntField(i as i32), Field::StringField(k.to_string())]))
+            .collect()
+    }
+
+    #[test]
+    fn merge_is_sorted() {
+        let mut d1 = Dictionary::new();
+        d1.encode("pear");
+        d1.encode("apple");
+        let mut d2 = Dictionary::new();
+        d2.encode("fig");
+        d2.encode("pear");
+        let (global, remaps) = Dictionary::merge(&[d1, d2]);
+        assert!(global.is_sorted());
+        assert_eq!(global.len(), 3);
+        assert_eq!(remaps, vec![vec![2, 0], vec![1, 2]]);
+    }
+
+    #[test]
+    fn encode_partitions_shares_codes() -> Result<(), CrustyError> {
+        let partitions = vec![string_tuples(&["b", "a", "b"]), string_tuples(&["c", "a"])];
+        let (dict, mut encoded) = encode_partitions(partitions.clone(), 1)?;
+        assert_eq!(encoded[0][0].get_field(1), Some(&Field::IntField(1)));
+        assert_eq!(encoded[1][1].get_field(1), Some(&Field::IntField(0)));
+        for partition in encoded.iter_mut() {
+            decode_tuples(partition, 1, &dict)?;
+        }
+        assert_eq!(partitions, encoded);
+        Ok(())
+    }
+
+    #[test]
+    fn encode_join_partitions_shares_codes() -> Result<(), CrustyError> {
+        let left = vec![string_tuples(&["b", "a"]), string_tuples(&["d"])];
+        // the right key is the only field
+        let keys = |keys: &[&str]| keys.iter().map(|k| Tuple::new(vec![Field::StringField(k.to_string())])).collect();
+        let right = vec![keys(&["a"]), keys(&["c", "d"])];
+        let (dict, encoded_l, encoded_r) = encode_join_partitions(left, 1, right, 0)?;
+        assert!(dict.is_sorted());
+        // the workers of both partitions gave d a local code, both map to the same global one
+        assert_eq!(encoded_l[1][0].get_field(1), Some(&Field::IntField(3)));
+        assert_eq!(encoded_r[1][1].get_field(0), Some(&Field::IntField(3)));
+        assert_eq!(encoded_l[0][1].get_field(1), encoded_r[0][0].get_field(0));
+        Ok(())
+    }
+
+    #[test]
+    fn interned_join_keys() -> Result<(), CrustyError> {
+        use crate::common::{SimplePredicateOp, TupleIterator};
+        use crate::join::{HashEqJoin, MergeStrategy, SortMergeJoin};
+        use crate::operators::compare_rows;
+
+        let keys = ["pear", "apple", "fig"];
+        let table = |n: usize, step: usize| string_tuples(&(0..n).map(|i| keys[i * step % keys.len()]).collect::<Vec<_>>());
+        let (left, right) = (table(30, 1), table(20, 2));
+        let schema = TableSchema::from_vecs(vec!["id", "key"], vec![DataType::Int, DataType::String]);
+        let scan = |tuples: &Vec<Tuple>| -> Box<dyn OpIterator + Send> { Box::new(TupleIterator::new(tuples.clone(), schema.clone())) };
+        let mut expected = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right), MergeStrategy::M_WAY)?;
+
+        let interner = Interner::new();
+        let intern = |tuples| Intern::new(scan(tuples), 1, interner.clone());
+        let join = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, intern(&left)?, intern(&right)?, MergeStrategy::M_WAY)?;
+        assert_eq!(join.get_schema().get_attribute(1).unwrap().dtype(), &DataType::Int);
+        let mut op = Resolve::new(Box::new(join), vec![1, 3], interner.clone())?;
+        assert_eq!(op.sort_order(), None);
+        assert_eq!(compare_rows(&mut expected, &mut op)?, 200);
+        assert_eq!(interner.len(), keys.len());
+
+        let hash = HashEqJoin::new(SimplePredicateOp::Equals, 1, 1, intern(&left)?, intern(&right)?)?;
+        let mut op = Resolve::new(Box::new(hash), vec![1, 3], interner.clone())?;
+        assert_eq!(compare_rows(&mut expected, &mut op)?, 200);
+        // only string fields are interned, and only int fields resolved
+        assert!(Intern::new(scan(&left), 0, interner.clone()).is_err());
+        assert!(Resolve::new(scan(&left), vec![1], interner).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn encode_non_string_key() {
+        let partitions = vec![string_tuples(&["a"])];
+        assert!(encode_partitions(partitions, 0).is_err());
+    }
+}
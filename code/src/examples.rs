@@ -0,0 +1,193 @@
+//! Complete pipelines built from the operators of the crate.
+//!
+//! Each function reads CSV files and returns the root of its operator tree without
+//! opening it; run it with `operators::write_csv` or by calling `next` directly.
+use std::path::Path;
+use crate::common::{AggOp, CrustyError, DataType, Field, OpIterator, SimplePredicateOp, TableSchema};
+use crate::join::{MergeStrategy, SortMergeJoin};
+use crate::keys::{date_bucket_join, TimeGranularity};
+use crate::operators::{Aggregate, CsvScan, Filter};
+use crate::setops::{Distinct, DistinctMethod, ExceptAll, UnionAll};
+
+/// Schema of customer files: id, region.
+pub fn customers_schema() -> TableSchema {
+    TableSchema::from_vecs(vec!["id", "region"], vec![DataType::Int, DataType::String])
+}
+
+/// Schema of order files: id, customer id, amount.
+pub fn orders_schema() -> TableSchema {
+    TableSchema::from_vecs(vec!["id", "customer_id", "amount"], vec![DataType::Int, DataType::Int, DataType::Int])
+}
+
+/// Schema of event files: id, timestamp in seconds.
+pub fn events_schema() -> TableSchema {
+    TableSchema::from_vecs(vec!["id", "ts"], vec![DataType::Int, DataType::Int])
+}
+
+/// Revenue and number of orders per region, counting only orders of at least an amount.
+///
+/// CSV scan -> filter -> sort-merge join -> aggregate. Output tuples are region, sum of
+/// the amounts and number of orders, ordered on the region.
+///
+/// # Arguments
+///
+/// * `customers` - Customer file, see customers_schema.
+/// * `orders` - Order file, see orders_schema.
+/// * `min_amount` - Smallest amount of the counted orders.
+/// * `strategy` - Level 3 method of the join.
+pub fn revenue_by_region(
+    customers: &Path,
+    orders: &Path,
+    min_amount: i32,
+    strategy: MergeStrategy,
+) -> Result<Box<dyn OpIterator>, CrustyError> {
+    let orders = Filter::new(
+        Box::new(CsvScan::new(orders, orders_schema())),
+        2,
+        SimplePredicateOp::GreaterThanOrEq,
+        Field::IntField(min_amount),
+    );
+    let customers = CsvScan::new(customers, customers_schema());
+    // order.customer_id = customer.id, joined tuples are the order then the customer
+    let join = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 0, orders, customers, strategy)?;
+    Ok(Box::new(Aggregate::new(Box::new(join), vec![4], vec![(2, AggOp::Sum), (0, AggOp::Count)])))
+}
+
+/// Distinct rows found in exactly one of two extracts of the same table.
+///
+/// (a except all b) union all (b except all a) -> distinct. Output tuples are ordered
+/// on all fields.
+///
+/// # Arguments
+///
+/// * `a` - First extract.
+/// * `b` - Second extract.
+/// * `schema` - Schema of both extracts.
+pub fn reconcile(a: &Path, b: &Path, schema: &TableSchema) -> Box<dyn OpIterator> {
+    let scan = |path: &Path| -> Box<dyn OpIterator> { Box::new(CsvScan::new(path, schema.clone())) };
+    let only_a = ExceptAll::new(scan(a), scan(b));
+    let only_b = ExceptAll::new(scan(b), scan(a));
+    let both = UnionAll::new(Box::new(only_a), Box::new(only_b));
+    Box::new(Distinct::new(Box::new(both), DistinctMethod::Sort))
+}
+
+/// Number of events per day of a calendar.
+///
+/// CSV scans -> date bucket join -> aggregate. Output tuples are the id of the day and
+/// its number of events, ordered on the id; days without events are left out.
+///
+/// # Arguments
+///
+/// * `events` - Event file, see events_schema.
+/// * `days` - Calendar file with one event per day at its start, see events_schema.
+/// * `strategy` - Level 3 method of the join.
+pub fn events_per_day(events: &Path, days: &Path, strategy: MergeStrategy) -> Result<Box<dyn OpIterator>, CrustyError> {
+    let events = Box::new(CsvScan::new(events, events_schema()));
+    let days = Box::new(CsvScan::new(days, events_schema()));
+    // event, event key, day, day key
+    let join = date_bucket_join(events, 1, days, 1, TimeGranularity::Day, strategy)?;
+    Ok(Box::new(Aggregate::new(Box::new(join), vec![3], vec![(0, AggOp::Count)])))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+    use crate::common::Tuple;
+    use crate::operators::write_csv;
+
+    const DAY: i32 = 24 * 60 * 60;
+
+    /// Fixture file removed when dropped.
+    struct TestFile(PathBuf);
+
+    impl TestFile {
+        fn new(name: &str, lines: &[&str]) -> Self {
+            let path = env::temp_dir().join(format!("join-examples-{}-{}.csv", process::id(), name));
+            fs::write(&path, lines.join("\n")).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn test_run(mut pipeline: Box<dyn OpIterator>) -> Result<String, CrustyError> {
+        let mut csv = Vec::new();
+        write_csv(&mut *pipeline, &mut csv)?;
+        Ok(String::from_utf8(csv).unwrap())
+    }
+
+    fn test_revenue_by_region(strategy: MergeStrategy, name: &str) -> Result<(), CrustyError> {
+        let customers = TestFile::new(&format!("customers-{}", name), &["1,east", "2,west", "3,east", "4,north"]);
+        let orders = TestFile::new(
+            &format!("orders-{}", name),
+            &["10,1,50", "11,1,5", "12,2,70", "13,3,30", "14,3,40", "15,5,99"],
+        );
+        let pipeline = revenue_by_region(&customers.0, &orders.0, 10, strategy)?;
+        assert_eq!(pipeline.get_schema().get_attribute(1).unwrap().name(), "sum(amount)");
+        assert_eq!(test_run(pipeline)?, "east,120,3\nwest,70,1\n");
+        Ok(())
+    }
+
+    fn test_reconcile() -> Result<(), CrustyError> {
+        let a = TestFile::new("a", &["1,x", "2,y", "2,y", "3,z"]);
+        let b = TestFile::new("b", &["1,x", "2,y", "4,w"]);
+        let schema = TableSchema::from_vecs(vec!["id", "name"], vec![DataType::Int, DataType::String]);
+        assert_eq!(test_run(reconcile(&a.0, &b.0, &schema))?, "2,y\n3,z\n4,w\n");
+        Ok(())
+    }
+
+    fn test_events_per_day(strategy: MergeStrategy) -> Result<(), CrustyError> {
+        let events = TestFile::new(
+            "events",
+            &["1,5", &format!("2,{}", DAY + 7), &format!("3,{}", DAY + 9), &format!("4,{}", 5 * DAY)],
+        );
+        let days = TestFile::new("days", &["100,0", &format!("101,{}", DAY), &format!("102,{}", 2 * DAY)]);
+        assert_eq!(test_run(events_per_day(&events.0, &days.0, strategy)?)?, "100,1\n101,2\n");
+        Ok(())
+    }
+
+    fn test_bad_line() {
+        let orders = TestFile::new("bad", &["10,1,50", "11,one,5"]);
+        let mut scan = CsvScan::new(&orders.0, orders_schema());
+        scan.open().unwrap();
+        assert_eq!(scan.next().unwrap(), Some(Tuple::new(vec![Field::IntField(10), Field::IntField(1), Field::IntField(50)])));
+        assert!(matches!(scan.next(), Err(CrustyError::ExecutionError(_))));
+    }
+
+    mod pipelines {
+        use super::*;
+
+        #[test]
+        fn revenue_by_region_m_way() -> Result<(), CrustyError> {
+            test_revenue_by_region(MergeStrategy::M_WAY, "m-way")
+        }
+
+        #[test]
+        fn revenue_by_region_m_pass() -> Result<(), CrustyError> {
+            test_revenue_by_region(MergeStrategy::M_PASS, "m-pass")
+        }
+
+        #[test]
+        fn reconcile() -> Result<(), CrustyError> {
+            test_reconcile()
+        }
+
+        #[test]
+        fn events_per_day() -> Result<(), CrustyError> {
+            test_events_per_day(MergeStrategy::M_WAY)
+        }
+
+        #[test]
+        fn bad_line() {
+            test_bad_line();
+        }
+    }
+}
@@ -0,0 +1,413 @@
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+use crate::common::{not_open, CrustyError, Field, OpIterator, TableSchema, Tuple};
+use crate::join::hash_field;
+
+/// Default number of tuples buffered in an exchange channel.
+pub const EXCHANGE_CAPACITY: usize = 1024;
+
+/// Tuples or the error sent by a producer thread.
+type Message = Result<Tuple, CrustyError>;
+
+/// Runs its child on a background thread, streaming the tuples over a bounded channel.
+///
+/// The child runs ahead of the consumer by up to the capacity of the channel, e.g. both
+/// children of a join produce concurrently. The output keeps the order of the child.
+pub struct Exchange {
+    /// Child, None while it runs on the producer thread.
+    child: Option<Box<dyn OpIterator + Send>>,
+    /// Schema of the child.
+    schema: TableSchema,
+    /// Sort order of the child.
+    order: Option<Vec<usize>>,
+    /// Number of tuples buffered in the channel.
+    capacity: usize,
+    /// Receiving end of the channel while open.
+    receiver: Option<Receiver<Message>>,
+    /// Producer thread, returning the child once it is done.
+    producer: Option<JoinHandle<Box<dyn OpIterator + Send>>>,
+}
+
+impl Exchange {
+    /// Exchange constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - Child run on the producer thread.
+    /// * `capacity` - Number of tuples buffered in the channel, at least 1.
+    pub fn new(child: Box<dyn OpIterator + Send>, capacity: usize) -> Self {
+        Self {
+            schema: child.get_schema().clone(),
+            order: child.sort_order(),
+            child: Some(child),
+            capacity: capacity.max(1),
+            receiver: None,
+            producer: None,
+        }
+    }
+
+    // helper method to drop the channel, so a blocked producer stops, and wait for the
+    // producer thread to take the child back
+    fn close_producer(&mut self) -> Result<(), CrustyError> {
+        self.receiver = None;
+        if let Some(producer) = self.producer.take() {
+            let child = producer
+                .join()
+                .map_err(|_| CrustyError::ExecutionError(String::from("exchange producer panicked")))?;
+            self.child = Some(child);
+        }
+        Ok(())
+    }
+}
+
+// helper method to run a child to completion, sending its tuples to a sink until it
+// refuses them; the child is closed and returned
+fn produce(mut child: Box<dyn OpIterator + Send>, mut send: impl FnMut(Message) -> bool) -> Box<dyn OpIterator + Send> {
+    if let Err(e) = child.open() {
+        send(Err(e));
+        return child;
+    }
+    loop {
+        match child.next() {
+            Ok(Some(t)) => {
+                if !send(Ok(t)) {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                send(Err(e));
+                break;
+            }
+        }
+    }
+    let _ = child.close();
+    child
+}
+
+impl OpIterator for Exchange {
+    /// Starts the producer thread.
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.close_producer()?;
+        let child = self
+            .child
+            .take()
+            .ok_or_else(|| CrustyError::ExecutionError(String::from("exchange child lost by a panic")))?;
+        let (sender, receiver) = sync_channel(self.capacity);
+        self.producer = Some(thread::spawn(move || produce(child, |m| sender.send(m).is_ok())));
+        self.receiver = Some(receiver);
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        let receiver = match &self.receiver {
+            None => return not_open(),
+            Some(receiver) => receiver,
+        };
+        match receiver.recv() {
+            Ok(m) => m.map(Some),
+            // the producer is done once it drops the sender
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Stops the producer thread, which closes the child.
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if self.receiver.is_none() {
+            return not_open();
+        }
+        self.close_producer()
+    }
+
+    /// Runs the child again from the start.
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if self.receiver.is_none() {
+            return not_open();
+        }
+        self.open()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        self.order.clone()
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        match &self.child {
+            Some(child) => vec![&**child as &dyn OpIterator],
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Drop for Exchange {
+    fn drop(&mut self) {
+        let _ = self.close_producer();
+    }
+}
+
+/// How RepartitionExchange assigns the tuples to its partitions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Partitioning {
+    /// On the hash of a field, into a number of partitions.
+    Hash { index: usize, partitions: usize },
+    /// On ranges of a field: partition i takes the keys from bounds[i - 1] included to
+    /// bounds[i] excluded, so there is one more partition than bounds.
+    Range { index: usize, bounds: Vec<Field> },
+}
+
+impl Partitioning {
+    /// Returns the number of partitions.
+    pub fn partitions(&self) -> usize {
+        match self {
+            Partitioning::Hash { partitions, .. } => (*partitions).max(1),
+            Partitioning::Range { bounds, .. } => bounds.len() + 1,
+        }
+    }
+
+    /// Returns the partition of a tuple, failing with an ExecutionError if it has no such field.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple` - Tuple to assign.
+    pub fn partition(&self, tuple: &Tuple) -> Result<usize, CrustyError> {
+        let (Partitioning::Hash { index, .. } | Partitioning::Range { index, .. }) = self;
+        let key = tuple
+            .get_field(*index)
+            .ok_or_else(|| CrustyError::ExecutionError(format!("tuple has no field {}", index)))?;
+        Ok(match self {
+            Partitioning::Hash { .. } => (hash_field(key) % self.partitions() as u64) as usize,
+            Partitioning::Range { bounds, .. } => bounds.partition_point(|bound| bound <= key),
+        })
+    }
+}
+
+/// Runs its child on a background thread, partitioning the tuples across one bounded
+/// channel per consumer.
+///
+/// Each partition is read by its own ExchangePartition, so N consumers, e.g. N partial
+/// joins, run on the same input concurrently. A full channel blocks the producer, so the
+/// partitions must be read concurrently, or the capacity must hold a whole partition.
+pub struct RepartitionExchange {
+    /// Child run on the producer thread.
+    child: Box<dyn OpIterator + Send>,
+    /// Assignment of the tuples to the partitions.
+    partitioning: Partitioning,
+    /// Number of tuples buffered in each channel.
+    capacity: usize,
+}
+
+impl RepartitionExchange {
+    /// RepartitionExchange constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - Child run on the producer thread.
+    /// * `partitioning` - Assignment of the tuples to the partitions.
+    /// * `capacity` - Number of tuples buffered in each channel, at least 1.
+    pub fn new(child: Box<dyn OpIterator + Send>, partitioning: Partitioning, capacity: usize) -> Self {
+        Self {
+            child,
+            partitioning,
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Start the producer thread, returning the consumer of every partition.
+    ///
+    /// An error of the child, e.g. a tuple without the partitioning field, is sent to
+    /// every partition.
+    pub fn start(self) -> Vec<ExchangePartition> {
+        self.spawn().0
+    }
+
+    /// Start the producer thread, returning the consumer of every partition and the
+    /// thread, which returns the closed child once every tuple was sent, see start.
+    pub fn spawn(self) -> (Vec<ExchangePartition>, JoinHandle<Box<dyn OpIterator + Send>>) {
+        let schema = self.child.get_schema().clone();
+        let order = self.child.sort_order();
+        let (senders, receivers): (Vec<SyncSender<Message>>, Vec<Receiver<Message>>) =
+            (0..self.partitioning.partitions()).map(|_| sync_channel(self.capacity)).unzip();
+        let partitioning = self.partitioning;
+        let producer = thread::spawn(move || {
+            let mut closed = vec![false; senders.len()];
+            produce(self.child, |m| {
+                match m.and_then(|t| partitioning.partition(&t).map(|p| (p, t))) {
+                    Ok((p, t)) => {
+                        // a partition that stopped reading does not stop the others
+                        closed[p] |= senders[p].send(Ok(t)).is_err();
+                        closed.contains(&false)
+                    }
+                    Err(e) => {
+                        for sender in senders.iter() {
+                            let _ = sender.send(Err(e.clone()));
+                        }
+                        false
+                    }
+                }
+            })
+        });
+        let partitions = receivers
+            .into_iter()
+            .map(|receiver| ExchangePartition {
+                receiver: Some(receiver),
+                schema: schema.clone(),
+                order: order.clone(),
+                open: false,
+            })
+            .collect();
+        (partitions, producer)
+    }
+}
+
+/// Consumer of a partition of a RepartitionExchange.
+///
+/// The tuples of the partition are streamed once, so it cannot be rewound.
+pub struct ExchangePartition {
+    /// Receiving end of the channel, None once closed.
+    receiver: Option<Receiver<Message>>,
+    /// Schema of the tuples.
+    schema: TableSchema,
+    /// Sort order of the partitioned child, kept within each partition.
+    order: Option<Vec<usize>>,
+    /// Whether the consumer is open.
+    open: bool,
+}
+
+impl OpIterator for ExchangePartition {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        if self.receiver.is_none() {
+            return Err(CrustyError::ExecutionError(String::from("exchange partition was closed")));
+        }
+        self.open = true;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        match self.receiver.as_ref().map(Receiver::recv) {
+            Some(Ok(m)) => m.map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Stops reading the partition; the producer drops its further tuples.
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        self.open = false;
+        self.receiver = None;
+        Ok(())
+    }
+
+    /// Fails with an ExecutionError, the partition is streamed once.
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        Err(CrustyError::ExecutionError(String::from("an exchange partition cannot be rewound")))
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        self.order.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{SimplePredicateOp, TupleIterator};
+    use crate::generator::{create_vec_tuple, get_int_table_schema};
+    use crate::join::{Join, MergeStrategy, SortMergeJoin};
+    use crate::morsel::parallel_map;
+    use crate::operators::compare_rows;
+
+    fn scan(tuples: &[Tuple]) -> Box<dyn OpIterator + Send> {
+        Box::new(TupleIterator::new(tuples.to_vec(), get_int_table_schema(2)))
+    }
+
+    // helper method to read every tuple of an operator
+    fn collect(op: &mut dyn OpIterator) -> Result<Vec<Tuple>, CrustyError> {
+        let mut res = Vec::new();
+        while let Some(t) = op.next()? {
+            res.push(t);
+        }
+        Ok(res)
+    }
+
+    #[test]
+    fn pipelined_join() -> Result<(), CrustyError> {
+        let left = create_vec_tuple(2000, 2, 300, 1);
+        let right = create_vec_tuple(2000, 2, 300, 2);
+        let mut exchange = Exchange::new(scan(&left), 4);
+        exchange.open()?;
+        assert_eq!(collect(&mut exchange)?, left);
+        // stopped early, then run again from the start
+        exchange.rewind()?;
+        assert_eq!(exchange.next()?, Some(left[0].clone()));
+        exchange.close()?;
+        assert_eq!(exchange.children().len(), 1);
+
+        // both children of the join produce on their own thread
+        let mut join = SortMergeJoin::new(
+            SimplePredicateOp::Equals,
+            1,
+            1,
+            Exchange::new(scan(&left), 16),
+            Exchange::new(scan(&right), EXCHANGE_CAPACITY),
+            MergeStrategy::M_WAY,
+        )?;
+        let mut oracle = Join::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right))?;
+        compare_rows(&mut oracle, &mut join)?;
+        Ok(())
+    }
+
+    #[test]
+    fn repartition() -> Result<(), CrustyError> {
+        let tuples = create_vec_tuple(3000, 2, 100, 3);
+        let bounds = vec![Field::IntField(25), Field::IntField(50)];
+        for partitioning in [
+            Partitioning::Hash { index: 1, partitions: 4 },
+            Partitioning::Range { index: 1, bounds: bounds.clone() },
+        ] {
+            let consumers = RepartitionExchange::new(scan(&tuples), partitioning.clone(), 8).start();
+            assert_eq!(consumers.len(), partitioning.partitions());
+            // the partitions are read concurrently
+            let parts = parallel_map(consumers.into_iter().collect(), 4, |mut consumer: ExchangePartition| {
+                consumer.open().unwrap();
+                collect(&mut consumer).unwrap()
+            });
+            for (p, part) in parts.iter().enumerate() {
+                assert!(part.iter().all(|t| partitioning.partition(t) == Ok(p)));
+            }
+            let mut all: Vec<Tuple> = parts.into_iter().flatten().collect();
+            let mut expected = tuples.clone();
+            all.sort_by(|a, b| a.field_vals.cmp(&b.field_vals));
+            expected.sort_by(|a, b| a.field_vals.cmp(&b.field_vals));
+            assert_eq!(all, expected);
+        }
+        let key = |x| Tuple::new(vec![Field::IntField(0), Field::IntField(x)]);
+        let range = Partitioning::Range { index: 1, bounds };
+        assert_eq!((range.partition(&key(24)), range.partition(&key(25)), range.partition(&key(99))), (Ok(0), Ok(1), Ok(2)));
+        assert!(range.partition(&Tuple::new(Vec::new())).is_err());
+
+        // an error of the child reaches every partition
+        let mut bad = RepartitionExchange::new(scan(&tuples), Partitioning::Hash { index: 5, partitions: 2 }, 8).start();
+        for consumer in bad.iter_mut() {
+            consumer.open()?;
+            assert!(consumer.next().is_err());
+            assert!(consumer.rewind().is_err());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+use std::fmt;
+use std::ops::{Add, Mul, Sub};
+use crate::collation::Collation;
+use crate::common::{CrustyError, DataType, Field, TableSchema, Tuple};
+
+/// Scalar expression over the fields of a tuple, e.g. a computed join key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    /// Field at an index of the tuple.
+    Column(usize),
+    /// Constant value.
+    Literal(Field),
+    /// Sum of two integer expressions, wrapping on overflow.
+    Add(Box<Expr>, Box<Expr>),
+    /// Difference of two integer expressions, wrapping on overflow.
+    Sub(Box<Expr>, Box<Expr>),
+    /// Product of two integer expressions, wrapping on overflow.
+    Mul(Box<Expr>, Box<Expr>),
+    /// String expression in lowercase.
+    Lower(Box<Expr>),
+    /// String expression in uppercase.
+    Upper(Box<Expr>),
+    /// Sort key of an expression under a collation, see Collation::sort_key.
+    Collate(Box<Expr>, Collation),
+}
+
+impl Expr {
+    /// Returns the lowercase of a string expression.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - Operand.
+    pub fn lower(expr: Expr) -> Self {
+        Expr::Lower(Box::new(expr))
+    }
+
+    /// Returns the uppercase of a string expression.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - Operand.
+    pub fn upper(expr: Expr) -> Self {
+        Expr::Upper(Box::new(expr))
+    }
+
+    /// Returns the sort key of an expression under a collation.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - Operand.
+    /// * `collation` - Collation of the key.
+    pub fn collate(expr: Expr, collation: Collation) -> Self {
+        Expr::Collate(Box::new(expr), collation)
+    }
+
+    /// Returns the type of the expression over tuples of a schema.
+    ///
+    /// Fails with a ValidationError if a column is not in the schema or an operand has
+    /// the wrong type.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the tuples.
+    pub fn data_type(&self, schema: &TableSchema) -> Result<DataType, CrustyError> {
+        let expect = |expr: &Expr, dtype: DataType| match expr.data_type(schema)? {
+            found if found == dtype => Ok(dtype),
+            found => Err(CrustyError::ValidationError(format!("{} is {:?}, expected {:?}", expr, found, dtype))),
+        };
+        match self {
+            Expr::Column(i) => schema
+                .get_attribute(*i)
+                .map(|attr| attr.dtype().clone())
+                .ok_or_else(|| CrustyError::ValidationError(format!("expression has no field {}", i))),
+            Expr::Literal(Field::IntField(_)) => Ok(DataType::Int),
+            Expr::Literal(Field::StringField(_)) => Ok(DataType::String),
+            Expr::Add(l, r) | Expr::Sub(l, r) | Expr::Mul(l, r) => {
+                expect(l, DataType::Int)?;
+                expect(r, DataType::Int)
+            }
+            Expr::Lower(e) | Expr::Upper(e) => expect(e, DataType::String),
+            Expr::Collate(e, _) => e.data_type(schema),
+        }
+    }
+
+    /// Evaluate the expression on a tuple.
+    ///
+    /// Fails with an ExecutionError if a column is not in the tuple or an operand has the
+    /// wrong type.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple` - Tuple to evaluate on.
+    pub fn eval(&self, tuple: &Tuple) -> Result<Field, CrustyError> {
+        let int = |expr: &Expr| match expr.eval(tuple)? {
+            Field::IntField(x) => Ok(x),
+            _ => Err(CrustyError::ExecutionError(format!("{} is not an integer", expr))),
+        };
+        let string = |expr: &Expr| match expr.eval(tuple)? {
+            Field::StringField(s) => Ok(s),
+            _ => Err(CrustyError::ExecutionError(format!("{} is not a string", expr))),
+        };
+        match self {
+            Expr::Column(i) => tuple
+                .get_field(*i)
+                .cloned()
+                .ok_or_else(|| CrustyError::ExecutionError(format!("tuple has no field {}", i))),
+            Expr::Literal(field) => Ok(field.clone()),
+            Expr::Add(l, r) => Ok(Field::IntField(int(l)?.wrapping_add(int(r)?))),
+            Expr::Sub(l, r) => Ok(Field::IntField(int(l)?.wrapping_sub(int(r)?))),
+            Expr::Mul(l, r) => Ok(Field::IntField(int(l)?.wrapping_mul(int(r)?))),
+            Expr::Lower(e) => Ok(Field::StringField(string(e)?.to_lowercase())),
+            Expr::Upper(e) => Ok(Field::StringField(string(e)?.to_uppercase())),
+            Expr::Collate(e, collation) => Ok(collation.sort_key(&e.eval(tuple)?).into_owned()),
+        }
+    }
+}
+
+impl Add for Expr {
+    type Output = Expr;
+
+    fn add(self, other: Expr) -> Expr {
+        Expr::Add(Box::new(self), Box::new(other))
+    }
+}
+
+impl Sub for Expr {
+    type Output = Expr;
+
+    fn sub(self, other: Expr) -> Expr {
+        Expr::Sub(Box::new(self), Box::new(other))
+    }
+}
+
+impl Mul for Expr {
+    type Output = Expr;
+
+    fn mul(self, other: Expr) -> Expr {
+        Expr::Mul(Box::new(self), Box::new(other))
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Column(i) => write!(f, "col{}", i),
+            Expr::Literal(Field::StringField(s)) => write!(f, "'{}'", s),
+            Expr::Literal(field) => write!(f, "{}", field),
+            Expr::Add(l, r) => write!(f, "({} + {})", l, r),
+            Expr::Sub(l, r) => write!(f, "({} - {})", l, r),
+            Expr::Mul(l, r) => write!(f, "({} * {})", l, r),
+            Expr::Lower(e) => write!(f, "lower({})", e),
+            Expr::Upper(e) => write!(f, "upper({})", e),
+            Expr::Collate(e, collation) => write!(f, "({} collate {:?})", e, collation),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::Attribute;
+
+    #[test]
+    fn eval_and_type() {
+        let schema = TableSchema::new(vec![
+            Attribute::new("a".to_string(), DataType::Int),
+            Attribute::new("b".to_string(), DataType::Int),
+            Attribute::new("s".to_string(), DataType::String),
+        ]);
+        let t = Tuple::new(vec![Field::IntField(3), Field::IntField(4), Field::StringField("MiXed".to_string())]);
+        let sum = Expr::Column(0) + Expr::Column(1) * Expr::Literal(Field::IntField(2));
+        assert_eq!(sum.eval(&t), Ok(Field::IntField(11)));
+        assert_eq!(sum.data_type(&schema), Ok(DataType::Int));
+        assert_eq!(sum.to_string(), "(col0 + (col1 * 2))");
+        assert_eq!(Expr::lower(Expr::Column(2)).eval(&t), Ok(Field::StringField("mixed".to_string())));
+        assert_eq!((Expr::Column(0) - Expr::Column(1)).eval(&t), Ok(Field::IntField(-1)));
+
+        let wrong = Expr::upper(Expr::Column(0));
+        assert!(matches!(wrong.data_type(&schema), Err(CrustyError::ValidationError(_))));
+        assert!(matches!(wrong.eval(&t), Err(CrustyError::ExecutionError(_))));
+        assert!(Expr::Column(3).data_type(&schema).is_err());
+        let folded = Expr::collate(Expr::Column(2), Collation::CaseInsensitive);
+        assert_eq!(folded.eval(&t), Ok(Field::StringField("mixed".to_string())));
+        assert_eq!(folded.to_string(), "(col2 collate CaseInsensitive)");
+    }
+}
@@ -0,0 +1,640 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::io::Write;
+use std::ops::Range;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use crate::common::{Attribute, CrustyError, DataType, Field, TableSchema, Tuple, TupleIterator};
+
+/// Source of synthetic integer tuples for tests and benchmarks.
+///
+/// Implementors only produce single values; tuples, scans and files are built from them.
+pub trait TupleGenerator {
+    /// Returns the value of a field of a tuple.
+    ///
+    /// # Arguments
+    ///
+    /// * `row` - Number of the tuple being generated.
+    /// * `field` - Index of the field in the tuple.
+    fn value(&mut self, row: usize, field: usize) -> i32;
+
+    /// Generate tuples of IntFields.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple_number` - Number of tuples.
+    /// * `width` - Number of fields of each tuple.
+    fn generate(&mut self, tuple_number: usize, width: usize) -> Vec<Tuple> {
+        (0..tuple_number)
+            .map(|row| Tuple::from_fields((0..width).map(|field| Field::IntField(self.value(row, field))).collect()))
+            .collect()
+    }
+
+    /// Generate tuples of IntFields into a TupleIterator.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple_number` - Number of tuples.
+    /// * `width` - Number of fields of each tuple.
+    fn generate_scan(&mut self, tuple_number: usize, width: usize) -> TupleIterator {
+        TupleIterator::new(self.generate(tuple_number, width), get_int_table_schema(width))
+    }
+
+    /// Generate tuples of IntFields as CSV lines.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple_number` - Number of tuples.
+    /// * `width` - Number of fields of each tuple.
+    /// * `writer` - Destination of the lines, e.g. a file.
+    fn generate_csv(&mut self, tuple_number: usize, width: usize, writer: &mut dyn Write) -> Result<(), CrustyError> {
+        for row in 0..tuple_number {
+            let values: Vec<String> = (0..width).map(|field| self.value(row, field).to_string()).collect();
+            writeln!(writer, "{}", values.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates a new table schema for a table with width number of IntFields.
+pub fn get_int_table_schema(width: usize) -> TableSchema {
+    TableSchema::new((0..width).map(|_| Attribute::new(String::new(), DataType::Int)).collect())
+}
+
+/// Seed of the generated data when none is given, e.g. by the benchmarks.
+pub const DEFAULT_SEED: u64 = 42;
+
+/// Creates a random source that draws the same values for the same seed.
+///
+/// # Arguments
+///
+/// * `seed` - Seed of the random source.
+pub fn seeded_rng(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+/// Creates tuples of uniform random IntFields in the 1000 values below range.
+///
+/// # Arguments
+///
+/// * `tuple_number` - Number of tuples.
+/// * `width` - Number of fields of each tuple.
+/// * `range` - Upper bound (exclusive) of the values, at least 1000.
+/// * `seed` - Seed of the random values, the same seed gives the same tuples.
+pub fn create_vec_tuple(tuple_number: usize, width: usize, range: usize, seed: u64) -> Vec<Tuple> {
+    let range = range as i32;
+    Uniform::new(seeded_rng(seed), (range - 1000)..range).generate(tuple_number, width)
+}
+
+/// Creates a left and a right table sharing a fraction of their tuples.
+///
+/// Both tables have tuple_number tuples of create_vec_tuple; the last overlap share of
+/// them are the same tuples on both sides, so at least that share of the keys match.
+///
+/// # Arguments
+///
+/// * `tuple_number` - Number of tuples of each table.
+/// * `width` - Number of fields of each tuple.
+/// * `range` - Upper bound (exclusive) of the values, at least 1000.
+/// * `overlap` - Share of the tuples in both tables, between 0 and 1.
+/// * `seed` - Seed of the random values, the same seed gives the same tables.
+pub fn create_overlapping_tuples(
+    tuple_number: usize,
+    width: usize,
+    range: usize,
+    overlap: f64,
+    seed: u64,
+) -> (Vec<Tuple>, Vec<Tuple>) {
+    let common_number = (tuple_number as f64 * overlap.clamp(0.0, 1.0)).round() as usize;
+    let range = range as i32;
+    let mut uniform = Uniform::new(seeded_rng(seed), (range - 1000)..range);
+    let common = uniform.generate(common_number, width);
+    let mut left = uniform.generate(tuple_number - common_number, width);
+    let mut right = uniform.generate(tuple_number - common_number, width);
+    left.extend(common.iter().cloned());
+    right.extend(common);
+    (left, right)
+}
+
+/// Returns the number of tuples of the equi-join of two tables, from the counts of their
+/// keys rather than by joining them, e.g. to check the output of a benchmark run.
+///
+/// # Arguments
+///
+/// * `left` - Left tuples.
+/// * `left_index` - Index of the left join field.
+/// * `right` - Right tuples.
+/// * `right_index` - Index of the right join field.
+pub fn expected_join_rows(left: &[Tuple], left_index: usize, right: &[Tuple], right_index: usize) -> usize {
+    let mut counts: HashMap<&Field, usize> = HashMap::new();
+    for t in left {
+        *counts.entry(&t.field_vals[left_index]).or_insert(0) += 1;
+    }
+    right.iter().map(|t| counts.get(&t.field_vals[right_index]).copied().unwrap_or(0)).sum()
+}
+
+/// Left and right tables with an exact join selectivity on their second field, see
+/// create_selective_tuples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectiveTables {
+    /// Left tuples.
+    pub left: Vec<Tuple>,
+    /// Right tuples.
+    pub right: Vec<Tuple>,
+    /// Number of distinct keys of each table.
+    pub distinct_keys: usize,
+    /// Number of distinct keys found in both tables.
+    pub shared_keys: usize,
+    /// Number of tuples of the equi-join of the tables on their second field.
+    pub output_rows: usize,
+}
+
+/// Creates a left and a right table joining on an exact share of their keys.
+///
+/// Each table has distinct_keys keys in its second field, spread evenly over its tuples,
+/// and only selectivity of them are shared, the others being disjoint. So exactly that
+/// share of the tuples of each side finds a match, unlike create_overlapping_tuples where
+/// random keys in a narrow band also match by accident. The other fields are random.
+///
+/// # Arguments
+///
+/// * `tuple_number` - Number of tuples of each table, at least 2.
+/// * `width` - Number of fields of each tuple, at least 2.
+/// * `distinct_keys` - Number of distinct keys of each table, between 1 and tuple_number.
+/// * `selectivity` - Share of the keys in both tables, between 0 and 1.
+/// * `seed` - Seed of the random values, the same seed gives the same tables.
+pub fn create_selective_tuples(
+    tuple_number: usize,
+    width: usize,
+    distinct_keys: usize,
+    selectivity: f64,
+    seed: u64,
+) -> SelectiveTables {
+    let distinct_keys = distinct_keys.clamp(1, tuple_number.max(1));
+    let shared_keys = (distinct_keys as f64 * selectivity.clamp(0.0, 1.0)).round() as usize;
+    let mut rng = seeded_rng(seed);
+    // keys below distinct_keys are the left ones, the right ones are the first shared
+    // keys and then keys the left side does not have
+    let mut keys: Vec<i32> = (0..(2 * distinct_keys - shared_keys) as i32).collect();
+    keys.shuffle(&mut rng);
+    let left_keys = &keys[..distinct_keys];
+    let right_keys = &keys[distinct_keys - shared_keys..];
+    let mut table = |keys: &[i32]| {
+        let mut tuples = Uniform::new(&mut rng, 0..i32::MAX).generate(tuple_number, width.max(2));
+        for (i, t) in tuples.iter_mut().enumerate() {
+            t.set_field(1, Field::IntField(keys[i % keys.len()]));
+        }
+        tuples.shuffle(&mut rng);
+        tuples
+    };
+    let (left, right) = (table(left_keys), table(right_keys));
+    // the first tuple_number % distinct_keys keys of a table get one more tuple
+    let count = |i: usize| tuple_number / distinct_keys + usize::from(i < tuple_number % distinct_keys);
+    let output_rows = (0..shared_keys).map(|i| count(distinct_keys - shared_keys + i) * count(i)).sum();
+    SelectiveTables {
+        left,
+        right,
+        distinct_keys,
+        shared_keys,
+        output_rows,
+    }
+}
+
+/// Orders of a TPC-H style table at scale factor 1.
+pub const TPCH_ORDERS: usize = 1_500_000;
+
+/// Customers of a TPC-H style table at scale factor 1.
+pub const TPCH_CUSTOMERS: usize = 150_000;
+
+/// Largest number of lineitems of an order.
+pub const TPCH_MAX_LINES: usize = 7;
+
+/// Days between the first and the last order date, 1992-01-01 to 1998-08-02.
+const TPCH_DAYS: i32 = 2406;
+
+/// Generator of orders and lineitem tables shaped like the ones of TPC-H, for
+/// foreign-key joins of lineitem with orders on their first field.
+///
+/// Like TPC-H, the order keys are sparse, only the first 8 of every 32 keys are used, and
+/// every order has 1 to 7 lineitems. With a skew, the lineitems instead go to orders drawn
+/// from a Zipfian distribution, so a few orders get most of them and join with a long run
+/// of lineitems. Prices are in cents and dates in days since 1992-01-01.
+pub struct TpchGenerator {
+    /// Scale factor, 1 for TPCH_ORDERS orders.
+    scale: f64,
+    /// Exponent of the Zipfian distribution of lineitems over orders, 0 for none.
+    skew: f64,
+    /// Seed of the random values.
+    seed: u64,
+}
+
+impl TpchGenerator {
+    /// Create a new TPC-H style generator.
+    ///
+    /// # Arguments
+    ///
+    /// * `scale` - Scale factor, e.g. 0.01 for 15000 orders and about 60000 lineitems.
+    /// * `seed` - Seed of the random values, the same seed gives the same tables.
+    pub fn new(scale: f64, seed: u64) -> Self {
+        Self { scale, skew: 0.0, seed }
+    }
+
+    /// Set the skew of the lineitems over the orders.
+    ///
+    /// # Arguments
+    ///
+    /// * `skew` - Exponent of the Zipfian distribution, 0 for 1 to 7 lineitems per order.
+    pub fn set_skew(&mut self, skew: f64) {
+        self.skew = skew.max(0.0);
+    }
+
+    /// Returns the number of orders, at least 1.
+    pub fn order_count(&self) -> usize {
+        ((TPCH_ORDERS as f64 * self.scale).round() as usize).max(1)
+    }
+
+    /// Returns the key of the i-th order.
+    ///
+    /// # Arguments
+    ///
+    /// * `i` - Number of the order.
+    pub fn order_key(i: usize) -> i32 {
+        ((i / 8) * 32 + i % 8 + 1) as i32
+    }
+
+    /// Returns the schema of the orders: o_orderkey, o_custkey, o_totalprice, o_orderdate.
+    pub fn orders_schema() -> TableSchema {
+        TableSchema::from_vecs(vec!["o_orderkey", "o_custkey", "o_totalprice", "o_orderdate"], vec![DataType::Int; 4])
+    }
+
+    /// Returns the schema of the lineitems: l_orderkey, l_linenumber, l_quantity,
+    /// l_extendedprice.
+    pub fn lineitem_schema() -> TableSchema {
+        TableSchema::from_vecs(vec!["l_orderkey", "l_linenumber", "l_quantity", "l_extendedprice"], vec![DataType::Int; 4])
+    }
+
+    /// Generate the orders, in key order.
+    pub fn orders(&self) -> Vec<Tuple> {
+        let mut rng = seeded_rng(self.seed);
+        let customers = ((TPCH_CUSTOMERS as f64 * self.scale).round() as i32).max(1);
+        (0..self.order_count())
+            .map(|i| {
+                Tuple::new(vec![
+                    Field::IntField(Self::order_key(i)),
+                    Field::IntField(rng.gen_range(1..=customers)),
+                    Field::IntField(rng.gen_range(100_000..50_000_000)),
+                    Field::IntField(rng.gen_range(0..TPCH_DAYS)),
+                ])
+            })
+            .collect()
+    }
+
+    /// Generate the lineitems, grouped by order key and numbered from 1 in each order.
+    ///
+    /// Every lineitem joins with exactly one order.
+    pub fn lineitem(&self) -> Vec<Tuple> {
+        let mut rng = seeded_rng(self.seed + 1);
+        let orders = self.order_count();
+        let lines: Vec<usize> = if self.skew == 0.0 {
+            (0..orders).map(|_| rng.gen_range(1..=TPCH_MAX_LINES)).collect()
+        } else {
+            // as many lineitems as without skew on average, drawn over the orders
+            let mut lines = vec![0; orders];
+            let mut zipfian = Zipfian::new(seeded_rng(self.seed + 2), 0, orders, self.skew);
+            for row in 0..orders * (TPCH_MAX_LINES + 1) / 2 {
+                lines[zipfian.value(row, 0) as usize] += 1;
+            }
+            lines
+        };
+        let mut tuples = Vec::with_capacity(lines.iter().sum());
+        for (i, count) in lines.into_iter().enumerate() {
+            for line in 1..=count {
+                let quantity = rng.gen_range(1..=50);
+                tuples.push(Tuple::new(vec![
+                    Field::IntField(Self::order_key(i)),
+                    Field::IntField(line as i32),
+                    Field::IntField(quantity),
+                    Field::IntField(quantity * rng.gen_range(90_000..=200_000)),
+                ]));
+            }
+        }
+        tuples
+    }
+}
+
+/// Values drawn uniformly from a range.
+pub struct Uniform<R: Rng> {
+    /// Random source.
+    rng: R,
+    /// Range of the values.
+    range: Range<i32>,
+}
+
+impl<R: Rng> Uniform<R> {
+    /// Create a new uniform generator.
+    ///
+    /// # Arguments
+    ///
+    /// * `rng` - Random source.
+    /// * `range` - Range of the values, must not be empty.
+    pub fn new(rng: R, range: Range<i32>) -> Self {
+        Self { rng, range }
+    }
+}
+
+impl<R: Rng> TupleGenerator for Uniform<R> {
+    fn value(&mut self, _row: usize, _field: usize) -> i32 {
+        self.rng.gen_range(self.range.clone())
+    }
+}
+
+/// Values counting up from a start, the same for every field of a tuple.
+pub struct Sequential {
+    /// Value of the first tuple.
+    start: i32,
+    /// Difference between the values of consecutive tuples.
+    step: i32,
+}
+
+impl Sequential {
+    /// Create a new sequential generator.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - Value of the first tuple.
+    /// * `step` - Difference between the values of consecutive tuples.
+    pub fn new(start: i32, step: i32) -> Self {
+        Self { start, step }
+    }
+}
+
+impl TupleGenerator for Sequential {
+    fn value(&mut self, row: usize, _field: usize) -> i32 {
+        self.start + self.step * row as i32
+    }
+}
+
+/// Values drawn from a normal distribution and rounded to the nearest integer.
+pub struct Gaussian<R: Rng> {
+    /// Random source.
+    rng: R,
+    /// Mean of the distribution.
+    mean: f64,
+    /// Standard deviation of the distribution.
+    std_dev: f64,
+}
+
+impl<R: Rng> Gaussian<R> {
+    /// Create a new Gaussian generator.
+    ///
+    /// # Arguments
+    ///
+    /// * `rng` - Random source.
+    /// * `mean` - Mean of the distribution.
+    /// * `std_dev` - Standard deviation of the distribution.
+    pub fn new(rng: R, mean: f64, std_dev: f64) -> Self {
+        Self { rng, mean, std_dev }
+    }
+}
+
+impl<R: Rng> TupleGenerator for Gaussian<R> {
+    fn value(&mut self, _row: usize, _field: usize) -> i32 {
+        // Box-Muller transform of two uniform samples
+        let u1: f64 = 1.0 - self.rng.gen::<f64>();
+        let u2: f64 = self.rng.gen();
+        let z = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+        (self.mean + self.std_dev * z).round() as i32
+    }
+}
+
+/// Values drawn from a Zipfian distribution over consecutive keys.
+///
+/// The key start + i is drawn with a probability proportional to 1 / (i + 1)^exponent,
+/// so start is the most frequent key and a larger exponent means more skew.
+pub struct Zipfian<R: Rng> {
+    /// Random source.
+    rng: R,
+    /// Most frequent key.
+    start: i32,
+    /// Cumulative probability of each key.
+    cdf: Vec<f64>,
+}
+
+impl<R: Rng> Zipfian<R> {
+    /// Create a new Zipfian generator.
+    ///
+    /// # Arguments
+    ///
+    /// * `rng` - Random source.
+    /// * `start` - Most frequent key.
+    /// * `keys` - Number of distinct keys, at least 1.
+    /// * `exponent` - Skew of the distribution, 0 for uniform keys.
+    pub fn new(rng: R, start: i32, keys: usize, exponent: f64) -> Self {
+        let mut cdf: Vec<f64> = Vec::with_capacity(keys);
+        let mut total = 0.0;
+        for i in 0..keys.max(1) {
+            total += 1.0 / ((i + 1) as f64).powf(exponent);
+            cdf.push(total);
+        }
+        for c in cdf.iter_mut() {
+            *c /= total;
+        }
+        Self { rng, start, cdf }
+    }
+}
+
+impl<R: Rng> TupleGenerator for Zipfian<R> {
+    fn value(&mut self, _row: usize, _field: usize) -> i32 {
+        let u: f64 = self.rng.gen();
+        let i = self.cdf.partition_point(|c| *c <= u).min(self.cdf.len() - 1);
+        self.start + i as i32
+    }
+}
+
+/// Repeats the tuples of another generator, so every tuple has duplicates.
+///
+/// Consecutive groups of factor tuples get the values of one tuple of the inner
+/// generator.
+pub struct Duplicates<G: TupleGenerator> {
+    /// Generator of the distinct tuples.
+    inner: G,
+    /// Number of copies of each distinct tuple.
+    factor: usize,
+    /// Group and values of the fields of the current distinct tuple.
+    current: Option<(usize, Vec<i32>)>,
+}
+
+impl<G: TupleGenerator> Duplicates<G> {
+    /// Create a new duplicating generator.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - Generator of the distinct tuples.
+    /// * `factor` - Number of copies of each distinct tuple, 1 for no duplicates.
+    pub fn new(inner: G, factor: usize) -> Self {
+        Self {
+            inner,
+            factor: factor.max(1),
+            current: None,
+        }
+    }
+}
+
+impl<G: TupleGenerator> TupleGenerator for Duplicates<G> {
+    fn value(&mut self, row: usize, field: usize) -> i32 {
+        let group = row / self.factor;
+        let values = match &mut self.current {
+            Some((g, values)) if *g == group => values,
+            current => &mut current.insert((group, Vec::new())).1,
+        };
+        // fields are asked for in order, so each value is drawn once per group
+        while values.len() <= field {
+            let value = self.inner.value(group, values.len());
+            values.push(value);
+        }
+        values[field]
+    }
+}
+
+/// Values computed by a closure of the row and field.
+pub struct FromFn<F: FnMut(usize, usize) -> i32>(pub F);
+
+impl<F: FnMut(usize, usize) -> i32> TupleGenerator for FromFn<F> {
+    fn value(&mut self, row: usize, field: usize) -> i32 {
+        (self.0)(row, field)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::OpIterator;
+    use rand::rngs::mock::StepRng;
+
+    #[test]
+    fn sequential_and_closure() -> Result<(), CrustyError> {
+        let tuples = Sequential::new(10, 5).generate(3, 2);
+        assert_eq!(tuples[2], Tuple::new(vec![Field::IntField(20), Field::IntField(20)]));
+
+        let mut scan = FromFn(|row, field| (row * 10 + field) as i32).generate_scan(2, 3);
+        scan.open()?;
+        assert_eq!(scan.next()?, Some(Tuple::new(vec![Field::IntField(0), Field::IntField(1), Field::IntField(2)])));
+        assert_eq!(scan.get_schema(), &get_int_table_schema(3));
+
+        let mut csv = Vec::new();
+        FromFn(|row, field| (row + field) as i32).generate_csv(2, 2, &mut csv)?;
+        assert_eq!(String::from_utf8(csv).unwrap(), "0,1\n1,2\n");
+        Ok(())
+    }
+
+    #[test]
+    fn random_in_range() {
+        let mut uniform = Uniform::new(StepRng::new(0, 1 << 60), 5..10);
+        assert!(uniform.generate(100, 2).iter().flat_map(|t| t.field_vals()).all(|f| (5..10).contains(&f.unwrap_int_field())));
+
+        let tuples = Gaussian::new(rand::thread_rng(), 100.0, 0.0).generate(10, 1);
+        assert!(tuples.iter().all(|t| t.get_field(0) == Some(&Field::IntField(100))));
+    }
+
+    #[test]
+    fn zipfian_and_duplicates() {
+        let tuples = Zipfian::new(seeded_rng(7), 10, 100, 1.5).generate(1000, 1);
+        let count = |key: i32| tuples.iter().filter(|t| t.get_field(0) == Some(&Field::IntField(key))).count();
+        assert!(tuples.iter().all(|t| (10..110).contains(&t.get_field(0).unwrap().unwrap_int_field())));
+        assert!(count(10) > count(11) && count(11) > count(20));
+
+        let tuples = Duplicates::new(Sequential::new(0, 1), 3).generate(7, 2);
+        let keys: Vec<i32> = tuples.iter().map(|t| t.get_field(1).unwrap().unwrap_int_field()).collect();
+        assert_eq!(keys, vec![0, 0, 0, 1, 1, 1, 2]);
+    }
+
+    #[test]
+    fn tpch_tables() -> Result<(), CrustyError> {
+        use crate::join::HashEqJoin;
+        use crate::common::{OpIterator, SimplePredicateOp};
+
+        // helper method to count the lineitems of each order key
+        fn lines_per_order(lineitem: &[Tuple]) -> HashMap<i32, usize> {
+            let mut counts = HashMap::new();
+            for t in lineitem {
+                *counts.entry(t.get_field(0).unwrap().unwrap_int_field()).or_insert(0) += 1;
+            }
+            counts
+        }
+
+        let generator = TpchGenerator::new(0.001, 3);
+        let (orders, lineitem) = (generator.orders(), generator.lineitem());
+        assert_eq!(orders.len(), 1500);
+        let keys: Vec<i32> = orders.iter().take(10).map(|t| t.get_field(0).unwrap().unwrap_int_field()).collect();
+        assert_eq!(keys, vec![1, 2, 3, 4, 5, 6, 7, 8, 33, 34]);
+        let counts = lines_per_order(&lineitem);
+        assert_eq!(counts.len(), 1500);
+        assert!(counts.values().all(|c| (1..=TPCH_MAX_LINES).contains(c)));
+
+        // each lineitem joins with its order only
+        let scan = |tuples: &[Tuple], schema| Box::new(TupleIterator::new(tuples.to_vec(), schema));
+        let mut op = HashEqJoin::new(
+            SimplePredicateOp::Equals,
+            0,
+            0,
+            scan(&lineitem, TpchGenerator::lineitem_schema()),
+            scan(&orders, TpchGenerator::orders_schema()),
+        )?;
+        op.open()?;
+        let mut joined = 0;
+        while op.next()?.is_some() {
+            joined += 1;
+        }
+        assert_eq!(joined, lineitem.len());
+
+        let mut generator = TpchGenerator::new(0.001, 3);
+        generator.set_skew(1.0);
+        let skewed = lines_per_order(&generator.lineitem());
+        assert!(skewed[&1] > 10 * TPCH_MAX_LINES);
+        assert!(skewed.keys().all(|k| counts.contains_key(k)));
+        assert_eq!(generator.lineitem(), generator.lineitem());
+        Ok(())
+    }
+
+    #[test]
+    fn selective_tables() -> Result<(), CrustyError> {
+        use crate::common::SimplePredicateOp;
+        use crate::join::HashEqJoin;
+        use std::collections::HashSet;
+
+        let keys = |tuples: &[Tuple]| -> HashSet<i32> { tuples.iter().map(|t| t.get_field(1).unwrap().unwrap_int_field()).collect() };
+        for (tuple_number, distinct_keys, selectivity) in [(1000, 100, 0.3), (1001, 1000, 0.5), (500, 7, 0.0), (64, 64, 1.0)] {
+            let tables = create_selective_tuples(tuple_number, 3, distinct_keys, selectivity, 5);
+            let (left, right) = (keys(&tables.left), keys(&tables.right));
+            assert_eq!((left.len(), right.len()), (distinct_keys, distinct_keys));
+            assert_eq!(left.intersection(&right).count(), tables.shared_keys);
+            assert_eq!(tables.shared_keys, (distinct_keys as f64 * selectivity).round() as usize);
+
+            let scan = |tuples: &[Tuple]| Box::new(TupleIterator::new(tuples.to_vec(), get_int_table_schema(3)));
+            let mut op = HashEqJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&tables.left), scan(&tables.right))?;
+            op.open()?;
+            let mut joined = 0;
+            while op.next()?.is_some() {
+                joined += 1;
+            }
+            assert_eq!(joined, tables.output_rows);
+        }
+        assert_eq!(create_selective_tuples(100, 2, 10, 0.5, 1), create_selective_tuples(100, 2, 10, 0.5, 1));
+
+        let tables = create_selective_tuples(1000, 2, 100, 0.3, 5);
+        assert_eq!(expected_join_rows(&tables.left, 1, &tables.right, 1), tables.output_rows);
+        assert_eq!(expected_join_rows(&tables.left, 1, &[], 1), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn overlapping_tables() {
+        let (left, right) = create_overlapping_tuples(100, 2, 5000, 0.3, 1);
+        assert_eq!((left.len(), right.len()), (100, 100));
+        assert_eq!(left[70..], right[70..]);
+        // the same seed gives the same tables
+        assert_eq!(create_overlapping_tuples(100, 2, 5000, 0.3, 1), (left, right));
+        assert_ne!(create_vec_tuple(10, 2, 5000, 1), create_vec_tuple(10, 2, 5000, 2));
+    }
+}
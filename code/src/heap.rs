@@ -0,0 +1,655 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use crate::buffer::{BufferPool, PoolStats};
+use crate::common::{not_open, CrustyError, Field, OpIterator, TableSchema, Tuple};
+use crate::metrics::OpMetrics;
+use crate::operators::in_key_range;
+
+/// Size of a page in bytes.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Size of the page header: number of slots, then start of the records, both u16.
+const HEADER_SIZE: usize = 4;
+
+/// Size of a slot of the directory: offset, then length of the record, both u16.
+const SLOT_SIZE: usize = 4;
+
+/// Size in bytes of the largest record a page can hold.
+pub const MAX_RECORD_SIZE: usize = PAGE_SIZE - HEADER_SIZE - SLOT_SIZE;
+
+/// Counter making the temporary file names of a process unique.
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Counter making the ids of the heap files of a process unique.
+static FILE_IDS: AtomicUsize = AtomicUsize::new(0);
+
+/// Location of a record in a heap file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RecordId {
+    /// Index of the page.
+    pub page: usize,
+    /// Index of the slot in the page.
+    pub slot: usize,
+}
+
+/// Slotted page of PAGE_SIZE bytes.
+///
+/// The header and the slot directory grow from the start of the page and the records
+/// from its end, so the free space is the gap between the two.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    /// Bytes of the page.
+    data: Vec<u8>,
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Page {
+    /// Create an empty page.
+    pub fn new() -> Self {
+        let mut page = Self { data: vec![0; PAGE_SIZE] };
+        page.set_u16(2, PAGE_SIZE);
+        page
+    }
+
+    /// Create a page from its bytes, failing with a ValidationError if they are not a page.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - Bytes of the page.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, CrustyError> {
+        let page = Self { data };
+        if page.data.len() != PAGE_SIZE
+            || page.records_start() > PAGE_SIZE
+            || HEADER_SIZE + page.slots() * SLOT_SIZE > page.records_start()
+        {
+            return Err(CrustyError::ValidationError(String::from("invalid page")));
+        }
+        Ok(page)
+    }
+
+    /// Returns the bytes of the page.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the number of slots.
+    pub fn slots(&self) -> usize {
+        self.get_u16(0)
+    }
+
+    /// Returns the number of free bytes.
+    pub fn free_space(&self) -> usize {
+        self.records_start() - HEADER_SIZE - self.slots() * SLOT_SIZE
+    }
+
+    /// Add a record, returning its slot, or None if the page has no room for it.
+    ///
+    /// # Arguments
+    ///
+    /// * `record` - Bytes of the record.
+    pub fn insert(&mut self, record: &[u8]) -> Option<usize> {
+        if record.len() + SLOT_SIZE > self.free_space() {
+            return None;
+        }
+        let slot = self.slots();
+        let start = self.records_start() - record.len();
+        self.data[start..start + record.len()].copy_from_slice(record);
+        self.set_u16(HEADER_SIZE + slot * SLOT_SIZE, start);
+        self.set_u16(HEADER_SIZE + slot * SLOT_SIZE + 2, record.len());
+        self.set_u16(0, slot + 1);
+        self.set_u16(2, start);
+        Some(slot)
+    }
+
+    /// Returns the bytes of a record, None if there is no such slot.
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - Slot of the record.
+    pub fn get(&self, slot: usize) -> Option<&[u8]> {
+        if slot >= self.slots() {
+            return None;
+        }
+        let start = self.get_u16(HEADER_SIZE + slot * SLOT_SIZE);
+        let len = self.get_u16(HEADER_SIZE + slot * SLOT_SIZE + 2);
+        self.data.get(start..start + len)
+    }
+
+    // helper method to get the start of the records
+    fn records_start(&self) -> usize {
+        self.get_u16(2)
+    }
+
+    // helper method to read a u16 of the page
+    fn get_u16(&self, at: usize) -> usize {
+        u16::from_le_bytes([self.data[at], self.data[at + 1]]) as usize
+    }
+
+    // helper method to write a u16 of the page
+    fn set_u16(&mut self, at: usize, value: usize) {
+        self.data[at..at + 2].copy_from_slice(&(value as u16).to_le_bytes());
+    }
+}
+
+/// File of slotted pages holding records, appended one after the other.
+///
+/// The last page stays in memory until it is full or the file is flushed.
+pub struct HeapFile {
+    /// Id of the file in the buffer pools.
+    id: usize,
+    /// Path of the file.
+    path: PathBuf,
+    /// Open file.
+    file: File,
+    /// Number of full pages written to the file.
+    pages: usize,
+    /// Last page, not full yet.
+    tail: Page,
+    /// Number of records.
+    len: usize,
+    /// Whether the file is removed when dropped.
+    temporary: bool,
+}
+
+impl HeapFile {
+    /// Create an empty heap file, truncating any file at the path.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, CrustyError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path)?;
+        Ok(Self {
+            id: FILE_IDS.fetch_add(1, Ordering::Relaxed),
+            path,
+            file,
+            pages: 0,
+            tail: Page::new(),
+            len: 0,
+            temporary: false,
+        })
+    }
+
+    /// Open a heap file written before, e.g. one kept for a checkpoint.
+    ///
+    /// Records inserted afterwards start on a new page. Fails with a ValidationError if the
+    /// file is not a whole number of pages.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CrustyError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let bytes = file.metadata()?.len() as usize;
+        if !bytes.is_multiple_of(PAGE_SIZE) {
+            return Err(CrustyError::ValidationError(format!(
+                "{} has {} bytes, not a multiple of pages",
+                path.display(),
+                bytes
+            )));
+        }
+        let mut heap = Self {
+            id: FILE_IDS.fetch_add(1, Ordering::Relaxed),
+            path,
+            file,
+            pages: bytes / PAGE_SIZE,
+            tail: Page::new(),
+            len: 0,
+            temporary: false,
+        };
+        for page in 0..heap.pages {
+            heap.len += heap.read_page(page)?.slots();
+        }
+        Ok(heap)
+    }
+
+    /// Create an empty heap file in the temporary directory, removed when dropped.
+    ///
+    /// Fails with an IOError on wasm32, which has no file system, so operators over their
+    /// memory budget fail to spill there instead of panicking.
+    pub fn temp() -> Result<Self, CrustyError> {
+        if cfg!(target_arch = "wasm32") {
+            return Err(CrustyError::IOError(String::from("no file system to create a temporary file in")));
+        }
+        let id = TEMP_FILES.fetch_add(1, Ordering::Relaxed);
+        let mut file = Self::create(env::temp_dir().join(format!("join-spill-{}-{}", process::id(), id)))?;
+        file.temporary = true;
+        Ok(file)
+    }
+
+    /// Returns the id of the file, unique in the process.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the number of records.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the file has no record.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of pages, counting the last one.
+    pub fn num_pages(&self) -> usize {
+        self.pages + (self.tail.slots() > 0) as usize
+    }
+
+    /// Returns true if the page is full and written, so it no longer changes.
+    ///
+    /// # Arguments
+    ///
+    /// * `page` - Index of the page.
+    pub fn is_page_full(&self, page: usize) -> bool {
+        page < self.pages
+    }
+
+    /// Append a record, failing with a ValidationError if it is over MAX_RECORD_SIZE bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `record` - Bytes of the record.
+    pub fn insert(&mut self, record: &[u8]) -> Result<RecordId, CrustyError> {
+        if record.len() > MAX_RECORD_SIZE {
+            return Err(CrustyError::ValidationError(format!(
+                "record of {} bytes does not fit in a page",
+                record.len()
+            )));
+        }
+        let slot = match self.tail.insert(record) {
+            Some(slot) => slot,
+            None => {
+                write_page(&mut self.file, self.pages, &self.tail)?;
+                self.pages += 1;
+                self.tail = Page::new();
+                self.tail.insert(record).unwrap()
+            }
+        };
+        self.len += 1;
+        Ok(RecordId { page: self.pages, slot })
+    }
+
+    /// Append a tuple, see insert.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple` - Tuple to append.
+    pub fn insert_tuple(&mut self, tuple: &Tuple) -> Result<RecordId, CrustyError> {
+        self.insert(&tuple.get_bytes())
+    }
+
+    /// Write the last page to the file, so the file holds every record.
+    pub fn flush(&mut self) -> Result<(), CrustyError> {
+        if self.tail.slots() > 0 {
+            write_page(&mut self.file, self.pages, &self.tail)?;
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// Read a page, failing with a ValidationError if there is no such page.
+    ///
+    /// # Arguments
+    ///
+    /// * `page` - Index of the page.
+    pub fn read_page(&mut self, page: usize) -> Result<Page, CrustyError> {
+        if page == self.pages && self.tail.slots() > 0 {
+            return Ok(self.tail.clone());
+        }
+        if page >= self.pages {
+            return Err(CrustyError::ValidationError(format!("no page {}", page)));
+        }
+        let mut data = vec![0; PAGE_SIZE];
+        self.file.seek(SeekFrom::Start((page * PAGE_SIZE) as u64))?;
+        self.file.read_exact(&mut data)?;
+        Page::from_bytes(data)
+    }
+
+    /// Returns the bytes of a record, failing with a ValidationError if there is none.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the record.
+    pub fn get(&mut self, id: RecordId) -> Result<Vec<u8>, CrustyError> {
+        self.read_page(id.page)?
+            .get(id.slot)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| CrustyError::ValidationError(format!("no record {:?}", id)))
+    }
+}
+
+// helper method to write a page at its offset
+fn write_page(file: &mut File, page: usize, data: &Page) -> Result<(), CrustyError> {
+    file.seek(SeekFrom::Start((page * PAGE_SIZE) as u64))?;
+    file.write_all(data.as_bytes())?;
+    Ok(())
+}
+
+impl Drop for HeapFile {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Position of a sequential read over the records of a heap file.
+///
+/// With a buffer pool the current page stays pinned until the cursor moves past it.
+#[derive(Default)]
+pub struct HeapCursor {
+    /// Index of the current page.
+    page: usize,
+    /// Next slot of the current page.
+    slot: usize,
+    /// Current page, None before the first read.
+    current: Option<Arc<Page>>,
+    /// Pool the pages are fetched through, if any.
+    pool: Option<Arc<BufferPool>>,
+    /// File id and index of the page pinned in the pool, if any.
+    pinned: Option<(usize, usize)>,
+    /// Page accesses through the pool.
+    stats: PoolStats,
+}
+
+impl HeapCursor {
+    /// Create a cursor fetching the pages through a buffer pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `pool` - Pool shared with the other readers.
+    pub fn with_pool(pool: Arc<BufferPool>) -> Self {
+        let mut cursor = Self::default();
+        cursor.pool = Some(pool);
+        cursor
+    }
+
+    /// Returns the page accesses of the cursor through its pool.
+    pub fn stats(&self) -> PoolStats {
+        self.stats
+    }
+
+    /// Move back to the first record, keeping the pool and the counters.
+    pub fn reset(&mut self) {
+        self.release();
+        self.page = 0;
+        self.slot = 0;
+    }
+
+    /// Returns the next record of the file in insertion order, None once all were read.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - File being read.
+    pub fn next_record(&mut self, file: &mut HeapFile) -> Result<Option<Vec<u8>>, CrustyError> {
+        loop {
+            if self.current.is_none() {
+                if self.page >= file.num_pages() {
+                    return Ok(None);
+                }
+                self.current = Some(match &self.pool {
+                    None => Arc::new(file.read_page(self.page)?),
+                    Some(pool) => {
+                        let full = file.is_page_full(self.page);
+                        let (page, hit) = pool.fetch_page(file, self.page)?;
+                        if full {
+                            self.pinned = Some((file.id(), self.page));
+                            self.stats.hits += hit as usize;
+                            self.stats.misses += !hit as usize;
+                        }
+                        page
+                    }
+                });
+            }
+            if let Some(record) = self.current.as_ref().and_then(|page| page.get(self.slot)) {
+                self.slot += 1;
+                return Ok(Some(record.to_vec()));
+            }
+            self.release();
+            self.page += 1;
+            self.slot = 0;
+        }
+    }
+
+    // helper method to drop the current page, unpinning it
+    fn release(&mut self) {
+        self.current = None;
+        if let (Some(pool), Some((file, page))) = (&self.pool, self.pinned.take()) {
+            pool.unpin(file, page);
+        }
+    }
+}
+
+impl Drop for HeapCursor {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// Scan of the tuples of a heap file, in insertion order.
+pub struct HeapFileScan {
+    /// File being scanned.
+    file: HeapFile,
+    /// Schema of the tuples.
+    schema: TableSchema,
+    /// Read position, None if not open.
+    cursor: Option<HeapCursor>,
+    /// Pool the pages are fetched through, if any.
+    pool: Option<Arc<BufferPool>>,
+    /// Rows returned and page accesses since the last open.
+    metrics: OpMetrics,
+    /// Field and values of the tuples returned, all if None.
+    key_range: Option<(usize, RangeInclusive<Field>)>,
+}
+
+impl HeapFileScan {
+    /// Create a scan of a file of tuples written by HeapFile::insert_tuple.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - File to scan.
+    /// * `schema` - Schema of the tuples.
+    pub fn new(file: HeapFile, schema: TableSchema) -> Self {
+        Self {
+            file,
+            schema,
+            cursor: None,
+            pool: None,
+            metrics: OpMetrics::default(),
+            key_range: None,
+        }
+    }
+
+    /// Fetch the pages through a buffer pool, e.g. one shared with other scans.
+    ///
+    /// # Arguments
+    ///
+    /// * `pool` - Pool to use on the next open.
+    pub fn set_buffer_pool(&mut self, pool: Arc<BufferPool>) {
+        self.pool = Some(pool);
+    }
+
+    /// Returns the scanned file, e.g. to append more tuples.
+    pub fn into_file(self) -> HeapFile {
+        self.file
+    }
+}
+
+impl OpIterator for HeapFileScan {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.cursor = Some(match &self.pool {
+            None => HeapCursor::default(),
+            Some(pool) => HeapCursor::with_pool(pool.clone()),
+        });
+        self.metrics = OpMetrics::default();
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        let cursor = match &mut self.cursor {
+            None => return not_open(),
+            Some(cursor) => cursor,
+        };
+        let mut tuple = cursor.next_record(&mut self.file)?.map(|bytes| Tuple::from_bytes(&bytes));
+        while tuple.as_ref().is_some_and(|t| !in_key_range(t, self.key_range.as_ref())) {
+            tuple = cursor.next_record(&mut self.file)?.map(|bytes| Tuple::from_bytes(&bytes));
+        }
+        self.metrics.rows_out += tuple.is_some() as usize;
+        self.metrics.page_hits = cursor.stats().hits;
+        self.metrics.page_misses = cursor.stats().misses;
+        Ok(tuple)
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.cursor = None;
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        match &mut self.cursor {
+            None => not_open(),
+            Some(cursor) => {
+                cursor.reset();
+                Ok(())
+            }
+        }
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+
+    /// Records outside the range are skipped once decoded.
+    fn request_key_range(&mut self, index: usize, range: Option<RangeInclusive<Field>>) -> bool {
+        if index >= self.schema.size() {
+            return false;
+        }
+        self.key_range = range.map(|range| (index, range));
+        true
+    }
+
+    fn name(&self) -> String {
+        String::from("HeapFileScan")
+    }
+
+    /// Counts the page hits and misses in the buffer pool since the last open.
+    fn metrics(&self) -> OpMetrics {
+        self.metrics
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::generator::{create_vec_tuple, get_int_table_schema};
+
+    #[test]
+    fn slotted_page() -> Result<(), CrustyError> {
+        let mut page = Page::new();
+        assert_eq!(page.free_space(), PAGE_SIZE - HEADER_SIZE);
+        assert_eq!(page.insert(b"abc"), Some(0));
+        assert_eq!(page.insert(b""), Some(1));
+        assert_eq!(page.insert(&[7; 100]), Some(2));
+        assert_eq!(page.get(0), Some(&b"abc"[..]));
+        assert_eq!(page.get(1), Some(&b""[..]));
+        assert_eq!(page.get(3), None);
+        let copy = Page::from_bytes(page.as_bytes().to_vec())?;
+        assert_eq!(copy.get(2), Some(&[7; 100][..]));
+        assert!(page.insert(&[0; PAGE_SIZE]).is_none());
+        assert!(Page::from_bytes(vec![0; 10]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn scan_pages() -> Result<(), CrustyError> {
+        let tuples = create_vec_tuple(1000, 3, 100, 4);
+        let mut file = HeapFile::temp()?;
+        let mut ids = Vec::new();
+        for t in &tuples {
+            ids.push(file.insert_tuple(t)?);
+        }
+        assert_eq!(file.len(), 1000);
+        assert!(file.num_pages() > 1);
+        assert_eq!(Tuple::from_bytes(&file.get(ids[500])?), tuples[500]);
+        assert!(file.insert(&[0; MAX_RECORD_SIZE + 1]).is_err());
+        file.flush()?;
+        let path = file.path().to_path_buf();
+        assert_eq!(fs::metadata(&path)?.len() as usize, file.num_pages() * PAGE_SIZE);
+        // a copy opened again holds the same records
+        let copy = path.with_extension("copy");
+        fs::copy(&path, &copy)?;
+        let mut opened = HeapFile::open(&copy)?;
+        assert_eq!((opened.len(), opened.num_pages()), (1000, file.num_pages()));
+        assert_eq!(opened.get(ids[500])?, file.get(ids[500])?);
+        drop(opened);
+        assert!(copy.exists());
+        fs::remove_file(copy)?;
+
+        let mut scan = HeapFileScan::new(file, get_int_table_schema(3));
+        if !cfg!(feature = "panic-not-open") {
+            assert!(scan.next().is_err());
+        }
+        scan.open()?;
+        for _ in 0..2 {
+            let mut res = Vec::new();
+            while let Some(t) = scan.next()? {
+                res.push(t);
+            }
+            assert_eq!(res, tuples);
+            scan.rewind()?;
+        }
+        scan.close()?;
+        drop(scan);
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn pooled_scan() -> Result<(), CrustyError> {
+        let tuples = create_vec_tuple(2000, 2, 100, 5);
+        let mut file = HeapFile::temp()?;
+        for t in &tuples {
+            file.insert_tuple(t)?;
+        }
+        let full = file.num_pages() - 1;
+        let pool = Arc::new(BufferPool::new(full));
+        let mut scan = HeapFileScan::new(file, get_int_table_schema(2));
+        scan.set_buffer_pool(pool.clone());
+        scan.open()?;
+        // the pool holds every full page, so the second pass only hits
+        for pass in 1..=2 {
+            let mut count = 0;
+            while scan.next()?.is_some() {
+                count += 1;
+            }
+            assert_eq!(count, tuples.len());
+            assert_eq!(scan.metrics().page_misses, full);
+            assert_eq!(scan.metrics().page_hits, full * (pass - 1));
+            scan.rewind()?;
+        }
+        scan.close()?;
+        assert_eq!(pool.pinned(), 0);
+        assert_eq!(pool.stats().evictions, 0);
+        Ok(())
+    }
+}
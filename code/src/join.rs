@@ -1,10 +1,37 @@
-use std::cmp::{max, min, min_by_key};
-use std::collections::HashMap;
-use std::{thread, vec};
-use serde_cbor::Value::Null;
-use crate::common::{CrustyError, Field, SimplePredicateOp, TableSchema, Tuple, OpIterator,
-                    TupleIterator};
-use crate::common::Constraint::NotNull;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::Arc;
+use std::{fmt, fs, vec};
+use crate::common::{not_open, AggOp, Attribute, CrustyError, DataType, Field, SimplePredicateOp, TableSchema, Tuple, TupleRef, OpIterator};
+use crate::buffer::BufferPool;
+use crate::checkpoint::{SideState, SortState};
+use crate::collation::Collation;
+use crate::columnar::{sort_row_ids, ColumnarBatch};
+use crate::config::{CoreAffinity, MemoryTracker, ProgressMonitor, SortConfig};
+use crate::dictionary::{decode_tuples, encode_join_partitions, Dictionary};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::common::TupleIterator;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::exchange::{Partitioning, RepartitionExchange, EXCHANGE_CAPACITY};
+use crate::expr::Expr;
+use crate::operators::{agg_attribute, Compute};
+use crate::keys::{cmp_normalized, is_exact, normalize_key};
+use crate::morsel::{default_workers, parallel_map_on, MORSEL_SIZE};
+use crate::network::sort_keys;
+use crate::radix::radix_sort_row_ids;
+use crate::metrics::{OpMetrics, PhaseStats};
+use crate::generator::seeded_rng;
+use crate::spill::{SpillCompression, SpillFile, SpillReader};
+use crate::trace::{close_event, phase_span, spill_event};
+#[cfg(feature = "visualize")]
+use crate::visualize::{EventSink, JoinEvent, JoinSide};
+use rand::Rng;
 
 /// Compares the fields of two tuples using a predicate. (You can add any other fields that you think are neccessary)
 #[derive(Clone, Copy)]
@@ -15,6 +42,12 @@ pub struct JoinPredicate {
     left_index: usize,
     /// Index of the field of the right table (tuple).
     right_index: usize,
+    /// How string fields are compared.
+    collation: Collation,
+    /// Whether at most one left tuple has each key.
+    unique_left: bool,
+    /// Whether at most one right tuple has each key.
+    unique_right: bool,
 }
 
 impl JoinPredicate {
@@ -25,46 +58,298 @@ impl JoinPredicate {
     /// * `op` - Operation to compare the two fields with.
     /// * `left_index` - Index of the field to compare in the left tuple.
     /// * `right_index` - Index of the field to compare in the right tuple.
-    fn new(op: SimplePredicateOp, left_index: usize, right_index: usize) -> Self {
+    pub fn new(op: SimplePredicateOp, left_index: usize, right_index: usize) -> Self {
         Self {
             op,
             left_index,
             right_index,
+            collation: Collation::Binary,
+            unique_left: false,
+            unique_right: false,
         }
     }
 
-    // Compare fields of two tuples on some predicate and return result
-    fn cmp(&self, left_tuple: &Tuple, right_tuple: &Tuple) -> bool {
+    /// Set how string fields are compared, binary by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `collation` - Collation of the compared fields.
+    pub fn set_collation(&mut self, collation: Collation) {
+        self.collation = collation;
+    }
+
+    /// Returns how string fields are compared.
+    pub fn collation(&self) -> Collation {
+        self.collation
+    }
+
+    /// Assume at most one left tuple has each key, e.g. when the left field is a primary key.
+    ///
+    /// # Arguments
+    ///
+    /// * `unique_left` - Whether the left keys are unique.
+    pub fn set_unique_left(&mut self, unique_left: bool) {
+        self.unique_left = unique_left;
+    }
+
+    /// Returns whether at most one left tuple has each key.
+    pub fn unique_left(&self) -> bool {
+        self.unique_left
+    }
+
+    /// Assume at most one right tuple has each key, e.g. when the right field is the primary
+    /// key a left foreign key references.
+    ///
+    /// # Arguments
+    ///
+    /// * `unique_right` - Whether the right keys are unique.
+    pub fn set_unique_right(&mut self, unique_right: bool) {
+        self.unique_right = unique_right;
+    }
+
+    /// Returns whether at most one right tuple has each key.
+    pub fn unique_right(&self) -> bool {
+        self.unique_right
+    }
+
+    /// Returns the sort key of the compared field of a left tuple under the collation.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple` - Left tuple.
+    pub fn left_key<'a>(&self, tuple: &'a Tuple) -> Cow<'a, Field> {
+        self.collation.sort_key(tuple.get_field(self.left_index).unwrap())
+    }
+
+    /// Returns the sort key of the compared field of a right tuple under the collation.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple` - Right tuple.
+    pub fn right_key<'a>(&self, tuple: &'a Tuple) -> Cow<'a, Field> {
+        self.collation.sort_key(tuple.get_field(self.right_index).unwrap())
+    }
+
+    /// Constructor resolving the compared fields by their attribute names.
+    ///
+    /// A name is either an attribute name or table.attribute, the table being one of the
+    /// two table names given with the schemas. An unqualified name found in both tables is
+    /// ambiguous. Names given right table first are swapped and the operation flipped, so
+    /// "customers.id" < "orders.customer_id" is "orders.customer_id" > "customers.id" when
+    /// orders is the left table. Fails with a ValidationError if a name cannot be resolved,
+    /// both names are of the same table or the fields have different types.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Operation to compare the two fields with.
+    /// * `left_name` - Name of the field on the left of the operation.
+    /// * `right_name` - Name of the field on the right of the operation.
+    /// * `left` - Name and schema of the left table.
+    /// * `right` - Name and schema of the right table.
+    pub fn from_names(
+        op: SimplePredicateOp,
+        left_name: &str,
+        right_name: &str,
+        left: (&str, &TableSchema),
+        right: (&str, &TableSchema),
+    ) -> Result<Self, CrustyError> {
+        let (first_left, first) = resolve_name(left_name, left, right)?;
+        let (second_left, second) = resolve_name(right_name, left, right)?;
+        let predicate = match (first_left, second_left) {
+            (true, false) => JoinPredicate::new(op, first, second),
+            (false, true) => JoinPredicate::new(op.flip(), second, first),
+            _ => {
+                return Err(CrustyError::ValidationError(format!(
+                    "{} and {} are fields of the same table",
+                    left_name, right_name
+                )))
+            }
+        };
+        validate_join_fields(left.1, predicate.left_index, right.1, predicate.right_index)?;
+        Ok(predicate)
+    }
+
+    /// Constructor finding the left field in the left schema and the right field in the
+    /// right schema by attribute name, see TableSchema::index_of.
+    ///
+    /// Fails with a ValidationError if a name is not an attribute of its schema or the
+    /// fields have different types.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Operation to compare the two fields with.
+    /// * `left_name` - Name of the field of the left tuple.
+    /// * `right_name` - Name of the field of the right tuple.
+    /// * `left` - Schema of the left tuples.
+    /// * `right` - Schema of the right tuples.
+    pub fn from_schemas(
+        op: SimplePredicateOp,
+        left_name: &str,
+        right_name: &str,
+        left: &TableSchema,
+        right: &TableSchema,
+    ) -> Result<Self, CrustyError> {
+        let (left_index, right_index) = (left.index_of(left_name)?, right.index_of(right_name)?);
+        validate_join_fields(left, left_index, right, right_index)?;
+        Ok(JoinPredicate::new(op, left_index, right_index))
+    }
+
+    /// Returns the operation the fields are compared with.
+    pub fn op(&self) -> SimplePredicateOp {
+        self.op
+    }
+
+    /// Returns the index of the field of the left tuple.
+    pub fn left_index(&self) -> usize {
+        self.left_index
+    }
+
+    /// Returns the index of the field of the right tuple.
+    pub fn right_index(&self) -> usize {
+        self.right_index
+    }
+
+    /// Compare fields of two tuples on some predicate and return result
+    pub fn cmp(&self, left_tuple: &Tuple, right_tuple: &Tuple) -> bool {
         let left_field = left_tuple.get_field(self.left_index).unwrap();
         let right_field = right_tuple.get_field(self.right_index).unwrap();
-        self.op.compare(left_field, right_field)
+        self.op.compare_collated(left_field, right_field, self.collation)
     }
+}
 
-    fn clone(&self) -> Self {
-        Self{
-            op: self.op,
-            left_index: self.left_index,
-            right_index: self.right_index
+// helper method to keep the projected attributes of a join schema
+fn project_schema(schema: &TableSchema, columns: &[usize]) -> Result<TableSchema, CrustyError> {
+    let attrs = columns
+        .iter()
+        .map(|i| {
+            schema.get_attribute(*i).cloned().ok_or_else(|| {
+                CrustyError::ValidationError(format!("join output has no field {}", i))
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(TableSchema::new(attrs))
+}
+
+// helper method to find a named field in one of the join tables, returns whether it is in
+// the left table and its index there
+fn resolve_name(name: &str, left: (&str, &TableSchema), right: (&str, &TableSchema)) -> Result<(bool, usize), CrustyError> {
+    let unknown = || CrustyError::ValidationError(format!("no join field is named {}", name));
+    // attribute names may contain dots themselves, so they are tried first
+    match (left.1.get_field_index(name), right.1.get_field_index(name)) {
+        (Some(i), None) => return Ok((true, *i)),
+        (None, Some(i)) => return Ok((false, *i)),
+        (Some(_), Some(_)) => {
+            return Err(CrustyError::ValidationError(format!(
+                "{} is in both tables, qualify it as {}.{} or {}.{}",
+                name, left.0, name, right.0, name
+            )))
         }
+        (None, None) => {}
+    }
+    let (table, attr) = name.split_once('.').ok_or_else(unknown)?;
+    if left.0 == right.0 && table == left.0 {
+        return Err(CrustyError::ValidationError(format!(
+            "both tables are named {}, {} is ambiguous",
+            table, name
+        )));
+    }
+    let (is_left, schema) = if table == left.0 {
+        (true, left.1)
+    } else if table == right.0 {
+        (false, right.1)
+    } else {
+        return Err(unknown());
+    };
+    schema.get_field_index(attr).map(|i| (is_left, *i)).ok_or_else(unknown)
+}
+
+// helper method to get a field of a join condition
+fn join_attribute<'a>(schema: &'a TableSchema, index: usize, side: &str) -> Result<&'a Attribute, CrustyError> {
+    schema.get_attribute(index).ok_or_else(|| {
+        CrustyError::ValidationError(format!(
+            "{} join index {} is out of bounds of {} fields",
+            side,
+            index,
+            schema.size()
+        ))
+    })
+}
+
+// helper method to check that the fields of a join condition exist and have the same type
+fn validate_join_fields(
+    left: &TableSchema,
+    left_index: usize,
+    right: &TableSchema,
+    right_index: usize,
+) -> Result<(), CrustyError> {
+    let left_attr = join_attribute(left, left_index, "left")?;
+    let right_attr = join_attribute(right, right_index, "right")?;
+    if left_attr.dtype() != right_attr.dtype() {
+        return Err(CrustyError::ValidationError(format!(
+            "cannot join left field {} of type {:?} with right field {} of type {:?}",
+            left_index,
+            left_attr.dtype(),
+            right_index,
+            right_attr.dtype()
+        )));
+    }
+    Ok(())
+}
+
+// helper method to check that the merge evaluates the operation of a join condition: it
+// scans each right run up to the left key, so right keys larger than the left key never join
+fn validate_merge_op(op: SimplePredicateOp) -> Result<(), CrustyError> {
+    match op {
+        SimplePredicateOp::Equals | SimplePredicateOp::GreaterThan | SimplePredicateOp::GreaterThanOrEq => Ok(()),
+        _ => Err(CrustyError::ValidationError(format!(
+            "sort-merge join cannot evaluate {:?}, swap the children for the reverse comparison",
+            op
+        ))),
     }
 }
 
+// helper method to build the output schema of a join, qualified by the aliases and
+// projected if given
+fn join_schema(
+    left: &TableSchema,
+    right: &TableSchema,
+    aliases: Option<&(String, String)>,
+    projection: Option<&[usize]>,
+) -> Result<TableSchema, CrustyError> {
+    let merged = match aliases {
+        Some((left_alias, right_alias)) => left.merge_qualified(right, left_alias, right_alias),
+        None => left.merge(right),
+    };
+    match projection {
+        Some(columns) => project_schema(&merged, columns),
+        None => Ok(merged),
+    }
+}
+
+// helper method to join two tuples, copying only the projected fields
+fn merge_projected(left: &Tuple, right: &Tuple, projection: Option<&[usize]>) -> Tuple {
+    TupleRef::new(left, right, projection).to_tuple()
+}
+
 /// Nested loop join implementation. (You can add any other fields that you think are neccessary)
-pub struct Join {
+pub struct Join<L = Box<dyn OpIterator>, R = Box<dyn OpIterator>> {
     /// Join condition.
     predicate: JoinPredicate,
     /// Left child node.
-    left_child: Box<dyn OpIterator>,
+    left_child: L,
     /// Right child node.
-    right_child: Box<dyn OpIterator>,
+    right_child: R,
     /// Schema of the result.
     schema: TableSchema,
 
     open: bool,
-    left_tuple_cur: Tuple, // Current left tuple being used (for outer loop)
+    left_tuple_cur: Option<Tuple>, // Current left tuple being used (for outer loop), None once the left child is exhausted
+    projection: Option<Vec<usize>>, // Output fields of the joined tuples, all if None
+    aliases: Option<(String, String)>, // Table aliases qualifying the output names, if any
+    metrics: OpMetrics,             // Rows and comparisons since the last open
 }
 
-impl Join {
+impl<L: OpIterator, R: OpIterator> Join<L, R> {
     /// Join constructor. Creates a new node for a nested-loop join.
     ///
     /// # Arguments
@@ -74,60 +359,113 @@ impl Join {
     /// * `right_index` - Index of the right field in join condition.
     /// * `left_child` - Left child of join operator.
     /// * `right_child` - Left child of join operator.
+    ///
+    /// Fails with a ValidationError if a join field does not exist or the fields have
+    /// different types.
     pub fn new(
         op: SimplePredicateOp,
         left_index: usize,
         right_index: usize,
-        left_child: Box<dyn OpIterator>,
-        right_child: Box<dyn OpIterator>,
-    ) -> Self {
-        Self {
+        left_child: L,
+        right_child: R,
+    ) -> Result<Self, CrustyError> {
+        validate_join_fields(left_child.get_schema(), left_index, right_child.get_schema(), right_index)?;
+        Ok(Self {
             predicate: JoinPredicate::new(op, left_index, right_index),
             schema: left_child.get_schema().merge(right_child.get_schema()),
             left_child,
             right_child,
             open: false,
-            left_tuple_cur: Tuple::new(Vec::new()),
-        }
+            left_tuple_cur: None,
+            projection: None,
+            aliases: None,
+            metrics: OpMetrics::default(),
+        })
+    }
+
+    /// Set how string join fields are compared, binary by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `collation` - Collation of the join fields.
+    pub fn set_collation(&mut self, collation: Collation) {
+        self.predicate.set_collation(collation);
+    }
+
+    /// Keep only some fields of the joined tuples, the others are never copied.
+    ///
+    /// # Arguments
+    ///
+    /// * `columns` - Indices of the output fields among the left fields followed by the right fields.
+    pub fn set_projection(&mut self, columns: Vec<usize>) -> Result<(), CrustyError> {
+        self.schema = join_schema(self.left_child.get_schema(), self.right_child.get_schema(), self.aliases.as_ref(), Some(&columns))?;
+        self.projection = Some(columns);
+        Ok(())
+    }
+
+    /// Name the output attributes alias.name after the table they come from, so they can
+    /// be found with get_field_index even when both children share names.
+    ///
+    /// # Arguments
+    ///
+    /// * `left_alias` - Alias of the left child.
+    /// * `right_alias` - Alias of the right child.
+    pub fn set_aliases(&mut self, left_alias: &str, right_alias: &str) -> Result<(), CrustyError> {
+        let aliases = (left_alias.to_string(), right_alias.to_string());
+        self.schema = join_schema(
+            self.left_child.get_schema(),
+            self.right_child.get_schema(),
+            Some(&aliases),
+            self.projection.as_deref(),
+        )?;
+        self.aliases = Some(aliases);
+        Ok(())
     }
 }
 
-impl OpIterator for Join {
+impl<L: OpIterator, R: OpIterator> OpIterator for Join<L, R> {
     fn open(&mut self) -> Result<(), CrustyError> {
         self.open = true;
+        self.metrics = OpMetrics::default();
         self.left_child.open()?;
-        self.left_tuple_cur = self.left_child.next()?.unwrap();
+        self.left_tuple_cur = self.left_child.next()?;
+        self.metrics.rows_in += self.left_tuple_cur.is_some() as usize;
         self.right_child.open()
     }
 
     /// Calculates the next tuple for a nested loop join.
     fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
         if !self.open {
-            panic!("Operator has not been opened")
+            return not_open();
         }
 
-        // Find next right child tuple to merge with current left tuple
-        let left_tuple = &self.left_tuple_cur;
-        while let Some(t) = self.right_child.next()? {
-            if self.predicate.cmp(left_tuple, &t) {
-                return Ok(Some(left_tuple.merge(&t)));
+        loop {
+            // Find next right child tuple to merge with current left tuple
+            let left_tuple = match &self.left_tuple_cur {
+                None => return Ok(None),
+                Some(t) => t,
+            };
+            while let Some(t) = self.right_child.next()? {
+                self.metrics.rows_in += 1;
+                self.metrics.comparisons += 1;
+                if self.predicate.cmp(left_tuple, &t) {
+                    self.metrics.rows_out += 1;
+                    return Ok(Some(merge_projected(left_tuple, &t, self.projection.as_deref())));
+                }
             }
-        }
 
-        // If no right tuple match, update left tuple and try from right child's start
-        match self.left_child.next()? {
-            None => Ok(None),
-            Some(t) => {
-                self.left_tuple_cur = t;
+            // If no right tuple match, update left tuple and try from right child's start
+            self.left_tuple_cur = self.left_child.next()?;
+            self.metrics.rows_in += self.left_tuple_cur.is_some() as usize;
+            if self.left_tuple_cur.is_some() {
                 self.right_child.rewind()?;
-                self.next()
             }
         }
     }
 
     fn close(&mut self) -> Result<(), CrustyError> {
         if !self.open {
-            panic!("Operator has not been opened")
+            return not_open();
         }
         self.left_child.close()?;
         self.right_child.close()?;
@@ -137,12 +475,13 @@ impl OpIterator for Join {
 
     fn rewind(&mut self) -> Result<(), CrustyError> {
         if !self.open {
-            panic!("Operator has not been opened")
+            return not_open();
         }
         // Rewind children, get first left (outer loop) tuple to join with
         self.left_child.rewind()?;
         self.right_child.rewind()?;
-        self.left_tuple_cur = self.left_child.next()?.unwrap();
+        self.left_tuple_cur = self.left_child.next()?;
+        self.metrics.rows_in += self.left_tuple_cur.is_some() as usize;
         Ok(())
     }
 
@@ -150,883 +489,6115 @@ impl OpIterator for Join {
     fn get_schema(&self) -> &TableSchema {
         &self.schema
     }
-}
 
-/// Hash equi-join implementation. (You can add any other fields that you think are neccessary)
-pub struct HashEqJoin {
-    predicate: JoinPredicate,
+    fn metrics(&self) -> OpMetrics {
+        self.metrics
+    }
 
-    left_child: Box<dyn OpIterator>,
-    right_child: Box<dyn OpIterator>,
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.left_child, &self.right_child]
+    }
+}
 
+/// Cross join (Cartesian product) implementation.
+///
+/// The left child is buffered in blocks and the right child is scanned once per block.
+pub struct CrossJoin<L = Box<dyn OpIterator>, R = Box<dyn OpIterator>> {
+    /// Left child node.
+    left_child: L,
+    /// Right child node.
+    right_child: R,
+    /// Schema of the result.
     schema: TableSchema,
-
+    /// Join status
     open: bool,
-    // Map attribute values to all tuples containing that value
-    ht: HashMap<Field, Vec<Tuple>>,
-    field_cur: Field,       // Current field being used as ht key
-    index_cur: usize,       // Current index in ht[field_cur]
-    right_tuple_cur: Tuple, // Current tuple from right child being used in joins
+    /// Number of left tuples buffered at a time.
+    block_size: usize,
+    /// Current block of left tuples.
+    block: Vec<Tuple>,
+    /// Index of the next tuple in block to merge with right_tuple_cur.
+    block_cur: usize,
+    /// Current tuple from right child being merged with the block.
+    right_tuple_cur: Option<Tuple>,
+    /// Maximum number of tuples to return, if any.
+    max_output: Option<usize>,
+    /// Number of tuples returned so far.
+    output_count: usize,
+    /// Output fields of the joined tuples, all if None.
+    projection: Option<Vec<usize>>,
+    /// Table aliases qualifying the output names, if any.
+    aliases: Option<(String, String)>,
+    /// Rows since the last open.
+    metrics: OpMetrics,
 }
 
-impl HashEqJoin {
-    /// Constructor for a hash equi-join operator.
+impl<L: OpIterator, R: OpIterator> CrossJoin<L, R> {
+    /// Cross join constructor.
     ///
     /// # Arguments
     ///
-    /// * `op` - Operation in join condition.
-    /// * `left_index` - Index of the left field in join condition.
-    /// * `right_index` - Index of the right field in join condition.
     /// * `left_child` - Left child of join operator.
-    /// * `right_child` - Left child of join operator.
-    #[allow(dead_code)]
+    /// * `right_child` - Right child of join operator.
+    /// * `block_size` - Number of left tuples buffered at a time.
     pub fn new(
-        op: SimplePredicateOp,
-        left_index: usize,
-        right_index: usize,
-        left_child: Box<dyn OpIterator>,
-        right_child: Box<dyn OpIterator>,
+        left_child: L,
+        right_child: R,
+        block_size: usize,
     ) -> Self {
         Self {
-            predicate: JoinPredicate::new(op, left_index, right_index),
             schema: left_child.get_schema().merge(right_child.get_schema()),
             left_child,
             right_child,
             open: false,
-            ht: HashMap::new(),
-            field_cur: Field::IntField(0),
-            index_cur: 0,
-            right_tuple_cur: Tuple::new(Vec::new()),
+            block_size: block_size.max(1),
+            block: Vec::new(),
+            block_cur: 0,
+            right_tuple_cur: None,
+            max_output: None,
+            output_count: 0,
+            projection: None,
+            aliases: None,
+            metrics: OpMetrics::default(),
         }
     }
 
-    // Find first right child tuple that will be used in the join result
-    fn partial_open(&mut self) -> Result<(), CrustyError> {
-        let right_index = self.predicate.right_index;
-        while let Some(t) = self.right_child.next()? {
-            let field = t.get_field(right_index).unwrap();
-            if self.ht.contains_key(field) {
-                self.field_cur = field.clone();
-                self.index_cur = 0;
-                self.right_tuple_cur = t;
-                return Ok(());
+    /// Cap the number of output tuples; next() fails once the product exceeds it.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_output` - Maximum number of tuples to return.
+    pub fn set_max_output(&mut self, max_output: usize) {
+        self.max_output = Some(max_output);
+    }
+
+    /// Keep only some fields of the joined tuples, the others are never copied.
+    ///
+    /// # Arguments
+    ///
+    /// * `columns` - Indices of the output fields among the left fields followed by the right fields.
+    pub fn set_projection(&mut self, columns: Vec<usize>) -> Result<(), CrustyError> {
+        self.schema = join_schema(self.left_child.get_schema(), self.right_child.get_schema(), self.aliases.as_ref(), Some(&columns))?;
+        self.projection = Some(columns);
+        Ok(())
+    }
+
+    /// Name the output attributes alias.name after the table they come from, so they can
+    /// be found with get_field_index even when both children share names.
+    ///
+    /// # Arguments
+    ///
+    /// * `left_alias` - Alias of the left child.
+    /// * `right_alias` - Alias of the right child.
+    pub fn set_aliases(&mut self, left_alias: &str, right_alias: &str) -> Result<(), CrustyError> {
+        let aliases = (left_alias.to_string(), right_alias.to_string());
+        self.schema = join_schema(
+            self.left_child.get_schema(),
+            self.right_child.get_schema(),
+            Some(&aliases),
+            self.projection.as_deref(),
+        )?;
+        self.aliases = Some(aliases);
+        Ok(())
+    }
+
+    // Buffer the next block of left tuples, returns false if the left child is exhausted
+    fn fill_block(&mut self) -> Result<bool, CrustyError> {
+        self.block.clear();
+        while self.block.len() < self.block_size {
+            match self.left_child.next()? {
+                Some(t) => {
+                    self.metrics.rows_in += 1;
+                    self.block.push(t);
+                }
+                None => break,
             }
         }
-        Ok(())
+        self.block_cur = 0;
+        self.right_tuple_cur = None;
+        Ok(!self.block.is_empty())
     }
 }
 
-impl OpIterator for HashEqJoin {
+impl<L: OpIterator, R: OpIterator> OpIterator for CrossJoin<L, R> {
     fn open(&mut self) -> Result<(), CrustyError> {
         self.open = true;
-
-        // Build hash table from left child
+        self.output_count = 0;
+        self.metrics = OpMetrics::default();
         self.left_child.open()?;
-        let left_index = self.predicate.left_index;
-        while let Some(t) = self.left_child.next()? {
-            let field = t.get_field(left_index).unwrap();
-            if let Some(vec) = self.ht.get_mut(field) {
-                vec.push(t);
-            } else {
-                self.ht.insert(field.clone(), vec![t]);
-            }
-        }
-
-        // Get first right child tuple to use in next()
         self.right_child.open()?;
-        self.partial_open()
+        self.fill_block()?;
+        Ok(())
     }
 
     fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
         if !self.open {
-            panic!("Operator has not been opened")
+            return not_open();
         }
-
-        // Try to use current right child tuple again
-        if let Some(t) = self.ht[&self.field_cur].get(self.index_cur) {
-            self.index_cur += 1;
-            return Ok(Some(t.merge(&self.right_tuple_cur)));
-        }
-
-        // If no match, find new right tuple and return first match with it
-        let right_index = self.predicate.right_index;
-        while let Some(t) = self.right_child.next()? {
-            let field = t.get_field(right_index).unwrap();
-            if let Some(vec) = self.ht.get(field) {
-                self.field_cur = field.clone();
-                self.index_cur = 1;
-                self.right_tuple_cur = t;
-                return Ok(Some(vec[0].merge(&self.right_tuple_cur)));
+        while !self.block.is_empty() {
+            // merge the current right tuple with the rest of the block
+            if let Some(right_tuple) = &self.right_tuple_cur {
+                if let Some(left_tuple) = self.block.get(self.block_cur) {
+                    if self.max_output.is_some_and(|max| self.output_count >= max) {
+                        return Err(CrustyError::ExecutionError(format!(
+                            "cross join output exceeds {} tuples",
+                            self.output_count
+                        )));
+                    }
+                    self.block_cur += 1;
+                    self.output_count += 1;
+                    self.metrics.rows_out += 1;
+                    return Ok(Some(merge_projected(left_tuple, right_tuple, self.projection.as_deref())));
+                }
+            }
+            // block done with this right tuple, move to the next one or the next block
+            match self.right_child.next()? {
+                Some(t) => {
+                    self.metrics.rows_in += 1;
+                    self.right_tuple_cur = Some(t);
+                    self.block_cur = 0;
+                }
+                None => {
+                    if !self.fill_block()? {
+                        break;
+                    }
+                    self.right_child.rewind()?;
+                }
             }
         }
-        // Out of right tuples
         Ok(None)
     }
 
     fn close(&mut self) -> Result<(), CrustyError> {
         if !self.open {
-            panic!("Operator has not been opened")
+            return not_open();
         }
-        // Close children, empty hash table
         self.left_child.close()?;
         self.right_child.close()?;
-        self.ht.clear();
+        self.block.clear();
         self.open = false;
         Ok(())
     }
 
     fn rewind(&mut self) -> Result<(), CrustyError> {
         if !self.open {
-            panic!("Operator has not been opened")
+            return not_open();
         }
-        // Keep hash table
-        // Rewind right child and get first tuple to use from it
+        self.left_child.rewind()?;
         self.right_child.rewind()?;
-        self.partial_open()
+        self.output_count = 0;
+        self.fill_block()?;
+        Ok(())
     }
 
     fn get_schema(&self) -> &TableSchema {
         &self.schema
     }
-}
 
+    fn metrics(&self) -> OpMetrics {
+        self.metrics
+    }
 
-/// Sort-merge join implementation
-pub struct SortMergeJoin {
-    /// Join condition.
-    predicate: JoinPredicate,
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.left_child, &self.right_child]
+    }
+}
+
+/// As-of join: each left tuple is joined with the right tuple of the greatest key not
+/// greater than its own, the standard join of time series, e.g. trades with the last quote.
+///
+/// Open sorts both children on their keys, skipping a child that provides the order, and
+/// next walks the two sorted runs once, keeping the right cursor past the keys not greater
+/// than the current left key, so each left tuple finds its match without a search. With
+/// several right tuples of that key, the last of them in the sorted run is the match. Left
+/// tuples without a right key at or before theirs, or farther than the tolerance, are not
+/// returned. The output is sorted on the left key.
+pub struct AsOfJoin<L = Box<dyn OpIterator>, R = Box<dyn OpIterator>> {
     /// Left child node.
-    left_child: Box<dyn OpIterator + Send>,
+    left_child: L,
     /// Right child node.
-    right_child: Box<dyn OpIterator + Send>,
+    right_child: R,
+    /// Index of the key of the left tuples.
+    left_index: usize,
+    /// Index of the key of the right tuples.
+    right_index: usize,
+    /// Largest difference of the left and right keys of a match, any if None.
+    tolerance: Option<i32>,
     /// Schema of the result.
     schema: TableSchema,
     /// Join status
     open: bool,
-    /// level 3 method: 1 for m-way; 2 for m-pass
-    sort_merge_method: isize,
-    /// left level 3 runs
-    pub l3_runs_l: Vec<Vec<Tuple>>,
-    /// right level 3 runs
-    pub l3_runs_r: Vec<Vec<Tuple>>,
-    /// right global minimum
-    min_r: Tuple,
-    /// right global maximum
-    max_r: Tuple,
+    /// Left tuples, sorted on the key.
+    left: Vec<Tuple>,
+    /// Right tuples, sorted on the key.
+    right: Vec<Tuple>,
+    /// Position of the next left tuple.
+    cursor: usize,
+    /// Number of right tuples with a key not greater than the last left key.
+    right_pos: usize,
+    /// Rows and key comparisons since the last open.
+    metrics: OpMetrics,
 }
 
-impl SortMergeJoin {
-    pub fn new(
-        op: SimplePredicateOp,
-        left_index: usize,
-        right_index: usize,
-        left_child: Box<dyn OpIterator + Send>,
-        right_child: Box<dyn OpIterator + Send>,
-        sort_merge_method: isize,
-    ) -> Self {
-        Self {
-            predicate: JoinPredicate::new(op, left_index, right_index),
+impl<L: OpIterator, R: OpIterator> AsOfJoin<L, R> {
+    /// As-of join constructor.
+    ///
+    /// Fails with a ValidationError if a key does not exist or the keys have different
+    /// types.
+    ///
+    /// # Arguments
+    ///
+    /// * `left_index` - Index of the key of the left tuples.
+    /// * `right_index` - Index of the key of the right tuples.
+    /// * `left_child` - Left child of join operator.
+    /// * `right_child` - Right child of join operator.
+    pub fn new(left_index: usize, right_index: usize, left_child: L, right_child: R) -> Result<Self, CrustyError> {
+        validate_join_fields(left_child.get_schema(), left_index, right_child.get_schema(), right_index)?;
+        Ok(Self {
             schema: left_child.get_schema().merge(right_child.get_schema()),
             left_child,
             right_child,
+            left_index,
+            right_index,
+            tolerance: None,
             open: false,
-            sort_merge_method,
-            l3_runs_l: Vec::new(),
-            l3_runs_r: Vec::new(),
-            min_r: Tuple::new(vec![Field::IntField(999999), Field::IntField(999999), Field::IntField(999999), Field::IntField(999999)]),
-            max_r: Tuple::new(vec![]),
+            left: Vec::new(),
+            right: Vec::new(),
+            cursor: 0,
+            right_pos: 0,
+            metrics: OpMetrics::default(),
+        })
+    }
+
+    /// Match only right keys at most tolerance below the left key, e.g. quotes of the last
+    /// second.
+    ///
+    /// Fails with a ValidationError if the keys are not integers or the tolerance is negative.
+    ///
+    /// # Arguments
+    ///
+    /// * `tolerance` - Largest difference of the left and right keys of a match.
+    pub fn set_tolerance(&mut self, tolerance: i32) -> Result<(), CrustyError> {
+        let dtype = self.left_child.get_schema().get_attribute(self.left_index).map(|attr| attr.dtype());
+        if dtype != Some(&DataType::Int) || tolerance < 0 {
+            return Err(CrustyError::ValidationError(format!(
+                "a tolerance of {} needs integer keys and cannot be negative",
+                tolerance
+            )));
         }
+        self.tolerance = Some(tolerance);
+        Ok(())
     }
 }
 
-// helper method to find min/max tuple
-fn compare_min(a: Tuple, b: Tuple, index: usize) -> Tuple {
-    if a.get_field(index) < b.get_field(index) {
-        return a;
-    } else {
-        return b;
+// helper method to read every tuple of an open child, sorted on a field unless it already is
+fn read_sorted(child: &mut impl OpIterator, index: usize, sorted: bool, metrics: &mut OpMetrics) -> Result<Vec<Tuple>, CrustyError> {
+    let mut tuples = Vec::new();
+    while let Some(t) = child.next()? {
+        tuples.push(t);
     }
-}
-fn compare_max(a: Tuple, b: Tuple, index: usize) -> Tuple {
-    if a.get_field(index) > b.get_field(index) {
-        return a;
-    } else {
-        return b;
+    metrics.rows_in += tuples.len();
+    if sorted {
+        return Ok(tuples);
     }
+    let tuple_bytes = child.get_schema().byte_size();
+    Ok(sort_tuples(SortKernel::Network, tuples, index, &SortConfig::default(), tuple_bytes))
 }
 
-// helper method to sort level 1 run
-fn sort_run_l1(mut run: Vec<Tuple>, index: usize) -> Vec<Tuple> {
-    let mut temp = Tuple::new(vec![]);
-    temp = compare_min(run[0].clone(), run[1].clone(), index);
-    run[1] = compare_max(run[0].clone(), run[1].clone(), index);
-    run[0] = temp.clone();
-    temp = compare_min(run[2].clone(), run[3].clone(), index);
-    run[3] = compare_max(run[2].clone(), run[3].clone(), index);
-    run[2] = temp.clone();
-
-    temp = compare_min(run[0].clone(), run[2].clone(), index);
-    run[2] = compare_max(run[0].clone(), run[2].clone(), index);
-    run[0] = temp;
-    temp = compare_min(run[1].clone(), run[3].clone(), index);
-    run[3] = compare_max(run[1].clone(), run[3].clone(), index);
-    run[1] = temp;
-
-    temp = compare_min(run[1].clone(), run[2].clone(), index);
-    run[2] = compare_max(run[1].clone(), run[2].clone(), index);
-    run[1] = temp;
-    return run;
-}
-// helper method to sort level 2 run
-fn sort_run_l2(mut run: Vec<Tuple>, index: usize) -> Vec<Tuple> {
-    // let mut temp = Tuple::new(vec![]);
-    // temp = min_tuple(run[3].clone(), run[7].clone(), index);
-    // run[7] = max_tuple(run[3].clone(), run[7].clone(), index);
-    // run[3] = temp.clone();
-    // temp = min_tuple(run[2].clone(), run[6].clone(), index);
-    // run[6] = max_tuple(run[2].clone(), run[6].clone(), index);
-    // run[2] = temp.clone();
-    // temp = min_tuple(run[1].clone(), run[5].clone(), index);
-    // run[5] = max_tuple(run[1].clone(), run[5].clone(), index);
-    // run[1] = temp;
-    // temp = min_tuple(run[0].clone(), run[4].clone(), index);
-    // run[4] = max_tuple(run[0].clone(), run[4].clone(), index);
-    // run[0] = temp;
-    //
-    // temp = min_tuple(run[0].clone(), run[2].clone(), index);
-    // run[2] = max_tuple(run[0].clone(), run[2].clone(), index);
-    // run[0] = temp.clone();
-    // temp = min_tuple(run[5].clone(), run[7].clone(), index);
-    // run[7] = max_tuple(run[5].clone(), run[7].clone(), index);
-    // run[5] = temp.clone();
-    // temp = min_tuple(run[1].clone(), run[3].clone(), index);
-    // run[3] = max_tuple(run[1].clone(), run[3].clone(), index);
-    // run[1] = temp;
-    // temp = min_tuple(run[4].clone(), run[6].clone(), index);
-    // run[6] = max_tuple(run[4].clone(), run[6].clone(), index);
-    // run[4] = temp;
-    //
-    // temp = min_tuple(run[0].clone(), run[1].clone(), index);
-    // run[1] = max_tuple(run[0].clone(), run[1].clone(), index);
-    // run[0] = temp.clone();
-    // temp = min_tuple(run[2].clone(), run[3].clone(), index);
-    // run[3] = max_tuple(run[2].clone(), run[3].clone(), index);
-    // run[2] = temp.clone();
-    // temp = min_tuple(run[4].clone(), run[5].clone(), index);
-    // run[5] = max_tuple(run[4].clone(), run[5].clone(), index);
-    // run[4] = temp;
-    // temp = min_tuple(run[6].clone(), run[7].clone(), index);
-    // run[7] = max_tuple(run[6].clone(), run[7].clone(), index);
-    // run[6] = temp;
-
-    // second way of doing sorting
-    if compare_max(run[3].clone(), run[7].clone(), index) == run[3].clone() {
-        run.swap(3, 7);
-    }
-    if compare_max(run[2].clone(), run[6].clone(), index) == run[2].clone() {
-        run.swap(2, 6);
-    }
-    if compare_max(run[1].clone(), run[5].clone(), index) == run[1].clone() {
-        run.swap(1, 5);
-    }
-    if compare_max(run[0].clone(), run[4].clone(), index) == run[0].clone() {
-        run.swap(0, 4);
-    }
-
-    if compare_max(run[0].clone(), run[2].clone(), index) == run[0].clone() {
-        run.swap(0, 2);
-    }
-    if compare_max(run[5].clone(), run[7].clone(), index) == run[5].clone() {
-        run.swap(5, 7);
-    }
-    if compare_max(run[1].clone(), run[3].clone(), index) == run[1].clone() {
-        run.swap(1, 3);
-    }
-    if compare_max(run[4].clone(), run[6].clone(), index) == run[4].clone() {
-        run.swap(4, 6);
-    }
-
-    if compare_max(run[0].clone(), run[1].clone(), index) == run[0].clone() {
-        run.swap(0, 1);
-    }
-    if compare_max(run[2].clone(), run[3].clone(), index) == run[2].clone() {
-        run.swap(2, 3);
-    }
-    if compare_max(run[4].clone(), run[5].clone(), index) == run[4].clone() {
-        run.swap(4, 5);
-    }
-    if compare_max(run[6].clone(), run[7].clone(), index) == run[6].clone() {
-        run.swap(6, 7);
-    }
-    return run;
-}
-// helper method to sort each run in runs
-fn sort_runs(mut runs: Vec<Vec<Tuple>>, index: usize, level: usize) -> Vec<Vec<Tuple>> {
-    let mut handles = Vec::new();
-    if level == 1 {
-        for mut run in runs {
-            let handle = thread::spawn(move || {
-                let new_run = sort_run_l1(run.clone(), index.clone());
-                new_run
-            });
-            handles.push(handle);
+impl<L: OpIterator, R: OpIterator> OpIterator for AsOfJoin<L, R> {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.open = true;
+        self.metrics = OpMetrics::default();
+        let left_sorted = self.left_child.request_order(self.left_index);
+        let right_sorted = self.right_child.request_order(self.right_index);
+        self.left_child.open()?;
+        self.right_child.open()?;
+        self.left = read_sorted(&mut self.left_child, self.left_index, left_sorted, &mut self.metrics)?;
+        self.right = read_sorted(&mut self.right_child, self.right_index, right_sorted, &mut self.metrics)?;
+        self.cursor = 0;
+        self.right_pos = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            return not_open();
         }
-    } else {
-        for mut run in runs {
-            let handle = thread::spawn(move || {
-                let new_run = sort_run_l2(run.clone(), index.clone());
-                new_run
-            });
-            handles.push(handle);
+        while let Some(left) = self.left.get(self.cursor) {
+            self.cursor += 1;
+            let key = left.get_field(self.left_index).unwrap();
+            // left keys only grow, so the right cursor never moves back
+            while let Some(right) = self.right.get(self.right_pos) {
+                self.metrics.comparisons += 1;
+                if right.get_field(self.right_index).unwrap() > key {
+                    break;
+                }
+                self.right_pos += 1;
+            }
+            let Some(right) = self.right_pos.checked_sub(1).map(|pos| &self.right[pos]) else {
+                continue;
+            };
+            let within = match (self.tolerance, key, right.get_field(self.right_index).unwrap()) {
+                (Some(tolerance), Field::IntField(l), Field::IntField(r)) => *l as i64 - *r as i64 <= tolerance as i64,
+                _ => true,
+            };
+            if within {
+                self.metrics.rows_out += 1;
+                return Ok(Some(left.merge(right)));
+            }
         }
+        Ok(None)
     }
 
-    let mut res = Vec::new();
-    for handle in handles {
-        res.push(handle.join().unwrap().clone());
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        self.left_child.close()?;
+        self.right_child.close()?;
+        self.left.clear();
+        self.right.clear();
+        self.open = false;
+        Ok(())
     }
 
-    res
-}
-
-// helper method to merge level 1 runs into level 2 runs
-fn merge_1_to_2(mut runs: Vec<Vec<Tuple>>) -> Vec<Vec<Tuple>> {
-    let mut counter = 1;
-    let mut temp = Vec::new();
-    let mut res = Vec::new();
-    for mut run in runs.clone() {
-        if counter % 2 != 0 {
-            temp.append(&mut run);
-            counter += 1;
-        } else {
-            run.reverse();
-            temp.append(&mut run);
-            counter += 1;
-            res.push(temp.clone());
-            temp = Vec::new();
+    /// Returns to the first output tuple without sorting the children again.
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
         }
+        self.cursor = 0;
+        self.right_pos = 0;
+        Ok(())
     }
-    res
-}
 
-// sort-merge runs by multi-way method
-fn sort_m_way_l3(mut runs: Vec<Vec<Tuple>>, min: Tuple, max: Tuple, index: usize) -> Vec<Vec<Tuple>> {
-    // redistribute runs into 3 runs (4 physical thread - 1)
-    let mut res_1 = Vec::new();
-    let mut res_2 = Vec::new();
-    let mut res_3 = Vec::new();
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
 
-    let min_val = min.get_field(index).unwrap().unwrap_int_field();
-    let max_val = max.get_field(index).unwrap().unwrap_int_field();
+    fn metrics(&self) -> OpMetrics {
+        self.metrics
+    }
 
-    let one_third = (min_val + (max_val - min_val) / 3) as isize;
-    let two_third = (min_val + (max_val - min_val) * 2 / 3) as isize;
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.left_child, &self.right_child]
+    }
 
-    // redistribute tuples based on the range partition
-    for run in &runs {
-        for t in run {
-            if *t.get_field(index).unwrap() <= Field::IntField(one_third as i32) {
-                res_1.push(t.clone());
-            } else if *t.get_field(index).unwrap() <= Field::IntField(two_third as i32) {
-                res_2.push(t.clone());
-            } else {
-                res_3.push(t.clone());
-            }
-        }
+    /// Tuples are returned in the order of the left key.
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        Some(vec![self.left_index])
     }
+}
 
-    res_1.sort_by(|a,b| a.get_field(index).unwrap().cmp(b.get_field(index).unwrap()));
-    res_2.sort_by(|a,b| a.get_field(index).unwrap().cmp(b.get_field(index).unwrap()));
-    res_3.sort_by(|a,b| a.get_field(index).unwrap().cmp(b.get_field(index).unwrap()));
-
-    return vec![res_1, res_2, res_3];
+/// Equi-join fused with an aggregate of the matching right tuples, e.g. the number of
+/// orders of each customer and their total.
+///
+/// Each left tuple is returned once, followed by the aggregates of the right tuples with
+/// its key, instead of once per matching right tuple, so a many-to-many join never builds
+/// its cross product. Open sorts the right child on its key, unless it provides the order,
+/// and folds each block of equal keys into its aggregates; next looks the key of each left
+/// tuple up among the blocks. Left tuples without a matching right tuple are not returned,
+/// as the join has no row of them to aggregate. The output follows the order of the left
+/// child.
+pub struct GroupJoin<L = Box<dyn OpIterator>, R = Box<dyn OpIterator>> {
+    /// Left child node.
+    left_child: L,
+    /// Right child node.
+    right_child: R,
+    /// Index of the key of the left tuples.
+    left_index: usize,
+    /// Index of the key of the right tuples.
+    right_index: usize,
+    /// Index of the aggregated right field and operation of each aggregate.
+    aggs: Vec<(usize, AggOp)>,
+    /// Schema of the result.
+    schema: TableSchema,
+    /// Join status
+    open: bool,
+    /// Key of each block of equal right keys with its aggregates, sorted on the key.
+    groups: Vec<(Field, Vec<Field>)>,
+    /// Rows and key comparisons since the last open.
+    metrics: OpMetrics,
 }
 
-// join the left run with right runs for m-way
-fn join_m_way(mut run: Vec<Tuple>, right_run: Vec<Tuple>, pre: JoinPredicate) -> Vec<Tuple> {
-    let mut res = Vec::new();
-    // loop through each tuple in the run
-    for t in &run {
-        // try to match with tuple in each right run
-        for t_r in &right_run {
-            // if right tuple bigger than current tuple then break
-            if *t_r.get_field(pre.right_index).unwrap() > *t.get_field(pre.left_index).unwrap() {
-                break;
-            } else if pre.cmp(t, t_r) {
-                res.push(t.merge(t_r));
+impl<L: OpIterator, R: OpIterator> GroupJoin<L, R> {
+    /// Group join constructor.
+    ///
+    /// The output schema is the left schema followed by one op(name) attribute per
+    /// aggregate, as of Aggregate. Fails with a ValidationError if a key does not exist,
+    /// the keys have different types, an aggregated field does not exist or a sum or an
+    /// average is not of an integer field.
+    ///
+    /// # Arguments
+    ///
+    /// * `left_index` - Index of the key of the left tuples.
+    /// * `right_index` - Index of the key of the right tuples.
+    /// * `aggs` - Index of the aggregated right field and operation of each aggregate.
+    /// * `left_child` - Left child of join operator.
+    /// * `right_child` - Right child of join operator.
+    pub fn new(
+        left_index: usize,
+        right_index: usize,
+        aggs: Vec<(usize, AggOp)>,
+        left_child: L,
+        right_child: R,
+    ) -> Result<Self, CrustyError> {
+        validate_join_fields(left_child.get_schema(), left_index, right_child.get_schema(), right_index)?;
+        let right_schema = right_child.get_schema();
+        let mut attrs = left_child.get_schema().attributes().cloned().collect::<Vec<_>>();
+        for (index, op) in &aggs {
+            let attr = join_attribute(right_schema, *index, "right")?;
+            if matches!(op, AggOp::Sum | AggOp::Avg) && attr.dtype() != &DataType::Int {
+                return Err(CrustyError::ValidationError(format!(
+                    "cannot {} right field {} of type {:?}",
+                    op,
+                    index,
+                    attr.dtype()
+                )));
             }
+            attrs.push(agg_attribute(right_schema, *index, *op));
         }
+        Ok(Self {
+            schema: TableSchema::new(attrs),
+            left_child,
+            right_child,
+            left_index,
+            right_index,
+            aggs,
+            open: false,
+            groups: Vec::new(),
+            metrics: OpMetrics::default(),
+        })
     }
-    res
-}
-// join the left run with right runs for m-pass
-fn join_m_pass(mut run: Vec<Tuple>, right_runs: Vec<Vec<Tuple>>, pre: JoinPredicate) -> Vec<Tuple> {
-    let mut res = Vec::new();
-    // loop through each tuple in the run
-    for t in &run {
-        // try to match with tuple in each right run
-        for right_run in &right_runs {
-            for t_r in right_run {
-                // if right tuple bigger than current tuple then break
-                if *t_r.get_field(pre.right_index).unwrap() > *t.get_field(pre.left_index).unwrap() {
-                    break;
-                } else if pre.cmp(t, t_r) {
-                    res.push(t.merge(t_r));
+
+    // Fold each block of equal keys of the sorted right tuples into its aggregates
+    fn aggregate_blocks(&mut self, right: Vec<Tuple>) {
+        self.groups.clear();
+        let mut count = 0;
+        for t in right {
+            let key = t.get_field(self.right_index).unwrap();
+            match self.groups.last_mut().filter(|(last, _)| last == key) {
+                Some((_, aggs)) => {
+                    for ((index, op), agg) in self.aggs.iter().zip(aggs.iter_mut()) {
+                        op.merge_field(t.get_field(*index).unwrap(), agg);
+                    }
+                    count += 1;
+                }
+                None => {
+                    self.finish_block(count);
+                    let aggs = self.aggs.iter().map(|(index, op)| op.new_field(t.get_field(*index).unwrap())).collect();
+                    self.groups.push((key.clone(), aggs));
+                    count = 1;
+                }
+            }
+        }
+        self.finish_block(count);
+    }
+
+    // helper method to turn the sums of the averages of the last block into averages
+    fn finish_block(&mut self, count: i32) {
+        if let Some((_, aggs)) = self.groups.last_mut() {
+            for ((_, op), agg) in self.aggs.iter().zip(aggs.iter_mut()) {
+                if let AggOp::Avg = op {
+                    *agg = Field::IntField(agg.unwrap_int_field() / count);
                 }
             }
         }
     }
-    res
 }
 
-impl OpIterator for SortMergeJoin {
+impl<L: OpIterator, R: OpIterator> OpIterator for GroupJoin<L, R> {
     fn open(&mut self) -> Result<(), CrustyError> {
         self.open = true;
+        self.metrics = OpMetrics::default();
+        let right_sorted = self.right_child.request_order(self.right_index);
         self.left_child.open()?;
         self.right_child.open()?;
-
-        let left_index = self.predicate.left_index;
-        let right_index = self.predicate.right_index;
-
-        // initialize the runs for level 1 sorting
-        let mut l1_runs_l = Vec::new();
-        let mut l1_runs_r = Vec::new();
-        // split children into level 1 runs
-        let mut l1_temp = Vec::new();
-
-        while let Some(t) = &self.left_child.next()? {
-            // each run contains 4 Tuples in order to fit into the register
-            if l1_temp.len() == 4 {
-                l1_runs_l.push(l1_temp.clone());
-                l1_temp = Vec::new();
-                l1_temp.push(t.clone());
-            } else {
-                l1_temp.push(t.clone());
-            }
-        }
-        l1_runs_l.push(l1_temp.clone());
-        l1_temp = Vec::new();
-        while let Some(t) = &self.right_child.next()? {
-            // each run contains 4 Tuples in order to fit into the register
-            if l1_temp.len() == 4 {
-                l1_runs_r.push(l1_temp.clone());
-                l1_temp = Vec::new();
-                l1_temp.push(t.clone());
-            } else {
-                l1_temp.push(t.clone());
-            }
-        }
-        l1_runs_r.push(l1_temp.clone());
-
-
-        // parallel sorting level 1 runs
-        l1_runs_l = sort_runs(l1_runs_l, left_index, 1);
-        l1_runs_r = sort_runs(l1_runs_r, right_index, 1);
-
-        // merge and sort into level 2 runs
-        let mut l2_runs_l = merge_1_to_2(l1_runs_l.clone());
-        let mut l2_runs_r = merge_1_to_2(l1_runs_r.clone());
-
-        // parallel sorting level 2 runs
-        l2_runs_l = sort_runs(l2_runs_l, left_index, 2);
-        l2_runs_r = sort_runs(l2_runs_r, right_index, 2);
-
-        // level 3 m-way/m-pass
-        if self.sort_merge_method == 1 {
-            // find right child's min/max
-            for run in l2_runs_r.clone() {
-                for t in run {
-                    if compare_max(t.clone(), self.max_r.clone(), right_index) == t {
-                        self.max_r = t.clone();
-                    }
-                    if compare_min(t.clone(), self.min_r.clone(), right_index) == t {
-                        self.min_r = t.clone();
-                    }
-                }
-            }
-
-            self.l3_runs_l = sort_m_way_l3(l2_runs_l, self.min_r.clone(), self.max_r.clone(), left_index);
-            self.l3_runs_r = sort_m_way_l3(l2_runs_r, self.min_r.clone(), self.max_r.clone(), right_index);
-        } else {
-            self.l3_runs_l = l2_runs_l;
-            self.l3_runs_r = l2_runs_r;
-        }
-        // assert_eq!(self.l3_runs_l, vec![vec![Tuple::new(vec![Field::StringField(String::from("Here"))])]]);
-
+        let right = read_sorted(&mut self.right_child, self.right_index, right_sorted, &mut self.metrics)?;
+        self.aggregate_blocks(right);
         Ok(())
     }
 
     fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
         if !self.open {
-            panic!("Operator has not been opened")
-        }
-
-        let mut handles = Vec::new();
-        let predicate = self.predicate.clone();
-
-        // M-Way
-        if self.sort_merge_method == 1 {
-            let mut run_counter = 0;
-            // loop through each run in left
-            for run_l in self.l3_runs_l.clone() {
-                let right_runs = self.l3_runs_r.clone();
-                let handle = thread::spawn(move || {
-                    let new_run = join_m_way(
-                        run_l.clone(),
-                        right_runs[run_counter].clone(),
-                        predicate);
-                    new_run
-                });
-                handles.push(handle);
-                run_counter += 1;
-            }
-        } else {
-        // Join M-Pass
-            for run in self.l3_runs_l.clone() {
-                let right_runs = self.l3_runs_r.clone();
-                let handle = thread::spawn(move || {
-                    let new_run = join_m_pass(
-                        run.clone(),
-                        right_runs.clone(),
-                        predicate);
-                    new_run
-                });
-                handles.push(handle);
-            }
+            return not_open();
         }
-
-        let mut joined_left_runs = Vec::new();
-        for handle in handles {
-            joined_left_runs.push(handle.join().unwrap());
+        while let Some(t) = self.left_child.next()? {
+            self.metrics.rows_in += 1;
+            let key = t.get_field(self.left_index).unwrap();
+            let comparisons = &mut self.metrics.comparisons;
+            let found = self.groups.binary_search_by(|(block, _)| {
+                *comparisons += 1;
+                block.cmp(key)
+            });
+            if let Ok(block) = found {
+                self.metrics.rows_out += 1;
+                let aggs = self.groups[block].1.iter().cloned();
+                return Ok(Some(Tuple::new(t.field_vals().cloned().chain(aggs).collect())));
+            }
         }
-        self.l3_runs_l = joined_left_runs;
-
         Ok(None)
     }
 
     fn close(&mut self) -> Result<(), CrustyError> {
         if !self.open {
-            panic!("Operator has not been opened")
+            return not_open();
         }
         self.left_child.close()?;
         self.right_child.close()?;
+        self.groups.clear();
         self.open = false;
         Ok(())
     }
 
+    /// Returns to the first left tuple, keeping the aggregates of the right child.
     fn rewind(&mut self) -> Result<(), CrustyError> {
         if !self.open {
-            panic!("Operator has not been opened")
+            return not_open();
         }
-        // Rewind children
-        self.left_child.rewind()?;
-        self.right_child.rewind()?;
-        self.l3_runs_l = Vec::new();
-        self.l3_runs_r = Vec::new();
-        self.min_r = Tuple::new(vec![Field::IntField(999999), Field::IntField(999999), Field::IntField(999999), Field::IntField(999999)]);
-        self.max_r = Tuple::new(vec![]);
-        Ok(())
+        self.left_child.rewind()
     }
 
-    /// return schema of the result
     fn get_schema(&self) -> &TableSchema {
         &self.schema
     }
+
+    fn metrics(&self) -> OpMetrics {
+        self.metrics
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.left_child, &self.right_child]
+    }
+
+    /// The left tuples keep the order of the left child.
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        self.left_child.sort_order()
+    }
 }
 
+/// How a SimilarityJoin matches string keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Similarity {
+    /// Keys at most this many character insertions, deletions or substitutions apart.
+    EditDistance(usize),
+    /// Keys starting with the same characters, at least this many of them.
+    Prefix(usize),
+}
 
-#[cfg(test)]
-mod test {
-    use std::ops::Deref;
-    use crate::common::*;
-    use super::*;
+/// Similarity join of string keys, for fuzzy matching, e.g. of misspelled names.
+///
+/// Open reads both children and joins every pair of keys that are similar. Under an edit
+/// distance of k, both sides are sorted on the length of their keys and each left key is
+/// only compared with the window of right keys whose lengths differ by at most k, as more
+/// edits are needed otherwise; a candidate pair is checked on the band of the distance
+/// table k cells around its diagonal, and given up once the whole band exceeds k. Under a
+/// prefix, both sides are sorted on their first characters and merged, so each left key
+/// meets only the right keys with the same prefix. The output is in no particular order.
+pub struct SimilarityJoin<L = Box<dyn OpIterator>, R = Box<dyn OpIterator>> {
+    /// Left child node.
+    left_child: L,
+    /// Right child node.
+    right_child: R,
+    /// Index of the key of the left tuples.
+    left_index: usize,
+    /// Index of the key of the right tuples.
+    right_index: usize,
+    /// How the keys are matched.
+    similarity: Similarity,
+    /// Schema of the result.
+    schema: TableSchema,
+    /// Join status
+    open: bool,
+    /// Joined tuples.
+    output: Vec<Tuple>,
+    /// Position of the next output tuple.
+    cursor: usize,
+    /// Rows and candidate pairs checked since the last open.
+    metrics: OpMetrics,
+}
 
-    /// Creates a Vec of tuples containing IntFields given a 2D Vec of i32 's
-    pub fn create_tuple_list(tuple_data: Vec<Vec<i32>>) -> Vec<Tuple> {
-        let mut tuples = Vec::new();
-        for item in &tuple_data {
-            let fields = item.iter().map(|i| Field::IntField(*i)).collect();
-            tuples.push(Tuple::new(fields));
+impl<L: OpIterator, R: OpIterator> SimilarityJoin<L, R> {
+    /// Similarity join constructor.
+    ///
+    /// Fails with a ValidationError if a key does not exist or is not a string.
+    ///
+    /// # Arguments
+    ///
+    /// * `similarity` - How the keys are matched.
+    /// * `left_index` - Index of the key of the left tuples.
+    /// * `right_index` - Index of the key of the right tuples.
+    /// * `left_child` - Left child of join operator.
+    /// * `right_child` - Right child of join operator.
+    pub fn new(
+        similarity: Similarity,
+        left_index: usize,
+        right_index: usize,
+        left_child: L,
+        right_child: R,
+    ) -> Result<Self, CrustyError> {
+        validate_join_fields(left_child.get_schema(), left_index, right_child.get_schema(), right_index)?;
+        if join_attribute(left_child.get_schema(), left_index, "left")?.dtype() != &DataType::String {
+            return Err(CrustyError::ValidationError(format!(
+                "a similarity join needs string keys, left field {} is not one",
+                left_index
+            )));
         }
-        tuples
+        Ok(Self {
+            schema: left_child.get_schema().merge(right_child.get_schema()),
+            left_child,
+            right_child,
+            left_index,
+            right_index,
+            similarity,
+            open: false,
+            output: Vec::new(),
+            cursor: 0,
+            metrics: OpMetrics::default(),
+        })
     }
-    /// Creates a new table schema for a table with width number of IntFields.
-    pub fn get_int_table_schema(width: usize) -> TableSchema {
-        let mut attrs = Vec::new();
-        for _ in 0..width {
-            attrs.push(Attribute::new(String::new(), DataType::Int))
+
+    // Join the keys within an edit distance, windows of right keys by length
+    fn join_edit_distance(&mut self, left: Vec<Tuple>, right: Vec<Tuple>, k: usize) {
+        let chars = |tuples: Vec<Tuple>, index: usize| {
+            let mut keyed: Vec<(Vec<char>, Tuple)> = tuples
+                .into_iter()
+                .map(|t| (t.get_field(index).unwrap().unwrap_string_field().chars().collect(), t))
+                .collect();
+            keyed.sort_by_key(|(key, _)| key.len());
+            keyed
+        };
+        let left = chars(left, self.left_index);
+        let right = chars(right, self.right_index);
+        // right keys before start are too short for the current and every later left key
+        let mut start = 0;
+        for (key, t) in &left {
+            while right.get(start).is_some_and(|(r, _)| r.len() + k < key.len()) {
+                start += 1;
+            }
+            for (r, t_r) in right[start..].iter().take_while(|(r, _)| r.len() <= key.len() + k) {
+                self.metrics.comparisons += 1;
+                if within_edit_distance(key, r, k) {
+                    self.output.push(t.merge(t_r));
+                }
+            }
         }
-        TableSchema::new(attrs)
     }
-    #[allow(dead_code)]
-    /// Asserts that iter1 and iter2 contain all the same tuples
-    pub fn match_all_tuples(
-        mut iter1: Box<dyn OpIterator>,
-        mut iter2: Box<dyn OpIterator>,
-    ) -> Result<(), CrustyError> {
-        while let Some(t1) = iter1.next()? {
-            let t2 = iter2.next()?.unwrap();
-            assert_eq!(t1, t2);
+
+    // Join the keys sharing a prefix, merging both sides sorted on it
+    fn join_prefix(&mut self, left: Vec<Tuple>, right: Vec<Tuple>, length: usize) {
+        // keys shorter than the prefix match nothing
+        let prefixed = |tuples: Vec<Tuple>, index: usize| {
+            let mut keyed: Vec<(String, Tuple)> = tuples
+                .into_iter()
+                .filter_map(|t| {
+                    let prefix: String = t.get_field(index).unwrap().unwrap_string_field().chars().take(length).collect();
+                    (prefix.chars().count() == length).then_some((prefix, t))
+                })
+                .collect();
+            keyed.sort_by(|a, b| a.0.cmp(&b.0));
+            keyed
+        };
+        let left = prefixed(left, self.left_index);
+        let right = prefixed(right, self.right_index);
+        let mut r = 0;
+        for (prefix, t) in &left {
+            while right.get(r).is_some_and(|(p, _)| p < prefix) {
+                self.metrics.comparisons += 1;
+                r += 1;
+            }
+            for (_, t_r) in right[r..].iter().take_while(|(p, _)| p == prefix) {
+                self.metrics.comparisons += 1;
+                self.output.push(t.merge(t_r));
+            }
         }
-        // assert_eq!(iter2.next()?.unwrap(), Tuple::new(vec![]));
-        assert!(iter2.next()?.is_none());
-        Ok(())
     }
+}
 
-    const WIDTH1: usize = 2;
-    const WIDTH2: usize = 3;
-    enum JoinType {
-        NestedLoop,
-        HashEq,
-        SortMerge,
+// helper method to tell whether two strings are within an edit distance, filling only the
+// band of the distance table k cells around the diagonal
+fn within_edit_distance(a: &[char], b: &[char], k: usize) -> bool {
+    if a.len().abs_diff(b.len()) > k {
+        return false;
     }
-
-    pub fn scan1() -> TupleIterator {
-        let tuples = create_tuple_list(vec![
-            vec![1, 4], vec![3, 3], vec![5, 6], vec![7, 8],
-            vec![1, 1], vec![3, 7], vec![5, 2], vec![7, 5]]);
-        let ts = get_int_table_schema(WIDTH1);
-        TupleIterator::new(tuples, ts)
+    // distances above k are all k + 1, as only whether one is within k matters
+    let far = k + 1;
+    let mut prev: Vec<usize> = (0..=b.len()).map(|j| j.min(far)).collect();
+    let mut cur = vec![far; b.len() + 1];
+    for i in 1..=a.len() {
+        let (low, high) = (i.saturating_sub(k).max(1), (i + k).min(b.len()));
+        // cells left and right of the band are out of reach within k
+        cur[low - 1] = if low == 1 { i.min(far) } else { far };
+        if high < b.len() {
+            cur[high + 1] = far;
+        }
+        let mut row_min = cur[low - 1];
+        for j in low..=high {
+            let substitution = prev[j - 1] + (a[i - 1] != b[j - 1]) as usize;
+            cur[j] = substitution.min(prev[j] + 1).min(cur[j - 1] + 1).min(far);
+            row_min = row_min.min(cur[j]);
+        }
+        if row_min > k {
+            return false;
+        }
+        std::mem::swap(&mut prev, &mut cur);
     }
+    prev[b.len()] <= k
+}
 
-    pub fn scan2() -> TupleIterator {
-        let tuples = create_tuple_list(vec![
-            vec![1, 2, 3], vec![2, 3, 4], vec![3, 4, 5], vec![4, 5, 6],
-            vec![5, 9, 7], vec![1, 10, 3], vec![2, 7, 4], vec![3, 6, 5],
-        ]);
-        let ts = get_int_table_schema(WIDTH2);
-        TupleIterator::new(tuples, ts)
+impl<L: OpIterator, R: OpIterator> OpIterator for SimilarityJoin<L, R> {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.open = true;
+        self.metrics = OpMetrics::default();
+        self.left_child.open()?;
+        self.right_child.open()?;
+        let mut read = |child: &mut dyn OpIterator| -> Result<Vec<Tuple>, CrustyError> {
+            let mut tuples = Vec::new();
+            while let Some(t) = child.next()? {
+                tuples.push(t);
+            }
+            self.metrics.rows_in += tuples.len();
+            Ok(tuples)
+        };
+        let left = read(&mut self.left_child)?;
+        let right = read(&mut self.right_child)?;
+        self.output.clear();
+        self.cursor = 0;
+        match self.similarity {
+            Similarity::EditDistance(k) => self.join_edit_distance(left, right, k),
+            Similarity::Prefix(length) => self.join_prefix(left, right, length),
+        }
+        Ok(())
     }
 
-    pub fn eq_join() -> TupleIterator {
-        let tuples = create_tuple_list(vec![
-            vec![5, 2, 1, 2, 3],
-            vec![3, 3, 2, 3, 4],
-            vec![1, 4, 3, 4, 5],
-            vec![7, 5, 4, 5, 6],
-            vec![5, 6, 3, 6, 5],
-            vec![3, 7, 2, 7, 4],
-        ]);
-        let ts = get_int_table_schema(WIDTH1 + WIDTH2);
-        TupleIterator::new(tuples, ts)
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        let tuple = self.output.get(self.cursor).cloned();
+        self.cursor += tuple.is_some() as usize;
+        self.metrics.rows_out += tuple.is_some() as usize;
+        Ok(tuple)
     }
 
-    fn construct_join(
-        ty: JoinType,
-        op: SimplePredicateOp,
-        left_index: usize,
-        right_index: usize,
-        l3_method: isize,
-    ) -> Box<dyn OpIterator> {
-        let s1 = Box::new(scan1());
-        let s2 = Box::new(scan2());
-        match ty {
-            JoinType::NestedLoop => Box::new(Join::new(op, left_index, right_index, s1, s2)),
-            JoinType::HashEq => Box::new(HashEqJoin::new(op, left_index, right_index, s1, s2)),
-            JoinType::SortMerge => Box::new(SortMergeJoin::new(op, left_index, right_index, s1, s2, l3_method)),
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
         }
+        self.left_child.close()?;
+        self.right_child.close()?;
+        self.output.clear();
+        self.open = false;
+        Ok(())
     }
 
-    fn test_get_schema(join_type: JoinType, l3_method: isize) {
-        let op = construct_join(join_type, SimplePredicateOp::Equals, 0, 0, l3_method);
-        let expected = get_int_table_schema(WIDTH1 + WIDTH2);
-        let actual = op.get_schema();
-        assert_eq!(&expected, actual);
+    /// Returns to the first output tuple without joining the children again.
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        self.cursor = 0;
+        Ok(())
     }
 
-    fn test_next_not_open(join_type: JoinType, l3_method: isize) {
-        let mut op = construct_join(join_type, SimplePredicateOp::Equals, 0, 0, l3_method);
-        op.next().unwrap();
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
     }
 
-    fn test_rewind_not_open(join_type: JoinType, l3_method: isize) {
-        let mut op = construct_join(join_type, SimplePredicateOp::Equals, 0, 0, l3_method);
-        op.rewind().unwrap();
+    fn metrics(&self) -> OpMetrics {
+        self.metrics
     }
 
-    fn test_rewind(join_type: JoinType, l3_method: isize) -> Result<(), CrustyError> {
-        let mut op = construct_join(join_type, SimplePredicateOp::Equals, 1, 1, l3_method);
-        op.open()?;
-        while op.next()?.is_some() {}
-        op.rewind()?;
-        assert_eq!(op.next(), Ok(None));
-        Ok(())
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.left_child, &self.right_child]
     }
+}
 
-    fn test_join_m_way() -> Result<(), CrustyError> {
-        // left run
-        let left_run = create_tuple_list(vec![
-            vec![5, 1], vec![3, 8], vec![1, 10], vec![1, 20]]);
-        // right runs
-        let mut right_run = create_tuple_list(vec![
-            vec![5, 1], vec![3, 2], vec![7, 3], vec![1, 4],
-            vec![1, 5], vec![3, 6], vec![5, 7], vec![7, 8]]);
-        // join predicate
-        let pre = JoinPredicate::new(SimplePredicateOp::Equals, 1, 1);
+/// Shape of the hash table of a hash join, for benchmarking.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketStats {
+    /// Number of distinct keys.
+    pub buckets: usize,
+    /// Number of tuples in the table.
+    pub tuples: usize,
+    /// Number of tuples in the largest bucket.
+    pub max_chain: usize,
+    /// Keys per slot of the table.
+    pub load_factor: f64,
+}
 
-        // join the result
-        let res = join_m_way(left_run, right_run, pre);
-        // expected
-        let target = create_tuple_list(vec![
-            vec![5, 1, 5, 1],
-            vec![3, 8, 7, 8],
-        ]);
+/// Hash equi-join implementation. (You can add any other fields that you think are neccessary)
+///
+/// With more than one partition the join is a hybrid hash join: tuples of the first
+/// partition are joined in memory, the others are spilled to disk on both sides and
+/// joined one partition at a time once the right child is exhausted. A join with a
+/// single partition switches to HASH_SPILL_PARTITIONS partitions once its hash table
+/// no longer fits in the limit of its memory tracker.
+pub struct HashEqJoin<L = Box<dyn OpIterator>, R = Box<dyn OpIterator>> {
+    predicate: JoinPredicate,
 
-        let ts = get_int_table_schema(4);
+    left_child: L,
+    right_child: R,
 
-        let mut target_op = Box::new(TupleIterator::new(target, ts.clone()));
-        let mut res_op = Box::new(TupleIterator::new(res, ts.clone()));
-        target_op.open()?;
-        res_op.open()?;
-        match_all_tuples(target_op, res_op)
-    }
+    schema: TableSchema,
+
+    open: bool,
+    // Map attribute values to all tuples containing that value, one table per radix partition
+    ht: Vec<HashMap<Field, Vec<Tuple>>>,
+    // Number of hash bits picking the radix partition of a key, 0 for a single table
+    radix_bits: u32,
+    // Build tuples scattered on their radix partition, inserted into the tables once the build side is read
+    staged: Vec<Vec<Tuple>>,
+    // Current right tuple being joined and index of the next match in its bucket
+    current: Option<(Tuple, usize)>,
+    // Number of hash partitions, 1 to keep the whole left child in memory
+    partitions: usize,
+    // Spilled left and right tuples of the partitions after the first
+    spilled_l: Vec<SpillFile>,
+    spilled_r: Vec<SpillFile>,
+    // Spilled partition pairs waiting to be joined
+    pending: VecDeque<(SpillFile, SpillFile)>,
+    // Spilled right partition being probed, or None while probing with the right child
+    probe: Option<SpillReader>,
+    // Whether the right child is exhausted
+    right_done: bool,
+    // Output fields of the joined tuples, all if None
+    projection: Option<Vec<usize>>,
+    // Table aliases qualifying the output names, if any
+    aliases: Option<(String, String)>,
+    // Tracker charged with the hash table
+    memory: MemoryTracker,
+    // Bytes of the hash table charged to the tracker
+    charged: usize,
+    // Progress reporting and cancellation of the build and probe
+    monitor: ProgressMonitor,
+    // Rows, probes and spilled partitions since the last open
+    metrics: OpMetrics,
+}
+
+/// Number of partitions a hash join switches to when its hash table exceeds the memory limit.
+pub const HASH_SPILL_PARTITIONS: usize = 8;
+
+/// Largest number of radix bits of a hash join, 65536 tables.
+pub const MAX_RADIX_BITS: u32 = 16;
+
+// helper method to hash a key, the spill partition takes the low bits and the radix partition the high bits
+pub(crate) fn hash_field(field: &Field) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    field.hash(&mut hasher);
+    hasher.finish()
+}
+
+// helper method to find the radix partition of a key from the top bits of its hash
+fn radix_table(field: &Field, bits: u32) -> usize {
+    if bits == 0 {
+        return 0;
+    }
+    (hash_field(field) >> (u64::BITS - bits)) as usize
+}
+
+impl<L: OpIterator, R: OpIterator> HashEqJoin<L, R> {
+    /// Constructor for a hash equi-join operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Operation in join condition.
+    /// * `left_index` - Index of the left field in join condition.
+    /// * `right_index` - Index of the right field in join condition.
+    /// * `left_child` - Left child of join operator.
+    /// * `right_child` - Left child of join operator.
+    ///
+    /// Fails with a ValidationError if the operation is not Equals, since the hash table
+    /// only finds equal keys, if a join field does not exist or the fields have different
+    /// types.
+    #[allow(dead_code)]
+    pub fn new(
+        op: SimplePredicateOp,
+        left_index: usize,
+        right_index: usize,
+        left_child: L,
+        right_child: R,
+    ) -> Result<Self, CrustyError> {
+        if op != SimplePredicateOp::Equals {
+            return Err(CrustyError::ValidationError(format!(
+                "a hash join only evaluates Equals, not {:?}",
+                op
+            )));
+        }
+        validate_join_fields(left_child.get_schema(), left_index, right_child.get_schema(), right_index)?;
+        Ok(Self {
+            predicate: JoinPredicate::new(op, left_index, right_index),
+            schema: left_child.get_schema().merge(right_child.get_schema()),
+            left_child,
+            right_child,
+            open: false,
+            ht: vec![HashMap::new()],
+            radix_bits: 0,
+            staged: Vec::new(),
+            current: None,
+            partitions: 1,
+            spilled_l: Vec::new(),
+            spilled_r: Vec::new(),
+            pending: VecDeque::new(),
+            probe: None,
+            right_done: false,
+            projection: None,
+            aliases: None,
+            memory: MemoryTracker::unbounded(),
+            charged: 0,
+            monitor: ProgressMonitor::default(),
+            metrics: OpMetrics::default(),
+        })
+    }
+
+    /// Set the tracker charged with the hash table, e.g. one shared by the whole plan.
+    ///
+    /// # Arguments
+    ///
+    /// * `memory` - Memory tracker.
+    pub fn set_memory_tracker(&mut self, memory: MemoryTracker) {
+        self.memory = memory;
+    }
+
+    /// Report the rows built and probed, and stop with an ExecutionError once cancelled or
+    /// past the deadline.
+    ///
+    /// # Arguments
+    ///
+    /// * `monitor` - Progress monitor, the phases are "build" and "probe".
+    pub fn set_progress_monitor(&mut self, monitor: ProgressMonitor) {
+        self.monitor = monitor;
+    }
+
+    /// Returns the tracker charged with the hash table.
+    pub fn memory_tracker(&self) -> &MemoryTracker {
+        &self.memory
+    }
+
+    /// Set the number of hash partitions, taking effect on the next open.
+    ///
+    /// # Arguments
+    ///
+    /// * `partitions` - Number of partitions, 1 to keep the whole left child in memory.
+    pub fn set_partitions(&mut self, partitions: usize) {
+        self.partitions = partitions.max(1);
+    }
+
+    /// Set the number of hash bits radix partitioning the build side, taking effect on
+    /// the next open.
+    ///
+    /// The build tuples are first scattered on their partition, then each partition gets
+    /// its own small hash table built in one go, so the table being built stays in cache.
+    /// Probes go straight to the table of their partition.
+    ///
+    /// # Arguments
+    ///
+    /// * `bits` - Number of bits, 0 for a single table, at most MAX_RADIX_BITS.
+    pub fn set_radix_bits(&mut self, bits: u32) {
+        self.radix_bits = bits.min(MAX_RADIX_BITS);
+    }
+    /// Set how string join fields are compared, binary by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `collation` - Collation of the join fields.
+    pub fn set_collation(&mut self, collation: Collation) {
+        self.predicate.set_collation(collation);
+    }
+
+    /// Keep only some fields of the joined tuples, the others are never copied.
+    ///
+    /// # Arguments
+    ///
+    /// * `columns` - Indices of the output fields among the left fields followed by the right fields.
+    pub fn set_projection(&mut self, columns: Vec<usize>) -> Result<(), CrustyError> {
+        self.schema = join_schema(self.left_child.get_schema(), self.right_child.get_schema(), self.aliases.as_ref(), Some(&columns))?;
+        self.projection = Some(columns);
+        Ok(())
+    }
+
+    /// Name the output attributes alias.name after the table they come from, so they can
+    /// be found with get_field_index even when both children share names.
+    ///
+    /// # Arguments
+    ///
+    /// * `left_alias` - Alias of the left child.
+    /// * `right_alias` - Alias of the right child.
+    pub fn set_aliases(&mut self, left_alias: &str, right_alias: &str) -> Result<(), CrustyError> {
+        let aliases = (left_alias.to_string(), right_alias.to_string());
+        self.schema = join_schema(
+            self.left_child.get_schema(),
+            self.right_child.get_schema(),
+            Some(&aliases),
+            self.projection.as_deref(),
+        )?;
+        self.aliases = Some(aliases);
+        Ok(())
+    }
+
+    /// Returns the shape of the hash table currently built, the first partition's in
+    /// partitioned mode until the right child is exhausted.
+    pub fn bucket_stats(&self) -> BucketStats {
+        let buckets = self.ht.iter().map(HashMap::len).sum();
+        let capacity: usize = self.ht.iter().map(HashMap::capacity).sum();
+        BucketStats {
+            buckets,
+            tuples: self.ht.iter().flat_map(HashMap::values).map(Vec::len).sum(),
+            max_chain: self.ht.iter().flat_map(HashMap::values).map(Vec::len).max().unwrap_or(0),
+            load_factor: if capacity == 0 { 0.0 } else { buckets as f64 / capacity as f64 },
+        }
+    }
+
+    // Partition of a key
+    fn partition(&self, field: &Field) -> usize {
+        if self.partitions == 1 {
+            return 0;
+        }
+        (hash_field(field) % self.partitions as u64) as usize
+    }
+
+    // Radix partition of a key, the index of its table
+    fn table(&self, field: &Field) -> usize {
+        radix_table(field, self.radix_bits)
+    }
+
+    // Add a tuple to its bucket of the hash table, or to its radix partition until finish_build
+    fn insert(&mut self, t: Tuple) {
+        let key = self.predicate.left_key(&t).into_owned();
+        let table = self.table(&key);
+        if self.radix_bits > 0 {
+            self.staged[table].push(t);
+        } else {
+            self.ht[table].entry(key).or_default().push(t);
+        }
+    }
+
+    // Build the table of each radix partition from its staged tuples, one partition at a time
+    fn finish_build(&mut self) {
+        let predicate = self.predicate;
+        for (table, staged) in self.ht.iter_mut().zip(self.staged.iter_mut()) {
+            for t in staged.drain(..) {
+                table.entry(predicate.left_key(&t).into_owned()).or_default().push(t);
+            }
+        }
+    }
+
+    // Empty the hash table and return its bytes to the tracker
+    fn clear_table(&mut self) {
+        let tables = 1 << self.radix_bits;
+        self.ht.clear();
+        self.ht.resize_with(tables, HashMap::new);
+        self.staged.clear();
+        self.staged.resize_with(if self.radix_bits > 0 { tables } else { 0 }, Vec::new);
+        self.memory.release(self.charged);
+        self.charged = 0;
+    }
+
+    // Add a left tuple of the first partition to the hash table, switching to partitioned
+    // mode if it does not fit in the memory limit
+    fn insert_charged(&mut self, t: Tuple) -> Result<(), CrustyError> {
+        let bytes = self.left_child.get_schema().byte_size();
+        if !self.memory.try_reserve(bytes) {
+            if self.partitions == 1 {
+                self.repartition()?;
+                let p = self.partition(&self.predicate.left_key(&t));
+                if p != 0 {
+                    return self.spilled_l[p - 1].write(&t);
+                }
+            }
+            // the first partition has to be joined in memory
+            self.memory.reserve(bytes);
+        }
+        self.charged += bytes;
+        self.insert(t);
+        Ok(())
+    }
+
+    // Switch to partitioned mode, spilling the tuples of the hash table outside the first partition
+    fn repartition(&mut self) -> Result<(), CrustyError> {
+        self.finish_build();
+        self.partitions = HASH_SPILL_PARTITIONS;
+        self.spilled_l = (1..self.partitions).map(|_| SpillFile::create()).collect::<Result<_, _>>()?;
+        self.spilled_r = (1..self.partitions).map(|_| SpillFile::create()).collect::<Result<_, _>>()?;
+        let bytes = self.left_child.get_schema().byte_size();
+        let tables: Vec<_> = self.ht.iter_mut().map(std::mem::take).collect();
+        for (field, tuples) in tables.into_iter().flatten() {
+            match self.partition(&field) {
+                0 => {
+                    let table = self.table(&field);
+                    self.ht[table].insert(field, tuples);
+                }
+                p => {
+                    self.memory.release(bytes * tuples.len());
+                    self.charged -= bytes * tuples.len();
+                    for t in &tuples {
+                        self.spilled_l[p - 1].write(t)?;
+                    }
+                }
+            }
+        }
+        spill_event("HashEqJoin", self.spilled_l.iter().map(SpillFile::len).sum());
+        Ok(())
+    }
+
+    // Build the hash table of the first partition from the left child, spilling the others
+    fn build(&mut self) -> Result<(), CrustyError> {
+        self.clear_table();
+        self.current = None;
+        self.pending.clear();
+        self.probe = None;
+        self.right_done = false;
+        self.spilled_l = (1..self.partitions).map(|_| SpillFile::create()).collect::<Result<_, _>>()?;
+        self.spilled_r = (1..self.partitions).map(|_| SpillFile::create()).collect::<Result<_, _>>()?;
+        let mut rows = 0;
+        while let Some(t) = self.left_child.next()? {
+            self.metrics.rows_in += 1;
+            rows += 1;
+            self.monitor.tick("build", rows)?;
+            match self.partition(&self.predicate.left_key(&t)) {
+                0 => self.insert_charged(t)?,
+                p => self.spilled_l[p - 1].write(&t)?,
+            }
+        }
+        self.monitor.report("build", rows);
+        self.finish_build();
+        Ok(())
+    }
+
+    // Next right tuple to probe the hash table with, switching to the next spilled
+    // partition once the current probe side is exhausted
+    fn next_probe(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        loop {
+            if let Some(reader) = self.probe.as_mut() {
+                if let Some(t) = reader.next_tuple()? {
+                    return Ok(Some(t));
+                }
+                self.probe = None;
+            } else if !self.right_done {
+                match self.right_child.next()? {
+                    Some(t) => {
+                        self.metrics.rows_in += 1;
+                        match self.partition(&self.predicate.right_key(&t)) {
+                            0 => return Ok(Some(t)),
+                            p => self.spilled_r[p - 1].write(&t)?,
+                        }
+                    }
+                    None => {
+                        self.right_done = true;
+                        // partitions without tuples on either side join nothing
+                        let spilled_l = std::mem::take(&mut self.spilled_l);
+                        let spilled_r = std::mem::take(&mut self.spilled_r);
+                        self.pending = spilled_l
+                            .into_iter()
+                            .zip(spilled_r)
+                            .filter(|(l, r)| !l.is_empty() && !r.is_empty())
+                            .collect();
+                        self.metrics.spills += self.pending.len();
+                    }
+                }
+                continue;
+            }
+            match self.pending.pop_front() {
+                None => return Ok(None),
+                Some((left, right)) => {
+                    self.monitor.check_deadline()?;
+                    self.clear_table();
+                    let bytes = self.left_child.get_schema().byte_size();
+                    let mut reader = left.into_reader()?;
+                    while let Some(t) = reader.next_tuple()? {
+                        // a spilled partition is joined in memory
+                        self.memory.reserve(bytes);
+                        self.charged += bytes;
+                        self.insert(t);
+                    }
+                    self.finish_build();
+                    self.probe = Some(right.into_reader()?);
+                }
+            }
+        }
+    }
+}
+
+impl<L: OpIterator, R: OpIterator> OpIterator for HashEqJoin<L, R> {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.open = true;
+
+        // Build hash table from left child
+        self.metrics = OpMetrics::default();
+        self.left_child.open()?;
+        let build_span = phase_span!("HashEqJoin", "build");
+        self.build()?;
+        build_span.finish(self.metrics.rows_in);
+
+        self.right_child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+
+        loop {
+            // Try to use current right tuple again
+            if let Some((right, index)) = self.current.as_mut() {
+                let key = self.predicate.right_key(right);
+                let table = &self.ht[radix_table(&key, self.radix_bits)];
+                if let Some(t) = table.get(key.as_ref()).and_then(|vec| vec.get(*index)) {
+                    *index += 1;
+                    self.metrics.rows_out += 1;
+                    return Ok(Some(merge_projected(t, right, self.projection.as_deref())));
+                }
+                // Bucket exhausted
+                self.current = None;
+            }
+
+            // Find new right tuple with a bucket
+            match self.next_probe()? {
+                Some(t) => {
+                    self.metrics.comparisons += 1;
+                    self.monitor.tick("probe", self.metrics.comparisons)?;
+                    let key = self.predicate.right_key(&t);
+                    if self.ht[self.table(&key)].contains_key(key.as_ref()) {
+                        self.current = Some((t, 0));
+                    }
+                }
+                // Out of right tuples
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        // Close children, empty hash table and spilled partitions
+        self.left_child.close()?;
+        self.right_child.close()?;
+        self.clear_table();
+        self.current = None;
+        self.spilled_l.clear();
+        self.spilled_r.clear();
+        self.pending.clear();
+        self.probe = None;
+        self.open = false;
+        close_event("HashEqJoin", &self.metrics);
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        self.right_child.rewind()?;
+        if self.partitions == 1 {
+            // Keep hash table
+            self.current = None;
+            self.right_done = false;
+            Ok(())
+        } else {
+            // The spilled partitions were consumed, partition both children again
+            self.left_child.rewind()?;
+            self.build()
+        }
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+
+    fn metrics(&self) -> OpMetrics {
+        self.metrics
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.left_child, &self.right_child]
+    }
+}
+
+/// Sort-merge join implementation
+pub struct SortMergeJoin<L = Box<dyn OpIterator + Send>, R = Box<dyn OpIterator + Send>> {
+    /// Join condition.
+    predicate: JoinPredicate,
+    /// Left child node.
+    left_child: L,
+    /// Right child node.
+    right_child: R,
+    /// Schema of the result.
+    schema: TableSchema,
+    /// Join status
+    open: bool,
+    /// level 3 method
+    strategy: MergeStrategy,
+    /// run sizes for the sorting/merging levels
+    sort_config: SortConfig,
+    /// kernel forming the sorted runs
+    sort_kernel: SortKernel,
+    /// number of workers joining the level 3 morsels
+    workers: usize,
+    /// number of left tuples in a level 3 morsel
+    morsel_size: usize,
+    /// cores the level 3 merge and join workers are pinned to, if any
+    merge_cores: Option<CoreAffinity>,
+    /// how m-way picks the key ranges of its partitions
+    splitter_method: SplitterMethod,
+    /// left and right tuple counts of each m-way partition
+    partition_sizes: Vec<(usize, usize)>,
+    /// rows, bytes and time of each phase since the last open
+    phases: Vec<PhaseStats>,
+    /// left level 3 runs
+    pub l3_runs_l: Vec<Vec<Tuple>>,
+    /// right level 3 runs, moved into the join threads by the first next
+    pub l3_runs_r: Vec<Vec<Tuple>>,
+    /// right global minimum key
+    min_r: Option<Field>,
+    /// right global maximum key
+    max_r: Option<Field>,
+    /// whether level 3 has joined the runs
+    joined: bool,
+    /// run and position in l3_runs_l of the next output tuple
+    cursor: (usize, usize),
+    /// output fields of the joined tuples, all if None
+    projection: Option<Vec<usize>>,
+    /// table aliases qualifying the output names, if any
+    aliases: Option<(String, String)>,
+    /// limit to the k tuples smallest on an output column, if any
+    top_k: Option<TopK>,
+    /// whether the output is put in order on all its fields, not just the join key
+    deterministic: bool,
+    /// tracker charged with the runs
+    memory: MemoryTracker,
+    /// bytes of the runs charged to the tracker
+    charged: usize,
+    /// sorted right runs spilled to disk, joined one at a time by m-pass
+    spilled_r: Vec<SpillFile>,
+    /// sorted runs of the left and right children completed by an open that did not reach
+    /// level 3, e.g. when cancelled, see save_state
+    progress: (SortedChild, SortedChild),
+    /// whether the level 3 runs hold every tuple of the children
+    sorted: bool,
+    /// checkpoint the next open resumes from, if any, see restore_state
+    restored: Option<(PathBuf, SortState)>,
+    /// right tuples joined at a time when the right child is streamed, see set_stream_right
+    stream_chunk: Option<usize>,
+    /// sorted left run and position in the right child while it is streamed
+    stream: Option<RightStream>,
+    /// left key range the right child was asked to keep on the last open
+    key_range: Option<RangeInclusive<Field>>,
+    /// pool the spilled runs are read back through, if any
+    buffer_pool: Option<Arc<BufferPool>>,
+    /// progress reporting and cancellation of the sort and join phases
+    monitor: ProgressMonitor,
+    /// rows, comparisons and spilled runs since the last open
+    metrics: OpMetrics,
+    /// receiver of the sort, merge and join events, if any
+    #[cfg(feature = "visualize")]
+    events: Option<Arc<dyn EventSink>>,
+}
+
+/// A level 3 method plugged into the sort-merge join.
+pub trait Level3Strategy: Send + Sync {
+    /// Join the sorted runs of the left child with the sorted runs of the right child.
+    ///
+    /// Returns the joined tuples as runs, e.g. one per worker thread.
+    ///
+    /// # Arguments
+    ///
+    /// * `runs_l` - Sorted runs of the left child.
+    /// * `runs_r` - Sorted runs of the right child.
+    /// * `predicate` - Join condition.
+    fn join(&self, runs_l: Vec<Vec<Tuple>>, runs_r: Vec<Vec<Tuple>>, predicate: &JoinPredicate) -> Vec<Vec<Tuple>>;
+}
+
+/// Level 3 method of the sort-merge join.
+#[derive(Clone)]
+pub enum MergeStrategy {
+    /// Range partition both sides and join each left partition with its right partition, and
+    /// with the lower ones too for a greater than predicate. String keys of a binary collation
+    /// are dictionary encoded by the partition threads, see dictionary::encode_join_partitions.
+    MWay {
+        /// Number of partitions, each joined by its own thread.
+        partitions: usize,
+    },
+    /// Join each left run with every right run.
+    MPass {
+        /// Number of right runs merged into one before the join, 1 to keep them as sorted.
+        fan_in: usize,
+    },
+    /// Radix sort each side whole into a single run, then merge join the two runs.
+    ///
+    /// Replaces the sorting network and merge levels, see radix::radix_sort_row_ids. With
+    /// sort cores set, each core sorts a share of the side and the shares are merged.
+    Radix,
+    /// A user provided level 3 method.
+    Custom(Arc<dyn Level3Strategy>),
+}
+
+impl MergeStrategy {
+    /// M-way with one partition per physical thread but one.
+    pub const M_WAY: MergeStrategy = MergeStrategy::MWay { partitions: M_WAY_PARTITIONS };
+
+    /// M-pass over the right runs as sorted.
+    pub const M_PASS: MergeStrategy = MergeStrategy::MPass { fan_in: 1 };
+}
+
+impl fmt::Debug for MergeStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergeStrategy::MWay { partitions } => f.debug_struct("MWay").field("partitions", partitions).finish(),
+            MergeStrategy::MPass { fan_in } => f.debug_struct("MPass").field("fan_in", fan_in).finish(),
+            MergeStrategy::Radix => f.write_str("Radix"),
+            MergeStrategy::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// Builder of a SortMergeJoin, see SortMergeJoin::builder.
+#[derive(Default)]
+pub struct SortMergeJoinBuilder {
+    /// Join condition.
+    predicate: Option<JoinPredicate>,
+    /// Left child node.
+    left_child: Option<Box<dyn OpIterator + Send>>,
+    /// Right child node.
+    right_child: Option<Box<dyn OpIterator + Send>>,
+    /// Level 3 method, m-way if not set.
+    strategy: Option<MergeStrategy>,
+    /// Run sizes for the sorting/merging levels.
+    sort_config: SortConfig,
+    /// Kernel forming the sorted runs, the sorting network if not set.
+    sort_kernel: Option<SortKernel>,
+    /// Number of workers joining the level 3 morsels, one per core if not set.
+    workers: Option<usize>,
+    /// Right tuples joined at a time when the right child is streamed, if set.
+    stream_right: Option<usize>,
+    /// How m-way picks the key ranges of its partitions, sampled if not set.
+    splitter_method: Option<SplitterMethod>,
+    /// Output fields of the joined tuples, all if not set.
+    projection: Option<Vec<usize>>,
+    /// Table aliases qualifying the output names, if set.
+    aliases: Option<(String, String)>,
+    /// Limit to the k tuples smallest on an output column, if set.
+    top_k: Option<TopK>,
+    /// Whether the output is put in order on all its fields.
+    deterministic: bool,
+    /// Join condition on computed keys, replacing the predicate if set.
+    key_exprs: Option<(SimplePredicateOp, Expr, Expr)>,
+    /// How string join keys are compared, binary if not set.
+    collation: Option<Collation>,
+    /// Join condition on attribute names, resolved against the children when built.
+    predicate_names: Option<(SimplePredicateOp, String, String)>,
+}
+
+impl SortMergeJoinBuilder {
+    /// Set the join condition.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Operation in join condition.
+    /// * `left_index` - Index of the left field in join condition.
+    /// * `right_index` - Index of the right field in join condition.
+    pub fn predicate(mut self, op: SimplePredicateOp, left_index: usize, right_index: usize) -> Self {
+        self.predicate = Some(JoinPredicate::new(op, left_index, right_index));
+        self
+    }
+
+    /// Set the join condition on the names of a left and a right attribute, see
+    /// JoinPredicate::from_schemas.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Operation in join condition.
+    /// * `left_name` - Name of the left field in join condition.
+    /// * `right_name` - Name of the right field in join condition.
+    pub fn predicate_names(mut self, op: SimplePredicateOp, left_name: &str, right_name: &str) -> Self {
+        self.predicate_names = Some((op, left_name.to_string(), right_name.to_string()));
+        self
+    }
+
+    /// Set the join condition from a predicate, e.g. one resolved from attribute names.
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - Join condition.
+    pub fn join_predicate(mut self, predicate: JoinPredicate) -> Self {
+        self.predicate = Some(predicate);
+        self
+    }
+
+    /// Set the join condition on keys computed from the tuples, e.g. left.col0 + left.col1
+    /// = right.col2 or lower(left.col0) = right.col1.
+    ///
+    /// The keys are appended to the children by a Compute operator and joined like fields,
+    /// then projected away: the output and the projection columns are those of a join on
+    /// fields.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Operation in join condition.
+    /// * `left` - Key expression over the left tuples.
+    /// * `right` - Key expression over the right tuples.
+    pub fn expr_predicate(mut self, op: SimplePredicateOp, left: Expr, right: Expr) -> Self {
+        self.key_exprs = Some((op, left, right));
+        self
+    }
+
+    /// Set how string join keys are compared, sorted and partitioned.
+    ///
+    /// Under a collation other than binary the children are sorted and joined on the sort
+    /// keys of their join fields, computed like expression keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `collation` - Collation of the join keys.
+    pub fn collation(mut self, collation: Collation) -> Self {
+        self.collation = Some(collation);
+        self
+    }
+
+    /// Set the left child.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - Left child of join operator.
+    pub fn left(mut self, child: Box<dyn OpIterator + Send>) -> Self {
+        self.left_child = Some(child);
+        self
+    }
+
+    /// Set the right child.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - Right child of join operator.
+    pub fn right(mut self, child: Box<dyn OpIterator + Send>) -> Self {
+        self.right_child = Some(child);
+        self
+    }
+
+    /// Set the level 3 method.
+    ///
+    /// # Arguments
+    ///
+    /// * `strategy` - Level 3 method.
+    pub fn strategy(mut self, strategy: MergeStrategy) -> Self {
+        self.strategy = Some(strategy);
+        self
+    }
+
+    /// Set the run sizes used by the sorting/merging levels, keeping the memory budget.
+    ///
+    /// # Arguments
+    ///
+    /// * `sort_config` - Run-formation configuration.
+    pub fn sort_config(mut self, sort_config: SortConfig) -> Self {
+        let memory_budget = self.sort_config.memory_budget;
+        self.sort_config = sort_config;
+        self.sort_config.memory_budget = memory_budget.or(sort_config.memory_budget);
+        self
+    }
+
+    /// Set the memory budget bounding the size of a run.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Maximum number of bytes a run may occupy.
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.sort_config.set_memory_budget(bytes);
+        self
+    }
+
+    /// Set the kernel forming the sorted runs.
+    ///
+    /// # Arguments
+    ///
+    /// * `kernel` - Sort kernel.
+    pub fn sort_kernel(mut self, kernel: SortKernel) -> Self {
+        self.sort_kernel = Some(kernel);
+        self
+    }
+
+    /// Set the number of workers joining the level 3 morsels.
+    ///
+    /// # Arguments
+    ///
+    /// * `workers` - Number of worker threads.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = Some(workers);
+        self
+    }
+
+    /// Stream the right child in chunks instead of sorting it whole, see
+    /// SortMergeJoin::set_stream_right.
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk` - Number of right tuples joined at a time.
+    pub fn stream_right(mut self, chunk: usize) -> Self {
+        self.stream_right = Some(chunk);
+        self
+    }
+
+    /// Set how the m-way level 3 picks the key ranges of its partitions.
+    ///
+    /// # Arguments
+    ///
+    /// * `splitter_method` - Splitter method.
+    pub fn splitter_method(mut self, splitter_method: SplitterMethod) -> Self {
+        self.splitter_method = Some(splitter_method);
+        self
+    }
+
+    /// Keep only some fields of the joined tuples.
+    ///
+    /// # Arguments
+    ///
+    /// * `columns` - Indices of the output fields among the left fields followed by the right fields.
+    pub fn projection(mut self, columns: Vec<usize>) -> Self {
+        self.projection = Some(columns);
+        self
+    }
+
+    /// Name the output attributes alias.name after the table they come from.
+    ///
+    /// # Arguments
+    ///
+    /// * `left_alias` - Alias of the left child.
+    /// * `right_alias` - Alias of the right child.
+    pub fn aliases(mut self, left_alias: &str, right_alias: &str) -> Self {
+        self.aliases = Some((left_alias.to_string(), right_alias.to_string()));
+        self
+    }
+
+    /// Return only the k output tuples smallest on an output column.
+    ///
+    /// # Arguments
+    ///
+    /// * `k` - Number of tuples.
+    /// * `column` - Index of the output column, after the projection.
+    pub fn top_k(mut self, k: usize, column: usize) -> Self {
+        self.top_k = Some(TopK::new(k, column));
+        self
+    }
+
+    /// Return the joined tuples in an order that does not depend on the level 3 method or
+    /// the workers, see SortMergeJoin::set_deterministic_order.
+    pub fn deterministic_order(mut self) -> Self {
+        self.deterministic = true;
+        self
+    }
+
+    /// Build the join, failing with a ValidationError if the predicate or a child is missing
+    /// or the projection or top-k column has a field out of range.
+    pub fn build(self) -> Result<SortMergeJoin, CrustyError> {
+        let missing = |name: &str| CrustyError::ValidationError(format!("sort-merge join has no {}", name));
+        let mut left_child = self.left_child.ok_or_else(|| missing("left child"))?;
+        let mut right_child = self.right_child.ok_or_else(|| missing("right child"))?;
+        let mut projection = self.projection;
+        let mut predicate = self.predicate;
+        if let Some((op, left_name, right_name)) = &self.predicate_names {
+            let (left, right) = (left_child.get_schema(), right_child.get_schema());
+            predicate = Some(JoinPredicate::from_schemas(*op, left_name, right_name, left, right)?);
+        }
+        let key_exprs = match (self.key_exprs, predicate, self.collation) {
+            (key_exprs, _, None | Some(Collation::Binary)) => key_exprs,
+            (Some((op, left, right)), _, Some(collation)) => {
+                Some((op, Expr::collate(left, collation), Expr::collate(right, collation)))
+            }
+            (None, Some(p), Some(collation)) => Some((
+                p.op,
+                Expr::collate(Expr::Column(p.left_index), collation),
+                Expr::collate(Expr::Column(p.right_index), collation),
+            )),
+            (None, None, Some(_)) => return Err(missing("predicate")),
+        };
+        let predicate = match key_exprs {
+            Some((op, left, right)) => {
+                let (left_width, right_width) = (left_child.get_schema().size(), right_child.get_schema().size());
+                left_child = Box::new(Compute::new(left_child, vec![left])?);
+                right_child = Box::new(Compute::new(right_child, vec![right])?);
+                // output columns skip the computed left key
+                let columns = projection.unwrap_or_else(|| (0..left_width + right_width).collect());
+                projection = Some(columns.into_iter().map(|c| if c < left_width { c } else { c + 1 }).collect());
+                JoinPredicate::new(op, left_width, right_width)
+            }
+            None => predicate.ok_or_else(|| missing("predicate"))?,
+        };
+        let mut join = SortMergeJoin::new(
+            predicate.op,
+            predicate.left_index,
+            predicate.right_index,
+            left_child,
+            right_child,
+            self.strategy.unwrap_or(MergeStrategy::M_WAY),
+        )?;
+        join.sort_config = self.sort_config;
+        if let Some(kernel) = self.sort_kernel {
+            join.sort_kernel = kernel;
+        }
+        if let Some(workers) = self.workers {
+            join.set_workers(workers);
+        }
+        if let Some(chunk) = self.stream_right {
+            join.set_stream_right(chunk);
+        }
+        if let Some(splitter_method) = self.splitter_method {
+            join.splitter_method = splitter_method;
+        }
+        if let Some(columns) = projection {
+            join.set_projection(columns)?;
+        }
+        if let Some((left_alias, right_alias)) = self.aliases {
+            join.set_aliases(&left_alias, &right_alias)?;
+        }
+        if let Some(top_k) = self.top_k {
+            join.set_top_k(top_k)?;
+        }
+        join.set_deterministic_order(self.deterministic);
+        Ok(join)
+    }
+}
+
+impl SortMergeJoin {
+    /// Returns a builder of a sort-merge join.
+    pub fn builder() -> SortMergeJoinBuilder {
+        SortMergeJoinBuilder::default()
+    }
+}
+
+impl<L: OpIterator, R: OpIterator> SortMergeJoin<L, R> {
+    /// Sort-merge join constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Operation in join condition.
+    /// * `left_index` - Index of the left field in join condition.
+    /// * `right_index` - Index of the right field in join condition.
+    /// * `left_child` - Left child of join operator.
+    /// * `right_child` - Right child of join operator.
+    /// * `strategy` - Level 3 method.
+    ///
+    /// Fails with a ValidationError if a join field does not exist, the fields have
+    /// different types, or the operation is not Equals, GreaterThan or GreaterThanOrEq,
+    /// the ones the merge evaluates; a custom level 3 method evaluates the others itself.
+    pub fn new(
+        op: SimplePredicateOp,
+        left_index: usize,
+        right_index: usize,
+        left_child: L,
+        right_child: R,
+        strategy: MergeStrategy,
+    ) -> Result<Self, CrustyError> {
+        validate_join_fields(left_child.get_schema(), left_index, right_child.get_schema(), right_index)?;
+        if !matches!(strategy, MergeStrategy::Custom(_)) {
+            validate_merge_op(op)?;
+        }
+        Ok(Self {
+            predicate: JoinPredicate::new(op, left_index, right_index),
+            schema: left_child.get_schema().merge(right_child.get_schema()),
+            left_child,
+            right_child,
+            open: false,
+            strategy,
+            sort_config: SortConfig::default(),
+            sort_kernel: SortKernel::Network,
+            workers: default_workers(),
+            morsel_size: MORSEL_SIZE,
+            merge_cores: None,
+            splitter_method: SplitterMethod::Sample(SPLITTER_SAMPLE_SIZE),
+            partition_sizes: Vec::new(),
+            phases: Vec::new(),
+            l3_runs_l: Vec::new(),
+            l3_runs_r: Vec::new(),
+            min_r: None,
+            max_r: None,
+            joined: false,
+            cursor: (0, 0),
+            projection: None,
+            aliases: None,
+            top_k: None,
+            deterministic: false,
+            memory: MemoryTracker::unbounded(),
+            charged: 0,
+            spilled_r: Vec::new(),
+            progress: Default::default(),
+            sorted: false,
+            restored: None,
+            stream_chunk: None,
+            stream: None,
+            key_range: None,
+            buffer_pool: None,
+            monitor: ProgressMonitor::default(),
+            metrics: OpMetrics::default(),
+            #[cfg(feature = "visualize")]
+            events: None,
+        })
+    }
+
+    /// Set the level 3 method.
+    ///
+    /// Fails with a ValidationError, as new does, if the merge cannot evaluate the predicate
+    /// and the method is not a custom one.
+    ///
+    /// # Arguments
+    ///
+    /// * `strategy` - Level 3 method to use on the next open.
+    pub fn set_strategy(&mut self, strategy: MergeStrategy) -> Result<(), CrustyError> {
+        if !matches!(strategy, MergeStrategy::Custom(_)) {
+            validate_merge_op(self.predicate.op)?;
+        }
+        self.strategy = strategy;
+        Ok(())
+    }
+
+    /// Set the run sizes used by the sorting/merging levels.
+    ///
+    /// # Arguments
+    ///
+    /// * `sort_config` - Run-formation configuration to use on the next open.
+    pub fn set_sort_config(&mut self, sort_config: SortConfig) {
+        self.sort_config = sort_config;
+    }
+
+    /// Set the kernel forming the sorted runs.
+    ///
+    /// # Arguments
+    ///
+    /// * `kernel` - Sort kernel to use on the next open.
+    pub fn set_sort_kernel(&mut self, kernel: SortKernel) {
+        self.sort_kernel = kernel;
+    }
+
+    // helper method to return the run sizes and kernel of the sort, radix sorts the whole
+    // input as one run instead of the configured levels, on the configured cores
+    fn run_formation(&self) -> (SortConfig, SortKernel) {
+        match self.strategy {
+            MergeStrategy::Radix => (
+                SortConfig {
+                    sort_cores: self.sort_config.sort_cores,
+                    spill_compression: self.sort_config.spill_compression,
+                    ..SortConfig::new(usize::MAX, 0)
+                },
+                SortKernel::Radix,
+            ),
+            _ => (self.sort_config, self.sort_kernel),
+        }
+    }
+
+    /// Set the number of workers joining the level 3 morsels, independent of the number
+    /// of runs or partitions.
+    ///
+    /// # Arguments
+    ///
+    /// * `workers` - Number of worker threads, at least 1.
+    pub fn set_workers(&mut self, workers: usize) {
+        self.workers = workers.max(1);
+    }
+
+    /// Pin the level 3 merge and join workers to a range of cores, see also
+    /// SortConfig::set_sort_cores for the sort workers.
+    ///
+    /// # Arguments
+    ///
+    /// * `cores` - Cores of the level 3 workers.
+    pub fn set_merge_cores(&mut self, cores: CoreAffinity) {
+        self.merge_cores = Some(cores);
+    }
+
+    /// Returns the number of workers joining the level 3 morsels.
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Set the number of left tuples in a level 3 morsel.
+    ///
+    /// # Arguments
+    ///
+    /// * `morsel_size` - Number of tuples, at least 1.
+    pub fn set_morsel_size(&mut self, morsel_size: usize) {
+        self.morsel_size = morsel_size.max(1);
+    }
+
+    /// Return only the k output tuples smallest on an output column, in order on it.
+    ///
+    /// When the column is the output join key, the morsels are joined in key order and the
+    /// merge stops as soon as k tuples no larger than any key left to join are found, each
+    /// morsel copying only its k smallest joined rows; with spilled right runs or another
+    /// column, every tuple is joined and the k smallest kept.
+    /// Ties keep the tuples joined first, or the tuples smallest on all their fields with a
+    /// deterministic order.
+    ///
+    /// # Arguments
+    ///
+    /// * `top_k` - Number of tuples and output column, set after the projection.
+    ///
+    /// Fails with a ValidationError if the column is not in the output schema.
+    pub fn set_top_k(&mut self, top_k: TopK) -> Result<(), CrustyError> {
+        if top_k.column >= self.schema.size() {
+            return Err(CrustyError::ValidationError(format!("top-k column {} is not in the join output", top_k.column)));
+        }
+        self.top_k = Some(top_k);
+        Ok(())
+    }
+
+    /// Put the joined tuples in order on all their fields, left fields first, after the
+    /// output key.
+    ///
+    /// The joined tuples of a key otherwise come in the order the workers joined them,
+    /// which depends on the level 3 method and the thread scheduling. With a deterministic
+    /// order every method and number of workers returns the same sequence, e.g. for golden
+    /// files or to diff two methods, at the cost of sorting the output.
+    ///
+    /// # Arguments
+    ///
+    /// * `deterministic` - Whether to order the output of the next open.
+    pub fn set_deterministic_order(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Set how the m-way level 3 picks the key ranges of its partitions.
+    ///
+    /// # Arguments
+    ///
+    /// * `splitter_method` - Splitter method to use on the next open.
+    pub fn set_splitter_method(&mut self, splitter_method: SplitterMethod) {
+        self.splitter_method = splitter_method;
+    }
+
+    /// Keep only some fields of the joined tuples, the others are never copied.
+    ///
+    /// # Arguments
+    ///
+    /// * `columns` - Indices of the output fields among the left fields followed by the right fields.
+    pub fn set_projection(&mut self, columns: Vec<usize>) -> Result<(), CrustyError> {
+        self.schema = join_schema(self.left_child.get_schema(), self.right_child.get_schema(), self.aliases.as_ref(), Some(&columns))?;
+        self.projection = Some(columns);
+        Ok(())
+    }
+
+    /// Name the output attributes alias.name after the table they come from, so they can
+    /// be found with get_field_index even when both children share names.
+    ///
+    /// # Arguments
+    ///
+    /// * `left_alias` - Alias of the left child.
+    /// * `right_alias` - Alias of the right child.
+    pub fn set_aliases(&mut self, left_alias: &str, right_alias: &str) -> Result<(), CrustyError> {
+        let aliases = (left_alias.to_string(), right_alias.to_string());
+        self.schema = join_schema(
+            self.left_child.get_schema(),
+            self.right_child.get_schema(),
+            Some(&aliases),
+            self.projection.as_deref(),
+        )?;
+        self.aliases = Some(aliases);
+        Ok(())
+    }
+
+    /// Set the tracker charged with the runs, e.g. one shared by the whole plan.
+    ///
+    /// # Arguments
+    ///
+    /// * `memory` - Memory tracker, the right runs spill once it is over its limit.
+    pub fn set_memory_tracker(&mut self, memory: MemoryTracker) {
+        self.memory = memory;
+    }
+
+    /// Returns the tracker charged with the runs.
+    pub fn memory_tracker(&self) -> &MemoryTracker {
+        &self.memory
+    }
+
+    /// Read the spilled right runs back through a buffer pool, counting its page hits
+    /// and misses in the metrics.
+    ///
+    /// # Arguments
+    ///
+    /// * `pool` - Pool shared with the other readers of heap files.
+    pub fn set_buffer_pool(&mut self, pool: Arc<BufferPool>) {
+        self.buffer_pool = Some(pool);
+    }
+
+    /// Report the rows sorted and the left tuples joined, and stop with an ExecutionError
+    /// once cancelled or past the deadline.
+    ///
+    /// # Arguments
+    ///
+    /// * `monitor` - Progress monitor, the phases are "sort left", "sort right" and "join".
+    pub fn set_progress_monitor(&mut self, monitor: ProgressMonitor) {
+        self.monitor = monitor;
+    }
+
+    /// Stream the right child in chunks instead of sorting it whole.
+    ///
+    /// Open sorts the left child only, into a single run. Each call to next that runs out of
+    /// joined tuples reads the next chunk of the right child, sorts it unless the child
+    /// returns its tuples sorted on the key, and merge joins it with the left run, so the
+    /// join holds the left child and one right chunk instead of both children: put the
+    /// smaller child on the left. The output is sorted on the key only for a sorted right
+    /// child. A top-k limit, a deterministic order, a custom strategy or a restored
+    /// checkpoint sort both children as before.
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk` - Number of right tuples joined at a time, at least 1.
+    pub fn set_stream_right(&mut self, chunk: usize) {
+        self.stream_chunk = Some(chunk.max(1));
+    }
+
+    /// Assume at most one right tuple has each key, e.g. a primary key referenced by a left
+    /// foreign key, see cost::foreign_key_join.
+    ///
+    /// Equi-joins then join the right tuple of a key with its left tuples without buffering
+    /// the block of right duplicates. A right key found twice in a sorted right run fails the
+    /// join with a ValidationError: the runs of the m-way and radix level 3 hold every tuple of
+    /// their keys, while m-pass only checks each right run on its own.
+    ///
+    /// # Arguments
+    ///
+    /// * `unique_right` - Whether the right keys are unique.
+    pub fn set_unique_right(&mut self, unique_right: bool) {
+        self.predicate.set_unique_right(unique_right);
+    }
+
+    /// Assume at most one left tuple has each key, e.g. a primary key.
+    ///
+    /// Like set_unique_right with the sides swapped, the left keys being checked within each
+    /// morsel of a left run. With both sides unique the equi-join takes the 1:1 fast path,
+    /// joining a pair per key and advancing both cursors at once.
+    ///
+    /// # Arguments
+    ///
+    /// * `unique_left` - Whether the left keys are unique.
+    pub fn set_unique_left(&mut self, unique_left: bool) {
+        self.predicate.set_unique_left(unique_left);
+    }
+
+    /// Report the runs formed and merged and the tuples joined by the next opens to a sink,
+    /// see visualize.
+    ///
+    /// Only the level 3 join of both sorted children is reported: the tuples joined by a
+    /// streamed right child, a top-k limit, a custom strategy or from spilled m-pass runs
+    /// are not.
+    ///
+    /// # Arguments
+    ///
+    /// * `sink` - Receiver of the events.
+    #[cfg(feature = "visualize")]
+    pub fn set_event_sink(&mut self, sink: Arc<dyn EventSink>) {
+        self.events = Some(sink);
+    }
+
+    // helper method to report the level 3 runs each run of a side was merged into
+    #[cfg(feature = "visualize")]
+    fn emit_merged(&self, side: JoinSide, l3_runs: &[Vec<Tuple>], inputs: impl Fn(usize) -> usize) {
+        if let Some(sink) = &self.events {
+            for (run, tuples) in l3_runs.iter().enumerate() {
+                sink.emit(JoinEvent::RunsMerged { side, run, inputs: inputs(run), rows: tuples.len() });
+            }
+        }
+    }
+
+    // helper method to report every pair the level 3 runs join, by their positions in the runs
+    #[cfg(feature = "visualize")]
+    fn emit_joined(&self, runs_l: &[Vec<Tuple>], runs_r: &[Vec<Tuple>], partitioned: bool) {
+        let Some(sink) = &self.events else {
+            return;
+        };
+        // positions from the addresses of the tuples in their runs
+        let row = |run: &[Tuple], t: &Tuple| (t as *const Tuple as usize - run.as_ptr() as usize) / std::mem::size_of::<Tuple>();
+        for (left_run, run) in runs_l.iter().enumerate() {
+            for (right_run, right) in runs_r.iter().enumerate() {
+                // see partition_right_runs
+                let lower = right_run < left_run && self.predicate.op != SimplePredicateOp::Equals;
+                if partitioned && right_run != left_run && !lower {
+                    continue;
+                }
+                join_runs_ref(run, right, &self.predicate, |joined| {
+                    let (left_row, right_row) = (row(run, joined.left()), row(right, joined.right()));
+                    sink.emit(JoinEvent::TuplesJoined { left_run, left_row, right_run, right_row });
+                });
+            }
+        }
+    }
+
+    /// Returns the smallest and largest left key of the last open, if the right child was
+    /// asked to skip the tuples outside them.
+    ///
+    /// Only right tuples with a key in the range can join an equi-join, so once the left
+    /// child is sorted open asks the right child to skip the others before it sorts them,
+    /// see OpIterator::request_key_range. Scans such as CsvScan and HeapFileScan agree; the
+    /// range is None for other predicates, a collation other than binary or an empty left
+    /// child.
+    pub fn left_key_range(&self) -> Option<&RangeInclusive<Field>> {
+        self.key_range.as_ref()
+    }
+
+    // helper method to tell whether the next open streams the right child
+    fn streams_right(&self) -> bool {
+        self.stream_chunk.is_some()
+            && self.top_k.is_none()
+            && !self.deterministic
+            && self.restored.is_none()
+            && !matches!(self.strategy, MergeStrategy::Custom(_))
+    }
+
+    // Read the next chunk of the streamed right child and join it with the left run into
+    // l3_runs_l, returns false once the right child is exhausted
+    fn join_right_chunk(&mut self) -> Result<bool, CrustyError> {
+        let chunk_size = self.stream_chunk.unwrap_or(MORSEL_SIZE);
+        let right_index = self.predicate.right_index;
+        let tuple_bytes = self.right_child.get_schema().byte_size();
+        let stream = self.stream.as_mut().unwrap();
+        self.memory.release(stream.charged);
+        stream.charged = 0;
+        let mut chunk = Vec::with_capacity(chunk_size);
+        while chunk.len() < chunk_size {
+            match self.right_child.next()? {
+                Some(t) => chunk.push(t),
+                None => break,
+            }
+        }
+        self.l3_runs_l.clear();
+        self.cursor = (0, 0);
+        if chunk.is_empty() {
+            return Ok(false);
+        }
+        stream.charged = chunk.len() * tuple_bytes;
+        self.memory.reserve(stream.charged);
+        let before = stream.rows;
+        stream.rows += chunk.len();
+        self.metrics.rows_in += chunk.len();
+        self.monitor.advance("join", before, stream.rows)?;
+        if !stream.presorted {
+            let runs = sort_buffer(chunk, right_index, &self.sort_config, self.sort_kernel, false, tuple_bytes);
+            chunk = merge_runs(runs, right_index);
+        }
+        // an equi-join only joins the left tuples in the key range of the chunk
+        let left = &stream.left[..];
+        let left = match self.predicate.op {
+            SimplePredicateOp::Equals => {
+                let key = |t: &Tuple| t.get_field(self.predicate.left_index).cloned();
+                let (first, last) = (chunk[0].get_field(right_index).cloned(), chunk[chunk.len() - 1].get_field(right_index).cloned());
+                let start = left.partition_point(|t| key(t) < first);
+                let end = left.partition_point(|t| key(t) <= last);
+                &left[start..end]
+            }
+            _ => left,
+        };
+        let (joined, stats) = join_m_pass(left, std::slice::from_ref(&chunk), &self.predicate, self.projection.as_deref());
+        stats.add_to(&mut self.metrics)?;
+        self.l3_runs_l = vec![joined];
+        Ok(true)
+    }
+
+    /// Save the sorted runs of the children to a checkpoint directory, see checkpoint.
+    ///
+    /// After an open stopped before level 3, e.g. cancelled or past its deadline, the
+    /// checkpoint holds the runs the sort completed: the runs of a child sorted whole and
+    /// the runs the right child spilled so far. After an open, it holds the level 3 runs.
+    /// The runs must be saved before close, and fail with an ExecutionError once joined.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Checkpoint directory, created if needed.
+    pub fn save_state(&mut self, dir: impl AsRef<Path>) -> Result<SortState, CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        if self.joined {
+            return Err(CrustyError::ExecutionError(String::from("the sorted runs were joined already")));
+        }
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let compression = self.sort_config.spill_compression;
+        let left = SideState::new(self.predicate.left_index, self.left_child.get_schema().size(), 0, true);
+        let right = SideState::new(self.predicate.right_index, self.right_child.get_schema().size(), 0, true);
+        let state = if self.sorted {
+            let rows_l = self.l3_runs_l.iter().map(Vec::len).sum();
+            let rows_r = self.l3_runs_r.iter().map(Vec::len).sum::<usize>() + self.spilled_r.iter().map(SpillFile::len).sum::<usize>();
+            SortState {
+                left: save_side(dir, "left", SideState { rows: rows_l, ..left }, &self.l3_runs_l, &mut [], compression)?,
+                right: save_side(dir, "right", SideState { rows: rows_r, ..right }, &self.l3_runs_r, &mut self.spilled_r, compression)?,
+            }
+        } else {
+            let (l, r) = &mut self.progress;
+            SortState {
+                left: save_side(dir, "left", SideState { rows: l.covered, sorted: l.sorted, ..left }, &l.runs, &mut l.spilled, compression)?,
+                right: save_side(dir, "right", SideState { rows: r.covered, sorted: r.sorted, ..right }, &r.runs, &mut r.spilled, compression)?,
+            }
+        };
+        state.save(dir)?;
+        Ok(state)
+    }
+
+    /// Resume the next open from a checkpoint written by save_state.
+    ///
+    /// The next open reads the runs of the checkpoint back instead of sorting their rows,
+    /// and reads each child past the rows its runs hold, so the children must return the
+    /// same rows in the same order as when the checkpoint was saved; a child sorted whole is
+    /// not read. Fails with a ValidationError if the checkpoint was saved by a join on other
+    /// keys or tuple widths.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Checkpoint directory.
+    pub fn restore_state(&mut self, dir: impl AsRef<Path>) -> Result<SortState, CrustyError> {
+        let dir = dir.as_ref();
+        let state = SortState::load(dir)?;
+        let sides = [
+            ("left", &state.left, self.predicate.left_index, self.left_child.get_schema().size()),
+            ("right", &state.right, self.predicate.right_index, self.right_child.get_schema().size()),
+        ];
+        for (name, side, key, width) in sides {
+            if (side.key, side.width) != (key, width) {
+                return Err(CrustyError::ValidationError(format!(
+                    "the {} runs of the checkpoint are sorted on field {} of {}, not field {} of {}",
+                    name, side.key, side.width, key, width
+                )));
+            }
+        }
+        self.restored = Some((dir.to_path_buf(), state.clone()));
+        Ok(state)
+    }
+
+    /// Returns the left and right tuple counts of each m-way partition of the last open.
+    pub fn partition_sizes(&self) -> &[(usize, usize)] {
+        &self.partition_sizes
+    }
+
+    /// Returns the rows, bytes and time of the sort, partition, merge and join phases
+    /// since the last open, so their throughputs compare across widths and cardinalities.
+    ///
+    /// The m-way partition phase is part of the merge phase.
+    pub fn phase_stats(&self) -> &[PhaseStats] {
+        &self.phases
+    }
+
+    /// Join the left level 3 runs with the right level 3 runs into l3_runs_l.
+    ///
+    /// The left runs are split into morsels of morsel_size tuples, joined by the pool of
+    /// workers against the shared right side: the right run of their partition for m-way
+    /// and radix, every right run for m-pass.
+    fn join_level3(&mut self) -> Result<(), CrustyError> {
+        let predicate = self.predicate;
+        let mut runs_l = std::mem::take(&mut self.l3_runs_l);
+        let mut runs_r = std::mem::take(&mut self.l3_runs_r);
+        if let MergeStrategy::Custom(strategy) = &self.strategy {
+            self.l3_runs_l = strategy.join(runs_l, runs_r, &predicate);
+            // custom strategies join whole tuples
+            if let Some(columns) = &self.projection {
+                let empty = Tuple::new(Vec::new());
+                for run in self.l3_runs_l.iter_mut() {
+                    for t in run.iter_mut() {
+                        *t = merge_projected(t, &empty, Some(columns));
+                    }
+                }
+            }
+            self.order_output();
+            self.order_ties();
+            return Ok(());
+        }
+
+        // M-Way and radix join each left run with the right runs of the key ranges it can match only
+        let partitioned = matches!(self.strategy, MergeStrategy::MWay { .. } | MergeStrategy::Radix);
+        #[cfg(feature = "visualize")]
+        if self.top_k.is_none() {
+            self.emit_joined(&runs_l, &runs_r, partitioned);
+        }
+        let dictionary = self.encode_keys(&mut runs_l, &mut runs_r)?;
+        let morsels: Vec<(usize, &[Tuple])> = runs_l
+            .iter()
+            .enumerate()
+            .flat_map(|(run, tuples)| tuples.chunks(self.morsel_size).map(move |morsel| (run, morsel)))
+            .collect();
+        let projection = self.projection.as_deref();
+        // the output join key bounds every tuple a morsel joins by the first left key of the morsel
+        if let Some(top_k) = self.top_k.filter(|top_k| self.spilled_r.is_empty() && self.output_key() == Some(top_k.column)) {
+            let mut morsels = morsels;
+            morsels.sort_by(|a, b| a.1[0].get_field(predicate.left_index).cmp(&b.1[0].get_field(predicate.left_index)));
+            let mut heap = TopKHeap::new(top_k, self.deterministic);
+            let mut stats = MergeStats::default();
+            for batch in morsels.chunks(self.workers.max(1)) {
+                self.monitor.check_deadline()?;
+                let first = batch[0].1[0].get_field(predicate.left_index);
+                // with a deterministic order, later ties of the bound may still be smaller on other fields
+                let past = |bound| match self.deterministic {
+                    true => first > Some(bound),
+                    false => first >= Some(bound),
+                };
+                if top_k.k == 0 || heap.bound().is_some_and(past) {
+                    break;
+                }
+                let deterministic = self.deterministic;
+                let joined = parallel_map_on(batch.to_vec(), self.workers, self.merge_cores, |(run, morsel)| {
+                    let right_runs = match partitioned {
+                        true => partition_right_runs(&runs_r, run, &predicate),
+                        false => &runs_r[..],
+                    };
+                    join_m_pass_top_k(morsel, right_runs, &predicate, projection, top_k, deterministic)
+                });
+                for (tuples, count) in joined {
+                    heap.extend(tuples);
+                    stats += count;
+                }
+            }
+            stats.add_to(&mut self.metrics)?;
+            self.l3_runs_l = vec![heap.into_sorted_vec()];
+            return self.decode_keys(dictionary.as_ref());
+        }
+        // workers skip their morsels once cancelled or past the deadline, and report the left tuples joined so far
+        let monitor = &self.monitor;
+        let progress = AtomicUsize::new(0);
+        let join_morsel = |morsel: &[Tuple], right_runs: &[Vec<Tuple>]| {
+            if monitor.check_deadline().is_err() {
+                return (Vec::new(), MergeStats::default());
+            }
+            let joined = join_m_pass(morsel, right_runs, &predicate, projection);
+            let rows = progress.fetch_add(morsel.len(), AtomicOrdering::SeqCst) + morsel.len();
+            let _ = monitor.advance("join", rows - morsel.len(), rows);
+            joined
+        };
+        let joined = parallel_map_on(morsels.clone(), self.workers, self.merge_cores, |(run, morsel)| {
+            let right_runs = match partitioned {
+                true => partition_right_runs(&runs_r, run, &predicate),
+                false => &runs_r[..],
+            };
+            (run, join_morsel(morsel, right_runs))
+        });
+        self.monitor.check_deadline()?;
+        let mut joined_left_runs = vec![Vec::new(); runs_l.len()];
+        let mut stats = MergeStats::default();
+        for (run, (tuples, count)) in joined {
+            joined_left_runs[run].extend(tuples);
+            stats += count;
+        }
+
+        // join every left morsel with each spilled right run in turn
+        let right_bytes = self.right_child.get_schema().byte_size();
+        for file in std::mem::take(&mut self.spilled_r) {
+            let run_bytes = file.len() * right_bytes;
+            let right_runs = vec![read_run(file, &self.memory, self.buffer_pool.as_ref(), &mut self.metrics, right_bytes)?];
+            let joined = parallel_map_on(morsels.clone(), self.workers, self.merge_cores, |(run, morsel)| {
+                (run, join_morsel(morsel, &right_runs))
+            });
+            self.monitor.check_deadline()?;
+            for (run, (tuples, count)) in joined {
+                joined_left_runs[run].extend(tuples);
+                stats += count;
+            }
+            self.memory.release(run_bytes);
+        }
+        stats.add_to(&mut self.metrics)?;
+        self.monitor.report("join", progress.into_inner());
+        self.l3_runs_l = joined_left_runs;
+        self.order_output();
+        self.order_ties();
+        if let Some(top_k) = self.top_k {
+            let mut heap = TopKHeap::new(top_k, self.deterministic);
+            heap.extend(std::mem::take(&mut self.l3_runs_l).into_iter().flatten());
+            self.l3_runs_l = vec![heap.into_sorted_vec()];
+        }
+        self.decode_keys(dictionary.as_ref())
+    }
+
+    // helper method to dictionary encode string join keys in the m-way workers, each with a
+    // local dictionary of its partition, so the merge compares integer codes; the merged
+    // codes follow the string order, so greater than partitions still join
+    fn encode_keys(&self, runs_l: &mut Vec<Vec<Tuple>>, runs_r: &mut Vec<Vec<Tuple>>) -> Result<Option<Dictionary>, CrustyError> {
+        let (left_index, right_index) = (self.predicate.left_index, self.predicate.right_index);
+        let is_string = |schema: &TableSchema, index| schema.get_attribute(index).map(Attribute::dtype) == Some(&DataType::String);
+        let encoded = matches!(self.strategy, MergeStrategy::MWay { .. })
+            && self.predicate.collation() == Collation::Binary
+            && runs_l.len() == runs_r.len()
+            && is_string(self.left_child.get_schema(), left_index)
+            && is_string(self.right_child.get_schema(), right_index);
+        if !encoded {
+            return Ok(None);
+        }
+        let (dictionary, left, right) = encode_join_partitions(std::mem::take(runs_l), left_index, std::mem::take(runs_r), right_index)?;
+        *runs_l = left;
+        *runs_r = right;
+        Ok(Some(dictionary))
+    }
+
+    // helper method to decode the join keys kept in the joined runs
+    fn decode_keys(&mut self, dictionary: Option<&Dictionary>) -> Result<(), CrustyError> {
+        let dictionary = match dictionary {
+            Some(dictionary) => dictionary,
+            None => return Ok(()),
+        };
+        let left_key = self.predicate.left_index;
+        let right_key = self.left_child.get_schema().size() + self.predicate.right_index;
+        let columns: Vec<usize> = match &self.projection {
+            None => vec![left_key, right_key],
+            Some(columns) => columns.iter().enumerate().filter(|(_, c)| **c == left_key || **c == right_key).map(|(i, _)| i).collect(),
+        };
+        for run in self.l3_runs_l.iter_mut() {
+            for index in columns.iter() {
+                decode_tuples(run, *index, dictionary)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the index of the left join key in the output tuples, if it is kept.
+    ///
+    /// The equal right key stands in for a projected away left key of an equi-join.
+    pub fn output_key(&self) -> Option<usize> {
+        let left_index = self.predicate.left_index;
+        match &self.projection {
+            None => Some(left_index),
+            Some(columns) => columns.iter().position(|c| *c == left_index).or_else(|| {
+                let right_key = self.left_child.get_schema().size() + self.predicate.right_index;
+                match self.predicate.op {
+                    SimplePredicateOp::Equals => columns.iter().position(|c| *c == right_key),
+                    _ => None,
+                }
+            }),
+        }
+    }
+
+    // helper method to put the joined runs in order on the output key, so streaming them in
+    // turn returns sorted tuples
+    fn order_output(&mut self) {
+        let key = match self.output_key() {
+            Some(key) => key,
+            None => return,
+        };
+        let sorted = |run: &[Tuple]| run.windows(2).all(|w| w[0].get_field(key) <= w[1].get_field(key));
+        // m-way runs are already sorted, m-pass runs are one sorted piece per right run
+        for run in self.l3_runs_l.iter_mut() {
+            if !sorted(run) {
+                run.sort_by(|a, b| a.get_field(key).cmp(&b.get_field(key)));
+            }
+        }
+        // m-way partitions are key ranges in order, other runs overlap and are merged
+        let runs: Vec<&Vec<Tuple>> = self.l3_runs_l.iter().filter(|run| !run.is_empty()).collect();
+        let in_order = runs.windows(2).all(|w| w[0].last().unwrap().get_field(key) <= w[1][0].get_field(key));
+        if !in_order {
+            let runs = std::mem::take(&mut self.l3_runs_l);
+            self.l3_runs_l = vec![merge_runs(runs, key)];
+        }
+    }
+
+    // helper method to sort the joined tuples on the output key then all their fields, when
+    // the order is deterministic
+    fn order_ties(&mut self) {
+        if !self.deterministic {
+            return;
+        }
+        let key = self.output_key();
+        // the runs are in order on the key but a key may span two of them
+        let mut tuples: Vec<Tuple> = std::mem::take(&mut self.l3_runs_l).into_iter().flatten().collect();
+        tuples.sort_by(|a, b| match key {
+            Some(key) => a.get_field(key).cmp(&b.get_field(key)).then_with(|| a.field_vals.cmp(&b.field_vals)),
+            None => a.field_vals.cmp(&b.field_vals),
+        });
+        self.l3_runs_l = vec![tuples];
+    }
+}
+
+/// Number of m-way level 3 partitions (4 physical thread - 1).
+pub const M_WAY_PARTITIONS: usize = 3;
+
+/// Default number of keys sampled to pick the m-way splitters.
+pub const SPLITTER_SAMPLE_SIZE: usize = 1024;
+
+/// Seed of the splitter sample, fixed so the same input gets the same partitions.
+pub const SPLITTER_SEED: u64 = 0;
+
+/// How the m-way level 3 picks the key ranges of its partitions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitterMethod {
+    /// Split the range between the right minimum and maximum into equal parts.
+    EqualRange,
+    /// Equi-depth splitters picked from a random sample of this many keys of both children,
+    /// so partitions get about the same number of tuples even on skewed keys.
+    Sample(usize),
+}
+
+/// Number of bytes after the common prefix of the minimum and maximum string keys that
+/// the range splitters of strings are picked from.
+pub const STRING_SPLITTER_BYTES: usize = 4;
+
+// helper method to split the key range between min and max into equal parts
+fn range_splitters(min: &Field, max: &Field, partitions: usize) -> Vec<Field> {
+    match (min, max) {
+        (Field::IntField(min), Field::IntField(max)) => {
+            let (min, max) = (*min as i64, *max as i64);
+            (1..partitions as i64)
+                .map(|i| Field::IntField((min + (max - min) * i / partitions as i64) as i32))
+                .collect()
+        }
+        (Field::StringField(min), Field::StringField(max)) => string_splitters(min, max, partitions),
+        // keys of different types are not range partitioned
+        _ => Vec::new(),
+    }
+}
+
+// helper method to split the range between two strings into equal parts, reading the
+// bytes after their common prefix as a base 256 number
+fn string_splitters(min: &str, max: &str, partitions: usize) -> Vec<Field> {
+    let (min, max) = (min.as_bytes(), max.as_bytes());
+    let common = min.iter().zip(max).take_while(|(a, b)| a == b).count();
+    let number = |s: &[u8]| {
+        (0..STRING_SPLITTER_BYTES).fold(0u64, |n, i| n * 256 + *s.get(common + i).unwrap_or(&0) as u64)
+    };
+    let (low, high) = (number(min), number(max));
+    let mut splitters: Vec<Field> = (1..partitions as u64)
+        .map(|i| {
+            let n = low + (high - low) * i / partitions as u64;
+            let mut bytes = max[..common].to_vec();
+            bytes.extend_from_slice(&n.to_be_bytes()[8 - STRING_SPLITTER_BYTES..]);
+            while bytes.len() > common && bytes.last() == Some(&0) {
+                bytes.pop();
+            }
+            // a splitter ending inside a multi-byte character is cut before it
+            let valid = match std::str::from_utf8(&bytes) {
+                Ok(_) => bytes.len(),
+                Err(e) => e.valid_up_to(),
+            };
+            Field::StringField(String::from_utf8_lossy(&bytes[..valid]).into_owned())
+        })
+        .collect();
+    // cut splitters may be out of order, partitions only need them sorted
+    splitters.sort();
+    splitters
+}
+
+// helper method to pick equi-depth splitters from a random sample of the keys
+fn sample_splitters(keys: &[&Field], partitions: usize, sample_size: usize) -> Vec<Field> {
+    if keys.is_empty() {
+        return Vec::new();
+    }
+    // small inputs are not sampled, their splitters are exact
+    let mut sample: Vec<&Field> = if keys.len() <= sample_size {
+        keys.to_vec()
+    } else {
+        let mut rng = seeded_rng(SPLITTER_SEED);
+        (0..sample_size).map(|_| keys[rng.gen_range(0..keys.len())]).collect()
+    };
+    sample.sort();
+    (1..partitions)
+        .map(|i| sample[(i * sample.len() / partitions).saturating_sub(1)].clone())
+        .collect()
+}
+
+// helper method to compute the normalized key of the join field of every tuple
+fn run_keys(run: &[Tuple], index: usize) -> Vec<u64> {
+    run.iter().map(|t| t.get_field(index).map_or(0, normalize_key)).collect()
+}
+
+// helper method to sort row ids on their normalized keys, Field::cmp only runs on equal keys
+fn sort_rows(run: &[Tuple], keys: &[u64], index: usize) -> Vec<usize> {
+    let mut rows: Vec<usize> = (0..run.len()).collect();
+    rows.sort_by(|a, b| cmp_normalized((keys[*a], run[*a].get_field(index)), (keys[*b], run[*b].get_field(index))));
+    rows
+}
+
+// helper method to move the tuples into the order of the row ids in place
+//
+// Row i takes the tuple at rows[i]; each cycle of the permutation is followed with swaps,
+// so no tuple is cloned and no second run is allocated.
+fn permute_in_place(run: &mut [Tuple], rows: &[usize]) {
+    let mut done = vec![false; run.len()];
+    for start in 0..run.len() {
+        let mut i = start;
+        while !done[i] {
+            done[i] = true;
+            if rows[i] != start {
+                run.swap(i, rows[i]);
+            }
+            i = rows[i];
+        }
+    }
+}
+
+// helper method to sort level 1 run
+fn sort_run_l1(mut run: Vec<Tuple>, index: usize) -> Vec<Tuple> {
+    // the sorting networks cover full runs of 4 or 8 exact keys and only permute row ids,
+    // string keys and the tail run are sorted directly
+    let keys = run_keys(&run, index);
+    let rows = match sort_keys(&keys) {
+        Some(rows) if keys.iter().all(|key| is_exact(*key)) => rows,
+        _ => sort_rows(&run, &keys, index),
+    };
+    permute_in_place(&mut run, &rows);
+    run
+}
+// helper method to sort each run in runs
+fn sort_runs(runs: Vec<Vec<Tuple>>, index: usize, cores: Option<CoreAffinity>) -> Vec<Vec<Tuple>> {
+    parallel_map_on(runs, default_workers(), cores, |run| sort_run_l1(run, index))
+}
+
+// Head of a run in the k-way merge, ordered so that the heap pops the smallest key first
+struct RunHead {
+    key: u64,
+    tuple: Tuple,
+    run: usize,
+    index: usize,
+}
+
+impl RunHead {
+    // helper method to create a head, normalizing its key once
+    fn new(tuple: Tuple, run: usize, index: usize) -> Self {
+        let key = tuple.get_field(index).map_or(0, normalize_key);
+        Self { key, tuple, run, index }
+    }
+}
+
+impl Ord for RunHead {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // reversed for the max-heap, ties go to the earlier run to keep the merge stable
+        cmp_normalized(
+            (other.key, other.tuple.get_field(other.index)),
+            (self.key, self.tuple.get_field(self.index)),
+        )
+        .then_with(|| other.run.cmp(&self.run))
+    }
+}
+
+impl PartialOrd for RunHead {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RunHead {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RunHead {}
+
+/// Limit of a top-k join, see SortMergeJoin::set_top_k.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TopK {
+    /// Number of tuples returned.
+    pub k: usize,
+    /// Index of the output column the tuples are ordered on.
+    pub column: usize,
+}
+
+impl TopK {
+    /// Create a top-k limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `k` - Number of tuples returned.
+    /// * `column` - Index of the output column the tuples are ordered on.
+    pub fn new(k: usize, column: usize) -> Self {
+        Self { k, column }
+    }
+}
+
+// Joined tuple in the top-k heap, ordered on its column, then on all its fields if by_values,
+// then on the order it was pushed in
+struct Ranked {
+    tuple: Tuple,
+    column: usize,
+    by_values: bool,
+    seq: usize,
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.tuple
+            .get_field(self.column)
+            .cmp(&other.tuple.get_field(other.column))
+            .then_with(|| match self.by_values {
+                true => self.tuple.field_vals.cmp(&other.tuple.field_vals),
+                false => Ordering::Equal,
+            })
+            .then_with(|| self.seq.cmp(&other.seq))
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+// Max-heap of the k smallest tuples pushed so far
+struct TopKHeap {
+    top_k: TopK,
+    by_values: bool,
+    heap: BinaryHeap<Ranked>,
+    pushed: usize,
+}
+
+impl TopKHeap {
+    fn new(top_k: TopK, by_values: bool) -> Self {
+        Self { top_k, by_values, heap: BinaryHeap::with_capacity(top_k.k), pushed: 0 }
+    }
+
+    // helper method to push tuples, dropping the largest once there are more than k
+    fn extend(&mut self, tuples: impl IntoIterator<Item = Tuple>) {
+        for tuple in tuples {
+            self.heap.push(Ranked { tuple, column: self.top_k.column, by_values: self.by_values, seq: self.pushed });
+            self.pushed += 1;
+            if self.heap.len() > self.top_k.k {
+                self.heap.pop();
+            }
+        }
+    }
+
+    // helper method to find the largest kept value once k tuples are kept, no later tuple
+    // above it can enter the heap, nor at it unless ordered by values
+    fn bound(&self) -> Option<&Field> {
+        if self.heap.len() < self.top_k.k {
+            return None;
+        }
+        self.heap.peek().and_then(|top| top.tuple.get_field(top.column))
+    }
+
+    fn into_sorted_vec(self) -> Vec<Tuple> {
+        self.heap.into_sorted_vec().into_iter().map(|ranked| ranked.tuple).collect()
+    }
+}
+
+// helper method to merge sorted runs into one sorted run, with a heap over the run cursors
+fn merge_runs(runs: Vec<Vec<Tuple>>, index: usize) -> Vec<Tuple> {
+    let mut res = Vec::with_capacity(runs.iter().map(|run| run.len()).sum());
+    let mut cursors: Vec<_> = runs.into_iter().map(|run| run.into_iter()).collect();
+    let mut heap = BinaryHeap::with_capacity(cursors.len());
+    for (run, cursor) in cursors.iter_mut().enumerate() {
+        if let Some(tuple) = cursor.next() {
+            heap.push(RunHead::new(tuple, run, index));
+        }
+    }
+    // take the smallest head and replace it with the next tuple of its run
+    while let Some(head) = heap.pop() {
+        let run = head.run;
+        res.push(head.tuple);
+        if let Some(tuple) = cursors[run].next() {
+            heap.push(RunHead::new(tuple, run, index));
+        }
+    }
+    res
+}
+
+// helper method to merge each pair of runs into one run of twice the size
+fn merge_pairs(runs: Vec<Vec<Tuple>>, index: usize, cores: Option<CoreAffinity>) -> Vec<Vec<Tuple>> {
+    let mut pairs = Vec::new();
+    let mut runs = runs.into_iter();
+    while let Some(run) = runs.next() {
+        // an odd run out is carried to the next level as is
+        let mut pair = vec![run];
+        pair.extend(runs.next());
+        pairs.push(pair);
+    }
+    parallel_map_on(pairs, default_workers(), cores, |pair| merge_runs(pair, index))
+}
+
+// helper method to merge every fan_in consecutive runs into one run
+fn merge_groups(runs: Vec<Vec<Tuple>>, fan_in: usize, index: usize, cores: Option<CoreAffinity>) -> Vec<Vec<Tuple>> {
+    if fan_in <= 1 {
+        return runs;
+    }
+    let mut groups = Vec::new();
+    let mut runs = runs.into_iter().peekable();
+    while runs.peek().is_some() {
+        groups.push(runs.by_ref().take(fan_in).collect::<Vec<_>>());
+    }
+    parallel_map_on(groups, default_workers(), cores, |group| merge_runs(group, index))
+}
+
+/// Right child of a sort-merge join streamed in chunks, see SortMergeJoin::set_stream_right.
+struct RightStream {
+    /// Left tuples in a single run sorted on the key.
+    left: Vec<Tuple>,
+    /// Whether the right child returns its tuples sorted on the key.
+    presorted: bool,
+    /// Number of right tuples read.
+    rows: usize,
+    /// Bytes of the right chunk charged to the tracker.
+    charged: usize,
+}
+
+/// Sorted runs of a child and the memory they hold.
+#[derive(Default)]
+struct SortedChild {
+    /// Runs kept in memory.
+    runs: Vec<Vec<Tuple>>,
+    /// Bytes of the runs in memory charged to the tracker.
+    charged: usize,
+    /// Runs spilled to disk, each sorted.
+    spilled: Vec<SpillFile>,
+    /// Bytes of the pages of the spilled runs.
+    spill_bytes: usize,
+    /// Number of tuples read from the child.
+    rows: usize,
+    /// Number of tuples of the child in the runs, the first ones read.
+    covered: usize,
+    /// Whether the runs hold every tuple of the child.
+    sorted: bool,
+}
+
+// helper method to sort buffered tuples of a child into runs of the configured size
+fn sort_buffer(
+    tuples: Vec<Tuple>,
+    index: usize,
+    config: &SortConfig,
+    kernel: SortKernel,
+    presorted: bool,
+    tuple_bytes: usize,
+) -> Vec<Vec<Tuple>> {
+    if tuples.is_empty() {
+        Vec::new()
+    } else if presorted {
+        // a child producing sorted output already is a single sorted run
+        vec![tuples]
+    } else {
+        form_runs(kernel, tuples, index, config, tuple_bytes)
+    }
+}
+
+// helper method to read the rest of a child into sorted runs of the configured size, added to
+// the runs of sorted
+//
+// Every tuple is charged to the memory tracker and ticks the monitor under phase. With
+// spill set, the tuples buffered when a charge does not fit in the limit are sorted into a
+// single run written to disk, compressed as configured. The runs written to disk stay in
+// sorted if the monitor stops the sort.
+#[allow(clippy::too_many_arguments)]
+fn sort_child(
+    child: &mut impl OpIterator,
+    index: usize,
+    config: &SortConfig,
+    kernel: SortKernel,
+    presorted: bool,
+    memory: &MemoryTracker,
+    spill: bool,
+    monitor: &ProgressMonitor,
+    phase: &'static str,
+    sorted: &mut SortedChild,
+) -> Result<(), CrustyError> {
+    let tuple_bytes = child.get_schema().byte_size();
+    let mut tuples = Vec::new();
+    while let Some(t) = child.next()? {
+        if !memory.try_reserve(tuple_bytes) {
+            if spill && !tuples.is_empty() {
+                let runs = sort_buffer(std::mem::take(&mut tuples), index, config, kernel, presorted, tuple_bytes);
+                let mut file = SpillFile::create()?;
+                file.set_compression(config.spill_compression);
+                for t in merge_runs(runs, index) {
+                    file.write(&t)?;
+                }
+                file.flush()?;
+                sorted.spill_bytes += file.disk_bytes();
+                spill_event("SortMergeJoin", file.len());
+                sorted.spilled.push(file);
+                sorted.covered = sorted.rows;
+                memory.release(sorted.charged);
+                sorted.charged = 0;
+            }
+            memory.reserve(tuple_bytes);
+        }
+        sorted.charged += tuple_bytes;
+        sorted.rows += 1;
+        tuples.push(t);
+        monitor.tick(phase, sorted.rows)?;
+    }
+    monitor.report(phase, sorted.rows);
+    sorted.runs.extend(sort_buffer(tuples, index, config, kernel, presorted, tuple_bytes));
+    sorted.covered = sorted.rows;
+    sorted.sorted = true;
+    Ok(())
+}
+
+// helper method to read a spilled run back, charging it to the tracker and counting its pages
+fn read_run(
+    file: SpillFile,
+    memory: &MemoryTracker,
+    pool: Option<&Arc<BufferPool>>,
+    metrics: &mut OpMetrics,
+    tuple_bytes: usize,
+) -> Result<Vec<Tuple>, CrustyError> {
+    memory.reserve(file.len() * tuple_bytes);
+    let mut reader = match pool {
+        None => file.into_reader()?,
+        Some(pool) => file.into_pooled_reader(pool.clone())?,
+    };
+    let mut run = Vec::new();
+    while let Some(t) = reader.next_tuple()? {
+        run.push(t);
+    }
+    metrics.page_hits += reader.stats().hits;
+    metrics.page_misses += reader.stats().misses;
+    Ok(run)
+}
+
+// helper method to save the sorted runs of a side to a checkpoint directory
+fn save_side(
+    dir: &Path,
+    name: &str,
+    mut side: SideState,
+    runs: &[Vec<Tuple>],
+    spilled: &mut [SpillFile],
+    compression: SpillCompression,
+) -> Result<SideState, CrustyError> {
+    for run in runs {
+        side.add_run(dir, name, run, compression)?;
+    }
+    for file in spilled {
+        side.add_spilled(dir, name, file)?;
+    }
+    Ok(side)
+}
+
+// helper method to find the smallest and largest key of sorted runs, None if they are empty
+fn key_range(runs: &[Vec<Tuple>], index: usize) -> Option<RangeInclusive<Field>> {
+    let min = runs.iter().filter_map(|run| run.first()?.get_field(index)).min()?;
+    let max = runs.iter().filter_map(|run| run.last()?.get_field(index)).max()?;
+    Some(min.clone()..=max.clone())
+}
+
+// helper method to take up a side of a checkpoint, reading its runs back into memory or
+// keeping them on disk as spilled runs, then reading the child past the rows they hold
+fn restore_side(
+    child: &mut impl OpIterator,
+    dir: &Path,
+    side: &SideState,
+    tuple_bytes: usize,
+    memory: &MemoryTracker,
+    metrics: &mut OpMetrics,
+    in_memory: bool,
+) -> Result<SortedChild, CrustyError> {
+    let mut sorted = SortedChild {
+        rows: side.rows,
+        covered: side.rows,
+        sorted: side.sorted,
+        ..SortedChild::default()
+    };
+    for file in side.open_runs(dir)? {
+        if in_memory {
+            sorted.charged += file.len() * tuple_bytes;
+            sorted.runs.push(read_run(file, memory, None, metrics, tuple_bytes)?);
+        } else {
+            sorted.spilled.push(file);
+        }
+    }
+    if !side.sorted {
+        for _ in 0..side.rows {
+            if child.next()?.is_none() {
+                return Err(CrustyError::ValidationError(format!(
+                    "the checkpoint holds {} rows, more than the child returns",
+                    side.rows
+                )));
+            }
+        }
+    }
+    Ok(sorted)
+}
+
+/// Kernel used to form the sorted runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKernel {
+    /// Sorting network on level 1 runs, then pairwise merging levels.
+    Network,
+    /// std sort on runs of the final size.
+    Std,
+    /// std sort of the row ids of runs of the final size on the key column only, then
+    /// the tuples are moved into place, see columnar::ColumnarBatch. String keys are
+    /// sorted on dictionary codes.
+    Columnar,
+    /// LSD radix sort of runs of the final size on their normalized keys, string keys
+    /// fall back to the std sort.
+    Radix,
+}
+
+/// Sort tuples into runs with the given kernel.
+///
+/// This is the run-generation phase of SortMergeJoin on its own, so the kernels can
+/// be benchmarked without the join.
+///
+/// # Arguments
+///
+/// * `kernel` - Kernel used to sort the runs.
+/// * `tuples` - Tuples to sort.
+/// * `index` - Index of the field to sort on.
+/// * `config` - Run sizes of the sorting/merging levels.
+/// * `tuple_bytes` - Estimated size of a tuple in bytes.
+pub fn form_runs(
+    kernel: SortKernel,
+    tuples: Vec<Tuple>,
+    index: usize,
+    config: &SortConfig,
+    tuple_bytes: usize,
+) -> Vec<Vec<Tuple>> {
+    let levels = config.merge_levels(tuple_bytes);
+    let final_run_size = config.final_run_size(tuple_bytes);
+    let cores = config.sort_cores;
+    match kernel {
+        SortKernel::Network => {
+            // split into level 1 runs, each run contains run_size Tuples in order to fit into the register
+            let runs = split_runs(tuples, config.run_size);
+
+            // parallel sorting level 1 runs
+            let mut runs = sort_runs(runs, index, cores);
+
+            // merge every fan-in runs into one at each level, pairwise by default
+            for _ in 0..levels {
+                runs = match config.merge_fan_in.max(2) {
+                    2 => merge_pairs(runs, index, cores),
+                    fan_in => merge_groups(runs, fan_in, index, cores),
+                };
+            }
+            runs
+        }
+        SortKernel::Std => parallel_map_on(split_runs(tuples, final_run_size), default_workers(), cores, |mut run| {
+            run.sort_by(|a, b| a.get_field(index).cmp(&b.get_field(index)));
+            run
+        }),
+        SortKernel::Columnar => {
+            let mut batch = ColumnarBatch::from_tuples(tuples);
+            // string keys are sorted on the codes of a sorted dictionary, decoded as the rows
+            // are taken; a column mixing types is left as is
+            if let Some(Field::StringField(_)) = batch.column(index).and_then(|keys| keys.first()) {
+                let _ = batch.encode_column(index, true);
+            }
+            let runs = match batch.key_column(index) {
+                Some(keys) => sort_row_ids(&keys, final_run_size, cores),
+                None => return Vec::new(),
+            };
+            runs.iter().map(|run| batch.take_rows(run)).collect()
+        }
+        SortKernel::Radix => {
+            // a run of the whole input is shared between the sort cores, one run per core
+            let (run_size, workers) = match cores {
+                Some(cores) if final_run_size == usize::MAX => (tuples.len().div_ceil(cores.count).max(1), cores.count),
+                _ => (final_run_size, default_workers()),
+            };
+            parallel_map_on(split_runs(tuples, run_size), workers, cores, |run| radix_sort_run(run, index))
+        }
+    }
+}
+
+/// Sort tuples on a field with the given kernel, forming the runs then merging them.
+///
+/// # Arguments
+///
+/// * `kernel` - Kernel used to sort the runs.
+/// * `tuples` - Tuples to sort.
+/// * `index` - Index of the field to sort on.
+/// * `config` - Run sizes of the sorting/merging levels.
+/// * `tuple_bytes` - Estimated size of a tuple in bytes.
+pub fn sort_tuples(
+    kernel: SortKernel,
+    tuples: Vec<Tuple>,
+    index: usize,
+    config: &SortConfig,
+    tuple_bytes: usize,
+) -> Vec<Tuple> {
+    merge_runs(form_runs(kernel, tuples, index, config, tuple_bytes), index)
+}
+
+// helper method to radix sort a run on its normalized keys
+fn radix_sort_run(mut run: Vec<Tuple>, index: usize) -> Vec<Tuple> {
+    let keys = run_keys(&run, index);
+    let rows = if keys.iter().all(|key| is_exact(*key)) {
+        radix_sort_row_ids(&keys)
+    } else {
+        sort_rows(&run, &keys, index)
+    };
+    permute_in_place(&mut run, &rows);
+    run
+}
+
+// helper method to split tuples into runs of run_size tuples
+fn split_runs(tuples: Vec<Tuple>, run_size: usize) -> Vec<Vec<Tuple>> {
+    let mut runs = Vec::new();
+    let mut temp = Vec::new();
+    for t in tuples {
+        if temp.len() == run_size {
+            runs.push(temp);
+            temp = Vec::new();
+        }
+        temp.push(t);
+    }
+    if !temp.is_empty() {
+        runs.push(temp);
+    }
+    runs
+}
+
+// sort-merge runs by multi-way method
+fn sort_m_way_l3(runs: Vec<Vec<Tuple>>, splitters: &[Field], index: usize, cores: Option<CoreAffinity>) -> Vec<Vec<Tuple>> {
+    // redistribute tuples based on the range partition, each run is split into one
+    // sorted sub-run per partition; partition i holds the keys up to splitter i
+    let mut partitions = vec![Vec::new(); splitters.len() + 1];
+    for run in runs {
+        let mut sub_runs = vec![Vec::new(); splitters.len() + 1];
+        for t in run {
+            let field = t.get_field(index).unwrap();
+            sub_runs[splitters.partition_point(|s| s < field)].push(t);
+        }
+        for (partition, sub_run) in partitions.iter_mut().zip(sub_runs) {
+            if !sub_run.is_empty() {
+                partition.push(sub_run);
+            }
+        }
+    }
+
+    // multi-way merge the sub-runs of each partition in parallel
+    parallel_map_on(partitions, default_workers(), cores, |partition| merge_runs(partition, index))
+}
+
+/// A run-length block of equal join keys in a sorted run: the key, the number of tuples
+/// holding it and the position of the first of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct KeyBlock<'a> {
+    key: &'a Field,
+    count: usize,
+    first_row: usize,
+}
+
+// helper method to find the block of the key at a position of a sorted run, galloping to
+// its end so a block of n tuples takes about 2 log n comparisons, also returns them
+fn key_block<'a>(run: &'a [Tuple], index: usize, first_row: usize) -> (KeyBlock<'a>, usize) {
+    let key = run[first_row].get_field(index).unwrap();
+    let same = |row: usize| row < run.len() && run[row].get_field(index) == Some(key);
+    let mut comparisons = 0;
+    // double the step while the key repeats, then bisect the last step
+    let (mut low, mut step) = (first_row, 1);
+    loop {
+        comparisons += 1;
+        if !same(low + step) {
+            break;
+        }
+        low += step;
+        step *= 2;
+    }
+    let mut high = (low + step).min(run.len());
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        comparisons += 1;
+        match same(mid) {
+            true => low = mid,
+            false => high = mid,
+        }
+    }
+    let block = KeyBlock {
+        key,
+        count: high - first_row,
+        first_row,
+    };
+    (block, comparisons)
+}
+
+/// Key comparisons and duplicate key blocks of a merge join, added to OpMetrics, and the
+/// keys found twice on a side assumed unique.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct MergeStats {
+    comparisons: usize,
+    dup_blocks: usize,
+    dup_rows: usize,
+    unique_violations: usize,
+}
+
+impl MergeStats {
+    // helper method to add the counts to the metrics of an operator, fails with a
+    // ValidationError if a key assumed unique was found twice
+    fn add_to(self, metrics: &mut OpMetrics) -> Result<(), CrustyError> {
+        metrics.comparisons += self.comparisons;
+        metrics.dup_blocks += self.dup_blocks;
+        metrics.dup_rows += self.dup_rows;
+        if self.unique_violations > 0 {
+            return Err(CrustyError::ValidationError(format!(
+                "{} joined keys assumed unique were found twice",
+                self.unique_violations
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl std::ops::AddAssign for MergeStats {
+    fn add_assign(&mut self, other: Self) {
+        self.comparisons += other.comparisons;
+        self.dup_blocks += other.dup_blocks;
+        self.dup_rows += other.dup_rows;
+        self.unique_violations += other.unique_violations;
+    }
+}
+
+// helper method to merge join two sorted runs on equal keys
+//
+// Both cursors advance past smaller keys; on a match each run is cut into the run-length
+// block of the key, found by galloping rather than comparing every tuple, and the cross
+// product of the two blocks is produced, so every pair of a many-to-many match is produced
+// exactly once, as a view of the two tuples. When a side has unique keys its tuple of a
+// key is joined with each tuple of the key on the other side without looking for blocks,
+// a 1:1 join advancing both cursors at once, and a next tuple with the same key on a
+// unique side is counted as a violation. Returns the key comparisons, the blocks with
+// duplicates and the violations.
+fn merge_join_eq<'a>(
+    run: &'a [Tuple],
+    right_run: &'a [Tuple],
+    pre: &JoinPredicate,
+    projection: Option<&'a [usize]>,
+    emit: &mut impl FnMut(TupleRef<'a>),
+) -> MergeStats {
+    // a morsel may start in the middle of the left run, so skip the smaller right keys at once
+    let (mut l, mut r) = match run.first() {
+        Some(t) => (0, right_run.partition_point(|t_r| t_r.get_field(pre.right_index) < t.get_field(pre.left_index))),
+        None => return MergeStats::default(),
+    };
+    let mut stats = MergeStats::default();
+    while l < run.len() && r < right_run.len() {
+        let key = run[l].get_field(pre.left_index).unwrap();
+        stats.comparisons += 1;
+        match key.cmp(right_run[r].get_field(pre.right_index).unwrap()) {
+            Ordering::Less => l += 1,
+            Ordering::Greater => r += 1,
+            Ordering::Equal if pre.unique_left || pre.unique_right => {
+                let (start_l, start_r) = (l, r);
+                match (pre.unique_left, pre.unique_right) {
+                    // 1:1, a single pair per key
+                    (true, true) => {
+                        emit(TupleRef::new(&run[l], &right_run[r], projection));
+                        l += 1;
+                        r += 1;
+                    }
+                    (false, true) => {
+                        while l < run.len() && run[l].get_field(pre.left_index) == Some(key) {
+                            emit(TupleRef::new(&run[l], &right_run[r], projection));
+                            l += 1;
+                        }
+                        r += 1;
+                    }
+                    _ => {
+                        while r < right_run.len() && right_run[r].get_field(pre.right_index) == Some(key) {
+                            emit(TupleRef::new(&run[l], &right_run[r], projection));
+                            r += 1;
+                        }
+                        l += 1;
+                    }
+                }
+                let has_key = |t: Option<&Tuple>, index| t.is_some_and(|t| t.get_field(index) == Some(key));
+                if (pre.unique_left && has_key(run.get(l), pre.left_index)) || (pre.unique_right && has_key(right_run.get(r), pre.right_index)) {
+                    stats.unique_violations += 1;
+                }
+                let rows = (l - start_l).max(r - start_r);
+                stats.comparisons += rows;
+                if rows > 1 {
+                    stats.dup_blocks += 1;
+                    stats.dup_rows += rows;
+                }
+            }
+            Ordering::Equal => {
+                let (left_block, left_comparisons) = key_block(run, pre.left_index, l);
+                let (right_block, right_comparisons) = key_block(right_run, pre.right_index, r);
+                stats.comparisons += left_comparisons + right_comparisons;
+                let block = &right_run[right_block.first_row..][..right_block.count];
+                for t in &run[left_block.first_row..][..left_block.count] {
+                    for t_r in block {
+                        emit(TupleRef::new(t, t_r, projection));
+                    }
+                }
+                if left_block.count > 1 || right_block.count > 1 {
+                    stats.dup_blocks += 1;
+                    stats.dup_rows += left_block.count * right_block.count;
+                }
+                l += left_block.count;
+                r += right_block.count;
+            }
+        }
+    }
+    stats
+}
+
+/// Join a sorted left run with a sorted right run, appending the joined tuples to res.
+///
+/// Equi-joins advance a cursor on each run; other predicates scan the right run for each
+/// left tuple up to the first larger key. Meant as a building block of Level3Strategy.
+///
+/// # Arguments
+///
+/// * `run` - Left run sorted on the left join field.
+/// * `right_run` - Right run sorted on the right join field.
+/// * `pre` - Join condition.
+/// * `res` - Joined tuples.
+pub fn join_runs(run: &[Tuple], right_run: &[Tuple], pre: &JoinPredicate, res: &mut Vec<Tuple>) {
+    join_runs_projected(run, right_run, pre, None, res);
+}
+
+/// Join a sorted left run with a sorted right run like join_runs, handing each joined row
+/// to emit as a view of its left and right tuples, so no field is copied.
+///
+/// # Arguments
+///
+/// * `run` - Left run sorted on the left join field.
+/// * `right_run` - Right run sorted on the right join field.
+/// * `pre` - Join condition.
+/// * `emit` - Consumer of the joined rows, in the order of the left run.
+pub fn join_runs_ref<'a>(run: &'a [Tuple], right_run: &'a [Tuple], pre: &JoinPredicate, mut emit: impl FnMut(TupleRef<'a>)) {
+    join_runs_with(run, right_run, pre, None, &mut emit);
+}
+
+// join_runs keeping only the projected fields of the joined tuples, returns the key
+// comparisons and duplicate key blocks
+fn join_runs_projected(
+    run: &[Tuple],
+    right_run: &[Tuple],
+    pre: &JoinPredicate,
+    projection: Option<&[usize]>,
+    res: &mut Vec<Tuple>,
+) -> MergeStats {
+    join_runs_with(run, right_run, pre, projection, &mut |row| res.push(row.to_tuple()))
+}
+
+// join_runs_ref on the projected fields of the joined rows, returns the key comparisons
+// and duplicate key blocks
+fn join_runs_with<'a>(
+    run: &'a [Tuple],
+    right_run: &'a [Tuple],
+    pre: &JoinPredicate,
+    projection: Option<&'a [usize]>,
+    emit: &mut impl FnMut(TupleRef<'a>),
+) -> MergeStats {
+    if let SimplePredicateOp::Equals = pre.op {
+        return merge_join_eq(run, right_run, pre, projection, emit);
+    }
+    let mut comparisons = 0;
+    // loop through each tuple in the run
+    for t in run {
+        // try to match with tuple in the right run
+        for t_r in right_run {
+            comparisons += 1;
+            // if right tuple bigger than current tuple then break
+            if *t_r.get_field(pre.right_index).unwrap() > *t.get_field(pre.left_index).unwrap() {
+                break;
+            } else if pre.cmp(t, t_r) {
+                emit(TupleRef::new(t, t_r, projection));
+            }
+        }
+    }
+    MergeStats {
+        comparisons,
+        ..MergeStats::default()
+    }
+}
+
+// helper method to get the right runs of the key range partitions a left run can join: an
+// equi-join only matches the same range, a greater than join the lower ranges too
+fn partition_right_runs<'a>(runs_r: &'a [Vec<Tuple>], left_run: usize, pre: &JoinPredicate) -> &'a [Vec<Tuple>] {
+    match pre.op {
+        SimplePredicateOp::Equals => runs_r.get(left_run).map_or(&[][..], std::slice::from_ref),
+        _ => &runs_r[..(left_run + 1).min(runs_r.len())],
+    }
+}
+
+// join the left run with each right run, also returns the key comparisons and duplicate
+// key blocks
+fn join_m_pass(run: &[Tuple], right_runs: &[Vec<Tuple>], pre: &JoinPredicate, projection: Option<&[usize]>) -> (Vec<Tuple>, MergeStats) {
+    let mut res = Vec::new();
+    let mut stats = MergeStats::default();
+    // try to match with tuple in each right run
+    for right_run in right_runs {
+        stats += join_runs_projected(run, right_run, pre, projection, &mut res);
+    }
+    (res, stats)
+}
+
+// join_m_pass copying only the joined rows among the k smallest of the morsel on the top-k
+// column, ties kept in join order unless ordered by values
+fn join_m_pass_top_k(
+    run: &[Tuple],
+    right_runs: &[Vec<Tuple>],
+    pre: &JoinPredicate,
+    projection: Option<&[usize]>,
+    top_k: TopK,
+    by_values: bool,
+) -> (Vec<Tuple>, MergeStats) {
+    let mut rows = Vec::new();
+    let mut stats = MergeStats::default();
+    for right_run in right_runs {
+        stats += join_runs_with(run, right_run, pre, projection, &mut |row| rows.push(row));
+    }
+    rows.sort_by(|a, b| {
+        a.get_field(top_k.column).cmp(&b.get_field(top_k.column)).then_with(|| match by_values {
+            true => a.field_vals().cmp(b.field_vals()),
+            false => Ordering::Equal,
+        })
+    });
+    rows.truncate(top_k.k);
+    (rows.iter().map(TupleRef::to_tuple).collect(), stats)
+}
+
+impl<L: OpIterator, R: OpIterator> OpIterator for SortMergeJoin<L, R> {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.open = true;
+        let open_span = phase_span!("SortMergeJoin", "open");
+        let left_index = self.predicate.left_index;
+        let right_index = self.predicate.right_index;
+
+        // ask children for sorted output so their sort can be skipped
+        let left_sorted = self.left_child.request_order(left_index);
+        let right_sorted = self.right_child.request_order(right_index);
+        self.left_child.open()?;
+        self.right_child.open()?;
+
+        // runs of a previous open are no longer held
+        self.memory.release(self.charged + self.progress.0.charged + self.progress.1.charged);
+        self.charged = 0;
+        self.spilled_r.clear();
+        self.sorted = false;
+        self.joined = false;
+        self.progress = Default::default();
+        if let Some(stream) = self.stream.take() {
+            self.memory.release(stream.charged);
+        }
+        let streaming = self.streams_right();
+
+        // sort children into runs through the sorting/merging levels, only the right runs spill
+        let (config, kernel) = self.run_formation();
+        let monitor = &self.monitor;
+        let left_bytes = self.left_child.get_schema().byte_size();
+        let right_bytes = self.right_child.get_schema().byte_size();
+        self.phases.clear();
+        self.metrics = OpMetrics::default();
+        // a restored checkpoint holds the first runs, read past their rows
+        let restored = self.restored.take();
+        if let Some((dir, state)) = &restored {
+            self.progress.0 = restore_side(&mut self.left_child, dir, &state.left, left_bytes, &self.memory, &mut self.metrics, true)?;
+        }
+        let sort_span = phase_span!("SortMergeJoin", "sort left");
+        if !self.progress.0.sorted {
+            let left = &mut self.progress.0;
+            sort_child(&mut self.left_child, left_index, &config, kernel, left_sorted, &self.memory, false, monitor, "sort left", left)?;
+        }
+        let rows = self.progress.0.rows;
+        let elapsed = sort_span.finish(rows);
+        self.phases.push(PhaseStats::new("sort left", rows, rows * left_bytes, elapsed));
+        monitor.check_deadline()?;
+
+        // once the left keys are known the right child, not read yet, can skip the tuples no
+        // left tuple joins; the range is the same for the right runs of a checkpoint
+        let range = match (self.predicate.op, self.predicate.collation()) {
+            (SimplePredicateOp::Equals, Collation::Binary) => key_range(&self.progress.0.runs, left_index),
+            _ => None,
+        };
+        self.key_range = None;
+        if self.right_child.request_key_range(right_index, range.clone()) {
+            self.right_child.rewind()?;
+            self.key_range = range;
+        }
+        if let Some((dir, state)) = &restored {
+            self.progress.1 = restore_side(&mut self.right_child, dir, &state.right, right_bytes, &self.memory, &mut self.metrics, false)?;
+        }
+        if streaming {
+            // the right child is joined chunk by chunk as next reads it
+            let left = std::mem::take(&mut self.progress.0);
+            self.charged = left.charged;
+            self.metrics.rows_in = left.rows;
+            self.stream = Some(RightStream {
+                left: merge_runs(left.runs, left_index),
+                presorted: right_sorted,
+                rows: 0,
+                charged: 0,
+            });
+            self.l3_runs_l.clear();
+            self.l3_runs_r.clear();
+            self.cursor = (0, 0);
+            open_span.finish(self.metrics.rows_in);
+            return Ok(());
+        }
+        let sort_span = phase_span!("SortMergeJoin", "sort right");
+        if !self.progress.1.sorted {
+            let right = &mut self.progress.1;
+            sort_child(&mut self.right_child, right_index, &config, kernel, right_sorted, &self.memory, true, monitor, "sort right", right)?;
+        }
+        let rows = self.progress.1.rows;
+        let elapsed = sort_span.finish(rows);
+        self.phases.push(PhaseStats::new("sort right", rows, rows * right_bytes, elapsed));
+        monitor.check_deadline()?;
+        let (left, right) = std::mem::take(&mut self.progress);
+        let input_bytes = left.rows * left_bytes + right.rows * right_bytes;
+        self.charged = left.charged + right.charged;
+        self.metrics = OpMetrics {
+            rows_in: left.rows + right.rows,
+            spills: right.spilled.len(),
+            spill_bytes: right.spill_bytes,
+            ..self.metrics
+        };
+        let runs_l = left.runs;
+        let mut runs_r = right.runs;
+        // m-pass joins the spilled right runs from disk one at a time, the others read them back
+        if let MergeStrategy::MPass { .. } = self.strategy {
+            self.spilled_r = right.spilled;
+        } else {
+            for file in right.spilled {
+                self.monitor.check_deadline()?;
+                self.charged += file.len() * right_bytes;
+                runs_r.push(read_run(file, &self.memory, self.buffer_pool.as_ref(), &mut self.metrics, right_bytes)?);
+            }
+        }
+        #[cfg(feature = "visualize")]
+        if let Some(sink) = &self.events {
+            let spilled = self.spilled_r.iter().map(SpillFile::len);
+            let sides = [(JoinSide::Left, runs_l.iter().map(Vec::len).collect::<Vec<_>>()), (JoinSide::Right, runs_r.iter().map(Vec::len).chain(spilled).collect())];
+            for (side, runs) in sides {
+                for (run, rows) in runs.into_iter().enumerate() {
+                    sink.emit(JoinEvent::RunFormed { side, run, rows });
+                }
+            }
+        }
+        #[cfg(feature = "visualize")]
+        let run_counts = (runs_l.len(), runs_r.len());
+
+        // level 3 m-way/m-pass
+        let merge_span = phase_span!("SortMergeJoin", "merge");
+        if let MergeStrategy::MWay { partitions } = self.strategy {
+            let partition_span = phase_span!("SortMergeJoin", "partition");
+            let partitions = partitions.max(1);
+            let splitters = match self.splitter_method {
+                SplitterMethod::EqualRange => {
+                    // find right child's min/max keys
+                    let keys = runs_r.iter().flatten().map(|t| t.get_field(right_index).unwrap());
+                    self.min_r = keys.clone().min().cloned();
+                    self.max_r = keys.max().cloned();
+                    match (&self.min_r, &self.max_r) {
+                        (Some(min), Some(max)) => range_splitters(min, max, partitions),
+                        // an empty right child joins nothing
+                        _ => Vec::new(),
+                    }
+                }
+                SplitterMethod::Sample(sample_size) => {
+                    let keys: Vec<&Field> = runs_l
+                        .iter()
+                        .flatten()
+                        .map(|t| t.get_field(left_index).unwrap())
+                        .chain(runs_r.iter().flatten().map(|t| t.get_field(right_index).unwrap()))
+                        .collect();
+                    sample_splitters(&keys, partitions, sample_size)
+                }
+            };
+
+            self.l3_runs_l = sort_m_way_l3(runs_l, &splitters, left_index, self.merge_cores);
+            self.l3_runs_r = sort_m_way_l3(runs_r, &splitters, right_index, self.merge_cores);
+            self.partition_sizes = self
+                .l3_runs_l
+                .iter()
+                .zip(&self.l3_runs_r)
+                .map(|(l, r)| (l.len(), r.len()))
+                .collect();
+            #[cfg(feature = "visualize")]
+            if let Some(sink) = &self.events {
+                for (partition, (left_rows, right_rows)) in self.partition_sizes.iter().copied().enumerate() {
+                    let (lower, upper) = (partition.checked_sub(1).and_then(|i| splitters.get(i)).cloned(), splitters.get(partition).cloned());
+                    sink.emit(JoinEvent::PartitionAssigned { partition, lower, upper, left_rows, right_rows });
+                }
+                self.emit_merged(JoinSide::Left, &self.l3_runs_l, |_| run_counts.0);
+                self.emit_merged(JoinSide::Right, &self.l3_runs_r, |_| run_counts.1);
+            }
+            let elapsed = partition_span.finish(self.metrics.rows_in);
+            self.phases.push(PhaseStats::new("partition", self.metrics.rows_in, input_bytes, elapsed));
+        } else if let MergeStrategy::MPass { fan_in } = self.strategy {
+            self.l3_runs_l = runs_l;
+            self.l3_runs_r = merge_groups(runs_r, fan_in, right_index, self.merge_cores);
+            #[cfg(feature = "visualize")]
+            if fan_in > 1 {
+                self.emit_merged(JoinSide::Right, &self.l3_runs_r, |run| fan_in.min(run_counts.1 - run * fan_in));
+            }
+        } else if let MergeStrategy::Radix = self.strategy {
+            // spilled right runs were read back as extra runs
+            self.l3_runs_l = vec![merge_runs(runs_l, left_index)];
+            self.l3_runs_r = vec![merge_runs(runs_r, right_index)];
+            #[cfg(feature = "visualize")]
+            {
+                self.emit_merged(JoinSide::Left, &self.l3_runs_l, |_| run_counts.0);
+                self.emit_merged(JoinSide::Right, &self.l3_runs_r, |_| run_counts.1);
+            }
+        } else {
+            self.l3_runs_l = runs_l;
+            self.l3_runs_r = runs_r;
+        }
+        // assert_eq!(self.l3_runs_l, vec![vec![Tuple::new(vec![Field::StringField(String::from("Here"))])]]);
+        let elapsed = merge_span.finish(self.metrics.rows_in);
+        self.phases.push(PhaseStats::new("merge", self.metrics.rows_in, input_bytes, elapsed));
+        self.sorted = true;
+        self.joined = false;
+        self.cursor = (0, 0);
+
+        open_span.finish(self.metrics.rows_in);
+        Ok(())
+    }
+
+    /// Joins the level 3 runs on the first call, then returns the joined tuples run by run.
+    ///
+    /// The joined runs stay available in l3_runs_l.
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        if self.stream.is_some() {
+            loop {
+                let (run, pos) = self.cursor;
+                if let Some(t) = self.l3_runs_l.get(run).and_then(|tuples| tuples.get(pos)) {
+                    self.cursor = (run, pos + 1);
+                    self.metrics.rows_out += 1;
+                    return Ok(Some(t.clone()));
+                }
+                if !self.join_right_chunk()? {
+                    return Ok(None);
+                }
+            }
+        }
+        if !self.joined {
+            let join_span = phase_span!("SortMergeJoin", "join");
+            self.join_level3()?;
+            let rows = self.l3_runs_l.iter().map(Vec::len).sum();
+            let elapsed = join_span.finish(rows);
+            self.phases.push(PhaseStats::new("join", rows, rows * self.schema.byte_size(), elapsed));
+            self.joined = true;
+        }
+        let (run, pos) = self.cursor;
+        match self.l3_runs_l.get(run) {
+            None => Ok(None),
+            Some(tuples) if pos < tuples.len() => {
+                self.cursor = (run, pos + 1);
+                self.metrics.rows_out += 1;
+                Ok(Some(tuples[pos].clone()))
+            }
+            Some(_) => {
+                self.cursor = (run + 1, 0);
+                self.next()
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        self.left_child.close()?;
+        self.right_child.close()?;
+        self.memory.release(self.charged + self.progress.0.charged + self.progress.1.charged);
+        self.charged = 0;
+        self.spilled_r.clear();
+        self.progress = Default::default();
+        if let Some(stream) = self.stream.take() {
+            self.memory.release(stream.charged);
+        }
+        self.open = false;
+        close_event("SortMergeJoin", &self.metrics);
+        Ok(())
+    }
+
+    /// Returns to the first output tuple without sorting the children again.
+    ///
+    /// Like the hash table of HashEqJoin, the runs are kept: the sorted runs are still
+    /// joined by the next call to next, the joined runs are returned again. A streamed
+    /// right child is rewound and streamed again.
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        if let Some(stream) = self.stream.as_mut() {
+            self.right_child.rewind()?;
+            self.memory.release(stream.charged);
+            stream.charged = 0;
+            stream.rows = 0;
+            // the right tuples are counted again as they are read, as after open
+            self.metrics.rows_in = stream.left.len();
+            self.l3_runs_l.clear();
+        }
+        self.cursor = (0, 0);
+        Ok(())
+    }
+
+    /// return schema of the result
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+
+    fn metrics(&self) -> OpMetrics {
+        self.metrics
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.left_child, &self.right_child]
+    }
+
+    /// Tuples are returned sorted on the left join key, or on the top-k column; a streamed
+    /// right child must declare its order on the key.
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        let right_index = self.predicate.right_index;
+        match self.top_k {
+            Some(top_k) => Some(vec![top_k.column]),
+            None if self.streams_right() && self.right_child.sort_order().is_none_or(|order| order.first() != Some(&right_index)) => None,
+            None => self.output_key().map(|key| vec![key]),
+        }
+    }
+}
+
+/// Join that starts as a hash join and falls back to a sort-merge join once its build
+/// side exceeds the limit of its memory tracker, for inputs of unknown cardinality.
+///
+/// The first open reads the left child into memory, charging the tracker. If the whole
+/// left child fits, it is the build side of a HashEqJoin. Otherwise the tuples read so far
+/// are handed to a SortMergeJoin ahead of the rest of the left child, so no left tuple is
+/// read twice, and the sort-merge join spills its right runs as needed. The chosen join
+/// is kept by later opens.
+pub struct AdaptiveJoin {
+    /// Join condition.
+    predicate: JoinPredicate,
+    /// Children, until the first open moves them into the chosen join.
+    children: Option<(Box<dyn OpIterator + Send>, Box<dyn OpIterator + Send>)>,
+    /// Schema of the result.
+    schema: TableSchema,
+    /// Join status
+    open: bool,
+    /// level 3 method of the sort-merge join falling back
+    strategy: MergeStrategy,
+    /// tracker deciding between the joins, then charged by the chosen one
+    memory: MemoryTracker,
+    /// progress reporting and cancellation handed to the chosen join
+    monitor: ProgressMonitor,
+    /// hash or sort-merge join chosen by the first open
+    chosen: Option<Box<dyn OpIterator + Send>>,
+    /// whether the chosen join is the sort-merge join
+    switched: bool,
+}
+
+impl AdaptiveJoin {
+    /// Constructor for an adaptive equi-join operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Operation in join condition.
+    /// * `left_index` - Index of the left field in join condition.
+    /// * `right_index` - Index of the right field in join condition.
+    /// * `left_child` - Left child of join operator, the build side of the hash join.
+    /// * `right_child` - Right child of join operator.
+    ///
+    /// Fails with a ValidationError if the operation is not Equals, since the hash join
+    /// evaluates no other, if a join field does not exist or the fields have different types.
+    pub fn new(
+        op: SimplePredicateOp,
+        left_index: usize,
+        right_index: usize,
+        left_child: Box<dyn OpIterator + Send>,
+        right_child: Box<dyn OpIterator + Send>,
+    ) -> Result<Self, CrustyError> {
+        if op != SimplePredicateOp::Equals {
+            return Err(CrustyError::ValidationError(format!(
+                "an adaptive join only evaluates Equals, not {:?}",
+                op
+            )));
+        }
+        validate_join_fields(left_child.get_schema(), left_index, right_child.get_schema(), right_index)?;
+        Ok(Self {
+            predicate: JoinPredicate::new(op, left_index, right_index),
+            schema: left_child.get_schema().merge(right_child.get_schema()),
+            children: Some((left_child, right_child)),
+            open: false,
+            strategy: MergeStrategy::M_PASS,
+            memory: MemoryTracker::unbounded(),
+            monitor: ProgressMonitor::default(),
+            chosen: None,
+            switched: false,
+        })
+    }
+
+    /// Set the tracker whose limit bounds the build side, e.g. one shared by the whole plan.
+    ///
+    /// # Arguments
+    ///
+    /// * `memory` - Memory tracker.
+    pub fn set_memory_tracker(&mut self, memory: MemoryTracker) {
+        self.memory = memory;
+    }
+
+    /// Set the progress monitor of the chosen join, taking effect on the first open.
+    ///
+    /// # Arguments
+    ///
+    /// * `monitor` - Progress monitor, see HashEqJoin and SortMergeJoin for the phases.
+    pub fn set_progress_monitor(&mut self, monitor: ProgressMonitor) {
+        self.monitor = monitor;
+    }
+
+    /// Set the level 3 method of the sort-merge join used after a fall back.
+    ///
+    /// Fails with a ValidationError if the merge cannot evaluate the predicate, see
+    /// SortMergeJoin::set_strategy.
+    ///
+    /// # Arguments
+    ///
+    /// * `strategy` - Level 3 method, m-pass by default since it spills its right runs.
+    pub fn set_strategy(&mut self, strategy: MergeStrategy) -> Result<(), CrustyError> {
+        if !matches!(strategy, MergeStrategy::Custom(_)) {
+            validate_merge_op(self.predicate.op)?;
+        }
+        self.strategy = strategy;
+        Ok(())
+    }
+
+    /// Returns true if the join fell back to the sort-merge join.
+    pub fn switched(&self) -> bool {
+        self.switched
+    }
+
+    // helper method to read the left child until it ends or exceeds the memory limit, then build the chosen join
+    fn choose(
+        &mut self,
+        mut left: Box<dyn OpIterator + Send>,
+        right: Box<dyn OpIterator + Send>,
+    ) -> Result<Box<dyn OpIterator + Send>, CrustyError> {
+        let (op, left_index, right_index) = (self.predicate.op, self.predicate.left_index, self.predicate.right_index);
+        let bytes = left.get_schema().byte_size();
+        left.open()?;
+        let mut taken = VecDeque::new();
+        let mut fits = true;
+        while let Some(t) = left.next()? {
+            taken.push_back(t);
+            self.monitor.check()?;
+            if !self.memory.try_reserve(bytes) {
+                fits = false;
+                break;
+            }
+        }
+        // the chosen join charges the tuples again as it holds them
+        self.memory.release(bytes * (taken.len() - usize::from(!fits)));
+        let left = Replay { taken, child: left, fresh: true };
+        self.switched = !fits;
+        if fits {
+            let mut hash = HashEqJoin::new(op, left_index, right_index, left, right)?;
+            hash.set_memory_tracker(self.memory.clone());
+            hash.set_progress_monitor(self.monitor.clone());
+            Ok(Box::new(hash))
+        } else {
+            let mut sort_merge = SortMergeJoin::new(op, left_index, right_index, left, right, self.strategy.clone())?;
+            sort_merge.set_memory_tracker(self.memory.clone());
+            sort_merge.set_progress_monitor(self.monitor.clone());
+            Ok(Box::new(sort_merge))
+        }
+    }
+}
+
+impl OpIterator for AdaptiveJoin {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        if let Some((left, right)) = self.children.take() {
+            let chosen = self.choose(left, right)?;
+            self.chosen = Some(chosen);
+        }
+        self.open = true;
+        self.chosen.as_mut().unwrap().open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        match self.chosen.as_mut() {
+            Some(chosen) if self.open => chosen.next(),
+            _ => not_open(),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        match self.chosen.as_mut() {
+            Some(chosen) if self.open => {
+                self.open = false;
+                chosen.close()
+            }
+            _ => not_open(),
+        }
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        match self.chosen.as_mut() {
+            Some(chosen) if self.open => chosen.rewind(),
+            _ => not_open(),
+        }
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+
+    fn metrics(&self) -> OpMetrics {
+        self.chosen.as_ref().map(|chosen| chosen.metrics()).unwrap_or_default()
+    }
+
+    /// The chosen join once open, the two children before.
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        match (&self.chosen, &self.children) {
+            (Some(chosen), _) => vec![chosen.as_ref() as &dyn OpIterator],
+            (None, Some((left, right))) => vec![left.as_ref() as &dyn OpIterator, right.as_ref()],
+            (None, None) => Vec::new(),
+        }
+    }
+}
+
+/// Left child of an adaptive join: the tuples it already read, then the rest of the child.
+struct Replay {
+    /// Tuples read before the join was chosen, not yet returned.
+    taken: VecDeque<Tuple>,
+    /// Left child, open and positioned after the taken tuples while fresh.
+    child: Box<dyn OpIterator + Send>,
+    /// Whether the child has not been reopened or rewound since the tuples were taken.
+    fresh: bool,
+}
+
+impl OpIterator for Replay {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        if self.fresh {
+            return Ok(());
+        }
+        self.child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        match self.taken.pop_front() {
+            Some(t) => Ok(Some(t)),
+            None => self.child.next(),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.taken.clear();
+        self.fresh = false;
+        self.child.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.taken.clear();
+        self.fresh = false;
+        self.child.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        self.child.get_schema()
+    }
+
+    fn name(&self) -> String {
+        self.child.name()
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        self.child.children()
+    }
+}
+
+/// Default number of partitions of the partitioned sort-merge join.
+pub const JOIN_PARTITIONS: usize = 4;
+
+/// Sort-merge equi-join of both children range-partitioned on the join key.
+///
+/// Each child runs behind a RepartitionExchange splitting it into disjoint key ranges,
+/// and every pair of left and right partitions is joined by its own SortMergeJoin on its
+/// own thread, so partitioning, sorting and merging all run in parallel. The outputs are
+/// concatenated in key range order, so the result is ordered on the join key.
+///
+/// The key ranges are given by set_bounds, or else sampled from the left child, which is
+/// then read into memory before it is partitioned. Not available on wasm32, which has no
+/// threads to run the exchanges.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct PartitionedSortMergeJoin {
+    /// Join condition.
+    predicate: JoinPredicate,
+    /// Children, None while they run behind the exchanges.
+    children: Option<(Box<dyn OpIterator + Send>, Box<dyn OpIterator + Send>)>,
+    /// Schema of the result.
+    schema: TableSchema,
+    /// Number of partitions when the bounds are sampled.
+    partitions: usize,
+    /// Upper bounds of the key ranges but the last, sampled if None.
+    bounds: Option<Vec<Field>>,
+    /// Level 3 method of the partition joins.
+    strategy: MergeStrategy,
+    /// Number of tuples buffered in each exchange channel.
+    capacity: usize,
+    /// Joined tuples of every partition, in key range order.
+    output: Vec<Tuple>,
+    /// Position of the next output tuple, None if not open.
+    cursor: Option<usize>,
+    /// Number of joined tuples of each partition of the last open.
+    partition_sizes: Vec<usize>,
+    /// Whether the partition joins put their output in order on all its fields.
+    deterministic: bool,
+    /// Progress reporting and cancellation shared by the partition joins.
+    monitor: ProgressMonitor,
+    /// Rows, comparisons and spills of the partition joins since the last open.
+    metrics: OpMetrics,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl PartitionedSortMergeJoin {
+    /// Constructor for a partitioned sort-merge equi-join operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `left_index` - Index of the left field in join condition.
+    /// * `right_index` - Index of the right field in join condition.
+    /// * `left_child` - Left child of join operator.
+    /// * `right_child` - Right child of join operator.
+    /// * `strategy` - Level 3 method of the partition joins.
+    ///
+    /// Fails with a ValidationError if a join field does not exist or the fields have
+    /// different types.
+    pub fn new(
+        left_index: usize,
+        right_index: usize,
+        left_child: Box<dyn OpIterator + Send>,
+        right_child: Box<dyn OpIterator + Send>,
+        strategy: MergeStrategy,
+    ) -> Result<Self, CrustyError> {
+        validate_join_fields(left_child.get_schema(), left_index, right_child.get_schema(), right_index)?;
+        Ok(Self {
+            predicate: JoinPredicate::new(SimplePredicateOp::Equals, left_index, right_index),
+            schema: left_child.get_schema().merge(right_child.get_schema()),
+            children: Some((left_child, right_child)),
+            partitions: JOIN_PARTITIONS,
+            bounds: None,
+            strategy,
+            capacity: EXCHANGE_CAPACITY,
+            output: Vec::new(),
+            cursor: None,
+            partition_sizes: Vec::new(),
+            deterministic: false,
+            monitor: ProgressMonitor::default(),
+            metrics: OpMetrics::default(),
+        })
+    }
+
+    /// Put the joined tuples of every partition in order on all their fields, see
+    /// SortMergeJoin::set_deterministic_order.
+    ///
+    /// # Arguments
+    ///
+    /// * `deterministic` - Whether to order the output of the next open.
+    pub fn set_deterministic_order(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Set the number of partitions of sampled bounds.
+    ///
+    /// # Arguments
+    ///
+    /// * `partitions` - Number of partitions to use on the next open, at least 1.
+    pub fn set_partitions(&mut self, partitions: usize) {
+        self.partitions = partitions.max(1);
+    }
+
+    /// Set the key ranges, so both children are partitioned as they are read.
+    ///
+    /// # Arguments
+    ///
+    /// * `bounds` - Ascending upper bounds of the key ranges but the last, see
+    ///   exchange::Partitioning::Range.
+    pub fn set_bounds(&mut self, bounds: Vec<Field>) {
+        self.bounds = Some(bounds);
+    }
+
+    /// Set the number of tuples buffered in each exchange channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - Number of tuples, at least 1.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+    }
+
+    /// Set the progress monitor of every partition join, which report their phases
+    /// separately.
+    ///
+    /// # Arguments
+    ///
+    /// * `monitor` - Progress monitor, see SortMergeJoin for the phases.
+    pub fn set_progress_monitor(&mut self, monitor: ProgressMonitor) {
+        self.monitor = monitor;
+    }
+
+    /// Returns the number of joined tuples of each partition of the last open.
+    pub fn partition_sizes(&self) -> &[usize] {
+        &self.partition_sizes
+    }
+
+    // helper method to partition both children and join every pair of partitions on its own
+    // thread, putting the children back once the exchanges are done
+    fn join_partitions(&mut self, left: Box<dyn OpIterator + Send>, right: Box<dyn OpIterator + Send>) -> Result<(), CrustyError> {
+        let (left_index, right_index) = (self.predicate.left_index, self.predicate.right_index);
+        // without bounds the left child is read first, to sample its keys
+        let (left_input, bounds, mut left) = match &self.bounds {
+            Some(bounds) => (left, bounds.clone(), None),
+            None => {
+                let mut left = left;
+                left.open()?;
+                let mut tuples = Vec::new();
+                while let Some(t) = left.next()? {
+                    tuples.push(t);
+                    self.monitor.check()?;
+                }
+                left.close()?;
+                let bounds = sample_bounds(&tuples, left_index, self.partitions);
+                let buffered: Box<dyn OpIterator + Send> =
+                    Box::new(TupleIterator::new(tuples, left.get_schema().clone()));
+                (buffered, bounds, Some(left))
+            }
+        };
+        let partitioning = |index| Partitioning::Range { index, bounds: bounds.clone() };
+        let (left_parts, left_producer) =
+            RepartitionExchange::new(left_input, partitioning(left_index), self.capacity).spawn();
+        let (right_parts, right_producer) =
+            RepartitionExchange::new(right, partitioning(right_index), self.capacity).spawn();
+        let (strategy, monitor, deterministic) = (&self.strategy, &self.monitor, self.deterministic);
+        // every partition join runs at once, so each exchange can feed all its channels
+        let joined: Vec<Result<(Vec<Tuple>, OpMetrics), CrustyError>> = std::thread::scope(|s| {
+            let handles: Vec<_> = left_parts
+                .into_iter()
+                .zip(right_parts)
+                .map(|(l, r)| {
+                    s.spawn(move || {
+                        let mut join = SortMergeJoin::new(SimplePredicateOp::Equals, left_index, right_index, l, r, strategy.clone())?;
+                        join.set_workers(1);
+                        join.set_deterministic_order(deterministic);
+                        join.set_progress_monitor(monitor.clone());
+                        join.open()?;
+                        let mut tuples = Vec::new();
+                        while let Some(t) = join.next()? {
+                            tuples.push(t);
+                        }
+                        Ok((tuples, join.metrics()))
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+        let producer_error = || CrustyError::ExecutionError(String::from("exchange producer panicked"));
+        let left_input = left_producer.join().map_err(|_| producer_error())?;
+        let right = right_producer.join().map_err(|_| producer_error())?;
+        self.children = Some((left.take().unwrap_or(left_input), right));
+        self.output.clear();
+        self.partition_sizes.clear();
+        self.metrics = OpMetrics::default();
+        let mut first_error = None;
+        for res in joined {
+            match res {
+                Ok((tuples, metrics)) => {
+                    self.partition_sizes.push(tuples.len());
+                    self.output.extend(tuples);
+                    self.metrics.rows_in += metrics.rows_in;
+                    self.metrics.comparisons += metrics.comparisons;
+                    self.metrics.dup_blocks += metrics.dup_blocks;
+                    self.metrics.dup_rows += metrics.dup_rows;
+                    self.metrics.spills += metrics.spills;
+                }
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+}
+
+// helper method to pick the upper bounds of equally sized key ranges from the keys of the tuples
+#[cfg(not(target_arch = "wasm32"))]
+fn sample_bounds(tuples: &[Tuple], index: usize, partitions: usize) -> Vec<Field> {
+    let step = (tuples.len() / SPLITTER_SAMPLE_SIZE).max(1);
+    let mut keys: Vec<&Field> = tuples.iter().step_by(step).filter_map(|t| t.get_field(index)).collect();
+    keys.sort();
+    let mut bounds: Vec<Field> = (1..partitions)
+        .filter_map(|i| keys.get(i * keys.len() / partitions).map(|key| (*key).clone()))
+        .collect();
+    bounds.dedup();
+    bounds
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl OpIterator for PartitionedSortMergeJoin {
+    /// Partitions and joins both children, which are then closed.
+    fn open(&mut self) -> Result<(), CrustyError> {
+        let (left, right) = self
+            .children
+            .take()
+            .ok_or_else(|| CrustyError::ExecutionError(String::from("join children lost by a panic")))?;
+        self.join_partitions(left, right)?;
+        self.cursor = Some(0);
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        let cursor = match self.cursor.as_mut() {
+            None => return not_open(),
+            Some(cursor) => cursor,
+        };
+        let t = self.output.get(*cursor).cloned();
+        *cursor += t.is_some() as usize;
+        self.metrics.rows_out += t.is_some() as usize;
+        Ok(t)
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if self.cursor.take().is_none() {
+            return not_open();
+        }
+        self.output.clear();
+        Ok(())
+    }
+
+    /// Returns the joined tuples again, without joining the children again.
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        match self.cursor.as_mut() {
+            None => not_open(),
+            Some(cursor) => {
+                *cursor = 0;
+                Ok(())
+            }
+        }
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        Some(vec![self.predicate.left_index])
+    }
+
+    fn metrics(&self) -> OpMetrics {
+        self.metrics
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        match &self.children {
+            Some((left, right)) => vec![left.as_ref() as &dyn OpIterator, right.as_ref()],
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::common::*;
+    use crate::config::{CancellationToken, Progress};
+    use crate::heap::{HeapFile, HeapFileScan};
+    use crate::operators::{Project, Sort};
+    use super::*;
+
+    /// Creates a Vec of tuples containing IntFields given a 2D Vec of i32 's
+    pub fn create_tuple_list(tuple_data: Vec<Vec<i32>>) -> Vec<Tuple> {
+        let mut tuples = Vec::new();
+        for item in &tuple_data {
+            let fields = item.iter().map(|i| Field::IntField(*i)).collect();
+            tuples.push(Tuple::new(fields));
+        }
+        tuples
+    }
+    /// Creates a new table schema for a table with width number of IntFields.
+    pub fn get_int_table_schema(width: usize) -> TableSchema {
+        let mut attrs = Vec::new();
+        for _ in 0..width {
+            attrs.push(Attribute::new(String::new(), DataType::Int))
+        }
+        TableSchema::new(attrs)
+    }
+    #[allow(dead_code)]
+    /// Asserts that iter1 and iter2 contain all the same tuples
+    pub fn match_all_tuples(
+        mut iter1: Box<dyn OpIterator>,
+        mut iter2: Box<dyn OpIterator>,
+    ) -> Result<(), CrustyError> {
+        while let Some(t1) = iter1.next()? {
+            let t2 = iter2.next()?.unwrap();
+            assert_eq!(t1, t2);
+        }
+        // assert_eq!(iter2.next()?.unwrap(), Tuple::new(vec![]));
+        assert!(iter2.next()?.is_none());
+        Ok(())
+    }
+
+    const WIDTH1: usize = 2;
+    const WIDTH2: usize = 3;
+    enum JoinType {
+        NestedLoop,
+        HashEq,
+        SortMerge,
+    }
+
+    pub fn scan1() -> TupleIterator {
+        let tuples = create_tuple_list(vec![
+            vec![1, 4], vec![3, 3], vec![5, 6], vec![7, 8],
+            vec![1, 1], vec![3, 7], vec![5, 2], vec![7, 5]]);
+        let ts = get_int_table_schema(WIDTH1);
+        TupleIterator::new(tuples, ts)
+    }
+
+    pub fn scan2() -> TupleIterator {
+        let tuples = create_tuple_list(vec![
+            vec![1, 2, 3], vec![2, 3, 4], vec![3, 4, 5], vec![4, 5, 6],
+            vec![5, 9, 7], vec![1, 10, 3], vec![2, 7, 4], vec![3, 6, 5],
+        ]);
+        let ts = get_int_table_schema(WIDTH2);
+        TupleIterator::new(tuples, ts)
+    }
+
+    pub fn eq_join() -> TupleIterator {
+        let tuples = create_tuple_list(vec![
+            vec![5, 2, 1, 2, 3],
+            vec![3, 3, 2, 3, 4],
+            vec![1, 4, 3, 4, 5],
+            vec![7, 5, 4, 5, 6],
+            vec![5, 6, 3, 6, 5],
+            vec![3, 7, 2, 7, 4],
+        ]);
+        let ts = get_int_table_schema(WIDTH1 + WIDTH2);
+        TupleIterator::new(tuples, ts)
+    }
+
+    fn construct_join(
+        ty: JoinType,
+        op: SimplePredicateOp,
+        left_index: usize,
+        right_index: usize,
+        strategy: MergeStrategy,
+    ) -> Box<dyn OpIterator> {
+        let s1 = Box::new(scan1());
+        let s2 = Box::new(scan2());
+        match ty {
+            JoinType::NestedLoop => Box::new(Join::new(op, left_index, right_index, s1, s2).unwrap()),
+            JoinType::HashEq => Box::new(HashEqJoin::new(op, left_index, right_index, s1, s2).unwrap()),
+            JoinType::SortMerge => Box::new(SortMergeJoin::new(op, left_index, right_index, s1, s2, strategy).unwrap()),
+        }
+    }
+
+    fn test_empty_child(join_type: JoinType, strategy: MergeStrategy) -> Result<(), CrustyError> {
+        for left_empty in [true, false] {
+            let empty = Box::new(TupleIterator::new(Vec::new(), get_int_table_schema(WIDTH1)));
+            let (s1, s2): (Box<dyn OpIterator + Send>, Box<dyn OpIterator + Send>) = if left_empty {
+                (empty, Box::new(scan2()))
+            } else {
+                (Box::new(scan1()), empty)
+            };
+            let mut op: Box<dyn OpIterator> = match join_type {
+                JoinType::NestedLoop => Box::new(Join::new(SimplePredicateOp::Equals, 1, 1, s1, s2).unwrap()),
+                JoinType::HashEq => Box::new(HashEqJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2).unwrap()),
+                JoinType::SortMerge => {
+                    let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, strategy.clone())?;
+                    op.set_splitter_method(SplitterMethod::EqualRange);
+                    op.open()?;
+                    op.next()?;
+                    assert!(op.l3_runs_l.concat().is_empty());
+                    continue;
+                }
+            };
+            op.open()?;
+            assert_eq!(op.next()?, None);
+            op.rewind()?;
+            assert_eq!(op.next()?, None);
+            op.close()?;
+        }
+        Ok(())
+    }
+
+    fn test_builder() -> Result<(), CrustyError> {
+        for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS] {
+            let mut op = SortMergeJoin::builder()
+                .predicate(SimplePredicateOp::Equals, 1, 1)
+                .left(Box::new(scan1()))
+                .right(Box::new(scan2()))
+                .strategy(strategy)
+                .memory_budget(1024)
+                .sort_config(SortConfig::new(4, 16))
+                .build()?;
+            assert_eq!(op.sort_config.memory_budget, Some(1024));
+            op.open()?;
+            op.next()?;
+            assert_eq!(op.l3_runs_l.concat().len(), 6);
+        }
+        let res = SortMergeJoin::builder()
+            .predicate(SimplePredicateOp::Equals, 1, 1)
+            .left(Box::new(scan1()))
+            .build();
+        assert!(matches!(res, Err(CrustyError::ValidationError(_))));
+        Ok(())
+    }
+
+    fn test_borrowed_children() -> Result<(), CrustyError> {
+        let mut left = scan1();
+        let mut right = scan2();
+        // the same scans are joined again on each iteration without boxing or giving them up
+        for _ in 0..2 {
+            let mut nested = Join::new(SimplePredicateOp::Equals, 1, 1, &mut left, &mut right)?;
+            nested.open()?;
+            let mut count = 0;
+            while nested.next()?.is_some() {
+                count += 1;
+            }
+            nested.close()?;
+            assert_eq!(count, 6);
+
+            let mut hash = HashEqJoin::new(SimplePredicateOp::Equals, 1, 1, &mut left, Box::new(scan2()))?;
+            hash.open()?;
+            assert!(hash.next()?.is_some());
+            hash.close()?;
+
+            let mut sort_merge = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, &mut left, &mut right, MergeStrategy::M_PASS)?;
+            sort_merge.open()?;
+            sort_merge.next()?;
+            assert_eq!(sort_merge.l3_runs_l.concat().len(), 6);
+            sort_merge.close()?;
+        }
+        Ok(())
+    }
+
+    fn test_hash_partitions(partitions: usize, radix_bits: u32) -> Result<(), CrustyError> {
+        let mut expected = Vec::new();
+        let mut eq = eq_join();
+        eq.open()?;
+        while let Some(t) = eq.next()? {
+            expected.push(t);
+        }
+        expected.sort_by(|a, b| a.field_vals.cmp(&b.field_vals));
+
+        let mut op = HashEqJoin::new(SimplePredicateOp::Equals, 1, 1, scan1(), scan2())?;
+        op.set_partitions(partitions);
+        op.set_radix_bits(radix_bits);
+        op.open()?;
+        // the result is the same after rewinding
+        for _ in 0..2 {
+            let mut res = Vec::new();
+            while let Some(t) = op.next()? {
+                res.push(t);
+            }
+            res.sort_by(|a, b| a.field_vals.cmp(&b.field_vals));
+            assert_eq!(res, expected);
+            op.rewind()?;
+        }
+        op.close()
+    }
+
+    fn test_hash_unmatched() -> Result<(), CrustyError> {
+        // no right tuple matches, so no bucket must be joined with a stale right tuple
+        let left = TupleIterator::new(create_tuple_list(vec![vec![1, 0], vec![2, 0]]), get_int_table_schema(WIDTH1));
+        let mut op = HashEqJoin::new(SimplePredicateOp::Equals, 1, 1, left, scan2())?;
+        op.open()?;
+        assert_eq!(op.next()?, None);
+        op.rewind()?;
+        assert_eq!(op.next()?, None);
+        op.close()
+    }
+
+    fn test_bucket_stats() -> Result<(), CrustyError> {
+        let mut op = HashEqJoin::new(SimplePredicateOp::Equals, 0, 0, scan1(), scan2())?;
+        op.open()?;
+        let stats = op.bucket_stats();
+        assert_eq!((stats.buckets, stats.tuples, stats.max_chain), (4, 8, 2));
+        assert!(stats.load_factor > 0.0 && stats.load_factor <= 1.0);
+        op.close()
+    }
+
+    fn test_projection() -> Result<(), CrustyError> {
+        // the right key and the left value, in that order
+        let columns = vec![3, 0];
+        let expected: Vec<Vec<i32>> = vec![vec![2, 5], vec![3, 3], vec![4, 1], vec![5, 7], vec![6, 5], vec![7, 3]];
+        let mut ops: Vec<Box<dyn OpIterator>> = Vec::new();
+        let mut nested = Join::new(SimplePredicateOp::Equals, 1, 1, scan1(), scan2())?;
+        nested.set_projection(columns.clone())?;
+        ops.push(Box::new(nested));
+        let mut hash = HashEqJoin::new(SimplePredicateOp::Equals, 1, 1, scan1(), scan2())?;
+        hash.set_projection(columns.clone())?;
+        ops.push(Box::new(hash));
+        for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS] {
+            let sort_merge = SortMergeJoin::builder()
+                .predicate(SimplePredicateOp::Equals, 1, 1)
+                .left(Box::new(scan1()))
+                .right(Box::new(scan2()))
+                .strategy(strategy)
+                .projection(columns.clone())
+                .build()?;
+            ops.push(Box::new(sort_merge));
+        }
+        for mut op in ops {
+            assert_eq!(op.get_schema(), &get_int_table_schema(2));
+            op.open()?;
+            let mut res = Vec::new();
+            while let Some(t) = op.next()? {
+                res.push(t.field_vals().map(Field::unwrap_int_field).collect::<Vec<_>>());
+            }
+            res.sort();
+            assert_eq!(res, expected);
+        }
+
+        let mut cross = CrossJoin::new(scan1(), scan2(), 3);
+        cross.set_projection(vec![4])?;
+        cross.open()?;
+        assert_eq!(cross.next()?, Some(Tuple::new(vec![Field::IntField(3)])));
+        assert!(matches!(cross.set_projection(vec![5]), Err(CrustyError::ValidationError(_))));
+        Ok(())
+    }
+
+    // helper method to run a join of scan1 and scan2 and sort its output
+    fn test_sorted_output(op: &mut dyn OpIterator) -> Result<Vec<Tuple>, CrustyError> {
+        op.open()?;
+        let mut res = Vec::new();
+        while let Some(t) = op.next()? {
+            res.push(t);
+        }
+        op.close()?;
+        res.sort_by(|a, b| a.field_vals.cmp(&b.field_vals));
+        Ok(res)
+    }
+
+    fn test_memory_limit() -> Result<(), CrustyError> {
+        let expected = test_sorted_output(&mut eq_join())?;
+
+        // room for 3 left tuples of 8 bytes, the hash table spills the other partitions
+        let tracker = MemoryTracker::new(24);
+        let mut hash = HashEqJoin::new(SimplePredicateOp::Equals, 1, 1, scan1(), scan2())?;
+        hash.set_memory_tracker(tracker.clone());
+        assert_eq!(test_sorted_output(&mut hash)?, expected);
+        assert_eq!(hash.partitions, HASH_SPILL_PARTITIONS);
+        assert_eq!(tracker.used(), 0);
+
+        // the staged tuples are moved into the tables before the repartition
+        let mut hash = HashEqJoin::new(SimplePredicateOp::Equals, 1, 1, scan1(), scan2())?;
+        hash.set_memory_tracker(MemoryTracker::new(24));
+        hash.set_radix_bits(2);
+        assert_eq!(test_sorted_output(&mut hash)?, expected);
+
+        // the left runs take 64 bytes, the right runs of 12 bytes tuples spill past 80
+        for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS] {
+            let tracker = MemoryTracker::new(80);
+            let mut sort_merge = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan1(), scan2(), strategy)?;
+            sort_merge.set_memory_tracker(tracker.clone());
+            assert_eq!(test_sorted_output(&mut sort_merge)?, expected);
+            assert!(tracker.peak() >= 64);
+            assert_eq!(tracker.used(), 0);
+        }
+        Ok(())
+    }
+
+    fn test_adaptive() -> Result<(), CrustyError> {
+        let expected = test_sorted_output(&mut eq_join())?;
+        // room for the 8 left tuples of 8 bytes keeps the hash join, room for 3 switches
+        for (limit, switched) in [(64, false), (24, true)] {
+            let tracker = MemoryTracker::new(limit);
+            let mut op = AdaptiveJoin::new(SimplePredicateOp::Equals, 1, 1, Box::new(scan1()), Box::new(scan2()))?;
+            op.set_memory_tracker(tracker.clone());
+            assert_eq!(op.children().len(), 2);
+            // the choice is kept when opened again
+            for _ in 0..2 {
+                assert_eq!(test_sorted_output(&mut op)?, expected);
+                assert_eq!(op.switched(), switched);
+                assert_eq!(op.children()[0].name(), if switched { "SortMergeJoin" } else { "HashEqJoin" });
+                assert_eq!(op.metrics().rows_out, expected.len());
+                assert_eq!(tracker.used(), 0);
+            }
+        }
+        // the hash join in memory would equi-join any other operation
+        for op in [SimplePredicateOp::GreaterThan, SimplePredicateOp::LessThan, SimplePredicateOp::NotEq] {
+            let res = AdaptiveJoin::new(op, 1, 1, Box::new(scan1()), Box::new(scan2()));
+            assert!(matches!(res, Err(CrustyError::ValidationError(_))));
+        }
+        if !cfg!(feature = "panic-not-open") {
+            let mut op = AdaptiveJoin::new(SimplePredicateOp::Equals, 1, 1, Box::new(scan1()), Box::new(scan2()))?;
+            assert!(matches!(op.next(), Err(CrustyError::ExecutionError(_))));
+        }
+        Ok(())
+    }
+
+    fn test_get_schema(join_type: JoinType, strategy: MergeStrategy) {
+        let op = construct_join(join_type, SimplePredicateOp::Equals, 0, 0, strategy);
+        let expected = get_int_table_schema(WIDTH1 + WIDTH2);
+        let actual = op.get_schema();
+        assert_eq!(&expected, actual);
+    }
+
+    fn test_next_not_open(join_type: JoinType, strategy: MergeStrategy) {
+        let mut op = construct_join(join_type, SimplePredicateOp::Equals, 0, 0, strategy);
+        assert!(matches!(op.next(), Err(CrustyError::ExecutionError(_))));
+    }
+
+    fn test_rewind_not_open(join_type: JoinType, strategy: MergeStrategy) {
+        let mut op = construct_join(join_type, SimplePredicateOp::Equals, 0, 0, strategy);
+        assert!(matches!(op.rewind(), Err(CrustyError::ExecutionError(_))));
+    }
+
+    fn test_rewind(strategy: MergeStrategy) -> Result<(), CrustyError> {
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan1(), scan2(), strategy)?;
+        op.open()?;
+        // rewinding before the join keeps the sorted runs
+        op.rewind()?;
+        let mut expected = Vec::new();
+        while let Some(t) = op.next()? {
+            expected.push(t);
+        }
+        assert_eq!(expected.len(), 6);
+        let partition_sizes = op.partition_sizes().to_vec();
+        // 8 tuples of 2 and 3 ints, joined into 6 tuples of 5 ints
+        let phases: Vec<(&str, usize, usize)> = op.phase_stats().iter().map(|p| (p.phase.as_str(), p.rows, p.bytes)).collect();
+        assert_eq!(phases[..2], [("sort left", 8, 64), ("sort right", 8, 96)]);
+        assert_eq!(phases[phases.len() - 2..], [("merge", 16, 160), ("join", 6, 120)]);
+        let phases = op.phase_stats().to_vec();
+        for taken in [0, 2, expected.len()] {
+            op.rewind()?;
+            for _ in 0..taken {
+                op.next()?;
+            }
+            op.rewind()?;
+            let mut res = Vec::new();
+            while let Some(t) = op.next()? {
+                res.push(t);
+            }
+            assert_eq!(res, expected, "rewound after {} tuples", taken);
+            // the runs were neither sorted nor partitioned again
+            assert_eq!(op.partition_sizes(), partition_sizes);
+            assert_eq!(op.phase_stats(), phases);
+            assert_eq!(op.metrics().rows_in, 16);
+        }
+        op.close()
+    }
+
+    fn test_join_m_way() -> Result<(), CrustyError> {
+        // left run
+        let left_run = create_tuple_list(vec![
+            vec![5, 1], vec![3, 8], vec![1, 10], vec![1, 20]]);
+        // right runs
+        let right_run = create_tuple_list(vec![
+            vec![5, 1], vec![3, 2], vec![7, 3], vec![1, 4],
+            vec![1, 5], vec![3, 6], vec![5, 7], vec![7, 8]]);
+        // join predicate
+        let pre = JoinPredicate::new(SimplePredicateOp::Equals, 1, 1);
+
+        // join the result
+        let (res, _) = join_m_pass(&left_run, std::slice::from_ref(&right_run), &pre, None);
+        // expected
+        let target = create_tuple_list(vec![
+            vec![5, 1, 5, 1],
+            vec![3, 8, 7, 8],
+        ]);
+
+        let ts = get_int_table_schema(4);
+
+        let mut target_op = Box::new(TupleIterator::new(target, ts.clone()));
+        let mut res_op = Box::new(TupleIterator::new(res, ts.clone()));
+        target_op.open()?;
+        res_op.open()?;
+        match_all_tuples(target_op, res_op)
+    }
 
     fn test_join_m_pass() -> Result<(), CrustyError> {
         // left run
         let left_run = create_tuple_list(vec![
             vec![5, 17], vec![3, 18], vec![1, 20], vec![1, 30]]);
         // right runs
-        let mut right_run1 = create_tuple_list(vec![
+        let right_run1 = create_tuple_list(vec![
             vec![5, 1], vec![3, 2], vec![7, 3], vec![1, 4],
             vec![1, 5], vec![3, 6], vec![5, 7], vec![7, 8]]);
-        let mut right_run2 = create_tuple_list(vec![
+        let right_run2 = create_tuple_list(vec![
             vec![5, 9], vec![3, 10], vec![7, 11], vec![1, 12],
             vec![1, 13], vec![3, 14], vec![5, 15], vec![7, 16]]);
-        let mut right_run3 = create_tuple_list(vec![
+        let right_run3 = create_tuple_list(vec![
             vec![6, 17], vec![5, 18], vec![7, 19], vec![1, 20],
             vec![1, 21], vec![3, 22], vec![5, 23], vec![7, 24]]);
         let right_runs = vec![right_run1, right_run2, right_run3];
         // join predicate
         let pre = JoinPredicate::new(SimplePredicateOp::Equals, 1, 1);
 
-        // join the result
-        let res = join_m_pass(left_run, right_runs, pre);
-        // expected
-        let target = create_tuple_list(vec![
-            vec![5, 17, 6, 17],
-            vec![3, 18, 5, 18],
-            vec![1, 20, 1, 20],
-        ]);
+        // join the result
+        let (res, _) = join_m_pass(&left_run, &right_runs, &pre, None);
+        // expected
+        let target = create_tuple_list(vec![
+            vec![5, 17, 6, 17],
+            vec![3, 18, 5, 18],
+            vec![1, 20, 1, 20],
+        ]);
+
+        let ts = get_int_table_schema(4);
+
+        let mut target_op = Box::new(TupleIterator::new(target, ts.clone()));
+        let mut res_op = Box::new(TupleIterator::new(res, ts.clone()));
+        target_op.open()?;
+        res_op.open()?;
+        match_all_tuples(target_op, res_op)
+    }
+
+    fn test_sort_m_way_l3(){
+        let run1 = create_tuple_list(vec![
+            vec![5, 17], vec![3, 18], vec![7, 19], vec![1, 20],
+            vec![1, 21], vec![3, 22], vec![5, 23], vec![7, 24]]);
+        let _run2 = create_tuple_list(vec![
+            vec![5, 9], vec![3, 10], vec![7, 11], vec![1, 12],
+            vec![1, 13], vec![3, 14], vec![5, 15], vec![7, 16]]);
+        let _run3 = create_tuple_list(vec![
+            vec![5, 1], vec![3, 2], vec![7, 3], vec![1, 4],
+            vec![1, 5], vec![3, 6], vec![5, 7], vec![7, 8]]);
+        // let tuples = vec![run1, run2, run3];
+        let tuples = vec![run1];
+        let splitters = range_splitters(&Field::IntField(17), &Field::IntField(24), M_WAY_PARTITIONS);
+        let res = sort_m_way_l3(tuples, &splitters, 1, None);
+        // assert_eq!(
+        //     create_tuple_list(vec![
+        //         vec![5, 1], vec![3, 2], vec![7, 3], vec![1, 4],
+        //         vec![1, 5], vec![3, 6], vec![5, 7], vec![7, 8]]),
+        //     *res.get(0).unwrap());
+        // assert_eq!(
+        //     create_tuple_list(vec![
+        //         vec![5, 9], vec![3, 10], vec![7, 11], vec![1, 12],
+        //         vec![1, 13], vec![3, 14], vec![5, 15], vec![7, 16]]),
+        //     *res.get(1).unwrap());
+        // assert_eq!(
+        //     create_tuple_list(vec![
+        //         vec![5, 17], vec![3, 18], vec![7, 19], vec![1, 20],
+        //         vec![1, 21], vec![3, 22], vec![5, 23], vec![7, 24]]),
+        //     *res.get(2).unwrap());
+        assert_eq!(
+            create_tuple_list(vec![vec![5, 17], vec![3, 18], vec![7, 19],]),
+            *res.first().unwrap());
+        assert_eq!(
+            create_tuple_list(vec![vec![1, 20], vec![1, 21]]),
+            *res.get(1).unwrap());
+        assert_eq!(
+            create_tuple_list(vec![vec![3, 22], vec![5, 23], vec![7, 24]]),
+            *res.get(2).unwrap());
+    }
+
+    fn test_merge_pairs() {
+        let run1 = create_tuple_list(vec![
+            vec![5, 17], vec![3, 18], vec![7, 19], vec![1, 20]]);
+        let run2 = create_tuple_list(vec![
+            vec![5, 9], vec![3, 10], vec![7, 11], vec![1, 12]]);
+        let tuples = vec![run1, run2];
+        let res = merge_pairs(tuples, 1, None);
+        let expected = vec![create_tuple_list(vec![
+            vec![5, 9], vec![3, 10], vec![7, 11], vec![1, 12],
+            vec![5, 17], vec![3, 18], vec![7, 19], vec![1, 20]])];
+        assert_eq!(res, expected);
+    }
+
+    fn test_level_one_sort() {
+        let mut tuples = create_tuple_list(vec![vec![1, 8], vec![3, 2], vec![5, 1], vec![7, 4]]);
+        tuples = sort_run_l1(tuples, 1);
+        assert_eq!(create_tuple_list(vec![vec![5, 1], vec![3, 2], vec![7, 4], vec![1, 8]]),
+                   tuples);
+    }
+
+    fn test_merge_runs() {
+        let runs = vec![
+            create_tuple_list(vec![vec![5, 1], vec![3, 2], vec![7, 4], vec![1, 8]]),
+            create_tuple_list(vec![]),
+            create_tuple_list(vec![vec![7, 0], vec![5, 5], vec![3, 7], vec![1, 9]]),
+            create_tuple_list(vec![vec![9, 2], vec![9, 4]]),
+        ];
+        let res = merge_runs(runs, 1);
+        assert_eq!(
+            create_tuple_list(vec![vec![7, 0], vec![5, 1], vec![3, 2], vec![9, 2], vec![7, 4],
+                                   vec![9, 4], vec![5, 5], vec![3, 7], vec![1, 8], vec![1, 9]]),
+            res);
+    }
+
+    fn test_final(
+        ty: JoinType,
+        op: SimplePredicateOp,
+        left_index: usize,
+        right_index: usize,
+        strategy: MergeStrategy,
+    ) {
+        let s1 = Box::new(scan1());
+        let s2 = Box::new(scan2());
+        let m_way = matches!(strategy, MergeStrategy::MWay { .. });
+        let mut op = match ty {
+            JoinType::SortMerge => Box::new(SortMergeJoin::new(op, left_index, right_index, s1, s2, strategy).unwrap()),
+            JoinType::NestedLoop => Box::new(SortMergeJoin::new(op, left_index, right_index, s1, s2, strategy).unwrap()),
+            JoinType::HashEq => Box::new(SortMergeJoin::new(op, left_index, right_index, s1, s2, strategy).unwrap()),
+        };
+        // the expected m-way partitions split the right key range into equal thirds
+        op.set_splitter_method(SplitterMethod::EqualRange);
+        op.open().unwrap();
+        op.next().unwrap();
+        let res = op.l3_runs_l.clone();
+        if m_way {
+            assert_eq!(res, vec![
+                create_tuple_list(vec![vec![5, 2, 1, 2, 3], vec![3, 3, 2, 3, 4], vec![1, 4, 3, 4, 5]]),
+                create_tuple_list(vec![vec![7, 5, 4, 5, 6], vec![5, 6, 3, 6, 5], vec![3, 7, 2, 7, 4],]),
+                create_tuple_list(vec![]),
+            ]);
+        } else {
+            assert_eq!(res,
+                       vec![create_tuple_list(vec![
+                           vec![5, 2, 1, 2, 3],
+                           vec![3, 3, 2, 3, 4],
+                           vec![1, 4, 3, 4, 5],
+                           vec![7, 5, 4, 5, 6],
+                           vec![5, 6, 3, 6, 5],
+                           vec![3, 7, 2, 7, 4],
+                       ])]);
+        }
+
+    }
+
+    fn test_sort_levels(target_run_size: usize, strategy: MergeStrategy, kernel: SortKernel) -> Result<(), CrustyError> {
+        let s1 = Box::new(scan1());
+        let s2 = Box::new(scan2());
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, strategy)?;
+        op.set_sort_config(SortConfig::new(4, target_run_size));
+        op.set_sort_kernel(kernel);
+        op.open()?;
+        op.next()?;
+        let mut res: Vec<Tuple> = op.l3_runs_l.concat();
+        let mut expected = eq_join();
+        expected.open()?;
+        let mut target = Vec::new();
+        while let Some(t) = expected.next()? {
+            target.push(t);
+        }
+        res.sort_by_key(|t| t.field_vals.clone());
+        target.sort_by_key(|t| t.field_vals.clone());
+        assert_eq!(target, res);
+        Ok(())
+    }
+
+    // joins every pair of runs on the calling thread
+    struct SingleThread;
+
+    impl Level3Strategy for SingleThread {
+        fn join(&self, runs_l: Vec<Vec<Tuple>>, runs_r: Vec<Vec<Tuple>>, predicate: &JoinPredicate) -> Vec<Vec<Tuple>> {
+            let mut res = Vec::new();
+            for run_l in &runs_l {
+                for run_r in &runs_r {
+                    join_runs(run_l, run_r, predicate, &mut res);
+                }
+            }
+            vec![res]
+        }
+    }
+
+    fn test_strategies() -> Result<(), CrustyError> {
+        let mut expected = Vec::new();
+        let mut oracle = eq_join();
+        oracle.open()?;
+        while let Some(t) = oracle.next()? {
+            expected.push(t);
+        }
+        expected.sort_by_key(|t| t.field_vals.clone());
+        let strategies = [
+            MergeStrategy::MWay { partitions: 1 },
+            MergeStrategy::MWay { partitions: 5 },
+            MergeStrategy::MPass { fan_in: 2 },
+            MergeStrategy::MPass { fan_in: 8 },
+            MergeStrategy::Radix,
+            MergeStrategy::Custom(Arc::new(SingleThread)),
+        ];
+        for strategy in strategies {
+            let m_way = matches!(strategy, MergeStrategy::MWay { .. });
+            let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, Box::new(scan1()), Box::new(scan2()), strategy)?;
+            op.open()?;
+            if m_way {
+                assert_eq!(op.partition_sizes().len(), op.l3_runs_l.len());
+            }
+            op.next()?;
+            let mut res = op.l3_runs_l.concat();
+            res.sort_by_key(|t| t.field_vals.clone());
+            assert_eq!(expected, res);
+        }
+        Ok(())
+    }
+
+    fn test_request_order(strategy: MergeStrategy) -> Result<(), CrustyError> {
+        // left child is sorted on the join key, right child is not
+        let left = create_tuple_list(vec![
+            vec![1, 1], vec![5, 2], vec![3, 3], vec![1, 4],
+            vec![7, 5], vec![5, 6], vec![3, 7], vec![7, 8]]);
+        let mut s1 = Box::new(TupleIterator::new(left.clone(), get_int_table_schema(WIDTH1)));
+        let mut s2 = Box::new(scan2());
+        assert!(s1.request_order(1));
+        assert!(!s2.request_order(1));
+
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, strategy.clone())?;
+        op.open()?;
+        op.next()?;
+        let mut res: Vec<Tuple> = op.l3_runs_l.concat();
+        res.sort_by_key(|t| t.field_vals.clone());
+        let mut expected = create_tuple_list(vec![
+            vec![5, 2, 1, 2, 3], vec![3, 3, 2, 3, 4], vec![1, 4, 3, 4, 5],
+            vec![7, 5, 4, 5, 6], vec![5, 6, 3, 6, 5], vec![3, 7, 2, 7, 4]]);
+        expected.sort_by_key(|t| t.field_vals.clone());
+        assert_eq!(expected, res);
+
+        // the output of a sort declares its order, so the join skips sorting it again
+        let mut sorted = Box::new(Sort::new(Box::new(scan2()), vec![1]));
+        assert!(sorted.request_order(1));
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, Box::new(scan1()), sorted, strategy)?;
+        assert_eq!(test_sorted_output(&mut op)?.len(), expected.len());
+
+        // with runs of 2 tuples a child sorted again would be cut into 4 runs, a sorted one
+        // is kept as a single run
+        let runs = |left: Box<dyn OpIterator + Send>, right: Box<dyn OpIterator + Send>| -> Result<(usize, usize), CrustyError> {
+            let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, left, right, MergeStrategy::M_PASS)?;
+            op.set_sort_config(SortConfig::new(2, 2));
+            op.open()?;
+            Ok((op.l3_runs_l.len(), op.l3_runs_r.len()))
+        };
+        let s1 = Box::new(TupleIterator::new(left, get_int_table_schema(WIDTH1)));
+        assert_eq!(runs(s1, Box::new(scan2()))?, (1, 4));
+        assert_eq!(runs(Box::new(scan2()), Box::new(Sort::new(Box::new(scan2()), vec![1])))?, (4, 1));
+        Ok(())
+    }
+
+    fn test_output_order(strategy: MergeStrategy) -> Result<(), CrustyError> {
+        let data = |n: i32, width: usize| {
+            let tuples = (0..n).map(|i| vec![i, (i * 7) % 13]).collect();
+            Box::new(TupleIterator::new(create_tuple_list(tuples), get_int_table_schema(width)))
+        };
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, data(40, 2), data(30, 2), strategy)?;
+        // small runs make m-pass join many right runs
+        op.set_sort_config(SortConfig::new(2, 4));
+        assert_eq!(op.sort_order(), Some(vec![1]));
+        op.open()?;
+        let mut keys = Vec::new();
+        while let Some(t) = op.next()? {
+            keys.push(t.get_field(1).unwrap().unwrap_int_field());
+        }
+        assert!(!keys.is_empty());
+        assert!(keys.windows(2).all(|w| w[0] <= w[1]));
+
+        // a downstream join is sorted on the key without sorting it again
+        let mut upstream = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, data(8, 2), data(8, 2), MergeStrategy::M_PASS)?;
+        assert!(upstream.request_order(1));
+        // the right key stands in for a projected away left key
+        upstream.set_projection(vec![0, 3])?;
+        assert_eq!(upstream.sort_order(), Some(vec![1]));
+        upstream.set_projection(vec![0, 2])?;
+        assert_eq!(upstream.sort_order(), None);
+        Ok(())
+    }
+
+    fn test_sample_splitters() -> Result<(), CrustyError> {
+        // keys 1 to 8 and one outlier, equal thirds of the range put 8 keys in one partition
+        let data: Vec<Vec<i32>> = (1..=8).chain([1000]).map(|k| vec![k, k]).collect();
+        let mut sizes = Vec::new();
+        for method in [SplitterMethod::EqualRange, SplitterMethod::Sample(SPLITTER_SAMPLE_SIZE)] {
+            let s1 = Box::new(TupleIterator::new(create_tuple_list(data.clone()), get_int_table_schema(2)));
+            let s2 = Box::new(TupleIterator::new(create_tuple_list(data.clone()), get_int_table_schema(2)));
+            let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, MergeStrategy::M_WAY)?;
+            op.set_splitter_method(method);
+            op.open()?;
+            sizes.push(op.partition_sizes().to_vec());
+            op.next()?;
+            assert_eq!(op.l3_runs_l.concat().len(), 9);
+        }
+        assert_eq!(sizes[0], vec![(8, 8), (0, 0), (1, 1)]);
+        assert_eq!(sizes[1], vec![(3, 3), (3, 3), (3, 3)]);
+
+        // a frequent key fills its partition and leaves the next one empty rather than being split
+        let keys: Vec<Field> = [1, 1, 1, 1, 2, 3].into_iter().map(Field::IntField).collect();
+        let keys: Vec<&Field> = keys.iter().collect();
+        assert_eq!(sample_splitters(&keys, 3, SPLITTER_SAMPLE_SIZE), vec![Field::IntField(1), Field::IntField(1)]);
+        Ok(())
+    }
+
+    fn test_expr_keys() -> Result<(), CrustyError> {
+        // left.col0 + left.col1 = right.col2, checked against every pair of tuples
+        let sum = Expr::Column(0) + Expr::Column(1);
+        let mut expected = Vec::new();
+        let right = test_sorted_output(&mut scan2())?;
+        for l in test_sorted_output(&mut scan1())? {
+            for r in right.iter() {
+                if sum.eval(&l)? == *r.get_field(2).unwrap() {
+                    expected.push(l.merge(r));
+                }
+            }
+        }
+        expected.sort_by_key(|t| t.field_vals.clone());
+        assert!(!expected.is_empty());
+        for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS, MergeStrategy::Radix] {
+            let mut op = SortMergeJoin::builder()
+                .expr_predicate(SimplePredicateOp::Equals, sum.clone(), Expr::Column(2))
+                .left(Box::new(scan1()))
+                .right(Box::new(scan2()))
+                .strategy(strategy)
+                .build()?;
+            assert_eq!(op.get_schema(), &get_int_table_schema(WIDTH1 + WIDTH2));
+            assert_eq!(test_sorted_output(&mut op)?, expected);
+        }
+
+        // lower(left.col0) = right.col0 with the projection of a join on fields
+        let schema = TableSchema::from_vecs(vec!["name"], vec![DataType::String]);
+        let strings = |names: &[&str]| names.iter().map(|n| Tuple::new(vec![Field::StringField(n.to_string())])).collect();
+        let mut op = SortMergeJoin::builder()
+            .expr_predicate(SimplePredicateOp::Equals, Expr::lower(Expr::Column(0)), Expr::Column(0))
+            .left(Box::new(TupleIterator::new(strings(&["Ann", "BOB", "cy"]), schema.clone())))
+            .right(Box::new(TupleIterator::new(strings(&["ann", "bob", "Cy"]), schema.clone())))
+            .projection(vec![1, 0])
+            .build()?;
+        let pair = |r: &str, l: &str| Tuple::new(vec![Field::StringField(r.to_string()), Field::StringField(l.to_string())]);
+        assert_eq!(test_sorted_output(&mut op)?, vec![pair("ann", "Ann"), pair("bob", "BOB")]);
+
+        // keys must type check
+        let wrong = SortMergeJoin::builder()
+            .expr_predicate(SimplePredicateOp::Equals, Expr::lower(Expr::Column(0)), Expr::Column(0))
+            .left(Box::new(scan1()))
+            .right(Box::new(scan2()))
+            .build();
+        assert!(matches!(wrong, Err(CrustyError::ValidationError(_))));
+        Ok(())
+    }
+
+    fn test_collation() -> Result<(), CrustyError> {
+        let schema = TableSchema::from_vecs(vec!["name", "id"], vec![DataType::String, DataType::Int]);
+        let table = |names: &[&str]| -> Box<dyn OpIterator + Send> {
+            let tuples = names
+                .iter()
+                .enumerate()
+                .map(|(i, n)| Tuple::new(vec![Field::StringField(n.to_string()), Field::IntField(i as i32)]))
+                .collect();
+            Box::new(TupleIterator::new(tuples, schema.clone()))
+        };
+        let left = ["Ann", "bob", "BOB", "Cy", "dee"];
+        let right = ["ann", "Bob", "cy", "CY", "eve"];
+        let ids = |res: Vec<Tuple>| -> Vec<(i32, i32)> {
+            let mut ids: Vec<_> = res.iter().map(|t| (t.get_field(1).unwrap().unwrap_int_field(), t.get_field(3).unwrap().unwrap_int_field())).collect();
+            ids.sort();
+            ids
+        };
+        let case_insensitive = vec![(0, 0), (1, 1), (2, 1), (3, 2), (3, 3)];
+        for (collation, expected) in [(Collation::Binary, Vec::new()), (Collation::CaseInsensitive, case_insensitive)] {
+            let mut nested = Join::new(SimplePredicateOp::Equals, 0, 0, table(&left), table(&right))?;
+            nested.set_collation(collation);
+            assert_eq!(ids(test_sorted_output(&mut nested)?), expected);
+            for (partitions, radix_bits) in [(1, 0), (3, 2)] {
+                let mut hash = HashEqJoin::new(SimplePredicateOp::Equals, 0, 0, table(&left), table(&right))?;
+                hash.set_collation(collation);
+                hash.set_partitions(partitions);
+                hash.set_radix_bits(radix_bits);
+                assert_eq!(ids(test_sorted_output(&mut hash)?), expected);
+            }
+            for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS, MergeStrategy::Radix] {
+                let mut sort_merge = SortMergeJoin::builder()
+                    .predicate(SimplePredicateOp::Equals, 0, 0)
+                    .left(table(&left))
+                    .right(table(&right))
+                    .strategy(strategy)
+                    .collation(collation)
+                    .build()?;
+                assert_eq!(sort_merge.get_schema().size(), 4);
+                assert_eq!(ids(test_sorted_output(&mut sort_merge)?), expected);
+            }
+        }
+        // ordering predicates compare the sort keys too
+        let mut nested = Join::new(SimplePredicateOp::LessThan, 0, 0, table(&["bob"]), table(&["Ann", "Cy"]))?;
+        nested.set_collation(Collation::CaseInsensitive);
+        assert_eq!(ids(test_sorted_output(&mut nested)?), vec![(0, 1)]);
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_partitioned() -> Result<(), CrustyError> {
+        let left = crate::generator::create_vec_tuple(3000, 2, 400, 1);
+        let right = crate::generator::create_vec_tuple(2000, 3, 400, 2);
+        let scan = |tuples: &Vec<Tuple>, width| -> Box<dyn OpIterator + Send> {
+            Box::new(TupleIterator::new(tuples.clone(), get_int_table_schema(width)))
+        };
+        let expected = test_sorted_output(&mut Join::new(SimplePredicateOp::Equals, 1, 2, scan(&left, 2), scan(&right, 3))?)?;
+        for (bounds, partitions) in [(None, 4), (Some(vec![Field::IntField(100), Field::IntField(250)]), 3)] {
+            for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS] {
+                let mut op = PartitionedSortMergeJoin::new(1, 2, scan(&left, 2), scan(&right, 3), strategy)?;
+                op.set_partitions(partitions);
+                op.set_capacity(16);
+                if let Some(bounds) = bounds.clone() {
+                    op.set_bounds(bounds);
+                }
+                op.open()?;
+                let mut res = Vec::new();
+                while let Some(t) = op.next()? {
+                    res.push(t);
+                }
+                // the partitions are concatenated in key order
+                assert!(res.windows(2).all(|w| w[0].get_field(1) <= w[1].get_field(1)));
+                assert_eq!(op.partition_sizes().len(), partitions);
+                assert_eq!(op.partition_sizes().iter().sum::<usize>(), expected.len());
+                assert_eq!(op.metrics().rows_in, 5000);
+                op.rewind()?;
+                assert_eq!(op.next()?.as_ref(), res.first());
+                op.close()?;
+                res.sort_by(|a, b| a.field_vals.cmp(&b.field_vals));
+                assert_eq!(res, expected);
+                // opened again on the children returned by the exchanges
+                assert_eq!(test_sorted_output(&mut op)?, expected);
+            }
+        }
+        Ok(())
+    }
+
+    fn test_buffer_pool() -> Result<(), CrustyError> {
+        let left = crate::generator::create_vec_tuple(1000, 2, 500, 1);
+        let right = crate::generator::create_vec_tuple(3000, 2, 500, 2);
+        let scan = |tuples: &Vec<Tuple>| -> Box<dyn OpIterator + Send> {
+            Box::new(TupleIterator::new(tuples.clone(), get_int_table_schema(2)))
+        };
+        let expected = test_sorted_output(&mut Join::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right))?)?;
+        // the left runs take 8000 bytes, the right runs spill every 1000 tuples
+        for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS] {
+            let pool = Arc::new(BufferPool::new(4));
+            let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right), strategy)?;
+            op.set_memory_tracker(MemoryTracker::new(16000));
+            op.set_buffer_pool(pool.clone());
+            assert_eq!(test_sorted_output(&mut op)?, expected);
+            let metrics = op.metrics();
+            assert!(metrics.spills >= 2);
+            // every spilled run is read once, through the full pages
+            assert_eq!((metrics.page_hits, metrics.page_misses), (0, pool.stats().misses));
+            assert!(metrics.page_misses > 0);
+            assert_eq!(pool.pinned(), 0);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "lz4")]
+    fn test_spill_compression() -> Result<(), CrustyError> {
+        use crate::spill::SpillCompression;
+        let left = crate::generator::create_vec_tuple(1000, 2, 500, 1);
+        let right = crate::generator::create_vec_tuple(3000, 2, 500, 2);
+        let scan = |tuples: &Vec<Tuple>| TupleIterator::new(tuples.clone(), get_int_table_schema(2));
+        let expected = test_sorted_output(&mut Join::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right))?)?;
+        // the same runs spill, compressed to fewer pages
+        for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS, MergeStrategy::Radix] {
+            let mut spill_bytes = Vec::new();
+            for compression in [SpillCompression::None, SpillCompression::Lz4] {
+                let mut config = SortConfig::default();
+                config.set_spill_compression(compression);
+                let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right), strategy.clone())?;
+                op.set_sort_config(config);
+                op.set_memory_tracker(MemoryTracker::new(16000));
+                assert_eq!(test_sorted_output(&mut op)?, expected);
+                assert!(op.metrics().spills >= 2);
+                spill_bytes.push(op.metrics().spill_bytes);
+            }
+            assert!(spill_bytes[1] < spill_bytes[0], "{:?}", spill_bytes);
+        }
+        Ok(())
+    }
+
+    fn test_progress() -> Result<(), CrustyError> {
+        let left = crate::generator::create_vec_tuple(1000, 2, 500, 1);
+        let right = crate::generator::create_vec_tuple(3000, 2, 500, 2);
+        let scan = |tuples: &Vec<Tuple>| -> Box<dyn OpIterator + Send> {
+            Box::new(TupleIterator::new(tuples.clone(), get_int_table_schema(2)))
+        };
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let token = CancellationToken::new();
+        let mut monitor = ProgressMonitor::new();
+        monitor.set_cancellation_token(token.clone());
+        let (seen, cancel) = (reports.clone(), token.clone());
+        monitor.set_progress_callback(
+            Arc::new(move |progress: Progress| {
+                seen.lock().unwrap().push(progress);
+                if progress.phase == "sort right" && progress.rows == 2000 && !cancel.is_cancelled() {
+                    cancel.cancel();
+                }
+            }),
+            500,
+        );
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right), MergeStrategy::M_WAY)?;
+        op.set_progress_monitor(monitor.clone());
+        match op.open() {
+            Err(CrustyError::ExecutionError(msg)) => assert_eq!(msg, "cancelled"),
+            res => panic!("expected a cancellation, got {:?}", res),
+        }
+        let last = |phase| reports.lock().unwrap().iter().filter(|p| p.phase == phase).map(|p| p.rows).max();
+        assert_eq!((last("sort left"), last("sort right"), last("join")), (Some(1000), Some(2000), None));
+
+        // a fresh token lets the join run to completion, reporting every phase
+        reports.lock().unwrap().clear();
+        monitor.set_cancellation_token(CancellationToken::new());
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right), MergeStrategy::M_WAY)?;
+        op.set_progress_monitor(monitor.clone());
+        test_sorted_output(&mut op)?;
+        assert_eq!((last("sort left"), last("sort right"), last("join")), (Some(1000), Some(3000), Some(1000)));
+
+        // a passed deadline stops the join between the sorts of its children
+        let mut expired = ProgressMonitor::new();
+        expired.set_deadline(crate::metrics::Instant::now());
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right), MergeStrategy::M_WAY)?;
+        op.set_progress_monitor(expired);
+        match op.open() {
+            Err(CrustyError::ExecutionError(msg)) => assert_eq!(msg, "deadline exceeded"),
+            res => panic!("expected a timeout, got {:?}", res),
+        }
+
+        // the hash join stops building on the cancelled token
+        monitor.set_cancellation_token(token);
+        let mut hash = HashEqJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right))?;
+        hash.set_progress_monitor(monitor);
+        assert!(hash.open().is_err());
+        Ok(())
+    }
+
+    fn test_stream_right() -> Result<(), CrustyError> {
+        let left = crate::generator::create_vec_tuple(1000, 2, 500, 1);
+        let right = crate::generator::create_vec_tuple(3000, 2, 500, 2);
+        let scan = |tuples: &Vec<Tuple>| -> Box<dyn OpIterator + Send> {
+            Box::new(TupleIterator::new(tuples.clone(), get_int_table_schema(2)))
+        };
+        let expected = test_sorted_output(&mut Join::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right))?)?;
+        for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS, MergeStrategy::Radix] {
+            // the left run of 8000 bytes and a right chunk of 4000 bytes are held at once
+            let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right), strategy)?;
+            op.set_stream_right(500);
+            assert_eq!(op.sort_order(), None);
+            assert_eq!(test_sorted_output(&mut op)?, expected);
+            assert_eq!(op.memory_tracker().peak(), 12000);
+            assert_eq!(op.memory_tracker().used(), 0);
+            assert_eq!(op.metrics().rows_in, 4000);
+
+            // rewound, the right child is streamed again
+            op.open()?;
+            let first = std::iter::from_fn(|| op.next().unwrap()).count();
+            op.rewind()?;
+            assert_eq!(op.metrics().rows_in, 1000);
+            assert_eq!(std::iter::from_fn(|| op.next().unwrap()).count(), first);
+            assert_eq!(op.metrics().rows_in, 4000);
+            op.close()?;
+        }
+
+        // a sorted right child is streamed as it is, and the output stays in key order
+        let sorted = || Box::new(Sort::new(scan(&right), vec![1]));
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), sorted(), MergeStrategy::M_WAY)?;
+        op.set_stream_right(128);
+        assert_eq!(op.sort_order(), Some(vec![1]));
+        op.open()?;
+        let res: Vec<Tuple> = std::iter::from_fn(|| op.next().unwrap()).collect();
+        assert!(res.windows(2).all(|w| w[0].get_field(1) <= w[1].get_field(1)));
+        let mut res = res;
+        res.sort_by(|a, b| a.field_vals.cmp(&b.field_vals));
+        assert_eq!(res, expected);
+
+        // other predicates join each chunk with the whole left run
+        let small = left[..100].to_vec();
+        let expected = test_sorted_output(&mut Join::new(SimplePredicateOp::GreaterThan, 1, 1, scan(&small), scan(&right))?)?;
+        assert!(!expected.is_empty());
+        let mut op = SortMergeJoin::builder()
+            .predicate(SimplePredicateOp::GreaterThan, 1, 1)
+            .left(scan(&small))
+            .right(scan(&right))
+            .stream_right(700)
+            .build()?;
+        assert_eq!(test_sorted_output(&mut op)?, expected);
+        Ok(())
+    }
+
+    fn test_key_range(strategy: MergeStrategy) -> Result<(), CrustyError> {
+        // left keys in 900..1900 and right keys in 0..1000, a tenth of the right tuples join
+        let left = crate::generator::create_vec_tuple(500, 2, 1900, 1);
+        let right = crate::generator::create_vec_tuple(3000, 2, 1000, 2);
+        let scan = |tuples: &Vec<Tuple>| TupleIterator::new(tuples.clone(), get_int_table_schema(2));
+        let heap = |tuples: &Vec<Tuple>| -> Result<HeapFileScan, CrustyError> {
+            let mut file = HeapFile::temp()?;
+            for t in tuples {
+                file.insert_tuple(t)?;
+            }
+            Ok(HeapFileScan::new(file, get_int_table_schema(2)))
+        };
+        let keys = || left.iter().map(|t| t.get_field(1).unwrap().clone());
+        let range = keys().min().unwrap()..=keys().max().unwrap();
+        let kept = right.iter().filter(|t| range.contains(t.get_field(1).unwrap())).count();
+        assert!(kept < right.len() / 5);
+
+        let expected = test_sorted_output(&mut Join::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right))?)?;
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), heap(&right)?, strategy.clone())?;
+        assert_eq!(test_sorted_output(&mut op)?, expected);
+        assert_eq!(op.left_key_range(), Some(&range));
+        assert_eq!(op.metrics().rows_in, left.len() + kept);
+
+        // the range holds for a streamed right child, other predicates read all of it
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), heap(&right)?, strategy.clone())?;
+        op.set_stream_right(256);
+        assert_eq!(test_sorted_output(&mut op)?, expected);
+        assert_eq!(op.left_key_range(), Some(&range));
+        let few = left[..20].to_vec();
+        let mut op = SortMergeJoin::new(SimplePredicateOp::GreaterThan, 1, 1, scan(&few), heap(&right)?, strategy)?;
+        test_sorted_output(&mut op)?;
+        assert_eq!((op.left_key_range(), op.metrics().rows_in), (None, few.len() + right.len()));
+        Ok(())
+    }
+
+    fn test_unique_right(strategy: MergeStrategy) -> Result<(), CrustyError> {
+        // many left tuples reference each unique right key
+        let left = crate::generator::create_vec_tuple(3000, 2, 1000, 1);
+        let mut right: Vec<Tuple> = (0..1000).map(|i| Tuple::new(vec![Field::IntField(i), Field::IntField(i)])).collect();
+        let scan = |tuples: &Vec<Tuple>| TupleIterator::new(tuples.clone(), get_int_table_schema(2));
+        let expected = test_sorted_output(&mut Join::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right))?)?;
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right), strategy.clone())?;
+        op.set_unique_right(true);
+        assert_eq!(test_sorted_output(&mut op)?, expected);
+        assert!(op.metrics().dup_blocks > 0);
+
+        // a joined right key found twice in a right run fails the join
+        let key = left[0].get_field(1).unwrap().unwrap_int_field() as usize;
+        right.insert(key + 1, right[key].clone());
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right), strategy)?;
+        op.set_unique_right(true);
+        match test_sorted_output(&mut op) {
+            Err(CrustyError::ValidationError(_)) => Ok(()),
+            other => panic!("expected a ValidationError, got {:?}", other),
+        }
+    }
+
+    fn test_unique_left(strategy: MergeStrategy) -> Result<(), CrustyError> {
+        let unique = |keys: std::ops::Range<i32>| keys.map(|i| Tuple::new(vec![Field::IntField(i), Field::IntField(i)])).collect::<Vec<_>>();
+        let many = crate::generator::create_vec_tuple(3000, 2, 1000, 1);
+        let scan = |tuples: &Vec<Tuple>| TupleIterator::new(tuples.clone(), get_int_table_schema(2));
+        // unique left keys with duplicate right keys, then 1:1 keys overlapping by half
+        for (mut left, right, unique_right) in [(unique(0..1000), many, false), (unique(0..1000), unique(500..1500), true)] {
+            let expected = test_sorted_output(&mut Join::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right))?)?;
+            let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right), strategy.clone())?;
+            op.set_unique_left(true);
+            op.set_unique_right(unique_right);
+            assert_eq!(test_sorted_output(&mut op)?, expected);
+            assert_eq!(op.metrics().dup_blocks > 0, !unique_right);
+
+            left.insert(700, left[699].clone());
+            let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right), strategy.clone())?;
+            op.set_unique_left(true);
+            op.set_unique_right(unique_right);
+            assert!(matches!(test_sorted_output(&mut op), Err(CrustyError::ValidationError(_))));
+        }
+        Ok(())
+    }
+
+    fn test_greater_than(strategy: MergeStrategy) -> Result<(), CrustyError> {
+        let (left, right) = (crate::generator::create_vec_tuple(200, 2, 1000, 1), crate::generator::create_vec_tuple(300, 2, 1000, 2));
+        let scan = |tuples: &Vec<Tuple>| TupleIterator::new(tuples.clone(), get_int_table_schema(2));
+        for op in [SimplePredicateOp::GreaterThan, SimplePredicateOp::GreaterThanOrEq] {
+            let expected = test_sorted_output(&mut Join::new(op, 1, 1, scan(&left), scan(&right))?)?;
+            let mut join = SortMergeJoin::new(op, 1, 1, scan(&left), scan(&right), strategy.clone())?;
+            join.set_sort_config(SortConfig::new(16, 64));
+            // matches cross the m-way partitions
+            assert_eq!(test_sorted_output(&mut join)?, expected);
+        }
+        Ok(())
+    }
+
+    fn test_checkpoint() -> Result<(), CrustyError> {
+        let left = crate::generator::create_vec_tuple(1000, 2, 500, 1);
+        let right = crate::generator::create_vec_tuple(3000, 2, 500, 2);
+        let scan = |tuples: &Vec<Tuple>| TupleIterator::new(tuples.clone(), get_int_table_schema(2));
+        let expected = test_sorted_output(&mut Join::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right))?)?;
+        let dir = std::env::temp_dir().join(format!("join-checkpoint-{}", std::process::id()));
+        for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS] {
+            // the left runs take 8000 bytes, the right runs spill every 1000 tuples until cancelled
+            let token = CancellationToken::new();
+            let mut monitor = ProgressMonitor::new();
+            monitor.set_cancellation_token(token.clone());
+            let cancel = token.clone();
+            monitor.set_progress_callback(
+                Arc::new(move |progress: Progress| {
+                    if progress.phase == "sort right" && progress.rows == 2500 {
+                        cancel.cancel();
+                    }
+                }),
+                500,
+            );
+            let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right), strategy.clone())?;
+            op.set_memory_tracker(MemoryTracker::new(16000));
+            op.set_progress_monitor(monitor);
+            assert!(op.open().is_err());
+            let state = op.save_state(&dir)?;
+            op.close()?;
+            assert_eq!((state.left.rows, state.left.sorted), (1000, true));
+            assert_eq!((state.right.rows, state.right.sorted, state.right.runs.len()), (2000, false, 2));
+            let run = &state.right.runs[0];
+            assert!(run.min_key <= run.max_key && run.rows == 1000);
+
+            // resumed, only the last 1000 right tuples are sorted
+            let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right), strategy)?;
+            op.set_memory_tracker(MemoryTracker::new(16000));
+            assert_eq!(op.restore_state(&dir)?, state);
+            assert_eq!(test_sorted_output(&mut op)?, expected);
+            assert_eq!(op.metrics().spills, 2);
+            fs::remove_dir_all(&dir)?;
+        }
+
+        // sorted runs saved after an open, restored without reading the children
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right), MergeStrategy::M_WAY)?;
+        op.open()?;
+        let state = op.save_state(&dir)?;
+        assert!(state.left.sorted && state.right.sorted);
+        op.next()?;
+        assert!(matches!(op.save_state(&dir), Err(CrustyError::ExecutionError(_))));
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&Vec::new()), scan(&Vec::new()), MergeStrategy::M_WAY)?;
+        op.restore_state(&dir)?;
+        assert_eq!(test_sorted_output(&mut op)?, expected);
+
+        // a join on other keys cannot resume
+        let mut other = SortMergeJoin::new(SimplePredicateOp::Equals, 0, 1, scan(&left), scan(&right), MergeStrategy::M_WAY)?;
+        assert!(matches!(other.restore_state(&dir), Err(CrustyError::ValidationError(_))));
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    fn test_top_k() -> Result<(), CrustyError> {
+        let left = crate::generator::create_vec_tuple(500, 2, 100, 1);
+        let right = crate::generator::create_vec_tuple(500, 2, 100, 2);
+        let scan = |tuples: &Vec<Tuple>| -> Box<dyn OpIterator + Send> {
+            Box::new(TupleIterator::new(tuples.clone(), get_int_table_schema(2)))
+        };
+        let mut full = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right), MergeStrategy::M_WAY)?;
+        let all = test_sorted_output(&mut full)?;
+        // the key column stops the merge early, the first column keeps the k smallest of every tuple
+        for (column, k) in [(1, 10), (1, 0), (0, 25), (1, all.len() + 5)] {
+            let mut column_values: Vec<&Field> = all.iter().map(|t| t.get_field(column).unwrap()).collect();
+            column_values.sort();
+            for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS, MergeStrategy::Radix] {
+                let mut op = SortMergeJoin::builder()
+                    .predicate(SimplePredicateOp::Equals, 1, 1)
+                    .left(scan(&left))
+                    .right(scan(&right))
+                    .strategy(strategy)
+                    .workers(2)
+                    .top_k(k, column)
+                    .build()?;
+                op.set_morsel_size(16);
+                assert_eq!(op.sort_order(), Some(vec![column]));
+                op.open()?;
+                let mut res = Vec::new();
+                while let Some(t) = op.next()? {
+                    res.push(t);
+                }
+                let values: Vec<&Field> = res.iter().map(|t| t.get_field(column).unwrap()).collect();
+                assert_eq!(values, column_values[..k.min(all.len())]);
+                assert!(res.iter().all(|t| all.contains(t)));
+                let comparisons = op.metrics().comparisons;
+                op.close()?;
+                if column == 1 && k < all.len() {
+                    op.top_k = None;
+                    test_sorted_output(&mut op)?;
+                    assert!(comparisons < op.metrics().comparisons);
+                }
+            }
+        }
+        assert!(full.set_top_k(TopK::new(1, 4)).is_err());
+        Ok(())
+    }
+
+    fn test_deterministic_order() -> Result<(), CrustyError> {
+        // few keys, so every key has many joined tuples
+        let left = crate::generator::create_vec_tuple(300, 3, 10, 1);
+        let right = crate::generator::create_vec_tuple(300, 3, 10, 2);
+        let scan = |tuples: &Vec<Tuple>| -> Box<dyn OpIterator + Send> {
+            Box::new(TupleIterator::new(tuples.clone(), get_int_table_schema(3)))
+        };
+        let mut expected = Vec::new();
+        for l in &left {
+            for r in &right {
+                if l.get_field(1) == r.get_field(1) {
+                    expected.push(l.merge(r));
+                }
+            }
+        }
+        expected.sort_by(|a, b| a.get_field(1).cmp(&b.get_field(1)).then_with(|| a.field_vals.cmp(&b.field_vals)));
+        let collect = |op: &mut dyn OpIterator| -> Result<Vec<Tuple>, CrustyError> {
+            op.open()?;
+            let mut res = Vec::new();
+            while let Some(t) = op.next()? {
+                res.push(t);
+            }
+            op.close()?;
+            Ok(res)
+        };
+        for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS, MergeStrategy::Radix] {
+            for workers in [1, 4] {
+                let mut op = SortMergeJoin::builder()
+                    .predicate(SimplePredicateOp::Equals, 1, 1)
+                    .left(scan(&left))
+                    .right(scan(&right))
+                    .strategy(strategy.clone())
+                    .workers(workers)
+                    .deterministic_order()
+                    .build()?;
+                op.set_morsel_size(16);
+                assert_eq!(collect(&mut op)?, expected, "{:?} on {} workers", strategy, workers);
+                // ties of the top-k bound are the tuples smallest on all their fields
+                op.set_top_k(TopK::new(50, 1))?;
+                assert_eq!(collect(&mut op)?, expected[..50]);
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let mut partitioned = PartitionedSortMergeJoin::new(1, 1, scan(&left), scan(&right), MergeStrategy::M_WAY)?;
+            partitioned.set_deterministic_order(true);
+            assert_eq!(collect(&mut partitioned)?, expected);
+        }
+        Ok(())
+    }
+
+    fn test_morsels() -> Result<(), CrustyError> {
+        let mut expected = Vec::new();
+        let mut oracle = eq_join();
+        oracle.open()?;
+        while let Some(t) = oracle.next()? {
+            expected.push(t);
+        }
+        expected.sort_by_key(|t| t.field_vals.clone());
+        for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS, MergeStrategy::Radix] {
+            // more workers than runs, and morsels splitting the runs between equal keys
+            let mut sort_config = SortConfig::default();
+            sort_config.set_sort_cores(CoreAffinity::new(0, 2));
+            let mut op = SortMergeJoin::builder()
+                .predicate(SimplePredicateOp::Equals, 1, 1)
+                .left(Box::new(scan1()))
+                .right(Box::new(scan2()))
+                .strategy(strategy)
+                .sort_config(sort_config)
+                .workers(8)
+                .build()?;
+            op.set_morsel_size(1);
+            op.set_merge_cores(CoreAffinity::new(0, 2));
+            assert_eq!(op.workers(), 8);
+            op.open()?;
+            op.next()?;
+            let mut res = op.l3_runs_l.concat();
+            res.sort_by_key(|t| t.field_vals.clone());
+            assert_eq!(expected, res);
+        }
+        Ok(())
+    }
+
+    fn test_radix_cores() -> Result<(), CrustyError> {
+        let expected = test_sorted_output(&mut eq_join())?;
+        let cores = CoreAffinity::new(0, 2);
+        let mut sort_config = SortConfig::default();
+        sort_config.set_sort_cores(cores);
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan1(), scan2(), MergeStrategy::Radix)?;
+        op.set_sort_config(sort_config);
+        // the whole input run keeps the sort cores, and each core radix sorts half of it
+        let (config, kernel) = op.run_formation();
+        assert_eq!((config.sort_cores, kernel), (Some(cores), SortKernel::Radix));
+        let tuples = create_tuple_list((0..101).rev().map(|i| vec![i, i % 7]).collect());
+        let runs = form_runs(kernel, tuples.clone(), 1, &config, 8);
+        assert_eq!(runs.iter().map(Vec::len).collect::<Vec<_>>(), vec![51, 50]);
+        assert!(runs.iter().all(|run| run.windows(2).all(|w| w[0].get_field(1) <= w[1].get_field(1))));
+        let unpinned = SortConfig { sort_cores: None, ..config };
+        assert_eq!(form_runs(kernel, tuples, 1, &unpinned, 8).len(), 1);
+        assert_eq!(test_sorted_output(&mut op)?, expected);
+        Ok(())
+    }
+
+    fn test_string_keys() -> Result<(), CrustyError> {
+        let splitters = range_splitters(&Field::StringField("apple".into()), &Field::StringField("apricot".into()), 3);
+        assert_eq!(splitters.len(), 2);
+        assert!(splitters.iter().all(|s| matches!(s, Field::StringField(s) if s.as_str() > "apple" && s.as_str() < "apricot")));
+
+        let words = ["pear", "apple", "fig", "kiwi", "plum", "date", "lime", "apple", "fig"];
+        let schema = TableSchema::from_vecs(vec!["id", "name"], vec![DataType::Int, DataType::String]);
+        let tuples: Vec<Tuple> = words
+            .iter()
+            .enumerate()
+            .map(|(i, w)| Tuple::new(vec![Field::IntField(i as i32), Field::StringField(w.to_string())]))
+            .collect();
+        let scan = || Box::new(TupleIterator::new(tuples.clone(), schema.clone()));
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(), scan(), MergeStrategy::M_WAY)?;
+        op.set_splitter_method(SplitterMethod::EqualRange);
+        // apple and fig match twice on each side
+        assert_eq!(test_sorted_output(&mut op)?.len(), 5 + 4 + 4);
+        // the keys are spread over the partitions
+        assert!(op.partition_sizes().iter().filter(|(l, _)| *l > 0).count() > 1);
+
+        // the m-way workers join dictionary codes, the kept keys are strings again
+        for op in [SimplePredicateOp::Equals, SimplePredicateOp::GreaterThan] {
+            for projection in [None, Some(vec![3, 0])] {
+                let mut nested = Join::new(op, 1, 1, scan(), scan())?;
+                let mut sort_merge = SortMergeJoin::new(op, 1, 1, scan(), scan(), MergeStrategy::MWay { partitions: 3 })?;
+                if let Some(columns) = &projection {
+                    nested.set_projection(columns.clone())?;
+                    sort_merge.set_projection(columns.clone())?;
+                }
+                assert_eq!(test_sorted_output(&mut sort_merge)?, test_sorted_output(&mut nested)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn test_invalid_fields() {
+        let strings = || {
+            let schema = TableSchema::from_vecs(vec!["id", "name"], vec![DataType::Int, DataType::String]);
+            Box::new(TupleIterator::new(Vec::new(), schema))
+        };
+        let invalid = |res: Result<(), CrustyError>, message: &str| match res {
+            Err(CrustyError::ValidationError(e)) => assert!(e.contains(message), "{}", e),
+            _ => panic!("expected a validation error"),
+        };
+        invalid(Join::new(SimplePredicateOp::Equals, 2, 1, Box::new(scan1()), Box::new(scan2())).map(|_| ()), "left join index 2");
+        invalid(HashEqJoin::new(SimplePredicateOp::Equals, 1, 5, Box::new(scan1()), Box::new(scan2())).map(|_| ()), "right join index 5");
+        invalid(
+            SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, Box::new(scan1()), strings(), MergeStrategy::M_WAY).map(|_| ()),
+            "of type Int with right field 1 of type String",
+        );
+        let builder = SortMergeJoin::builder()
+            .predicate(SimplePredicateOp::Equals, 0, 1)
+            .left(Box::new(scan1()))
+            .right(strings());
+        invalid(builder.build().map(|_| ()), "cannot join");
+        // fields of the same type at different positions are fine
+        assert!(SortMergeJoin::new(SimplePredicateOp::Equals, 0, 0, Box::new(scan1()), strings(), MergeStrategy::M_WAY).is_ok());
+    }
+
+    fn test_merge_predicate() -> Result<(), CrustyError> {
+        let scan = |seed| TupleIterator::new(crate::generator::create_vec_tuple(200, 2, 1000, seed), get_int_table_schema(2));
+        // the merge only scans right keys up to the left key, so smaller than would join nothing
+        for op in [SimplePredicateOp::LessThan, SimplePredicateOp::LessThanOrEq, SimplePredicateOp::NotEq, SimplePredicateOp::All] {
+            assert!(!test_sorted_output(&mut Join::new(op, 1, 1, scan(1), scan(2))?)?.is_empty());
+            for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS, MergeStrategy::Radix] {
+                let res = SortMergeJoin::new(op, 1, 1, scan(1), scan(2), strategy);
+                assert!(matches!(res, Err(CrustyError::ValidationError(_))));
+            }
+            let builder = SortMergeJoin::builder()
+                .predicate(op, 1, 1)
+                .left(Box::new(scan(1)))
+                .right(Box::new(scan(2)));
+            assert!(matches!(builder.build(), Err(CrustyError::ValidationError(_))));
+
+            // a custom strategy may evaluate it, but cannot be switched for a merge afterwards
+            let mut join = SortMergeJoin::new(op, 1, 1, scan(1), scan(2), MergeStrategy::Custom(Arc::new(SingleThread)))?;
+            for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS, MergeStrategy::Radix] {
+                assert!(matches!(join.set_strategy(strategy), Err(CrustyError::ValidationError(_))));
+            }
+            join.set_strategy(MergeStrategy::Custom(Arc::new(SingleThread)))?;
+            assert!(matches!(join.strategy, MergeStrategy::Custom(_)));
+        }
+        // the reverse comparison with the children swapped is evaluated
+        let expected = test_sorted_output(&mut Join::new(SimplePredicateOp::LessThan, 1, 1, scan(1), scan(2))?)?.len();
+        let mut op = SortMergeJoin::new(SimplePredicateOp::GreaterThan, 1, 1, scan(2), scan(1), MergeStrategy::M_PASS)?;
+        assert_eq!(test_sorted_output(&mut op)?.len(), expected);
+        Ok(())
+    }
+
+    fn test_named_predicate() -> Result<(), CrustyError> {
+        let orders = TableSchema::from_vecs(vec!["id", "customer_id", "amount"], vec![DataType::Int, DataType::Int, DataType::Int]);
+        let customers = TableSchema::from_vecs(vec!["id", "region"], vec![DataType::Int, DataType::String]);
+        let tables = || (("orders", &orders), ("customers", &customers));
+        let resolve = |op, left_name, right_name| {
+            let (left, right) = tables();
+            JoinPredicate::from_names(op, left_name, right_name, left, right)
+                .map(|p| (p.op(), p.left_index(), p.right_index()))
+        };
+        assert_eq!(resolve(SimplePredicateOp::Equals, "customer_id", "customers.id")?, (SimplePredicateOp::Equals, 1, 0));
+        assert_eq!(resolve(SimplePredicateOp::Equals, "orders.customer_id", "customers.id")?, (SimplePredicateOp::Equals, 1, 0));
+        // right table first
+        assert_eq!(resolve(SimplePredicateOp::LessThan, "customers.id", "amount")?, (SimplePredicateOp::GreaterThan, 2, 0));
+
+        let message = |res: Result<_, CrustyError>| match res {
+            Err(CrustyError::ValidationError(e)) => e,
+            _ => panic!("expected a validation error"),
+        };
+        assert!(message(resolve(SimplePredicateOp::Equals, "id", "customers.id")).contains("qualify it as orders.id or customers.id"));
+        assert!(message(resolve(SimplePredicateOp::Equals, "orders.id", "orders.amount")).contains("same table"));
+        assert!(message(resolve(SimplePredicateOp::Equals, "orders.name", "customers.id")).contains("no join field"));
+        assert!(message(resolve(SimplePredicateOp::Equals, "orders.id", "region")).contains("cannot join"));
+
+        let (left, right) = tables();
+        let predicate = JoinPredicate::from_names(SimplePredicateOp::Equals, "orders.customer_id", "customers.id", left, right)?;
+        let mut op = SortMergeJoin::builder()
+            .join_predicate(predicate)
+            .left(Box::new(TupleIterator::new(create_tuple_list(vec![vec![10, 1, 5], vec![11, 2, 7]]), orders.clone())))
+            .right(Box::new(TupleIterator::new(
+                vec![Tuple::new(vec![Field::IntField(2), Field::StringField("west".into())])],
+                customers.clone(),
+            )))
+            .aliases("orders", "customers")
+            .build()?;
+        assert_eq!(test_sorted_output(&mut op)?.len(), 1);
+        let schema = op.get_schema();
+        assert_eq!((schema.get_field_index("orders.id"), schema.get_field_index("customers.id")), (Some(&0), Some(&3)));
+
+        // fields named in their own schema, read back by name through a projection
+        let op = SortMergeJoin::builder()
+            .predicate_names(SimplePredicateOp::Equals, "customer_id", "id")
+            .left(Box::new(TupleIterator::new(create_tuple_list(vec![vec![10, 1, 5], vec![11, 2, 7]]), orders.clone())))
+            .right(Box::new(TupleIterator::new(
+                vec![Tuple::new(vec![Field::IntField(2), Field::StringField("west".into())])],
+                customers.clone(),
+            )))
+            .aliases("orders", "customers")
+            .build()?;
+        let mut project = Project::from_names(Box::new(op), &["customers.region", "orders.amount"])?;
+        let res = test_sorted_output(&mut project)?;
+        let row = SchemaBoundTuple::new(&res[0], project.get_schema());
+        assert_eq!((row.get_str("customers.region")?, row.get_int("orders.amount")?), ("west", 7));
+        assert_eq!(res[0].get_field_by_name(project.get_schema(), "orders.amount"), Some(&Field::IntField(7)));
+        assert!(Project::from_names(Box::new(scan1()), &["amount"]).is_err());
+        let predicate = JoinPredicate::from_schemas(SimplePredicateOp::Equals, "customer_id", "region", &orders, &customers);
+        assert!(message(predicate.map(|p| (p.op(), p.left_index(), p.right_index()))).contains("cannot join"));
+        Ok(())
+    }
+
+    fn test_duplicate_keys(strategy: MergeStrategy) -> Result<(), CrustyError> {
+        // every key repeats, key 2 has 6 tuples on the left and 5 on the right
+        let left: Vec<Vec<i32>> = (0..24).map(|i| vec![i, i % 4]).collect();
+        let right: Vec<Vec<i32>> = (0..15).map(|i| vec![i, (i % 3) * 2]).collect();
+        let s1 = Box::new(TupleIterator::new(create_tuple_list(left.clone()), get_int_table_schema(2)));
+        let s2 = Box::new(TupleIterator::new(create_tuple_list(right.clone()), get_int_table_schema(2)));
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, strategy)?;
+        op.open()?;
+        op.next()?;
+        let mut res: Vec<Tuple> = op.l3_runs_l.concat();
+        res.sort_by_key(|t| t.field_vals.clone());
+
+        // each matching pair exactly once
+        let mut expected = Vec::new();
+        for l in &left {
+            for r in &right {
+                if l[1] == r[1] {
+                    expected.push(vec![l[0], l[1], r[0], r[1]]);
+                }
+            }
+        }
+        let mut expected = create_tuple_list(expected);
+        expected.sort_by_key(|t| t.field_vals.clone());
+        assert_eq!(expected.len(), 6 * 5 + 6 * 5);
+        assert_eq!(expected, res);
+        // the runs may hold a key in several blocks, each with duplicates on the left
+        let metrics = op.metrics();
+        assert!(metrics.dup_blocks >= 2);
+        assert_eq!(metrics.dup_rows, expected.len());
+
+        // blocks are found by galloping, not by comparing every tuple of the cross product
+        let block: Vec<Tuple> = (0..300).map(|i| Tuple::new(vec![Field::IntField(i), Field::IntField(7)])).collect();
+        let pre = JoinPredicate::new(SimplePredicateOp::Equals, 1, 1);
+        let mut rows = 0;
+        let stats = join_runs_with(&block, &block, &pre, None, &mut |_| rows += 1);
+        assert_eq!((rows, stats.dup_blocks, stats.dup_rows), (90000, 1, 90000));
+        assert!(stats.comparisons < 40);
+        assert_eq!(key_block(&block[..5], 1, 2).0, KeyBlock { key: &Field::IntField(7), count: 3, first_row: 2 });
+        Ok(())
+    }
+
+    fn test_cross_join(block_size: usize) -> Result<(), CrustyError> {
+        let mut op = CrossJoin::new(Box::new(scan1()), Box::new(scan2()), block_size);
+        let mut oracle = Join::new(SimplePredicateOp::All, 0, 0, Box::new(scan1()), Box::new(scan2()))?;
+        op.open()?;
+        oracle.open()?;
+        let mut res = Vec::new();
+        while let Some(t) = op.next()? {
+            res.push(t);
+        }
+        let mut expected = Vec::new();
+        while let Some(t) = oracle.next()? {
+            expected.push(t);
+        }
+        assert_eq!(res.len(), 64);
+        res.sort_by_key(|t| t.field_vals.clone());
+        expected.sort_by_key(|t| t.field_vals.clone());
+        assert_eq!(expected, res);
+
+        op.rewind()?;
+        let mut count = 0;
+        while op.next()?.is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 64);
+        op.close()
+    }
+
+    fn test_cross_join_cap() -> Result<(), CrustyError> {
+        let mut op = CrossJoin::new(Box::new(scan1()), Box::new(scan2()), 4);
+        op.set_max_output(10);
+        op.open()?;
+        for _ in 0..10 {
+            assert!(op.next()?.is_some());
+        }
+        assert!(matches!(op.next(), Err(CrustyError::ExecutionError(_))));
+        Ok(())
+    }
+
+    fn test_as_of_join() -> Result<(), CrustyError> {
+        // left keys in 0..1000, right keys every third value from 0 to 897, in reverse order
+        let left = crate::generator::create_vec_tuple(400, 2, 1000, 3);
+        let right = create_tuple_list((0..300).rev().map(|i| vec![i, i * 3]).collect());
+        let scan = |tuples: &Vec<Tuple>| TupleIterator::new(tuples.clone(), get_int_table_schema(2));
+        let oracle = |tolerance: i32| {
+            let mut expected: Vec<Tuple> = left
+                .iter()
+                .filter_map(|t| {
+                    let key = t.get_field(1).unwrap().unwrap_int_field();
+                    let matched = right.iter().filter(|r| r.get_field(1).unwrap().unwrap_int_field() <= key);
+                    let matched = matched.max_by_key(|r| r.get_field(1).unwrap().unwrap_int_field())?;
+                    (key - matched.get_field(1).unwrap().unwrap_int_field() <= tolerance).then(|| t.merge(matched))
+                })
+                .collect();
+            expected.sort_by(|a, b| a.field_vals.cmp(&b.field_vals));
+            expected
+        };
+        let mut op = AsOfJoin::new(1, 1, scan(&left), scan(&right))?;
+        op.open()?;
+        let mut res = Vec::new();
+        while let Some(t) = op.next()? {
+            res.push(t);
+        }
+        // sorted on the left key, every left tuple matches as the right keys start at 0
+        assert!(res.windows(2).all(|w| w[0].get_field(1) <= w[1].get_field(1)));
+        assert_eq!(op.sort_order(), Some(vec![1]));
+        assert_eq!(res.len(), left.len());
+        op.rewind()?;
+        assert_eq!(op.next()?.as_ref(), res.first());
+        op.close()?;
+        res.sort_by(|a, b| a.field_vals.cmp(&b.field_vals));
+        assert_eq!(res, oracle(i32::MAX));
 
-        let ts = get_int_table_schema(4);
+        // left keys 2 past a right key have no match within 1
+        let mut op = AsOfJoin::new(1, 1, scan(&left), scan(&right))?;
+        op.set_tolerance(1)?;
+        let expected = oracle(1);
+        assert!(expected.len() < left.len());
+        assert_eq!(test_sorted_output(&mut op)?, expected);
+        assert!(op.set_tolerance(-1).is_err());
+        assert!(AsOfJoin::new(2, 1, scan(&left), scan(&right)).is_err());
 
-        let mut target_op = Box::new(TupleIterator::new(target, ts.clone()));
-        let mut res_op = Box::new(TupleIterator::new(res, ts.clone()));
-        target_op.open()?;
-        res_op.open()?;
-        match_all_tuples(target_op, res_op)
+        // a left key before every right key has no match
+        let early = create_tuple_list(vec![vec![0, -5], vec![1, 4]]);
+        let mut op = AsOfJoin::new(1, 1, scan(&early), scan(&right))?;
+        assert_eq!(test_sorted_output(&mut op)?, create_tuple_list(vec![vec![1, 4, 1, 3]]));
+        Ok(())
     }
 
-    fn test_sort_m_way_l3(){
-        let mut run1 = create_tuple_list(vec![
-            vec![5, 17], vec![3, 18], vec![7, 19], vec![1, 20],
-            vec![1, 21], vec![3, 22], vec![5, 23], vec![7, 24]]);
-        let mut run2 = create_tuple_list(vec![
-            vec![5, 9], vec![3, 10], vec![7, 11], vec![1, 12],
-            vec![1, 13], vec![3, 14], vec![5, 15], vec![7, 16]]);
-        let mut run3 = create_tuple_list(vec![
-            vec![5, 1], vec![3, 2], vec![7, 3], vec![1, 4],
-            vec![1, 5], vec![3, 6], vec![5, 7], vec![7, 8]]);
-        // let tuples = vec![run1, run2, run3];
-        let tuples = vec![run1];
-        let res = sort_m_way_l3(
-            tuples,
-            Tuple::new(vec![Field::IntField(5), Field::IntField(17)]),
-            Tuple::new(vec![Field::IntField(7), Field::IntField(24)]),
-            1);
-        // assert_eq!(
-        //     create_tuple_list(vec![
-        //         vec![5, 1], vec![3, 2], vec![7, 3], vec![1, 4],
-        //         vec![1, 5], vec![3, 6], vec![5, 7], vec![7, 8]]),
-        //     *res.get(0).unwrap());
-        // assert_eq!(
-        //     create_tuple_list(vec![
-        //         vec![5, 9], vec![3, 10], vec![7, 11], vec![1, 12],
-        //         vec![1, 13], vec![3, 14], vec![5, 15], vec![7, 16]]),
-        //     *res.get(1).unwrap());
-        // assert_eq!(
-        //     create_tuple_list(vec![
-        //         vec![5, 17], vec![3, 18], vec![7, 19], vec![1, 20],
-        //         vec![1, 21], vec![3, 22], vec![5, 23], vec![7, 24]]),
-        //     *res.get(2).unwrap());
-        assert_eq!(
-            create_tuple_list(vec![vec![5, 17], vec![3, 18], vec![7, 19],]),
-            *res.get(0).unwrap());
-        assert_eq!(
-            create_tuple_list(vec![vec![1, 20], vec![1, 21]]),
-            *res.get(1).unwrap());
-        assert_eq!(
-            create_tuple_list(vec![vec![3, 22], vec![5, 23], vec![7, 24]]),
-            *res.get(2).unwrap());
+    fn test_similarity_join() -> Result<(), CrustyError> {
+        use rand::Rng;
+        // short words of a small alphabet, so many pairs are similar
+        let mut rng = crate::generator::seeded_rng(5);
+        let mut words = |n: usize| -> Vec<Tuple> {
+            (0..n)
+                .map(|i| {
+                    let len = rng.gen_range(0..7);
+                    let word: String = (0..len).map(|_| ['a', 'b', 'c', 'é'][rng.gen_range(0..4)]).collect();
+                    Tuple::new(vec![Field::IntField(i as i32), Field::StringField(word)])
+                })
+                .collect()
+        };
+        let (left, right) = (words(150), words(200));
+        let schema = TableSchema::from_vecs(vec!["id", "word"], vec![DataType::Int, DataType::String]);
+        let scan = |tuples: &Vec<Tuple>| TupleIterator::new(tuples.clone(), schema.clone());
+        // full distance table of two words
+        let distance = |a: &str, b: &str| {
+            let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+            let mut prev: Vec<usize> = (0..=b.len()).collect();
+            for i in 1..=a.len() {
+                let mut cur = vec![i; b.len() + 1];
+                for j in 1..=b.len() {
+                    cur[j] = (prev[j - 1] + (a[i - 1] != b[j - 1]) as usize).min(prev[j] + 1).min(cur[j - 1] + 1);
+                }
+                prev = cur;
+            }
+            prev[b.len()]
+        };
+        let oracle = |similar: &dyn Fn(&str, &str) -> bool| {
+            let mut expected = Vec::new();
+            for l in &left {
+                for r in &right {
+                    if similar(l.get_field(1).unwrap().unwrap_string_field(), r.get_field(1).unwrap().unwrap_string_field()) {
+                        expected.push(l.merge(r));
+                    }
+                }
+            }
+            expected.sort_by(|a, b| a.field_vals.cmp(&b.field_vals));
+            expected
+        };
+        for k in 0..3 {
+            let mut op = SimilarityJoin::new(Similarity::EditDistance(k), 1, 1, scan(&left), scan(&right))?;
+            assert_eq!(test_sorted_output(&mut op)?, oracle(&|a, b| distance(a, b) <= k));
+            // the length window skips the pairs of keys too different in length
+            assert!(op.metrics().comparisons < left.len() * right.len());
+        }
+        for length in [0, 2, 3] {
+            let mut op = SimilarityJoin::new(Similarity::Prefix(length), 1, 1, scan(&left), scan(&right))?;
+            let shared = |a: &str, b: &str| a.chars().zip(b.chars()).take_while(|(x, y)| x == y).count() >= length;
+            assert_eq!(test_sorted_output(&mut op)?, oracle(&shared));
+        }
+        assert!(within_edit_distance(&['k', 'i', 't', 't', 'e', 'n'], &['s', 'i', 't', 't', 'i', 'n', 'g'], 3));
+        assert!(!within_edit_distance(&['k', 'i', 't', 't', 'e', 'n'], &['s', 'i', 't', 't', 'i', 'n', 'g'], 2));
+        let ints = || TupleIterator::new(Vec::new(), get_int_table_schema(2));
+        assert!(SimilarityJoin::new(Similarity::Prefix(1), 1, 1, ints(), ints()).is_err());
+        Ok(())
     }
 
-    fn test_merge_1_to_2() {
-        let mut run1 = create_tuple_list(vec![
-            vec![5, 17], vec![3, 18], vec![7, 19], vec![1, 20]]);
-        let mut run2 = create_tuple_list(vec![
-            vec![5, 9], vec![3, 10], vec![7, 11], vec![1, 12]]);
-        let tuples = vec![run1, run2];
-        let res = merge_1_to_2(tuples);
-        let mut expected = Vec::new();
-        expected.push(create_tuple_list(vec![
-            vec![5, 17], vec![3, 18], vec![7, 19], vec![1, 20],
-            vec![1, 12], vec![7, 11], vec![3, 10], vec![5, 9]]));
+    fn test_group_join() -> Result<(), CrustyError> {
+        use crate::operators::Aggregate;
+        // left ids are unique, so grouping the join on the left fields groups by left tuple
+        let left = create_tuple_list((0..300).map(|i| vec![i, (i * 7) % 120]).collect());
+        let right = crate::generator::create_vec_tuple(2000, 2, 1000, 4)
+            .into_iter()
+            .map(|t| Tuple::new(vec![t.get_field(0).unwrap().clone(), Field::IntField(t.get_field(1).unwrap().unwrap_int_field() % 100)]))
+            .collect::<Vec<_>>();
+        let scan = |tuples: &Vec<Tuple>| TupleIterator::new(tuples.clone(), get_int_table_schema(2));
+        let aggs = vec![(0, AggOp::Count), (0, AggOp::Sum), (0, AggOp::Min), (0, AggOp::Max), (0, AggOp::Avg)];
+        let join = Join::new(SimplePredicateOp::Equals, 1, 1, scan(&left), scan(&right))?;
+        let shifted = aggs.iter().map(|(index, op)| (index + 2, *op)).collect();
+        let mut expected = Aggregate::new(Box::new(join), vec![0, 1], shifted);
+        let expected = test_sorted_output(&mut expected)?;
+
+        let mut op = GroupJoin::new(1, 1, aggs.clone(), scan(&left), scan(&right))?;
+        assert_eq!(op.get_schema().size(), 2 + aggs.len());
+        assert_eq!(op.get_schema().get_attribute(3).unwrap().name(), "sum()");
+        let res = test_sorted_output(&mut op)?;
+        // left keys 100 to 119 match no right key
+        assert!(!res.is_empty() && res.len() < left.len());
         assert_eq!(res, expected);
+        // one output row per left tuple, however many right tuples it joins
+        assert_eq!(op.metrics().rows_out, res.len());
+
+        let strings = TableSchema::from_vecs(vec!["key", "name"], vec![DataType::Int, DataType::String]);
+        let names = TupleIterator::new(Vec::new(), strings);
+        assert!(GroupJoin::new(1, 0, vec![(1, AggOp::Sum)], scan(&left), names).is_err());
+        assert!(GroupJoin::new(1, 1, vec![(2, AggOp::Count)], scan(&left), scan(&right)).is_err());
+        Ok(())
     }
 
-    fn test_level_one_sort() {
-        let mut tuples = create_tuple_list(vec![vec![1, 8], vec![3, 2], vec![5, 1], vec![7, 4]]);
-        tuples = sort_run_l1(tuples, 1);
-        assert_eq!(create_tuple_list(vec![vec![5, 1], vec![3, 2], vec![7, 4], vec![1, 8]]),
-                   tuples);
+    fn test_nested_loop_unmatched() -> Result<(), CrustyError> {
+        // 200000 left tuples in a row match no right tuple, then the last one matches
+        let mut left: Vec<Tuple> = (0..200_000).map(|i| Tuple::new(vec![Field::IntField(i), Field::IntField(-1)])).collect();
+        left.push(Tuple::new(vec![Field::IntField(0), Field::IntField(1)]));
+        let right = create_tuple_list(vec![vec![1, 1]]);
+        let mut op = Join::new(
+            SimplePredicateOp::Equals,
+            1,
+            1,
+            TupleIterator::new(left.clone(), get_int_table_schema(2)),
+            TupleIterator::new(right, get_int_table_schema(2)),
+        )?;
+        op.open()?;
+        assert_eq!(op.next()?, Some(Tuple::new(vec![Field::IntField(0), Field::IntField(1), Field::IntField(1), Field::IntField(1)])));
+        assert_eq!(op.next()?, None);
+        let rows_in = op.metrics().rows_in;
+        assert_eq!(rows_in, 2 * left.len());
+        // a rewind counts the first left tuple again, as the open does
+        op.rewind()?;
+        while op.next()?.is_some() {}
+        assert_eq!(op.metrics().rows_in, 2 * rows_in);
+        op.close()
     }
 
-    fn test_level_two_sort() {
-        let mut tuples = create_tuple_list(vec![
-            vec![5, 1], vec![3, 2], vec![7, 4], vec![1, 8],
-            vec![1, 9], vec![3, 7], vec![5, 5], vec![7, 0]]);
-        tuples = sort_run_l2(tuples, 1);
-        assert_eq!(
-            create_tuple_list(vec![vec![7, 0], vec![5, 1], vec![3, 2], vec![7, 4],
-                                   vec![5, 5], vec![3, 7], vec![1, 8], vec![1, 9]]),
-            tuples);
+    mod nested_loop_join {
+        use super::*;
+
+        #[test]
+        fn unmatched_left_tuples() -> Result<(), CrustyError> {
+            test_nested_loop_unmatched()
+        }
     }
 
-    fn test_final(
-        ty: JoinType,
-        op: SimplePredicateOp,
-        left_index: usize,
-        right_index: usize,
-        l3_method: isize,
-    ) {
-        let s1 = Box::new(scan1());
-        let s2 = Box::new(scan2());
-        let mut opI = match ty {
-            JoinType::SortMerge => Box::new(SortMergeJoin::new(op, left_index, right_index, s1, s2, l3_method)),
-            JoinType::NestedLoop => Box::new(SortMergeJoin::new(op, left_index, right_index, s1, s2, l3_method)),
-            JoinType::HashEq => Box::new(SortMergeJoin::new(op, left_index, right_index, s1, s2, l3_method)),
-        };
-        opI.open();
-        opI.next();
-        let res = opI.deref().l3_runs_l.clone();
-        if l3_method == 1 {
-            assert_eq!(res, vec![
-                create_tuple_list(vec![vec![5, 2, 1, 2, 3], vec![3, 3, 2, 3, 4], vec![1, 4, 3, 4, 5]]),
-                create_tuple_list(vec![vec![7, 5, 4, 5, 6], vec![5, 6, 3, 6, 5], vec![3, 7, 2, 7, 4],]),
-                create_tuple_list(vec![]),
-            ]);
-        } else {
-            assert_eq!(res,
-                       vec![create_tuple_list(vec![
-                           vec![5, 2, 1, 2, 3],
-                           vec![3, 3, 2, 3, 4],
-                           vec![1, 4, 3, 4, 5],
-                           vec![7, 5, 4, 5, 6],
-                           vec![5, 6, 3, 6, 5],
-                           vec![3, 7, 2, 7, 4],
-                       ])]);
+    mod group_join {
+        use super::*;
+
+        #[test]
+        fn aggregates_matches() -> Result<(), CrustyError> {
+            test_group_join()
+        }
+    }
+
+    mod similarity_join {
+        use super::*;
+
+        #[test]
+        fn similar_keys() -> Result<(), CrustyError> {
+            test_similarity_join()
+        }
+    }
+
+    mod as_of_join {
+        use super::*;
+
+        #[test]
+        fn nearest_predecessor() -> Result<(), CrustyError> {
+            test_as_of_join()
+        }
+    }
+
+    mod cross_join {
+        use super::*;
+
+        #[test]
+        fn product() -> Result<(), CrustyError> {
+            for block_size in [1, 3, 8, 100] {
+                test_cross_join(block_size)?;
+            }
+            Ok(())
+        }
+
+        #[test]
+        fn cap() -> Result<(), CrustyError> {
+            test_cross_join_cap()
+        }
+
+        #[test]
+        fn get_schema() {
+            let op = CrossJoin::new(Box::new(scan1()), Box::new(scan2()), 4);
+            assert_eq!(&get_int_table_schema(WIDTH1 + WIDTH2), op.get_schema());
+        }
+    }
+
+    mod hash_eq_join {
+        use super::*;
+
+        #[test]
+        fn get_schema() {
+            test_get_schema(JoinType::HashEq, MergeStrategy::M_WAY);
+        }
+
+        #[test]
+        fn eq_join() -> Result<(), CrustyError> {
+            test_hash_partitions(1, 0)
+        }
+
+        #[test]
+        fn partitioned() -> Result<(), CrustyError> {
+            for partitions in [2, 3, 8] {
+                test_hash_partitions(partitions, 0)?;
+            }
+            Ok(())
+        }
+
+        #[test]
+        fn radix_partitioned() -> Result<(), CrustyError> {
+            for (partitions, radix_bits) in [(1, 1), (1, 4), (3, 2), (1, MAX_RADIX_BITS + 1)] {
+                test_hash_partitions(partitions, radix_bits)?;
+            }
+            Ok(())
+        }
+
+        #[test]
+        fn unmatched() -> Result<(), CrustyError> {
+            test_hash_unmatched()
+        }
+
+        #[test]
+        fn non_equi_predicate() {
+            // the hash table only finds equal keys
+            for op in [SimplePredicateOp::LessThan, SimplePredicateOp::GreaterThanOrEq, SimplePredicateOp::NotEq] {
+                let res = HashEqJoin::new(op, 1, 1, scan1(), scan2());
+                assert!(matches!(res, Err(CrustyError::ValidationError(_))));
+            }
+        }
+
+        #[test]
+        fn bucket_stats() -> Result<(), CrustyError> {
+            test_bucket_stats()
         }
 
+        #[test]
+        fn memory_limit() -> Result<(), CrustyError> {
+            test_memory_limit()
+        }
+    }
+
+    mod adaptive_join {
+        use super::*;
+
+        #[test]
+        fn switches() -> Result<(), CrustyError> {
+            test_adaptive()
+        }
     }
 
     mod sort_merge_join {
@@ -1034,36 +6605,158 @@ mod test {
 
         #[test]
         fn get_schema() {
-            test_get_schema(JoinType::SortMerge, 1);
+            test_get_schema(JoinType::SortMerge, MergeStrategy::M_WAY);
         }
 
         #[test]
-        #[should_panic]
+        #[cfg_attr(feature = "panic-not-open", should_panic)]
         fn next_not_open() {
-            test_next_not_open(JoinType::SortMerge, 1);
+            test_next_not_open(JoinType::SortMerge, MergeStrategy::M_WAY);
         }
 
         #[test]
-        #[should_panic]
+        #[cfg_attr(feature = "panic-not-open", should_panic)]
         fn rewind_not_open() {
-            test_rewind_not_open(JoinType::SortMerge, 1);
+            test_rewind_not_open(JoinType::SortMerge, MergeStrategy::M_WAY);
+        }
+
+        #[test]
+        fn expr_keys() -> Result<(), CrustyError> {
+            test_expr_keys()
+        }
+
+        #[test]
+        fn collation() -> Result<(), CrustyError> {
+            test_collation()
+        }
+
+        #[test]
+        fn top_k() -> Result<(), CrustyError> {
+            test_top_k()
+        }
+
+        #[test]
+        fn deterministic_order() -> Result<(), CrustyError> {
+            test_deterministic_order()
+        }
+
+        #[test]
+        fn buffer_pool() -> Result<(), CrustyError> {
+            test_buffer_pool()
+        }
+
+        #[test]
+        fn stream_right() -> Result<(), CrustyError> {
+            test_stream_right()
+        }
+
+        #[test]
+        fn checkpoint() -> Result<(), CrustyError> {
+            test_checkpoint()
+        }
+
+        #[test]
+        fn key_range() -> Result<(), CrustyError> {
+            test_key_range(MergeStrategy::M_WAY)?;
+            test_key_range(MergeStrategy::M_PASS)?;
+            test_key_range(MergeStrategy::Radix)
+        }
+
+        #[test]
+        fn unique_right() -> Result<(), CrustyError> {
+            test_unique_right(MergeStrategy::M_WAY)?;
+            test_unique_right(MergeStrategy::M_PASS)?;
+            test_unique_right(MergeStrategy::Radix)
+        }
+
+        #[test]
+        fn unique_left() -> Result<(), CrustyError> {
+            test_unique_left(MergeStrategy::M_WAY)?;
+            test_unique_left(MergeStrategy::M_PASS)?;
+            test_unique_left(MergeStrategy::Radix)
+        }
+
+        #[test]
+        fn merge_predicate() -> Result<(), CrustyError> {
+            test_merge_predicate()
+        }
+
+        #[test]
+        fn greater_than() -> Result<(), CrustyError> {
+            test_greater_than(MergeStrategy::M_WAY)?;
+            test_greater_than(MergeStrategy::MWay { partitions: 4 })?;
+            test_greater_than(MergeStrategy::M_PASS)?;
+            test_greater_than(MergeStrategy::Radix)
+        }
+
+        #[cfg(feature = "lz4")]
+        #[test]
+        fn spill_compression() -> Result<(), CrustyError> {
+            test_spill_compression()
+        }
+
+        #[test]
+        fn progress() -> Result<(), CrustyError> {
+            test_progress()
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        #[test]
+        fn partitioned() -> Result<(), CrustyError> {
+            test_partitioned()
         }
 
         #[test]
         fn rewind() -> Result<(), CrustyError> {
-            test_rewind(JoinType::SortMerge, 1)
+            for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS, MergeStrategy::Radix] {
+                test_rewind(strategy)?;
+            }
+            Ok(())
+        }
+
+        #[test]
+        fn borrowed_children() -> Result<(), CrustyError> {
+            test_borrowed_children()
+        }
+
+        #[test]
+        fn strategies() -> Result<(), CrustyError> {
+            test_strategies()
+        }
+
+        #[test]
+        fn builder() -> Result<(), CrustyError> {
+            test_builder()
+        }
+
+        #[test]
+        fn projection() -> Result<(), CrustyError> {
+            test_projection()
+        }
+
+        #[test]
+        fn empty_child() -> Result<(), CrustyError> {
+            test_empty_child(JoinType::NestedLoop, MergeStrategy::M_WAY)?;
+            test_empty_child(JoinType::HashEq, MergeStrategy::M_WAY)?;
+            test_empty_child(JoinType::SortMerge, MergeStrategy::M_WAY)?;
+            test_empty_child(JoinType::SortMerge, MergeStrategy::M_PASS)
         }
 
         #[test]
         fn eq_join_m_way() {
-            // test_eq_join(JoinType::SortMerge, 1)
-            test_final(JoinType::SortMerge, SimplePredicateOp::Equals, 1, 1, 1);
+            // test_eq_join(JoinType::SortMerge, MergeStrategy::M_WAY)
+            test_final(JoinType::SortMerge, SimplePredicateOp::Equals, 1, 1, MergeStrategy::M_WAY);
         }
 
         #[test]
         fn eq_join_m_pass() {
-            // test_eq_join(JoinType::SortMerge, 2)
-            test_final(JoinType::SortMerge, SimplePredicateOp::Equals, 1, 1, 2);
+            // test_eq_join(JoinType::SortMerge, MergeStrategy::M_PASS)
+            test_final(JoinType::SortMerge, SimplePredicateOp::Equals, 1, 1, MergeStrategy::M_PASS);
+        }
+
+        #[test]
+        fn eq_join_radix() {
+            test_final(JoinType::SortMerge, SimplePredicateOp::Equals, 1, 1, MergeStrategy::Radix);
         }
 
         #[test]
@@ -1077,13 +6770,73 @@ mod test {
         }
 
         #[test]
-        fn sort_l2() {
-            test_level_two_sort();
+        fn merge_k_way() {
+            test_merge_runs();
+        }
+
+        #[test]
+        fn merge_pairs() {
+            test_merge_pairs();
+        }
+
+        #[test]
+        fn sort_levels() -> Result<(), CrustyError> {
+            for target_run_size in [1, 4, 8, 16, 64] {
+                for kernel in [SortKernel::Network, SortKernel::Std, SortKernel::Columnar, SortKernel::Radix] {
+                    test_sort_levels(target_run_size, MergeStrategy::M_WAY, kernel)?;
+                    test_sort_levels(target_run_size, MergeStrategy::M_PASS, kernel)?;
+                }
+            }
+            Ok(())
+        }
+
+        #[test]
+        fn request_order() -> Result<(), CrustyError> {
+            test_request_order(MergeStrategy::M_WAY)?;
+            test_request_order(MergeStrategy::M_PASS)
+        }
+
+        #[test]
+        fn sample_splitters() -> Result<(), CrustyError> {
+            test_sample_splitters()
+        }
+
+        #[test]
+        fn output_order() -> Result<(), CrustyError> {
+            test_output_order(MergeStrategy::M_WAY)?;
+            test_output_order(MergeStrategy::M_PASS)
+        }
+
+        #[test]
+        fn named_predicate() -> Result<(), CrustyError> {
+            test_named_predicate()
+        }
+
+        #[test]
+        fn invalid_fields() {
+            test_invalid_fields();
+        }
+
+        #[test]
+        fn morsels() -> Result<(), CrustyError> {
+            test_morsels()
+        }
+
+        #[test]
+        fn radix_cores() -> Result<(), CrustyError> {
+            test_radix_cores()
+        }
+
+        #[test]
+        fn string_keys() -> Result<(), CrustyError> {
+            test_string_keys()
         }
 
         #[test]
-        fn merge_1_2() {
-            test_merge_1_to_2();
+        fn duplicate_keys() -> Result<(), CrustyError> {
+            test_duplicate_keys(MergeStrategy::M_WAY)?;
+            test_duplicate_keys(MergeStrategy::M_PASS)?;
+            test_duplicate_keys(MergeStrategy::Radix)
         }
 
         #[test]
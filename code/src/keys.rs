@@ -0,0 +1,354 @@
+use std::cmp::Ordering;
+use crate::common::{Attribute, CrustyError, DataType, Field, OpIterator, SimplePredicateOp, TableSchema, Tuple};
+use crate::join::{MergeStrategy, SortMergeJoin};
+
+/// Granularity timestamps are truncated to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeGranularity {
+    Second,
+    Minute,
+    Hour,
+    Day,
+}
+
+impl TimeGranularity {
+    /// Returns the length of the granularity in seconds.
+    pub fn seconds(&self) -> i32 {
+        match self {
+            TimeGranularity::Second => 1,
+            TimeGranularity::Minute => 60,
+            TimeGranularity::Hour => 60 * 60,
+            TimeGranularity::Day => 24 * 60 * 60,
+        }
+    }
+}
+
+/// Derives the join key of a tuple from one of its fields.
+///
+/// Every extractor is monotonic in its source field, so input sorted on the source
+/// field is also sorted on the derived key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyExtractor {
+    /// The field at the index as is.
+    Field(usize),
+    /// The timestamp in seconds at the index, truncated to the start of its bucket.
+    Truncated(usize, TimeGranularity),
+}
+
+impl KeyExtractor {
+    /// Returns the index of the field the key is derived from.
+    pub fn source_index(&self) -> usize {
+        match self {
+            KeyExtractor::Field(i) | KeyExtractor::Truncated(i, _) => *i,
+        }
+    }
+
+    /// Returns the data type of the derived key.
+    ///
+    /// Fails with a ValidationError if the source field does not exist or a truncated
+    /// timestamp is not an integer.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the tuples the key is derived from.
+    pub fn dtype(&self, schema: &TableSchema) -> Result<DataType, CrustyError> {
+        let index = self.source_index();
+        let dtype = schema
+            .get_attribute(index)
+            .map(|attr| attr.dtype().clone())
+            .ok_or_else(|| CrustyError::ValidationError(format!("key source field {} does not exist", index)))?;
+        match self {
+            KeyExtractor::Truncated(_, _) if dtype != DataType::Int => Err(CrustyError::ValidationError(format!(
+                "field {} is not a timestamp",
+                index
+            ))),
+            _ => Ok(dtype),
+        }
+    }
+
+    /// Derive the key of a tuple.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple` - Tuple to derive the key from.
+    pub fn extract(&self, tuple: &Tuple) -> Result<Field, CrustyError> {
+        let index = self.source_index();
+        let field = tuple
+            .get_field(index)
+            .ok_or_else(|| CrustyError::ExecutionError(format!("tuple has no field {}", index)))?;
+        match (self, field) {
+            (KeyExtractor::Field(_), _) => Ok(field.clone()),
+            (KeyExtractor::Truncated(_, granularity), Field::IntField(ts)) => {
+                let seconds = granularity.seconds();
+                // the bucket of a timestamp near i32::MIN may start before it
+                ts.div_euclid(seconds).checked_mul(seconds).map(Field::IntField).ok_or_else(|| {
+                    CrustyError::ExecutionError(format!("the {:?} of timestamp {} starts out of range", granularity, ts))
+                })
+            }
+            (KeyExtractor::Truncated(_, _), _) => Err(CrustyError::ExecutionError(format!(
+                "field {} is not a timestamp",
+                index
+            ))),
+        }
+    }
+}
+
+/// Tag of normalized string keys, strings sort after every integer as in Field::cmp.
+const STRING_TAG: u64 = 1 << 63;
+
+/// Encode a field as a fixed-width key whose unsigned order is the order of the fields.
+///
+/// Integers are bias-encoded and exact. Strings keep their first bytes, so two strings
+/// with equal keys may still differ; `cmp_normalized` then falls back to Field::cmp.
+///
+/// # Arguments
+///
+/// * `field` - Field to encode.
+pub fn normalize_key(field: &Field) -> u64 {
+    match field {
+        Field::IntField(x) => (*x as u32 ^ 0x8000_0000) as u64,
+        Field::StringField(s) => {
+            let mut prefix = [0u8; 8];
+            let len = s.len().min(8);
+            prefix[..len].copy_from_slice(&s.as_bytes()[..len]);
+            STRING_TAG | (u64::from_be_bytes(prefix) >> 1)
+        }
+    }
+}
+
+/// Returns true if the normalized key is exact, so equal keys mean equal fields.
+///
+/// # Arguments
+///
+/// * `key` - Normalized key, see normalize_key.
+pub fn is_exact(key: u64) -> bool {
+    key & STRING_TAG == 0
+}
+
+/// Compare two fields by their normalized keys, calling Field::cmp only on equal keys.
+///
+/// # Arguments
+///
+/// * `a` - Normalized key and field of the left side.
+/// * `b` - Normalized key and field of the right side.
+pub fn cmp_normalized(a: (u64, Option<&Field>), b: (u64, Option<&Field>)) -> Ordering {
+    a.0.cmp(&b.0).then_with(|| a.1.cmp(&b.1))
+}
+
+/// Appends a derived key to every tuple of its child.
+pub struct KeyScan {
+    /// Child node.
+    child: Box<dyn OpIterator + Send>,
+    /// Extractor deriving the appended key.
+    key: KeyExtractor,
+    /// Schema of the child with the key attribute appended.
+    schema: TableSchema,
+}
+
+impl KeyScan {
+    /// KeyScan constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - Child node.
+    /// * `key` - Extractor deriving the appended key.
+    ///
+    /// Fails with a ValidationError if the key cannot be derived from the child, see
+    /// KeyExtractor::dtype.
+    pub fn new(child: Box<dyn OpIterator + Send>, key: KeyExtractor) -> Result<Self, CrustyError> {
+        let key_attr = Attribute::new(String::from("key"), key.dtype(child.get_schema())?);
+        let schema = child.get_schema().merge(&TableSchema::new(vec![key_attr]));
+        Ok(Self { child, key, schema })
+    }
+
+    /// Returns the index of the appended key in the output tuples.
+    pub fn key_index(&self) -> usize {
+        self.schema.size() - 1
+    }
+}
+
+impl OpIterator for KeyScan {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        match self.child.next()? {
+            None => Ok(None),
+            Some(mut t) => {
+                let key = self.key.extract(&t)?;
+                t.field_vals.push(key);
+                Ok(Some(t))
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.child.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.child.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.child]
+    }
+
+    /// Appending a field keeps the order of the child.
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        self.child.sort_order()
+    }
+
+    /// The key is monotonic in its source field, so sorting the child on the source
+    /// field sorts the output on the key.
+    fn request_order(&mut self, index: usize) -> bool {
+        if index == self.key_index() {
+            self.child.request_order(self.key.source_index())
+        } else {
+            self.child.request_order(index)
+        }
+    }
+}
+
+/// Sort-merge join on timestamps truncated to a granularity on both sides.
+///
+/// The truncated keys are appended as the last field of each side, so output tuples are
+/// the left tuple, its key, the right tuple and its key. Children already sorted on their
+/// timestamps are not sorted again. Fails with a ValidationError if an index is out of
+/// bounds or a timestamp is not an integer.
+///
+/// # Arguments
+///
+/// * `left_child` - Left child of join operator.
+/// * `left_index` - Index of the timestamp in the left child.
+/// * `right_child` - Right child of join operator.
+/// * `right_index` - Index of the timestamp in the right child.
+/// * `granularity` - Granularity the timestamps are truncated to.
+/// * `strategy` - Level 3 method.
+pub fn date_bucket_join(
+    left_child: Box<dyn OpIterator + Send>,
+    left_index: usize,
+    right_child: Box<dyn OpIterator + Send>,
+    right_index: usize,
+    granularity: TimeGranularity,
+    strategy: MergeStrategy,
+) -> Result<SortMergeJoin, CrustyError> {
+    let left = KeyScan::new(left_child, KeyExtractor::Truncated(left_index, granularity))?;
+    let right = KeyScan::new(right_child, KeyExtractor::Truncated(right_index, granularity))?;
+    let (left_key, right_key) = (left.key_index(), right.key_index());
+    SortMergeJoin::new(
+        SimplePredicateOp::Equals,
+        left_key,
+        right_key,
+        Box::new(left),
+        Box::new(right),
+        strategy,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::TupleIterator;
+
+    const HOUR: i32 = 60 * 60;
+    const DAY: i32 = 24 * HOUR;
+
+    fn scan(tuple_data: Vec<Vec<i32>>) -> Box<TupleIterator> {
+        let tuples = tuple_data
+            .iter()
+            .map(|item| Tuple::new(item.iter().map(|i| Field::IntField(*i)).collect()))
+            .collect();
+        let schema = TableSchema::from_vecs(vec!["id", "ts"], vec![DataType::Int, DataType::Int]);
+        Box::new(TupleIterator::new(tuples, schema))
+    }
+
+    #[test]
+    fn normalized_order() {
+        let fields = [
+            Field::IntField(i32::MIN),
+            Field::IntField(-1),
+            Field::IntField(0),
+            Field::IntField(i32::MAX),
+            Field::StringField(String::new()),
+            Field::StringField("a".to_string()),
+            Field::StringField("abcdefgh".to_string()),
+            Field::StringField("abcdefghi".to_string()),
+            Field::StringField("b".to_string()),
+        ];
+        for a in fields.iter() {
+            for b in fields.iter() {
+                let (ka, kb) = (normalize_key(a), normalize_key(b));
+                // the keys never contradict the fields, only long strings may tie
+                assert!(ka.cmp(&kb) == a.cmp(b) || ka == kb);
+                assert_eq!(cmp_normalized((ka, Some(a)), (kb, Some(b))), a.cmp(b));
+            }
+        }
+    }
+
+    #[test]
+    fn truncate() -> Result<(), CrustyError> {
+        let t = Tuple::new(vec![Field::IntField(DAY + 3 * HOUR + 5), Field::IntField(-1)]);
+        let day = KeyExtractor::Truncated(0, TimeGranularity::Day);
+        let hour = KeyExtractor::Truncated(0, TimeGranularity::Hour);
+        assert_eq!(day.extract(&t)?, Field::IntField(DAY));
+        assert_eq!(hour.extract(&t)?, Field::IntField(DAY + 3 * HOUR));
+        // before the epoch the bucket still starts at or before the timestamp
+        assert_eq!(KeyExtractor::Truncated(1, TimeGranularity::Day).extract(&t)?, Field::IntField(-DAY));
+        // the day of i32::MIN starts before i32::MIN, its second does not
+        let min = Tuple::new(vec![Field::IntField(i32::MIN)]);
+        assert!(matches!(day.extract(&min), Err(CrustyError::ExecutionError(_))));
+        let second = KeyExtractor::Truncated(0, TimeGranularity::Second);
+        assert_eq!(second.extract(&min)?, Field::IntField(i32::MIN));
+        assert_eq!(day.extract(&Tuple::new(vec![Field::IntField(i32::MAX)]))?, Field::IntField(i32::MAX / DAY * DAY));
+        Ok(())
+    }
+
+    #[test]
+    fn join_events_to_days() -> Result<(), CrustyError> {
+        for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS] {
+            // events at various times of day 0, 1 and 3
+            let events = scan(vec![vec![1, 5], vec![2, DAY + HOUR], vec![3, DAY + 20 * HOUR], vec![4, 3 * DAY + 1]]);
+            // daily reference data for days 0 to 2
+            let days = scan(vec![vec![10, 0], vec![11, DAY], vec![12, 2 * DAY]]);
+            let mut op = date_bucket_join(events, 1, days, 1, TimeGranularity::Day, strategy)?;
+            op.open()?;
+            op.next()?;
+            let mut res: Vec<(i32, i32)> = op
+                .l3_runs_l
+                .concat()
+                .iter()
+                .map(|t| (t.get_field(0).unwrap().unwrap_int_field(), t.get_field(3).unwrap().unwrap_int_field()))
+                .collect();
+            res.sort();
+            assert_eq!(res, vec![(1, 10), (2, 11), (3, 11)]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_sources() {
+        // a missing field and a string timestamp fail before the join is opened
+        let res = date_bucket_join(scan(Vec::new()), 7, scan(Vec::new()), 1, TimeGranularity::Day, MergeStrategy::M_PASS);
+        assert!(matches!(res, Err(CrustyError::ValidationError(_))));
+        let schema = TableSchema::from_vecs(vec!["id", "ts"], vec![DataType::Int, DataType::String]);
+        let strings = Box::new(TupleIterator::new(Vec::new(), schema));
+        let res = date_bucket_join(scan(Vec::new()), 1, strings, 1, TimeGranularity::Day, MergeStrategy::M_PASS);
+        assert!(matches!(res, Err(CrustyError::ValidationError(_))));
+        assert!(matches!(KeyScan::new(scan(Vec::new()), KeyExtractor::Field(2)), Err(CrustyError::ValidationError(_))));
+    }
+
+    #[test]
+    fn sorted_timestamps_stay_sorted() -> Result<(), CrustyError> {
+        let mut sorted = KeyScan::new(scan(vec![vec![1, 5], vec![2, HOUR]]), KeyExtractor::Truncated(1, TimeGranularity::Day))?;
+        assert!(sorted.request_order(sorted.key_index()));
+        let mut unsorted = KeyScan::new(scan(vec![vec![1, HOUR], vec![2, 5]]), KeyExtractor::Truncated(1, TimeGranularity::Day))?;
+        assert!(!unsorted.request_order(unsorted.key_index()));
+        Ok(())
+    }
+}
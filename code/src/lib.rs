@@ -1,5 +1,47 @@
 pub mod join;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "async")]
+pub mod async_iter;
+pub mod buffer;
+pub mod catalog;
+pub mod checkpoint;
+pub mod collation;
+pub mod columnar;
+pub mod keys;
 pub mod common;
+pub mod config;
+pub mod cost;
+pub mod dictionary;
+pub mod examples;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod exchange;
+pub mod expr;
+pub mod generator;
+pub mod heap;
+pub mod metrics;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub mod morsel;
+pub mod network;
+pub mod operators;
+#[cfg(feature = "perf")]
+pub mod perf;
+pub mod plan;
+pub mod radix;
+pub mod results;
+pub mod setops;
+pub mod snapshot;
+pub mod spill;
+#[cfg(feature = "sql")]
+pub mod sql;
+pub mod stats;
+pub mod sweep;
+#[cfg(feature = "testutil")]
+pub mod testutil;
+pub mod trace;
+#[cfg(feature = "visualize")]
+pub mod visualize;
 // mod testutil_common;
 // mod testutil_op_iter;
 // mod testutil_query_ex;
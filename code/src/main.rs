@@ -1,381 +1,516 @@
-use std::cmp::min_by_key;
-use std::fs::File;
-use std::io::Write;
-use std::ops::Deref;
-use std::time::Instant;
+//! Command line runner of a single join, of the join picked by the cost model, of a SQL
+//! query (with the sql feature), of the sort kernels or of a sweep over a grid of joins
+//! (with the sweep feature).
+//!
+//! The timings here are single runs meant for a quick look; the benchmarks comparing the
+//! strategies are in benches/join.rs, run with `cargo bench`.
+use std::{env, fs};
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use join::join::*;
 use join::common::*;
-use rand::Rng;
-
-// function to creat number of tuples for benchmark
-pub fn create_vec_tuple(tuple_number: usize, width: usize, range: usize) -> Vec<Tuple> {
-    let mut rng = rand::thread_rng();
-
-    let mut tuple_data = Vec::new();
+use join::config::{CacheSizes, CoreAffinity, MemoryTracker, ProgressMonitor, SortConfig};
+use join::cost::{choose_join, JoinPlan};
+use join::generator::{create_overlapping_tuples, create_vec_tuple, expected_join_rows, get_int_table_schema, DEFAULT_SEED};
+use join::metrics::explain_analyze;
+use join::morsel::default_workers;
+use join::operators::{compare_rows, RowIds};
+#[cfg(feature = "sweep")]
+use join::results::summarize;
+use join::results::{throughput, write_results, ResultFormat, RunResult, Summary};
+#[cfg(feature = "sweep")]
+use join::sweep::{run_sweep, SweepGrid};
+use join::stats::{estimate_join_rows, TableStats};
+
+/// Untimed and timed runs of each configuration, set by --warmups and --repeat.
+#[derive(Debug, Clone, Copy)]
+struct Repeats {
+    /// Runs before the timed ones, e.g. to fill the caches and start the threads.
+    warmups: usize,
+    /// Timed runs, at least 1.
+    runs: usize,
+}
 
-    // create tuples based on the tuple number
-    for _ in 0..tuple_number {
-        let mut tuple = Vec::new();
-        // create fields in each tuple, base on the tuple's width
-        for _ in 0..width {
-            tuple.push(rng.gen_range((range-1000)..range) as i32);
+impl Repeats {
+    // helper method to time the runs of a closure, returning the times in seconds of the
+    // timed runs and the output of the last run
+    fn time<T>(&self, mut run: impl FnMut() -> Result<T, Box<dyn Error>>) -> Result<(Vec<f64>, T), Box<dyn Error>> {
+        for _ in 0..self.warmups {
+            run()?;
         }
-        tuple_data.push(tuple);
-    }
-
-    let mut res = Vec::new();
-    for item in &tuple_data {
-        let fields = item.iter().map(|i| Field::IntField(*i)).collect();
-        res.push(Tuple::new(fields));
-    }
-    res
-}
-/// Creates a new table schema for a table with width number of IntFields.
-pub fn get_int_table_schema(width: usize) -> TableSchema {
-    let mut attrs = Vec::new();
-    for _ in 0..width {
-        attrs.push(Attribute::new(String::new(), DataType::Int))
+        let mut samples = Vec::with_capacity(self.runs);
+        let mut output = None;
+        for _ in 0..self.runs.max(1) {
+            let now = Instant::now();
+            output = Some(run()?);
+            samples.push(now.elapsed().as_secs_f64());
+        }
+        Ok((samples, output.unwrap()))
     }
-    TableSchema::new(attrs)
 }
 
-// helper method to benchmark 5k tuples with at least 10% are same
-fn dis_10(mut file: &File) {
-    file.write_all("10%:\n".as_ref());
-
-    let WIDTH = 2;
-    let mut left_child = create_vec_tuple(1843 as usize, WIDTH, 1000);
-    let mut right_child = create_vec_tuple(1843 as usize, WIDTH, 1000);
-    let mut common = create_vec_tuple(205 as usize, WIDTH, 1000);
-    left_child.append(&mut common);
-    right_child.append(&mut common);
-    let schema = get_int_table_schema(WIDTH);
-
-    let s1 = Box::new(TupleIterator::new(left_child.clone(), schema.clone()));
-    let s2 = Box::new(TupleIterator::new(right_child.clone(), schema.clone()));
-    let s1_1 = Box::new(TupleIterator::new(left_child, schema.clone()));
-    let s2_1 = Box::new(TupleIterator::new(right_child, schema.clone()));
-    let mut op1 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, 1));
-    let mut op2 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1_1, s2_1, 2));
-
-    // M-way
-    file.write_all("m-way:\n".as_ref());
-    let now = Instant::now();
-    op1.open();
-    op1.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
-
-    // M-way
-    file.write_all("m-pass:\n".as_ref());
-    let now = Instant::now();
-    op2.open();
-    op2.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
+// helper method to report the left/right tuple counts of each m-way partition
+fn partition_sizes<L: OpIterator, R: OpIterator>(file: &mut dyn Write, op: &SortMergeJoin<L, R>) -> Result<(), Box<dyn Error>> {
+    let sizes: Vec<String> = op.partition_sizes().iter().map(|(l, r)| format!("{}/{}", l, r)).collect();
+    file.write_all(format!("partitions: {}\n", sizes.join(" ")).as_ref())?;
+    Ok(())
 }
-// helper method to benchmark 5k tuples with at least 30% are same
-fn dis_30(mut file: &File) {
-    file.write_all("30%:\n".as_ref());
-
-    let width = 2;
-    let mut left_child = create_vec_tuple(1434 as usize, width, 1000);
-    let mut right_child = create_vec_tuple(1434 as usize, width, 1000);
-    let mut common = create_vec_tuple(614 as usize, width, 1000);
-    left_child.append(&mut common);
-    right_child.append(&mut common);
-    let schema1 = get_int_table_schema(width);
-    let schema2 = get_int_table_schema(width);
-
-    let s1 = Box::new(TupleIterator::new(left_child.clone(), schema1.clone()));
-    let s2 = Box::new(TupleIterator::new(right_child.clone(), schema2.clone()));
-    let s1_1 = Box::new(TupleIterator::new(left_child, schema1));
-    let s2_1 = Box::new(TupleIterator::new(right_child, schema2));
-    let mut op1 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, 1));
-    let mut op2 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1_1, s2_1, 2));
-
-
-    // M-way
-    file.write_all("m-way:\n".as_ref());
-    let now = Instant::now();
-    op1.open();
-    op1.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
-
-    // M-way
-    file.write_all("m-pass:\n".as_ref());
-    let now = Instant::now();
-    op2.open();
-    op2.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
 
+// helper method to report the largest number of bytes the join held at once, the
+// metrics of its operators and, with the perf feature, the hardware counts of its phases
+fn join_report<L: OpIterator, R: OpIterator>(file: &mut dyn Write, op: &SortMergeJoin<L, R>) -> Result<(), Box<dyn Error>> {
+    file.write_all(format!("peak memory: {} bytes\n", op.memory_tracker().peak()).as_ref())?;
+    file.write_all(explain_analyze(op).as_ref())?;
+    #[cfg(feature = "perf")]
+    file.write_all(join::perf::phase_report().as_ref())?;
+    Ok(())
 }
-// helper method to benchmark 5k tuples with at least 50% are same
-fn dis_50(mut file: &File) {
-    file.write_all("50%:\n".as_ref());
-
-    let width = 2;
-    let mut left_child = create_vec_tuple(1024 as usize, width, 1000);
-    let mut right_child = create_vec_tuple(1024 as usize, width, 1000);
-    let mut common = create_vec_tuple(1024 as usize, width, 1000);
-    left_child.append(&mut common);
-    right_child.append(&mut common);
-    let schema1 = get_int_table_schema(width);
-    let schema2 = get_int_table_schema(width);
-
-    let s1 = Box::new(TupleIterator::new(left_child.clone(), schema1.clone()));
-    let s2 = Box::new(TupleIterator::new(right_child.clone(), schema2.clone()));
-    let s1_1 = Box::new(TupleIterator::new(left_child, schema1));
-    let s2_1 = Box::new(TupleIterator::new(right_child, schema2));
-    let mut op1 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, 1));
-    let mut op2 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1_1, s2_1, 2));
 
+// helper method to check the sort-merge and hash joins against the nested loop join; the
+// inputs carry row ids, so the tuple shown on a difference names the rows it came from
+fn verify(file: &mut dyn Write, left: &[Tuple], right: &[Tuple], strategy: MergeStrategy) -> Result<(), Box<dyn Error>> {
+    let schema = get_int_table_schema(left.first().map_or(0, |t| t.size()));
+    let scan = |tuples: &[Tuple]| Box::new(RowIds::new(Box::new(TupleIterator::new(tuples.to_vec(), schema.clone()))));
+    let mut oracle = Join::new(SimplePredicateOp::Equals, 1, 1, scan(left), scan(right))?;
+    let mut sort_merge = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(left), scan(right), strategy)?;
+    let rows = compare_rows(&mut oracle, &mut sort_merge).map_err(|e| format!("sort-merge join is wrong: {}", e))?;
+    let mut hash = HashEqJoin::new(SimplePredicateOp::Equals, 1, 1, scan(left), scan(right))?;
+    compare_rows(&mut oracle, &mut hash).map_err(|e| format!("hash join is wrong: {}", e))?;
+    file.write_all(format!("verified: {} tuples\n", rows).as_ref())?;
+    Ok(())
+}
 
-    // M-way
-    file.write_all("m-way:\n".as_ref());
-    let now = Instant::now();
-    op1.open();
-    op1.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
+// method to run one join of two generated tables
+// arguments: [m-way|m-pass|radix] [tuples] [overlap %] [range] [text|csv|json], --verify
+// anywhere to check the output against the nested loop join, and --pin anywhere to pin the
+// sort and merge workers one per core (with the affinity feature); with a timeout the join
+// fails with "deadline exceeded" once it runs longer; with repeats the join is opened again
+// for each run, and the text output gives the median time and the statistics of the runs;
+// --check-rows anywhere fails the run when its output rows differ from the ones counted from
+// the keys of the tables, and --expect-rows n when they differ from n; with the mmap feature,
+// --mmap anywhere writes the tables to row files in the temporary directory and joins
+// memory-mapped scans of them; --spill-budget n spills the right runs once the join holds n
+// bytes, and with the lz4 feature --lz4 anywhere compresses the spilled runs; --stream-right n
+// sorts only the left table whole and joins the right one in sorted chunks of n tuples
+fn run_join(
+    file: &mut dyn Write,
+    args: &[String],
+    seed: u64,
+    timeout: Option<Duration>,
+    repeats: Repeats,
+) -> Result<(), Box<dyn Error>> {
+    let check = args.iter().any(|s| s == "--verify");
+    let pin = args.iter().any(|s| s == "--pin");
+    let check_rows = args.iter().any(|s| s == "--check-rows");
+    let mapped = args.iter().any(|s| s == "--mmap");
+    let lz4 = args.iter().any(|s| s == "--lz4");
+    let flags = ["--verify", "--pin", "--check-rows", "--mmap", "--lz4"];
+    let mut args: Vec<String> = args.iter().filter(|s| !flags.contains(&s.as_str())).cloned().collect();
+    let spill_budget = take_count(&mut args, "--spill-budget", usize::MAX)?;
+    let stream_chunk = take_count(&mut args, "--stream-right", 0)?;
+    let expect_rows = match take_count(&mut args, "--expect-rows", usize::MAX)? {
+        usize::MAX => None,
+        rows => Some(rows),
+    };
+    let (name, strategy) = match args.first().map(|s| s.as_str()) {
+        Some("m-pass") => ("m-pass", MergeStrategy::M_PASS),
+        Some("radix") => ("radix", MergeStrategy::Radix),
+        _ => ("m-way", MergeStrategy::M_WAY),
+    };
+    let tuple_number = args.get(1).map_or(Ok(2048), |s| s.parse())?;
+    let overlap: usize = args.get(2).map_or(Ok(10), |s| s.parse())?;
+    let range = args.get(3).map_or(Ok(1000), |s| s.parse())?;
+    let format = args.get(4).and_then(|s| ResultFormat::from_name(s));
 
-    // M-way
-    file.write_all("m-pass:\n".as_ref());
-    let now = Instant::now();
-    op2.open();
-    op2.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
+    let width = 2;
+    let overlap = overlap as f64 / 100.0;
+    let (left_child, right_child) = create_overlapping_tuples(tuple_number, width, range, overlap, seed);
+    if check {
+        verify(file, &left_child, &right_child, strategy.clone())?;
+    }
+    let expect_rows = match check_rows {
+        true => Some(expected_join_rows(&left_child, 1, &right_child, 1)),
+        false => expect_rows,
+    };
+    let schema = get_int_table_schema(width);
+    // the row files are removed once the join, declared after them, is dropped
+    let (s1, s2, _files) = scans(left_child, right_child, &schema, mapped)?;
+    let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, strategy.clone())?;
+    let mut sort_config = SortConfig::default();
+    if pin {
+        let cores = CoreAffinity::new(0, default_workers());
+        sort_config.set_sort_cores(cores);
+        op.set_merge_cores(cores);
+    }
+    if lz4 {
+        #[cfg(feature = "lz4")]
+        sort_config.set_spill_compression(join::spill::SpillCompression::Lz4);
+        #[cfg(not(feature = "lz4"))]
+        return Err("--lz4 needs the lz4 feature".into());
+    }
+    op.set_sort_config(sort_config);
+    if spill_budget != usize::MAX {
+        op.set_memory_tracker(MemoryTracker::new(spill_budget));
+    }
+    if stream_chunk > 0 {
+        op.set_stream_right(stream_chunk);
+    }
 
-}
-// method to benchmark different cardinality with 12 permutations
-fn distribution(mut file: &File) {
-    file.write_all("Micro-benchmark with different distribution\n".as_ref());
-    dis_10(file);
-    // dis_30(file);
-    // dis_50(file);
+    if let Some(timeout) = timeout {
+        let mut monitor = ProgressMonitor::new();
+        monitor.set_timeout(timeout);
+        op.set_progress_monitor(monitor);
+    }
+    let mut opened = false;
+    let (samples, output_rows) = repeats.time(|| {
+        if opened {
+            op.close()?;
+        }
+        opened = true;
+        op.open()?;
+        let mut output_rows = 0;
+        while op.next()?.is_some() {
+            output_rows += 1;
+        }
+        Ok(output_rows)
+    })?;
+    if let Some(expected) = expect_rows.filter(|rows| *rows != output_rows) {
+        return Err(format!("{} returned {} tuples, {} expected", name, output_rows, expected).into());
+    }
+    let summary = Summary::of(&samples).unwrap();
+    let input_rows = 2 * tuple_number;
+    let input_bytes = input_rows * get_int_table_schema(width).byte_size();
+
+    if let Some(format) = format {
+        let run_id = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis().to_string();
+        let results: Vec<RunResult> = samples
+            .iter()
+            .map(|&elapsed| {
+                let (tuples_per_s, mb_per_s) = throughput(input_rows, input_bytes, elapsed);
+                RunResult {
+                    run_id: run_id.clone(),
+                    algorithm: name.to_string(),
+                    rows: tuple_number,
+                    overlap,
+                    seed,
+                    threads: op.workers(),
+                    elapsed_s: elapsed,
+                    peak_mem: op.memory_tracker().peak(),
+                    output_rows,
+                    tuples_per_s,
+                    mb_per_s,
+                    phases: op.phase_stats().to_vec(),
+                }
+            })
+            .collect();
+        write_results(&results, format, file)?;
+        return Ok(());
+    }
+    let (tuples_per_s, mb_per_s) = throughput(input_rows, input_bytes, summary.median_s);
+    file.write_all(format!("{}: {} tuples, {}% overlap, keys below {}\n", name, tuple_number, overlap * 100.0, range).as_ref())?;
+    file.write_all(format!("{}\n", summary.median_s).as_ref())?;
+    if summary.runs > 1 {
+        file.write_all(format!("{}\n", summary).as_ref())?;
+    }
+    file.write_all(format!("{:.0} tuples/s, {:.1} MB/s\n", tuples_per_s, mb_per_s).as_ref())?;
+    match expect_rows {
+        Some(_) => file.write_all(format!("output rows: {}, as expected\n", output_rows).as_ref())?,
+        None => file.write_all(format!("output rows: {}\n", output_rows).as_ref())?,
+    }
+    for phase in op.phase_stats() {
+        file.write_all(format!("{}\n", phase).as_ref())?;
+    }
+    join_report(file, &op)?;
+    if let MergeStrategy::MWay { .. } = strategy {
+        partition_sizes(file, &op)?;
+    }
+    Ok(())
 }
 
-// helper method to benchmark 2^11 = 2048 tuples
-fn c_11(mut file: &File) {
-    file.write_all("2^11 = 2048:\n".as_ref());
-    let width1 = 2;
-    let width2 = 3;
-    let mut left_child = create_vec_tuple(2048 as usize, width1, 1000);
-    let mut right_child = create_vec_tuple(2048 as usize, width2, 1000);
-    let schema1 = get_int_table_schema(width1);
-    let schema2 = get_int_table_schema(width1);
+/// Boxed scan of a join input.
+type Scan = Box<dyn OpIterator + Send>;
 
-    let s1 = Box::new(TupleIterator::new(left_child.clone(), schema1.clone()));
-    let s2 = Box::new(TupleIterator::new(right_child.clone(), schema2.clone()));
-    let s1_1 = Box::new(TupleIterator::new(left_child, schema1));
-    let s2_1 = Box::new(TupleIterator::new(right_child, schema2));
-    let mut op1 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, 1));
-    let mut op2 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1_1, s2_1, 2));
-
-    // M-way
-    file.write_all("m-way:\n".as_ref());
-    let now = Instant::now();
-    op1.open();
-    op1.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
+/// Temporary row files of memory-mapped scans, removed when dropped so every return of a
+/// run removes them, failed ones included.
+struct RowFiles(Vec<PathBuf>);
 
-    // M-way
-    file.write_all("m-pass:\n".as_ref());
-    let now = Instant::now();
-    op2.open();
-    op2.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
+impl Drop for RowFiles {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            // a file that could not be written may not exist
+            let _ = fs::remove_file(path);
+        }
+    }
 }
-// helper method to benchmark 2^15 = 32768 tuples
-fn c_15(mut file: &File) {
-    file.write_all("2^15 = 32768:\n".as_ref());
-    let width1 = 2;
-    let width2 = 3;
-    let mut left_child = create_vec_tuple(32768 as usize, width1, 1000);
-    let mut right_child = create_vec_tuple(32768 as usize, width2, 1000);
-    let schema1 = get_int_table_schema(width1);
-    let schema2 = get_int_table_schema(width1);
-
-    let s1 = Box::new(TupleIterator::new(left_child.clone(), schema1.clone()));
-    let s2 = Box::new(TupleIterator::new(right_child.clone(), schema2.clone()));
-    let s1_1 = Box::new(TupleIterator::new(left_child, schema1));
-    let s2_1 = Box::new(TupleIterator::new(right_child, schema2));
-    let mut op1 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, 1));
-    let mut op2 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1_1, s2_1, 2));
-
-    // M-way
-    file.write_all("m-way:\n".as_ref());
-    let now = Instant::now();
-    op1.open();
-    op1.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
 
-    // M-way
-    file.write_all("m-pass:\n".as_ref());
-    let now = Instant::now();
-    op2.open();
-    op2.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
+// helper method to build the scans of the tables of a join, memory-mapped scans of row files
+// when mapped, returning the scans and the files to remove once done
+fn scans(left: Vec<Tuple>, right: Vec<Tuple>, schema: &TableSchema, mapped: bool) -> Result<(Scan, Scan, RowFiles), Box<dyn Error>> {
+    if !mapped {
+        return Ok((Box::new(TupleIterator::new(left, schema.clone())), Box::new(TupleIterator::new(right, schema.clone())), RowFiles(Vec::new())));
+    }
+    #[cfg(feature = "mmap")]
+    {
+        use join::mmap::{write_row_file, MmapScan};
+        let path = |side: &str| env::temp_dir().join(format!("join-{}-{}.rows", std::process::id(), side));
+        let files = RowFiles(vec![path("left"), path("right")]);
+        write_row_file(&files.0[0], schema, &left)?;
+        write_row_file(&files.0[1], schema, &right)?;
+        let s1 = Box::new(MmapScan::new(&files.0[0], schema.clone()));
+        let s2 = Box::new(MmapScan::new(&files.0[1], schema.clone()));
+        Ok((s1, s2, files))
+    }
+    #[cfg(not(feature = "mmap"))]
+    Err("--mmap needs the mmap feature".into())
 }
-// helper method to benchmark 2^17 = 131072 tuples
-fn c_17(mut file: &File) {
-    file.write_all("2^17 = 131072:\n".as_ref());
-    let width1 = 2;
-    let width2 = 3;
-    let mut left_child = create_vec_tuple(131072 as usize, width1, 1000);
-    let mut right_child = create_vec_tuple(131072 as usize, width2, 1000);
-    let schema1 = get_int_table_schema(width1);
-    let schema2 = get_int_table_schema(width1);
 
-    let s1 = Box::new(TupleIterator::new(left_child.clone(), schema1.clone()));
-    let s2 = Box::new(TupleIterator::new(right_child.clone(), schema2.clone()));
-    let s1_1 = Box::new(TupleIterator::new(left_child, schema1));
-    let s2_1 = Box::new(TupleIterator::new(right_child, schema2));
-    let mut op1 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, 1));
-    let mut op2 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1_1, s2_1, 2));
+// method to run the join picked by the cost model, logging its decision before the timing and
+// the rows predicted from the table statistics next to the actual rows
+// arguments: [tuples] [overlap %] [range] [memory budget in bytes]
+fn run_plan(file: &mut dyn Write, args: &[String], seed: u64) -> Result<(), Box<dyn Error>> {
+    let tuple_number = args.first().map_or(Ok(2048), |s| s.parse())?;
+    let overlap: usize = args.get(1).map_or(Ok(10), |s| s.parse())?;
+    let range = args.get(2).map_or(Ok(1000), |s| s.parse())?;
+    let budget: Option<usize> = args.get(3).map(|s| s.parse()).transpose()?;
 
-    // M-way
-    file.write_all("m-way:\n".as_ref());
-    let now = Instant::now();
-    op1.open();
-    op1.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
+    let width = 2;
+    let (left_child, right_child) = create_overlapping_tuples(tuple_number, width, range, overlap as f64 / 100.0, seed);
+    let schema = get_int_table_schema(width);
+    let mut s1 = Box::new(TupleIterator::new(left_child, schema.clone()));
+    let mut s2 = Box::new(TupleIterator::new(right_child, schema));
+    let left_stats = TableStats::from_op(s1.as_mut(), 1024)?;
+    let right_stats = TableStats::from_op(s2.as_mut(), 1024)?;
+    let predicted = estimate_join_rows(&left_stats.columns[1], &right_stats.columns[1], SimplePredicateOp::Equals);
+    let mut plan = JoinPlan::new(left_stats.plan_input(1), right_stats.plan_input(1), SimplePredicateOp::Equals);
+    if let Some(budget) = budget {
+        plan.set_memory_budget(budget);
+    }
+    let (mut op, report) = choose_join(&plan, s1, s2)?;
+    file.write_all(format!("{}\n", report).as_ref())?;
 
-    // M-way
-    file.write_all("m-pass:\n".as_ref());
     let now = Instant::now();
-    op2.open();
-    op2.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
-}
-// method to benchmark different cardinality
-fn cardinality(mut file: &File) {
-    file.write_all("Micro-benchmark with different cardinality\n".as_ref());
-    // c_11(file);
-    // c_15(file);
-    c_17(file);
+    op.open()?;
+    let mut output_rows = 0;
+    while op.next()?.is_some() {
+        output_rows += 1;
+    }
+    let elapsed = now.elapsed().as_secs_f64();
+    file.write_all(format!("{} tuples, {} predicted, in {}\n", output_rows, predicted, elapsed).as_ref())?;
+    Ok(())
 }
 
-// helper method to benchmark 2048 tuples with 4000-5000
-fn r_5000(mut file: &File) {
-    file.write_all("4000-5000:\n".as_ref());
-    let width1 = 2;
-    let width2 = 3;
-    let mut left_child = create_vec_tuple(2048 as usize, width1, 5000);
-    let mut right_child = create_vec_tuple(2048 as usize, width2, 5000);
-    let schema1 = get_int_table_schema(width1);
-    let schema2 = get_int_table_schema(width1);
-
-    let s1 = Box::new(TupleIterator::new(left_child.clone(), schema1.clone()));
-    let s2 = Box::new(TupleIterator::new(right_child.clone(), schema2.clone()));
-    let s1_1 = Box::new(TupleIterator::new(left_child, schema1));
-    let s2_1 = Box::new(TupleIterator::new(right_child, schema2));
-    let mut op1 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, 1));
-    let mut op2 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1_1, s2_1, 2));
-
-    // M-way
-    file.write_all("m-way:\n".as_ref());
-    let now = Instant::now();
-    op1.open();
-    op1.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
+// method to run a query over two generated tables l and r, each with the columns id and key,
+// and print its tuples then its plan
+// arguments: "query" [tuples] [overlap %] [range]
+#[cfg(feature = "sql")]
+fn run_sql(file: &mut dyn Write, args: &[String], seed: u64) -> Result<(), Box<dyn Error>> {
+    let sql = args.first().ok_or("sql needs a query")?;
+    let tuple_number = args.get(1).map_or(Ok(2048), |s| s.parse())?;
+    let overlap: usize = args.get(2).map_or(Ok(10), |s| s.parse())?;
+    let range = args.get(3).map_or(Ok(1000), |s| s.parse())?;
+
+    let (left, right) = create_overlapping_tuples(tuple_number, 2, range, overlap as f64 / 100.0, seed);
+    let schema = TableSchema::from_vecs(vec!["id", "key"], vec![DataType::Int, DataType::Int]);
+    let mut catalog = join::catalog::Catalog::new();
+    catalog.register_tuples("l", left, schema.clone());
+    catalog.register_tuples("r", right, schema);
+    let ctx = join::sql::SqlContext::new(catalog);
+    let mut op = ctx.execute(sql)?;
+    join::operators::write_csv(&mut op, file)?;
+    file.write_all(op.to_string().as_ref())?;
+    Ok(())
+}
 
-    // M-way
-    file.write_all("m-pass:\n".as_ref());
-    let now = Instant::now();
-    op2.open();
-    op2.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
+// helper method to benchmark one sort kernel on copies of the tuples, made outside of the
+// timed runs
+#[allow(clippy::too_many_arguments)]
+fn kernel(
+    file: &mut dyn Write,
+    name: &str,
+    kernel: SortKernel,
+    tuples: &[Tuple],
+    config: &SortConfig,
+    tuple_bytes: usize,
+    repeats: Repeats,
+) -> Result<(), Box<dyn Error>> {
+    file.write_all(format!("{}:\n", name).as_ref())?;
+    let mut copies: Vec<Vec<Tuple>> = (0..repeats.warmups + repeats.runs.max(1)).map(|_| tuples.to_vec()).collect();
+    #[cfg(feature = "perf")]
+    let counters = join::perf::PerfCounters::open().ok();
+    let (samples, _) = repeats.time(|| {
+        form_runs(kernel, copies.pop().unwrap(), 1, config, tuple_bytes);
+        Ok(())
+    })?;
+    let summary = Summary::of(&samples).unwrap();
+    file.write_all(summary.median_s.to_string().as_ref())?;
+    file.write_all("\n".as_ref())?;
+    if summary.runs > 1 {
+        file.write_all(format!("{}\n", summary).as_ref())?;
+    }
+    #[cfg(feature = "perf")]
+    if let Some(counters) = counters {
+        file.write_all(format!("{}\n", counters.read()?).as_ref())?;
+    }
+    Ok(())
 }
-// helper method to benchmark 2048 tuples with 9000-10000
-fn r_10000(mut file: &File) {
-    file.write_all("9000-10000:\n".as_ref());
-    let width1 = 2;
-    let width2 = 3;
-    let mut left_child = create_vec_tuple(2048 as usize, width1, 10000);
-    let mut right_child = create_vec_tuple(2048 as usize, width2, 10000);
-    let schema1 = get_int_table_schema(width1);
-    let schema2 = get_int_table_schema(width1);
 
-    let s1 = Box::new(TupleIterator::new(left_child.clone(), schema1.clone()));
-    let s2 = Box::new(TupleIterator::new(right_child.clone(), schema2.clone()));
-    let s1_1 = Box::new(TupleIterator::new(left_child, schema1));
-    let s2_1 = Box::new(TupleIterator::new(right_child, schema2));
-    let mut op1 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, 1));
-    let mut op2 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1_1, s2_1, 2));
+// method to benchmark the run generation and sort kernels without the join
+// arguments: [tuples] [run size] [target run size] [int|string]
+fn kernels(file: &mut dyn Write, args: &[String], seed: u64, repeats: Repeats) -> Result<(), Box<dyn Error>> {
+    let tuple_number = args.first().map_or(Ok(131072), |s| s.parse())?;
+    let run_size = args.get(1).map_or(Ok(4), |s| s.parse())?;
+    let target_run_size = args.get(2).map_or(Ok(8), |s| s.parse())?;
+    let key_type = args.get(3).map_or("int", |s| s.as_str());
 
-    // M-way
-    file.write_all("m-way:\n".as_ref());
-    let now = Instant::now();
-    op1.open();
-    op1.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
+    let width = 2;
+    let mut tuples = create_vec_tuple(tuple_number, width, 1000, seed);
+    let mut schema = get_int_table_schema(width);
+    if key_type == "string" {
+        // zero padded so the strings sort like the integers they come from
+        for t in tuples.iter_mut() {
+            let key = t.get_field(1).unwrap().unwrap_int_field();
+            t.set_field(1, Field::StringField(format!("{:010}", key)));
+        }
+        schema = TableSchema::new(vec![
+            Attribute::new(String::new(), DataType::Int),
+            Attribute::new(String::new(), DataType::String),
+        ]);
+    }
+    let config = SortConfig::new(run_size, target_run_size);
+
+    file.write_all(format!(
+        "Sort kernels: {} {} keys, runs of {} to {}\n",
+        tuple_number, key_type, run_size, target_run_size).as_ref())?;
+    for (name, sort_kernel) in [
+        ("network", SortKernel::Network),
+        ("std", SortKernel::Std),
+        ("columnar", SortKernel::Columnar),
+        ("radix", SortKernel::Radix),
+    ] {
+        kernel(file, name, sort_kernel, &tuples, &config, schema.byte_size(), repeats)?;
+    }
+    Ok(())
+}
 
-    // M-way
-    file.write_all("m-pass:\n".as_ref());
-    let now = Instant::now();
-    op2.open();
-    op2.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
+// method to sweep the run size and merge fan-in of the network kernel, next to the
+// configuration sized on the detected caches
+// arguments: [tuples]
+fn run_sizes(file: &mut dyn Write, args: &[String], seed: u64) -> Result<(), Box<dyn Error>> {
+    let tuple_number = args.first().map_or(Ok(131072), |s| s.parse())?;
+    let width = 2;
+    let tuples = create_vec_tuple(tuple_number, width, 1000, seed);
+    let tuple_bytes = get_int_table_schema(width).byte_size();
+    let cache = CacheSizes::detect();
+    let auto = SortConfig::for_cache(&cache, tuple_bytes);
+    file.write_all(format!(
+        "Run size sweep: {} tuples, L1 {} bytes, L2 {} bytes, target run size {}\n",
+        tuple_number, cache.l1_bytes, cache.l2_bytes, auto.target_run_size).as_ref())?;
+    file.write_all("run size,fan-in,levels,seconds\n".as_ref())?;
+    let mut configs = Vec::new();
+    for run_size in [4, 8, 64, 512, auto.run_size] {
+        for fan_in in [2, 4, 8, 16] {
+            let mut config = SortConfig::new(run_size, auto.target_run_size);
+            config.set_merge_fan_in(fan_in);
+            configs.push(config);
+        }
+    }
+    configs.dedup();
+    for config in configs {
+        let now = Instant::now();
+        form_runs(SortKernel::Network, tuples.clone(), 1, &config, tuple_bytes);
+        let elapsed = now.elapsed().as_secs_f64();
+        file.write_all(format!(
+            "{},{},{},{}\n",
+            config.run_size, config.merge_fan_in, config.merge_levels(tuple_bytes), elapsed).as_ref())?;
+    }
+    Ok(())
 }
-// helper method to benchmark 2048 tuples with 99000-100000
-fn r_100000(mut file: &File) {
-    file.write_all("99000-100000:\n".as_ref());
-    let width1 = 2;
-    let width2 = 3;
-    let mut left_child = create_vec_tuple(2048 as usize, width1, 100000);
-    let mut right_child = create_vec_tuple(2048 as usize, width2, 100000);
-    let schema1 = get_int_table_schema(width1);
-    let schema2 = get_int_table_schema(width1);
 
-    let s1 = Box::new(TupleIterator::new(left_child.clone(), schema1.clone()));
-    let s2 = Box::new(TupleIterator::new(right_child.clone(), schema2.clone()));
-    let s1_1 = Box::new(TupleIterator::new(left_child, schema1));
-    let s2_1 = Box::new(TupleIterator::new(right_child, schema2));
-    let mut op1 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, 1));
-    let mut op2 = Box::new(SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1_1, s2_1, 2));
+// method to run every combination of a grid of join parameters, writing one result per
+// timed run
+// arguments: grid.toml [csv|json] [results file], see join::sweep for the grid; with a
+// results file the statistics of each configuration are printed
+#[cfg(feature = "sweep")]
+fn run_grid(file: &mut dyn Write, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let path = args.first().ok_or("sweep needs a grid file")?;
+    let format = args.get(1).map_or(Some(ResultFormat::Csv), |s| ResultFormat::from_name(s)).ok_or("unknown result format")?;
+    let grid = SweepGrid::from_toml(&fs::read_to_string(path)?)?;
+    let run_id = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis().to_string();
+    let results = run_sweep(&grid, &run_id)?;
+    match args.get(2) {
+        Some(out) => {
+            write_results(&results, format, &mut fs::File::create(out)?)?;
+            // the results went to the file, so the statistics of each configuration are shown
+            for config in summarize(&results) {
+                file.write_all(format!(
+                    "{} {} tuples, {}% overlap, {} threads: {}\n",
+                    config.algorithm, config.rows, config.overlap * 100.0, config.threads, config.summary).as_ref())?;
+            }
+        }
+        None => write_results(&results, format, file)?,
+    }
+    Ok(())
+}
 
-    // M-way
-    file.write_all("m-way:\n".as_ref());
-    let now = Instant::now();
-    op1.open();
-    op1.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
+// helper method to remove --seed and its value from the arguments, so a run can be repeated
+// with the same data
+fn take_seed(args: &mut Vec<String>) -> Result<u64, Box<dyn Error>> {
+    match args.iter().position(|s| s == "--seed") {
+        None => Ok(DEFAULT_SEED),
+        Some(i) => {
+            let seed = args.get(i + 1).ok_or("--seed needs a value")?.parse()?;
+            args.drain(i..i + 2);
+            Ok(seed)
+        }
+    }
+}
 
-    // M-way
-    file.write_all("m-pass:\n".as_ref());
-    let now = Instant::now();
-    op2.open();
-    op2.next();
-    file.write_all(now.elapsed().as_secs_f64().to_string().as_ref());
-    file.write_all("\n".as_ref());
+// helper method to remove a flag and its count from the arguments, e.g. --repeat 5
+fn take_count(args: &mut Vec<String>, flag: &str, default: usize) -> Result<usize, Box<dyn Error>> {
+    match args.iter().position(|s| s == flag) {
+        None => Ok(default),
+        Some(i) => {
+            let count = args.get(i + 1).ok_or(format!("{} needs a value", flag))?.parse()?;
+            args.drain(i..i + 2);
+            Ok(count)
+        }
+    }
 }
 
-// method to benchmark different cardinality
-fn range(mut file: &File) {
-    file.write_all("Micro-benchmark with different range\n".as_ref());
-    r_5000(file);
-    r_10000(file);
-    r_100000(file);
+// helper method to remove --timeout and its value in seconds from the arguments, so runs of
+// a sweep with pathological parameters give up instead of hanging
+fn take_timeout(args: &mut Vec<String>) -> Result<Option<Duration>, Box<dyn Error>> {
+    match args.iter().position(|s| s == "--timeout") {
+        None => Ok(None),
+        Some(i) => {
+            let secs: f64 = args.get(i + 1).ok_or("--timeout needs a value")?.parse()?;
+            args.drain(i..i + 2);
+            Ok(Some(Duration::try_from_secs_f64(secs)?))
+        }
+    }
 }
 
-fn main() {
-    let mut file = File::create("res_dis.txt").unwrap();
-    // cardinality(&file);
-    distribution(&file);
-    // range(&file);
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut out = io::stdout();
+    // pick the command from the first argument, a join by default
+    let mut args: Vec<String> = env::args().collect();
+    let seed = take_seed(&mut args)?;
+    let timeout = take_timeout(&mut args)?;
+    let repeats = Repeats {
+        warmups: take_count(&mut args, "--warmups", 0)?,
+        runs: take_count(&mut args, "--repeat", 1)?,
+    };
+    match args.get(1).map(|s| s.as_str()) {
+        Some("kernels") => kernels(&mut out, &args[2..], seed, repeats),
+        Some("runs") => run_sizes(&mut out, &args[2..], seed),
+        #[cfg(feature = "sweep")]
+        Some("sweep") => run_grid(&mut out, &args[2..]),
+        Some("join") => run_join(&mut out, &args[2..], seed, timeout, repeats),
+        Some("plan") => run_plan(&mut out, &args[2..], seed),
+        #[cfg(feature = "sql")]
+        Some("sql") => run_sql(&mut out, &args[2..], seed),
+        _ => run_join(&mut out, &args[1..], seed, timeout, repeats),
+    }
 }
@@ -0,0 +1,483 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+/// Monotonic clock of the metrics, deadlines and traces. wasm32 has no OS clock, so the
+/// browser's performance clock is read there instead.
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+pub use web_time::Instant;
+use crate::common::{CrustyError, Field, OpIterator, TableSchema, Tuple};
+use crate::config::MemoryTracker;
+use serde::{Deserialize, Serialize};
+
+/// Execution statistics of an operator.
+///
+/// Operators count what only they can see, e.g. the join comparisons; time is measured by
+/// wrapping an operator in Metered. Counters restart when the operator is opened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpMetrics {
+    /// Tuples read from the children.
+    pub rows_in: usize,
+    /// Tuples returned.
+    pub rows_out: usize,
+    /// Time spent in open.
+    pub open_time: Duration,
+    /// Time spent in next, over all calls.
+    pub next_time: Duration,
+    /// Partitions or runs written to disk.
+    pub spills: usize,
+    /// Bytes of the pages of the runs written to disk, after compression if any.
+    pub spill_bytes: usize,
+    /// Join key comparisons.
+    pub comparisons: usize,
+    /// Keys an equi-join found more than once on a side, whose blocks of equal keys it
+    /// joined as a cross product.
+    pub dup_blocks: usize,
+    /// Rows joined from those blocks.
+    pub dup_rows: usize,
+    /// Pages of heap files found in the buffer pool.
+    pub page_hits: usize,
+    /// Pages of heap files read from disk through the buffer pool.
+    pub page_misses: usize,
+}
+
+impl fmt::Display for OpMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rows in {}, rows out {}, open {:.3}ms, next {:.3}ms, spills {} ({} bytes), comparisons {}, duplicate key blocks {} ({} rows), page hits {}, page misses {}",
+            self.rows_in,
+            self.rows_out,
+            self.open_time.as_secs_f64() * 1000.0,
+            self.next_time.as_secs_f64() * 1000.0,
+            self.spills,
+            self.spill_bytes,
+            self.comparisons,
+            self.dup_blocks,
+            self.dup_rows,
+            self.page_hits,
+            self.page_misses
+        )
+    }
+}
+
+/// Bytes in a megabyte of the throughputs.
+pub const BYTES_PER_MB: f64 = 1_000_000.0;
+
+/// Rows, bytes and time of one phase of an operator, e.g. the left sort of a join.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PhaseStats {
+    /// Name of the phase, e.g. sort left.
+    pub phase: String,
+    /// Tuples the phase processed.
+    pub rows: usize,
+    /// Bytes of the tuples, see TableSchema::byte_size.
+    pub bytes: usize,
+    /// Time the phase took, in seconds.
+    pub elapsed_s: f64,
+}
+
+impl PhaseStats {
+    /// PhaseStats constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `phase` - Name of the phase.
+    /// * `rows` - Tuples the phase processed.
+    /// * `bytes` - Bytes of the tuples.
+    /// * `elapsed` - Time the phase took.
+    pub fn new(phase: &str, rows: usize, bytes: usize, elapsed: Duration) -> Self {
+        Self {
+            phase: phase.to_string(),
+            rows,
+            bytes,
+            elapsed_s: elapsed.as_secs_f64(),
+        }
+    }
+
+    /// Returns the tuples processed per second, 0 for a phase too short to time.
+    pub fn tuples_per_s(&self) -> f64 {
+        per_second(self.rows as f64, self.elapsed_s)
+    }
+
+    /// Returns the megabytes processed per second, 0 for a phase too short to time.
+    pub fn mb_per_s(&self) -> f64 {
+        per_second(self.bytes as f64 / BYTES_PER_MB, self.elapsed_s)
+    }
+}
+
+impl fmt::Display for PhaseStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} tuples in {:.3}ms, {:.0} tuples/s, {:.1} MB/s",
+            self.phase,
+            self.rows,
+            self.elapsed_s * 1000.0,
+            self.tuples_per_s(),
+            self.mb_per_s()
+        )
+    }
+}
+
+/// Returns an amount divided by seconds, 0 when no time passed.
+///
+/// # Arguments
+///
+/// * `amount` - Amount processed, e.g. tuples.
+/// * `seconds` - Time it took.
+pub fn per_second(amount: f64, seconds: f64) -> f64 {
+    match seconds > 0.0 {
+        true => amount / seconds,
+        false => 0.0,
+    }
+}
+
+/// Measures the time an operator spends in open and next and counts its output.
+///
+/// The other metrics are the ones of the wrapped operator.
+pub struct Metered<T> {
+    /// Wrapped operator.
+    inner: T,
+    /// Rows returned and time measured since the last open.
+    metrics: OpMetrics,
+}
+
+impl<T: OpIterator> Metered<T> {
+    /// Metered constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - Operator to measure.
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            metrics: OpMetrics::default(),
+        }
+    }
+
+    /// Returns the wrapped operator.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: OpIterator> OpIterator for Metered<T> {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.metrics = OpMetrics::default();
+        let start = Instant::now();
+        let res = self.inner.open();
+        self.metrics.open_time = start.elapsed();
+        res
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        let start = Instant::now();
+        let res = self.inner.next();
+        self.metrics.next_time += start.elapsed();
+        if let Ok(Some(_)) = res {
+            self.metrics.rows_out += 1;
+        }
+        res
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.inner.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.inner.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        self.inner.get_schema()
+    }
+
+    fn request_order(&mut self, index: usize) -> bool {
+        self.inner.request_order(index)
+    }
+
+    fn request_key_range(&mut self, index: usize, range: Option<RangeInclusive<Field>>) -> bool {
+        self.inner.request_key_range(index, range)
+    }
+
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        self.inner.sort_order()
+    }
+
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn metrics(&self) -> OpMetrics {
+        OpMetrics {
+            rows_out: self.metrics.rows_out,
+            open_time: self.metrics.open_time,
+            next_time: self.metrics.next_time,
+            ..self.inner.metrics()
+        }
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        self.inner.children()
+    }
+}
+
+/// Format an operator tree with the metrics of every operator, one operator per line
+/// and children indented under their parent.
+///
+/// # Arguments
+///
+/// * `op` - Root of the tree.
+pub fn explain_analyze(op: &dyn OpIterator) -> String {
+    let mut res = String::new();
+    explain_node(op, 0, &mut res);
+    res
+}
+
+// helper method to format an operator and its children at a depth
+fn explain_node(op: &dyn OpIterator, depth: usize, res: &mut String) {
+    res.push_str(&format!("{}{} ({})\n", "  ".repeat(depth), op.name(), op.metrics()));
+    for child in op.children() {
+        explain_node(child, depth + 1, res);
+    }
+}
+
+/// Prefix of the names of the exported metrics.
+pub const METRICS_PREFIX: &str = "join";
+
+/// Totals of the metrics of the runs of one operator.
+#[derive(Debug, Clone, Copy, Default)]
+struct OperatorTotals {
+    /// Number of runs recorded.
+    runs: usize,
+    /// Sum of the metrics of the runs.
+    metrics: OpMetrics,
+}
+
+/// Memory of a tracker at the last recording.
+#[derive(Debug, Clone, Copy, Default)]
+struct MemoryGauge {
+    /// Bytes charged.
+    used: usize,
+    /// Largest number of bytes charged at once.
+    peak: usize,
+}
+
+/// Name, help and value of a metric family of the registry.
+type Family<T> = (&'static str, &'static str, fn(&T) -> f64);
+
+/// Counters exported for every operator.
+const OPERATOR_COUNTERS: [Family<OperatorTotals>; 12] = [
+    ("runs_total", "Recorded runs of the operator.", |t| t.runs as f64),
+    ("rows_in_total", "Tuples read from the children.", |t| t.metrics.rows_in as f64),
+    ("rows_out_total", "Tuples returned.", |t| t.metrics.rows_out as f64),
+    ("open_seconds_total", "Time spent in open.", |t| t.metrics.open_time.as_secs_f64()),
+    ("next_seconds_total", "Time spent in next.", |t| t.metrics.next_time.as_secs_f64()),
+    ("spills_total", "Partitions or runs written to disk.", |t| t.metrics.spills as f64),
+    ("spill_bytes_total", "Bytes of the runs written to disk.", |t| t.metrics.spill_bytes as f64),
+    ("comparisons_total", "Join key comparisons.", |t| t.metrics.comparisons as f64),
+    ("dup_blocks_total", "Keys joined as blocks of duplicates.", |t| t.metrics.dup_blocks as f64),
+    ("dup_rows_total", "Rows joined from blocks of duplicate keys.", |t| t.metrics.dup_rows as f64),
+    ("page_hits_total", "Pages of heap files found in the buffer pool.", |t| t.metrics.page_hits as f64),
+    ("page_misses_total", "Pages of heap files read through the buffer pool.", |t| t.metrics.page_misses as f64),
+];
+
+/// Gauges exported for every memory tracker.
+const MEMORY_GAUGES: [Family<MemoryGauge>; 2] = [
+    ("memory_used_bytes", "Bytes charged to the memory tracker.", |g| g.used as f64),
+    ("memory_peak_bytes", "Largest number of bytes charged to the memory tracker at once.", |g| g.peak as f64),
+];
+
+/// Registry of the metrics of executed operators, in the Prometheus text format.
+///
+/// Services record each operator tree once it ran, and serve render on their scrape
+/// endpoint. Counters are summed by operator name over every recorded run; memory is a
+/// gauge per tracker name. Clones share the same registry, so one can be handed to every
+/// query.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsRegistry {
+    /// Totals by operator name.
+    operators: Arc<Mutex<BTreeMap<String, OperatorTotals>>>,
+    /// Memory gauges by tracker name.
+    memory: Arc<Mutex<BTreeMap<String, MemoryGauge>>>,
+}
+
+impl MetricsRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the metrics of a run of an operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `operator` - Name of the operator, the label of its metrics.
+    /// * `metrics` - Metrics of the run.
+    pub fn observe(&self, operator: &str, metrics: &OpMetrics) {
+        let mut operators = self.operators.lock().unwrap();
+        let totals = operators.entry(operator.to_string()).or_default();
+        totals.runs += 1;
+        let sum = &mut totals.metrics;
+        sum.rows_in += metrics.rows_in;
+        sum.rows_out += metrics.rows_out;
+        sum.open_time += metrics.open_time;
+        sum.next_time += metrics.next_time;
+        sum.spills += metrics.spills;
+        sum.spill_bytes += metrics.spill_bytes;
+        sum.comparisons += metrics.comparisons;
+        sum.dup_blocks += metrics.dup_blocks;
+        sum.dup_rows += metrics.dup_rows;
+        sum.page_hits += metrics.page_hits;
+        sum.page_misses += metrics.page_misses;
+    }
+
+    /// Add the metrics of every operator of a tree that ran, see explain_analyze.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Root of the tree.
+    pub fn record(&self, op: &dyn OpIterator) {
+        self.observe(&op.name(), &op.metrics());
+        for child in op.children() {
+            self.record(child);
+        }
+    }
+
+    /// Set the memory gauges of a tracker.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the tracker, e.g. of its query or operator.
+    /// * `tracker` - Memory tracker.
+    pub fn record_memory(&self, name: &str, tracker: &MemoryTracker) {
+        self.memory.lock().unwrap().insert(
+            name.to_string(),
+            MemoryGauge {
+                used: tracker.used(),
+                peak: tracker.peak(),
+            },
+        );
+    }
+
+    /// Returns the sum of the recorded metrics of an operator, if it ran.
+    ///
+    /// # Arguments
+    ///
+    /// * `operator` - Name of the operator.
+    pub fn totals(&self, operator: &str) -> Option<OpMetrics> {
+        self.operators.lock().unwrap().get(operator).map(|totals| totals.metrics)
+    }
+
+    /// Returns the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let operators = self.operators.lock().unwrap();
+        let memory = self.memory.lock().unwrap();
+        let mut res = String::new();
+        for (name, help, value) in OPERATOR_COUNTERS {
+            write_family(&mut res, name, help, "counter", "operator", operators.iter().map(|(op, t)| (op.as_str(), value(t))));
+        }
+        for (name, help, value) in MEMORY_GAUGES {
+            write_family(&mut res, name, help, "gauge", "tracker", memory.iter().map(|(tracker, g)| (tracker.as_str(), value(g))));
+        }
+        res
+    }
+}
+
+// helper method to write a metric family with one sample per label value, nothing if empty
+fn write_family<'a>(
+    res: &mut String,
+    name: &str,
+    help: &str,
+    kind: &str,
+    label: &str,
+    samples: impl Iterator<Item = (&'a str, f64)>,
+) {
+    let mut samples = samples.peekable();
+    if samples.peek().is_none() {
+        return;
+    }
+    res.push_str(&format!("# HELP {}_{} {}\n", METRICS_PREFIX, name, help));
+    res.push_str(&format!("# TYPE {}_{} {}\n", METRICS_PREFIX, name, kind));
+    for (value_label, value) in samples {
+        let escaped = value_label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        res.push_str(&format!("{}_{}{{{}=\"{}\"}} {}\n", METRICS_PREFIX, name, label, escaped, value));
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{Field, SimplePredicateOp, TupleIterator};
+    use crate::generator::get_int_table_schema;
+    use crate::join::{HashEqJoin, Join, MergeStrategy, SortMergeJoin};
+
+    fn scan(keys: Vec<i32>) -> Box<dyn OpIterator> {
+        let tuples = keys.into_iter().map(|k| Tuple::new(vec![Field::IntField(k)])).collect();
+        Box::new(TupleIterator::new(tuples, get_int_table_schema(1)))
+    }
+
+    #[test]
+    fn explain_join() -> Result<(), CrustyError> {
+        let join = Join::new(SimplePredicateOp::Equals, 0, 0, scan(vec![1, 2, 3]), Box::new(Metered::new(scan(vec![2, 3]))))?;
+        let mut op = Metered::new(join);
+        op.open()?;
+        while op.next()?.is_some() {}
+        let metrics = op.metrics();
+        assert_eq!((metrics.rows_in, metrics.rows_out, metrics.comparisons), (9, 2, 6));
+
+        let lines: Vec<String> = explain_analyze(&op).lines().map(String::from).collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("Join (rows in 9, rows out 2,"));
+        assert!(lines[1].starts_with("  TupleIterator (rows in 0, rows out 0,"));
+        // the metered right child is scanned once per left tuple
+        assert!(lines[2].starts_with("  TupleIterator (rows in 0, rows out 6,"));
+        Ok(())
+    }
+
+    #[test]
+    fn join_counters() -> Result<(), CrustyError> {
+        let mut sort_merge = SortMergeJoin::new(SimplePredicateOp::Equals, 0, 0, scan(vec![3, 1, 2]), scan(vec![2, 3, 2]), MergeStrategy::M_PASS)?;
+        let mut hash = HashEqJoin::new(SimplePredicateOp::Equals, 0, 0, scan(vec![3, 1, 2]), scan(vec![2, 3, 2]))?;
+        for op in [&mut sort_merge as &mut dyn OpIterator, &mut hash] {
+            op.open()?;
+            while op.next()?.is_some() {}
+            let metrics = op.metrics();
+            assert_eq!((metrics.rows_in, metrics.rows_out, metrics.spills), (6, 3, 0));
+            assert!(metrics.comparisons > 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn prometheus_registry() -> Result<(), CrustyError> {
+        let registry = MetricsRegistry::new();
+        for _ in 0..2 {
+            let mut hash = HashEqJoin::new(SimplePredicateOp::Equals, 0, 0, scan(vec![3, 1, 2]), scan(vec![2, 3, 2]))?;
+            hash.open()?;
+            while hash.next()?.is_some() {}
+            registry.record(&hash);
+        }
+        let tracker = MemoryTracker::new(100);
+        tracker.reserve(40);
+        registry.record_memory("query \"1\"", &tracker);
+        assert_eq!(registry.totals("HashEqJoin").map(|m| (m.rows_in, m.rows_out)), Some((12, 6)));
+        assert_eq!(registry.totals("Join"), None);
+
+        let text = registry.render();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines.contains(&"# TYPE join_rows_out_total counter"));
+        assert!(lines.contains(&"join_runs_total{operator=\"HashEqJoin\"} 2"));
+        assert!(lines.contains(&"join_rows_out_total{operator=\"HashEqJoin\"} 6"));
+        assert!(lines.contains(&"join_rows_out_total{operator=\"TupleIterator\"} 0"));
+        assert!(lines.contains(&"join_memory_peak_bytes{tracker=\"query \\\"1\\\"\"} 40"));
+        // every sample follows the HELP and TYPE lines of its family
+        assert_eq!(lines.iter().filter(|l| l.starts_with("# TYPE")).count(), 14);
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+//! Scans of memory-mapped row files, with the mmap feature, on Unix.
+//!
+//! A row file holds tuples as the fixed-size rows of TableSchema::serialize_row, one after
+//! the other without header, so row i starts at byte i * byte_size. MmapScan maps the file
+//! read only and decodes a row when it is asked for, so scans of files larger than memory
+//! leave the paging to the OS page cache instead of allocating every tuple up front.
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::ptr::NonNull;
+use crate::common::{not_open, CrustyError, OpIterator, TableSchema, Tuple};
+
+/// Write tuples to a row file, replacing it.
+///
+/// Fails with a ValidationError if a tuple does not fit the schema, see
+/// TableSchema::serialize_row.
+///
+/// # Arguments
+///
+/// * `path` - Path of the file.
+/// * `schema` - Schema of the tuples.
+/// * `tuples` - Tuples to write, in order.
+pub fn write_row_file<'a>(
+    path: impl AsRef<Path>,
+    schema: &TableSchema,
+    tuples: impl IntoIterator<Item = &'a Tuple>,
+) -> Result<(), CrustyError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for tuple in tuples {
+        writer.write_all(&schema.serialize_row(tuple)?)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read-only mapping of a whole file, unmapped when dropped.
+struct Mapping {
+    /// Start of the mapped bytes.
+    ptr: NonNull<u8>,
+    /// Number of mapped bytes, more than 0.
+    len: usize,
+}
+
+// the mapping is read only and owned by one scan
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+    /// Map a file of len bytes, len being more than 0.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - Open file.
+    /// * `len` - Length of the file.
+    fn new(file: &File, len: usize) -> Result<Self, CrustyError> {
+        // a private read-only mapping of the whole file, read in order
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        unsafe { libc::madvise(ptr, len, libc::MADV_SEQUENTIAL) };
+        Ok(Self {
+            ptr: NonNull::new(ptr as *mut u8).unwrap(),
+            len,
+        })
+    }
+
+    /// Returns the mapped bytes.
+    fn bytes(&self) -> &[u8] {
+        // the mapping stays valid and unchanged until dropped
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.as_ptr() as *mut libc::c_void, self.len) };
+    }
+}
+
+/// Scan of a memory-mapped row file, see write_row_file.
+///
+/// The file must not be changed while the scan is open.
+pub struct MmapScan {
+    /// Path of the file.
+    path: PathBuf,
+    /// Schema of the rows.
+    schema: TableSchema,
+    /// Mapping of the file while open, None for an empty file.
+    mapping: Option<Mapping>,
+    /// Whether the scan is open.
+    open: bool,
+    /// Number of rows of the file.
+    rows: usize,
+    /// Number of the next row.
+    pos: usize,
+}
+
+impl MmapScan {
+    /// MmapScan constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file.
+    /// * `schema` - Schema of the rows.
+    pub fn new(path: impl Into<PathBuf>, schema: TableSchema) -> Self {
+        Self {
+            path: path.into(),
+            schema,
+            mapping: None,
+            open: false,
+            rows: 0,
+            pos: 0,
+        }
+    }
+
+    /// Returns the number of rows of the file, 0 until opened.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+}
+
+impl OpIterator for MmapScan {
+    /// Maps the file, failing with a ValidationError if it is not a whole number of rows.
+    fn open(&mut self) -> Result<(), CrustyError> {
+        let file = File::open(&self.path)?;
+        let len = file.metadata()?.len() as usize;
+        let row_size = self.schema.byte_size();
+        if row_size == 0 || !len.is_multiple_of(row_size) {
+            return Err(CrustyError::ValidationError(format!(
+                "{} has {} bytes, not a multiple of rows of {} bytes",
+                self.path.display(),
+                len,
+                row_size
+            )));
+        }
+        self.mapping = match len {
+            0 => None,
+            len => Some(Mapping::new(&file, len)?),
+        };
+        self.rows = len / row_size;
+        self.pos = 0;
+        self.open = true;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        let mapping = match &self.mapping {
+            Some(mapping) if self.pos < self.rows => mapping,
+            _ => return Ok(None),
+        };
+        let row_size = self.schema.byte_size();
+        let start = self.pos * row_size;
+        self.pos += 1;
+        self.schema.deserialize_row(&mapping.bytes()[start..start + row_size]).map(Some)
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        self.mapping = None;
+        self.open = false;
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        self.pos = 0;
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{env, fs, process};
+    use crate::common::{DataType, Field, SimplePredicateOp, TupleIterator};
+    use crate::generator::{create_vec_tuple, get_int_table_schema};
+    use crate::join::{MergeStrategy, SortMergeJoin};
+    use crate::operators::compare_rows;
+
+    // helper method to name a fixture in the temporary directory
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("join-mmap-{}-{}.rows", process::id(), name))
+    }
+
+    #[test]
+    fn scan_rows() -> Result<(), CrustyError> {
+        let schema = TableSchema::from_vecs(vec!["id", "name"], vec![DataType::Int, DataType::String]);
+        let tuples: Vec<Tuple> =
+            (0..100).map(|i| Tuple::new(vec![Field::IntField(i), Field::StringField(format!("n{}", i))])).collect();
+        let path = temp_path("scan");
+        write_row_file(&path, &schema, &tuples)?;
+        assert_eq!(fs::metadata(&path)?.len() as usize, 100 * schema.byte_size());
+
+        let mut scan = MmapScan::new(&path, schema.clone());
+        if !cfg!(feature = "panic-not-open") {
+            assert!(scan.next().is_err());
+        }
+        scan.open()?;
+        assert_eq!(scan.rows(), 100);
+        let mut read = Vec::new();
+        while let Some(t) = scan.next()? {
+            read.push(t);
+        }
+        assert_eq!(read, tuples);
+        scan.rewind()?;
+        assert_eq!(scan.next()?, Some(tuples[0].clone()));
+        scan.close()?;
+
+        // a truncated row, and an empty file
+        fs::write(&path, vec![0; schema.byte_size() + 1])?;
+        assert!(matches!(scan.open(), Err(CrustyError::ValidationError(_))));
+        fs::write(&path, [])?;
+        scan.open()?;
+        assert_eq!(scan.next()?, None);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn join_mapped_files() -> Result<(), CrustyError> {
+        let schema = get_int_table_schema(2);
+        let (left, right) = (create_vec_tuple(500, 2, 1000, 1), create_vec_tuple(500, 2, 1000, 2));
+        let (left_path, right_path) = (temp_path("left"), temp_path("right"));
+        write_row_file(&left_path, &schema, &left)?;
+        write_row_file(&right_path, &schema, &right)?;
+        let scan = |tuples: Vec<Tuple>| TupleIterator::new(tuples, schema.clone());
+        let mut expected = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(left), scan(right), MergeStrategy::M_WAY)?;
+        let mut op = SortMergeJoin::new(
+            SimplePredicateOp::Equals,
+            1,
+            1,
+            MmapScan::new(&left_path, schema.clone()),
+            MmapScan::new(&right_path, schema.clone()),
+            MergeStrategy::M_WAY,
+        )?;
+        compare_rows(&mut expected, &mut op)?;
+        fs::remove_file(left_path)?;
+        fs::remove_file(right_path)?;
+        Ok(())
+    }
+}
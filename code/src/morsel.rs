@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::thread;
+use crate::config::CoreAffinity;
+
+/// Default number of left tuples in a morsel of the level 3 join.
+pub const MORSEL_SIZE: usize = 4096;
+
+/// Whether worker pools run on threads. wasm32 cannot spawn them, so there every pool runs
+/// its tasks one after the other on the caller's thread, the sequential execution mode.
+pub const THREADED: bool = cfg!(not(target_arch = "wasm32"));
+
+/// Returns the default number of workers, one per available core, or 1 without threads.
+pub fn default_workers() -> usize {
+    match THREADED {
+        true => thread::available_parallelism().map_or(1, |n| n.get()),
+        false => 1,
+    }
+}
+
+/// Pin the current thread to a core, returning false if it stays unpinned.
+///
+/// A no-op without the affinity feature or on a platform without thread affinity.
+///
+/// # Arguments
+///
+/// * `core` - Id of the core.
+pub fn pin_current_thread(core: usize) -> bool {
+    #[cfg(feature = "affinity")]
+    {
+        core_affinity::set_for_current(core_affinity::CoreId { id: core })
+    }
+    #[cfg(not(feature = "affinity"))]
+    {
+        let _ = core;
+        false
+    }
+}
+
+/// Run a task on every item with a pool of workers, returning the results in item order.
+///
+/// Items are dealt round-robin onto one queue per worker. A worker takes from the front
+/// of its own queue and, once it is empty, steals from the back of the others, so uneven
+/// items still keep every worker busy. A single worker or item runs on the caller's thread,
+/// as does every item without threads, see THREADED.
+///
+/// # Arguments
+///
+/// * `items` - Items to run the task on.
+/// * `workers` - Number of worker threads, at least 1.
+/// * `task` - Task run on each item.
+pub fn parallel_map<T: Send, R: Send>(items: Vec<T>, workers: usize, task: impl Fn(T) -> R + Sync) -> Vec<R> {
+    parallel_map_on(items, workers, None, task)
+}
+
+/// parallel_map with the workers pinned to a range of cores, see CoreAffinity.
+///
+/// # Arguments
+///
+/// * `items` - Items to run the task on.
+/// * `workers` - Number of worker threads, at least 1.
+/// * `cores` - Cores of the workers, unpinned if None.
+/// * `task` - Task run on each item.
+pub fn parallel_map_on<T: Send, R: Send>(
+    items: Vec<T>,
+    workers: usize,
+    cores: Option<CoreAffinity>,
+    task: impl Fn(T) -> R + Sync,
+) -> Vec<R> {
+    let workers = workers.clamp(1, items.len().max(1));
+    // the caller's thread is not pinned, a pinned phase always runs on its workers
+    if !THREADED || (workers == 1 && cores.is_none()) {
+        return items.into_iter().map(task).collect();
+    }
+    let len = items.len();
+    let queues: Vec<Mutex<VecDeque<(usize, T)>>> = (0..workers).map(|_| Mutex::new(VecDeque::new())).collect();
+    for (i, item) in items.into_iter().enumerate() {
+        queues[i % workers].lock().unwrap().push_back((i, item));
+    }
+    let done: Vec<Vec<(usize, R)>> = thread::scope(|s| {
+        let handles: Vec<_> = (0..workers)
+            .map(|worker| {
+                let (queues, task) = (&queues, &task);
+                s.spawn(move || {
+                    if let Some(cores) = cores {
+                        pin_current_thread(cores.core(worker));
+                    }
+                    let mut done = Vec::new();
+                    while let Some((i, item)) = next_item(queues, worker) {
+                        done.push((i, task(item)));
+                    }
+                    done
+                })
+            })
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    });
+    // no item is added while the workers run, so every item was taken exactly once
+    let mut results: Vec<Option<R>> = (0..len).map(|_| None).collect();
+    for (i, result) in done.into_iter().flatten() {
+        results[i] = Some(result);
+    }
+    results.into_iter().flatten().collect()
+}
+
+// helper method to take the next item of a worker, stealing from the other queues once its own is empty
+fn next_item<T>(queues: &[Mutex<VecDeque<(usize, T)>>], worker: usize) -> Option<(usize, T)> {
+    if let Some(item) = queues[worker].lock().unwrap().pop_front() {
+        return Some(item);
+    }
+    (1..queues.len()).find_map(|k| queues[(worker + k) % queues.len()].lock().unwrap().pop_back())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn keeps_item_order() {
+        let threads = Mutex::new(Vec::new());
+        let calls = AtomicUsize::new(0);
+        let squares = parallel_map((0..1000u64).collect(), 4, |i| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            threads.lock().unwrap().push(thread::current().id());
+            // uneven items, so the workers steal from each other
+            if i % 97 == 0 {
+                thread::sleep(std::time::Duration::from_millis(2));
+            }
+            i * i
+        });
+        assert_eq!(squares, (0..1000u64).map(|i| i * i).collect::<Vec<_>>());
+        assert_eq!(calls.load(Ordering::Relaxed), 1000);
+        let mut threads = threads.into_inner().unwrap();
+        threads.sort_by_key(|id| format!("{:?}", id));
+        threads.dedup();
+        assert!(threads.len() <= 4);
+        assert!(parallel_map(Vec::<u64>::new(), 4, |i| i).is_empty());
+    }
+
+    #[test]
+    fn pinned_workers() {
+        let cores = CoreAffinity::new(0, 1);
+        assert_eq!((cores.core(0), cores.core(3)), (0, 0));
+        // pinning may not be supported, but the items are run either way
+        let sums = parallel_map_on((0..100u64).collect(), 2, Some(cores), |i| i + 1);
+        assert_eq!(sums.iter().sum::<u64>(), 5050);
+        if !cfg!(feature = "affinity") {
+            assert!(!pin_current_thread(0));
+        }
+    }
+}
@@ -0,0 +1,196 @@
+/// Comparators of the sorting network of 4 keys.
+const NETWORK_4: [(usize, usize); 5] = [(0, 1), (2, 3), (0, 2), (1, 3), (1, 2)];
+
+/// Comparators of Batcher's odd-even merge sorting network of 8 keys.
+const NETWORK_8: [(usize, usize); 19] = [
+    (0, 2), (1, 3), (4, 6), (5, 7),
+    (0, 4), (1, 5), (2, 6), (3, 7),
+    (0, 1), (2, 3), (4, 5), (6, 7),
+    (2, 4), (3, 5),
+    (1, 4), (3, 6),
+    (1, 2), (3, 4), (5, 6),
+];
+
+/// Sort 4 or 8 normalized keys with a sorting network, see keys::normalize_key.
+///
+/// Returns the row ids in key order, None for any other number of keys. With the simd
+/// feature on an AVX2 cpu the keys and row ids are sorted in vector registers with
+/// min/max vector ops; otherwise the same network runs on scalars.
+///
+/// # Arguments
+///
+/// * `keys` - Normalized key of every row.
+pub fn sort_keys(keys: &[u64]) -> Option<Vec<usize>> {
+    match keys.len() {
+        4 => {
+            #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+            if is_x86_feature_detected!("avx2") {
+                // SAFETY: the cpu supports avx2 and there are exactly 4 keys
+                return Some(unsafe { avx2::sort4(keys) });
+            }
+            Some(sort_scalar(keys, &NETWORK_4))
+        }
+        8 => {
+            #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+            if is_x86_feature_detected!("avx2") {
+                // SAFETY: the cpu supports avx2 and there are exactly 8 keys
+                return Some(unsafe { avx2::sort8(keys) });
+            }
+            Some(sort_scalar(keys, &NETWORK_8))
+        }
+        _ => None,
+    }
+}
+
+// helper method to run a sorting network on scalar keys, carrying their row ids
+fn sort_scalar(keys: &[u64], network: &[(usize, usize)]) -> Vec<usize> {
+    let mut keyed: Vec<(u64, usize)> = keys.iter().copied().zip(0..).collect();
+    for (i, j) in network {
+        if keyed[*i].0 > keyed[*j].0 {
+            keyed.swap(*i, *j);
+        }
+    }
+    keyed.into_iter().map(|(_, row)| row).collect()
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    /// Lane order swapping neighbours, pairs (0, 1) and (2, 3).
+    const PAIRS: i32 = 0b10_11_00_01;
+    /// Lane order swapping halves, pairs (0, 2) and (1, 3).
+    const HALVES: i32 = 0b01_00_11_10;
+    /// Lane order swapping the middle lanes, pair (1, 2).
+    const MIDDLE: i32 = 0b11_01_10_00;
+    /// Lane order reversing the lanes.
+    const REVERSE: i32 = 0b00_01_10_11;
+
+    // helper method to load 4 lanes
+    #[target_feature(enable = "avx2")]
+    unsafe fn load(lanes: [i64; 4]) -> __m256i {
+        _mm256_loadu_si256(lanes.as_ptr() as *const __m256i)
+    }
+
+    // helper method to store 4 lanes
+    #[target_feature(enable = "avx2")]
+    unsafe fn store(v: __m256i) -> [i64; 4] {
+        let mut lanes = [0i64; 4];
+        _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, v);
+        lanes
+    }
+
+    // helper method to compare unsigned keys, avx2 only has a signed 64 bit compare
+    #[target_feature(enable = "avx2")]
+    unsafe fn greater(a: __m256i, b: __m256i) -> __m256i {
+        let bias = _mm256_set1_epi64x(i64::MIN);
+        _mm256_cmpgt_epi64(_mm256_xor_si256(a, bias), _mm256_xor_si256(b, bias))
+    }
+
+    // helper method to run one stage of compare-exchanges between the lanes paired by
+    // the permutation; lanes in min keep the smaller key, lanes in max the larger one
+    #[target_feature(enable = "avx2")]
+    unsafe fn stage<const PERM: i32>(keys: __m256i, rows: __m256i, min: [i64; 4], max: [i64; 4]) -> (__m256i, __m256i) {
+        let other_keys = _mm256_permute4x64_epi64::<PERM>(keys);
+        let other_rows = _mm256_permute4x64_epi64::<PERM>(rows);
+        // equal keys never swap, so both lanes of a pair agree on the outcome
+        let swap = _mm256_or_si256(
+            _mm256_and_si256(greater(keys, other_keys), load(min)),
+            _mm256_and_si256(greater(other_keys, keys), load(max)),
+        );
+        (_mm256_blendv_epi8(keys, other_keys, swap), _mm256_blendv_epi8(rows, other_rows, swap))
+    }
+
+    // helper method to sort the 4 lanes of a register
+    #[target_feature(enable = "avx2")]
+    unsafe fn sort_register(keys: __m256i, rows: __m256i) -> (__m256i, __m256i) {
+        let (keys, rows) = stage::<PAIRS>(keys, rows, [-1, 0, -1, 0], [0, -1, 0, -1]);
+        let (keys, rows) = stage::<HALVES>(keys, rows, [-1, -1, 0, 0], [0, 0, -1, -1]);
+        stage::<MIDDLE>(keys, rows, [0, -1, 0, 0], [0, 0, -1, 0])
+    }
+
+    // helper method to sort the 4 lanes of a bitonic register
+    #[target_feature(enable = "avx2")]
+    unsafe fn clean_bitonic(keys: __m256i, rows: __m256i) -> (__m256i, __m256i) {
+        let (keys, rows) = stage::<HALVES>(keys, rows, [-1, -1, 0, 0], [0, 0, -1, -1]);
+        stage::<PAIRS>(keys, rows, [-1, 0, -1, 0], [0, -1, 0, -1])
+    }
+
+    // helper method to load 4 keys
+    #[target_feature(enable = "avx2")]
+    unsafe fn load_keys(keys: &[u64]) -> __m256i {
+        _mm256_loadu_si256(keys.as_ptr() as *const __m256i)
+    }
+
+    // helper method to convert stored row id lanes
+    fn row_ids(lanes: [i64; 4]) -> impl Iterator<Item = usize> {
+        lanes.into_iter().map(|row| row as usize)
+    }
+
+    /// Sort exactly 4 keys, returning their row ids in key order.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn sort4(keys: &[u64]) -> Vec<usize> {
+        let (_, rows) = sort_register(load_keys(keys), load([0, 1, 2, 3]));
+        row_ids(store(rows)).collect()
+    }
+
+    /// Sort exactly 8 keys, returning their row ids in key order.
+    ///
+    /// Both halves are sorted in their own register, then merged by a bitonic merge.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn sort8(keys: &[u64]) -> Vec<usize> {
+        let (a_keys, a_rows) = sort_register(load_keys(&keys[..4]), load([0, 1, 2, 3]));
+        let (b_keys, b_rows) = sort_register(load_keys(&keys[4..]), load([4, 5, 6, 7]));
+        // the first half ascending and the second descending form a bitonic sequence
+        let b_keys = _mm256_permute4x64_epi64::<REVERSE>(b_keys);
+        let b_rows = _mm256_permute4x64_epi64::<REVERSE>(b_rows);
+        let swap = greater(a_keys, b_keys);
+        let (_, low_rows) = clean_bitonic(
+            _mm256_blendv_epi8(a_keys, b_keys, swap),
+            _mm256_blendv_epi8(a_rows, b_rows, swap),
+        );
+        let (_, high_rows) = clean_bitonic(
+            _mm256_blendv_epi8(b_keys, a_keys, swap),
+            _mm256_blendv_epi8(b_rows, a_rows, swap),
+        );
+        row_ids(store(low_rows)).chain(row_ids(store(high_rows))).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::generator::seeded_rng;
+    use rand::Rng;
+
+    // helper method to check that the row ids put the keys in order
+    fn check(keys: &[u64]) {
+        let rows = sort_keys(keys).unwrap();
+        let mut seen = rows.clone();
+        seen.sort();
+        assert_eq!(seen, (0..keys.len()).collect::<Vec<_>>());
+        assert!(rows.windows(2).all(|w| keys[w[0]] <= keys[w[1]]), "{:?}", keys);
+    }
+
+    #[test]
+    fn sorts_every_binary_input() {
+        // by the 0-1 principle a network sorting every input of 0s and 1s sorts any input
+        for len in [4, 8] {
+            for bits in 0..1u64 << len {
+                let keys: Vec<u64> = (0..len).map(|i| (bits >> i) & 1).collect();
+                check(&keys);
+            }
+        }
+        assert!(sort_keys(&[1, 2, 3]).is_none());
+    }
+
+    #[test]
+    fn sorts_unsigned_keys() {
+        let mut rng = seeded_rng(3);
+        for _ in 0..1000 {
+            let keys: Vec<u64> = (0..8).map(|_| rng.gen_range(0..4) << 62 | rng.gen_range(0..8)).collect();
+            check(&keys);
+            check(&keys[..4]);
+        }
+    }
+}
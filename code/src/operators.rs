@@ -0,0 +1,1097 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use crate::config::ProgressMonitor;
+use crate::trace::phase_span;
+use crate::expr::Expr;
+use crate::common::{not_open, AggOp, Attribute, CrustyError, DataType, Field, Fields, OpIterator, SimplePredicateOp, TableSchema, Tuple};
+
+/// Scan of a CSV file without header, one tuple per line.
+///
+/// Fields are separated by commas and parsed by the dtypes of the schema.
+pub struct CsvScan {
+    /// Path of the file.
+    path: PathBuf,
+    /// Schema of the lines.
+    schema: TableSchema,
+    /// Reader of the file while open.
+    reader: Option<BufReader<File>>,
+    /// Number of the last line read, for error messages.
+    line: usize,
+    /// Field and values of the tuples returned, all if None.
+    key_range: Option<(usize, RangeInclusive<Field>)>,
+}
+
+impl CsvScan {
+    /// CsvScan constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file.
+    /// * `schema` - Schema of the lines.
+    pub fn new(path: impl Into<PathBuf>, schema: TableSchema) -> Self {
+        Self {
+            path: path.into(),
+            schema,
+            reader: None,
+            line: 0,
+            key_range: None,
+        }
+    }
+}
+
+/// Parse a CSV line into a tuple of a schema, failing with an ExecutionError naming the line.
+///
+/// # Arguments
+///
+/// * `line` - Line without its line break.
+/// * `number` - Number of the line in the file.
+/// * `schema` - Schema of the lines.
+pub(crate) fn parse_csv_line(line: &str, number: usize, schema: &TableSchema) -> Result<Tuple, CrustyError> {
+    let values: Vec<&str> = line.split(',').collect();
+    if values.len() != schema.size() {
+        return Err(CrustyError::ExecutionError(format!(
+            "line {} has {} fields, expected {}",
+            number,
+            values.len(),
+            schema.size()
+        )));
+    }
+    let mut fields = Fields::with_capacity(values.len());
+    for (value, attr) in values.iter().zip(schema.attributes()) {
+        let field = match attr.dtype() {
+            DataType::Int => Field::IntField(value.trim().parse().map_err(|_| {
+                CrustyError::ExecutionError(format!("line {}: {:?} is not an integer", number, value))
+            })?),
+            DataType::String => Field::StringField(value.to_string()),
+        };
+        fields.push(field);
+    }
+    Ok(Tuple::from_fields(fields))
+}
+
+impl OpIterator for CsvScan {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.reader = Some(BufReader::new(File::open(&self.path)?));
+        self.line = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        let reader = match self.reader.as_mut() {
+            Some(reader) => reader,
+            None => return not_open(),
+        };
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+            // empty lines, e.g. a trailing one, are skipped
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                continue;
+            }
+            let tuple = parse_csv_line(line, self.line, &self.schema)?;
+            if in_key_range(&tuple, self.key_range.as_ref()) {
+                return Ok(Some(tuple));
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if self.reader.take().is_none() {
+            return not_open();
+        }
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if self.reader.is_none() {
+            return not_open();
+        }
+        self.open()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+
+    /// Lines outside the range are skipped once parsed.
+    fn request_key_range(&mut self, index: usize, range: Option<RangeInclusive<Field>>) -> bool {
+        if index >= self.schema.size() {
+            return false;
+        }
+        self.key_range = range.map(|range| (index, range));
+        true
+    }
+}
+
+/// Whether the field of a tuple lies in the range a scan was asked for, see
+/// OpIterator::request_key_range.
+///
+/// # Arguments
+///
+/// * `tuple` - Tuple read by the scan.
+/// * `key_range` - Index of the field and the values kept, if any.
+pub(crate) fn in_key_range(tuple: &Tuple, key_range: Option<&(usize, RangeInclusive<Field>)>) -> bool {
+    key_range.is_none_or(|(index, range)| tuple.get_field(*index).is_some_and(|field| range.contains(field)))
+}
+
+/// Selection of the tuples whose field compares to a constant.
+pub struct Filter {
+    /// Child node.
+    child: Box<dyn OpIterator + Send>,
+    /// Index of the compared field.
+    index: usize,
+    /// Comparison of the field (left) with the constant (right).
+    op: SimplePredicateOp,
+    /// Constant the field is compared with.
+    value: Field,
+}
+
+impl Filter {
+    /// Filter constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - Child node.
+    /// * `index` - Index of the compared field.
+    /// * `op` - Comparison of the field with the constant.
+    /// * `value` - Constant the field is compared with.
+    pub fn new(child: Box<dyn OpIterator + Send>, index: usize, op: SimplePredicateOp, value: Field) -> Self {
+        Self { child, index, op, value }
+    }
+}
+
+impl OpIterator for Filter {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        while let Some(t) = self.child.next()? {
+            let field = t
+                .get_field(self.index)
+                .ok_or_else(|| CrustyError::ExecutionError(format!("tuple has no field {}", self.index)))?;
+            if self.op.compare(field, &self.value) {
+                return Ok(Some(t));
+            }
+        }
+        Ok(None)
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.child.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.child.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        self.child.get_schema()
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.child]
+    }
+
+    /// Filtering keeps the order of the child.
+    fn request_order(&mut self, index: usize) -> bool {
+        self.child.request_order(index)
+    }
+
+    /// The child can skip the tuples outside the range before they are compared.
+    fn request_key_range(&mut self, index: usize, range: Option<RangeInclusive<Field>>) -> bool {
+        self.child.request_key_range(index, range)
+    }
+
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        self.child.sort_order()
+    }
+}
+
+/// Check of the constraints of the schema of its child on the tuples it returns.
+///
+/// A field with a NotNull constraint, or one implying it such as PrimaryKey, must have a
+/// value in every tuple: a tuple shorter than the schema has none. A field with a Unique
+/// constraint, or PrimaryKey, must not repeat a value of an earlier tuple since the last
+/// open or rewind. Tuples are passed on unchanged; the first one breaking a constraint
+/// fails next with an ExecutionError naming the field.
+pub struct ConstraintValidator {
+    /// Child node.
+    child: Box<dyn OpIterator + Send>,
+    /// Indices of the fields every tuple must have.
+    not_null: Vec<usize>,
+    /// Index of each unique field with the values seen so far.
+    unique: Vec<(usize, HashSet<Field>)>,
+}
+
+impl ConstraintValidator {
+    /// ConstraintValidator constructor.
+    ///
+    /// Fails with a ValidationError if a field with a NotNull constraint is nullable in the
+    /// schema, e.g. padded by an outer join, see TableSchema::merge_outer.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - Child node.
+    pub fn new(child: Box<dyn OpIterator + Send>) -> Result<Self, CrustyError> {
+        let mut not_null = Vec::new();
+        let mut unique = Vec::new();
+        for (i, attr) in child.get_schema().attributes().enumerate() {
+            if attr.constraint.is_not_null() {
+                if attr.is_nullable() {
+                    return Err(CrustyError::ValidationError(format!(
+                        "field {} is {:?} but nullable",
+                        attr.name(),
+                        attr.constraint
+                    )));
+                }
+                not_null.push(i);
+            }
+            if attr.constraint.is_unique() {
+                unique.push((i, HashSet::new()));
+            }
+        }
+        Ok(Self { child, not_null, unique })
+    }
+
+    // helper method to forget the unique values seen
+    fn reset(&mut self) {
+        for (_, seen) in &mut self.unique {
+            seen.clear();
+        }
+    }
+}
+
+// helper method to name a field in error messages, by its index if it has no name
+fn field_name(schema: &TableSchema, index: usize) -> String {
+    match schema.get_attribute(index) {
+        Some(attr) if !attr.name().is_empty() => attr.name().to_string(),
+        _ => index.to_string(),
+    }
+}
+
+impl OpIterator for ConstraintValidator {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.reset();
+        self.child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        let t = match self.child.next()? {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+        let schema = self.child.get_schema();
+        if let Some(&i) = self.not_null.iter().find(|i| t.get_field(**i).is_none()) {
+            return Err(CrustyError::ExecutionError(format!("tuple has no value of NOT NULL field {}", field_name(schema, i))));
+        }
+        for (i, seen) in &mut self.unique {
+            if let Some(field) = t.get_field(*i) {
+                if !seen.insert(field.clone()) {
+                    return Err(CrustyError::ExecutionError(format!(
+                        "duplicate value {} of UNIQUE field {}",
+                        field,
+                        field_name(schema, *i)
+                    )));
+                }
+            }
+        }
+        Ok(Some(t))
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.reset();
+        self.child.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.reset();
+        self.child.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        self.child.get_schema()
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.child]
+    }
+
+    /// Checking keeps the order of the child.
+    fn request_order(&mut self, index: usize) -> bool {
+        self.child.request_order(index)
+    }
+
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        self.child.sort_order()
+    }
+}
+
+/// Projection of some fields of every tuple of its child.
+pub struct Project {
+    /// Child node.
+    child: Box<dyn OpIterator + Send>,
+    /// Indices of the kept fields in the child, in output order.
+    columns: Vec<usize>,
+    /// Schema of the kept fields.
+    schema: TableSchema,
+}
+
+impl Project {
+    /// Project constructor.
+    ///
+    /// Fails with a ValidationError if a column is not a field of the child.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - Child node.
+    /// * `columns` - Indices of the kept fields in the child, in output order.
+    pub fn new(child: Box<dyn OpIterator + Send>, columns: Vec<usize>) -> Result<Self, CrustyError> {
+        let attrs = columns
+            .iter()
+            .map(|i| {
+                child.get_schema().get_attribute(*i).cloned().ok_or_else(|| {
+                    CrustyError::ValidationError(format!("projection has no field {}", i))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            child,
+            columns,
+            schema: TableSchema::new(attrs),
+        })
+    }
+
+    /// Project constructor keeping fields by attribute name.
+    ///
+    /// Fails with a ValidationError if a name is not an attribute of the child.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - Child node.
+    /// * `names` - Names of the kept attributes of the child, in output order.
+    pub fn from_names(child: Box<dyn OpIterator + Send>, names: &[&str]) -> Result<Self, CrustyError> {
+        let columns = names.iter().map(|name| child.get_schema().index_of(name)).collect::<Result<_, _>>()?;
+        Self::new(child, columns)
+    }
+}
+
+impl OpIterator for Project {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        match self.child.next()? {
+            None => Ok(None),
+            Some(t) => {
+                let fields = self
+                    .columns
+                    .iter()
+                    .map(|i| {
+                        t.get_field(*i)
+                            .cloned()
+                            .ok_or_else(|| CrustyError::ExecutionError(format!("tuple has no field {}", i)))
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(Some(Tuple::from_fields(fields)))
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.child.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.child.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.child]
+    }
+
+    /// The output is sorted on the kept prefix of the order of the child.
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        let order: Vec<usize> = self
+            .child
+            .sort_order()?
+            .iter()
+            .map_while(|f| self.columns.iter().position(|c| c == f))
+            .collect();
+        if order.is_empty() {
+            None
+        } else {
+            Some(order)
+        }
+    }
+}
+
+/// Appends the value of expressions to every tuple of its child, e.g. to join on a
+/// computed key.
+pub struct Compute {
+    /// Child node.
+    child: Box<dyn OpIterator + Send>,
+    /// Expressions evaluated on each tuple, appended in order.
+    exprs: Vec<Expr>,
+    /// Schema of the child followed by one field per expression.
+    schema: TableSchema,
+}
+
+impl Compute {
+    /// Compute constructor.
+    ///
+    /// The appended fields are named after their expression. Fails with a ValidationError
+    /// if an expression does not type check against the schema of the child.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - Child node.
+    /// * `exprs` - Expressions evaluated on each tuple, appended in order.
+    pub fn new(child: Box<dyn OpIterator + Send>, exprs: Vec<Expr>) -> Result<Self, CrustyError> {
+        let mut attrs: Vec<Attribute> = child.get_schema().attributes().cloned().collect();
+        for expr in exprs.iter() {
+            attrs.push(Attribute::new(expr.to_string(), expr.data_type(child.get_schema())?));
+        }
+        Ok(Self {
+            child,
+            exprs,
+            schema: TableSchema::new(attrs),
+        })
+    }
+}
+
+impl OpIterator for Compute {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        match self.child.next()? {
+            None => Ok(None),
+            Some(mut t) => {
+                let values = self.exprs.iter().map(|expr| expr.eval(&t)).collect::<Result<Vec<_>, _>>()?;
+                t.field_vals.extend(values);
+                Ok(Some(t))
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.child.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.child.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.child]
+    }
+
+    /// The child fields keep their place, so the output keeps the order of the child.
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        self.child.sort_order()
+    }
+}
+
+/// Name of the field RowIds appends.
+pub const ROW_ID_FIELD: &str = "row_id";
+
+/// Appends to every tuple of its child its row id, the position of the tuple in the output
+/// of the child since the last open or rewind.
+///
+/// Wrapping both children of a join records the provenance of each joined tuple: the left
+/// row id follows the left fields and the right row id ends the tuple, see row_ids. The
+/// join fields keep their index, only right fields after a projection or predicate on the
+/// right move up by the left row id.
+pub struct RowIds {
+    /// Child node.
+    child: Box<dyn OpIterator + Send>,
+    /// Schema of the child followed by the row id.
+    schema: TableSchema,
+    /// Row id of the next tuple.
+    next_id: usize,
+}
+
+impl RowIds {
+    /// RowIds constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - Child node.
+    pub fn new(child: Box<dyn OpIterator + Send>) -> Self {
+        let mut attrs: Vec<Attribute> = child.get_schema().attributes().cloned().collect();
+        attrs.push(Attribute::new(ROW_ID_FIELD.to_string(), DataType::Int));
+        Self {
+            child,
+            schema: TableSchema::new(attrs),
+            next_id: 0,
+        }
+    }
+}
+
+impl OpIterator for RowIds {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.next_id = 0;
+        self.child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        match self.child.next()? {
+            None => Ok(None),
+            Some(mut t) => {
+                let id = i32::try_from(self.next_id)
+                    .map_err(|_| CrustyError::ExecutionError(format!("row id {} does not fit a field", self.next_id)))?;
+                t.field_vals.push(Field::IntField(id));
+                self.next_id += 1;
+                Ok(Some(t))
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.child.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.next_id = 0;
+        self.child.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.child]
+    }
+
+    /// The row ids follow the order of the child, so the child can provide any order.
+    fn request_order(&mut self, index: usize) -> bool {
+        index < self.child.get_schema().size() && self.child.request_order(index)
+    }
+
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        self.child.sort_order()
+    }
+}
+
+/// Returns the left and right row ids of a tuple joined from two RowIds children.
+///
+/// Fails with an ExecutionError if the tuple has no row ids at those places.
+///
+/// # Arguments
+///
+/// * `tuple` - Joined tuple, all fields kept.
+/// * `left_width` - Number of fields of the left child, its row id included.
+pub fn row_ids(tuple: &Tuple, left_width: usize) -> Result<(usize, usize), CrustyError> {
+    let id = |index: Option<usize>| match index.and_then(|i| tuple.get_field(i)) {
+        Some(Field::IntField(id)) if *id >= 0 => Ok(*id as usize),
+        _ => Err(CrustyError::ExecutionError(format!("tuple {} has no row ids", tuple))),
+    };
+    Ok((id(left_width.checked_sub(1))?, id(tuple.size().checked_sub(1))?))
+}
+
+/// Grouped aggregation.
+///
+/// Output tuples are the group by fields followed by one field per aggregate,
+/// ordered on the group by fields. Averages are truncated to integers and an empty
+/// child has no groups.
+pub struct Aggregate {
+    /// Child node.
+    child: Box<dyn OpIterator + Send>,
+    /// Indices of the group by fields.
+    group_by: Vec<usize>,
+    /// Index of the aggregated field and operation of each aggregate.
+    aggs: Vec<(usize, AggOp)>,
+    /// Schema of the result.
+    schema: TableSchema,
+    /// Aggregate status
+    open: bool,
+    /// Aggregated tuples, computed on the first call to next.
+    results: Option<std::vec::IntoIter<Tuple>>,
+}
+
+impl Aggregate {
+    /// Aggregate constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - Child node.
+    /// * `group_by` - Indices of the group by fields, empty for a single group.
+    /// * `aggs` - Index of the aggregated field and operation of each aggregate.
+    pub fn new(child: Box<dyn OpIterator + Send>, group_by: Vec<usize>, aggs: Vec<(usize, AggOp)>) -> Self {
+        let child_schema = child.get_schema();
+        let mut attrs: Vec<Attribute> = group_by
+            .iter()
+            .filter_map(|i| child_schema.get_attribute(*i).cloned())
+            .collect();
+        for (i, op) in &aggs {
+            attrs.push(agg_attribute(child_schema, *i, *op));
+        }
+        Self {
+            child,
+            group_by,
+            aggs,
+            schema: TableSchema::new(attrs),
+            open: false,
+            results: None,
+        }
+    }
+
+    // helper method to aggregate the whole child
+    fn aggregate(&mut self) -> Result<Vec<Tuple>, CrustyError> {
+        // group fields to the aggregates and number of tuples of the group
+        let mut groups: BTreeMap<Vec<Field>, (Vec<Field>, i32)> = BTreeMap::new();
+        while let Some(t) = self.child.next()? {
+            let field = |i: usize| {
+                t.get_field(i)
+                    .ok_or_else(|| CrustyError::ExecutionError(format!("tuple has no field {}", i)))
+            };
+            let key = self.group_by.iter().map(|i| field(*i).cloned()).collect::<Result<Vec<_>, _>>()?;
+            match groups.get_mut(&key) {
+                Some((aggs, count)) => {
+                    for ((i, op), agg) in self.aggs.iter().zip(aggs.iter_mut()) {
+                        op.merge_field(field(*i)?, agg);
+                    }
+                    *count += 1;
+                }
+                None => {
+                    let aggs = self.aggs.iter().map(|(i, op)| Ok(op.new_field(field(*i)?))).collect::<Result<_, CrustyError>>()?;
+                    groups.insert(key, (aggs, 1));
+                }
+            }
+        }
+        Ok(groups
+            .into_iter()
+            .map(|(mut key, (aggs, count))| {
+                for ((_, op), agg) in self.aggs.iter().zip(aggs) {
+                    key.push(match op {
+                        AggOp::Avg => Field::IntField(agg.unwrap_int_field() / count),
+                        _ => agg,
+                    });
+                }
+                Tuple::new(key)
+            })
+            .collect())
+    }
+}
+
+/// Attribute of an aggregate of a field, named op(name); min and max keep the type of the
+/// field, the other aggregates are integers.
+///
+/// # Arguments
+///
+/// * `schema` - Schema of the aggregated tuples.
+/// * `index` - Index of the aggregated field.
+/// * `op` - Operation of the aggregate.
+pub(crate) fn agg_attribute(schema: &TableSchema, index: usize, op: AggOp) -> Attribute {
+    let (name, dtype) = match schema.get_attribute(index) {
+        Some(attr) => (attr.name().to_string(), attr.dtype().clone()),
+        None => (String::new(), DataType::Int),
+    };
+    let dtype = match op {
+        AggOp::Max | AggOp::Min => dtype,
+        _ => DataType::Int,
+    };
+    Attribute::new(format!("{}({})", op, name), dtype)
+}
+
+impl OpIterator for Aggregate {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.child.open()?;
+        self.open = true;
+        self.results = None;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        if self.results.is_none() {
+            self.results = Some(self.aggregate()?.into_iter());
+        }
+        Ok(self.results.as_mut().and_then(|results| results.next()))
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        self.child.close()?;
+        self.open = false;
+        self.results = None;
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        self.child.rewind()?;
+        self.results = None;
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        &self.schema
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.child]
+    }
+
+    /// Groups are returned ordered on the group by fields.
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        if self.group_by.is_empty() {
+            None
+        } else {
+            Some((0..self.group_by.len()).collect())
+        }
+    }
+}
+
+/// In-memory sort of its child, ascending on some fields.
+///
+/// Tuples equal on the fields keep the order of the child.
+pub struct Sort {
+    /// Child node.
+    child: Box<dyn OpIterator + Send>,
+    /// Indices of the fields to sort on, most significant first.
+    fields: Vec<usize>,
+    /// Sort status
+    open: bool,
+    /// Sorted tuples, computed on the first call to next.
+    results: Option<std::vec::IntoIter<Tuple>>,
+    /// Progress reporting and cancellation of the read of the child.
+    monitor: ProgressMonitor,
+}
+
+impl Sort {
+    /// Sort constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - Child node.
+    /// * `fields` - Indices of the fields to sort on, most significant first.
+    pub fn new(child: Box<dyn OpIterator + Send>, fields: Vec<usize>) -> Self {
+        Self {
+            child,
+            fields,
+            open: false,
+            results: None,
+            monitor: ProgressMonitor::default(),
+        }
+    }
+
+    /// Report the rows read from the child, and stop with an ExecutionError once cancelled or
+    /// past the deadline.
+    ///
+    /// # Arguments
+    ///
+    /// * `monitor` - Progress monitor, the phase is "sort".
+    pub fn set_progress_monitor(&mut self, monitor: ProgressMonitor) {
+        self.monitor = monitor;
+    }
+
+    // helper method to read and sort the whole child
+    fn sort(&mut self) -> Result<Vec<Tuple>, CrustyError> {
+        let sort_span = phase_span!("Sort", "sort");
+        let mut tuples = Vec::new();
+        while let Some(t) = self.child.next()? {
+            if let Some(i) = self.fields.iter().find(|i| t.get_field(**i).is_none()) {
+                return Err(CrustyError::ExecutionError(format!("tuple has no field {}", i)));
+            }
+            tuples.push(t);
+            self.monitor.tick("sort", tuples.len())?;
+        }
+        self.monitor.report("sort", tuples.len());
+        let fields = &self.fields;
+        tuples.sort_by(|a, b| {
+            fields
+                .iter()
+                .map(|i| a.get_field(*i).cmp(&b.get_field(*i)))
+                .find(|o| o.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+        sort_span.finish(tuples.len());
+        Ok(tuples)
+    }
+}
+
+impl OpIterator for Sort {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.child.open()?;
+        self.open = true;
+        self.results = None;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        if self.results.is_none() {
+            self.results = Some(self.sort()?.into_iter());
+        }
+        Ok(self.results.as_mut().and_then(|results| results.next()))
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        self.child.close()?;
+        self.open = false;
+        self.results = None;
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        self.child.rewind()?;
+        self.results = None;
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        self.child.get_schema()
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.child]
+    }
+
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        Some(self.fields.clone())
+    }
+}
+
+/// Run an operator and write its tuples as CSV lines.
+///
+/// Returns the number of tuples written. The operator is opened and closed.
+///
+/// # Arguments
+///
+/// * `op` - Operator to run.
+/// * `writer` - Destination of the lines, e.g. a file.
+pub fn write_csv(op: &mut dyn OpIterator, writer: &mut dyn Write) -> Result<usize, CrustyError> {
+    op.open()?;
+    let mut count = 0;
+    while let Some(t) = op.next()? {
+        writeln!(writer, "{}", t.to_csv())?;
+        count += 1;
+    }
+    op.close()?;
+    Ok(count)
+}
+
+/// Check that two operators return the same tuples, in any order.
+///
+/// Returns the number of tuples of each; a difference is an ExecutionError counting the
+/// missing and unexpected tuples. Both operators are opened and closed.
+///
+/// # Arguments
+///
+/// * `expected` - Operator trusted to be right, e.g. a nested loop join.
+/// * `actual` - Operator to check.
+pub fn compare_rows(expected: &mut dyn OpIterator, actual: &mut dyn OpIterator) -> Result<usize, CrustyError> {
+    // tuple to the number of expected copies not returned by actual yet
+    let mut counts: HashMap<Tuple, isize> = HashMap::new();
+    let mut rows = 0;
+    expected.open()?;
+    while let Some(t) = expected.next()? {
+        *counts.entry(t).or_insert(0) += 1;
+        rows += 1;
+    }
+    expected.close()?;
+    actual.open()?;
+    while let Some(t) = actual.next()? {
+        *counts.entry(t).or_insert(0) -= 1;
+    }
+    actual.close()?;
+
+    let missing: isize = counts.values().filter(|c| **c > 0).sum();
+    let unexpected: isize = -counts.values().filter(|c| **c < 0).sum::<isize>();
+    if missing > 0 || unexpected > 0 {
+        let example = counts.iter().find(|(_, c)| **c != 0).map(|(t, _)| t.to_string()).unwrap_or_default();
+        return Err(CrustyError::ExecutionError(format!(
+            "{} missing and {} unexpected tuples, e.g. {}",
+            missing, unexpected, example
+        )));
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{Constraint, TupleIterator};
+    use crate::generator::get_int_table_schema;
+
+    fn scan(keys: Vec<i32>) -> TupleIterator {
+        let tuples = keys.into_iter().map(|k| Tuple::new(vec![Field::IntField(k)])).collect();
+        TupleIterator::new(tuples, get_int_table_schema(1))
+    }
+
+    #[test]
+    fn sort_declares_order() -> Result<(), CrustyError> {
+        let tuples = vec![vec![2, 1], vec![1, 2], vec![2, 0], vec![1, 1]]
+            .into_iter()
+            .map(|t| Tuple::new(t.into_iter().map(Field::IntField).collect()))
+            .collect();
+        let mut op = Sort::new(Box::new(TupleIterator::new(tuples, get_int_table_schema(2))), vec![0, 1]);
+        assert_eq!(op.sort_order(), Some(vec![0, 1]));
+        assert!(op.request_order(0) && !op.request_order(1));
+        op.open()?;
+        let mut res = Vec::new();
+        while let Some(t) = op.next()? {
+            res.push(t.field_vals.iter().map(Field::unwrap_int_field).collect::<Vec<_>>());
+        }
+        assert_eq!(res, vec![vec![1, 1], vec![1, 2], vec![2, 0], vec![2, 1]]);
+        // a filter keeps the order
+        let filter = Filter::new(Box::new(op), 0, SimplePredicateOp::Equals, Field::IntField(1));
+        assert_eq!(filter.sort_order(), Some(vec![0, 1]));
+        Ok(())
+    }
+
+    #[test]
+    fn project_keeps_order() -> Result<(), CrustyError> {
+        let tuples = vec![vec![1, 5, 7], vec![2, 4, 6]]
+            .into_iter()
+            .map(|t| Tuple::new(t.into_iter().map(Field::IntField).collect()))
+            .collect();
+        let sorted = Sort::new(Box::new(TupleIterator::new(tuples, get_int_table_schema(3))), vec![0, 1]);
+        let mut op = Project::new(Box::new(sorted), vec![2, 0])?;
+        assert_eq!(op.sort_order(), Some(vec![1]));
+        op.open()?;
+        assert_eq!(op.next()?, Some(Tuple::new(vec![Field::IntField(7), Field::IntField(1)])));
+        assert!(matches!(Project::new(Box::new(scan(vec![1])), vec![1]), Err(CrustyError::ValidationError(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn row_id_provenance() -> Result<(), CrustyError> {
+        use crate::join::{MergeStrategy, SortMergeJoin};
+        let left = RowIds::new(Box::new(scan(vec![3, 1, 2])));
+        let right = RowIds::new(Box::new(scan(vec![2, 3, 3])));
+        assert_eq!(left.get_schema().get_field_index(ROW_ID_FIELD), Some(&1));
+        let left_width = left.get_schema().size();
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 0, 0, left, right, MergeStrategy::M_WAY)?;
+        op.open()?;
+        let mut ids = Vec::new();
+        while let Some(t) = op.next()? {
+            ids.push(row_ids(&t, left_width)?);
+        }
+        ids.sort();
+        assert_eq!(ids, vec![(0, 1), (0, 2), (2, 0)]);
+        // a rewind numbers the rows from 0 again
+        let mut op = RowIds::new(Box::new(scan(vec![5, 6])));
+        op.open()?;
+        op.next()?;
+        op.rewind()?;
+        assert_eq!(op.next()?, Some(Tuple::new(vec![Field::IntField(5), Field::IntField(0)])));
+        assert!(row_ids(&Tuple::new(vec![Field::IntField(5)]), 2).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn compare_any_order() -> Result<(), CrustyError> {
+        assert_eq!(compare_rows(&mut scan(vec![1, 2, 2]), &mut scan(vec![2, 1, 2]))?, 3);
+        // a duplicate too many is a difference
+        assert!(matches!(
+            compare_rows(&mut scan(vec![1, 2]), &mut scan(vec![2, 1, 2])),
+            Err(CrustyError::ExecutionError(_))
+        ));
+        Ok(())
+    }
+    #[test]
+    fn csv_key_range() -> Result<(), CrustyError> {
+        let path = std::env::temp_dir().join(format!("join-operators-{}.csv", std::process::id()));
+        std::fs::write(&path, "1,5\n2,9\n\n3,7\n4,2\n")?;
+        let mut scan = CsvScan::new(&path, get_int_table_schema(2));
+        let read = |scan: &mut CsvScan| -> Result<Vec<i32>, CrustyError> {
+            scan.open()?;
+            let mut keys = Vec::new();
+            while let Some(t) = scan.next()? {
+                keys.push(t.get_field(0).unwrap().unwrap_int_field());
+            }
+            scan.close()?;
+            Ok(keys)
+        };
+        assert!(scan.request_key_range(1, Some(Field::IntField(5)..=Field::IntField(8))));
+        assert_eq!(read(&mut scan)?, vec![1, 3]);
+        assert!(scan.request_key_range(1, None));
+        assert_eq!(read(&mut scan)?, vec![1, 2, 3, 4]);
+        assert!(!scan.request_key_range(2, None));
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn constraint_validator() -> Result<(), CrustyError> {
+        let schema = TableSchema::new(vec![
+            Attribute::new_pk(String::from("id"), DataType::Int),
+            Attribute::new_with_constraint(String::from("name"), DataType::String, Constraint::NotNull),
+            Attribute::new_with_constraint(String::from("email"), DataType::String, Constraint::Unique),
+        ]);
+        let row = |id: i32, fields: &[&str]| {
+            let strings = fields.iter().map(|f| Field::StringField(f.to_string()));
+            Tuple::new(std::iter::once(Field::IntField(id)).chain(strings).collect())
+        };
+        let read = |tuples: Vec<Tuple>| -> Result<usize, CrustyError> {
+            let mut op = ConstraintValidator::new(Box::new(TupleIterator::new(tuples, schema.clone())))?;
+            op.open()?;
+            let mut rows = 0;
+            while op.next()?.is_some() {
+                rows += 1;
+            }
+            // the unique values seen are forgotten on rewind, so reading again passes
+            op.rewind()?;
+            while op.next()?.is_some() {}
+            op.close()?;
+            Ok(rows)
+        };
+        let valid = vec![row(1, &["a", "a@x"]), row(2, &["a", "b@x"]), row(3, &["b"])];
+        assert_eq!(read(valid)?, 3);
+        let error = |tuples| match read(tuples) {
+            Err(CrustyError::ExecutionError(e)) => e,
+            other => panic!("expected an execution error, got {:?}", other),
+        };
+        assert_eq!(error(vec![row(1, &["a"]), row(1, &["b"])]), "duplicate value 1 of UNIQUE field id");
+        assert_eq!(error(vec![row(1, &["a", "a@x"]), row(2, &["b", "a@x"])]), "duplicate value a@x of UNIQUE field email");
+        assert_eq!(error(vec![row(1, &[])]), "tuple has no value of NOT NULL field name");
+
+        // the padded side of an outer join is nullable, so it cannot be NOT NULL
+        let ids = TableSchema::from_vecs(vec!["key"], vec![DataType::Int]);
+        let left = ids.merge_outer(&schema, false, false);
+        assert!(left.attributes().all(|attr| !attr.is_nullable()));
+        let outer = ids.merge_outer(&schema, false, true);
+        assert_eq!(outer.attributes().filter(|attr| attr.is_nullable()).count(), 3);
+        assert!(ConstraintValidator::new(Box::new(TupleIterator::new(Vec::new(), outer))).is_err());
+        let attr: Attribute = serde_json::from_str(r#"{"name": "id", "dtype": "Int", "constraint": "None"}"#).unwrap();
+        assert!(!attr.is_nullable());
+        Ok(())
+    }
+}
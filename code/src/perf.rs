@@ -0,0 +1,247 @@
+//! Hardware counters of the operator phases, with the perf feature.
+//!
+//! Wall-clock time does not show why a kernel is fast, so the phases the operators trace,
+//! see trace::PhaseGuard, also read the cycles, instructions, cache misses and branch
+//! mispredictions of the process from Linux perf events, and add them up per operator and
+//! phase. The counters of a thread are opened by its first phase and count the threads it
+//! spawns afterwards, e.g. the sort and merge workers, once they exit. Only user space is
+//! counted, which perf_event_paranoid up to 2 allows; where the counters cannot be opened,
+//! e.g. in containers or on other systems, phases are not counted.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::{AddAssign, Sub};
+use std::sync::Mutex;
+use crate::common::CrustyError;
+
+/// Values of the hardware counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CounterValues {
+    /// CPU cycles.
+    pub cycles: u64,
+    /// Retired instructions.
+    pub instructions: u64,
+    /// Last level cache misses.
+    pub cache_misses: u64,
+    /// Mispredicted branches.
+    pub branch_misses: u64,
+}
+
+impl AddAssign for CounterValues {
+    fn add_assign(&mut self, other: Self) {
+        self.cycles += other.cycles;
+        self.instructions += other.instructions;
+        self.cache_misses += other.cache_misses;
+        self.branch_misses += other.branch_misses;
+    }
+}
+
+impl Sub for CounterValues {
+    type Output = Self;
+
+    /// Counts between two reads, the later one first.
+    fn sub(self, earlier: Self) -> Self {
+        Self {
+            cycles: self.cycles.saturating_sub(earlier.cycles),
+            instructions: self.instructions.saturating_sub(earlier.instructions),
+            cache_misses: self.cache_misses.saturating_sub(earlier.cache_misses),
+            branch_misses: self.branch_misses.saturating_sub(earlier.branch_misses),
+        }
+    }
+}
+
+impl fmt::Display for CounterValues {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ipc = match self.cycles {
+            0 => 0.0,
+            cycles => self.instructions as f64 / cycles as f64,
+        };
+        write!(
+            f,
+            "cycles {}, instructions {} ({:.2} per cycle), cache misses {}, branch misses {}",
+            self.cycles, self.instructions, ipc, self.cache_misses, self.branch_misses
+        )
+    }
+}
+
+/// Open perf event counters of the calling thread and the threads it spawns afterwards.
+pub struct PerfCounters {
+    /// Counters of the cycles, instructions, cache misses and branch misses.
+    #[cfg(target_os = "linux")]
+    fds: Vec<std::os::fd::OwnedFd>,
+}
+
+/// Generic hardware events of perf, in the order of the CounterValues fields.
+#[cfg(target_os = "linux")]
+const HARDWARE_EVENTS: [u64; 4] = [
+    0, // PERF_COUNT_HW_CPU_CYCLES
+    1, // PERF_COUNT_HW_INSTRUCTIONS
+    3, // PERF_COUNT_HW_CACHE_MISSES
+    5, // PERF_COUNT_HW_BRANCH_MISSES
+];
+
+/// First version of perf_event_attr, which every kernel with perf events accepts.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+impl PerfCounters {
+    /// Open and start the counters.
+    ///
+    /// Returns an ExecutionError when the system does not allow them.
+    #[cfg(target_os = "linux")]
+    pub fn open() -> Result<Self, CrustyError> {
+        use std::os::fd::FromRawFd;
+        // inherit to the threads spawned later, and user space only
+        const INHERIT: u64 = 1 << 1;
+        const EXCLUDE_KERNEL: u64 = 1 << 5;
+        const EXCLUDE_HV: u64 = 1 << 6;
+        let mut fds = Vec::with_capacity(HARDWARE_EVENTS.len());
+        for config in HARDWARE_EVENTS {
+            let attr = PerfEventAttr {
+                type_: 0, // PERF_TYPE_HARDWARE
+                size: std::mem::size_of::<PerfEventAttr>() as u32,
+                config,
+                flags: INHERIT | EXCLUDE_KERNEL | EXCLUDE_HV,
+                ..PerfEventAttr::default()
+            };
+            // counts this thread (pid 0) on any cpu (-1), outside of a group (-1)
+            let fd = unsafe { libc::syscall(libc::SYS_perf_event_open, &attr as *const PerfEventAttr, 0, -1, -1, 0) };
+            if fd < 0 {
+                return Err(CrustyError::ExecutionError(format!(
+                    "cannot open perf counters: {}",
+                    std::io::Error::last_os_error()
+                )));
+            }
+            // the descriptor was just returned by the kernel and is owned here only
+            fds.push(unsafe { std::os::fd::OwnedFd::from_raw_fd(fd as i32) });
+        }
+        Ok(Self { fds })
+    }
+
+    /// Open and start the counters, an ExecutionError outside of Linux.
+    #[cfg(not(target_os = "linux"))]
+    pub fn open() -> Result<Self, CrustyError> {
+        Err(CrustyError::ExecutionError(String::from("perf counters need Linux")))
+    }
+
+    /// Returns the counts since the counters were opened.
+    #[cfg(target_os = "linux")]
+    pub fn read(&self) -> Result<CounterValues, CrustyError> {
+        use std::io::Read;
+        let mut values = [0u64; 4];
+        for (fd, value) in self.fds.iter().zip(values.iter_mut()) {
+            let mut bytes = [0u8; 8];
+            std::fs::File::from(fd.try_clone()?).read_exact(&mut bytes)?;
+            *value = u64::from_ne_bytes(bytes);
+        }
+        let [cycles, instructions, cache_misses, branch_misses] = values;
+        Ok(CounterValues {
+            cycles,
+            instructions,
+            cache_misses,
+            branch_misses,
+        })
+    }
+
+    /// Returns the counts since the counters were opened, never reached outside of Linux.
+    #[cfg(not(target_os = "linux"))]
+    pub fn read(&self) -> Result<CounterValues, CrustyError> {
+        Ok(CounterValues::default())
+    }
+}
+
+thread_local! {
+    /// Counters of the thread, None until its first phase and Err if they cannot be opened.
+    static THREAD_COUNTERS: RefCell<Option<Result<PerfCounters, CrustyError>>> = const { RefCell::new(None) };
+}
+
+/// Counts of each operator and phase since the last reset_phase_counters.
+static PHASE_COUNTERS: Mutex<BTreeMap<(String, String), CounterValues>> = Mutex::new(BTreeMap::new());
+
+/// Returns the counts of the calling thread, opening its counters on the first call, or
+/// None if they cannot be opened.
+pub(crate) fn thread_counts() -> Option<CounterValues> {
+    THREAD_COUNTERS.with(|counters| {
+        let mut counters = counters.borrow_mut();
+        counters.get_or_insert_with(PerfCounters::open).as_ref().ok().and_then(|c| c.read().ok())
+    })
+}
+
+/// Add the counts of a phase to the ones of its operator and phase.
+///
+/// # Arguments
+///
+/// * `operator` - Name of the operator.
+/// * `phase` - Name of the phase.
+/// * `values` - Counts of the phase.
+pub(crate) fn record_phase(operator: &str, phase: &str, values: CounterValues) {
+    let mut phases = PHASE_COUNTERS.lock().unwrap();
+    *phases.entry((operator.to_string(), phase.to_string())).or_default() += values;
+}
+
+/// Returns the counts of each operator and phase since the last reset, in name order.
+pub fn phase_counters() -> Vec<(String, String, CounterValues)> {
+    let phases = PHASE_COUNTERS.lock().unwrap();
+    phases.iter().map(|((operator, phase), values)| (operator.clone(), phase.clone(), *values)).collect()
+}
+
+/// Forget the counts of the phases, e.g. between two benchmark runs.
+pub fn reset_phase_counters() {
+    PHASE_COUNTERS.lock().unwrap().clear();
+}
+
+/// Returns the counts of each operator and phase as lines, e.g. for a benchmark report.
+pub fn phase_report() -> String {
+    phase_counters()
+        .into_iter()
+        .map(|(operator, phase, values)| format!("{} {}: {}\n", operator, phase, values))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{OpIterator, SimplePredicateOp, TupleIterator};
+    use crate::generator::{create_vec_tuple, get_int_table_schema};
+    use crate::join::{MergeStrategy, SortMergeJoin};
+
+    #[test]
+    fn count_phases() -> Result<(), CrustyError> {
+        let scan = |seed| TupleIterator::new(create_vec_tuple(4096, 2, 1000, seed), get_int_table_schema(2));
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(1), scan(2), MergeStrategy::M_WAY)?;
+        op.open()?;
+        while op.next()?.is_some() {}
+        let phases = phase_counters();
+        if PerfCounters::open().is_err() {
+            // not allowed here, so nothing is counted
+            assert!(phases.is_empty());
+            return Ok(());
+        }
+        // the test threads share the counts, so only the phases of this join are checked
+        let join = |phase: &str| {
+            phases
+                .iter()
+                .find(|(o, p, _)| o == "SortMergeJoin" && p == phase)
+                .map(|(_, _, values)| *values)
+                .unwrap()
+        };
+        for phase in ["sort left", "sort right", "partition", "merge", "join"] {
+            assert!(join(phase).instructions > 0, "{}", phase);
+        }
+        assert!(join("open").cycles >= join("sort left").cycles);
+        Ok(())
+    }
+}
@@ -0,0 +1,602 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::catalog::ScanSource;
+use crate::common::{AggOp, CrustyError, Field, OpIterator, SimplePredicateOp, TableSchema, Tuple};
+use crate::config::ExecutionContext;
+use crate::cost::{choose_join, estimate_output_rows, JoinAlgorithm, JoinPlan, PlanInput};
+use crate::metrics::{Metered, OpMetrics};
+use crate::operators::{Aggregate, Filter, Project};
+
+/// Version of the plan files written by to_json, plans of a later version are not read.
+pub const PLAN_FORMAT_VERSION: u32 = 1;
+
+/// Logical query plan, a tree of relational operators lowered to physical ones by to_physical.
+///
+/// Children are always listed left first, as the physical operators do. A plan, its scan
+/// sources included, serializes to JSON with to_json so a benchmark configuration can be
+/// shared and replayed with from_json.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LogicalPlan {
+    /// Scan of a table.
+    Scan {
+        /// Name of the table.
+        name: String,
+        /// Schema of the tuples.
+        schema: TableSchema,
+        /// Where the tuples come from.
+        source: ScanSource,
+        /// Number of tuples of the table.
+        rows: usize,
+    },
+    /// Tuples whose field compares to a constant.
+    Filter {
+        /// Input node.
+        input: Box<LogicalPlan>,
+        /// Index of the compared field.
+        index: usize,
+        /// Comparison of the field (left) with the constant (right).
+        op: SimplePredicateOp,
+        /// Constant the field is compared with.
+        value: Field,
+    },
+    /// Some fields of every tuple.
+    Project {
+        /// Input node.
+        input: Box<LogicalPlan>,
+        /// Indices of the kept fields, in output order.
+        columns: Vec<usize>,
+    },
+    /// Join of two inputs, the algorithm is picked by the cost model when lowered unless set.
+    Join {
+        /// Left input.
+        left: Box<LogicalPlan>,
+        /// Right input.
+        right: Box<LogicalPlan>,
+        /// Operation in join condition.
+        op: SimplePredicateOp,
+        /// Index of the left field in join condition.
+        left_index: usize,
+        /// Index of the right field in join condition.
+        right_index: usize,
+        /// Algorithm forced with set_algorithm, None to let the cost model pick.
+        #[serde(default)]
+        algorithm: Option<JoinAlgorithm>,
+    },
+    /// Grouped aggregation.
+    Aggregate {
+        /// Input node.
+        input: Box<LogicalPlan>,
+        /// Indices of the group by fields.
+        group_by: Vec<usize>,
+        /// Index of the aggregated field and operation of each aggregate.
+        aggs: Vec<(usize, AggOp)>,
+    },
+}
+
+impl LogicalPlan {
+    /// Scan of tuples held in memory, see catalog::Catalog to scan tables by name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the table.
+    /// * `tuples` - Tuples of the table.
+    /// * `schema` - Schema of the tuples.
+    pub fn tuples(name: &str, tuples: Vec<Tuple>, schema: TableSchema) -> Self {
+        LogicalPlan::Scan {
+            name: name.to_string(),
+            schema,
+            rows: tuples.len(),
+            source: ScanSource::Tuples(tuples),
+        }
+    }
+
+    /// Keep the tuples whose field compares to a constant.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Index of the compared field.
+    /// * `op` - Comparison of the field with the constant.
+    /// * `value` - Constant the field is compared with.
+    pub fn filter(self, index: usize, op: SimplePredicateOp, value: Field) -> Self {
+        LogicalPlan::Filter {
+            input: Box::new(self),
+            index,
+            op,
+            value,
+        }
+    }
+
+    /// Keep some fields of every tuple.
+    ///
+    /// # Arguments
+    ///
+    /// * `columns` - Indices of the kept fields, in output order.
+    pub fn project(self, columns: Vec<usize>) -> Self {
+        LogicalPlan::Project {
+            input: Box::new(self),
+            columns,
+        }
+    }
+
+    /// Join with another plan, this one being the left input.
+    ///
+    /// # Arguments
+    ///
+    /// * `right` - Right input.
+    /// * `op` - Operation in join condition.
+    /// * `left_index` - Index of the left field in join condition.
+    /// * `right_index` - Index of the right field in join condition.
+    pub fn join(self, right: LogicalPlan, op: SimplePredicateOp, left_index: usize, right_index: usize) -> Self {
+        LogicalPlan::Join {
+            left: Box::new(self),
+            right: Box::new(right),
+            op,
+            left_index,
+            right_index,
+            algorithm: None,
+        }
+    }
+
+    /// Force the algorithm of a join node, e.g. to replay a plan with the join it ran with.
+    ///
+    /// Fails with a ValidationError if the node is not a join.
+    ///
+    /// # Arguments
+    ///
+    /// * `join_algorithm` - Join algorithm.
+    pub fn set_algorithm(&mut self, join_algorithm: JoinAlgorithm) -> Result<(), CrustyError> {
+        match self {
+            LogicalPlan::Join { algorithm, .. } => {
+                *algorithm = Some(join_algorithm);
+                Ok(())
+            }
+            _ => Err(CrustyError::ValidationError(format!("{} is not a join", self.label()))),
+        }
+    }
+
+    /// Aggregate the tuples per group.
+    ///
+    /// # Arguments
+    ///
+    /// * `group_by` - Indices of the group by fields, empty for a single group.
+    /// * `aggs` - Index of the aggregated field and operation of each aggregate.
+    pub fn aggregate(self, group_by: Vec<usize>, aggs: Vec<(usize, AggOp)>) -> Self {
+        LogicalPlan::Aggregate {
+            input: Box::new(self),
+            group_by,
+            aggs,
+        }
+    }
+
+    /// Returns the inputs of the node, left first.
+    pub fn children(&self) -> Vec<&LogicalPlan> {
+        match self {
+            LogicalPlan::Scan { .. } => vec![],
+            LogicalPlan::Filter { input, .. } | LogicalPlan::Project { input, .. } | LogicalPlan::Aggregate { input, .. } => {
+                vec![input]
+            }
+            LogicalPlan::Join { left, right, .. } => vec![left, right],
+        }
+    }
+
+    /// Estimate the number of output tuples of the node.
+    ///
+    /// Filters keep a tenth of the tuples on equality and a third on ranges, aggregates
+    /// keep a tenth as groups, and joins follow cost::estimate_output_rows.
+    pub fn estimated_rows(&self) -> usize {
+        match self {
+            LogicalPlan::Scan { rows, .. } => *rows,
+            LogicalPlan::Filter { input, op, .. } => {
+                let rows = input.estimated_rows();
+                match op {
+                    SimplePredicateOp::Equals => rows / 10,
+                    SimplePredicateOp::NotEq => rows - rows / 10,
+                    SimplePredicateOp::All => rows,
+                    _ => rows / 3,
+                }
+            }
+            LogicalPlan::Project { input, .. } => input.estimated_rows(),
+            LogicalPlan::Join { left, right, op, .. } => {
+                estimate_output_rows(left.estimated_rows(), right.estimated_rows(), *op, (None, None))
+            }
+            LogicalPlan::Aggregate { input, group_by, .. } => {
+                let rows = input.estimated_rows();
+                if group_by.is_empty() {
+                    rows.min(1)
+                } else {
+                    (rows / 10).max(rows.min(1))
+                }
+            }
+        }
+    }
+
+    /// Serialize the plan as JSON, tagged with PLAN_FORMAT_VERSION.
+    ///
+    /// Scans keep their source: tuples held in memory are written out, CSV files by path and
+    /// generated tables by range and seed, so the files must be shared along with the plan.
+    pub fn to_json(&self) -> Result<String, CrustyError> {
+        let file = PlanFile {
+            version: PLAN_FORMAT_VERSION,
+            plan: self.clone(),
+        };
+        serde_json::to_string_pretty(&file).map_err(|e| CrustyError::CrustyError(e.to_string()))
+    }
+
+    /// Deserialize a plan written by to_json.
+    ///
+    /// Fails with a ValidationError if the JSON is not a plan or was written by a later
+    /// version of the format.
+    ///
+    /// # Arguments
+    ///
+    /// * `json` - Serialized plan.
+    pub fn from_json(json: &str) -> Result<Self, CrustyError> {
+        let file: PlanFile = serde_json::from_str(json).map_err(|e| CrustyError::ValidationError(format!("not a plan: {}", e)))?;
+        if file.version > PLAN_FORMAT_VERSION {
+            return Err(CrustyError::ValidationError(format!(
+                "plan format version {} is newer than {}",
+                file.version, PLAN_FORMAT_VERSION
+            )));
+        }
+        Ok(file.plan)
+    }
+
+    /// Write the plan to a file as JSON, replacing it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CrustyError> {
+        fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    /// Read a plan from a file, see from_json.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CrustyError> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// Lower the plan to the operators executing it.
+    ///
+    /// Every operator is wrapped in a Metered so the actual row counts can be printed next to
+    /// the estimates once the plan has run. Joins pick their algorithm with cost::choose_join
+    /// from the estimated rows of their inputs, unless it was forced. Fails with a
+    /// ValidationError if a field index does not exist.
+    pub fn to_physical(&self) -> Result<PhysicalPlan, CrustyError> {
+        Ok(PhysicalPlan {
+            logical: self.clone(),
+            root: self.lower(None)?,
+        })
+    }
+
+    /// Lower the plan like to_physical, each join bounded by the memory an execution context
+    /// grants it.
+    ///
+    /// Every join is registered in the context under its operator_name, keeping a budget
+    /// already set for it, before any grant is computed, so the joins without a budget of
+    /// their own split what the others leave of the global budget.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Context arbitrating the memory budget of the query.
+    pub fn to_physical_in(&self, ctx: &mut ExecutionContext) -> Result<PhysicalPlan, CrustyError> {
+        self.register_joins(ctx);
+        Ok(PhysicalPlan {
+            logical: self.clone(),
+            root: self.lower(Some(ctx))?,
+        })
+    }
+
+    /// Returns the name of the operator of the node in an ExecutionContext: the table of a
+    /// scan, "a join b" for a join of the tables a and b, the name of its input otherwise.
+    pub fn operator_name(&self) -> String {
+        match self {
+            LogicalPlan::Scan { name, .. } => name.clone(),
+            LogicalPlan::Join { left, right, .. } => {
+                let side = |plan: &LogicalPlan| match plan.operator_name() {
+                    name if name.contains(" join ") => format!("({})", name),
+                    name => name,
+                };
+                format!("{} join {}", side(left), side(right))
+            }
+            LogicalPlan::Filter { input, .. } | LogicalPlan::Project { input, .. } | LogicalPlan::Aggregate { input, .. } => {
+                input.operator_name()
+            }
+        }
+    }
+
+    // helper method to register the joins of the node and its inputs
+    fn register_joins(&self, ctx: &mut ExecutionContext) {
+        if let LogicalPlan::Join { .. } = self {
+            ctx.register(&self.operator_name());
+        }
+        for child in self.children() {
+            child.register_joins(ctx);
+        }
+    }
+
+    // helper method to lower a node and its inputs
+    fn lower(&self, ctx: Option<&ExecutionContext>) -> Result<Box<dyn OpIterator + Send>, CrustyError> {
+        let op: Box<dyn OpIterator + Send> = match self {
+            LogicalPlan::Scan { schema, source, rows, .. } => source.scan(schema, *rows),
+            LogicalPlan::Filter { input, index, op, value } => {
+                let child = input.lower(ctx)?;
+                if *index >= child.get_schema().size() {
+                    return Err(CrustyError::ValidationError(format!("filter has no field {}", index)));
+                }
+                Box::new(Filter::new(child, *index, *op, value.clone()))
+            }
+            LogicalPlan::Project { input, columns } => Box::new(Project::new(input.lower(ctx)?, columns.clone())?),
+            LogicalPlan::Join {
+                left,
+                right,
+                op,
+                left_index,
+                right_index,
+                algorithm,
+            } => {
+                let (left_child, right_child) = (left.lower(ctx)?, right.lower(ctx)?);
+                let mut plan = JoinPlan::new(
+                    PlanInput::new(left_child.get_schema().clone(), left.estimated_rows(), *left_index),
+                    PlanInput::new(right_child.get_schema().clone(), right.estimated_rows(), *right_index),
+                    *op,
+                );
+                if let Some(algorithm) = algorithm {
+                    plan.set_algorithm(*algorithm);
+                }
+                if let Some(bytes) = ctx.and_then(|ctx| ctx.operator_budget(&self.operator_name())) {
+                    plan.set_memory_budget(bytes);
+                }
+                choose_join(&plan, left_child, right_child)?.0
+            }
+            LogicalPlan::Aggregate { input, group_by, aggs } => {
+                let child = input.lower(ctx)?;
+                let size = child.get_schema().size();
+                if let Some(i) = group_by.iter().chain(aggs.iter().map(|(i, _)| i)).find(|i| **i >= size) {
+                    return Err(CrustyError::ValidationError(format!("aggregate has no field {}", i)));
+                }
+                Box::new(Aggregate::new(child, group_by.clone(), aggs.clone()))
+            }
+        };
+        Ok(Box::new(Metered::new(op)))
+    }
+
+    // helper method to describe the node on one line
+    fn label(&self) -> String {
+        match self {
+            LogicalPlan::Scan { name, .. } => format!("Scan {}", name),
+            LogicalPlan::Filter { index, op, value, .. } => format!("Filter #{} {:?} {}", index, op, value),
+            LogicalPlan::Project { columns, .. } => format!("Project {:?}", columns),
+            LogicalPlan::Join {
+                op,
+                left_index,
+                right_index,
+                algorithm,
+                ..
+            } => match algorithm {
+                Some(algorithm) => format!("Join #{} {:?} #{} using {:?}", left_index, op, right_index, algorithm),
+                None => format!("Join #{} {:?} #{}", left_index, op, right_index),
+            },
+            LogicalPlan::Aggregate { group_by, aggs, .. } => {
+                let aggs: Vec<String> = aggs.iter().map(|(i, op)| format!("{}(#{})", op, i)).collect();
+                format!("Aggregate {} by {:?}", aggs.join(", "), group_by)
+            }
+        }
+    }
+}
+
+/// Plan file written by to_json.
+#[derive(Serialize, Deserialize)]
+struct PlanFile {
+    version: u32,
+    plan: LogicalPlan,
+}
+
+// helper method to format a node and its inputs at a depth, with the actual row counts of
+// the physical operator lowered from the node if there is one
+fn fmt_node(plan: &LogicalPlan, op: Option<&dyn OpIterator>, depth: usize, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}{} (estimated {}", "  ".repeat(depth), plan.label(), plan.estimated_rows())?;
+    if let Some(op) = op {
+        write!(f, ", actual {}, {}", op.metrics().rows_out, op.name())?;
+    }
+    writeln!(f, ")")?;
+    let op_children = op.map(|op| op.children()).unwrap_or_default();
+    for (i, child) in plan.children().into_iter().enumerate() {
+        fmt_node(child, op_children.get(i).copied(), depth + 1, f)?;
+    }
+    Ok(())
+}
+
+/// One node per line, inputs indented under their parent, with estimated row counts.
+impl fmt::Display for LogicalPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_node(self, None, 0, f)
+    }
+}
+
+/// Operators lowered from a logical plan, run through the OpIterator interface.
+pub struct PhysicalPlan {
+    /// Plan the operators were lowered from.
+    logical: LogicalPlan,
+    /// Root operator.
+    root: Box<dyn OpIterator + Send>,
+}
+
+impl PhysicalPlan {
+    /// Returns the plan the operators were lowered from.
+    pub fn logical(&self) -> &LogicalPlan {
+        &self.logical
+    }
+
+    /// Returns the root operator.
+    pub fn into_root(self) -> Box<dyn OpIterator + Send> {
+        self.root
+    }
+}
+
+impl OpIterator for PhysicalPlan {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.root.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        self.root.next()
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.root.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.root.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        self.root.get_schema()
+    }
+
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        self.root.sort_order()
+    }
+
+    fn name(&self) -> String {
+        self.root.name()
+    }
+
+    fn metrics(&self) -> OpMetrics {
+        self.root.metrics()
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        self.root.children()
+    }
+}
+
+/// The logical plan with estimated and actual row counts and the operator of each node.
+impl fmt::Display for PhysicalPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_node(&self.logical, Some(self.root.as_ref()), 0, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::generator::get_int_table_schema;
+    use crate::operators::write_csv;
+
+    fn table(name: &str, keys: impl Iterator<Item = i32>) -> LogicalPlan {
+        let tuples = keys.enumerate().map(|(i, k)| Tuple::new(vec![Field::IntField(i as i32), Field::IntField(k)])).collect();
+        LogicalPlan::tuples(name, tuples, get_int_table_schema(2))
+    }
+
+    #[test]
+    fn lower_and_print() -> Result<(), CrustyError> {
+        let plan = table("a", (0..100).map(|i| i % 10))
+            .filter(1, SimplePredicateOp::LessThan, Field::IntField(5))
+            .join(table("b", 0..10), SimplePredicateOp::Equals, 1, 1)
+            .aggregate(vec![1], vec![(0, AggOp::Count)])
+            .project(vec![1]);
+        assert_eq!(
+            plan.to_string(),
+            "Project [1] (estimated 1)\n\
+             \x20 Aggregate count(#0) by [1] (estimated 1)\n\
+             \x20   Join #1 Equals #1 (estimated 10)\n\
+             \x20     Filter #1 LessThan 5 (estimated 33)\n\
+             \x20       Scan a (estimated 100)\n\
+             \x20     Scan b (estimated 10)\n"
+        );
+
+        let mut physical = plan.to_physical()?;
+        assert_eq!(write_csv(&mut physical, &mut std::io::sink())?, 5);
+        let lines: Vec<String> = physical.to_string().lines().map(String::from).collect();
+        assert_eq!(lines[0], "Project [1] (estimated 1, actual 5, Project)");
+        assert_eq!(lines[1], "  Aggregate count(#0) by [1] (estimated 1, actual 5, Aggregate)");
+        assert_eq!(lines[2], "    Join #1 Equals #1 (estimated 10, actual 50, HashEqJoin)");
+        assert_eq!(lines[3], "      Filter #1 LessThan 5 (estimated 33, actual 50, Filter)");
+        assert_eq!(lines[5], "      Scan b (estimated 10, actual 10, TupleIterator)");
+        Ok(())
+    }
+
+    #[test]
+    fn json_round_trip() -> Result<(), CrustyError> {
+        let generated = LogicalPlan::Scan {
+            name: String::from("g"),
+            schema: get_int_table_schema(2),
+            source: ScanSource::Generated { range: 1000, seed: 7 },
+            rows: 500,
+        };
+        let mut plan = table("a", (0..100).map(|i| i % 10))
+            .filter(1, SimplePredicateOp::GreaterThanOrEq, Field::IntField(2))
+            .join(generated, SimplePredicateOp::Equals, 1, 1);
+        plan.set_algorithm(JoinAlgorithm::SortMergeMPass)?;
+        let plan = plan.aggregate(vec![1], vec![(0, AggOp::Count), (3, AggOp::Max)]).project(vec![0, 2]);
+
+        let path = std::env::temp_dir().join(format!("join-plan-{}.json", std::process::id()));
+        plan.save(&path)?;
+        let replayed = LogicalPlan::load(&path)?;
+        fs::remove_file(&path)?;
+        assert_eq!(replayed, plan);
+        assert!(replayed.to_string().contains("Join #1 Equals #1 using SortMergeMPass"));
+
+        // the replayed plan runs the same join and gives the same tuples
+        let run = |plan: &LogicalPlan| -> Result<(Vec<Tuple>, String), CrustyError> {
+            let mut physical = plan.to_physical()?;
+            physical.open()?;
+            let mut tuples = Vec::new();
+            while let Some(t) = physical.next()? {
+                tuples.push(t);
+            }
+            physical.close()?;
+            Ok((tuples, physical.to_string()))
+        };
+        let (expected, printed) = run(&plan)?;
+        assert!(!expected.is_empty());
+        assert!(printed.contains("SortMergeJoin"));
+        assert_eq!(run(&replayed)?, (expected, printed));
+
+        // a later version and other JSON fail, as does forcing the algorithm of a scan
+        let newer = plan.to_json()?.replacen("\"version\": 1", "\"version\": 2", 1);
+        assert!(matches!(LogicalPlan::from_json(&newer), Err(CrustyError::ValidationError(_))));
+        assert!(matches!(LogicalPlan::from_json("{}"), Err(CrustyError::ValidationError(_))));
+        assert!(matches!(table("a", 0..10).set_algorithm(JoinAlgorithm::HashEq), Err(CrustyError::ValidationError(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn lower_in_context() -> Result<(), CrustyError> {
+        let mut inner = table("a", (0..200).map(|i| i % 50)).join(table("b", 0..50), SimplePredicateOp::Equals, 1, 1);
+        inner.set_algorithm(JoinAlgorithm::SortMergeMPass)?;
+        let plan = inner.join(table("c", 0..50), SimplePredicateOp::Equals, 1, 1);
+        assert_eq!(plan.operator_name(), "(a join b) join c");
+        assert_eq!(plan.children()[0].operator_name(), "a join b");
+
+        // the outer join asks for most of the budget, the inner one gets the rest and spills
+        let mut ctx = ExecutionContext::new(1 << 20);
+        ctx.set_operator_budget("(a join b) join c", (1 << 20) - 64);
+        let mut physical = plan.to_physical_in(&mut ctx)?;
+        assert_eq!(ctx.operator_budget("a join b"), Some(64));
+        assert_eq!(write_csv(&mut physical, &mut std::io::sink())?, 200);
+        assert!(physical.children()[0].metrics().spills > 0);
+
+        // without a context the joins are not bounded
+        let mut physical = plan.to_physical()?;
+        assert_eq!(write_csv(&mut physical, &mut std::io::sink())?, 200);
+        assert_eq!(physical.children()[0].metrics().spills, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn lower_validates() {
+        let plan = table("a", 0..10).filter(2, SimplePredicateOp::Equals, Field::IntField(0));
+        assert!(matches!(plan.to_physical(), Err(CrustyError::ValidationError(_))));
+        let plan = table("a", 0..10).join(table("b", 0..10), SimplePredicateOp::Equals, 0, 2);
+        assert!(matches!(plan.to_physical(), Err(CrustyError::ValidationError(_))));
+    }
+}
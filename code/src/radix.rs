@@ -0,0 +1,59 @@
+/// Number of key bits sorted by each pass.
+const RADIX_BITS: usize = 8;
+
+/// Number of buckets of a pass.
+const BUCKETS: usize = 1 << RADIX_BITS;
+
+/// Sort row ids on their normalized keys with an LSD radix sort, see keys::normalize_key.
+///
+/// Each pass distributes the rows on one byte of the key, least significant first, so
+/// the sort is stable. Passes on a byte shared by every key are skipped, so integer keys
+/// take at most 4 passes.
+///
+/// # Arguments
+///
+/// * `keys` - Normalized key of every row.
+pub fn radix_sort_row_ids(keys: &[u64]) -> Vec<usize> {
+    let mut rows: Vec<usize> = (0..keys.len()).collect();
+    let mut scratch = vec![0; keys.len()];
+    for shift in (0..u64::BITS as usize).step_by(RADIX_BITS) {
+        let digit = |row: usize| ((keys[row] >> shift) as usize) & (BUCKETS - 1);
+        let mut counts = [0usize; BUCKETS];
+        for row in rows.iter() {
+            counts[digit(*row)] += 1;
+        }
+        if counts.contains(&keys.len()) {
+            continue;
+        }
+        // turn the counts into the first position of each bucket
+        let mut next = [0usize; BUCKETS];
+        for bucket in 1..BUCKETS {
+            next[bucket] = next[bucket - 1] + counts[bucket - 1];
+        }
+        for row in rows.iter() {
+            let bucket = digit(*row);
+            scratch[next[bucket]] = *row;
+            next[bucket] += 1;
+        }
+        std::mem::swap(&mut rows, &mut scratch);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::generator::seeded_rng;
+    use rand::Rng;
+
+    #[test]
+    fn sorts_stably() {
+        let mut rng = seeded_rng(5);
+        let keys: Vec<u64> = (0..10_000).map(|_| rng.gen_range(0..64) << rng.gen_range(0..58)).collect();
+        let rows = radix_sort_row_ids(&keys);
+        let mut expected: Vec<usize> = (0..keys.len()).collect();
+        expected.sort_by_key(|row| keys[*row]);
+        assert_eq!(rows, expected);
+        assert!(radix_sort_row_ids(&[]).is_empty());
+    }
+}
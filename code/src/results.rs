@@ -0,0 +1,298 @@
+use std::fmt;
+use std::io::Write;
+use serde::{Deserialize, Serialize};
+use crate::common::CrustyError;
+use crate::metrics::{per_second, PhaseStats, BYTES_PER_MB};
+
+/// Columns of the CSV results, in the order of the RunResult fields.
+///
+/// The phases only go to the JSON results.
+pub const RESULT_COLUMNS: [&str; 11] = [
+    "run_id",
+    "algorithm",
+    "rows",
+    "overlap",
+    "seed",
+    "threads",
+    "elapsed_s",
+    "peak_mem",
+    "output_rows",
+    "tuples_per_s",
+    "mb_per_s",
+];
+
+/// Measurements of one benchmark run of a join.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunResult {
+    /// Identifier shared by the runs of one invocation.
+    pub run_id: String,
+    /// Join method, e.g. m-way.
+    pub algorithm: String,
+    /// Number of tuples of each input.
+    pub rows: usize,
+    /// Share of the tuples found in both inputs, between 0 and 1.
+    pub overlap: f64,
+    /// Seed of the generated inputs.
+    pub seed: u64,
+    /// Number of threads joining the runs.
+    pub threads: usize,
+    /// Time from open to the last tuple, in seconds.
+    pub elapsed_s: f64,
+    /// Largest number of bytes held by the join at once.
+    pub peak_mem: usize,
+    /// Number of joined tuples.
+    pub output_rows: usize,
+    /// Input tuples of both sides joined per second.
+    pub tuples_per_s: f64,
+    /// Megabytes of the input tuples of both sides joined per second.
+    pub mb_per_s: f64,
+    /// Rows, bytes and time of each phase of the join.
+    #[serde(default)]
+    pub phases: Vec<PhaseStats>,
+}
+
+/// Returns the tuples and megabytes processed per second.
+///
+/// # Arguments
+///
+/// * `tuples` - Tuples processed.
+/// * `bytes` - Bytes of the tuples, see TableSchema::byte_size.
+/// * `elapsed_s` - Time it took, in seconds.
+pub fn throughput(tuples: usize, bytes: usize, elapsed_s: f64) -> (f64, f64) {
+    (per_second(tuples as f64, elapsed_s), per_second(bytes as f64 / BYTES_PER_MB, elapsed_s))
+}
+
+/// Statistics of the times of repeated runs of one configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    /// Number of timed runs.
+    pub runs: usize,
+    /// Mean time, in seconds.
+    pub mean_s: f64,
+    /// Median time, the mean of the middle two for an even number of runs.
+    pub median_s: f64,
+    /// Sample standard deviation of the times, 0 for a single run.
+    pub std_dev_s: f64,
+    /// Shortest time.
+    pub min_s: f64,
+    /// Longest time.
+    pub max_s: f64,
+}
+
+impl Summary {
+    /// Returns the statistics of some times, None without any.
+    ///
+    /// # Arguments
+    ///
+    /// * `samples` - Times of the runs, in seconds.
+    pub fn of(samples: &[f64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let runs = sorted.len();
+        let mean_s = sorted.iter().sum::<f64>() / runs as f64;
+        let median_s = match runs % 2 {
+            1 => sorted[runs / 2],
+            _ => (sorted[runs / 2 - 1] + sorted[runs / 2]) / 2.0,
+        };
+        let std_dev_s = match runs {
+            1 => 0.0,
+            _ => (sorted.iter().map(|s| (s - mean_s).powi(2)).sum::<f64>() / (runs - 1) as f64).sqrt(),
+        };
+        Some(Self {
+            runs,
+            mean_s,
+            median_s,
+            std_dev_s,
+            min_s: sorted[0],
+            max_s: sorted[runs - 1],
+        })
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} runs: mean {:.6}s, median {:.6}s, stddev {:.6}s, min {:.6}s, max {:.6}s",
+            self.runs, self.mean_s, self.median_s, self.std_dev_s, self.min_s, self.max_s
+        )
+    }
+}
+
+/// Summary of the repetitions of one configuration of a benchmark.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigSummary {
+    /// Join method, e.g. m-way.
+    pub algorithm: String,
+    /// Number of tuples of each input.
+    pub rows: usize,
+    /// Share of the tuples found in both inputs, between 0 and 1.
+    pub overlap: f64,
+    /// Number of threads joining the runs.
+    pub threads: usize,
+    /// Statistics of the times of the runs.
+    pub summary: Summary,
+}
+
+/// Returns the statistics of the runs of each configuration, in the order the
+/// configurations first appear.
+///
+/// # Arguments
+///
+/// * `results` - Results of the runs, e.g. of a sweep.
+pub fn summarize(results: &[RunResult]) -> Vec<ConfigSummary> {
+    let mut configs: Vec<(&RunResult, Vec<f64>)> = Vec::new();
+    for r in results {
+        let same = |c: &&mut (&RunResult, Vec<f64>)| {
+            (&c.0.algorithm, c.0.rows, c.0.overlap, c.0.threads) == (&r.algorithm, r.rows, r.overlap, r.threads)
+        };
+        match configs.iter_mut().find(same) {
+            Some((_, samples)) => samples.push(r.elapsed_s),
+            None => configs.push((r, vec![r.elapsed_s])),
+        }
+    }
+    configs
+        .into_iter()
+        .map(|(r, samples)| ConfigSummary {
+            algorithm: r.algorithm.clone(),
+            rows: r.rows,
+            overlap: r.overlap,
+            threads: r.threads,
+            summary: Summary::of(&samples).unwrap(),
+        })
+        .collect()
+}
+
+/// Format results are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultFormat {
+    /// Header line, then one line per run.
+    Csv,
+    /// Array of one object per run.
+    Json,
+}
+
+impl ResultFormat {
+    /// Returns the format of a name, csv or json.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the format.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "csv" => Some(ResultFormat::Csv),
+            "json" => Some(ResultFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Write benchmark results so they can be loaded by plotting tools.
+///
+/// # Arguments
+///
+/// * `results` - Results to write.
+/// * `format` - Format of the output.
+/// * `writer` - Destination of the results, e.g. a file.
+pub fn write_results(results: &[RunResult], format: ResultFormat, writer: &mut dyn Write) -> Result<(), CrustyError> {
+    match format {
+        ResultFormat::Csv => {
+            writeln!(writer, "{}", RESULT_COLUMNS.join(","))?;
+            for r in results {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{},{},{},{},{}",
+                    r.run_id,
+                    r.algorithm,
+                    r.rows,
+                    r.overlap,
+                    r.seed,
+                    r.threads,
+                    r.elapsed_s,
+                    r.peak_mem,
+                    r.output_rows,
+                    r.tuples_per_s,
+                    r.mb_per_s
+                )?;
+            }
+        }
+        ResultFormat::Json => {
+            serde_json::to_writer_pretty(&mut *writer, results)
+                .map_err(|e| CrustyError::CrustyError(format!("cannot write results: {}", e)))?;
+            writeln!(writer)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_result(algorithm: &str) -> RunResult {
+        RunResult {
+            run_id: String::from("1"),
+            algorithm: algorithm.to_string(),
+            rows: 2048,
+            overlap: 0.1,
+            seed: 42,
+            threads: 3,
+            elapsed_s: 0.25,
+            peak_mem: 65536,
+            output_rows: 4000,
+            tuples_per_s: 16384.0,
+            mb_per_s: 0.125,
+            phases: vec![PhaseStats::new("sort left", 2048, 16384, std::time::Duration::from_millis(500))],
+        }
+    }
+
+    #[test]
+    fn csv() -> Result<(), CrustyError> {
+        let mut out = Vec::new();
+        write_results(&[test_result("m-way"), test_result("m-pass")], ResultFormat::Csv, &mut out)?;
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "run_id,algorithm,rows,overlap,seed,threads,elapsed_s,peak_mem,output_rows,tuples_per_s,mb_per_s");
+        assert_eq!(lines[2], "1,m-pass,2048,0.1,42,3,0.25,65536,4000,16384,0.125");
+        Ok(())
+    }
+
+    #[test]
+    fn throughputs() {
+        // 2 sides of 2048 tuples of 8 bytes in a quarter second
+        assert_eq!(throughput(4096, 32768, 0.25), (16384.0, 0.131072));
+        assert_eq!(throughput(4096, 32768, 0.0), (0.0, 0.0));
+        let phase = &test_result("m-way").phases[0];
+        assert_eq!((phase.tuples_per_s(), phase.mb_per_s()), (4096.0, 0.032768));
+    }
+
+    #[test]
+    fn summaries() {
+        let summary = Summary::of(&[3.0, 1.0, 2.0, 6.0]).unwrap();
+        assert_eq!((summary.runs, summary.mean_s, summary.median_s), (4, 3.0, 2.5));
+        assert_eq!((summary.min_s, summary.max_s), (1.0, 6.0));
+        assert!((summary.std_dev_s - (14.0f64 / 3.0).sqrt()).abs() < 1e-12);
+        assert_eq!(Summary::of(&[0.5]).unwrap().std_dev_s, 0.0);
+        assert_eq!(Summary::of(&[]), None);
+
+        let mut results = vec![test_result("m-way"), test_result("radix"), test_result("m-way")];
+        results[2].elapsed_s = 0.75;
+        let configs = summarize(&results);
+        assert_eq!(configs.len(), 2);
+        assert_eq!((configs[0].algorithm.as_str(), configs[0].summary.runs, configs[0].summary.mean_s), ("m-way", 2, 0.5));
+        assert_eq!((configs[1].algorithm.as_str(), configs[1].summary.runs), ("radix", 1));
+    }
+
+    #[test]
+    fn json_round_trip() -> Result<(), CrustyError> {
+        let results = vec![test_result("m-way")];
+        let mut out = Vec::new();
+        write_results(&results, ResultFormat::Json, &mut out)?;
+        let read: Vec<RunResult> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(read, results);
+        Ok(())
+    }
+}
@@ -0,0 +1,932 @@
+//! Duplicate elimination and set operations over children with the same schema.
+//!
+//! Distinct finds duplicates with a hash set or by sorting, Union deduplicates the
+//! concatenation of its children, and Intersect and ExceptAll merge their sorted children.
+//! The sorts go through the run generation and merge of the sort-merge join, see
+//! join::sort_tuples.
+
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use crate::common::{not_open, CrustyError, OpIterator, TableSchema, Tuple};
+use crate::config::SortConfig;
+use crate::cost::HASH_ENTRY_OVERHEAD;
+use crate::join::{sort_tuples, SortKernel};
+use crate::metrics::OpMetrics;
+use crate::spill::{SpillFile, SpillReader};
+
+/// Number of partitions the hash method spills to once its set is full.
+pub const SPILL_PARTITIONS: usize = 8;
+
+// helper method to order tuples on all of their fields
+fn cmp_tuples(a: &Tuple, b: &Tuple) -> Ordering {
+    a.field_vals.cmp(&b.field_vals)
+}
+
+// helper method to check that two children produce union compatible tuples
+fn check_schemas(left: &TableSchema, right: &TableSchema) -> Result<(), CrustyError> {
+    if left.size() != right.size() {
+        return Err(CrustyError::ValidationError(format!(
+            "schemas have {} and {} attributes",
+            left.size(),
+            right.size()
+        )));
+    }
+    for (i, (l, r)) in left.attributes().zip(right.attributes()).enumerate() {
+        if l.dtype() != r.dtype() {
+            return Err(CrustyError::ValidationError(format!(
+                "attribute {} has types {:?} and {:?}",
+                i,
+                l.dtype(),
+                r.dtype()
+            )));
+        }
+    }
+    Ok(())
+}
+
+// helper method to read a child into a vector sorted on all fields
+//
+// The tuples are sorted on their first field by the sort of the join, then each block of
+// equal first fields is ordered on the remaining fields.
+fn read_sorted(child: &mut Box<dyn OpIterator>, config: &SortConfig, kernel: SortKernel) -> Result<Vec<Tuple>, CrustyError> {
+    let mut tuples = Vec::new();
+    while let Some(t) = child.next()? {
+        tuples.push(t);
+    }
+    // sorted inputs, and tuples without a field to sort on, are used as they are
+    if child.get_schema().size() == 0 || tuples.windows(2).all(|w| cmp_tuples(&w[0], &w[1]) != Ordering::Greater) {
+        return Ok(tuples);
+    }
+    let mut tuples = sort_tuples(kernel, tuples, 0, config, child.get_schema().byte_size());
+    let mut start = 0;
+    while start < tuples.len() {
+        let first = tuples[start].get_field(0).cloned();
+        let len = tuples[start..].iter().take_while(|t| t.get_field(0) == first.as_ref()).count();
+        tuples[start..start + len].sort_unstable_by(cmp_tuples);
+        start += len;
+    }
+    Ok(tuples)
+}
+
+/// Method used to find duplicate tuples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistinctMethod {
+    /// Remember every returned tuple in a hash set, partitioning and spilling the
+    /// tuples that do not fit in the memory budget.
+    Hash,
+    /// Sort the child and skip equal neighbours.
+    Sort,
+}
+
+/// Duplicate elimination.
+pub struct Distinct {
+    /// Child node.
+    child: Box<dyn OpIterator>,
+    /// Method used to find duplicates.
+    method: DistinctMethod,
+    /// Distinct status
+    open: bool,
+    /// Tuples returned so far (hash method).
+    seen: HashSet<Tuple>,
+    /// Memory available to the hash set in bytes, if bounded.
+    memory_budget: Option<usize>,
+    /// Partitions the tuples over the budget are spilled to in the current pass.
+    spilled: Vec<SpillFile>,
+    /// Spilled partitions waiting for their own pass.
+    pending: VecDeque<SpillFile>,
+    /// Partition read by the current pass, or None while reading the child.
+    source: Option<SpillReader>,
+    /// Number of the current pass, used to salt the partitioning hash.
+    pass: usize,
+    /// Number of partitions spilled since the last open.
+    spills: usize,
+    /// Run sizes of the sort (sort method).
+    sort_config: SortConfig,
+    /// Kernel forming the sorted runs (sort method).
+    sort_kernel: SortKernel,
+    /// Sorted tuples of the child (sort method).
+    sorted: Vec<Tuple>,
+    /// Index of the next tuple in sorted.
+    index_cur: usize,
+}
+
+impl Distinct {
+    /// Distinct constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - Child node.
+    /// * `method` - Method used to find duplicates.
+    pub fn new(child: Box<dyn OpIterator>, method: DistinctMethod) -> Self {
+        Self {
+            child,
+            method,
+            open: false,
+            seen: HashSet::new(),
+            memory_budget: None,
+            spilled: Vec::new(),
+            pending: VecDeque::new(),
+            source: None,
+            pass: 0,
+            spills: 0,
+            sort_config: SortConfig::default(),
+            sort_kernel: SortKernel::Network,
+            sorted: Vec::new(),
+            index_cur: 0,
+        }
+    }
+
+    /// Set the run sizes used by the sort method, including the memory budget of a run.
+    ///
+    /// # Arguments
+    ///
+    /// * `sort_config` - Run-formation configuration to use on the next open.
+    pub fn set_sort_config(&mut self, sort_config: SortConfig) {
+        self.sort_config = sort_config;
+    }
+
+    /// Set the kernel forming the sorted runs of the sort method.
+    ///
+    /// # Arguments
+    ///
+    /// * `kernel` - Sort kernel to use on the next open.
+    pub fn set_sort_kernel(&mut self, kernel: SortKernel) {
+        self.sort_kernel = kernel;
+    }
+
+    /// Set the memory budget of the hash method.
+    ///
+    /// Once the hash set holds as many tuples as fit in the budget, unseen tuples are
+    /// hash partitioned into spill files. Each partition is deduplicated by a later pass
+    /// with an empty set, and spills again if it still does not fit.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Memory available to the hash set in bytes.
+    pub fn set_memory_budget(&mut self, bytes: usize) {
+        self.memory_budget = Some(bytes);
+    }
+
+    // helper method to return the number of tuples the hash set may hold
+    fn capacity(&self) -> usize {
+        let tuple_bytes = self.child.get_schema().byte_size() + HASH_ENTRY_OVERHEAD;
+        self.memory_budget.map_or(usize::MAX, |budget| (budget / tuple_bytes).max(1))
+    }
+
+    // helper method to return the next tuple of the current pass
+    fn next_input(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        match self.source.as_mut() {
+            None => self.child.next(),
+            Some(reader) => reader.next_tuple(),
+        }
+    }
+
+    // helper method to write a tuple to its spill partition
+    fn spill(&mut self, t: &Tuple) -> Result<(), CrustyError> {
+        if self.spilled.is_empty() {
+            for _ in 0..SPILL_PARTITIONS {
+                self.spilled.push(SpillFile::create()?);
+            }
+        }
+        let mut hasher = DefaultHasher::new();
+        (self.pass, t).hash(&mut hasher);
+        self.spilled[hasher.finish() as usize % SPILL_PARTITIONS].write(t)
+    }
+
+    // helper method to start a pass over the next pending partition, returns false if none
+    fn next_pass(&mut self) -> Result<bool, CrustyError> {
+        let spilled: Vec<SpillFile> = self.spilled.drain(..).filter(|f| !f.is_empty()).collect();
+        self.spills += spilled.len();
+        self.pending.extend(spilled);
+        match self.pending.pop_front() {
+            None => Ok(false),
+            Some(file) => {
+                // the partition shares no tuple with the earlier passes
+                self.seen.clear();
+                self.source = Some(file.into_reader()?);
+                self.pass += 1;
+                Ok(true)
+            }
+        }
+    }
+
+    // helper method to drop the hash method state
+    fn reset_hash(&mut self) {
+        self.seen.clear();
+        self.spilled.clear();
+        self.pending.clear();
+        self.source = None;
+        self.pass = 0;
+    }
+}
+
+impl OpIterator for Distinct {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.open = true;
+        self.spills = 0;
+        self.child.open()?;
+        if self.method == DistinctMethod::Sort {
+            // read the whole child and sort it, duplicates end up next to each other
+            self.sorted = read_sorted(&mut self.child, &self.sort_config, self.sort_kernel)?;
+            self.index_cur = 0;
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        match self.method {
+            DistinctMethod::Hash => {
+                let capacity = self.capacity();
+                loop {
+                    match self.next_input()? {
+                        Some(t) => {
+                            if self.seen.contains(&t) {
+                                continue;
+                            }
+                            if self.seen.len() < capacity {
+                                self.seen.insert(t.clone());
+                                return Ok(Some(t));
+                            }
+                            // unseen tuples over the budget are left to a later pass
+                            self.spill(&t)?;
+                        }
+                        None => {
+                            if !self.next_pass()? {
+                                return Ok(None);
+                            }
+                        }
+                    }
+                }
+            }
+            DistinctMethod::Sort => {
+                let t = match self.sorted.get(self.index_cur) {
+                    None => return Ok(None),
+                    Some(t) => t.clone(),
+                };
+                // skip the equal neighbours
+                while self.sorted.get(self.index_cur) == Some(&t) {
+                    self.index_cur += 1;
+                }
+                Ok(Some(t))
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        self.child.close()?;
+        self.reset_hash();
+        self.sorted.clear();
+        self.open = false;
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        match self.method {
+            DistinctMethod::Hash => {
+                self.reset_hash();
+                self.child.rewind()
+            }
+            // keep the sorted tuples
+            DistinctMethod::Sort => {
+                self.index_cur = 0;
+                Ok(())
+            }
+        }
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        self.child.get_schema()
+    }
+
+    fn metrics(&self) -> OpMetrics {
+        OpMetrics {
+            spills: self.spills,
+            ..OpMetrics::default()
+        }
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.child]
+    }
+
+    /// The sort method returns tuples sorted on all fields.
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        match self.method {
+            DistinctMethod::Sort => Some((0..self.get_schema().size()).collect()),
+            _ => None,
+        }
+    }
+}
+
+/// Concatenation of two children, keeping duplicates.
+pub struct UnionAll {
+    /// Left child node.
+    left_child: Box<dyn OpIterator>,
+    /// Right child node.
+    right_child: Box<dyn OpIterator>,
+    /// UnionAll status
+    open: bool,
+    /// Whether the left child is exhausted.
+    left_done: bool,
+}
+
+impl UnionAll {
+    /// UnionAll constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `left_child` - Left child node.
+    /// * `right_child` - Right child node.
+    pub fn new(left_child: Box<dyn OpIterator>, right_child: Box<dyn OpIterator>) -> Self {
+        Self {
+            left_child,
+            right_child,
+            open: false,
+            left_done: false,
+        }
+    }
+}
+
+impl OpIterator for UnionAll {
+    /// Opens both children, failing with a ValidationError if their schemas differ.
+    fn open(&mut self) -> Result<(), CrustyError> {
+        check_schemas(self.left_child.get_schema(), self.right_child.get_schema())?;
+        self.open = true;
+        self.left_done = false;
+        self.left_child.open()?;
+        self.right_child.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        if !self.left_done {
+            if let Some(t) = self.left_child.next()? {
+                return Ok(Some(t));
+            }
+            self.left_done = true;
+        }
+        self.right_child.next()
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        self.left_child.close()?;
+        self.right_child.close()?;
+        self.open = false;
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        self.left_done = false;
+        self.left_child.rewind()?;
+        self.right_child.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        self.left_child.get_schema()
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.left_child, &self.right_child]
+    }
+}
+
+/// Union of two children without duplicates.
+pub struct Union {
+    /// Duplicate elimination over the concatenated children.
+    distinct: Distinct,
+}
+
+impl Union {
+    /// Union constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `left_child` - Left child node.
+    /// * `right_child` - Right child node.
+    /// * `method` - Method used to find duplicates.
+    pub fn new(
+        left_child: Box<dyn OpIterator>,
+        right_child: Box<dyn OpIterator>,
+        method: DistinctMethod,
+    ) -> Self {
+        let all = Box::new(UnionAll::new(left_child, right_child));
+        Self {
+            distinct: Distinct::new(all, method),
+        }
+    }
+
+    /// Set the memory budget of the hash method, see Distinct::set_memory_budget.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Memory available to the hash set in bytes.
+    pub fn set_memory_budget(&mut self, bytes: usize) {
+        self.distinct.set_memory_budget(bytes);
+    }
+}
+
+impl OpIterator for Union {
+    /// Opens both children, failing with a ValidationError if their schemas differ.
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.distinct.open()
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        self.distinct.next()
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        self.distinct.close()
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.distinct.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        self.distinct.get_schema()
+    }
+
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        self.distinct.sort_order()
+    }
+
+    fn metrics(&self) -> OpMetrics {
+        self.distinct.metrics()
+    }
+
+    /// The children of the concatenation deduplicated by the union.
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        self.distinct.children().into_iter().flat_map(|all| all.children()).collect()
+    }
+}
+
+/// Merge-based intersection of two children without duplicates.
+pub struct Intersect {
+    /// Left child node.
+    left_child: Box<dyn OpIterator>,
+    /// Right child node.
+    right_child: Box<dyn OpIterator>,
+    /// Intersect status
+    open: bool,
+    /// Sorted tuples of the left child.
+    left_sorted: Vec<Tuple>,
+    /// Sorted tuples of the right child.
+    right_sorted: Vec<Tuple>,
+    /// Index of the next tuple in left_sorted.
+    left_cur: usize,
+    /// Index of the next tuple in right_sorted.
+    right_cur: usize,
+}
+
+impl Intersect {
+    /// Intersect constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `left_child` - Left child node.
+    /// * `right_child` - Right child node.
+    pub fn new(left_child: Box<dyn OpIterator>, right_child: Box<dyn OpIterator>) -> Self {
+        Self {
+            left_child,
+            right_child,
+            open: false,
+            left_sorted: Vec::new(),
+            right_sorted: Vec::new(),
+            left_cur: 0,
+            right_cur: 0,
+        }
+    }
+}
+
+impl OpIterator for Intersect {
+    /// Opens and sorts both children, failing with a ValidationError if their schemas differ.
+    ///
+    /// Children whose tuples are already sorted are not sorted again.
+    fn open(&mut self) -> Result<(), CrustyError> {
+        check_schemas(self.left_child.get_schema(), self.right_child.get_schema())?;
+        self.open = true;
+        self.left_child.open()?;
+        self.right_child.open()?;
+        self.left_sorted = read_sorted(&mut self.left_child, &SortConfig::default(), SortKernel::Network)?;
+        self.right_sorted = read_sorted(&mut self.right_child, &SortConfig::default(), SortKernel::Network)?;
+        self.left_cur = 0;
+        self.right_cur = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        // advance the cursor with the smaller tuple until both point to equal tuples
+        while let (Some(l), Some(r)) = (
+            self.left_sorted.get(self.left_cur),
+            self.right_sorted.get(self.right_cur),
+        ) {
+            match cmp_tuples(l, r) {
+                Ordering::Less => self.left_cur += 1,
+                Ordering::Greater => self.right_cur += 1,
+                Ordering::Equal => {
+                    let t = l.clone();
+                    // skip the duplicates on both sides
+                    while self.left_sorted.get(self.left_cur) == Some(&t) {
+                        self.left_cur += 1;
+                    }
+                    while self.right_sorted.get(self.right_cur) == Some(&t) {
+                        self.right_cur += 1;
+                    }
+                    return Ok(Some(t));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        self.left_child.close()?;
+        self.right_child.close()?;
+        self.left_sorted.clear();
+        self.right_sorted.clear();
+        self.open = false;
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        // keep the sorted tuples
+        self.left_cur = 0;
+        self.right_cur = 0;
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        self.left_child.get_schema()
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.left_child, &self.right_child]
+    }
+
+    /// Tuples are returned sorted on all fields.
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        Some((0..self.get_schema().size()).collect())
+    }
+}
+
+/// Merge-based multiset difference of two children.
+///
+/// A tuple found m times in the left child and n times in the right child is returned
+/// max(m - n, 0) times, as in SQL EXCEPT ALL.
+pub struct ExceptAll {
+    /// Left child node.
+    left_child: Box<dyn OpIterator>,
+    /// Right child node.
+    right_child: Box<dyn OpIterator>,
+    /// ExceptAll status
+    open: bool,
+    /// Sorted tuples of the left child.
+    left_sorted: Vec<Tuple>,
+    /// Sorted tuples of the right child.
+    right_sorted: Vec<Tuple>,
+    /// Index of the next tuple in left_sorted.
+    left_cur: usize,
+    /// Index of the next tuple in right_sorted.
+    right_cur: usize,
+}
+
+impl ExceptAll {
+    /// ExceptAll constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `left_child` - Left child node.
+    /// * `right_child` - Right child node, whose tuples are removed from the left.
+    pub fn new(left_child: Box<dyn OpIterator>, right_child: Box<dyn OpIterator>) -> Self {
+        Self {
+            left_child,
+            right_child,
+            open: false,
+            left_sorted: Vec::new(),
+            right_sorted: Vec::new(),
+            left_cur: 0,
+            right_cur: 0,
+        }
+    }
+}
+
+impl OpIterator for ExceptAll {
+    /// Opens and sorts both children, failing with a ValidationError if their schemas differ.
+    ///
+    /// Children whose tuples are already sorted are not sorted again.
+    fn open(&mut self) -> Result<(), CrustyError> {
+        check_schemas(self.left_child.get_schema(), self.right_child.get_schema())?;
+        self.open = true;
+        self.left_child.open()?;
+        self.right_child.open()?;
+        self.left_sorted = read_sorted(&mut self.left_child, &SortConfig::default(), SortKernel::Network)?;
+        self.right_sorted = read_sorted(&mut self.right_child, &SortConfig::default(), SortKernel::Network)?;
+        self.left_cur = 0;
+        self.right_cur = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        while let Some(l) = self.left_sorted.get(self.left_cur) {
+            // skip the right tuples smaller than the left one
+            while self
+                .right_sorted
+                .get(self.right_cur)
+                .is_some_and(|r| cmp_tuples(r, l) == Ordering::Less)
+            {
+                self.right_cur += 1;
+            }
+            self.left_cur += 1;
+            match self.right_sorted.get(self.right_cur) {
+                // each right duplicate cancels one left duplicate
+                Some(r) if r == l => self.right_cur += 1,
+                _ => return Ok(Some(l.clone())),
+            }
+        }
+        Ok(None)
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        self.left_child.close()?;
+        self.right_child.close()?;
+        self.left_sorted.clear();
+        self.right_sorted.clear();
+        self.open = false;
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        if !self.open {
+            return not_open();
+        }
+        // keep the sorted tuples
+        self.left_cur = 0;
+        self.right_cur = 0;
+        Ok(())
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        self.left_child.get_schema()
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        vec![&self.left_child, &self.right_child]
+    }
+
+    /// Tuples are returned sorted on all fields.
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        Some((0..self.get_schema().size()).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{Attribute, DataType, Field, TupleIterator};
+
+    fn scan(tuple_data: Vec<Vec<i32>>) -> Box<dyn OpIterator> {
+        let tuples: Vec<Tuple> = tuple_data
+            .iter()
+            .map(|item| Tuple::new(item.iter().map(|i| Field::IntField(*i)).collect()))
+            .collect();
+        let width = tuples.first().map_or(0, |t| t.size());
+        let attrs = (0..width).map(|_| Attribute::new(String::new(), DataType::Int)).collect();
+        Box::new(TupleIterator::new(tuples, TableSchema::new(attrs)))
+    }
+
+    fn collect(op: &mut dyn OpIterator) -> Result<Vec<Tuple>, CrustyError> {
+        let mut res = Vec::new();
+        while let Some(t) = op.next()? {
+            res.push(t);
+        }
+        Ok(res)
+    }
+
+    fn test_distinct(method: DistinctMethod) -> Result<(), CrustyError> {
+        let child = scan(vec![vec![3, 1], vec![1, 2], vec![3, 1], vec![1, 2], vec![1, 3], vec![3, 1]]);
+        let mut op = Distinct::new(child, method);
+        op.open()?;
+        let mut res = collect(&mut op)?;
+        if method == DistinctMethod::Hash {
+            res.sort_by(|a, b| a.field_vals.cmp(&b.field_vals));
+        }
+        let expected = vec![
+            Tuple::new(vec![Field::IntField(1), Field::IntField(2)]),
+            Tuple::new(vec![Field::IntField(1), Field::IntField(3)]),
+            Tuple::new(vec![Field::IntField(3), Field::IntField(1)]),
+        ];
+        assert_eq!(expected, res);
+
+        op.rewind()?;
+        assert_eq!(collect(&mut op)?.len(), 3);
+        op.close()
+    }
+
+    fn test_distinct_spill() -> Result<(), CrustyError> {
+        // 40 distinct tuples, each three times, with room for 4 tuples in the hash set
+        let data: Vec<Vec<i32>> = (0..120).map(|i| vec![i % 40, (i % 40) * 2]).collect();
+        let mut op = Distinct::new(scan(data), DistinctMethod::Hash);
+        op.set_memory_budget(4 * (8 + HASH_ENTRY_OVERHEAD));
+        op.open()?;
+        let mut res = collect(&mut op)?;
+        res.sort_by(cmp_tuples);
+        let expected = ints((0..40).map(|i| vec![i, i * 2]).collect());
+        assert_eq!(expected, res);
+
+        op.rewind()?;
+        assert_eq!(collect(&mut op)?.len(), 40);
+        op.close()?;
+
+        let left = scan((0..30).map(|i| vec![i, 0]).collect());
+        let right = scan((10..50).map(|i| vec![i, 0]).collect());
+        let mut op = Union::new(left, right, DistinctMethod::Hash);
+        op.set_memory_budget(1);
+        op.open()?;
+        assert_eq!(collect(&mut op)?.len(), 50);
+        op.close()
+    }
+
+    fn test_distinct_kernels() -> Result<(), CrustyError> {
+        // 50 distinct tuples sharing first fields, each twice, sorted in runs of 4 with a
+        // budget of 2 runs
+        let data: Vec<Vec<i32>> = (0..100).rev().map(|i| vec![(i % 50) / 5, i % 50]).collect();
+        let expected = ints((0..50).map(|i| vec![i / 5, i]).collect());
+        let mut config = SortConfig::new(4, 64);
+        config.set_memory_budget(2 * 4 * 8);
+        for kernel in [SortKernel::Network, SortKernel::Std, SortKernel::Columnar, SortKernel::Radix] {
+            let mut op = Distinct::new(scan(data.clone()), DistinctMethod::Sort);
+            op.set_sort_config(config);
+            op.set_sort_kernel(kernel);
+            op.open()?;
+            assert_eq!(collect(&mut op)?, expected);
+            op.close()?;
+        }
+        Ok(())
+    }
+
+    fn ints(tuple_data: Vec<Vec<i32>>) -> Vec<Tuple> {
+        tuple_data
+            .iter()
+            .map(|item| Tuple::new(item.iter().map(|i| Field::IntField(*i)).collect()))
+            .collect()
+    }
+
+    fn test_union(method: DistinctMethod) -> Result<(), CrustyError> {
+        let left = scan(vec![vec![1, 2], vec![3, 4], vec![1, 2]]);
+        let right = scan(vec![vec![3, 4], vec![5, 6]]);
+        let mut op = Union::new(left, right, method);
+        op.open()?;
+        let mut res = collect(&mut op)?;
+        res.sort_by(cmp_tuples);
+        assert_eq!(ints(vec![vec![1, 2], vec![3, 4], vec![5, 6]]), res);
+        op.close()
+    }
+
+    fn test_union_all() -> Result<(), CrustyError> {
+        let left = scan(vec![vec![1, 2], vec![1, 2]]);
+        let right = scan(vec![vec![1, 2], vec![5, 6]]);
+        let mut op = UnionAll::new(left, right);
+        op.open()?;
+        assert_eq!(ints(vec![vec![1, 2], vec![1, 2], vec![1, 2], vec![5, 6]]), collect(&mut op)?);
+        op.rewind()?;
+        assert_eq!(collect(&mut op)?.len(), 4);
+        op.close()
+    }
+
+    fn test_intersect() -> Result<(), CrustyError> {
+        let left = scan(vec![vec![5, 6], vec![1, 2], vec![3, 4], vec![1, 2], vec![7, 8]]);
+        let right = scan(vec![vec![1, 2], vec![1, 2], vec![5, 6], vec![9, 9]]);
+        let mut op = Intersect::new(left, right);
+        op.open()?;
+        assert_eq!(ints(vec![vec![1, 2], vec![5, 6]]), collect(&mut op)?);
+        op.rewind()?;
+        assert_eq!(collect(&mut op)?.len(), 2);
+        op.close()
+    }
+
+    fn test_except_all() -> Result<(), CrustyError> {
+        let left = scan(vec![vec![3, 4], vec![1, 2], vec![1, 2], vec![5, 6], vec![1, 2], vec![3, 4]]);
+        let right = scan(vec![vec![1, 2], vec![3, 4], vec![3, 4], vec![3, 4], vec![0, 0]]);
+        let mut op = ExceptAll::new(left, right);
+        op.open()?;
+        assert_eq!(ints(vec![vec![1, 2], vec![1, 2], vec![5, 6]]), collect(&mut op)?);
+        op.rewind()?;
+        assert_eq!(collect(&mut op)?.len(), 3);
+        op.close()
+    }
+
+    fn test_schema_mismatch() {
+        let left = scan(vec![vec![1, 2]]);
+        let right = scan(vec![vec![1, 2, 3]]);
+        let mut op = Intersect::new(left, right);
+        assert!(matches!(op.open(), Err(CrustyError::ValidationError(_))));
+
+        let left = scan(vec![vec![1]]);
+        let right = Box::new(TupleIterator::new(
+            vec![Tuple::new(vec![Field::StringField(String::from("a"))])],
+            TableSchema::new(vec![Attribute::new(String::new(), DataType::String)]),
+        ));
+        let mut op = UnionAll::new(left, right);
+        assert!(matches!(op.open(), Err(CrustyError::ValidationError(_))));
+    }
+
+    mod set_ops {
+        use super::*;
+
+        #[test]
+        fn union_hash() -> Result<(), CrustyError> {
+            test_union(DistinctMethod::Hash)
+        }
+
+        #[test]
+        fn union_sort() -> Result<(), CrustyError> {
+            test_union(DistinctMethod::Sort)
+        }
+
+        #[test]
+        fn union_all() -> Result<(), CrustyError> {
+            test_union_all()
+        }
+
+        #[test]
+        fn intersect() -> Result<(), CrustyError> {
+            test_intersect()
+        }
+
+        #[test]
+        fn except_all() -> Result<(), CrustyError> {
+            test_except_all()
+        }
+
+        #[test]
+        fn schema_mismatch() {
+            test_schema_mismatch();
+        }
+    }
+
+    mod distinct {
+        use super::*;
+
+        #[test]
+        fn hash() -> Result<(), CrustyError> {
+            test_distinct(DistinctMethod::Hash)
+        }
+
+        #[test]
+        fn sort() -> Result<(), CrustyError> {
+            test_distinct(DistinctMethod::Sort)
+        }
+
+        #[test]
+        fn sort_kernels() -> Result<(), CrustyError> {
+            test_distinct_kernels()
+        }
+
+        #[test]
+        fn hash_spill() -> Result<(), CrustyError> {
+            test_distinct_spill()
+        }
+
+        #[test]
+        #[cfg_attr(feature = "panic-not-open", should_panic)]
+        fn next_not_open() {
+            let mut op = Distinct::new(scan(vec![vec![1]]), DistinctMethod::Hash);
+            assert!(matches!(op.next(), Err(CrustyError::ExecutionError(_))));
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! Snapshots of tuples with their schema, so sorted runs and join results can be cached on
+//! disk and read back between benchmark runs.
+//!
+//! A snapshot is self-describing: it starts with the magic bytes MAGIC, the format version
+//! as a 2 byte little endian integer and the length of the header as a 4 byte little endian
+//! integer. The header, in CBOR, holds the schema, the field the runs are sorted on if any
+//! and the number of tuples of each run. The body follows, the tuples of the runs one after
+//! the other, each a CBOR value.
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::common::{CrustyError, TableSchema, Tuple, TupleIterator};
+
+/// Bytes starting every snapshot.
+pub const MAGIC: [u8; 4] = *b"JSNP";
+
+/// Version of the format written, snapshots of a later version are not read.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Header of a snapshot, see the module documentation.
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    schema: TableSchema,
+    sort_key: Option<usize>,
+    runs: Vec<usize>,
+}
+
+/// Runs of tuples sharing a schema, e.g. the sorted runs of a child or the output of a join.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Schema of the tuples.
+    pub schema: TableSchema,
+    /// Index of the field each run is sorted on, None if unsorted.
+    pub sort_key: Option<usize>,
+    /// Runs of tuples.
+    pub runs: Vec<Vec<Tuple>>,
+}
+
+impl Snapshot {
+    /// Create a snapshot of a single unsorted run.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the tuples.
+    /// * `tuples` - Tuples of the run.
+    pub fn new(schema: TableSchema, tuples: Vec<Tuple>) -> Self {
+        Self {
+            schema,
+            sort_key: None,
+            runs: vec![tuples],
+        }
+    }
+
+    /// Create a snapshot of runs sorted on a field.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the tuples.
+    /// * `sort_key` - Index of the field each run is sorted on.
+    /// * `runs` - Sorted runs.
+    pub fn from_runs(schema: TableSchema, sort_key: usize, runs: Vec<Vec<Tuple>>) -> Self {
+        Self {
+            schema,
+            sort_key: Some(sort_key),
+            runs,
+        }
+    }
+
+    /// Returns the number of tuples of all the runs.
+    pub fn len(&self) -> usize {
+        self.runs.iter().map(Vec::len).sum()
+    }
+
+    /// Returns true if the runs hold no tuple.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns an iterator over the tuples of the runs, one run after the other, e.g. to
+    /// replay a cached join result.
+    pub fn into_iterator(self) -> TupleIterator {
+        TupleIterator::new(self.runs.into_iter().flatten().collect(), self.schema)
+    }
+
+    /// Serialize the snapshot, see the module documentation.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, CrustyError> {
+        let header = Header {
+            schema: self.schema.clone(),
+            sort_key: self.sort_key,
+            runs: self.runs.iter().map(Vec::len).collect(),
+        };
+        let header = serde_cbor::to_vec(&header).map_err(|e| CrustyError::CrustyError(e.to_string()))?;
+        let mut bytes = Vec::with_capacity(10 + header.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&header);
+        for t in self.runs.iter().flatten() {
+            serde_cbor::to_writer(&mut bytes, t).map_err(|e| CrustyError::CrustyError(e.to_string()))?;
+        }
+        Ok(bytes)
+    }
+
+    /// Deserialize a snapshot.
+    ///
+    /// Fails with a ValidationError if the bytes are not a snapshot, were written by a later
+    /// version of the format, or hold tuples that do not match the runs or the schema.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Serialized snapshot.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CrustyError> {
+        let invalid = |reason: String| CrustyError::ValidationError(format!("not a snapshot: {}", reason));
+        if bytes.len() < 10 || bytes[..4] != MAGIC {
+            return Err(invalid(String::from("bad magic bytes")));
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version > FORMAT_VERSION {
+            return Err(CrustyError::ValidationError(format!(
+                "snapshot format version {} is newer than {}",
+                version, FORMAT_VERSION
+            )));
+        }
+        let header_len = u32::from_le_bytes(bytes[6..10].try_into().unwrap()) as usize;
+        // header_len is untrusted and usize may be 32 bits, e.g. on wasm32
+        let (end, header) = 10usize
+            .checked_add(header_len)
+            .and_then(|end| Some((end, bytes.get(10..end)?)))
+            .ok_or_else(|| invalid(String::from("truncated header")))?;
+        let header: Header = serde_cbor::from_slice(header).map_err(|e| invalid(e.to_string()))?;
+        let mut tuples = serde_cbor::Deserializer::from_slice(&bytes[end..]).into_iter::<Tuple>();
+        let mut runs = Vec::with_capacity(header.runs.len());
+        for len in header.runs {
+            let run = tuples
+                .by_ref()
+                .take(len)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| invalid(e.to_string()))?;
+            if run.len() != len {
+                return Err(invalid(format!("a run of {} tuples holds {}", len, run.len())));
+            }
+            if let Some(t) = run.iter().find(|t| t.size() != header.schema.size()) {
+                return Err(invalid(format!("tuple {:?} does not match the schema", t)));
+            }
+            runs.push(run);
+        }
+        if tuples.next().is_some() {
+            return Err(invalid(String::from("tuples past the last run")));
+        }
+        Ok(Self {
+            schema: header.schema,
+            sort_key: header.sort_key,
+            runs,
+        })
+    }
+
+    /// Write the snapshot to a file, replacing it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CrustyError> {
+        fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    /// Read a snapshot from a file, see from_bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CrustyError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{Attribute, Constraint, DataType, Field, OpIterator};
+    use crate::generator::{create_vec_tuple, get_int_table_schema};
+    use crate::join::{MergeStrategy, SortMergeJoin};
+
+    #[test]
+    fn round_trip() -> Result<(), CrustyError> {
+        // a join result, and sorted runs with a constrained string field
+        let scan = |seed| TupleIterator::new(create_vec_tuple(500, 2, 1000, seed), get_int_table_schema(2));
+        let mut join = SortMergeJoin::new(crate::common::SimplePredicateOp::Equals, 1, 1, scan(1), scan(2), MergeStrategy::M_WAY)?;
+        join.open()?;
+        let mut tuples = Vec::new();
+        while let Some(t) = join.next()? {
+            tuples.push(t);
+        }
+        let result = Snapshot::new(join.get_schema().clone(), tuples);
+        let schema = TableSchema::new(vec![
+            Attribute::new_pk(String::from("id"), DataType::Int),
+            Attribute::new_with_constraint(String::from("name"), DataType::String, Constraint::NotNull),
+        ]);
+        let row = |i: i32| Tuple::new(vec![Field::IntField(i), Field::StringField(format!("n{}", i))]);
+        let runs = Snapshot::from_runs(schema, 0, vec![(0..3).map(row).collect(), Vec::new(), (3..5).map(row).collect()]);
+
+        let path = std::env::temp_dir().join(format!("join-snapshot-{}", std::process::id()));
+        for snapshot in [result, runs] {
+            snapshot.save(&path)?;
+            assert_eq!(Snapshot::load(&path)?, snapshot);
+        }
+        fs::remove_file(&path)?;
+
+        // a later version, a truncated body, an oversized header length and other bytes fail
+        let mut bytes = Snapshot::from_runs(get_int_table_schema(1), 0, vec![vec![Tuple::new(vec![Field::IntField(1)])]]).to_bytes()?;
+        assert_eq!(Snapshot::from_bytes(&bytes)?.len(), 1);
+        bytes.pop();
+        assert!(matches!(Snapshot::from_bytes(&bytes), Err(CrustyError::ValidationError(_))));
+        let mut huge = bytes.clone();
+        huge[6..10].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(Snapshot::from_bytes(&huge), Err(CrustyError::ValidationError(_))));
+        bytes[4] = 2;
+        assert!(matches!(Snapshot::from_bytes(&bytes), Err(CrustyError::ValidationError(_))));
+        assert!(matches!(Snapshot::from_bytes(b"{}"), Err(CrustyError::ValidationError(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,438 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use crate::buffer::{BufferPool, PoolStats};
+use crate::common::{CrustyError, TableSchema, Tuple};
+use crate::heap::{HeapCursor, HeapFile, PAGE_SIZE};
+#[cfg(feature = "lz4")]
+use crate::heap::MAX_RECORD_SIZE;
+#[cfg(feature = "lz4")]
+use lz4_flex::block::{compress_prepend_size, decompress_size_prepended};
+
+/// Compression of the tuples of a spill file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpillCompression {
+    /// Each tuple is a record of the file.
+    #[default]
+    None,
+    /// Tuples are gathered into blocks of SPILL_BLOCK_SIZE bytes compressed with LZ4, see
+    /// lz4_flex, trading the CPU time of compressing for fewer pages written and read.
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+/// Bytes of tuples gathered into a compressed block.
+#[cfg(feature = "lz4")]
+pub const SPILL_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Temporary file of tuples written by an operator over its memory budget.
+///
+/// Tuples are stored as records of a temporary heap file, serialized as CBOR, or as
+/// fixed-size rows when the file is created with a schema. The file is removed when
+/// dropped.
+///
+/// With compression, the tuples are length prefixed and gathered into blocks; each block is
+/// compressed with its raw length prepended, and written after its compressed length, as a
+/// 4 byte little endian integer, into a stream of bytes cut into records of a whole page.
+pub struct SpillFile {
+    /// Pages of the file.
+    heap: HeapFile,
+    /// Schema of the fixed-size rows, None for CBOR.
+    layout: Option<TableSchema>,
+    /// Compression of the tuples.
+    compression: SpillCompression,
+    /// Number of tuples written.
+    count: usize,
+    /// Tuples of the block being gathered.
+    #[cfg(feature = "lz4")]
+    block: Vec<u8>,
+    /// Compressed bytes not written yet, less than a record.
+    #[cfg(feature = "lz4")]
+    stream: Vec<u8>,
+}
+
+impl SpillFile {
+    /// Create a new empty spill file in the temporary directory.
+    pub fn create() -> Result<Self, CrustyError> {
+        Ok(Self {
+            heap: HeapFile::temp()?,
+            layout: None,
+            compression: SpillCompression::None,
+            count: 0,
+            #[cfg(feature = "lz4")]
+            block: Vec::new(),
+            #[cfg(feature = "lz4")]
+            stream: Vec::new(),
+        })
+    }
+
+    /// Create a new empty spill file of fixed-size rows, see TableSchema::serialize_row.
+    ///
+    /// Rows take the same byte_size bytes whatever their values.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - Schema of the tuples written.
+    pub fn with_schema(schema: TableSchema) -> Result<Self, CrustyError> {
+        let mut file = Self::create()?;
+        file.layout = Some(schema);
+        Ok(file)
+    }
+
+    /// Open a copy of a spill file written by save, without removing it when dropped.
+    ///
+    /// Fails with a ValidationError if an uncompressed file does not hold len tuples.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the copy.
+    /// * `layout` - Schema of the fixed-size rows, None for CBOR, as the file was created.
+    /// * `compression` - Compression of the tuples of the file.
+    /// * `len` - Number of tuples of the file.
+    pub fn open(
+        path: impl AsRef<Path>,
+        layout: Option<TableSchema>,
+        compression: SpillCompression,
+        len: usize,
+    ) -> Result<Self, CrustyError> {
+        let heap = HeapFile::open(path)?;
+        if compression == SpillCompression::None && heap.len() != len {
+            return Err(CrustyError::ValidationError(format!(
+                "{} holds {} tuples, not {}",
+                heap.path().display(),
+                heap.len(),
+                len
+            )));
+        }
+        Ok(Self {
+            heap,
+            layout,
+            compression,
+            count: len,
+            #[cfg(feature = "lz4")]
+            block: Vec::new(),
+            #[cfg(feature = "lz4")]
+            stream: Vec::new(),
+        })
+    }
+
+    /// Write a copy of the tuples written so far, kept after the file is removed, e.g. for a
+    /// checkpoint.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the copy, replaced if it exists.
+    pub fn save(&mut self, path: impl AsRef<Path>) -> Result<(), CrustyError> {
+        self.flush()?;
+        self.heap.flush()?;
+        fs::copy(self.heap.path(), path)?;
+        Ok(())
+    }
+
+    /// Returns the compression of the tuples.
+    pub fn compression(&self) -> SpillCompression {
+        self.compression
+    }
+
+    /// Set the compression of the tuples, before the first one is written.
+    ///
+    /// # Arguments
+    ///
+    /// * `compression` - Compression of the tuples.
+    pub fn set_compression(&mut self, compression: SpillCompression) {
+        self.compression = compression;
+    }
+
+    /// Append a tuple to the file.
+    ///
+    /// # Arguments
+    ///
+    /// * `tuple` - Tuple to write.
+    pub fn write(&mut self, tuple: &Tuple) -> Result<(), CrustyError> {
+        let record = match &self.layout {
+            Some(schema) => schema.serialize_row(tuple)?,
+            None => tuple.get_bytes(),
+        };
+        match self.compression {
+            SpillCompression::None => {
+                self.heap.insert(&record)?;
+            }
+            #[cfg(feature = "lz4")]
+            SpillCompression::Lz4 => {
+                self.block.extend_from_slice(&(record.len() as u32).to_le_bytes());
+                self.block.extend_from_slice(&record);
+                if self.block.len() >= SPILL_BLOCK_SIZE {
+                    self.compress_block()?;
+                }
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    // helper method to compress the gathered block into the stream, writing its whole records
+    #[cfg(feature = "lz4")]
+    fn compress_block(&mut self) -> Result<(), CrustyError> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let compressed = compress_prepend_size(&self.block);
+        self.stream.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        self.stream.extend_from_slice(&compressed);
+        self.block.clear();
+        let whole = self.stream.len() - self.stream.len() % MAX_RECORD_SIZE;
+        for record in self.stream[..whole].chunks(MAX_RECORD_SIZE) {
+            self.heap.insert(record)?;
+        }
+        self.stream.drain(..whole);
+        Ok(())
+    }
+
+    /// Write the tuples gathered for compression, so disk_bytes counts every tuple written.
+    pub fn flush(&mut self) -> Result<(), CrustyError> {
+        #[cfg(feature = "lz4")]
+        {
+            self.compress_block()?;
+            if !self.stream.is_empty() {
+                self.heap.insert(&self.stream)?;
+                self.stream.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of tuples written.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Returns true if no tuple was written.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns the bytes of the pages written, counting the last one in full.
+    pub fn disk_bytes(&self) -> usize {
+        self.heap.num_pages() * PAGE_SIZE
+    }
+
+    /// Finish writing and read the tuples back in the order they were written.
+    pub fn into_reader(self) -> Result<SpillReader, CrustyError> {
+        SpillReader::new(self, HeapCursor::default())
+    }
+
+    /// Finish writing and read the tuples back through a buffer pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `pool` - Pool the pages are fetched through.
+    pub fn into_pooled_reader(self, pool: Arc<BufferPool>) -> Result<SpillReader, CrustyError> {
+        SpillReader::new(self, HeapCursor::with_pool(pool))
+    }
+}
+
+/// Reader over the tuples of a finished spill file.
+pub struct SpillReader {
+    /// Position of the next tuple.
+    cursor: HeapCursor,
+    /// File being read, removed when the reader is dropped.
+    file: SpillFile,
+    /// Compressed bytes read and not decompressed yet.
+    #[cfg(feature = "lz4")]
+    stream: Vec<u8>,
+    /// Decompressed block being read.
+    #[cfg(feature = "lz4")]
+    block: Vec<u8>,
+    /// Position of the next tuple in the block.
+    #[cfg(feature = "lz4")]
+    pos: usize,
+}
+
+impl SpillReader {
+    // helper method to start reading a file from its first page
+    fn new(mut file: SpillFile, cursor: HeapCursor) -> Result<Self, CrustyError> {
+        file.flush()?;
+        Ok(Self {
+            cursor,
+            file,
+            #[cfg(feature = "lz4")]
+            stream: Vec::new(),
+            #[cfg(feature = "lz4")]
+            block: Vec::new(),
+            #[cfg(feature = "lz4")]
+            pos: 0,
+        })
+    }
+
+    /// Returns the page accesses of the reader through its buffer pool.
+    pub fn stats(&self) -> PoolStats {
+        self.cursor.stats()
+    }
+
+    /// Returns the next tuple of the file, or None once all tuples were read.
+    pub fn next_tuple(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        let record = match self.file.compression {
+            SpillCompression::None => match self.cursor.next_record(&mut self.file.heap)? {
+                None => return Ok(None),
+                Some(record) => record,
+            },
+            #[cfg(feature = "lz4")]
+            SpillCompression::Lz4 => {
+                if self.pos == self.block.len() && !self.next_block()? {
+                    return Ok(None);
+                }
+                let start = self.pos + 4;
+                let len = self.block.get(self.pos..start).map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize);
+                let record = len.and_then(|len| self.block.get(start..start + len));
+                let record = record.ok_or_else(|| CrustyError::ValidationError(String::from("corrupt spill block")))?;
+                self.pos = start + record.len();
+                record.to_vec()
+            }
+        };
+        match &self.file.layout {
+            Some(schema) => schema.deserialize_row(&record).map(Some),
+            None => Ok(Some(Tuple::from_bytes(&record))),
+        }
+    }
+
+    // helper method to take the next bytes of the compressed stream, None at the end of the file
+    #[cfg(feature = "lz4")]
+    fn read_stream(&mut self, n: usize) -> Result<Option<Vec<u8>>, CrustyError> {
+        while self.stream.len() < n {
+            match self.cursor.next_record(&mut self.file.heap)? {
+                Some(record) => self.stream.extend_from_slice(&record),
+                None if self.stream.is_empty() => return Ok(None),
+                None => return Err(CrustyError::ValidationError(String::from("truncated spill block"))),
+            }
+        }
+        Ok(Some(self.stream.drain(..n).collect()))
+    }
+
+    // helper method to decompress the next block, false at the end of the file
+    #[cfg(feature = "lz4")]
+    fn next_block(&mut self) -> Result<bool, CrustyError> {
+        let header = match self.read_stream(4)? {
+            None => return Ok(false),
+            Some(header) => header,
+        };
+        let compressed_len = u32::from_le_bytes(header[..].try_into().unwrap()) as usize;
+        let compressed = self
+            .read_stream(compressed_len)?
+            .ok_or_else(|| CrustyError::ValidationError(String::from("truncated spill block")))?;
+        self.block = decompress_size_prepended(&compressed)
+            .map_err(|e| CrustyError::ValidationError(format!("corrupt spill block: {}", e)))?;
+        self.pos = 0;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{DataType, Field};
+
+    #[test]
+    fn round_trip() -> Result<(), CrustyError> {
+        let tuples = vec![
+            Tuple::new(vec![Field::IntField(1), Field::StringField(String::from("a"))]),
+            Tuple::new(vec![Field::IntField(-2), Field::StringField(String::new())]),
+        ];
+        let mut file = SpillFile::create()?;
+        for t in &tuples {
+            file.write(t)?;
+        }
+        assert_eq!(file.len(), 2);
+        let path = file.heap.path().to_path_buf();
+        let mut reader = file.into_reader()?;
+        let mut res = Vec::new();
+        while let Some(t) = reader.next_tuple()? {
+            res.push(t);
+        }
+        assert_eq!(tuples, res);
+        drop(reader);
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn saved_copy() -> Result<(), CrustyError> {
+        let tuples: Vec<Tuple> = (0..300).map(|i| Tuple::new(vec![Field::IntField(i)])).collect();
+        let mut file = SpillFile::create()?;
+        for t in &tuples {
+            file.write(t)?;
+        }
+        let path = std::env::temp_dir().join(format!("join-saved-{}", std::process::id()));
+        file.save(&path)?;
+        drop(file);
+        let copy = SpillFile::open(&path, None, SpillCompression::None, tuples.len())?;
+        assert_eq!(copy.len(), tuples.len());
+        let mut reader = copy.into_reader()?;
+        assert_eq!(std::iter::from_fn(|| reader.next_tuple().unwrap()).collect::<Vec<_>>(), tuples);
+        drop(reader);
+        // the copy is kept
+        assert!(path.exists());
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn fixed_rows() -> Result<(), CrustyError> {
+        let schema = TableSchema::from_vecs(vec!["a", "b"], vec![DataType::Int, DataType::String]);
+        let tuples = vec![
+            Tuple::new(vec![Field::IntField(7), Field::StringField(String::from("xyz"))]),
+            Tuple::new(vec![Field::IntField(i32::MIN), Field::StringField("s".repeat(128))]),
+        ];
+        let mut file = SpillFile::with_schema(schema.clone())?;
+        for t in &tuples {
+            file.write(t)?;
+        }
+        assert!(file.write(&Tuple::new(vec![Field::IntField(1)])).is_err());
+        for _ in 0..100 {
+            file.write(&tuples[0])?;
+        }
+        // a page holds as many rows as fit with their slots
+        assert_eq!(file.heap.num_pages(), 102usize.div_ceil(PAGE_SIZE / (schema.byte_size() + 4)));
+        let mut reader = file.into_reader()?;
+        assert_eq!(reader.next_tuple()?.as_ref(), Some(&tuples[0]));
+        assert_eq!(reader.next_tuple()?.as_ref(), Some(&tuples[1]));
+        assert_eq!(std::iter::from_fn(|| reader.next_tuple().unwrap()).count(), 100);
+        Ok(())
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn compressed_blocks() -> Result<(), CrustyError> {
+        let schema = TableSchema::from_vecs(vec!["a", "b"], vec![DataType::Int, DataType::String]);
+        let tuples: Vec<Tuple> = (0..5000)
+            .map(|i| Tuple::new(vec![Field::IntField(i % 100), Field::StringField(format!("row {}", i % 10))]))
+            .collect();
+        for layout in [None, Some(schema.clone())] {
+            let mut plain = SpillFile::create()?;
+            plain.layout = layout.clone();
+            let mut file = SpillFile::create()?;
+            file.layout = layout;
+            file.set_compression(SpillCompression::Lz4);
+            for t in &tuples {
+                plain.write(t)?;
+                file.write(t)?;
+            }
+            assert_eq!(file.len(), tuples.len());
+            file.flush()?;
+            // padded strings and repeated rows compress to a fraction of the pages
+            assert!(file.disk_bytes() * 4 < plain.disk_bytes());
+            let mut reader = file.into_reader()?;
+            assert_eq!(std::iter::from_fn(|| reader.next_tuple().unwrap()).collect::<Vec<_>>(), tuples);
+            assert_eq!(reader.next_tuple()?, None);
+        }
+
+        // a single block written by into_reader
+        let mut file = SpillFile::with_schema(schema)?;
+        file.set_compression(SpillCompression::Lz4);
+        file.write(&tuples[1])?;
+        let mut reader = file.into_reader()?;
+        assert_eq!(reader.next_tuple()?.as_ref(), Some(&tuples[1]));
+        assert_eq!(reader.next_tuple()?, None);
+        Ok(())
+    }
+}
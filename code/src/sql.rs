@@ -0,0 +1,407 @@
+//! Tiny SQL front end building operator pipelines over the tables of a catalog.
+//!
+//! Supported queries have the shape
+//! `SELECT items FROM a [AS x] [JOIN b [AS y] ON a.c = b.d]... [WHERE conditions] [GROUP BY columns]`
+//! where the items are `*`, `t.*`, columns and COUNT/SUM/MIN/MAX/AVG of a column, the join
+//! conditions compare a column of each side, and the WHERE conditions are comparisons of a
+//! column with a constant joined by AND. Anything else fails with a ValidationError.
+use sqlparser::ast::{
+    BinaryOperator, Expr, FunctionArg, FunctionArgExpr, FunctionArguments, GroupByExpr, JoinConstraint, JoinOperator,
+    SelectItem, SetExpr, Statement, TableFactor, UnaryOperator, Value,
+};
+use sqlparser::dialect::GenericDialect;
+use sqlparser::parser::Parser;
+use crate::catalog::Catalog;
+use crate::common::{AggOp, CrustyError, Field, SimplePredicateOp, TableSchema};
+use crate::plan::{LogicalPlan, PhysicalPlan};
+
+/// Runs queries over the tables of a catalog.
+#[derive(Debug, Clone, Default)]
+pub struct SqlContext {
+    /// Tables queries can read.
+    catalog: Catalog,
+}
+
+// helper method to reject a part of a query the front end does not handle
+fn unsupported<T>(what: impl std::fmt::Display) -> Result<T, CrustyError> {
+    Err(CrustyError::ValidationError(format!("unsupported SQL: {}", what)))
+}
+
+// helper method to map a SQL comparison to a predicate operator
+fn predicate_op(op: &BinaryOperator) -> Result<SimplePredicateOp, CrustyError> {
+    match op {
+        BinaryOperator::Eq => Ok(SimplePredicateOp::Equals),
+        BinaryOperator::NotEq => Ok(SimplePredicateOp::NotEq),
+        BinaryOperator::Lt => Ok(SimplePredicateOp::LessThan),
+        BinaryOperator::LtEq => Ok(SimplePredicateOp::LessThanOrEq),
+        BinaryOperator::Gt => Ok(SimplePredicateOp::GreaterThan),
+        BinaryOperator::GtEq => Ok(SimplePredicateOp::GreaterThanOrEq),
+        _ => unsupported(op),
+    }
+}
+
+// helper method to read a constant, None if the expression is not one
+fn literal(expr: &Expr) -> Result<Option<Field>, CrustyError> {
+    let number = |s: &str| {
+        s.parse()
+            .map(Field::IntField)
+            .map_err(|_| CrustyError::ValidationError(format!("{} is not an integer", s)))
+    };
+    match expr {
+        Expr::Value(Value::Number(s, _)) => number(s).map(Some),
+        Expr::Value(Value::SingleQuotedString(s)) => Ok(Some(Field::StringField(s.clone()))),
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => match expr.as_ref() {
+            Expr::Value(Value::Number(s, _)) => number(&format!("-{}", s)).map(Some),
+            _ => unsupported(expr),
+        },
+        Expr::Nested(expr) => literal(expr),
+        _ => Ok(None),
+    }
+}
+
+// helper method to split a condition into the comparisons joined by AND
+fn conjuncts(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            let mut res = conjuncts(left);
+            res.extend(conjuncts(right));
+            res
+        }
+        Expr::Nested(expr) => conjuncts(expr),
+        _ => vec![expr],
+    }
+}
+
+/// Names of the fields of a plan output: the alias of their table and their column name.
+struct Scope(Vec<(String, String)>);
+
+impl Scope {
+    // helper method to name the fields of a table
+    fn new(alias: &str, schema: &TableSchema) -> Self {
+        Scope(schema.attributes().map(|a| (alias.to_string(), a.name().to_string())).collect())
+    }
+
+    // helper method to find the index of a column, None if the expression is not a column
+    fn resolve(&self, expr: &Expr) -> Result<Option<usize>, CrustyError> {
+        let (table, column) = match expr {
+            Expr::Identifier(ident) => (None, &ident.value),
+            Expr::CompoundIdentifier(idents) if idents.len() == 2 => (Some(&idents[0].value), &idents[1].value),
+            Expr::Nested(expr) => return self.resolve(expr),
+            _ => return Ok(None),
+        };
+        let mut matches = self
+            .0
+            .iter()
+            .enumerate()
+            .filter(|(_, (t, c))| c == column && table.is_none_or(|table| table == t));
+        match (matches.next(), matches.next()) {
+            (Some((i, _)), None) => Ok(Some(i)),
+            (None, _) => Err(CrustyError::ValidationError(format!("no column is named {}", expr))),
+            (Some(_), Some(_)) => Err(CrustyError::ValidationError(format!("column {} is ambiguous", expr))),
+        }
+    }
+
+    // helper method to find the index of a column, failing if the expression is not one
+    fn column(&self, expr: &Expr) -> Result<usize, CrustyError> {
+        match self.resolve(expr)? {
+            Some(i) => Ok(i),
+            None => unsupported(format!("{} is not a column", expr)),
+        }
+    }
+}
+
+// helper method to read an aggregate call, None if the expression is not one
+fn aggregate(expr: &Expr, scope: &Scope) -> Result<Option<(usize, AggOp)>, CrustyError> {
+    let function = match expr {
+        Expr::Function(function) => function,
+        _ => return Ok(None),
+    };
+    let op = match function.name.to_string().to_lowercase().as_str() {
+        "count" => AggOp::Count,
+        "sum" => AggOp::Sum,
+        "min" => AggOp::Min,
+        "max" => AggOp::Max,
+        "avg" => AggOp::Avg,
+        _ => return unsupported(&function.name),
+    };
+    let args = match &function.args {
+        FunctionArguments::List(list) if list.duplicate_treatment.is_none() => &list.args,
+        _ => return unsupported(expr),
+    };
+    match (args.as_slice(), op) {
+        ([FunctionArg::Unnamed(FunctionArgExpr::Wildcard)], AggOp::Count) => Ok(Some((0, op))),
+        ([FunctionArg::Unnamed(FunctionArgExpr::Expr(arg))], _) => Ok(Some((scope.column(arg)?, op))),
+        _ => unsupported(expr),
+    }
+}
+
+impl SqlContext {
+    /// Create a new context reading the tables of a catalog.
+    ///
+    /// # Arguments
+    ///
+    /// * `catalog` - Tables queries can read.
+    pub fn new(catalog: Catalog) -> Self {
+        Self { catalog }
+    }
+
+    /// Returns the catalog, e.g. to register more tables.
+    pub fn catalog_mut(&mut self) -> &mut Catalog {
+        &mut self.catalog
+    }
+
+    // helper method to scan a table of the FROM clause with the names of its columns
+    fn scan(&self, relation: &TableFactor) -> Result<(LogicalPlan, Scope), CrustyError> {
+        let (name, alias) = match relation {
+            TableFactor::Table { name, alias, args: None, .. } => (name.to_string(), alias),
+            _ => return unsupported(relation),
+        };
+        let plan = self.catalog.plan(&name)?;
+        let schema = &self.catalog.get(&name).expect("planned tables are registered").schema;
+        let alias = alias.as_ref().map_or(name.clone(), |a| a.name.value.clone());
+        Ok((plan, Scope::new(&alias, schema)))
+    }
+
+    /// Build the logical plan of a query.
+    ///
+    /// # Arguments
+    ///
+    /// * `sql` - Query, see the module documentation for what is supported.
+    pub fn plan(&self, sql: &str) -> Result<LogicalPlan, CrustyError> {
+        let statements = Parser::parse_sql(&GenericDialect {}, sql)
+            .map_err(|e| CrustyError::ValidationError(format!("cannot parse SQL: {}", e)))?;
+        let query = match statements.as_slice() {
+            [Statement::Query(query)] => query,
+            _ => return unsupported("only a single SELECT is supported"),
+        };
+        if query.with.is_some() || query.order_by.is_some() || query.limit.is_some() || query.offset.is_some() {
+            return unsupported(query);
+        }
+        let select = match query.body.as_ref() {
+            SetExpr::Select(select) => select,
+            body => return unsupported(body),
+        };
+        if select.distinct.is_some() || select.having.is_some() {
+            return unsupported(select);
+        }
+        let from = match select.from.as_slice() {
+            [from] => from,
+            _ => return unsupported("FROM needs exactly one table, join the others"),
+        };
+
+        // left-deep joins in the order of the query
+        let (mut plan, mut scope) = self.scan(&from.relation)?;
+        for join in &from.joins {
+            let on = match &join.join_operator {
+                JoinOperator::Inner(JoinConstraint::On(on)) => on,
+                _ => return unsupported(format!("{} is not an inner join with ON", join)),
+            };
+            let (right, right_scope) = self.scan(&join.relation)?;
+            let (left_expr, op, right_expr) = match on {
+                Expr::BinaryOp { left, op, right } => (left, predicate_op(op)?, right),
+                _ => return unsupported(on),
+            };
+            // the condition may name the right table first
+            let (op, left_index, right_index) = match (scope.resolve(left_expr), right_scope.resolve(right_expr)) {
+                (Ok(Some(l)), Ok(Some(r))) => (op, l, r),
+                _ => (op.flip(), scope.column(right_expr)?, right_scope.column(left_expr)?),
+            };
+            plan = plan.join(right, op, left_index, right_index);
+            scope.0.extend(right_scope.0);
+        }
+
+        if let Some(selection) = &select.selection {
+            for condition in conjuncts(selection) {
+                let (left, op, right) = match condition {
+                    Expr::BinaryOp { left, op, right } => (left, predicate_op(op)?, right),
+                    _ => return unsupported(condition),
+                };
+                plan = match (literal(left)?, literal(right)?) {
+                    (None, Some(value)) => plan.filter(scope.column(left)?, op, value),
+                    (Some(value), None) => plan.filter(scope.column(right)?, op.flip(), value),
+                    _ => return unsupported(condition),
+                };
+            }
+        }
+
+        let group_by = match &select.group_by {
+            GroupByExpr::Expressions(exprs, modifiers) if modifiers.is_empty() => {
+                exprs.iter().map(|e| scope.column(e)).collect::<Result<Vec<_>, _>>()?
+            }
+            group_by => return unsupported(group_by),
+        };
+        let exprs: Vec<&Expr> = select
+            .projection
+            .iter()
+            .filter_map(|item| match item {
+                SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => Some(expr),
+                _ => None,
+            })
+            .collect();
+        let mut aggs = Vec::new();
+        for expr in &exprs {
+            if let Some(agg) = aggregate(expr, &scope)? {
+                aggs.push(agg);
+            }
+        }
+
+        let columns = if group_by.is_empty() && aggs.is_empty() {
+            let mut columns = Vec::new();
+            for item in &select.projection {
+                match item {
+                    SelectItem::Wildcard(_) => columns.extend(0..scope.0.len()),
+                    SelectItem::QualifiedWildcard(table, _) => {
+                        let table = table.to_string();
+                        let start = columns.len();
+                        columns.extend(scope.0.iter().enumerate().filter(|(_, (t, _))| *t == table).map(|(i, _)| i));
+                        if columns.len() == start {
+                            return Err(CrustyError::ValidationError(format!("no table is named {}", table)));
+                        }
+                    }
+                    SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => columns.push(scope.column(expr)?),
+                }
+            }
+            columns
+        } else {
+            // aggregate tuples are the group by fields then the aggregates
+            let mut columns = Vec::new();
+            let mut next_agg = group_by.len();
+            for item in &select.projection {
+                let expr = match item {
+                    SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => expr,
+                    _ => return unsupported(format!("{} with GROUP BY", item)),
+                };
+                if aggregate(expr, &scope)?.is_some() {
+                    columns.push(next_agg);
+                    next_agg += 1;
+                } else {
+                    let i = scope.column(expr)?;
+                    let position = group_by
+                        .iter()
+                        .position(|g| *g == i)
+                        .ok_or_else(|| CrustyError::ValidationError(format!("{} is not grouped", expr)))?;
+                    columns.push(position);
+                }
+            }
+            let width = group_by.len() + aggs.len();
+            plan = plan.aggregate(group_by, aggs);
+            if columns == (0..width).collect::<Vec<_>>() {
+                return Ok(plan);
+            }
+            columns
+        };
+        if columns == (0..scope.0.len()).collect::<Vec<_>>() {
+            Ok(plan)
+        } else {
+            Ok(plan.project(columns))
+        }
+    }
+
+    /// Build the operators executing a query, not opened yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `sql` - Query, see the module documentation for what is supported.
+    pub fn execute(&self, sql: &str) -> Result<PhysicalPlan, CrustyError> {
+        self.plan(sql)?.to_physical()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{DataType, OpIterator, Tuple};
+    use crate::examples::{customers_schema, orders_schema};
+
+    fn tuple(fields: Vec<Field>) -> Tuple {
+        Tuple::new(fields)
+    }
+
+    fn context() -> SqlContext {
+        let mut catalog = Catalog::new();
+        let region = |s: &str| Field::StringField(s.to_string());
+        let customers = vec![
+            tuple(vec![Field::IntField(1), region("east")]),
+            tuple(vec![Field::IntField(2), region("west")]),
+            tuple(vec![Field::IntField(3), region("east")]),
+        ];
+        let orders = (0..6)
+            .map(|i| tuple(vec![Field::IntField(10 + i), Field::IntField(i % 3 + 1), Field::IntField(100 * i)]))
+            .collect();
+        catalog.register_tuples("customers", customers, customers_schema());
+        catalog.register_tuples("orders", orders, orders_schema());
+        SqlContext::new(catalog)
+    }
+
+    fn run(ctx: &SqlContext, sql: &str) -> Result<Vec<Vec<Field>>, CrustyError> {
+        let mut op = ctx.execute(sql)?;
+        op.open()?;
+        let mut res = Vec::new();
+        while let Some(t) = op.next()? {
+            res.push(t.field_vals().cloned().collect());
+        }
+        res.sort();
+        Ok(res)
+    }
+
+    #[test]
+    fn join_and_filter() -> Result<(), CrustyError> {
+        let ctx = context();
+        let res = run(
+            &ctx,
+            "SELECT o.id, c.region FROM orders o JOIN customers c ON c.id = o.customer_id WHERE o.amount >= 300 AND 'east' = region",
+        )?;
+        assert_eq!(
+            res,
+            vec![
+                vec![Field::IntField(13), Field::StringField(String::from("east"))],
+                vec![Field::IntField(15), Field::StringField(String::from("east"))],
+            ]
+        );
+        assert_eq!(run(&ctx, "SELECT * FROM orders JOIN customers ON customer_id = customers.id")?.len(), 6);
+        assert_eq!(run(&ctx, "SELECT customers.* FROM orders JOIN customers ON customer_id = customers.id")?[0].len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn group_by() -> Result<(), CrustyError> {
+        let ctx = context();
+        let res = run(
+            &ctx,
+            "SELECT SUM(amount), region, COUNT(*) FROM orders JOIN customers ON customer_id = customers.id GROUP BY region",
+        )?;
+        assert_eq!(
+            res,
+            vec![
+                vec![Field::IntField(500), Field::StringField(String::from("west")), Field::IntField(2)],
+                vec![Field::IntField(1000), Field::StringField(String::from("east")), Field::IntField(4)],
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects() {
+        let ctx = context();
+        for sql in [
+            "SELECT * FROM missing",
+            "SELECT id FROM orders JOIN customers ON customer_id = customers.id",
+            "SELECT * FROM orders LEFT JOIN customers ON customer_id = customers.id",
+            "SELECT region, COUNT(*) FROM customers",
+            "SELECT * FROM orders ORDER BY id",
+            "SELECT FROM",
+        ] {
+            assert!(matches!(ctx.plan(sql), Err(CrustyError::ValidationError(_))), "{}", sql);
+        }
+        let mut other = context();
+        other.catalog_mut().register_tuples("t", vec![], TableSchema::from_vecs(vec!["a"], vec![DataType::Int]));
+        assert!(other.plan("SELECT a FROM t WHERE a = b").is_err());
+    }
+}
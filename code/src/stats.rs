@@ -0,0 +1,328 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use rand::Rng;
+use crate::common::{CrustyError, Field, OpIterator, SimplePredicateOp, TableSchema};
+use crate::cost::{estimate_output_rows, PlanInput};
+use crate::generator::seeded_rng;
+
+/// Number of index bits of the HyperLogLog sketches, 2^12 registers of one byte.
+pub const HLL_PRECISION: u32 = 12;
+
+/// Number of buckets of the column histograms.
+pub const HISTOGRAM_BUCKETS: usize = 32;
+
+/// Seed of the sample histograms are built from.
+pub const STATS_SEED: u64 = 0;
+
+/// HyperLogLog sketch counting the distinct values of a column in constant memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    /// Largest rank seen by each register.
+    registers: Vec<u8>,
+    /// Number of index bits of a hash.
+    precision: u32,
+}
+
+impl HyperLogLog {
+    /// Create a new empty sketch.
+    ///
+    /// # Arguments
+    ///
+    /// * `precision` - Number of index bits, between 4 and 16; more bits lower the error.
+    pub fn new(precision: u32) -> Self {
+        let precision = precision.clamp(4, 16);
+        Self {
+            registers: vec![0; 1 << precision],
+            precision,
+        }
+    }
+
+    /// Add a value to the sketch.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - Value to count.
+    pub fn insert<T: Hash>(&mut self, value: &T) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let index = (hash >> (64 - self.precision)) as usize;
+        // position of the first set bit after the index bits
+        let rank = ((hash << self.precision).leading_zeros() + 1).min(64 - self.precision + 1) as u8;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    /// Returns the estimated number of distinct values inserted.
+    pub fn estimate(&self) -> usize {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        // linear counting is more accurate while many registers are empty
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as usize
+        } else {
+            estimate.round() as usize
+        }
+    }
+}
+
+/// Equi-depth histogram of a column, every bucket holding the same share of the rows.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Histogram {
+    /// Largest value of each bucket, in ascending order.
+    bounds: Vec<Field>,
+}
+
+impl Histogram {
+    /// Create a histogram from sampled values.
+    ///
+    /// # Arguments
+    ///
+    /// * `sample` - Values of the column.
+    /// * `buckets` - Number of buckets, fewer if the sample is smaller.
+    pub fn new(mut sample: Vec<Field>, buckets: usize) -> Self {
+        sample.sort();
+        let buckets = buckets.min(sample.len());
+        let bounds = (1..=buckets).map(|i| sample[i * sample.len() / buckets - 1].clone()).collect();
+        Self { bounds }
+    }
+
+    /// Returns the largest value of each bucket.
+    pub fn bounds(&self) -> &[Field] {
+        &self.bounds
+    }
+
+    /// Returns the estimated share of the rows whose value satisfies `value op field`,
+    /// between 0 and 1.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Predicate operator, with the column value on its left.
+    /// * `field` - Value on the right of the predicate.
+    pub fn selectivity(&self, op: SimplePredicateOp, field: &Field) -> f64 {
+        if self.bounds.is_empty() {
+            return 0.0;
+        }
+        let share = |n: usize| n as f64 / self.bounds.len() as f64;
+        let below = share(self.bounds.partition_point(|b| b < field));
+        let at_most = share(self.bounds.partition_point(|b| b <= field));
+        match op {
+            SimplePredicateOp::LessThan => below,
+            SimplePredicateOp::LessThanOrEq => at_most,
+            SimplePredicateOp::GreaterThan => 1.0 - at_most,
+            SimplePredicateOp::GreaterThanOrEq => 1.0 - below,
+            SimplePredicateOp::Equals => at_most - below,
+            SimplePredicateOp::NotEq => 1.0 - (at_most - below),
+            SimplePredicateOp::All => 1.0,
+        }
+    }
+}
+
+/// Statistics of one column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnStats {
+    /// Number of rows of the column.
+    pub rows: usize,
+    /// Smallest value, None if there are no rows.
+    pub min: Option<Field>,
+    /// Largest value, None if there are no rows.
+    pub max: Option<Field>,
+    /// Estimated number of distinct values.
+    pub distinct: usize,
+    /// Histogram of a sample of the values.
+    pub histogram: Histogram,
+}
+
+impl ColumnStats {
+    // helper method to check whether the value ranges of two columns overlap
+    fn overlaps(&self, other: &ColumnStats) -> bool {
+        match (&self.min, &self.max, &other.min, &other.max) {
+            (Some(min), Some(max), Some(other_min), Some(other_max)) => min <= other_max && other_min <= max,
+            _ => false,
+        }
+    }
+}
+
+/// Statistics of every column of a table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableStats {
+    /// Schema of the table.
+    pub schema: TableSchema,
+    /// Number of rows.
+    pub rows: usize,
+    /// Statistics of each column, in schema order.
+    pub columns: Vec<ColumnStats>,
+}
+
+impl TableStats {
+    /// Collect the statistics of the tuples of an operator.
+    ///
+    /// Min, max and distinct counts cover every tuple; histograms are built from a uniform
+    /// sample so their memory stays bounded. The operator is opened and closed.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Operator to read.
+    /// * `sample_size` - Number of tuples sampled for the histograms.
+    pub fn from_op(op: &mut dyn OpIterator, sample_size: usize) -> Result<Self, CrustyError> {
+        let schema = op.get_schema().clone();
+        let width = schema.size();
+        let mut rng = seeded_rng(STATS_SEED);
+        let mut sketches = vec![HyperLogLog::new(HLL_PRECISION); width];
+        let mut min: Vec<Option<Field>> = vec![None; width];
+        let mut max: Vec<Option<Field>> = vec![None; width];
+        let mut sample = Vec::with_capacity(sample_size);
+        let mut rows = 0;
+        op.open()?;
+        while let Some(t) = op.next()? {
+            for (i, field) in t.field_vals().enumerate().take(width) {
+                sketches[i].insert(field);
+                if min[i].as_ref().is_none_or(|m| field < m) {
+                    min[i] = Some(field.clone());
+                }
+                if max[i].as_ref().is_none_or(|m| field > m) {
+                    max[i] = Some(field.clone());
+                }
+            }
+            // reservoir sampling keeps every tuple with the same probability
+            if sample.len() < sample_size {
+                sample.push(t);
+            } else {
+                let j = rng.gen_range(0..=rows);
+                if j < sample_size {
+                    sample[j] = t;
+                }
+            }
+            rows += 1;
+        }
+        op.close()?;
+
+        let columns = (0..width)
+            .map(|i| {
+                let values = sample.iter().filter_map(|t| t.get_field(i).cloned()).collect();
+                ColumnStats {
+                    rows,
+                    min: min[i].take(),
+                    max: max[i].take(),
+                    // the sketch cannot tell there are more distinct values than rows
+                    distinct: sketches[i].estimate().min(rows),
+                    histogram: Histogram::new(values, HISTOGRAM_BUCKETS),
+                }
+            })
+            .collect();
+        Ok(Self { schema, rows, columns })
+    }
+
+    /// Returns the planner input of the table joined on a column.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_index` - Index of the join key.
+    pub fn plan_input(&self, key_index: usize) -> PlanInput {
+        let mut input = PlanInput::new(self.schema.clone(), self.rows, key_index);
+        if let Some(column) = self.columns.get(key_index) {
+            input.set_distinct_keys(column.distinct);
+        }
+        input
+    }
+}
+
+/// Estimate the number of output tuples of a join from the statistics of its keys.
+///
+/// Equi-joins of columns whose value ranges do not overlap are empty, otherwise they
+/// follow the distinct counts as in cost::estimate_output_rows. Range predicates compare
+/// the left histogram with every bucket bound of the right histogram.
+///
+/// # Arguments
+///
+/// * `left` - Statistics of the left join key.
+/// * `right` - Statistics of the right join key.
+/// * `op` - Predicate operator of the join, with the left key on its left.
+pub fn estimate_join_rows(left: &ColumnStats, right: &ColumnStats, op: SimplePredicateOp) -> usize {
+    // in f64 as estimate_output_rows, the casts back saturate
+    let cross = left.rows as f64 * right.rows as f64;
+    let distinct = (Some(left.distinct), Some(right.distinct));
+    match op {
+        SimplePredicateOp::Equals if !left.overlaps(right) => 0,
+        SimplePredicateOp::NotEq if !left.overlaps(right) => cross as usize,
+        SimplePredicateOp::Equals | SimplePredicateOp::NotEq | SimplePredicateOp::All => {
+            estimate_output_rows(left.rows, right.rows, op, distinct)
+        }
+        _ => {
+            let bounds = right.histogram.bounds();
+            if bounds.is_empty() {
+                return 0;
+            }
+            let selectivity: f64 =
+                bounds.iter().map(|b| left.histogram.selectivity(op, b)).sum::<f64>() / bounds.len() as f64;
+            (cross * selectivity).round() as usize
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{Tuple, TupleIterator};
+    use crate::generator::get_int_table_schema;
+    use crate::join::Join;
+    use crate::operators::write_csv;
+
+    fn scan(keys: impl Iterator<Item = i32>) -> TupleIterator {
+        let tuples = keys.map(|k| Tuple::new(vec![Field::IntField(k)])).collect();
+        TupleIterator::new(tuples, get_int_table_schema(1))
+    }
+
+    #[test]
+    fn distinct_count() {
+        for n in [10, 1000, 100000] {
+            let mut sketch = HyperLogLog::new(HLL_PRECISION);
+            for i in 0..n {
+                sketch.insert(&Field::IntField(i));
+                sketch.insert(&Field::IntField(i));
+            }
+            let error = (sketch.estimate() as f64 - n as f64).abs() / n as f64;
+            assert!(error < 0.05, "estimated {} distinct of {}", sketch.estimate(), n);
+        }
+    }
+
+    #[test]
+    fn table_stats() -> Result<(), CrustyError> {
+        let mut op = scan((0..2000).map(|i| i % 500));
+        let stats = TableStats::from_op(&mut op, 256)?;
+        let column = &stats.columns[0];
+        assert_eq!((stats.rows, column.rows), (2000, 2000));
+        assert_eq!((column.min.clone(), column.max.clone()), (Some(Field::IntField(0)), Some(Field::IntField(499))));
+        assert!((475..525).contains(&column.distinct));
+        assert_eq!(column.histogram.bounds().len(), HISTOGRAM_BUCKETS);
+        let below = column.histogram.selectivity(SimplePredicateOp::LessThan, &Field::IntField(250));
+        assert!((0.4..0.6).contains(&below));
+        assert_eq!(stats.plan_input(0).distinct_keys, Some(column.distinct));
+        Ok(())
+    }
+
+    #[test]
+    fn predicted_vs_actual() -> Result<(), CrustyError> {
+        let left = || scan((0..1000).map(|i| i % 100));
+        let right = || scan((0..400).map(|i| i % 200));
+        let l = TableStats::from_op(&mut left(), 1024)?;
+        let r = TableStats::from_op(&mut right(), 1024)?;
+        for op in [SimplePredicateOp::Equals, SimplePredicateOp::LessThan, SimplePredicateOp::GreaterThanOrEq] {
+            let predicted = estimate_join_rows(&l.columns[0], &r.columns[0], op) as f64;
+            let actual = write_csv(&mut Join::new(op, 0, 0, left(), right())?, &mut std::io::sink())? as f64;
+            assert!((predicted - actual).abs() / actual < 0.1, "{:?}: predicted {}, actual {}", op, predicted, actual);
+        }
+
+        // disjoint key ranges never match
+        let other = TableStats::from_op(&mut scan(1000..1100), 1024)?;
+        assert_eq!(estimate_join_rows(&l.columns[0], &other.columns[0], SimplePredicateOp::Equals), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+//! Benchmark sweeps over a grid of join parameters.
+//!
+//! A grid lists the values of each parameter, rows × overlap × threads × algorithm, and
+//! every combination is joined after some warm-up runs, then timed over repetitions. The
+//! grid is read from a TOML file of top-level keys, e.g.
+//!
+//! ```toml
+//! rows = [2048, 32768]
+//! overlap = [10, 30, 50]   # percent of the tuples found on both sides
+//! threads = [1, 4]
+//! algorithms = ["m-way", "radix"]
+//! warmups = 1
+//! repetitions = 3
+//! check_rows = true        # fail when a join returns the wrong number of tuples
+//! ```
+//!
+//! Keys left out keep the defaults of SweepGrid. Reading grid files needs the sweep feature.
+use serde::Deserialize;
+use crate::common::{CrustyError, OpIterator, SimplePredicateOp, TupleIterator};
+use crate::generator::{create_overlapping_tuples, expected_join_rows, get_int_table_schema, DEFAULT_SEED};
+use crate::join::{MergeStrategy, SortMergeJoin};
+use crate::metrics::Instant;
+use crate::results::{throughput, RunResult};
+
+/// Algorithms a sweep can run, with their names in grid files.
+pub const ALGORITHMS: [(&str, MergeStrategy); 3] = [
+    ("m-way", MergeStrategy::M_WAY),
+    ("m-pass", MergeStrategy::M_PASS),
+    ("radix", MergeStrategy::Radix),
+];
+
+/// Number of fields of the generated tuples, the key is the second one.
+const WIDTH: usize = 2;
+
+/// Values of the parameters of a sweep, and how often each combination runs.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SweepGrid {
+    /// Numbers of tuples of each input.
+    pub rows: Vec<usize>,
+    /// Shares of the tuples found in both inputs, in percent.
+    pub overlap: Vec<usize>,
+    /// Numbers of workers joining the runs.
+    pub threads: Vec<usize>,
+    /// Names of the algorithms, see ALGORITHMS.
+    pub algorithms: Vec<String>,
+    /// Upper bound of the generated keys.
+    pub range: usize,
+    /// Untimed runs of each combination before the timed ones.
+    pub warmups: usize,
+    /// Timed runs of each combination.
+    pub repetitions: usize,
+    /// Seed of the generated inputs.
+    pub seed: u64,
+    /// Whether a run returning another number of tuples than the one counted from the keys
+    /// of the inputs fails the sweep.
+    pub check_rows: bool,
+}
+
+impl Default for SweepGrid {
+    /// One run of every algorithm on 2048 tuples with 10% overlap.
+    fn default() -> Self {
+        Self {
+            rows: vec![2048],
+            overlap: vec![10],
+            threads: vec![1],
+            algorithms: ALGORITHMS.iter().map(|(name, _)| name.to_string()).collect(),
+            range: 1000,
+            warmups: 0,
+            repetitions: 1,
+            seed: DEFAULT_SEED,
+            check_rows: true,
+        }
+    }
+}
+
+/// One combination of the parameters of a grid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepPoint {
+    /// Number of tuples of each input.
+    pub rows: usize,
+    /// Share of the tuples found in both inputs, in percent.
+    pub overlap: usize,
+    /// Number of workers joining the runs.
+    pub threads: usize,
+    /// Name of the algorithm.
+    pub algorithm: String,
+}
+
+impl SweepGrid {
+    /// Read a grid from the text of a TOML file.
+    ///
+    /// Unknown keys, unknown algorithms, tables and values of the wrong type are
+    /// ValidationErrors naming the line.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - Text of the file.
+    #[cfg(feature = "sweep")]
+    pub fn from_toml(text: &str) -> Result<Self, CrustyError> {
+        let grid: SweepGrid = toml::from_str(text).map_err(|e| CrustyError::ValidationError(format!("sweep grid: {}", e)))?;
+        for algorithm in &grid.algorithms {
+            if strategy(algorithm).is_none() {
+                return Err(CrustyError::ValidationError(format!("unknown algorithm {}", algorithm)));
+            }
+        }
+        Ok(grid)
+    }
+
+    /// Returns every combination of the parameters, the algorithm changing fastest.
+    pub fn points(&self) -> Vec<SweepPoint> {
+        let mut points = Vec::new();
+        for &rows in &self.rows {
+            for &overlap in &self.overlap {
+                for &threads in &self.threads {
+                    for algorithm in &self.algorithms {
+                        points.push(SweepPoint {
+                            rows,
+                            overlap,
+                            threads,
+                            algorithm: algorithm.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        points
+    }
+}
+
+/// Run every combination of a grid, returning one result per timed run.
+///
+/// The inputs of a combination are generated once, outside of the timed runs; a run
+/// times the join from open to its last tuple. With check_rows, a run returning another
+/// number of tuples than the one counted from the keys of the inputs is an ExecutionError.
+///
+/// # Arguments
+///
+/// * `grid` - Parameters of the sweep.
+/// * `run_id` - Identifier of the sweep, shared by its results.
+pub fn run_sweep(grid: &SweepGrid, run_id: &str) -> Result<Vec<RunResult>, CrustyError> {
+    let mut results = Vec::new();
+    for point in grid.points() {
+        let strategy = strategy(&point.algorithm)
+            .ok_or_else(|| CrustyError::ValidationError(format!("unknown algorithm {}", point.algorithm)))?;
+        let overlap = point.overlap as f64 / 100.0;
+        let (left, right) = create_overlapping_tuples(point.rows, WIDTH, grid.range, overlap, grid.seed);
+        let expected = match grid.check_rows {
+            true => Some(expected_join_rows(&left, 1, &right, 1)),
+            false => None,
+        };
+        for repetition in 0..grid.warmups + grid.repetitions {
+            let schema = get_int_table_schema(WIDTH);
+            let s1: Box<dyn OpIterator + Send> = Box::new(TupleIterator::new(left.clone(), schema.clone()));
+            let s2: Box<dyn OpIterator + Send> = Box::new(TupleIterator::new(right.clone(), schema));
+            let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, s1, s2, strategy.clone())?;
+            op.set_workers(point.threads);
+            let now = Instant::now();
+            op.open()?;
+            let mut output_rows = 0;
+            while op.next()?.is_some() {
+                output_rows += 1;
+            }
+            let elapsed = now.elapsed().as_secs_f64();
+            op.close()?;
+            if let Some(expected) = expected.filter(|rows| *rows != output_rows) {
+                return Err(CrustyError::ExecutionError(format!(
+                    "{} returned {} tuples for {} rows with {}% overlap, {} expected",
+                    point.algorithm, output_rows, point.rows, point.overlap, expected
+                )));
+            }
+            if repetition < grid.warmups {
+                continue;
+            }
+            let input_rows = left.len() + right.len();
+            let (tuples_per_s, mb_per_s) = throughput(input_rows, input_rows * get_int_table_schema(WIDTH).byte_size(), elapsed);
+            results.push(RunResult {
+                run_id: run_id.to_string(),
+                algorithm: point.algorithm.clone(),
+                rows: point.rows,
+                overlap,
+                seed: grid.seed,
+                threads: op.workers(),
+                elapsed_s: elapsed,
+                peak_mem: op.memory_tracker().peak(),
+                output_rows,
+                tuples_per_s,
+                mb_per_s,
+                phases: op.phase_stats().to_vec(),
+            });
+        }
+    }
+    Ok(results)
+}
+
+// helper method to find the strategy of an algorithm name
+fn strategy(name: &str) -> Option<MergeStrategy> {
+    ALGORITHMS.iter().find(|(n, _)| *n == name).map(|(_, strategy)| strategy.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "sweep")]
+    #[test]
+    fn read_grid() -> Result<(), CrustyError> {
+        let grid = SweepGrid::from_toml(
+            "# old dis_* and c_* runs\n\
+             rows = [2048, 32_768]\n\
+             overlap = [10,\n  30, # middle\n  50,\n]\n\
+             algorithms = [\"m-way\", \"radix\"]\n\
+             threads = [2]\n\
+             warmups = 1\n\
+             check_rows = false\n",
+        )?;
+        assert_eq!(grid.rows, vec![2048, 32768]);
+        assert_eq!(grid.overlap, vec![10, 30, 50]);
+        assert_eq!(grid.threads, vec![2]);
+        assert_eq!((grid.warmups, grid.repetitions, grid.range, grid.check_rows), (1, 1, 1000, false));
+        let points = grid.points();
+        assert_eq!(points.len(), 12);
+        assert_eq!(points[1], SweepPoint { rows: 2048, overlap: 10, threads: 2, algorithm: String::from("radix") });
+
+        for bad in ["rows = [1, \"a\"]", "rows = [-1]", "check_rows = 1", "size = 3", "[grid]", "algorithms = [\"sort\"]", "rows = [1, 2"] {
+            assert!(matches!(SweepGrid::from_toml(bad), Err(CrustyError::ValidationError(_))), "{}", bad);
+        }
+        // brackets and hashes inside strings are part of them
+        match SweepGrid::from_toml("algorithms = [\"m-way\", \"a]#b\"] # c") {
+            Err(CrustyError::ValidationError(e)) => assert_eq!(e, "unknown algorithm a]#b"),
+            other => panic!("{:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sweep_runs() -> Result<(), CrustyError> {
+        let grid = SweepGrid {
+            rows: vec![256],
+            overlap: vec![10, 50],
+            threads: vec![1, 3],
+            algorithms: vec![String::from("m-way"), String::from("radix")],
+            warmups: 1,
+            repetitions: 2,
+            ..SweepGrid::default()
+        };
+        let results = run_sweep(&grid, "1")?;
+        assert_eq!(results.len(), 16);
+        // the algorithms and repetitions of a combination join the same tuples
+        for same in results.chunks(4) {
+            assert!(same.iter().all(|r| r.output_rows == same[0].output_rows && r.threads == same[0].threads));
+        }
+        assert_eq!((results[4].overlap, results[4].threads), (0.1, 3));
+        let (left, right) = create_overlapping_tuples(256, WIDTH, 1000, 0.1, DEFAULT_SEED);
+        assert_eq!(results[0].output_rows, expected_join_rows(&left, 1, &right, 1));
+        Ok(())
+    }
+}
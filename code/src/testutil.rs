@@ -0,0 +1,230 @@
+//! Proptest strategies for join inputs and a nested-loop oracle, with the testutil feature.
+//!
+//! The strategies generate random schemas, tables and join predicates whose keys come from
+//! a small domain, so random tables still join on many keys. Downstream users and CI can
+//! run any join operator on a generated JoinCase and check it against the oracle, which
+//! compares every left tuple with every right tuple and shares no code with the joins.
+use proptest::collection::vec;
+use proptest::prelude::*;
+use crate::common::{Attribute, CrustyError, DataType, Field, OpIterator, SimplePredicateOp, TableSchema, Tuple, TupleIterator};
+use crate::operators::compare_rows;
+
+/// Default largest number of fields of a generated schema.
+pub const MAX_WIDTH: usize = 4;
+
+/// Default largest number of tuples of a generated table.
+pub const MAX_ROWS: usize = 64;
+
+/// Default number of distinct keys of a generated field.
+pub const KEY_RANGE: i32 = 16;
+
+/// Two tables and the predicate joining them.
+#[derive(Debug, Clone)]
+pub struct JoinCase {
+    /// Schema of the left tuples.
+    pub left_schema: TableSchema,
+    /// Left tuples.
+    pub left: Vec<Tuple>,
+    /// Schema of the right tuples.
+    pub right_schema: TableSchema,
+    /// Right tuples.
+    pub right: Vec<Tuple>,
+    /// Operation of the join condition.
+    pub op: SimplePredicateOp,
+    /// Index of the left field in the join condition.
+    pub left_index: usize,
+    /// Index of the right field in the join condition, of the type of the left one.
+    pub right_index: usize,
+}
+
+impl JoinCase {
+    /// Returns a scan of the left tuples, e.g. the left child of the join under test.
+    pub fn left_scan(&self) -> TupleIterator {
+        TupleIterator::new(self.left.clone(), self.left_schema.clone())
+    }
+
+    /// Returns a scan of the right tuples, e.g. the right child of the join under test.
+    pub fn right_scan(&self) -> TupleIterator {
+        TupleIterator::new(self.right.clone(), self.right_schema.clone())
+    }
+
+    /// Returns the joined tuples, left fields first, computed by a nested loop.
+    pub fn expected(&self) -> Vec<Tuple> {
+        let mut res = Vec::new();
+        for l in &self.left {
+            for r in &self.right {
+                let (key_l, key_r) = (&l.field_vals[self.left_index], &r.field_vals[self.right_index]);
+                if self.op.compare(key_l, key_r) {
+                    res.push(l.merge(r));
+                }
+            }
+        }
+        res
+    }
+
+    /// Check that a join returns the tuples of the oracle, in any order.
+    ///
+    /// Returns the number of joined tuples; a difference is an ExecutionError, see
+    /// operators::compare_rows. The join is opened and closed.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - Join of the scans of the case.
+    pub fn check(&self, op: &mut dyn OpIterator) -> Result<usize, CrustyError> {
+        let mut oracle = TupleIterator::new(self.expected(), self.left_schema.merge(&self.right_schema));
+        compare_rows(&mut oracle, op)
+    }
+}
+
+/// Strategy for a data type.
+pub fn arb_data_type() -> impl Strategy<Value = DataType> {
+    prop_oneof![Just(DataType::Int), Just(DataType::String)]
+}
+
+/// Strategy for a field of a data type, with key_range distinct values.
+///
+/// Strings are zero padded numbers, so they sort like the numbers they come from.
+///
+/// # Arguments
+///
+/// * `dtype` - Type of the field.
+/// * `key_range` - Number of distinct values, at least 1.
+pub fn arb_field(dtype: DataType, key_range: i32) -> impl Strategy<Value = Field> {
+    (0..key_range.max(1)).prop_map(move |key| match dtype {
+        DataType::Int => Field::IntField(key),
+        DataType::String => Field::StringField(format!("{:04}", key)),
+    })
+}
+
+/// Strategy for a schema of 1 to max_width fields named c0, c1, ...
+///
+/// # Arguments
+///
+/// * `max_width` - Largest number of fields, at least 1.
+pub fn arb_schema(max_width: usize) -> impl Strategy<Value = TableSchema> {
+    vec(arb_data_type(), 1..=max_width.max(1)).prop_map(|dtypes| {
+        TableSchema::new(
+            dtypes
+                .into_iter()
+                .enumerate()
+                .map(|(i, dtype)| Attribute::new(format!("c{}", i), dtype))
+                .collect(),
+        )
+    })
+}
+
+/// Strategy for a tuple of a schema.
+///
+/// # Arguments
+///
+/// * `schema` - Schema of the tuple.
+/// * `key_range` - Number of distinct values of each field.
+pub fn arb_tuple(schema: &TableSchema, key_range: i32) -> impl Strategy<Value = Tuple> {
+    let fields: Vec<_> = schema.attributes().map(|attr| arb_field(attr.dtype().clone(), key_range)).collect();
+    fields.prop_map(Tuple::new)
+}
+
+/// Strategy for up to max_rows tuples of a schema.
+///
+/// # Arguments
+///
+/// * `schema` - Schema of the tuples.
+/// * `max_rows` - Largest number of tuples.
+/// * `key_range` - Number of distinct values of each field.
+pub fn arb_tuples(schema: &TableSchema, max_rows: usize, key_range: i32) -> impl Strategy<Value = Vec<Tuple>> {
+    vec(arb_tuple(schema, key_range), 0..=max_rows)
+}
+
+/// Strategy for the operation of a join condition, including All.
+pub fn arb_predicate_op() -> impl Strategy<Value = SimplePredicateOp> {
+    prop_oneof![
+        Just(SimplePredicateOp::Equals),
+        Just(SimplePredicateOp::GreaterThan),
+        Just(SimplePredicateOp::LessThan),
+        Just(SimplePredicateOp::LessThanOrEq),
+        Just(SimplePredicateOp::GreaterThanOrEq),
+        Just(SimplePredicateOp::NotEq),
+        Just(SimplePredicateOp::All),
+    ]
+}
+
+/// Strategy for a join of two random tables with a join condition of one of the
+/// operations.
+///
+/// The right schema gets a field of the type of the left join field at a random index,
+/// so the join fields always have the same type.
+///
+/// # Arguments
+///
+/// * `ops` - Strategy for the operation, e.g. Just(SimplePredicateOp::Equals).
+/// * `max_width` - Largest number of fields of a schema.
+/// * `max_rows` - Largest number of tuples of a table.
+/// * `key_range` - Number of distinct values of each field.
+pub fn arb_join_case(
+    ops: impl Strategy<Value = SimplePredicateOp>,
+    max_width: usize,
+    max_rows: usize,
+    key_range: i32,
+) -> impl Strategy<Value = JoinCase> {
+    (arb_schema(max_width), arb_schema(max_width), ops)
+        .prop_flat_map(|(left_schema, right_schema, op)| {
+            (Just(left_schema.clone()), Just(right_schema.clone()), Just(op), 0..left_schema.size(), 0..right_schema.size())
+        })
+        .prop_flat_map(move |(left_schema, right_schema, op, left_index, right_index)| {
+            // the right join field takes the type of the left one
+            let dtype = left_schema.get_attribute(left_index).unwrap().dtype().clone();
+            let mut attrs: Vec<Attribute> = right_schema.attributes().cloned().collect();
+            attrs[right_index] = Attribute::new(format!("c{}", right_index), dtype);
+            let right_schema = TableSchema::new(attrs);
+            (
+                arb_tuples(&left_schema, max_rows, key_range),
+                arb_tuples(&right_schema, max_rows, key_range),
+                Just(left_schema),
+                Just(right_schema),
+            )
+                .prop_map(move |(left, right, left_schema, right_schema)| JoinCase {
+                    left_schema,
+                    left,
+                    right_schema,
+                    right,
+                    op,
+                    left_index,
+                    right_index,
+                })
+        })
+}
+
+/// Strategy for an equi-join of two random tables, with the default sizes.
+pub fn arb_equi_join_case() -> impl Strategy<Value = JoinCase> {
+    arb_join_case(Just(SimplePredicateOp::Equals), MAX_WIDTH, MAX_ROWS, KEY_RANGE)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::join::{HashEqJoin, Join, MergeStrategy, SortMergeJoin};
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn sort_merge_matches_oracle(case in arb_equi_join_case()) {
+            for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS, MergeStrategy::Radix] {
+                let mut op = SortMergeJoin::new(case.op, case.left_index, case.right_index, case.left_scan(), case.right_scan(), strategy)?;
+                case.check(&mut op)?;
+            }
+        }
+
+        #[test]
+        fn hash_matches_oracle(case in arb_equi_join_case()) {
+            let mut op = HashEqJoin::new(case.op, case.left_index, case.right_index, case.left_scan(), case.right_scan())?;
+            case.check(&mut op)?;
+        }
+
+        #[test]
+        fn nested_loop_matches_oracle(case in arb_join_case(arb_predicate_op(), MAX_WIDTH, 16, KEY_RANGE)) {
+            let mut op = Join::new(case.op, case.left_index, case.right_index, Box::new(case.left_scan()), Box::new(case.right_scan()))?;
+            prop_assert_eq!(case.check(&mut op)?, case.expected().len());
+        }
+    }
+}
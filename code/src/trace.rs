@@ -0,0 +1,317 @@
+//! Tracing spans and events of the operators, with the tracing feature.
+//!
+//! Operators enter a span for open and for each of their sort, merge and join phases, and
+//! emit an event with the row count and duration when a phase ends, when a run or
+//! partition spills and when they close. Attach a subscriber, e.g. tracing-subscriber or
+//! tracing-flame, to collect them. Without the feature the helpers compile to nothing, so
+//! the operators call them unconditionally. With the perf feature, the phases also count
+//! hardware events, see perf.rs.
+use std::ops::RangeInclusive;
+use std::time::Duration;
+use crate::common::{CrustyError, Field, OpIterator, TableSchema, Tuple};
+use crate::metrics::{Instant, OpMetrics};
+
+/// Guard of the span of an operator phase, exited when dropped.
+pub(crate) struct PhaseGuard {
+    /// Entered span of the phase.
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+    /// Time the phase started.
+    start: Instant,
+    /// Operator, phase and hardware counts when the phase started.
+    #[cfg(feature = "perf")]
+    counts: Option<(String, &'static str, crate::perf::CounterValues)>,
+}
+
+impl PhaseGuard {
+    /// Create the guard of an entered span, see phase_span.
+    ///
+    /// # Arguments
+    ///
+    /// * `span` - Span of the phase.
+    /// * `operator` - Name of the operator.
+    /// * `phase` - Name of the phase.
+    #[cfg(feature = "tracing")]
+    pub(crate) fn new(span: tracing::Span, operator: &str, phase: &'static str) -> Self {
+        let _ = (operator, phase);
+        Self {
+            span: span.entered(),
+            #[cfg(feature = "perf")]
+            counts: crate::perf::thread_counts().map(|counts| (operator.to_string(), phase, counts)),
+            start: Instant::now(),
+        }
+    }
+
+    /// Create the guard of a phase, a no-op without the tracing feature.
+    ///
+    /// # Arguments
+    ///
+    /// * `operator` - Name of the operator.
+    /// * `phase` - Name of the phase.
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn new(operator: &str, phase: &'static str) -> Self {
+        let _ = (operator, phase);
+        Self {
+            #[cfg(feature = "perf")]
+            counts: crate::perf::thread_counts().map(|counts| (operator.to_string(), phase, counts)),
+            start: Instant::now(),
+        }
+    }
+
+    /// End the phase with an event holding its row count and duration, returning the
+    /// duration.
+    ///
+    /// # Arguments
+    ///
+    /// * `rows` - Number of rows the phase processed.
+    pub(crate) fn finish(self, rows: usize) -> Duration {
+        let elapsed = self.start.elapsed();
+        let elapsed_us = elapsed.as_micros() as u64;
+        #[cfg(feature = "perf")]
+        if let (Some((operator, phase, start)), Some(end)) = (&self.counts, crate::perf::thread_counts()) {
+            crate::perf::record_phase(operator, phase, end - *start);
+        }
+        #[cfg(feature = "tracing")]
+        {
+            let _ = &self.span;
+            tracing::debug!(rows, elapsed_us, "phase done");
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = (rows, elapsed_us);
+        elapsed
+    }
+}
+
+/// Enter a span named after a phase of an operator, returning its PhaseGuard.
+///
+/// The phase must be a literal, so every phase gets its own span name in flame graphs.
+#[cfg(feature = "tracing")]
+macro_rules! phase_span {
+    ($operator:expr, $phase:literal) => {
+        $crate::trace::PhaseGuard::new(tracing::info_span!($phase, operator = $operator), $operator, $phase)
+    };
+}
+
+/// Enter a span named after a phase of an operator, returning its PhaseGuard.
+///
+/// The phase must be a literal, so every phase gets its own span name in flame graphs.
+#[cfg(not(feature = "tracing"))]
+macro_rules! phase_span {
+    ($operator:expr, $phase:literal) => {
+        $crate::trace::PhaseGuard::new($operator, $phase)
+    };
+}
+pub(crate) use phase_span;
+
+/// Emit an event for tuples an operator wrote to disk.
+///
+/// # Arguments
+///
+/// * `operator` - Name of the operator.
+/// * `tuples` - Number of tuples spilled.
+pub(crate) fn spill_event(operator: &str, tuples: usize) {
+    #[cfg(feature = "tracing")]
+    tracing::info!(operator, tuples, "spill");
+    #[cfg(not(feature = "tracing"))]
+    let _ = (operator, tuples);
+}
+
+/// Emit an event with the metrics of an operator as it closes.
+///
+/// # Arguments
+///
+/// * `operator` - Name of the operator.
+/// * `metrics` - Metrics since the last open.
+pub(crate) fn close_event(operator: &str, metrics: &OpMetrics) {
+    #[cfg(feature = "tracing")]
+    tracing::info!(
+        operator,
+        rows_in = metrics.rows_in,
+        rows_out = metrics.rows_out,
+        spills = metrics.spills,
+        comparisons = metrics.comparisons,
+        dup_blocks = metrics.dup_blocks,
+        "close"
+    );
+    #[cfg(not(feature = "tracing"))]
+    let _ = (operator, metrics);
+}
+
+/// Wraps an operator in spans for its open, next and close calls, named after the
+/// operator, e.g. for a child that does not trace itself.
+///
+/// The next spans are at trace level, so a subscriber only pays for them when asked to.
+pub struct Traced<T> {
+    /// Wrapped operator.
+    inner: T,
+    /// Name of the wrapped operator.
+    name: String,
+    /// Tuples returned since the last open.
+    rows: usize,
+}
+
+impl<T: OpIterator> Traced<T> {
+    /// Traced constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - Operator to trace.
+    pub fn new(inner: T) -> Self {
+        Self {
+            name: inner.name(),
+            inner,
+            rows: 0,
+        }
+    }
+
+    /// Returns the wrapped operator.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: OpIterator> OpIterator for Traced<T> {
+    fn open(&mut self) -> Result<(), CrustyError> {
+        self.rows = 0;
+        let guard = phase_span!(self.name.as_str(), "open");
+        let res = self.inner.open();
+        guard.finish(0);
+        res
+    }
+
+    fn next(&mut self) -> Result<Option<Tuple>, CrustyError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("next", operator = self.name.as_str()).entered();
+        let res = self.inner.next();
+        if let Ok(Some(_)) = res {
+            self.rows += 1;
+        }
+        res
+    }
+
+    fn close(&mut self) -> Result<(), CrustyError> {
+        let guard = phase_span!(self.name.as_str(), "close");
+        let res = self.inner.close();
+        guard.finish(self.rows);
+        res
+    }
+
+    fn rewind(&mut self) -> Result<(), CrustyError> {
+        self.rows = 0;
+        self.inner.rewind()
+    }
+
+    fn get_schema(&self) -> &TableSchema {
+        self.inner.get_schema()
+    }
+
+    fn request_order(&mut self, index: usize) -> bool {
+        self.inner.request_order(index)
+    }
+
+    fn request_key_range(&mut self, index: usize, range: Option<RangeInclusive<Field>>) -> bool {
+        self.inner.request_key_range(index, range)
+    }
+
+    fn sort_order(&self) -> Option<Vec<usize>> {
+        self.inner.sort_order()
+    }
+
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn metrics(&self) -> OpMetrics {
+        self.inner.metrics()
+    }
+
+    fn children(&self) -> Vec<&dyn OpIterator> {
+        self.inner.children()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{SimplePredicateOp, TupleIterator};
+    use crate::config::MemoryTracker;
+    use crate::generator::{create_vec_tuple, get_int_table_schema};
+    use crate::join::{MergeStrategy, SortMergeJoin};
+
+    fn join() -> Result<SortMergeJoin, CrustyError> {
+        let scan = |n, seed| -> Box<dyn OpIterator + Send> {
+            Box::new(TupleIterator::new(create_vec_tuple(n, 2, 100, seed), get_int_table_schema(2)))
+        };
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(200, 1), scan(600, 2), MergeStrategy::M_WAY)?;
+        // the right runs spill every 200 tuples
+        op.set_memory_tracker(MemoryTracker::new(400 * 8));
+        Ok(op)
+    }
+
+    #[test]
+    fn traced_forwards() -> Result<(), CrustyError> {
+        let mut expected = join()?;
+        let mut op = Traced::new(join()?);
+        assert_eq!(op.name(), "SortMergeJoin");
+        assert_eq!(crate::operators::compare_rows(&mut expected, &mut op)?, op.metrics().rows_out);
+        Ok(())
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn spans_and_events() -> Result<(), CrustyError> {
+        use std::sync::{Arc, Mutex};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        // records the names of the spans and events
+        #[derive(Default)]
+        struct Names(Arc<Mutex<Vec<String>>>);
+        impl Subscriber for Names {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut names = self.0.lock().unwrap();
+                names.push(span.metadata().name().to_string());
+                Id::from_u64(names.len() as u64)
+            }
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, event: &Event<'_>) {
+                struct Message(String);
+                impl tracing::field::Visit for Message {
+                    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+                        if field.name() == "message" {
+                            self.0 = format!("{:?}", value);
+                        }
+                    }
+                }
+                let mut message = Message(String::new());
+                event.record(&mut message);
+                self.0.lock().unwrap().push(message.0);
+            }
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let names = Names::default();
+        let seen = names.0.clone();
+        tracing::subscriber::with_default(names, || {
+            let mut op = Traced::new(join()?);
+            op.open()?;
+            while op.next()?.is_some() {}
+            op.close()
+        })?;
+        let seen = seen.lock().unwrap();
+        let count = |name: &str| seen.iter().filter(|s| *s == name).count();
+        // spans of the wrapper and the join, or the span of the wrapper and the event of the join
+        assert_eq!((count("open"), count("close")), (2, 2));
+        for phase in ["sort left", "sort right", "merge", "join"] {
+            assert_eq!(count(phase), 1, "{}", phase);
+        }
+        assert_eq!(count("spill"), 2);
+        assert!(count("next") > 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+//! Event stream of the sort-merge join, with the visualize feature, so an external tool can
+//! animate how the level 3 methods proceed.
+//!
+//! A SortMergeJoin given an EventSink with set_event_sink reports, in order: the sorted runs
+//! each child is cut into, the m-way partitions and their key ranges, the runs merged into
+//! each level 3 run, then every joined pair as the positions of its tuples in the level 3
+//! runs. M-way merges every run into its partitions before joining partition with partition,
+//! while m-pass joins each left run with every right run, so the two streams differ in the
+//! RunsMerged and TuplesJoined events. JsonLinesSink writes the events as JSON lines, e.g.
+//! `{"event":"RunFormed","side":"Left","run":0,"rows":4096}`.
+use std::io::Write;
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use crate::common::Field;
+
+/// Child of a join.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JoinSide {
+    Left,
+    Right,
+}
+
+/// Step of a sort-merge join, see the module documentation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event")]
+pub enum JoinEvent {
+    /// A child was sorted into a run, spilled runs included.
+    RunFormed {
+        side: JoinSide,
+        run: usize,
+        rows: usize,
+    },
+    /// An m-way partition takes the keys from lower, inclusive, to upper, exclusive; None is
+    /// unbounded.
+    PartitionAssigned {
+        partition: usize,
+        lower: Option<Field>,
+        upper: Option<Field>,
+        left_rows: usize,
+        right_rows: usize,
+    },
+    /// Sorted runs were merged into a level 3 run.
+    RunsMerged {
+        side: JoinSide,
+        run: usize,
+        inputs: usize,
+        rows: usize,
+    },
+    /// A left tuple joined a right tuple, given by their level 3 runs and positions there.
+    TuplesJoined {
+        left_run: usize,
+        left_row: usize,
+        right_run: usize,
+        right_row: usize,
+    },
+}
+
+/// Receiver of the events of a join.
+pub trait EventSink: Send + Sync {
+    /// Receive the next event.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - Event of the join.
+    fn emit(&self, event: JoinEvent);
+}
+
+/// Sink keeping the events in memory.
+#[derive(Debug, Default)]
+pub struct EventLog {
+    /// Events received so far.
+    events: Mutex<Vec<JoinEvent>>,
+}
+
+impl EventLog {
+    /// Create an empty log.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the events received so far, in order.
+    pub fn events(&self) -> Vec<JoinEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl EventSink for EventLog {
+    fn emit(&self, event: JoinEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+/// Sink writing each event as a line of JSON.
+pub struct JsonLinesSink<W: Write + Send> {
+    /// Destination of the lines, e.g. a file.
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLinesSink<W> {
+    /// Create a sink writing to a writer.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Destination of the lines.
+    pub fn new(writer: W) -> Self {
+        Self { writer: Mutex::new(writer) }
+    }
+
+    /// Returns the writer, e.g. to flush it.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap()
+    }
+}
+
+impl<W: Write + Send> EventSink for JsonLinesSink<W> {
+    /// Write the event, dropping it if the writer fails: the events only illustrate the join.
+    fn emit(&self, event: JoinEvent) {
+        let mut writer = self.writer.lock().unwrap();
+        if serde_json::to_writer(&mut *writer, &event).is_ok() {
+            let _ = writeln!(writer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use crate::common::{CrustyError, OpIterator, SimplePredicateOp, Tuple, TupleIterator};
+    use crate::config::SortConfig;
+    use crate::generator::{create_vec_tuple, get_int_table_schema};
+    use crate::join::{MergeStrategy, SortMergeJoin};
+
+    // helper method to join two tables recording the events
+    fn events(strategy: MergeStrategy, left: &[Tuple], right: &[Tuple]) -> Result<(Vec<Tuple>, Vec<JoinEvent>), CrustyError> {
+        let scan = |tuples: &[Tuple]| TupleIterator::new(tuples.to_vec(), get_int_table_schema(2));
+        let log = Arc::new(EventLog::new());
+        let mut op = SortMergeJoin::new(SimplePredicateOp::Equals, 1, 1, scan(left), scan(right), strategy)?;
+        op.set_sort_config(SortConfig::new(4, 16));
+        op.set_event_sink(log.clone());
+        op.open()?;
+        let mut tuples = Vec::new();
+        while let Some(t) = op.next()? {
+            tuples.push(t);
+        }
+        op.close()?;
+        Ok((tuples, log.events()))
+    }
+
+    #[test]
+    fn m_way_and_m_pass() -> Result<(), CrustyError> {
+        let (left, right) = (create_vec_tuple(100, 2, 1000, 1), create_vec_tuple(100, 2, 1000, 2));
+        for strategy in [MergeStrategy::M_WAY, MergeStrategy::M_PASS] {
+            let (tuples, events) = events(strategy.clone(), &left, &right)?;
+            let formed = |side| events.iter().filter(|e| matches!(e, JoinEvent::RunFormed { side: s, .. } if *s == side)).count();
+            assert!(formed(JoinSide::Left) > 1 && formed(JoinSide::Right) > 1);
+            let partitions = events.iter().filter(|e| matches!(e, JoinEvent::PartitionAssigned { .. })).count();
+            assert_eq!(partitions > 0, matches!(strategy, MergeStrategy::MWay { .. }));
+
+            // a TuplesJoined event per output tuple, after every other event
+            let joined: Vec<_> = events.iter().skip_while(|e| !matches!(e, JoinEvent::TuplesJoined { .. })).collect();
+            assert!(joined.iter().all(|e| matches!(e, JoinEvent::TuplesJoined { .. })));
+            assert_eq!(joined.len(), tuples.len());
+        }
+
+        let sink = JsonLinesSink::new(Vec::new());
+        sink.emit(JoinEvent::RunFormed { side: JoinSide::Left, run: 0, rows: 4096 });
+        assert_eq!(sink.into_inner(), b"{\"event\":\"RunFormed\",\"side\":\"Left\",\"run\":0,\"rows\":4096}\n");
+        Ok(())
+    }
+}